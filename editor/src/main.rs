@@ -1,5 +1,5 @@
 use ramneryd::ecs::prelude::*;
-use ramneryd::{Module, Modules};
+use ramneryd::{Module, Modules, RunOptions};
 
 use structopt::StructOpt;
 
@@ -12,6 +12,28 @@ struct EditorArgs {
     gltf_files: Vec<PathBuf>,
     #[structopt(parse(from_os_str), name = "rsf-file", long)]
     rsf_files: Vec<PathBuf>,
+    /// Directory that is listed in the asset browser
+    #[structopt(parse(from_os_str), long)]
+    content_dir: Option<PathBuf>,
+    /// Load the entity templates in this directory, which are listed in the templates window
+    #[structopt(parse(from_os_str), long)]
+    templates: Option<PathBuf>,
+    /// Load a scene that was saved with --save-scene
+    #[structopt(parse(from_os_str), long)]
+    load_scene: Option<PathBuf>,
+    /// Save the scene to this file on exit
+    #[structopt(parse(from_os_str), long)]
+    save_scene: Option<PathBuf>,
+    /// Capture this frame, counting from 0, with RenderDoc. Requires the renderdoc feature.
+    #[structopt(long)]
+    capture_frame: Option<usize>,
+    /// Print the defines, uniform blocks and descriptor bindings of the built-in shaders and exit
+    #[structopt(long)]
+    print_shader_interface: bool,
+    /// Enable the vulkan validation layers and give the vulkan objects readable names, e.g. for
+    /// RenderDoc
+    #[structopt(long)]
+    vk_debug: bool,
 }
 
 impl Module for EditorArgs {
@@ -22,11 +44,31 @@ impl Module for EditorArgs {
         self.rsf_files
             .iter()
             .for_each(|f| ramneryd::asset::rsf::load_asset(world, f));
+        if let Some(dir) = &self.content_dir {
+            world.insert(ramneryd::asset::ContentDirectory(dir.clone()));
+        }
     }
 }
 
 fn main() {
-    let viewer = Box::new(EditorArgs::from_args());
-    let modules = Modules(vec![viewer]);
-    ramneryd::run(modules);
+    let editor = Box::new(EditorArgs::from_args());
+    if editor.print_shader_interface {
+        if let Err(e) = ramneryd::render::shader_interface::print() {
+            eprintln!("Failed to print the shader interface: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let options = RunOptions {
+        load_scene: editor.load_scene.clone(),
+        templates: editor.templates.clone(),
+        save_scene: editor.save_scene.clone(),
+        capture_frame: editor.capture_frame,
+        edit_mode: true,
+        vk_debug: editor.vk_debug,
+        ..Default::default()
+    };
+    let modules = Modules(vec![editor]);
+    ramneryd::run_with_options(modules, options);
 }
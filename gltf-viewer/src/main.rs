@@ -1,5 +1,5 @@
 use ramneryd::ecs::prelude::*;
-use ramneryd::{Module, Modules};
+use ramneryd::{Module, Modules, RunOptions};
 
 use structopt::StructOpt;
 
@@ -10,16 +10,81 @@ use std::path::PathBuf;
 struct GltfViewer {
     #[structopt(parse(from_os_str))]
     file: PathBuf,
+    /// Name or index of the scene to load. Defaults to the default scene of the file.
+    #[structopt(long)]
+    scene: Option<ramneryd::asset::gltf::SceneSelection>,
+    /// Render offscreen without creating a window
+    #[structopt(long)]
+    headless: bool,
+    /// Exit after rendering this many frames
+    #[structopt(long)]
+    frames: Option<usize>,
+    /// Write the last rendered frame to this image file on exit. Requires --headless.
+    #[structopt(parse(from_os_str), long)]
+    dump: Option<PathBuf>,
+    /// Also write the linear depth, world normals and entity ids next to the --dump image, as
+    /// <name>_depth.exr, <name>_normal.exr and <name>_entity.exr
+    #[structopt(long)]
+    dump_aovs: bool,
+    /// Write the scene color of the last frame, before tonemapping, to this .exr or .hdr file on
+    /// exit
+    #[structopt(parse(from_os_str), long)]
+    dump_hdr: Option<PathBuf>,
+    /// Write a 360 degree panorama from the camera to this image file on exit. Requires --headless.
+    #[structopt(parse(from_os_str), long)]
+    panorama: Option<PathBuf>,
+    /// Make the panorama a top-bottom stereo pair with this distance between the eyes, in meters
+    #[structopt(long)]
+    panorama_stereo: Option<f32>,
+    /// Load a scene saved with --save-scene on top of the file
+    #[structopt(parse(from_os_str), long)]
+    load_scene: Option<PathBuf>,
+    /// Save the scene to this file on exit
+    #[structopt(parse(from_os_str), long)]
+    save_scene: Option<PathBuf>,
+    /// Capture this frame, counting from 0, with RenderDoc. Requires the renderdoc feature.
+    #[structopt(long)]
+    capture_frame: Option<usize>,
+    /// Set cvars from this file, with one name = value per line
+    #[structopt(parse(from_os_str), long)]
+    config: Option<PathBuf>,
+    /// Set a cvar, e.g. --set r.bloom.threshold=2.0. Can be given multiple times.
+    #[structopt(long = "set", number_of_values = 1)]
+    cvars: Vec<String>,
+    /// Load key bindings from this RON file, and save rebindings to it
+    #[structopt(parse(from_os_str), long)]
+    bindings: Option<PathBuf>,
+    /// Load the entity templates in this directory, which can be spawned with the spawn command
+    #[structopt(parse(from_os_str), long)]
+    templates: Option<PathBuf>,
+    /// Adjust the order of the engine systems with this RON file
+    #[structopt(parse(from_os_str), long)]
+    system_order: Option<PathBuf>,
+    /// Write the commands recorded for each frame to this file on exit
+    #[structopt(parse(from_os_str), long)]
+    trace: Option<PathBuf>,
+    /// Compare the commands recorded for each frame to a trace written with --trace. Exits with an
+    /// error if they differ, when run with --headless.
+    #[structopt(parse(from_os_str), long)]
+    expect_trace: Option<PathBuf>,
+    /// Enable the vulkan validation layers and give the vulkan objects readable names, e.g. for
+    /// RenderDoc
+    #[structopt(long)]
+    vk_debug: bool,
 }
 
 impl Module for GltfViewer {
     fn init(&mut self, world: &mut World) {
         use ramneryd::{
             math::{Quat, Rgb, Transform, Vec3},
-            render::Light,
+            render::{Light, LightIntensity},
         };
 
-        ramneryd::asset::gltf::load_asset(world, &self.file);
+        ramneryd::asset::gltf::load_asset_scene(
+            world,
+            &self.file,
+            self.scene.clone().unwrap_or_default(),
+        );
 
         if false {
             world
@@ -76,7 +141,9 @@ impl Module for GltfViewer {
                 g: 1.0,
                 b: 1.0,
             },
-            angle: std::f32::consts::FRAC_PI_4,
+            intensity: LightIntensity::default(),
+            inner_angle: 0.0,
+            outer_angle: std::f32::consts::FRAC_PI_4,
             range: 5.0,
         };
 
@@ -99,6 +166,27 @@ impl Module for GltfViewer {
 
 fn main() {
     let viewer = Box::new(GltfViewer::from_args());
+    let options = RunOptions {
+        headless: viewer.headless,
+        frame_limit: viewer.frames,
+        dump_image: viewer.dump.clone(),
+        dump_aovs: viewer.dump_aovs,
+        dump_hdr_image: viewer.dump_hdr.clone(),
+        panorama: viewer.panorama.clone(),
+        panorama_eye_separation: viewer.panorama_stereo,
+        load_scene: viewer.load_scene.clone(),
+        save_scene: viewer.save_scene.clone(),
+        capture_frame: viewer.capture_frame,
+        config: viewer.config.clone(),
+        cvars: viewer.cvars.clone(),
+        key_bindings: viewer.bindings.clone(),
+        templates: viewer.templates.clone(),
+        edit_mode: false,
+        system_order: viewer.system_order.clone(),
+        command_trace: viewer.trace.clone(),
+        expected_command_trace: viewer.expect_trace.clone(),
+        vk_debug: viewer.vk_debug,
+    };
     let modules = Modules(vec![viewer]);
-    ramneryd::run(modules);
+    ramneryd::run_with_options(modules, options);
 }
@@ -13,6 +13,7 @@ pub(crate) fn impl_component(di: &DeriveInput) -> TokenStream {
     let (impl_generics, ty_generics, where_clause) = di.generics.split_for_impl();
 
     let mut generate_inspect = false;
+    let mut generate_snapshot = false;
     let mut storage: Option<Path> = None;
     for attr in di.attrs.iter() {
         if attr.path.is_ident("component") {
@@ -31,6 +32,8 @@ pub(crate) fn impl_component(di: &DeriveInput) -> TokenStream {
                             NestedMeta::Meta(Meta::Path(path)) => {
                                 if path.is_ident("inspect") {
                                     generate_inspect = true;
+                                } else if path.is_ident("snapshot") {
+                                    generate_snapshot = true;
                                 }
                             }
                             NestedMeta::Meta(Meta::NameValue(nv)) => {
@@ -58,13 +61,21 @@ pub(crate) fn impl_component(di: &DeriveInput) -> TokenStream {
         quote! {None}
     };
 
+    let snapshot = if generate_snapshot {
+        quote! {Some(<#name>::snapshot)}
+    } else {
+        quote! {None}
+    };
+
     let meta_component = quote::quote! {
         crate::ecs::meta::Component {
             name: stringify!(#name),
             size: std::mem::size_of::<#name>(),
             has: <#name>::has,
             register: <#name>::register,
+            storage_id: <#name>::storage_id,
             inspect: #inspect,
+            snapshot: #snapshot,
         }
     };
 
@@ -85,6 +96,35 @@ pub(crate) fn impl_component(di: &DeriveInput) -> TokenStream {
         quote! {}
     };
 
+    let snapshot_impl = if generate_snapshot {
+        quote! {
+            impl #impl_generics #name #ty_generics #where_clause {
+                fn snapshot(world: &crate::ecs::World) -> crate::ecs::meta::Restore {
+                    use crate::ecs::prelude::WorldExt;
+                    use specs::Join as _;
+                    let entities = world.entities();
+                    let storage = world.read_storage::<Self>();
+                    let saved: Vec<(crate::ecs::Entity, Self)> = (&entities, &storage)
+                        .join()
+                        .map(|(ent, comp)| (ent, comp.clone()))
+                        .collect();
+                    Box::new(move |world: &crate::ecs::World| {
+                        let entities = world.entities();
+                        let mut storage = world.write_storage::<Self>();
+                        storage.clear();
+                        for (ent, comp) in saved {
+                            if entities.is_alive(ent) {
+                                storage.insert(ent, comp).expect("Entity is alive");
+                            }
+                        }
+                    })
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
     // TODO: meta() can be const when we have function pointer as const
     quote! {
         /// specs
@@ -103,6 +143,10 @@ pub(crate) fn impl_component(di: &DeriveInput) -> TokenStream {
                 use crate::ecs::prelude::WorldExt;
                 world.register::<Self>();
             }
+
+            pub fn storage_id() -> crate::ecs::prelude::ResourceId {
+                crate::ecs::prelude::ResourceId::new::<specs::storage::MaskedStorage<Self>>()
+            }
         }
 
         /// Meta function
@@ -115,6 +159,8 @@ pub(crate) fn impl_component(di: &DeriveInput) -> TokenStream {
 
         #inspect_impl
 
+        #snapshot_impl
+
         // TODO: Use meta() here when const
         #[linkme::distributed_slice(crate::ecs::meta::ALL_COMPONENTS)]
         static #name_caps: crate::ecs::meta::Component = #meta_component;
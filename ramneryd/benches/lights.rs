@@ -0,0 +1,161 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use ramneryd::render::uniform::{cone_scale_offset, PackedLight, LIGHT_KIND_SPOT};
+
+/// The previous, fp32 only, layout of a light. 80 bytes instead of 48.
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct Fp32Light {
+    pos: [f32; 4],
+    dir_cutoff: [f32; 4],
+    color_range: [f32; 4],
+    spot_scale_offset: [f32; 4],
+    shadow_idx: [u32; 4],
+}
+
+struct SpotLight {
+    pos: [f32; 3],
+    direction: [f32; 3],
+    color: [f32; 3],
+    range: f32,
+    inner_angle: f32,
+    outer_angle: f32,
+}
+
+fn spot_lights(n: usize) -> Vec<SpotLight> {
+    (0..n)
+        .map(|i| {
+            let f = i as f32;
+            SpotLight {
+                pos: [f.sin() * 10.0, 3.0, f.cos() * 10.0],
+                direction: [0.0, -1.0, 0.0],
+                color: [100.0, 80.0 + f, 60.0],
+                range: 15.0,
+                inner_angle: 0.3,
+                outer_angle: 0.5,
+            }
+        })
+        .collect()
+}
+
+fn pack_fp32(lights: &[SpotLight], out: &mut Vec<Fp32Light>) {
+    out.clear();
+    out.extend(lights.iter().enumerate().map(|(i, l)| {
+        let (scale, offset) = cone_scale_offset(l.inner_angle.cos(), l.outer_angle.cos());
+        Fp32Light {
+            pos: [l.pos[0], l.pos[1], l.pos[2], 1.0],
+            dir_cutoff: [
+                l.direction[0],
+                l.direction[1],
+                l.direction[2],
+                l.outer_angle.cos(),
+            ],
+            color_range: [l.color[0], l.color[1], l.color[2], l.range],
+            spot_scale_offset: [scale, offset, 0.0, 0.0],
+            shadow_idx: [i as u32; 4],
+        }
+    }));
+}
+
+fn pack_fp16(lights: &[SpotLight], out: &mut Vec<PackedLight>) {
+    out.clear();
+    out.extend(lights.iter().enumerate().map(|(i, l)| {
+        PackedLight::spot(
+            l.pos,
+            l.direction,
+            l.color,
+            l.range,
+            (l.inner_angle, l.outer_angle),
+        )
+        .with_shadow_idx(i as u32)
+    }));
+}
+
+// The shaded point is at the origin
+fn attenuation(pos: [f32; 3], range: f32, dir: [f32; 3], (scale, offset): (f32, f32)) -> f32 {
+    let v = pos;
+    let dist_sqr = v[0] * v[0] + v[1] * v[1] + v[2] * v[2];
+    let smooth = (1.0 - (dist_sqr / (range * range)).powi(2))
+        .max(0.0)
+        .min(1.0);
+    let dist = dist_sqr.sqrt().max(0.01);
+    let cos_angle = -(v[0] * dir[0] + v[1] * dir[1] + v[2] * dir[2]) / dist;
+    let cone = (cos_angle * scale + offset).max(0.0).min(1.0);
+    smooth * smooth / dist_sqr.max(0.0001) * cone * cone
+}
+
+// The shading cost is approximated on the cpu by what the shader does per light: unpack and
+// compute the attenuation.
+fn shade_fp32(lights: &[Fp32Light]) -> f32 {
+    lights
+        .iter()
+        .map(|l| {
+            let (pos, dir, color, scale_offset) =
+                (l.pos, l.dir_cutoff, l.color_range, l.spot_scale_offset);
+            let a = attenuation(
+                [pos[0], pos[1], pos[2]],
+                color[3],
+                [dir[0], dir[1], dir[2]],
+                (scale_offset[0], scale_offset[1]),
+            );
+            a * (color[0] + color[1] + color[2])
+        })
+        .sum()
+}
+
+fn shade_fp16(lights: &[PackedLight]) -> f32 {
+    lights
+        .iter()
+        .filter(|l| l.kind() == LIGHT_KIND_SPOT)
+        .map(|l| {
+            let pos_range = l.pos_range;
+            let color = l.color();
+            let a = attenuation(
+                [pos_range[0], pos_range[1], pos_range[2]],
+                pos_range[3],
+                l.direction(),
+                l.cone_scale_offset(),
+            );
+            a * (color[0] + color[1] + color[2])
+        })
+        .sum()
+}
+
+const NUM_LIGHTS: [usize; 3] = [16, 128, 512];
+
+pub fn light_buffer_update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("light_buffer_update");
+    for &n in NUM_LIGHTS.iter() {
+        let lights = spot_lights(n);
+        let mut fp32 = Vec::with_capacity(n);
+        let mut fp16 = Vec::with_capacity(n);
+        group.bench_with_input(BenchmarkId::new("fp32", n), &lights, |b, lights| {
+            b.iter(|| pack_fp32(black_box(lights), &mut fp32))
+        });
+        group.bench_with_input(BenchmarkId::new("fp16", n), &lights, |b, lights| {
+            b.iter(|| pack_fp16(black_box(lights), &mut fp16))
+        });
+    }
+    group.finish();
+}
+
+pub fn light_shading(c: &mut Criterion) {
+    let mut group = c.benchmark_group("light_shading");
+    for &n in NUM_LIGHTS.iter() {
+        let lights = spot_lights(n);
+        let mut fp32 = Vec::new();
+        let mut fp16 = Vec::new();
+        pack_fp32(&lights, &mut fp32);
+        pack_fp16(&lights, &mut fp16);
+        group.bench_with_input(BenchmarkId::new("fp32", n), &fp32, |b, lights| {
+            b.iter(|| shade_fp32(black_box(lights)))
+        });
+        group.bench_with_input(BenchmarkId::new("fp16", n), &fp16, |b, lights| {
+            b.iter(|| shade_fp16(black_box(lights)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, light_buffer_update, light_shading);
+criterion_main!(benches);
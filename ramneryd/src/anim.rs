@@ -0,0 +1,740 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ecs::prelude::*;
+use crate::math::{BoundingBox, Mat4, ModelMatrix, Quat, Transform, Vec3};
+use crate::time::{DeltaTime, Time};
+
+/// Marks a node in the scene graph that is used as a joint by at least one skeleton
+#[derive(Default, Component)]
+#[component(storage = "NullStorage")]
+pub struct Joint;
+
+/// The skeleton of a skinned mesh. Joint i of the skeleton corresponds to joint index i in the
+/// vertex attributes of the mesh.
+#[derive(Debug, Clone, Component)]
+#[component(inspect)]
+pub struct Skeleton {
+    pub joints: Vec<Entity>,
+    pub inverse_bind_matrices: Vec<Mat4>,
+}
+
+impl Skeleton {
+    /// Compute the skinning matrix for a joint. The skinned vertex is later transformed by the
+    /// model matrix of the mesh so this has to be undone here, hence the inverse.
+    pub fn joint_matrix(&self, idx: usize, joint_model: &Mat4, inv_mesh_model: &Mat4) -> Mat4 {
+        *inv_mesh_model * *joint_model * self.inverse_bind_matrices[idx]
+    }
+}
+
+/// Weights of the morph targets of the mesh on this entity
+#[derive(Debug, Clone, Component)]
+#[component(inspect, snapshot)]
+pub struct MorphWeights(pub Vec<f32>);
+
+/// Bounds of the vertices of a skinned and/or morphed mesh. These are used to keep the
+/// BoundingBox of the mesh up to date as it is deformed.
+#[derive(Debug, Clone, Component)]
+pub struct DeformedBounds {
+    /// The bounds of the undeformed mesh
+    pub base: BoundingBox,
+    /// Per morph target, the bounds of the vertex displacements
+    pub morph_displacements: Vec<BoundingBox>,
+    /// Per joint, the bounds of the vertices that it influences, in bind pose. These contain the
+    /// vertices displaced by any combination of morph target weights in [0, 1]. Empty if the mesh
+    /// isn't skinned.
+    pub joint_bounds: Vec<Option<BoundingBox>>,
+}
+
+impl DeformedBounds {
+    /// skin is the joint indices and weights per vertex. None if there are no vertices.
+    pub fn new(
+        positions: &[Vec3],
+        morph_targets: &[Vec<Vec3>],
+        skin: Option<&[([u16; 4], [f32; 4])]>,
+    ) -> Option<Self> {
+        let base = BoundingBox::from_points(positions.iter().copied())?;
+        let morph_displacements = morph_targets
+            .iter()
+            .map(|d| {
+                BoundingBox::from_points(d.iter().copied()).unwrap_or(BoundingBox {
+                    min: Vec3::zero(),
+                    max: Vec3::zero(),
+                })
+            })
+            .collect();
+
+        let mut joint_bounds: Vec<Option<BoundingBox>> = Vec::new();
+        for (i, (joints, weights)) in skin.unwrap_or(&[]).iter().enumerate() {
+            let mut vertex = BoundingBox {
+                min: positions[i],
+                max: positions[i],
+            };
+            for d in morph_targets.iter().filter_map(|d| d.get(i)) {
+                vertex.min += Vec3::partial_min(*d, Vec3::zero());
+                vertex.max += Vec3::partial_max(*d, Vec3::zero());
+            }
+
+            for (joint, weight) in joints.iter().zip(weights.iter()) {
+                if *weight <= 0.0 {
+                    continue;
+                }
+
+                let joint = *joint as usize;
+                if joint_bounds.len() <= joint {
+                    joint_bounds.resize(joint + 1, None);
+                }
+                match &mut joint_bounds[joint] {
+                    Some(bounds) => bounds.combine(vertex),
+                    None => joint_bounds[joint] = Some(vertex),
+                }
+            }
+        }
+
+        Some(Self {
+            base,
+            morph_displacements,
+            joint_bounds,
+        })
+    }
+
+    fn morphed(&self, weights: Option<&MorphWeights>) -> BoundingBox {
+        let mut bbox = self.base;
+        let weights = weights.map(|w| w.0.as_slice()).unwrap_or(&[]);
+        for (d, w) in self.morph_displacements.iter().zip(weights.iter()) {
+            let (a, b) = (d.min * *w, d.max * *w);
+            bbox.min += Vec3::partial_min(a, b);
+            bbox.max += Vec3::partial_max(a, b);
+        }
+
+        bbox
+    }
+
+    /// joint_matrices are the skinning matrices per joint index, see Skeleton::joint_matrix
+    fn skinned(&self, joint_matrices: impl Iterator<Item = (usize, Mat4)>) -> Option<BoundingBox> {
+        let transformed = joint_matrices.filter_map(|(i, m)| {
+            let bounds = self.joint_bounds.get(i).copied().flatten()?;
+            Some(bounds.transformed(&m))
+        });
+
+        transformed.fold(None, |acc: Option<BoundingBox>, mut bbox| {
+            if let Some(acc) = acc {
+                bbox.combine(acc);
+            }
+            Some(bbox)
+        })
+    }
+}
+
+/// Recomputes the bounding boxes of deformed meshes after the transforms have been propagated
+pub struct UpdateDeformedBounds;
+impl UpdateDeformedBounds {
+    pub const ID: &'static str = "UpdateDeformedBounds";
+}
+
+impl<'a> System<'a> for UpdateDeformedBounds {
+    type SystemData = (
+        ReadStorage<'a, DeformedBounds>,
+        ReadStorage<'a, Skeleton>,
+        ReadStorage<'a, MorphWeights>,
+        ReadStorage<'a, ModelMatrix>,
+        WriteStorage<'a, BoundingBox>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (deformed_bounds, skeletons, morph_weights, model_matrices, mut bboxes) = data;
+        for (bounds, skeleton, weights, mesh_model, bbox) in (
+            &deformed_bounds,
+            skeletons.maybe(),
+            morph_weights.maybe(),
+            &model_matrices,
+            &mut bboxes,
+        )
+            .join()
+        {
+            match skeleton {
+                Some(skeleton) => {
+                    let inv_mesh_model = mesh_model.0.inverted();
+                    let joint_matrices =
+                        skeleton.joints.iter().enumerate().filter_map(|(i, joint)| {
+                            let joint_model = model_matrices.get(*joint)?;
+                            Some((i, skeleton.joint_matrix(i, &joint_model.0, &inv_mesh_model)))
+                        });
+                    if let Some(skinned) = bounds.skinned(joint_matrices) {
+                        *bbox = skinned;
+                    }
+                }
+                None => *bbox = bounds.morphed(weights),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+}
+
+#[derive(Debug, Clone)]
+pub enum Keyframes {
+    Translation(Vec<Vec3>),
+    Rotation(Vec<Quat>),
+    Scale(Vec<f32>),
+    /// One set of morph target weights per keyframe
+    Weights(Vec<Vec<f32>>),
+}
+
+impl Keyframes {
+    pub fn len(&self) -> usize {
+        match self {
+            Keyframes::Translation(v) => v.len(),
+            Keyframes::Rotation(v) => v.len(),
+            Keyframes::Scale(v) => v.len(),
+            Keyframes::Weights(v) => v.len(),
+        }
+    }
+}
+
+/// Animates one property of the transform, or the morph weights, of the target entity
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub target: Entity,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+}
+
+// Returns the indices of the keyframes surrounding t and how far between them t is, in [0, 1].
+// Times before/after the first/last keyframe are clamped.
+fn keyframe_interval(times: &[f32], t: f32) -> (usize, usize, f32) {
+    assert!(!times.is_empty());
+    let next = times.iter().position(|x| *x > t).unwrap_or(times.len());
+    if next == 0 {
+        return (0, 0, 0.0);
+    }
+
+    if next == times.len() {
+        return (next - 1, next - 1, 0.0);
+    }
+
+    let prev = next - 1;
+    let span = times[next] - times[prev];
+    let factor = if span > 0.0 {
+        (t - times[prev]) / span
+    } else {
+        0.0
+    };
+
+    (prev, next, factor)
+}
+
+impl Channel {
+    pub fn duration(&self) -> f32 {
+        self.times.last().copied().unwrap_or(0.0)
+    }
+
+    pub fn animates_weights(&self) -> bool {
+        matches!(self.keyframes, Keyframes::Weights(_))
+    }
+
+    fn interval(&self, t: f32) -> Option<(usize, usize, f32)> {
+        if self.times.is_empty() || self.keyframes.len() != self.times.len() {
+            return None;
+        }
+
+        let (prev, next, factor) = keyframe_interval(&self.times, t);
+        let factor = match self.interpolation {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear => factor,
+        };
+
+        Some((prev, next, factor))
+    }
+
+    pub fn sample_weights(&self, t: f32, weights: &mut MorphWeights) {
+        let v = match &self.keyframes {
+            Keyframes::Weights(v) => v,
+            _ => return,
+        };
+
+        let (prev, next, factor) = match self.interval(t) {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        for (w, (a, b)) in weights.0.iter_mut().zip(v[prev].iter().zip(v[next].iter())) {
+            *w = a + (b - a) * factor;
+        }
+    }
+
+    pub fn sample(&self, t: f32, transform: &mut Transform) {
+        let (prev, next, factor) = match self.interval(t) {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        match &self.keyframes {
+            Keyframes::Translation(v) => {
+                transform.position = Vec3::lerp(v[prev], v[next], factor);
+            }
+            Keyframes::Rotation(v) => {
+                transform.rotation = Quat::slerp(v[prev], v[next], factor).normalized();
+            }
+            Keyframes::Scale(v) => {
+                transform.scale = v[prev] + (v[next] - v[prev]) * factor;
+            }
+            Keyframes::Weights(_) => (),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Component)]
+#[component(inspect, snapshot)]
+pub struct Animation {
+    pub duration: f32,
+    pub time: f32,
+    pub playing: bool,
+    pub looping: bool,
+    #[inspect(ignore)]
+    pub channels: Vec<Channel>,
+}
+
+impl Animation {
+    pub fn new(channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .map(Channel::duration)
+            .fold(0.0f32, |acc, d| acc.max(d));
+        Self {
+            duration,
+            time: 0.0,
+            playing: false,
+            looping: true,
+            channels,
+        }
+    }
+
+    pub fn advance(&mut self, dt: DeltaTime) {
+        self.time += dt.as_secs();
+        if self.time <= self.duration {
+            return;
+        }
+
+        if self.looping && self.duration > 0.0 {
+            self.time %= self.duration;
+        } else {
+            self.time = self.duration;
+            self.playing = false;
+        }
+    }
+
+    fn apply<'a>(
+        &self,
+        transforms: &mut WriteStorage<'a, Transform>,
+        morph_weights: &mut WriteStorage<'a, MorphWeights>,
+    ) {
+        for channel in self.channels.iter() {
+            if channel.animates_weights() {
+                if let Some(weights) = morph_weights.get_mut(channel.target) {
+                    channel.sample_weights(self.time, weights);
+                }
+            } else if let Some(transform) = transforms.get_mut(channel.target) {
+                channel.sample(self.time, transform);
+            }
+        }
+    }
+
+    /// Like apply, but the sampled values are returned instead of written to the targets
+    fn sample_pose<'a>(
+        &self,
+        transforms: &WriteStorage<'a, Transform>,
+        morph_weights: &WriteStorage<'a, MorphWeights>,
+    ) -> Pose {
+        let mut pose = Pose::default();
+        for channel in self.channels.iter() {
+            let target = channel.target;
+            if channel.animates_weights() {
+                if let Some(current) = morph_weights.get(target) {
+                    let weights = pose
+                        .weights
+                        .entry(target)
+                        .or_insert_with(|| current.clone());
+                    channel.sample_weights(self.time, weights);
+                }
+            } else if let Some(current) = transforms.get(target) {
+                let transform = pose.transforms.entry(target).or_insert(*current);
+                channel.sample(self.time, transform);
+            }
+        }
+
+        pose
+    }
+}
+
+/// The values that an animation gives its targets at some point in time
+#[derive(Debug, Default)]
+struct Pose {
+    transforms: HashMap<Entity, Transform>,
+    weights: HashMap<Entity, MorphWeights>,
+}
+
+fn blend_transforms(a: &Transform, b: &Transform, factor: f32) -> Transform {
+    Transform {
+        position: Vec3::lerp(a.position, b.position, factor),
+        rotation: Quat::slerp(a.rotation, b.rotation, factor).normalized(),
+        scale: a.scale + (b.scale - a.scale) * factor,
+    }
+}
+
+/// Write the blend of two poses to their targets. Targets that are only in one of the poses are
+/// blended with their current values.
+fn apply_blended<'a>(
+    from: &Pose,
+    to: &Pose,
+    factor: f32,
+    transforms: &mut WriteStorage<'a, Transform>,
+    morph_weights: &mut WriteStorage<'a, MorphWeights>,
+) {
+    let targets: HashSet<Entity> = from
+        .transforms
+        .keys()
+        .chain(to.transforms.keys())
+        .copied()
+        .collect();
+    for target in targets {
+        if let Some(transform) = transforms.get_mut(target) {
+            let a = from.transforms.get(&target).copied().unwrap_or(*transform);
+            let b = to.transforms.get(&target).copied().unwrap_or(*transform);
+            *transform = blend_transforms(&a, &b, factor);
+        }
+    }
+
+    let targets: HashSet<Entity> = from
+        .weights
+        .keys()
+        .chain(to.weights.keys())
+        .copied()
+        .collect();
+    for target in targets {
+        if let Some(weights) = morph_weights.get_mut(target) {
+            let current = weights.0.clone();
+            let a = from.weights.get(&target).map_or(&current, |w| &w.0);
+            let b = to.weights.get(&target).map_or(&current, |w| &w.0);
+            for (w, (a, b)) in weights.0.iter_mut().zip(a.iter().zip(b.iter())) {
+                *w = a + (b - a) * factor;
+            }
+        }
+    }
+}
+
+/// Plays one of the animations of an asset at a time and crossfades between them when switching.
+/// The animations are the entities with an Animation component. While they are controlled by a
+/// player, their playing flag is managed by it.
+#[derive(Debug, Clone, Default, Component)]
+#[component(inspect, snapshot)]
+pub struct AnimationPlayer {
+    /// The animation that is playing, or being faded in
+    pub current: Option<Entity>,
+    /// The animation that is being faded out
+    pub previous: Option<Entity>,
+    pub fade_duration: f32,
+    pub fade_remaining: f32,
+    #[inspect(ignore)]
+    started: bool,
+}
+
+impl AnimationPlayer {
+    /// Start playing the animation from the beginning, fading out the current one over crossfade
+    /// seconds
+    pub fn play(&mut self, animation: Entity, crossfade: f32) {
+        if crossfade > 0.0 && self.current.is_some() && self.current != Some(animation) {
+            self.previous = self.current;
+            self.fade_duration = crossfade;
+            self.fade_remaining = crossfade;
+        } else {
+            self.previous = None;
+            self.fade_remaining = 0.0;
+        }
+        self.current = Some(animation);
+        self.started = false;
+    }
+
+    /// The weight of the current animation, in [0, 1]. The previous one has the rest.
+    pub fn blend_factor(&self) -> f32 {
+        if self.previous.is_none() || self.fade_duration <= 0.0 {
+            return 1.0;
+        }
+
+        (1.0 - self.fade_remaining / self.fade_duration)
+            .max(0.0)
+            .min(1.0)
+    }
+
+    fn animations(&self) -> impl Iterator<Item = Entity> {
+        self.current.into_iter().chain(self.previous)
+    }
+
+    /// Returns the animation that finished fading out, if any
+    fn advance(&mut self, dt: DeltaTime) -> Option<Entity> {
+        if self.previous.is_none() {
+            return None;
+        }
+
+        self.fade_remaining -= dt.as_secs();
+        if self.fade_remaining > 0.0 {
+            return None;
+        }
+
+        self.fade_remaining = 0.0;
+        self.previous.take()
+    }
+}
+
+pub struct AnimationSampler;
+impl AnimationSampler {
+    pub const ID: &'static str = "AnimationSampler";
+}
+
+impl<'a> System<'a> for AnimationSampler {
+    type SystemData = (
+        Entities<'a>,
+        ReadExpect<'a, Time>,
+        WriteStorage<'a, AnimationPlayer>,
+        WriteStorage<'a, Animation>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, MorphWeights>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, time, mut players, mut animations, mut transforms, mut morph_weights) = data;
+        let dt = time.delta_sim();
+        let controlled: HashSet<Entity> = players.join().flat_map(|p| p.animations()).collect();
+        for (ent, animation) in (&entities, &mut animations).join() {
+            if !animation.playing || controlled.contains(&ent) {
+                continue;
+            }
+
+            animation.advance(dt);
+            animation.apply(&mut transforms, &mut morph_weights);
+        }
+
+        for player in (&mut players).join() {
+            let current = match player.current {
+                Some(current) => current,
+                None => continue,
+            };
+
+            if !player.started {
+                if let Some(animation) = animations.get_mut(current) {
+                    animation.time = 0.0;
+                    animation.playing = true;
+                }
+                player.started = true;
+            }
+
+            if let Some(finished) = player.advance(dt) {
+                if let Some(animation) = animations.get_mut(finished) {
+                    animation.playing = false;
+                }
+            }
+
+            let factor = player.blend_factor();
+            let mut poses = Vec::with_capacity(2);
+            for ent in player.previous.into_iter().chain(Some(current)) {
+                if let Some(animation) = animations.get_mut(ent) {
+                    if animation.playing {
+                        animation.advance(dt);
+                    }
+                    if player.previous.is_some() {
+                        poses.push(animation.sample_pose(&transforms, &morph_weights));
+                    } else {
+                        animation.apply(&mut transforms, &mut morph_weights);
+                    }
+                }
+            }
+
+            if let [from, to] = poses.as_slice() {
+                apply_blended(from, to, factor, &mut transforms, &mut morph_weights);
+            }
+        }
+    }
+}
+
+pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
+    builder.with(AnimationSampler, AnimationSampler::ID, &[])
+}
+
+/// These systems depend on the propagated transforms
+pub fn register_post_transform_systems<'a, 'b>(
+    builder: ExecutorBuilder<'a, 'b>,
+) -> ExecutorBuilder<'a, 'b> {
+    builder.with(
+        UpdateDeformedBounds,
+        UpdateDeformedBounds::ID,
+        &[crate::graph::TransformPropagation::ID],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vek::approx::assert_abs_diff_eq;
+
+    #[test]
+    fn interval() {
+        let times = [0.0, 1.0, 3.0];
+        assert_eq!(keyframe_interval(&times, -1.0), (0, 0, 0.0));
+        assert_eq!(keyframe_interval(&times, 0.0), (0, 1, 0.0));
+        assert_eq!(keyframe_interval(&times, 0.5), (0, 1, 0.5));
+        assert_eq!(keyframe_interval(&times, 2.0), (1, 2, 0.5));
+        assert_eq!(keyframe_interval(&times, 3.0), (2, 2, 0.0));
+        assert_eq!(keyframe_interval(&times, 10.0), (2, 2, 0.0));
+    }
+
+    fn translation_channel(interpolation: Interpolation) -> Channel {
+        let mut world = World::new();
+        Channel {
+            target: world.create_entity().build(),
+            interpolation,
+            times: vec![0.0, 2.0],
+            keyframes: Keyframes::Translation(vec![
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(2.0, 4.0, 0.0),
+            ]),
+        }
+    }
+
+    #[test]
+    fn sample_linear() {
+        let channel = translation_channel(Interpolation::Linear);
+        let mut t = Transform::identity();
+        channel.sample(0.5, &mut t);
+        assert_abs_diff_eq!(t.position, Vec3::new(0.5, 1.0, 0.0));
+        channel.sample(5.0, &mut t);
+        assert_abs_diff_eq!(t.position, Vec3::new(2.0, 4.0, 0.0));
+    }
+
+    #[test]
+    fn sample_step() {
+        let channel = translation_channel(Interpolation::Step);
+        let mut t = Transform::identity();
+        channel.sample(1.9, &mut t);
+        assert_abs_diff_eq!(t.position, Vec3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn sample_weights() {
+        let mut world = World::new();
+        let channel = Channel {
+            target: world.create_entity().build(),
+            interpolation: Interpolation::Linear,
+            times: vec![0.0, 1.0],
+            keyframes: Keyframes::Weights(vec![vec![0.0, 1.0], vec![1.0, 0.0]]),
+        };
+        let mut weights = MorphWeights(vec![0.0, 0.0]);
+        channel.sample_weights(0.25, &mut weights);
+        assert_abs_diff_eq!(weights.0[0], 0.25);
+        assert_abs_diff_eq!(weights.0[1], 0.75);
+    }
+
+    #[test]
+    fn morphed_bounds() {
+        let positions = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0)];
+        let targets = [vec![Vec3::new(0.0, 2.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)]];
+        let bounds = DeformedBounds::new(&positions, &targets, None).unwrap();
+        assert!(bounds.joint_bounds.is_empty());
+
+        let bbox = bounds.morphed(None);
+        assert_abs_diff_eq!(bbox.min, Vec3::new(0.0, 0.0, 0.0));
+        assert_abs_diff_eq!(bbox.max, Vec3::new(1.0, 1.0, 1.0));
+
+        let bbox = bounds.morphed(Some(&MorphWeights(vec![0.5])));
+        assert_abs_diff_eq!(bbox.min, Vec3::new(-0.5, 0.0, 0.0));
+        assert_abs_diff_eq!(bbox.max, Vec3::new(1.0, 2.0, 1.0));
+
+        // Negative weights displace in the other direction
+        let bbox = bounds.morphed(Some(&MorphWeights(vec![-1.0])));
+        assert_abs_diff_eq!(bbox.min, Vec3::new(0.0, -2.0, 0.0));
+        assert_abs_diff_eq!(bbox.max, Vec3::new(2.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn skinned_bounds() {
+        let positions = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)];
+        let skin = [
+            ([0, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]),
+            ([1, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]),
+        ];
+        let bounds = DeformedBounds::new(&positions, &[], Some(&skin)).unwrap();
+        assert_eq!(bounds.joint_bounds.len(), 2);
+
+        // The second joint is moved one unit along x
+        let matrices = vec![
+            (0, Mat4::identity()),
+            (1, Mat4::translation_3d(Vec3::new(1.0, 0.0, 0.0))),
+        ];
+        let bbox = bounds.skinned(matrices.into_iter()).unwrap();
+        assert_abs_diff_eq!(bbox.min, Vec3::new(0.0, 0.0, 0.0));
+        assert_abs_diff_eq!(bbox.max, Vec3::new(1.0, 1.0, 0.0));
+
+        // Rotating a joint by 90 degrees around z
+        let matrices = vec![(1, Mat4::rotation_z(std::f32::consts::FRAC_PI_2))];
+        let bbox = bounds.skinned(matrices.into_iter()).unwrap();
+        assert_abs_diff_eq!(bbox.min, Vec3::new(-1.0, 0.0, 0.0), epsilon = 0.0001);
+        assert_abs_diff_eq!(bbox.max, Vec3::new(-1.0, 0.0, 0.0), epsilon = 0.0001);
+    }
+
+    #[test]
+    fn crossfade() {
+        let mut world = World::new();
+        let a = world.create_entity().build();
+        let b = world.create_entity().build();
+
+        let mut player = AnimationPlayer::default();
+        player.play(a, 1.0);
+        assert_eq!(player.previous, None);
+        assert_abs_diff_eq!(player.blend_factor(), 1.0);
+
+        player.play(b, 2.0);
+        assert_eq!(player.animations().collect::<Vec<_>>(), vec![b, a]);
+        assert_abs_diff_eq!(player.blend_factor(), 0.0);
+
+        let dt = DeltaTime::from(std::time::Duration::from_secs_f32(0.5));
+        assert_eq!(player.advance(dt), None);
+        assert_abs_diff_eq!(player.blend_factor(), 0.25, epsilon = 0.0001);
+        for _ in 0..2 {
+            assert_eq!(player.advance(dt), None);
+        }
+        assert_eq!(player.advance(dt), Some(a));
+        assert_eq!(player.previous, None);
+        assert_abs_diff_eq!(player.blend_factor(), 1.0);
+    }
+
+    #[test]
+    fn blend() {
+        let a = Transform::pos(0.0, 0.0, 0.0);
+        let b = Transform {
+            position: Vec3::new(2.0, 0.0, 0.0),
+            scale: 3.0,
+            ..Default::default()
+        };
+        let t = blend_transforms(&a, &b, 0.5);
+        assert_abs_diff_eq!(t.position, Vec3::new(1.0, 0.0, 0.0));
+        assert_abs_diff_eq!(t.scale, 2.0);
+    }
+
+    #[test]
+    fn advance_loops() {
+        let mut anim = Animation::new(vec![translation_channel(Interpolation::Linear)]);
+        assert_abs_diff_eq!(anim.duration, 2.0);
+        anim.playing = true;
+        anim.advance(DeltaTime::from(std::time::Duration::from_secs_f32(2.5)));
+        assert_abs_diff_eq!(anim.time, 0.5, epsilon = 0.0001);
+        assert!(anim.playing);
+
+        anim.looping = false;
+        anim.advance(DeltaTime::from(std::time::Duration::from_secs_f32(2.0)));
+        assert_abs_diff_eq!(anim.time, 2.0);
+        assert!(!anim.playing);
+    }
+}
@@ -0,0 +1,322 @@
+//! Load and unload assets while the app is running. Commands are queued in the AssetCommands
+//! resource, from the ui or any system, and applied by AssetCommandSys before the loaders run.
+
+use crate::ecs::prelude::*;
+
+use imgui::*;
+
+use std::path::{Path, PathBuf};
+
+use super::gltf::{GltfAsset, LoadGltfAsset, SceneSelection};
+use super::mesh_file::{self, LoadMeshFile, MeshFileAsset};
+use super::rsf::LoadRsfAsset;
+use super::ContentDirectory;
+use crate::graph::{sys as graph, Children, Parent};
+use crate::render::ui::UiFrame;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetCommand {
+    Load {
+        path: PathBuf,
+        scene: SceneSelection,
+    },
+    /// Delete a loaded glTF root and everything below it
+    Unload(Entity),
+    /// Unload all the glTF roots that were loaded from this file
+    UnloadPath(PathBuf),
+}
+
+/// The queue of asset commands. Applied once per frame, in the order they were queued.
+#[derive(Debug, Default)]
+pub struct AssetCommands {
+    queue: Vec<AssetCommand>,
+}
+
+impl AssetCommands {
+    pub fn load(&mut self, path: impl Into<PathBuf>) {
+        self.load_scene(path, SceneSelection::Default);
+    }
+
+    pub fn load_scene(&mut self, path: impl Into<PathBuf>, scene: SceneSelection) {
+        self.queue.push(AssetCommand::Load {
+            path: path.into(),
+            scene,
+        });
+    }
+
+    pub fn unload(&mut self, root: Entity) {
+        self.queue.push(AssetCommand::Unload(root));
+    }
+
+    pub fn unload_path(&mut self, path: impl Into<PathBuf>) {
+        self.queue.push(AssetCommand::UnloadPath(path.into()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    fn drain(&mut self) -> Vec<AssetCommand> {
+        std::mem::take(&mut self.queue)
+    }
+}
+
+fn is_gltf(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("gltf") | Some("glb")
+    )
+}
+
+fn is_rsf(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()) == Some("rsf")
+}
+
+pub struct AssetCommandSys;
+
+impl AssetCommandSys {
+    pub const ID: &'static str = "AssetCommandSys";
+}
+
+#[derive(SystemData)]
+pub struct AssetCommandData<'a> {
+    entities: Entities<'a>,
+    commands: Write<'a, AssetCommands>,
+    load_gltf: WriteStorage<'a, LoadGltfAsset>,
+    load_rsf: WriteStorage<'a, LoadRsfAsset>,
+    load_mesh_file: WriteStorage<'a, LoadMeshFile>,
+    gltf_assets: ReadStorage<'a, GltfAsset>,
+    mesh_file_assets: ReadStorage<'a, MeshFileAsset>,
+    parents: ReadStorage<'a, Parent>,
+    children: WriteStorage<'a, Children>,
+}
+
+impl<'a> AssetCommandData<'a> {
+    fn unload(&mut self, root: Entity) {
+        if !self.entities.is_alive(root) {
+            log::warn!("Can't unload {:?}, it has already been deleted", root);
+            return;
+        }
+
+        if let Some(Parent { parent }) = self.parents.get(root) {
+            if let Some(siblings) = self.children.get_mut(*parent) {
+                siblings.children.retain(|c| *c != root);
+            }
+        }
+
+        let mut nodes = Vec::new();
+        graph::breadth_first(&self.children, root, |node| nodes.push(node));
+        for node in nodes {
+            self.entities.delete(node).expect("Failed to delete entity");
+        }
+    }
+}
+
+impl<'a> System<'a> for AssetCommandSys {
+    type SystemData = AssetCommandData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        for cmd in data.commands.drain() {
+            match cmd {
+                AssetCommand::Load { path, scene } => {
+                    log::info!("Loading {}", path.display());
+                    let ent = data.entities.create();
+                    if is_gltf(&path) {
+                        data.load_gltf
+                            .insert(ent, LoadGltfAsset::new(path, scene))
+                            .expect("Entity was just created");
+                    } else if is_rsf(&path) {
+                        data.load_rsf
+                            .insert(ent, LoadRsfAsset::new(path))
+                            .expect("Entity was just created");
+                    } else if mesh_file::is_mesh_file(&path) {
+                        data.load_mesh_file
+                            .insert(ent, LoadMeshFile::new(path))
+                            .expect("Entity was just created");
+                    } else {
+                        log::error!("Don't know how to load {}", path.display());
+                        data.entities.delete(ent).expect("Entity was just created");
+                    }
+                }
+                AssetCommand::Unload(root) => data.unload(root),
+                AssetCommand::UnloadPath(path) => {
+                    let roots: Vec<Entity> = (
+                        &data.entities,
+                        data.gltf_assets.maybe(),
+                        data.mesh_file_assets.maybe(),
+                    )
+                        .join()
+                        .filter(|(_, gltf, mesh_file)| {
+                            gltf.map(|a| a.path()) == Some(path.as_path())
+                                || mesh_file.map(|a| a.path()) == Some(path.as_path())
+                        })
+                        .map(|(ent, _, _)| ent)
+                        .collect();
+                    if roots.is_empty() {
+                        log::warn!("{} is not loaded", path.display());
+                    }
+                    for root in roots {
+                        data.unload(root);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// State of the file-open dialog
+#[derive(Default)]
+struct OpenDialogState {
+    dir: Option<PathBuf>,
+    /// Sub-directories and loadable files in `dir`, directories first
+    entries: Vec<PathBuf>,
+}
+
+impl OpenDialogState {
+    fn set_dir(&mut self, dir: PathBuf) {
+        self.entries.clear();
+        match std::fs::read_dir(&dir) {
+            Ok(read_dir) => {
+                self.entries
+                    .extend(
+                        read_dir
+                            .filter_map(Result::ok)
+                            .map(|e| e.path())
+                            .filter(|p| {
+                                p.is_dir() || is_gltf(p) || is_rsf(p) || mesh_file::is_mesh_file(p)
+                            }),
+                    );
+            }
+            Err(e) => log::error!("Failed to read {}: {}", dir.display(), e),
+        }
+        self.entries
+            .sort_by(|a, b| b.is_dir().cmp(&a.is_dir()).then_with(|| a.cmp(b)));
+        self.dir = Some(dir);
+    }
+}
+
+/// Lists the loaded glTF assets and has a file dialog to load more
+pub(crate) fn build_ui<'a>(world: &mut World, ui: &UiFrame<'a>, pos: [f32; 2]) -> [f32; 2] {
+    let size = [300.0, 200.0];
+
+    Window::new(im_str!("Loaded assets"))
+        .position(pos, Condition::FirstUseEver)
+        .size(size, Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let mut commands = world.write_resource::<AssetCommands>();
+
+            let modal_id = im_str!("Open asset");
+            if ui.inner().button(im_str!("Open..."), [0.0, 0.0]) {
+                ui.inner().open_popup(modal_id);
+            }
+            ui.inner().popup_modal(modal_id).build(|| {
+                let id = String::from("OpenAssetDialog");
+                let mut storage = ui.storage();
+                if !storage.contains_key(&id) {
+                    storage.insert(id.clone(), OpenDialogState::default());
+                }
+                let state: &mut OpenDialogState = storage
+                    .get_mut(&id)
+                    .expect("Open dialog state was just inserted");
+
+                if state.dir.is_none() {
+                    let start = world
+                        .try_fetch::<ContentDirectory>()
+                        .map(|dir| dir.0.clone())
+                        .or_else(|| std::env::current_dir().ok())
+                        .unwrap_or_else(|| PathBuf::from("."));
+                    state.set_dir(start);
+                }
+                let dir = state.dir.clone().expect("Dir was just set");
+
+                ui.inner().text(im_str!("{}", dir.display()));
+                ui.inner().separator();
+                let mut next_dir = None;
+                if let Some(parent) = dir.parent() {
+                    if Selectable::new(im_str!("..")).build(ui.inner()) {
+                        next_dir = Some(parent.to_path_buf());
+                    }
+                }
+                for entry in &state.entries {
+                    let name = entry.file_name().unwrap_or_else(|| entry.as_os_str());
+                    if entry.is_dir() {
+                        let label = im_str!("{}/", name.to_string_lossy());
+                        if Selectable::new(&label).build(ui.inner()) {
+                            next_dir = Some(entry.clone());
+                        }
+                    } else if Selectable::new(&im_str!("{}", name.to_string_lossy()))
+                        .build(ui.inner())
+                    {
+                        commands.load(entry.clone());
+                        ui.inner().close_current_popup();
+                    }
+                }
+                if let Some(next_dir) = next_dir {
+                    state.set_dir(next_dir);
+                }
+
+                ui.inner().separator();
+                if ui.inner().button(im_str!("Cancel"), [0.0, 0.0]) {
+                    ui.inner().close_current_popup();
+                }
+            });
+            ui.inner().separator();
+
+            let entities = world.entities();
+            let gltf_assets = world.read_storage::<GltfAsset>();
+            for (ent, asset) in (&entities, &gltf_assets).join() {
+                let id_token = ui.inner().push_id(ent.id() as i32);
+                if ui.inner().button(im_str!("Unload"), [0.0, 0.0]) {
+                    commands.unload(ent);
+                }
+                ui.inner().same_line(0.0);
+                ui.inner().text(im_str!("{}", asset.path().display()));
+                id_token.pop(ui.inner());
+            }
+        });
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use specs::RunNow;
+
+    #[test]
+    fn commands_load_by_extension_and_unload_hierarchies() {
+        let mut world = World::new();
+        crate::ecs::meta::register_all_components(&mut world);
+        world.insert(AssetCommands::default());
+
+        {
+            let mut commands = world.write_resource::<AssetCommands>();
+            commands.load("a/model.glb");
+            commands.load("scene.rsf");
+            commands.load("scan.ply");
+            commands.load("notes.txt");
+        }
+        AssetCommandSys.run_now(&world);
+        world.maintain();
+        assert!(world.read_resource::<AssetCommands>().is_empty());
+        assert_eq!(world.read_storage::<LoadGltfAsset>().join().count(), 1);
+        assert_eq!(world.read_storage::<LoadRsfAsset>().join().count(), 1);
+        assert_eq!(world.read_storage::<LoadMeshFile>().join().count(), 1);
+        assert_eq!(world.entities().join().count(), 3);
+
+        let root = world.create_entity().build();
+        let child = world.create_entity().build();
+        let grandchild = world.create_entity().build();
+        crate::graph::world::add_edge(&mut world, root, child);
+        crate::graph::world::add_edge(&mut world, child, grandchild);
+        let other = world.create_entity().build();
+
+        world.write_resource::<AssetCommands>().unload(root);
+        AssetCommandSys.run_now(&world);
+        world.maintain();
+        for ent in &[root, child, grandchild] {
+            assert!(!world.is_alive(*ent));
+        }
+        assert!(world.is_alive(other));
+    }
+}
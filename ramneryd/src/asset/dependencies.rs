@@ -0,0 +1,177 @@
+use std::path::{Path, PathBuf};
+
+use crate::ecs::prelude::*;
+use crate::file_watcher::{PollTimer, WatchedFiles};
+use crate::render::material::ReloadTextures;
+
+use super::gltf::{GltfAsset, LoadGltfAsset};
+
+/// How a source file is used by its dependents. This decides how much has to be reloaded when
+/// the file changes on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependencyKind {
+    /// An image that is used as a texture by the materials of the dependents
+    Texture,
+    /// The document or a binary buffer of a glTF file. The dependents are the root entities of
+    /// the loaded glTF assets.
+    Gltf,
+}
+
+#[derive(Debug)]
+struct SourceFile {
+    kind: DependencyKind,
+    dependents: Vec<Entity>,
+}
+
+/// Tracks which entities were created from which files on disk
+#[derive(Debug, Default)]
+pub struct AssetDependencies {
+    sources: WatchedFiles<SourceFile>,
+}
+
+impl AssetDependencies {
+    pub fn add(&mut self, path: &Path, kind: DependencyKind, ent: Entity) {
+        let source = self.sources.watch(path, || SourceFile {
+            kind,
+            dependents: Vec::new(),
+        });
+
+        if !source.dependents.contains(&ent) {
+            source.dependents.push(ent);
+        }
+    }
+
+    pub fn dependents(&self, path: &Path) -> &[Entity] {
+        self.sources
+            .get(path)
+            .map(|s| s.dependents.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Returns the sources that have been modified since the last poll, along with their dependents.
+    /// Dead dependents are pruned and sources without dependents are no longer tracked.
+    fn poll(&mut self, entities: &Entities) -> Vec<(PathBuf, DependencyKind, Vec<Entity>)> {
+        self.sources.retain(|_, source| {
+            source.dependents.retain(|ent| entities.is_alive(*ent));
+            !source.dependents.is_empty()
+        });
+
+        self.sources
+            .poll()
+            .into_iter()
+            .map(|(path, source)| (path, source.kind, source.dependents.clone()))
+            .collect()
+    }
+}
+
+/// Polls the tracked source files and schedules reloads of the parts of the assets that changed
+#[derive(Default)]
+pub struct AssetWatcher {
+    timer: PollTimer,
+}
+
+impl AssetWatcher {
+    pub const ID: &'static str = "AssetWatcher";
+}
+
+#[derive(SystemData)]
+pub struct AssetWatcherData<'a> {
+    entities: Entities<'a>,
+    dependencies: Write<'a, AssetDependencies>,
+    reload_textures: WriteStorage<'a, ReloadTextures>,
+    gltf_assets: ReadStorage<'a, GltfAsset>,
+    load_gltf_assets: WriteStorage<'a, LoadGltfAsset>,
+}
+
+impl<'a> System<'a> for AssetWatcher {
+    type SystemData = AssetWatcherData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        if !self.timer.ready() {
+            return;
+        }
+
+        let Self::SystemData {
+            entities,
+            mut dependencies,
+            mut reload_textures,
+            gltf_assets,
+            mut load_gltf_assets,
+        } = data;
+
+        for (path, kind, dependents) in dependencies.poll(&entities) {
+            log::info!("{} changed on disk", path.display());
+            match kind {
+                DependencyKind::Texture => {
+                    for ent in dependents {
+                        let reload = reload_textures
+                            .entry(ent)
+                            .expect("Dependents are alive")
+                            .or_insert_with(ReloadTextures::default);
+                        reload.0.push(path.clone());
+                    }
+                }
+                DependencyKind::Gltf => {
+                    for ent in dependents {
+                        if let Some(asset) = gltf_assets.get(ent) {
+                            load_gltf_assets
+                                .insert(ent, asset.reload())
+                                .expect("Dependents are alive");
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dead_dependents_are_pruned() {
+        let mut world = World::new();
+        let alive = world.create_entity().build();
+        let dead = world.create_entity().build();
+
+        let mut deps = AssetDependencies::default();
+        let path = Path::new("does/not/exist.png");
+        deps.add(path, DependencyKind::Texture, alive);
+        deps.add(path, DependencyKind::Texture, alive);
+        deps.add(path, DependencyKind::Texture, dead);
+        assert_eq!(deps.dependents(path), &[alive, dead]);
+
+        world.delete_entity(dead).unwrap();
+        assert!(deps.poll(&world.entities()).is_empty());
+        assert_eq!(deps.dependents(path), &[alive]);
+
+        world.delete_entity(alive).unwrap();
+        deps.poll(&world.entities());
+        assert!(deps.sources.is_empty());
+    }
+
+    #[test]
+    fn modified_sources_are_reported() {
+        let mut world = World::new();
+        let ent = world.create_entity().build();
+
+        let path = std::env::temp_dir().join("ramneryd_dependency_test.png");
+        std::fs::write(&path, b"").unwrap();
+
+        let mut deps = AssetDependencies::default();
+        deps.add(&path, DependencyKind::Texture, ent);
+        assert!(deps.poll(&world.entities()).is_empty());
+
+        // Pretend the file was written after it was loaded
+        deps.sources.touch(&path);
+        let changed = deps.poll(&world.entities());
+        assert_eq!(
+            changed,
+            vec![(path.clone(), DependencyKind::Texture, vec![ent])]
+        );
+        assert!(deps.poll(&world.entities()).is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
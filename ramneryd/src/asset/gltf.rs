@@ -1,6 +1,9 @@
 use crate::ecs;
 use crate::ecs::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 
 use trekanten::mem::BufferMutability;
 use trekanten::mem::{OwningIndexBufferDescriptor, OwningVertexBufferDescriptor};
@@ -9,17 +12,63 @@ use trekanten::texture::{MipMaps, TextureDescriptor};
 use trekanten::util;
 use trekanten::vertex::VertexFormat;
 
+use super::dependencies::{AssetDependencies, DependencyKind};
+use super::server::AssetServer;
+use super::timings::LoadTimings;
+use crate::anim::{
+    Animation, AnimationPlayer, Channel, DeformedBounds, Interpolation, Joint, Keyframes,
+    MorphWeights, Skeleton,
+};
 use crate::camera::Camera;
 use crate::common::Name;
 use crate::graph::sys as graph;
 use crate::math::*;
+use crate::metadata::Metadata;
 use crate::render;
-use crate::render::material::{PhysicallyBased, TextureUse2};
-use crate::render::mesh::CpuMesh;
-use crate::render::uniform::PBRMaterialData;
+use crate::render::lod::{self, LodGroup};
+use crate::render::material::{
+    MaterialId, MaterialLibrary, PhysicallyBased, SharedMaterial, TextureUse2, UsesMaterial,
+    DEFAULT_IOR,
+};
+use crate::render::mesh::{CpuMesh, SharedMesh};
+use crate::render::uniform::{PBRMaterialData, MAX_NUM_JOINTS, MAX_NUM_MORPH_TARGETS};
+
+// Images with the same content are loaded from the first file that had it, so that the materials
+// that use them can share the texture
+fn dedup_image(ctx: &mut RecGltfCtx, image_index: usize, image_path: PathBuf) -> PathBuf {
+    use std::hash::{Hash, Hasher};
+    if let Some(path) = ctx.image_paths.get(&image_index) {
+        return path.clone();
+    }
+
+    profiling::scope!("hash image");
+    let path = match std::fs::read(&image_path) {
+        Ok(bytes) => {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            bytes.hash(&mut hasher);
+            let path = ctx
+                .image_hashes
+                .entry(hasher.finish())
+                .or_insert_with(|| image_path.clone())
+                .clone();
+            if path != image_path {
+                log::debug!(
+                    "{} is the same image as {}",
+                    image_path.display(),
+                    path.display()
+                );
+            }
+            path
+        }
+        // The loader reports the error when it fails to read it
+        Err(_) => image_path,
+    };
+    ctx.image_paths.insert(image_index, path.clone());
+    path
+}
 
 fn load_texture(
-    ctx: &RecGltfCtx,
+    ctx: &mut RecGltfCtx,
     texture: &gltf::texture::Texture,
     coord_set: u32,
     format: util::Format,
@@ -34,15 +83,15 @@ fn load_texture(
         gltf::texture::WrappingMode::Repeat
     );
 
-    let image_src = texture.source().source();
+    let image = texture.source();
 
     use gltf::image::Source;
-    let image_path = match image_src {
+    let image_path = match image.source() {
         Source::Uri { uri, .. } => {
             let parent_path = Path::new(&ctx.path).parent().expect("Invalid path");
             let mut image_path = parent_path.to_path_buf();
             image_path.push(uri);
-            image_path
+            dedup_image(ctx, image.index(), image_path)
         }
         x => unimplemented!("Unsupported image source {:?}", x),
     };
@@ -62,16 +111,23 @@ fn check_supported<'a>(primitive: &gltf::Primitive<'a>) {
             Semantic::Tangents => (),
             Semantic::Colors(0) => (),
             Semantic::TexCoords(0) => (),
+            Semantic::Joints(0) => (),
+            Semantic::Weights(0) => (),
             _ => unimplemented!("Unsupported semantic: {:?}", semantic),
         }
     }
 }
 
 // TODO: Find a way to handle binding mapping here and in shader in one place.
+// The attribute order is: position, normal, joints, weights, morph target position deltas,
+// morph target normal deltas, uv, color, tangent.
+// Joints and weights are only used if the mesh is skinned.
+// Returns the vertex buffer, if it has vertex colors and the number of morph targets
 fn interleave_vertex_buffer<'a>(
-    ctx: &RecGltfCtx,
+    ctx: &mut RecGltfCtx,
     primitive: &gltf::Primitive<'a>,
-) -> (OwningVertexBufferDescriptor, bool) {
+    skinned: bool,
+) -> (OwningVertexBufferDescriptor, bool, usize) {
     check_supported(primitive);
     let reader = primitive.reader(|buffer| Some(&ctx.buffers[buffer.index()]));
     let positions = reader.read_positions().expect("Found no positions");
@@ -81,9 +137,70 @@ fn interleave_vertex_buffer<'a>(
         .add_attribute(util::Format::FLOAT3) // position
         .add_attribute(util::Format::FLOAT3); // normal
 
-    let tangents = reader.read_tangents();
-    let tex_coords = reader.read_tex_coords(0);
-    let colors = reader.read_colors(0);
+    // skinned is only set if the primitive has both, see has_skin_attributes
+    let skin: Option<Vec<([f32; 4], [f32; 4])>> = reader
+        .read_joints(0)
+        .zip(reader.read_weights(0))
+        .filter(|_| skinned)
+        .map(|(joints, weights)| {
+            joints
+                .into_u16()
+                .map(|j| [j[0] as f32, j[1] as f32, j[2] as f32, j[3] as f32])
+                .zip(weights.into_f32())
+                .collect()
+        });
+    let n_vertices = primitive
+        .get(&gltf::mesh::Semantic::Positions)
+        .expect("Found no positions")
+        .count();
+    let n_morph_targets = primitive.morph_targets().count();
+    if n_morph_targets > MAX_NUM_MORPH_TARGETS {
+        log::warn!(
+            "Only {} of {} morph targets are used",
+            MAX_NUM_MORPH_TARGETS,
+            n_morph_targets
+        );
+    }
+    let morph_targets: Vec<(Vec<[f32; 3]>, Vec<[f32; 3]>)> = reader
+        .read_morph_targets()
+        .take(MAX_NUM_MORPH_TARGETS)
+        .map(|(positions, normals, _tangents)| {
+            (
+                positions
+                    .map(|p| p.collect())
+                    .unwrap_or_else(|| vec![[0.0; 3]; n_vertices]),
+                normals
+                    .map(|n| n.collect())
+                    .unwrap_or_else(|| vec![[0.0; 3]; n_vertices]),
+            )
+        })
+        .collect();
+    let tex_coords: Option<Vec<[f32; 2]>> =
+        reader.read_tex_coords(0).map(|t| t.into_f32().collect());
+    let colors: Option<Vec<[f32; 4]>> = reader.read_colors(0).map(|c| c.into_rgba_f32().collect());
+    let mut tangents: Option<Vec<[f32; 4]>> = reader.read_tangents().map(|t| t.collect());
+
+    let positions: Vec<[f32; 3]> = positions.collect();
+    let normals: Vec<[f32; 3]> = normals.collect();
+    let has_normal_map = primitive.material().normal_texture().is_some();
+    if let (true, None, Some(tex_coords)) = (has_normal_map, &tangents, &tex_coords) {
+        log::debug!("Generating tangents for normal mapped mesh without tangents");
+        profiling::scope!("generate tangents");
+        let timer = ctx.timings.start("tangents");
+        let generated = generate_tangents(ctx, primitive, &positions, &normals, tex_coords);
+        ctx.timings.stop(timer);
+        tangents = Some(generated);
+    }
+
+    if skin.is_some() {
+        format = format
+            .add_attribute(util::Format::FLOAT4) // joints
+            .add_attribute(util::Format::FLOAT4); // weights
+    }
+
+    for _ in 0..(2 * morph_targets.len()) {
+        format = format.add_attribute(util::Format::FLOAT3);
+    }
 
     if tex_coords.is_some() {
         format = format.add_attribute(util::Format::FLOAT2);
@@ -98,53 +215,66 @@ fn interleave_vertex_buffer<'a>(
     }
 
     let format = format.build();
+    let has_vertex_colors = colors.is_some();
 
     // TODO: Prealloc
     let mut data = Vec::new();
-    let has_vertex_colors = colors.is_some();
-
-    // TODO: How to map this to shader layout?
-    let it = positions.zip(normals);
-    match (colors, tex_coords, tangents) {
-        (None, Some(tex_coords), Some(tangents)) => {
-            for ((uv, tan), (pos, nor)) in tex_coords.into_f32().zip(tangents).zip(it) {
-                data.extend_from_slice(util::as_bytes(&pos));
-                data.extend_from_slice(util::as_bytes(&nor));
-                data.extend_from_slice(util::as_bytes(&uv));
-                data.extend_from_slice(util::as_bytes(&tan));
-            }
+    for (i, (pos, nor)) in positions.iter().zip(normals.iter()).enumerate() {
+        data.extend_from_slice(util::as_bytes(pos));
+        data.extend_from_slice(util::as_bytes(nor));
+        if let Some(skin) = &skin {
+            data.extend_from_slice(util::as_bytes(&skin[i].0));
+            data.extend_from_slice(util::as_bytes(&skin[i].1));
         }
-        (Some(colors), Some(tex_coords), None) => {
-            for ((uv, col), (pos, nor)) in tex_coords.into_f32().zip(colors.into_rgba_f32()).zip(it)
-            {
-                data.extend_from_slice(util::as_bytes(&pos));
-                data.extend_from_slice(util::as_bytes(&nor));
-                data.extend_from_slice(util::as_bytes(&uv));
-                data.extend_from_slice(util::as_bytes(&col));
-            }
+        for (positions, _) in morph_targets.iter() {
+            data.extend_from_slice(util::as_bytes(&positions[i]));
         }
-        (None, Some(tex_coords), None) => {
-            for (uv, (pos, nor)) in tex_coords.into_f32().zip(it) {
-                data.extend_from_slice(util::as_bytes(&pos));
-                data.extend_from_slice(util::as_bytes(&nor));
-                data.extend_from_slice(util::as_bytes(&uv));
-            }
+        for (_, normals) in morph_targets.iter() {
+            data.extend_from_slice(util::as_bytes(&normals[i]));
         }
-        (None, None, None) => {
-            for (pos, nor) in it {
-                data.extend_from_slice(util::as_bytes(&pos));
-                data.extend_from_slice(util::as_bytes(&nor));
-            }
+        if let Some(tex_coords) = &tex_coords {
+            data.extend_from_slice(util::as_bytes(&tex_coords[i]));
+        }
+        if let Some(colors) = &colors {
+            data.extend_from_slice(util::as_bytes(&colors[i]));
+        }
+        if let Some(tangents) = &tangents {
+            data.extend_from_slice(util::as_bytes(&tangents[i]));
         }
-        _ => unimplemented!("Unsupported vertex format"),
     }
 
     (
         OwningVertexBufferDescriptor::from_raw(data, format, BufferMutability::Immutable),
         has_vertex_colors,
+        morph_targets.len(),
     )
 }
 
+// The normal map is in tangent space, so a mesh without tangents needs them generated to be shaded
+// correctly.
+fn generate_tangents<'a>(
+    ctx: &RecGltfCtx,
+    primitive: &gltf::Primitive<'a>,
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    tex_coords: &[[f32; 2]],
+) -> Vec<[f32; 4]> {
+    let reader = primitive.reader(|buffer| Some(&ctx.buffers[buffer.index()]));
+    let indices: Vec<u32> = reader
+        .read_indices()
+        .expect("Found no indices")
+        .into_u32()
+        .collect();
+    let positions: Vec<Vec3> = positions.iter().map(|&p| Vec3::from(p)).collect();
+    let normals: Vec<Vec3> = normals.iter().map(|&n| Vec3::from(n)).collect();
+    let tex_coords: Vec<Vec2> = tex_coords.iter().map(|&t| Vec2::from(t)).collect();
+
+    render::geometry::generate_tangents(&positions, &normals, &tex_coords, &indices)
+        .into_iter()
+        .map(|t| t.into_array())
+        .collect()
+}
+
 fn to_index_buffer(indices: gltf::mesh::util::ReadIndices<'_>) -> OwningIndexBufferDescriptor {
     use gltf::mesh::util::ReadIndices;
     match indices {
@@ -163,13 +293,33 @@ fn to_index_buffer(indices: gltf::mesh::util::ReadIndices<'_>) -> OwningIndexBuf
     }
 }
 
-fn load_primitive<'a>(ctx: &mut RecGltfCtx, primitive: &gltf::Primitive<'a>) -> PendingGltfModel {
-    assert!(primitive.mode() == gltf::mesh::Mode::Triangles);
-    let reader = primitive.reader(|buffer| Some(&ctx.buffers[buffer.index()]));
+/// If the primitive can be skinned. Primitives of skinned meshes without them are drawn unskinned.
+fn has_skin_attributes(primitive: &gltf::Primitive) -> bool {
+    use gltf::mesh::Semantic;
+    primitive.get(&Semantic::Joints(0)).is_some() && primitive.get(&Semantic::Weights(0)).is_some()
+}
 
-    let triangle_index_data = reader.read_indices().expect("Found no indices");
-    let index_buffer = to_index_buffer(triangle_index_data);
-    let (vertex_buffer, has_vertex_colors) = interleave_vertex_buffer(ctx, primitive);
+fn load_primitive<'a>(
+    ctx: &mut RecGltfCtx,
+    primitive: &gltf::Primitive<'a>,
+    skinned: bool,
+) -> (PendingGltfModel, usize) {
+    assert!(primitive.mode() == gltf::mesh::Mode::Triangles);
+    let (index_buffer, vertex_buffer, has_vertex_colors, num_morph_targets) = {
+        profiling::scope!("build mesh");
+        let timer = ctx.timings.start("meshes");
+        let reader = primitive.reader(|buffer| Some(&ctx.buffers[buffer.index()]));
+        let index_buffer = to_index_buffer(reader.read_indices().expect("Found no indices"));
+        let (vertex_buffer, has_vertex_colors, num_morph_targets) =
+            interleave_vertex_buffer(ctx, primitive, skinned);
+        ctx.timings.stop(timer);
+        (
+            index_buffer,
+            vertex_buffer,
+            has_vertex_colors,
+            num_morph_targets,
+        )
+    };
 
     let mesh = CpuMesh {
         vertex_buffer,
@@ -179,10 +329,6 @@ fn load_primitive<'a>(ctx: &mut RecGltfCtx, primitive: &gltf::Primitive<'a>) ->
 
     let mat = primitive.material();
     let pbr_mr = mat.pbr_metallic_roughness();
-    if mat.emissive_texture().is_some() {
-        unimplemented!("No support for emissive texture!");
-    }
-
     let base_color_texture = pbr_mr.base_color_texture().map(|info| {
         load_texture(
             ctx,
@@ -210,6 +356,25 @@ fn load_primitive<'a>(ctx: &mut RecGltfCtx, primitive: &gltf::Primitive<'a>) ->
         )
     });
 
+    let emissive_texture = mat.emissive_texture().map(|info| {
+        load_texture(
+            ctx,
+            &info.texture(),
+            info.tex_coord(),
+            util::Format::RGBA_SRGB,
+        )
+    });
+
+    let occlusion_texture = mat.occlusion_texture().map(|info| {
+        load_texture(
+            ctx,
+            &info.texture(),
+            info.tex_coord(),
+            util::Format::RGBA_UNORM,
+        )
+    });
+
+    let (transmission, ior) = transmission_from_extras(&mat);
     let material = PhysicallyBased {
         base_color_factor: Vec4::from(pbr_mr.base_color_factor()),
         metallic_factor: pbr_mr.metallic_factor(),
@@ -218,10 +383,69 @@ fn load_primitive<'a>(ctx: &mut RecGltfCtx, primitive: &gltf::Primitive<'a>) ->
         normal_map,
         base_color_texture,
         metallic_roughness_texture,
+        emissive_factor: Rgb::from(mat.emissive_factor()),
+        emissive_texture,
+        occlusion_strength: mat.occlusion_texture().map(|o| o.strength()).unwrap_or(1.0),
+        occlusion_texture,
+        transmission,
+        ior,
         has_vertex_colors,
     };
 
-    PendingGltfModel { material, mesh }
+    (PendingGltfModel { material, mesh }, num_morph_targets)
+}
+
+/// The transmission and index of refraction of the material. The glTF crate does not read
+/// KHR_materials_transmission or KHR_materials_ior, so they are read from the extras of the material
+/// instead, e.g. "transmission": 1.0, "ior": 1.33.
+fn transmission_from_extras(mat: &gltf::Material) -> (f32, f32) {
+    let json = mat.extras().as_ref().and_then(|extras| {
+        serde_json::from_str::<serde_json::Value>(extras.get())
+            .map_err(|e| log::warn!("Ignoring extras of material {:?}: {}", mat.index(), e))
+            .ok()
+    });
+    let get = |key: &str| {
+        json.as_ref()
+            .and_then(|json| json.get(key))
+            .and_then(|v| v.as_f64())
+            .map(|v| v as f32)
+    };
+    let transmission = get("transmission").unwrap_or(0.0).max(0.0).min(1.0);
+    (transmission, get("ior").unwrap_or(DEFAULT_IOR))
+}
+
+// The bounding box of the primitive only covers the bind pose so deformed meshes need more data to
+// update it
+fn load_deformed_bounds(
+    ctx: &RecGltfCtx,
+    primitive: &gltf::Primitive,
+    skinned: bool,
+) -> Option<DeformedBounds> {
+    let reader = primitive.reader(|buffer| Some(&ctx.buffers[buffer.index()]));
+    let has_morph_targets = primitive.morph_targets().next().is_some();
+    if !skinned && !has_morph_targets {
+        return None;
+    }
+
+    let positions: Vec<Vec3> = reader.read_positions()?.map(Vec3::from).collect();
+    let morph_targets: Vec<Vec<Vec3>> = reader
+        .read_morph_targets()
+        .take(MAX_NUM_MORPH_TARGETS)
+        .map(|(displacements, _normals, _tangents)| {
+            displacements
+                .map(|d| d.map(Vec3::from).collect())
+                .unwrap_or_else(|| vec![Vec3::zero(); positions.len()])
+        })
+        .collect();
+    let skin: Option<Vec<([u16; 4], [f32; 4])>> = if skinned {
+        let joints = reader.read_joints(0)?.into_u16();
+        let weights = reader.read_weights(0)?.into_f32();
+        Some(joints.zip(weights).collect())
+    } else {
+        None
+    };
+
+    DeformedBounds::new(&positions, &morph_targets, skin.as_deref())
 }
 
 fn get_transform(src: gltf::scene::Transform) -> Transform {
@@ -251,7 +475,32 @@ fn load_node_rec(ctx: &mut RecGltfCtx, src: &gltf::Node) -> ecs::Entity {
         node = node.with(Name::from(name), &mut ctx.data.names);
     }
 
+    if let Some(extras) = src.extras() {
+        match serde_json::from_str::<serde_json::Value>(extras.get()) {
+            Ok(json) => {
+                let metadata = Metadata::from_json(&json);
+                if !metadata.is_empty() {
+                    node = node.with(metadata, &mut ctx.data.metadata);
+                }
+            }
+            Err(e) => log::warn!("Ignoring extras of node {}: {}", src.index(), e),
+        }
+    }
+
     let node = node.build();
+    ctx.node_entities.insert(src.index(), node);
+
+    let skin = src.skin().filter(|skin| {
+        let n_joints = skin.joints().len();
+        if n_joints > MAX_NUM_JOINTS {
+            log::warn!(
+                "Skin with {} joints is not supported, max is {}. Ignoring skin.",
+                n_joints,
+                MAX_NUM_JOINTS
+            );
+        }
+        n_joints <= MAX_NUM_JOINTS
+    });
 
     if let Some(mesh) = src.mesh() {
         let mesh_child = ctx
@@ -261,14 +510,90 @@ fn load_node_rec(ctx: &mut RecGltfCtx, src: &gltf::Node) -> ecs::Entity {
             .with(Transform::identity(), ctx.data.transforms)
             .build();
 
+        let default_weights = src.weights().or_else(|| mesh.weights()).unwrap_or(&[]);
+        let mesh_index = mesh.index();
         for (i, primitive) in mesh.primitives().enumerate() {
-            let PendingGltfModel { mesh, material } = load_primitive(ctx, &primitive);
+            let skinned = skin.is_some() && has_skin_attributes(&primitive);
+            if skin.is_some() && !skinned {
+                log::warn!(
+                    "Primitive {} of skinned mesh {} has no joints or weights, it is not skinned",
+                    i,
+                    mesh_index
+                );
+            }
+            // Nodes that use the same mesh share the gpu resources of its primitives
+            let primitive_key = (mesh_index, i, skinned);
+            let (shared_mesh, material, num_morph_targets) =
+                match ctx.shared_primitives.get(&primitive_key) {
+                    Some(shared) => shared.clone(),
+                    None => {
+                        let (PendingGltfModel { mesh, material }, num_morph_targets) =
+                            load_primitive(ctx, &primitive, skinned);
+                        let shared = (SharedMesh::new(mesh), material, num_morph_targets);
+                        ctx.shared_primitives.insert(primitive_key, shared.clone());
+                        shared
+                    }
+                };
 
             let bbox = BoundingBox {
                 min: Vec3::from(primitive.bounding_box().min),
                 max: Vec3::from(primitive.bounding_box().max),
             };
+            let deformed_bounds = load_deformed_bounds(ctx, &primitive, skinned);
 
+            let gltf_material = primitive.material();
+            let material_name = gltf_material.name().map(String::from).unwrap_or_else(|| {
+                let file_name = ctx.path.file_stem().unwrap_or_default().to_string_lossy();
+                match gltf_material.index() {
+                    Some(idx) => format!("{} material {}", file_name, idx),
+                    None => format!("{} default material", file_name),
+                }
+            });
+            let material_key = (gltf_material.index(), material.has_vertex_colors);
+            // Materials with the same parameters, e.g. copies with other names, are imported once
+            let shared = ctx
+                .shared_materials
+                .get(&material_key)
+                .cloned()
+                .or_else(|| {
+                    let same = ctx
+                        .unique_materials
+                        .iter()
+                        .find(|(_, shared)| shared.0.same_parameters(&material))
+                        .cloned();
+                    if same.is_some() {
+                        log::debug!("{} is the same as an earlier material", material_name);
+                    }
+                    same
+                });
+            let (material_id, shared_material) = match shared {
+                Some(shared) => shared,
+                None => {
+                    let material_id = ctx.data.material_library.import(
+                        material_name,
+                        material,
+                        &ctx.path,
+                        gltf_material.index(),
+                    );
+                    let shared_material = ctx
+                        .data
+                        .material_library
+                        .shared(material_id)
+                        .expect("The material was just imported");
+                    ctx.unique_materials
+                        .push((material_id, shared_material.clone()));
+                    (material_id, shared_material)
+                }
+            };
+            ctx.shared_materials
+                .insert(material_key, (material_id, shared_material.clone()));
+            let material = (*shared_material.0).clone();
+
+            let texture_paths: Vec<PathBuf> = material
+                .textures()
+                .filter_map(|tex| tex.path())
+                .map(Path::to_path_buf)
+                .collect();
             let prim_child = ctx
                 .data
                 .entities
@@ -276,9 +601,40 @@ fn load_node_rec(ctx: &mut RecGltfCtx, src: &gltf::Node) -> ecs::Entity {
                 .with(Name(format!("Primitive {}", i)), ctx.data.names)
                 .with(bbox, ctx.data.bboxes)
                 .with(Transform::identity(), ctx.data.transforms)
-                .with(mesh, ctx.data.meshes)
+                .with((*shared_mesh.0).clone(), ctx.data.meshes)
+                .with(shared_mesh, ctx.data.shared_meshes)
                 .with(material, ctx.data.pb_materials)
+                .with(shared_material, ctx.data.shared_materials)
+                .with(UsesMaterial(material_id), ctx.data.uses_material)
                 .build();
+            for path in texture_paths {
+                ctx.data
+                    .dependencies
+                    .add(&path, DependencyKind::Texture, prim_child);
+            }
+            if let (true, Some(skin)) = (skinned, &skin) {
+                ctx.skinned_meshes.push((prim_child, skin.index()));
+            }
+            if let Some(deformed_bounds) = deformed_bounds {
+                ctx.data
+                    .deformed_bounds
+                    .insert(prim_child, deformed_bounds)
+                    .expect("Failed to insert deformed bounds");
+            }
+            if num_morph_targets > 0 {
+                let mut weights = vec![0.0; num_morph_targets];
+                for (dst, w) in weights.iter_mut().zip(default_weights.iter()) {
+                    *dst = *w;
+                }
+                ctx.data
+                    .morph_weights
+                    .insert(prim_child, MorphWeights(weights))
+                    .expect("Failed to insert morph weights");
+                ctx.morph_primitives
+                    .entry(src.index())
+                    .or_default()
+                    .push(prim_child);
+            }
             graph::add_edge(
                 &mut ctx.data.children_storage,
                 &mut ctx.data.parent_storage,
@@ -311,6 +667,7 @@ fn load_node_rec(ctx: &mut RecGltfCtx, src: &gltf::Node) -> ecs::Entity {
     }
     */
 
+    let mut lod_levels = Vec::new();
     for gltf_child in src.children() {
         let child = load_node_rec(ctx, &gltf_child);
         graph::add_edge(
@@ -319,11 +676,187 @@ fn load_node_rec(ctx: &mut RecGltfCtx, src: &gltf::Node) -> ecs::Entity {
             node,
             child,
         );
+        if let Some(level) = gltf_child.name().and_then(lod::level_from_name) {
+            lod_levels.push((level, child));
+        }
+    }
+
+    if lod_levels.len() > 1 {
+        lod_levels.sort_by_key(|(level, _)| *level);
+        let mut group = LodGroup::new(lod_levels.into_iter().map(|(_, ent)| ent).collect());
+        // The distances can be set in the extras, e.g. "lod_distances": [5.0, 20.0]
+        if let Some(metadata) = ctx.data.metadata.get(node) {
+            for (i, d) in group.distances.iter_mut().enumerate() {
+                if let Some(v) = metadata.get_float(&format!("lod_distances.{}", i)) {
+                    *d = v as f32;
+                }
+            }
+        }
+        ctx.data
+            .lod_groups
+            .insert(node, group)
+            .expect("Failed to insert lod group");
     }
 
     node
 }
 
+// Has to run after all nodes are loaded as joints may refer to any node
+fn load_skins(ctx: &mut RecGltfCtx, gltf_doc: &gltf::Document) {
+    let skins: Vec<gltf::Skin> = gltf_doc.skins().collect();
+    for (ent, skin_idx) in ctx.skinned_meshes.iter() {
+        let skin = &skins[*skin_idx];
+        let joints: Vec<ecs::Entity> = skin
+            .joints()
+            .map(|node| ctx.node_entities[&node.index()])
+            .collect();
+
+        let reader = skin.reader(|buffer| Some(&ctx.buffers[buffer.index()]));
+        let inverse_bind_matrices: Vec<Mat4> = match reader.read_inverse_bind_matrices() {
+            Some(it) => it.map(Mat4::from_col_arrays).collect(),
+            None => vec![Mat4::identity(); joints.len()],
+        };
+        assert_eq!(joints.len(), inverse_bind_matrices.len());
+
+        for joint in joints.iter() {
+            ctx.data
+                .joints
+                .insert(*joint, Joint)
+                .expect("Failed to insert joint");
+        }
+
+        ctx.data
+            .skeletons
+            .insert(
+                *ent,
+                Skeleton {
+                    joints,
+                    inverse_bind_matrices,
+                },
+            )
+            .expect("Failed to insert skeleton");
+    }
+}
+
+// Weight animations target the primitives of the node so there may be several channels
+fn load_channels(ctx: &RecGltfCtx, channel: &gltf::animation::Channel) -> Option<Vec<Channel>> {
+    use gltf::animation::util::ReadOutputs;
+
+    let node_idx = channel.target().node().index();
+    let reader = channel.reader(|buffer| Some(&ctx.buffers[buffer.index()]));
+    let times: Vec<f32> = reader.read_inputs()?.collect();
+
+    let (interpolation, cubic) = match channel.sampler().interpolation() {
+        gltf::animation::Interpolation::Step => (Interpolation::Step, false),
+        gltf::animation::Interpolation::Linear => (Interpolation::Linear, false),
+        gltf::animation::Interpolation::CubicSpline => {
+            log::warn!("Cubic spline interpolation is not supported, using linear");
+            (Interpolation::Linear, true)
+        }
+    };
+
+    // Cubic spline keyframes are stored as (in-tangent, value, out-tangent)
+    fn values<T>(it: impl Iterator<Item = T>, cubic: bool) -> Vec<T> {
+        if cubic {
+            it.skip(1).step_by(3).collect()
+        } else {
+            it.collect()
+        }
+    }
+
+    let keyframes = match reader.read_outputs()? {
+        ReadOutputs::Translations(it) => Keyframes::Translation(values(it.map(Vec3::from), cubic)),
+        ReadOutputs::Rotations(it) => Keyframes::Rotation(values(
+            it.into_f32()
+                .map(|r| Quat::from_xyzw(r[0], r[1], r[2], r[3]).normalized()),
+            cubic,
+        )),
+        ReadOutputs::Scales(it) => Keyframes::Scale(values(
+            it.map(|s| {
+                if !s.iter().all(|x| (*x - s[0]).abs() < f32::EPSILON) {
+                    log::warn!("Non-uniform scaling in animation: {:?}", s);
+                    log::warn!("Using only {}", s[0]);
+                }
+                s[0]
+            }),
+            cubic,
+        )),
+        ReadOutputs::MorphTargetWeights(it) => {
+            let flat: Vec<f32> = it.into_f32().collect();
+            let n_values = if cubic { 3 } else { 1 };
+            let n_targets = flat.len() / (times.len() * n_values).max(1);
+            let weights = flat
+                .chunks(n_targets.max(1))
+                .skip(if cubic { 1 } else { 0 })
+                .step_by(n_values)
+                .map(|w| w.iter().copied().take(MAX_NUM_MORPH_TARGETS).collect())
+                .collect();
+            Keyframes::Weights(weights)
+        }
+    };
+
+    let channel = |target| Channel {
+        target,
+        interpolation,
+        times: times.clone(),
+        keyframes: keyframes.clone(),
+    };
+
+    if let Keyframes::Weights(_) = keyframes {
+        let targets = ctx.morph_primitives.get(&node_idx)?;
+        Some(targets.iter().map(|ent| channel(*ent)).collect())
+    } else {
+        // The target might not be part of the loaded scene
+        Some(vec![channel(*ctx.node_entities.get(&node_idx)?)])
+    }
+}
+
+fn load_animations(ctx: &mut RecGltfCtx, gltf_doc: &gltf::Document, root: ecs::Entity) {
+    let mut player = AnimationPlayer::default();
+    for (i, src) in gltf_doc.animations().enumerate() {
+        let channels: Vec<Channel> = src
+            .channels()
+            .filter_map(|channel| load_channels(ctx, &channel))
+            .flatten()
+            .collect();
+        if channels.is_empty() {
+            continue;
+        }
+
+        let animation = Animation::new(channels);
+
+        let name = src
+            .name()
+            .map(Name::from)
+            .unwrap_or_else(|| Name(format!("Animation {}", i)));
+        let ent = ctx
+            .data
+            .entities
+            .build_entity()
+            .with(animation, ctx.data.animations)
+            .with(name, ctx.data.names)
+            .build();
+        graph::add_edge(
+            &mut ctx.data.children_storage,
+            &mut ctx.data.parent_storage,
+            root,
+            ent,
+        );
+
+        // Several animations may target the same nodes so only play the first one
+        if player.current.is_none() {
+            player.play(ent, 0.0);
+        }
+    }
+
+    if player.current.is_some() {
+        ctx.data
+            .animation_players
+            .insert(root, player)
+            .expect("Root is alive");
+    }
+}
+
 #[allow(dead_code)]
 fn get_cam_transform(
     gltf_doc: gltf::Document,
@@ -369,24 +902,128 @@ fn get_cam_transform(
     cam_transform
 }
 
+/// Which of the scenes in a glTF file to load
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SceneSelection {
+    /// The scene marked as default in the file, or the first one if there is none
+    Default,
+    Index(usize),
+    Name(String),
+}
+
+impl Default for SceneSelection {
+    fn default() -> Self {
+        SceneSelection::Default
+    }
+}
+
+impl std::str::FromStr for SceneSelection {
+    type Err = std::convert::Infallible;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse::<usize>() {
+            Ok(idx) => SceneSelection::Index(idx),
+            Err(_) => SceneSelection::Name(String::from(s)),
+        })
+    }
+}
+
+fn select_scene<'a>(
+    gltf_doc: &'a gltf::Document,
+    selection: &SceneSelection,
+) -> Option<gltf::Scene<'a>> {
+    match selection {
+        SceneSelection::Default => gltf_doc
+            .default_scene()
+            .or_else(|| gltf_doc.scenes().next()),
+        SceneSelection::Index(idx) => gltf_doc.scenes().nth(*idx),
+        SceneSelection::Name(name) => gltf_doc
+            .scenes()
+            .find(|scene| scene.name() == Some(name.as_str())),
+    }
+}
+
+/// The nodes that are not the child of another node, which are loaded for files without scenes
+pub(crate) fn root_nodes(gltf_doc: &gltf::Document) -> Vec<gltf::Node> {
+    let children: HashSet<usize> = gltf_doc
+        .nodes()
+        .flat_map(|node| node.children())
+        .map(|child| child.index())
+        .collect();
+    gltf_doc
+        .nodes()
+        .filter(|node| !children.contains(&node.index()))
+        .collect()
+}
+
+fn scene_name(scene: &gltf::Scene) -> String {
+    scene
+        .name()
+        .map(String::from)
+        .unwrap_or_else(|| format!("Scene {}", scene.index()))
+}
+
 pub fn load_asset(world: &mut World, path: &Path) {
+    load_asset_scene(world, path, SceneSelection::Default);
+}
+
+pub fn load_asset_scene(world: &mut World, path: &Path, scene: SceneSelection) {
     world
         .create_entity()
         .with(LoadGltfAsset {
             path: PathBuf::from(path),
+            scene,
         })
         .build();
 }
 
+/// Added to the root entity of a loaded glTF file
 #[derive(Default, Component)]
 #[component(inspect)]
 pub struct GltfAsset {
     path: PathBuf,
+    scene: usize,
+    scenes: Vec<String>,
+    #[inspect(ignore)]
+    timings: LoadTimings,
+    /// When the scene was spawned, until all of its meshes and materials are on the gpu
+    #[inspect(ignore)]
+    upload_started: Option<Instant>,
 }
 
+impl GltfAsset {
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn scene(&self) -> usize {
+        self.scene
+    }
+
+    /// How long the stages of the last load took
+    pub fn timings(&self) -> &LoadTimings {
+        &self.timings
+    }
+
+    /// Load the current scene again
+    pub(crate) fn reload(&self) -> LoadGltfAsset {
+        LoadGltfAsset {
+            path: self.path.clone(),
+            scene: SceneSelection::Index(self.scene),
+        }
+    }
+}
+
+/// Load a glTF file. If the entity already has a loaded scene, it is unloaded first.
 #[derive(Default, Component)]
 pub struct LoadGltfAsset {
     path: PathBuf,
+    scene: SceneSelection,
+}
+
+impl LoadGltfAsset {
+    pub(crate) fn new(path: PathBuf, scene: SceneSelection) -> Self {
+        Self { path, scene }
+    }
 }
 
 #[derive(Component)]
@@ -396,7 +1033,7 @@ pub struct PendingGltfModel {
     material: PhysicallyBased,
 }
 
-struct GltfLoader;
+pub(crate) struct GltfLoader;
 
 impl GltfLoader {
     pub const ID: &'static str = "GltfLoader";
@@ -410,10 +1047,25 @@ struct LoaderData<'a> {
     parent_storage: WriteStorage<'a, graph::Parent>,
     children_storage: WriteStorage<'a, graph::Children>,
     names: WriteStorage<'a, Name>,
+    metadata: WriteStorage<'a, Metadata>,
+    lod_groups: WriteStorage<'a, LodGroup>,
     meshes: WriteStorage<'a, render::mesh::CpuMesh>,
+    shared_meshes: WriteStorage<'a, SharedMesh>,
     pb_materials: WriteStorage<'a, render::material::PhysicallyBased>,
+    shared_materials: WriteStorage<'a, SharedMaterial>,
     bboxes: WriteStorage<'a, BoundingBox>,
+    deformed_bounds: WriteStorage<'a, DeformedBounds>,
     cameras: WriteStorage<'a, Camera>,
+    skeletons: WriteStorage<'a, Skeleton>,
+    joints: WriteStorage<'a, Joint>,
+    animations: WriteStorage<'a, Animation>,
+    animation_players: WriteStorage<'a, AnimationPlayer>,
+    morph_weights: WriteStorage<'a, MorphWeights>,
+    gltf_assets: WriteStorage<'a, GltfAsset>,
+    dependencies: Write<'a, AssetDependencies>,
+    uses_material: WriteStorage<'a, UsesMaterial>,
+    material_library: Write<'a, MaterialLibrary>,
+    asset_server: Read<'a, AssetServer>,
 }
 
 struct CtxData<'a, 'b> {
@@ -422,18 +1074,46 @@ struct CtxData<'a, 'b> {
     parent_storage: &'b mut WriteStorage<'a, graph::Parent>,
     children_storage: &'b mut WriteStorage<'a, graph::Children>,
     names: &'b mut WriteStorage<'a, Name>,
+    metadata: &'b mut WriteStorage<'a, Metadata>,
+    lod_groups: &'b mut WriteStorage<'a, LodGroup>,
     meshes: &'b mut WriteStorage<'a, CpuMesh>,
+    shared_meshes: &'b mut WriteStorage<'a, SharedMesh>,
     pb_materials: &'b mut WriteStorage<'a, render::material::PhysicallyBased>,
+    shared_materials: &'b mut WriteStorage<'a, SharedMaterial>,
     #[allow(dead_code)]
     cameras: &'b mut WriteStorage<'a, Camera>,
     bboxes: &'b mut WriteStorage<'a, BoundingBox>,
+    deformed_bounds: &'b mut WriteStorage<'a, DeformedBounds>,
+    skeletons: &'b mut WriteStorage<'a, Skeleton>,
+    joints: &'b mut WriteStorage<'a, Joint>,
+    animations: &'b mut WriteStorage<'a, Animation>,
+    animation_players: &'b mut WriteStorage<'a, AnimationPlayer>,
+    morph_weights: &'b mut WriteStorage<'a, MorphWeights>,
+    dependencies: &'b mut AssetDependencies,
+    uses_material: &'b mut WriteStorage<'a, UsesMaterial>,
+    material_library: &'b mut MaterialLibrary,
 }
 
 struct RecGltfCtx<'a, 'b> {
     pub data: CtxData<'a, 'b>,
-    pub buffers: Vec<gltf::buffer::Data>,
+    pub buffers: Arc<Vec<gltf::buffer::Data>>,
     pub path: PathBuf,
     pub material_buffer: Vec<PBRMaterialData>,
+    pub node_entities: HashMap<usize, ecs::Entity>,
+    pub skinned_meshes: Vec<(ecs::Entity, usize)>,
+    /// Primitive entities with morph targets, per node index
+    pub morph_primitives: HashMap<usize, Vec<ecs::Entity>>,
+    /// Per mesh index, primitive index and whether it is skinned
+    pub shared_primitives: HashMap<(usize, usize, bool), (SharedMesh, PhysicallyBased, usize)>,
+    /// Per material index and whether the primitive has vertex colors
+    pub shared_materials: HashMap<(Option<usize>, bool), (MaterialId, SharedMaterial)>,
+    /// The imported materials, which all have different parameters
+    pub unique_materials: Vec<(MaterialId, SharedMaterial)>,
+    /// The file that each image is loaded from, per image index
+    pub image_paths: HashMap<usize, PathBuf>,
+    /// The first file with the content, per hash of the file
+    pub image_hashes: HashMap<u64, PathBuf>,
+    pub timings: LoadTimings,
 }
 
 impl<'a> System<'a> for GltfLoader {
@@ -447,23 +1127,83 @@ impl<'a> System<'a> for GltfLoader {
             mut children_storage,
             mut parent_storage,
             mut names,
+            mut metadata,
+            mut lod_groups,
             mut meshes,
+            mut shared_meshes,
             mut pb_materials,
+            mut shared_materials,
             mut cameras,
             mut bboxes,
+            mut deformed_bounds,
+            mut skeletons,
+            mut joints,
+            mut animations,
+            mut animation_players,
+            mut morph_weights,
+            mut gltf_assets,
+            mut dependencies,
+            mut uses_material,
+            mut material_library,
+            asset_server,
         } = data;
 
         for (ent, _) in (&entities, &load_assets).join() {
             let asset = load_assets.get(ent).expect("Just filtered on this!");
             log::trace!("load gltf asset {}", asset.path.display());
 
-            let start = std::time::Instant::now();
-            let (gltf_doc, buffers, _images) =
-                gltf::import(&asset.path).expect("Unable to import gltf");
-            log::trace!(
-                "gltf import took {} ms",
-                start.elapsed().as_secs_f32() * 1000.0
-            );
+            // Preloaded by the asset server, or imported here, which blocks
+            let mut timings = LoadTimings::default();
+            let (gltf_doc, buffers) = match asset_server.imported_gltf(&asset.path) {
+                Some(imported) => {
+                    timings.extend(&imported.timings);
+                    (imported.document.clone(), Arc::clone(&imported.buffers))
+                }
+                None => {
+                    let (gltf_doc, buffers, _images) = timings.time("parse", || {
+                        profiling::scope!("parse gltf");
+                        gltf::import(&asset.path).expect("Unable to import gltf")
+                    });
+                    (gltf_doc, Arc::new(buffers))
+                }
+            };
+
+            let scene = match select_scene(&gltf_doc, &asset.scene) {
+                Some(scene) => Some(scene),
+                None if asset.scene == SceneSelection::Default => None,
+                None => {
+                    log::error!(
+                        "Scene {:?} not found in {}, loading the default scene",
+                        asset.scene,
+                        asset.path.display()
+                    );
+                    select_scene(&gltf_doc, &SceneSelection::Default)
+                }
+            };
+            let nodes = match &scene {
+                Some(scene) => scene.nodes().collect(),
+                None => {
+                    log::warn!(
+                        "{} has no scenes, loading the nodes without parents",
+                        asset.path.display()
+                    );
+                    root_nodes(&gltf_doc)
+                }
+            };
+
+            // Unload the previously loaded scene, if any
+            if children_storage.contains(ent) {
+                let mut prev = Vec::new();
+                graph::breadth_first(&children_storage, ent, |node| {
+                    if node != ent {
+                        prev.push(node);
+                    }
+                });
+                for node in prev {
+                    entities.delete(node).expect("Failed to delete entity");
+                }
+                children_storage.remove(ent);
+            }
 
             let ctx_data = CtxData {
                 entities: &entities,
@@ -471,24 +1211,45 @@ impl<'a> System<'a> for GltfLoader {
                 parent_storage: &mut parent_storage,
                 children_storage: &mut children_storage,
                 names: &mut names,
+                metadata: &mut metadata,
+                lod_groups: &mut lod_groups,
                 cameras: &mut cameras,
                 bboxes: &mut bboxes,
+                deformed_bounds: &mut deformed_bounds,
                 pb_materials: &mut pb_materials,
+                shared_materials: &mut shared_materials,
                 meshes: &mut meshes,
+                shared_meshes: &mut shared_meshes,
+                skeletons: &mut skeletons,
+                joints: &mut joints,
+                animations: &mut animations,
+                animation_players: &mut animation_players,
+                morph_weights: &mut morph_weights,
+                dependencies: &mut dependencies,
+                uses_material: &mut uses_material,
+                material_library: &mut material_library,
             };
-            assert_eq!(gltf_doc.scenes().len(), 1);
             let mut rec_ctx = RecGltfCtx {
                 buffers,
                 path: asset.path.clone(),
                 data: ctx_data,
                 material_buffer: Vec::new(),
+                node_entities: HashMap::new(),
+                skinned_meshes: Vec::new(),
+                morph_primitives: HashMap::new(),
+                shared_primitives: HashMap::new(),
+                shared_materials: HashMap::new(),
+                unique_materials: Vec::new(),
+                image_paths: HashMap::new(),
+                image_hashes: HashMap::new(),
+                timings,
             };
 
             // A scene may have several root nodes
-            let nodes = gltf_doc.scenes().next().expect("No scenes!").nodes();
-            if gltf_doc.scenes().len() > 1 {
-                log::warn!("More than one scene found, only displaying the first");
-                log::warn!("Number of scenes: {}", gltf_doc.scenes().len());
+            profiling::scope!("spawn gltf scene");
+            let scene_timer = rec_ctx.timings.start("scene");
+            if let Some(scene) = &scene {
+                log::trace!("Loading scene {}", scene_name(scene));
             }
             for node in nodes {
                 log::trace!("Root node {}", node.name().unwrap_or("node_no_name"));
@@ -507,11 +1268,177 @@ impl<'a> System<'a> for GltfLoader {
                     .insert(ent, Transform::identity())
                     .unwrap();
             }
+
+            load_skins(&mut rec_ctx, &gltf_doc);
+            load_animations(&mut rec_ctx, &gltf_doc, ent);
+            rec_ctx.timings.stop(scene_timer);
+            log::debug!("Spawned {}: {}", asset.path.display(), rec_ctx.timings);
+
+            // Changes to the document or buffers require the whole file to be imported again
+            let parent_path = asset.path.parent().expect("Invalid path");
+            rec_ctx
+                .data
+                .dependencies
+                .add(&asset.path, DependencyKind::Gltf, ent);
+            for buffer in gltf_doc.buffers() {
+                if let gltf::buffer::Source::Uri(uri) = buffer.source() {
+                    rec_ctx.data.dependencies.add(
+                        &parent_path.join(uri),
+                        DependencyKind::Gltf,
+                        ent,
+                    );
+                }
+            }
+
+            gltf_assets
+                .insert(
+                    ent,
+                    GltfAsset {
+                        path: asset.path.clone(),
+                        scene: scene.as_ref().map_or(0, |scene| scene.index()),
+                        scenes: gltf_doc.scenes().map(|s| scene_name(&s)).collect(),
+                        timings: rec_ctx.timings,
+                        upload_started: Some(Instant::now()),
+                    },
+                )
+                .expect("Failed to insert gltf asset");
         }
         load_assets.clear();
     }
 }
 
+/// Times the upload of the spawned scenes, until all of their meshes and materials are on the gpu,
+/// and logs the timings of the load when it is done. The image files of the textures are decoded
+/// by the trekanten loader, so that is part of the upload.
+pub(crate) struct GltfUploadTiming;
+
+impl GltfUploadTiming {
+    pub const ID: &'static str = "GltfUploadTiming";
+}
+
+impl<'a> System<'a> for GltfUploadTiming {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, GltfAsset>,
+        ReadStorage<'a, graph::Children>,
+        ReadStorage<'a, CpuMesh>,
+        ReadStorage<'a, SharedMesh>,
+        ReadStorage<'a, render::mesh::GpuMesh>,
+        ReadStorage<'a, PhysicallyBased>,
+        ReadStorage<'a, SharedMaterial>,
+        ReadStorage<'a, render::material::GpuMaterial>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut gltf_assets,
+            children,
+            meshes,
+            shared_meshes,
+            gpu_meshes,
+            materials,
+            shared_materials,
+            gpu_materials,
+        ): Self::SystemData,
+    ) {
+        for (ent, asset) in (&entities, &mut gltf_assets).join() {
+            let started = match asset.upload_started {
+                Some(started) => started,
+                None => continue,
+            };
+
+            let mut uploaded = true;
+            graph::breadth_first(&children, ent, |node| {
+                let has_mesh = meshes.contains(node) || shared_meshes.contains(node);
+                let has_material = materials.contains(node) || shared_materials.contains(node);
+                uploaded &= !has_mesh || gpu_meshes.contains(node);
+                uploaded &= !has_material || gpu_materials.contains(node);
+            });
+            if !uploaded {
+                continue;
+            }
+
+            asset.timings.add("upload", started.elapsed());
+            asset.upload_started = None;
+            log::info!(
+                "Loaded {} in {:.2} ms: {}",
+                asset.path.display(),
+                asset.timings.total().as_secs_f32() * 1000.0,
+                asset.timings
+            );
+        }
+    }
+}
+
 pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
-    builder.with(GltfLoader, GltfLoader::ID, &[])
+    builder
+        .with(
+            GltfLoader,
+            GltfLoader::ID,
+            &[super::commands::AssetCommandSys::ID],
+        )
+        .with(GltfUploadTiming, GltfUploadTiming::ID, &[GltfLoader::ID])
+}
+
+pub(crate) fn build_ui<'a>(
+    world: &mut World,
+    ui: &crate::render::ui::UiFrame<'a>,
+    pos: [f32; 2],
+) -> [f32; 2] {
+    let size = [300.0, 120.0];
+
+    imgui::Window::new(imgui::im_str!("glTF scenes"))
+        .position(pos, imgui::Condition::FirstUseEver)
+        .size(size, imgui::Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let entities = world.entities();
+            let assets = world.read_storage::<GltfAsset>();
+            let mut load_assets = world.write_storage::<LoadGltfAsset>();
+            for (ent, asset) in (&entities, &assets).join() {
+                ui.inner().text(imgui::im_str!("{}", asset.path.display()));
+                let names: Vec<imgui::ImString> = asset
+                    .scenes
+                    .iter()
+                    .map(|name| imgui::ImString::from(name.clone()))
+                    .collect();
+                let items: Vec<&imgui::ImStr> = names.iter().map(|name| name.as_ref()).collect();
+                let mut idx = asset.scene;
+                let id = imgui::im_str!("Scene##{}", ent.id());
+                let selected =
+                    imgui::ComboBox::new(&id).build_simple_string(ui.inner(), &mut idx, &items);
+                if selected && idx != asset.scene {
+                    load_assets
+                        .insert(
+                            ent,
+                            LoadGltfAsset {
+                                path: asset.path.clone(),
+                                scene: SceneSelection::Index(idx),
+                            },
+                        )
+                        .expect("Failed to insert load asset");
+                }
+
+                let label = if asset.upload_started.is_some() {
+                    imgui::im_str!("Uploading...##{}", ent.id())
+                } else {
+                    let total = asset.timings.total().as_secs_f32() * 1000.0;
+                    imgui::im_str!("Loaded in {:.2} ms##{}", total, ent.id())
+                };
+                imgui::TreeNode::new(&label).build(ui.inner(), || {
+                    for (name, depth, duration) in asset.timings.iter() {
+                        ui.inner().text(imgui::im_str!(
+                            "{:indent$}{}: {:.2} ms",
+                            "",
+                            name,
+                            duration.as_secs_f32() * 1000.0,
+                            indent = 2 * depth
+                        ));
+                    }
+                });
+            }
+        });
+
+    size
 }
@@ -0,0 +1,896 @@
+//! OBJ and PLY files, e.g. quick mesh dumps from other tools, so that they can be viewed without
+//! converting them to glTF first. A file is loaded as one mesh with a default material: surfaces
+//! are PBR, with the vertex colors if there are any, and files without faces are drawn as unlit
+//! points. Normals are computed if the file has none.
+//!
+//! Only the geometry is loaded. OBJ materials, groups and lines are ignored, as are the PLY
+//! elements other than vertices and faces. PLY files can be ASCII or binary.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use trekanten::mem::{BufferMutability, OwningIndexBufferDescriptor, OwningVertexBufferDescriptor};
+use trekanten::pipeline::PolygonMode;
+use trekanten::util::{self, Format};
+use trekanten::vertex::VertexFormat;
+
+use crate::common::Name;
+use crate::ecs::prelude::*;
+use crate::math::{BoundingBox, Rgb, Rgba, Transform, Vec2, Vec3, Vec4};
+use crate::render::material::{PhysicallyBased, Unlit, DEFAULT_IOR};
+use crate::render::mesh::CpuMesh;
+
+#[derive(Debug, Error)]
+pub enum MeshFileError {
+    #[error("Failed to read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Line {0}: {1}")]
+    Obj(usize, String),
+    #[error("Invalid PLY file: {0}")]
+    Ply(String),
+    #[error("{0} is not an OBJ or PLY file")]
+    UnknownFormat(PathBuf),
+    #[error("{0} has no vertices")]
+    Empty(PathBuf),
+}
+
+fn ply_error(msg: impl Into<String>) -> MeshFileError {
+    MeshFileError::Ply(msg.into())
+}
+
+pub fn is_mesh_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("obj") | Some("ply")
+    )
+}
+
+/// The vertex attributes of a mesh file, before they are interleaved
+#[derive(Debug, Default)]
+struct MeshData {
+    positions: Vec<Vec3>,
+    normals: Option<Vec<Vec3>>,
+    tex_coords: Option<Vec<Vec2>>,
+    colors: Option<Vec<Vec4>>,
+    /// Triangles, empty for point clouds
+    indices: Vec<u32>,
+}
+
+/// Area weighted vertex normals, as the triangles are CCW the cross products point out
+fn compute_normals(positions: &[Vec3], indices: &[u32]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::zero(); positions.len()];
+    for tri in indices.chunks_exact(3) {
+        let [a, b, c] = [tri[0] as usize, tri[1] as usize, tri[2] as usize];
+        let normal = (positions[b] - positions[a]).cross(positions[c] - positions[a]);
+        for &i in [a, b, c].iter() {
+            normals[i] += normal;
+        }
+    }
+
+    normals
+        .into_iter()
+        .map(|n| {
+            if n.magnitude_squared() > 0.0 {
+                n.normalized()
+            } else {
+                Vec3::unit_y()
+            }
+        })
+        .collect()
+}
+
+impl MeshData {
+    /// Same vertex layout as the glTF meshes: position, normal, texture coordinates and color. The
+    /// pipelines only expect texture coordinates when there are textures, which the default
+    /// material does not have, so they are dropped when they would offset the colors.
+    fn surface(self) -> CpuMesh {
+        let normals = self
+            .normals
+            .unwrap_or_else(|| compute_normals(&self.positions, &self.indices));
+        let colors = self.colors;
+        let tex_coords = self.tex_coords.filter(|_| colors.is_none());
+
+        let mut format = VertexFormat::builder()
+            .add_attribute(Format::FLOAT3)
+            .add_attribute(Format::FLOAT3);
+        if tex_coords.is_some() {
+            format = format.add_attribute(Format::FLOAT2);
+        }
+        if colors.is_some() {
+            format = format.add_attribute(Format::FLOAT4);
+        }
+
+        let mut data = Vec::new();
+        for (i, (pos, nor)) in self.positions.iter().zip(normals.iter()).enumerate() {
+            data.extend_from_slice(util::as_bytes(&pos.into_array()));
+            data.extend_from_slice(util::as_bytes(&nor.into_array()));
+            if let Some(tex_coords) = &tex_coords {
+                data.extend_from_slice(util::as_bytes(&tex_coords[i].into_array()));
+            }
+            if let Some(colors) = &colors {
+                data.extend_from_slice(util::as_bytes(&colors[i].into_array()));
+            }
+        }
+
+        CpuMesh {
+            vertex_buffer: OwningVertexBufferDescriptor::from_raw(
+                data,
+                format.build(),
+                BufferMutability::Immutable,
+            ),
+            index_buffer: OwningIndexBufferDescriptor::from_vec(
+                self.indices,
+                BufferMutability::Immutable,
+            ),
+            polygon_mode: PolygonMode::Fill,
+        }
+    }
+
+    /// Positions only, like the other unlit meshes. Each point is a degenerate triangle that is
+    /// drawn with PolygonMode::Point.
+    fn point_cloud(self) -> CpuMesh {
+        let format = VertexFormat::builder()
+            .add_attribute(Format::FLOAT3)
+            .build();
+        let mut data = Vec::with_capacity(self.positions.len() * 12);
+        for pos in self.positions.iter() {
+            data.extend_from_slice(util::as_bytes(&pos.into_array()));
+        }
+        let indices: Vec<u32> = (0..self.positions.len() as u32)
+            .flat_map(|i| std::iter::repeat(i).take(3))
+            .collect();
+
+        CpuMesh {
+            vertex_buffer: OwningVertexBufferDescriptor::from_raw(
+                data,
+                format,
+                BufferMutability::Immutable,
+            ),
+            index_buffer: OwningIndexBufferDescriptor::from_vec(
+                indices,
+                BufferMutability::Immutable,
+            ),
+            polygon_mode: PolygonMode::Point,
+        }
+    }
+}
+
+/// An OBJ index, which starts at 1 or is negative to count back from the last vertex
+fn resolve_obj_index(s: &str, len: usize) -> Option<usize> {
+    let idx: i64 = s.parse().ok()?;
+    let resolved = if idx < 0 { len as i64 + idx } else { idx - 1 };
+    if resolved >= 0 && (resolved as usize) < len {
+        Some(resolved as usize)
+    } else {
+        None
+    }
+}
+
+fn parse_obj(contents: &str) -> Result<MeshData, MeshFileError> {
+    let mut positions = Vec::new();
+    let mut colors = Vec::new();
+    let mut tex_coords = Vec::new();
+    let mut normals = Vec::new();
+    // The indices of the position, texture coordinates and normal of each corner. Corners with the
+    // same indices share a vertex.
+    type Corner = (usize, Option<usize>, Option<usize>);
+    let mut corners: Vec<Corner> = Vec::new();
+    let mut vertices: HashMap<Corner, u32> = HashMap::new();
+    let mut indices = Vec::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let error = |msg: &str| MeshFileError::Obj(i + 1, msg.to_owned());
+        let mut tokens = line.split('#').next().unwrap_or("").split_whitespace();
+        let keyword = tokens.next();
+        if keyword == Some("f") {
+            let mut face = Vec::new();
+            for corner in tokens {
+                let mut parts = corner.split('/');
+                let optional = |part: Option<&str>, len: usize| match part {
+                    None | Some("") => Ok(None),
+                    Some(s) => resolve_obj_index(s, len)
+                        .map(Some)
+                        .ok_or_else(|| error(&format!("Invalid index in {}", corner))),
+                };
+                let pos = resolve_obj_index(parts.next().unwrap_or(""), positions.len())
+                    .ok_or_else(|| error(&format!("Invalid index in {}", corner)))?;
+                let tex = optional(parts.next(), tex_coords.len())?;
+                let nor = optional(parts.next(), normals.len())?;
+
+                let key = (pos, tex, nor);
+                let idx = *vertices.entry(key).or_insert_with(|| {
+                    corners.push(key);
+                    corners.len() as u32 - 1
+                });
+                face.push(idx);
+            }
+            if face.len() < 3 {
+                return Err(error("A face needs at least three vertices"));
+            }
+            // Fan triangulation, which assumes convex polygons
+            for k in 1..face.len() - 1 {
+                indices.extend_from_slice(&[face[0], face[k], face[k + 1]]);
+            }
+            continue;
+        }
+
+        if !matches!(keyword, Some("v") | Some("vt") | Some("vn")) {
+            continue;
+        }
+        let values = tokens
+            .map(|t| t.parse::<f32>().map_err(|_| error("Expected a number")))
+            .collect::<Result<Vec<f32>, MeshFileError>>()?;
+        match (keyword, values.as_slice()) {
+            (Some("v"), [x, y, z, rest @ ..]) => {
+                positions.push(Vec3::new(*x, *y, *z));
+                // A common extension, with the color after the position
+                if let [r, g, b, ..] = rest {
+                    colors.push(Vec4::new(*r, *g, *b, 1.0));
+                }
+            }
+            // The origin of OBJ texture coordinates is the bottom left. v is optional and 0 by
+            // default.
+            (Some("vt"), [u, rest @ ..]) => {
+                let v = rest.first().copied().unwrap_or(0.0);
+                tex_coords.push(Vec2::new(*u, 1.0 - v));
+            }
+            (Some("vn"), [x, y, z]) => normals.push(Vec3::new(*x, *y, *z).normalized()),
+            _ => return Err(error("Too few values")),
+        }
+    }
+
+    let colors = Some(colors).filter(|c| !c.is_empty() && c.len() == positions.len());
+    if corners.is_empty() {
+        return Ok(MeshData {
+            positions,
+            colors,
+            ..Default::default()
+        });
+    }
+
+    let tex_coords = if corners.iter().all(|c| c.1.is_some()) {
+        Some(corners.iter().map(|c| tex_coords[c.1.unwrap()]).collect())
+    } else {
+        None
+    };
+    let normals = if corners.iter().all(|c| c.2.is_some()) {
+        Some(corners.iter().map(|c| normals[c.2.unwrap()]).collect())
+    } else {
+        None
+    };
+    Ok(MeshData {
+        positions: corners.iter().map(|c| positions[c.0]).collect(),
+        normals,
+        tex_coords,
+        colors: colors.map(|colors| corners.iter().map(|c| colors[c.0]).collect()),
+        indices,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(s: &str) -> Result<Self, MeshFileError> {
+        Ok(match s {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => return Err(ply_error(format!("Unknown type {}", s))),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    /// Colors are stored as integers from 0 to the max of the type, or as floats from 0 to 1
+    fn normalize(self, v: f64) -> f64 {
+        match self {
+            Self::U8 => v / u8::MAX as f64,
+            Self::U16 => v / u16::MAX as f64,
+            _ => v,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Property {
+    Scalar(String, Scalar),
+    /// The type of the count and of the items
+    List(String, Scalar, Scalar),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    /// The index in the scalars that read_element returns
+    fn scalar(&self, name: &str) -> Option<(usize, Scalar)> {
+        self.properties
+            .iter()
+            .filter_map(|p| match p {
+                Property::Scalar(n, ty) => Some((n, *ty)),
+                Property::List(..) => None,
+            })
+            .enumerate()
+            .find(|(_, (n, _))| *n == name)
+            .map(|(i, (_, ty))| (i, ty))
+    }
+
+    /// The index in the properties of the first list with one of the names
+    fn list(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|p| match p {
+            Property::List(n, ..) => names.contains(&n.as_str()),
+            Property::Scalar(..) => false,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+fn parse_ply_header(header: &str) -> Result<(PlyFormat, Vec<Element>), MeshFileError> {
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err(ply_error("Missing magic number"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", f, _version] => {
+                format = Some(match *f {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return Err(ply_error(format!("Unknown format {}", f))),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| ply_error(format!("Invalid count {}", count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .ok_or_else(|| ply_error("Property before element"))?
+                .properties
+                .push(Property::List(
+                    name.to_string(),
+                    Scalar::parse(count)?,
+                    Scalar::parse(item)?,
+                )),
+            ["property", ty, name] => elements
+                .last_mut()
+                .ok_or_else(|| ply_error("Property before element"))?
+                .properties
+                .push(Property::Scalar(name.to_string(), Scalar::parse(ty)?)),
+            ["comment", ..] | ["obj_info", ..] | [] => (),
+            _ => return Err(ply_error(format!("Unexpected header line {}", line))),
+        }
+    }
+
+    let format = format.ok_or_else(|| ply_error("Missing format"))?;
+    Ok((format, elements))
+}
+
+/// The values of the body of a PLY file, in order
+enum PlyValues<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], big_endian: bool },
+}
+
+impl<'a> PlyValues<'a> {
+    fn next(&mut self, ty: Scalar) -> Result<f64, MeshFileError> {
+        let (data, big_endian) = match self {
+            Self::Ascii(tokens) => {
+                return tokens
+                    .next()
+                    .and_then(|t| t.parse::<f64>().ok())
+                    .ok_or_else(|| ply_error("Expected a number"))
+            }
+            Self::Binary { data, big_endian } => (data, *big_endian),
+        };
+
+        let size = ty.size();
+        if data.len() < size {
+            return Err(ply_error("Unexpected end of file"));
+        }
+        let remaining: &'a [u8] = *data;
+        let (head, rest) = remaining.split_at(size);
+        *data = rest;
+        let mut bytes = [0u8; 8];
+        bytes[..size].copy_from_slice(head);
+        if big_endian {
+            bytes[..size].reverse();
+        }
+
+        let [b0, b1, b2, b3, ..] = bytes;
+        Ok(match ty {
+            Scalar::I8 => b0 as i8 as f64,
+            Scalar::U8 => b0 as f64,
+            Scalar::I16 => i16::from_le_bytes([b0, b1]) as f64,
+            Scalar::U16 => u16::from_le_bytes([b0, b1]) as f64,
+            Scalar::I32 => i32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Scalar::U32 => u32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Scalar::F32 => f32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Scalar::F64 => f64::from_le_bytes(bytes),
+        })
+    }
+
+    /// Reads the scalars of one element into `scalars` and the items of the list property at
+    /// `list_idx`, see Element::list, into `list`. Other lists are skipped.
+    fn read_element(
+        &mut self,
+        element: &Element,
+        scalars: &mut Vec<f64>,
+        list_idx: Option<usize>,
+        list: &mut Vec<f64>,
+    ) -> Result<(), MeshFileError> {
+        scalars.clear();
+        list.clear();
+        for (i, property) in element.properties.iter().enumerate() {
+            match *property {
+                Property::Scalar(_, ty) => scalars.push(self.next(ty)?),
+                Property::List(_, count_ty, item_ty) => {
+                    let count = self.next(count_ty)? as usize;
+                    for _ in 0..count {
+                        let item = self.next(item_ty)?;
+                        if list_idx == Some(i) {
+                            list.push(item);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_ply(contents: &[u8]) -> Result<MeshData, MeshFileError> {
+    const END_HEADER: &[u8] = b"end_header";
+    let header_end = contents
+        .windows(END_HEADER.len())
+        .position(|w| w == END_HEADER)
+        .ok_or_else(|| ply_error("Missing end_header"))?;
+    let body_start = contents[header_end..]
+        .iter()
+        .position(|b| *b == b'\n')
+        .map(|i| header_end + i + 1)
+        .unwrap_or_else(|| contents.len());
+    let header = std::str::from_utf8(&contents[..header_end])
+        .map_err(|_| ply_error("The header is not text"))?;
+    let (format, elements) = parse_ply_header(header)?;
+
+    let body = &contents[body_start..];
+    let mut values = match format {
+        PlyFormat::Ascii => PlyValues::Ascii(
+            std::str::from_utf8(body)
+                .map_err(|_| ply_error("The body of an ascii file is not text"))?
+                .split_ascii_whitespace(),
+        ),
+        PlyFormat::BinaryLittleEndian => PlyValues::Binary {
+            data: body,
+            big_endian: false,
+        },
+        PlyFormat::BinaryBigEndian => PlyValues::Binary {
+            data: body,
+            big_endian: true,
+        },
+    };
+
+    let mut mesh = MeshData::default();
+    let mut scalars = Vec::new();
+    let mut list = Vec::new();
+    for element in elements.iter() {
+        match element.name.as_str() {
+            "vertex" => {
+                let find = |names: &[&str]| -> Option<Vec<(usize, Scalar)>> {
+                    names.iter().map(|name| element.scalar(name)).collect()
+                };
+                let position = find(&["x", "y", "z"])
+                    .ok_or_else(|| ply_error("The vertices have no position"))?;
+                let normal = find(&["nx", "ny", "nz"]);
+                let tex_coord = find(&["u", "v"])
+                    .or_else(|| find(&["s", "t"]))
+                    .or_else(|| find(&["texture_u", "texture_v"]));
+                let color = find(&["red", "green", "blue"]);
+                let alpha = element.scalar("alpha");
+
+                let mut positions = Vec::with_capacity(element.count);
+                let mut normals = Vec::new();
+                let mut tex_coords = Vec::new();
+                let mut colors = Vec::new();
+                for _ in 0..element.count {
+                    values.read_element(element, &mut scalars, None, &mut list)?;
+                    let get = |(i, _): (usize, Scalar)| scalars[i] as f32;
+                    let get_color = |(i, ty): (usize, Scalar)| ty.normalize(scalars[i]) as f32;
+                    positions.push(Vec3::new(
+                        get(position[0]),
+                        get(position[1]),
+                        get(position[2]),
+                    ));
+                    if let Some(n) = &normal {
+                        normals.push(Vec3::new(get(n[0]), get(n[1]), get(n[2])).normalized());
+                    }
+                    if let Some(t) = &tex_coord {
+                        tex_coords.push(Vec2::new(get(t[0]), 1.0 - get(t[1])));
+                    }
+                    if let Some(c) = &color {
+                        let a = alpha.map(get_color).unwrap_or(1.0);
+                        colors.push(Vec4::new(
+                            get_color(c[0]),
+                            get_color(c[1]),
+                            get_color(c[2]),
+                            a,
+                        ));
+                    }
+                }
+                mesh.positions = positions;
+                mesh.normals = normal.map(|_| normals);
+                mesh.tex_coords = tex_coord.map(|_| tex_coords);
+                mesh.colors = color.map(|_| colors);
+            }
+            "face" => {
+                // Faces can have other lists, e.g. the texture coordinates of their corners
+                let indices = element
+                    .list(&["vertex_indices", "vertex_index"])
+                    .ok_or_else(|| ply_error("The faces have no vertex indices"))?;
+                for _ in 0..element.count {
+                    values.read_element(element, &mut scalars, Some(indices), &mut list)?;
+                    if list.len() < 3 {
+                        return Err(ply_error("A face needs at least three vertices"));
+                    }
+                    for k in 1..list.len() - 1 {
+                        for &idx in [list[0], list[k], list[k + 1]].iter() {
+                            mesh.indices.push(idx as u32);
+                        }
+                    }
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    values.read_element(element, &mut scalars, None, &mut list)?;
+                }
+            }
+        }
+    }
+
+    let n_vertices = mesh.positions.len();
+    if mesh.indices.iter().any(|&i| i as usize >= n_vertices) {
+        return Err(ply_error("A face refers to a vertex that does not exist"));
+    }
+    Ok(mesh)
+}
+
+fn read(path: &Path) -> Result<MeshData, MeshFileError> {
+    let contents = std::fs::read(path).map_err(|e| MeshFileError::Io(path.to_path_buf(), e))?;
+    let mesh = match path.extension().and_then(|e| e.to_str()) {
+        Some("obj") => parse_obj(&String::from_utf8_lossy(&contents))?,
+        Some("ply") => parse_ply(&contents)?,
+        _ => return Err(MeshFileError::UnknownFormat(path.to_path_buf())),
+    };
+    if mesh.positions.is_empty() {
+        return Err(MeshFileError::Empty(path.to_path_buf()));
+    }
+    Ok(mesh)
+}
+
+/// The positions, normals and triangles of the file, for previews of it, see render::thumbnail.
+/// There are no triangles in point clouds.
+pub(crate) fn read_triangles(
+    path: &Path,
+) -> Result<(Vec<Vec3>, Vec<Vec3>, Vec<u32>), MeshFileError> {
+    let mesh = read(path)?;
+    let normals = match mesh.normals {
+        Some(normals) => normals,
+        None => compute_normals(&mesh.positions, &mesh.indices),
+    };
+    Ok((mesh.positions, normals, mesh.indices))
+}
+
+fn default_material(has_vertex_colors: bool) -> PhysicallyBased {
+    PhysicallyBased {
+        base_color_factor: Vec4::new(1.0, 1.0, 1.0, 1.0),
+        metallic_factor: 0.0,
+        roughness_factor: 0.5,
+        normal_scale: 1.0,
+        normal_map: None,
+        base_color_texture: None,
+        metallic_roughness_texture: None,
+        emissive_factor: Rgb::new(0.0, 0.0, 0.0),
+        emissive_texture: None,
+        occlusion_strength: 1.0,
+        occlusion_texture: None,
+        transmission: 0.0,
+        ior: DEFAULT_IOR,
+        has_vertex_colors,
+    }
+}
+
+#[derive(Default, Component)]
+pub(crate) struct LoadMeshFile {
+    path: PathBuf,
+}
+
+impl LoadMeshFile {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+/// The file that the mesh of the entity was loaded from
+#[derive(Default, Component)]
+#[component(inspect)]
+pub struct MeshFileAsset {
+    path: PathBuf,
+}
+
+impl MeshFileAsset {
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+pub fn load_asset(world: &mut World, path: &Path) {
+    world
+        .create_entity()
+        .with(LoadMeshFile::new(PathBuf::from(path)))
+        .build();
+}
+
+struct MeshFileLoader;
+
+impl MeshFileLoader {
+    pub const ID: &'static str = "MeshFileLoader";
+}
+
+#[derive(SystemData)]
+struct MeshFileLoaderData<'a> {
+    entities: Entities<'a>,
+    load: WriteStorage<'a, LoadMeshFile>,
+    assets: WriteStorage<'a, MeshFileAsset>,
+    names: WriteStorage<'a, Name>,
+    transforms: WriteStorage<'a, Transform>,
+    bboxes: WriteStorage<'a, BoundingBox>,
+    meshes: WriteStorage<'a, CpuMesh>,
+    pbr: WriteStorage<'a, PhysicallyBased>,
+    unlit: WriteStorage<'a, Unlit>,
+}
+
+impl<'a> System<'a> for MeshFileLoader {
+    type SystemData = MeshFileLoaderData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let MeshFileLoaderData {
+            entities,
+            mut load,
+            mut assets,
+            mut names,
+            mut transforms,
+            mut bboxes,
+            mut meshes,
+            mut pbr,
+            mut unlit,
+        } = data;
+
+        let loads: Vec<(Entity, PathBuf)> = (&entities, &load)
+            .join()
+            .map(|(ent, l)| (ent, l.path.clone()))
+            .collect();
+        load.clear();
+
+        for (ent, path) in loads {
+            let mesh = match read(&path) {
+                Ok(mesh) => mesh,
+                Err(e) => {
+                    log::error!("Failed to load {}: {}", path.display(), e);
+                    entities.delete(ent).expect("The entity is alive");
+                    continue;
+                }
+            };
+            log::info!(
+                "Loaded {} with {} vertices and {} triangles",
+                path.display(),
+                mesh.positions.len(),
+                mesh.indices.len() / 3
+            );
+
+            let bbox = BoundingBox::from_points(mesh.positions.iter().copied())
+                .expect("Meshes without vertices are not loaded");
+            bboxes.insert(ent, bbox).expect("The entity is alive");
+            if mesh.indices.is_empty() {
+                meshes
+                    .insert(ent, mesh.point_cloud())
+                    .expect("The entity is alive");
+                unlit
+                    .insert(
+                        ent,
+                        Unlit {
+                            color: Rgba::new(1.0, 1.0, 1.0, 1.0),
+                        },
+                    )
+                    .expect("The entity is alive");
+            } else {
+                let material = default_material(mesh.colors.is_some());
+                meshes
+                    .insert(ent, mesh.surface())
+                    .expect("The entity is alive");
+                pbr.insert(ent, material).expect("The entity is alive");
+            }
+
+            if !transforms.contains(ent) {
+                transforms
+                    .insert(ent, Transform::identity())
+                    .expect("The entity is alive");
+            }
+            if !names.contains(ent) {
+                let name = path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                names
+                    .insert(ent, Name::from(name))
+                    .expect("The entity is alive");
+            }
+            assets
+                .insert(ent, MeshFileAsset { path })
+                .expect("The entity is alive");
+        }
+    }
+}
+
+pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
+    builder.with(
+        MeshFileLoader,
+        MeshFileLoader::ID,
+        &[super::commands::AssetCommandSys::ID],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obj_faces_share_vertices_with_the_same_indices() {
+        let obj = "
+            # A quad with a shared normal
+            mtllib quad.mtl
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vt 0 0
+            vn 0 0 1
+            f 1//1 2//1 3//1 4//1
+            f -4/1/1 -2/1/1 -1/1/1
+        ";
+        let mesh = parse_obj(obj).unwrap();
+        assert_eq!(mesh.positions.len(), 7);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3, 4, 5, 6]);
+        assert_eq!(mesh.normals.unwrap()[0], Vec3::new(0.0, 0.0, 1.0));
+        // Only the second face has texture coordinates
+        assert!(mesh.tex_coords.is_none());
+
+        assert!(matches!(
+            parse_obj("v 0 0 0\nf 1 2 3"),
+            Err(MeshFileError::Obj(2, _))
+        ));
+    }
+
+    #[test]
+    fn obj_texture_coordinates_without_v() {
+        let obj = "
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            vt 0.25
+            vt 0.5 1
+            f 1/1 2/1 3/2
+        ";
+        let tex_coords = parse_obj(obj).unwrap().tex_coords.unwrap();
+        assert_eq!(tex_coords[0], Vec2::new(0.25, 1.0));
+        assert_eq!(tex_coords[2], Vec2::new(0.5, 0.0));
+    }
+
+    #[test]
+    fn ply_faces_with_several_lists_use_the_vertex_indices() {
+        let ply = "ply\nformat ascii 1.0\nelement vertex 4\n\
+                   property float x\nproperty float y\nproperty float z\n\
+                   element face 1\nproperty list uchar float texcoord\n\
+                   property list uchar int vertex_indices\nproperty list uchar int flags\n\
+                   end_header\n\
+                   0 0 0\n1 0 0\n1 1 0\n0 1 0\n\
+                   6 0 0 1 0 1 1 4 0 1 2 3 1 7\n";
+        let mesh = parse_ply(ply.as_bytes()).unwrap();
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+
+        let without_indices = ply.replace("vertex_indices", "corners");
+        assert!(matches!(
+            parse_ply(without_indices.as_bytes()),
+            Err(MeshFileError::Ply(_))
+        ));
+    }
+
+    #[test]
+    fn ascii_and_binary_ply_are_the_same_mesh() {
+        let header = |format: &str| {
+            format!(
+                "ply\nformat {} 1.0\ncomment made by hand\nelement vertex 3\n\
+                 property float x\nproperty float y\nproperty float z\n\
+                 property uchar red\nproperty uchar green\nproperty uchar blue\n\
+                 element face 1\nproperty list uchar int vertex_indices\nend_header\n",
+                format
+            )
+        };
+        let ascii = header("ascii") + "0 0 0 255 0 0\n1 0 0 0 255 0\n0 1 0 0 0 255\n3 0 1 2\n";
+        let mut binary = header("binary_little_endian").into_bytes();
+        for (pos, color) in [
+            ([0.0f32, 0.0, 0.0], [255u8, 0, 0]),
+            ([1.0, 0.0, 0.0], [0, 255, 0]),
+            ([0.0, 1.0, 0.0], [0, 0, 255]),
+        ]
+        .iter()
+        {
+            for p in pos.iter() {
+                binary.extend_from_slice(&p.to_le_bytes());
+            }
+            binary.extend_from_slice(color);
+        }
+        binary.push(3);
+        for i in 0..3i32 {
+            binary.extend_from_slice(&i.to_le_bytes());
+        }
+
+        for contents in [ascii.into_bytes(), binary].iter() {
+            let mesh = parse_ply(contents).unwrap();
+            assert_eq!(mesh.positions[1], Vec3::new(1.0, 0.0, 0.0));
+            assert_eq!(mesh.indices, vec![0, 1, 2]);
+            assert_eq!(
+                mesh.colors.as_ref().unwrap()[2],
+                Vec4::new(0.0, 0.0, 1.0, 1.0)
+            );
+            assert!(mesh.normals.is_none());
+            // CCW in the x/y-plane
+            assert_eq!(
+                compute_normals(&mesh.positions, &mesh.indices)[0],
+                Vec3::new(0.0, 0.0, 1.0)
+            );
+        }
+    }
+}
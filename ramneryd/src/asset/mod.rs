@@ -1,10 +1,39 @@
 use crate::ecs;
 
+use std::path::PathBuf;
+
+pub mod commands;
+pub mod dependencies;
 pub mod gltf;
+pub mod mesh_file;
+pub mod prefab;
 pub mod rsf;
+pub mod server;
+pub mod template;
+pub mod timings;
+
+/// Root directory of the assets that are listed in the asset browser
+pub struct ContentDirectory(pub PathBuf);
 
 pub fn register_systems<'a, 'b>(
     builder: ecs::ExecutorBuilder<'a, 'b>,
 ) -> ecs::ExecutorBuilder<'a, 'b> {
-    register_module_systems!(builder, self::gltf, rsf)
+    let builder = builder
+        .with(
+            commands::AssetCommandSys,
+            commands::AssetCommandSys::ID,
+            &[],
+        )
+        .with(
+            template::TemplateCommandSys,
+            template::TemplateCommandSys::ID,
+            &[],
+        );
+    register_module_systems!(builder, self::gltf, rsf, mesh_file)
+        .with(
+            dependencies::AssetWatcher::default(),
+            dependencies::AssetWatcher::ID,
+            &[],
+        )
+        .with(server::AssetServerSys, server::AssetServerSys::ID, &[])
 }
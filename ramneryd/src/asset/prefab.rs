@@ -0,0 +1,318 @@
+//! Prefabs are templates of an entity hierarchy, e.g. a loaded asset, that can be instantiated
+//! several times with different root transforms. The instances share the gpu meshes and materials
+//! of the template, see SharedMesh and SharedMaterial.
+//!
+//! The template is a copy of the hierarchy when it was registered, so later edits of the source
+//! entities are not seen by new instances. Only names, transforms, bounding boxes, meshes,
+//! materials and lights are copied; skins and animations are not.
+
+use crate::ecs::prelude::*;
+
+use imgui::*;
+
+use crate::common::Name;
+use crate::graph::{self, Parent};
+use crate::math::{BoundingBox, Transform};
+use crate::render::light::Light;
+use crate::render::material::{PhysicallyBased, SharedMaterial, UsesMaterial};
+use crate::render::mesh::{CpuMesh, SharedMesh};
+use crate::render::ui::UiFrame;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PrefabHandle(usize);
+
+struct PrefabNode {
+    /// Index of the parent in Prefab::nodes, None for the root
+    parent: Option<usize>,
+    name: Option<Name>,
+    transform: Transform,
+    bbox: Option<BoundingBox>,
+    mesh: Option<SharedMesh>,
+    material: Option<SharedMaterial>,
+    uses_material: Option<UsesMaterial>,
+    light: Option<Light>,
+}
+
+pub struct Prefab {
+    pub name: String,
+    /// Breadth first, so parents come before their children
+    nodes: Vec<PrefabNode>,
+}
+
+impl Prefab {
+    fn capture(world: &World, root: Entity, name: String) -> Self {
+        let mut entities = Vec::new();
+        graph::world::breadth_first(world, root, |ent| entities.push(ent));
+
+        let parents = world.read_storage::<Parent>();
+        let names = world.read_storage::<Name>();
+        let transforms = world.read_storage::<Transform>();
+        let bboxes = world.read_storage::<BoundingBox>();
+        let cpu_meshes = world.read_storage::<CpuMesh>();
+        let shared_meshes = world.read_storage::<SharedMesh>();
+        let materials = world.read_storage::<PhysicallyBased>();
+        let shared_materials = world.read_storage::<SharedMaterial>();
+        let uses_material = world.read_storage::<UsesMaterial>();
+        let lights = world.read_storage::<Light>();
+
+        let nodes = entities
+            .iter()
+            .map(|&ent| {
+                let parent = if ent == root {
+                    None
+                } else {
+                    parents
+                        .get(ent)
+                        .and_then(|p| entities.iter().position(|e| *e == p.parent))
+                };
+                // Meshes and materials that are not shared yet are shared between the instances
+                let mesh = shared_meshes
+                    .get(ent)
+                    .cloned()
+                    .or_else(|| cpu_meshes.get(ent).cloned().map(SharedMesh::new));
+                let material = shared_materials
+                    .get(ent)
+                    .cloned()
+                    .or_else(|| materials.get(ent).cloned().map(SharedMaterial::new));
+                PrefabNode {
+                    parent,
+                    name: names.get(ent).cloned(),
+                    transform: transforms
+                        .get(ent)
+                        .copied()
+                        .unwrap_or_else(Transform::identity),
+                    bbox: bboxes.get(ent).copied(),
+                    mesh,
+                    material,
+                    uses_material: uses_material.get(ent).copied(),
+                    light: lights.get(ent).cloned(),
+                }
+            })
+            .collect();
+
+        Self { name, nodes }
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+/// The registered prefabs
+#[derive(Default)]
+pub struct Prefabs {
+    prefabs: Vec<Prefab>,
+}
+
+impl Prefabs {
+    pub fn get(&self, handle: PrefabHandle) -> Option<&Prefab> {
+        self.prefabs.get(handle.0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (PrefabHandle, &Prefab)> {
+        self.prefabs
+            .iter()
+            .enumerate()
+            .map(|(i, prefab)| (PrefabHandle(i), prefab))
+    }
+}
+
+/// Added to the root entity of a prefab instance
+#[derive(Debug, Clone, Copy, Component)]
+pub struct PrefabInstance(pub PrefabHandle);
+
+/// Register the hierarchy below root, including root, as a prefab
+pub fn register(world: &mut World, root: Entity, name: impl Into<String>) -> PrefabHandle {
+    let prefab = Prefab::capture(world, root, name.into());
+    log::info!(
+        "Registered prefab {} with {} nodes",
+        prefab.name,
+        prefab.num_nodes()
+    );
+    let mut prefabs = world.entry::<Prefabs>().or_insert_with(Prefabs::default);
+    prefabs.prefabs.push(prefab);
+    PrefabHandle(prefabs.prefabs.len() - 1)
+}
+
+/// Create a new instance of the prefab. The root of the instance gets the transform, the other
+/// entities keep the ones they had in the template. Returns the root, or None if the handle is not
+/// a registered prefab.
+pub fn instantiate(
+    world: &mut World,
+    handle: PrefabHandle,
+    transform: Transform,
+) -> Option<Entity> {
+    let prefabs = world.try_fetch::<Prefabs>()?;
+    let prefab = prefabs.get(handle)?;
+
+    let entities = world.entities();
+    let mut parents = world.write_storage::<Parent>();
+    let mut children = world.write_storage::<graph::Children>();
+    let mut names = world.write_storage::<Name>();
+    let mut transforms = world.write_storage::<Transform>();
+    let mut bboxes = world.write_storage::<BoundingBox>();
+    let mut shared_meshes = world.write_storage::<SharedMesh>();
+    let mut shared_materials = world.write_storage::<SharedMaterial>();
+    let mut uses_material = world.write_storage::<UsesMaterial>();
+    let mut lights = world.write_storage::<Light>();
+
+    let mut instance: Vec<Entity> = Vec::with_capacity(prefab.nodes.len());
+    for node in prefab.nodes.iter() {
+        let tfm = match node.parent {
+            Some(_) => node.transform,
+            None => transform,
+        };
+        let mut builder = entities.build_entity().with(tfm, &mut transforms);
+        if let Some(name) = &node.name {
+            builder = builder.with(name.clone(), &mut names);
+        }
+        if let Some(bbox) = node.bbox {
+            builder = builder.with(bbox, &mut bboxes);
+        }
+        if let Some(mesh) = &node.mesh {
+            builder = builder.with(mesh.clone(), &mut shared_meshes);
+        }
+        if let Some(material) = &node.material {
+            builder = builder.with(material.clone(), &mut shared_materials);
+        }
+        if let Some(material) = node.uses_material {
+            builder = builder.with(material, &mut uses_material);
+        }
+        if let Some(light) = &node.light {
+            builder = builder.with(light.clone(), &mut lights);
+        }
+        let ent = builder.build();
+
+        if let Some(parent) = node.parent {
+            graph::sys::add_edge(&mut children, &mut parents, instance[parent], ent);
+        }
+        instance.push(ent);
+    }
+
+    let root = *instance.first()?;
+    world
+        .write_storage::<PrefabInstance>()
+        .insert(root, PrefabInstance(handle))
+        .expect("The root was just created");
+    Some(root)
+}
+
+/// Registers the selected entity as a prefab and instantiates the registered ones
+pub(crate) fn build_ui<'a>(world: &mut World, ui: &UiFrame<'a>, pos: [f32; 2]) -> [f32; 2] {
+    let size = [300.0, 200.0];
+
+    let mut to_register = None;
+    let mut to_instantiate = None;
+    Window::new(im_str!("Prefabs"))
+        .position(pos, Condition::FirstUseEver)
+        .size(size, Condition::FirstUseEver)
+        .collapsed(true, Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let selected = world
+                .try_fetch::<crate::editor::Selection>()
+                .and_then(|selection| selection.entities().first().copied())
+                .filter(|ent| world.is_alive(*ent));
+            if let Some(ent) = selected {
+                if ui.inner().button(im_str!("Register selection"), [0.0, 0.0]) {
+                    to_register = Some(ent);
+                }
+            } else {
+                ui.inner().text(im_str!("Select an entity to register it"));
+            }
+            ui.inner().separator();
+
+            let id = String::from("PrefabInstancePosition");
+            let mut position: [f32; 3] = ui.storage().get(&id).copied().unwrap_or_default();
+            if InputFloat3::new(ui.inner(), im_str!("Position"), &mut position).build() {
+                ui.storage().insert(id, position);
+            }
+
+            if let Some(prefabs) = world.try_fetch::<Prefabs>() {
+                for (handle, prefab) in prefabs.iter() {
+                    let id_token = ui.inner().push_id(handle.0 as i32);
+                    if ui.inner().button(im_str!("Instantiate"), [0.0, 0.0]) {
+                        to_instantiate = Some((handle, position));
+                    }
+                    ui.inner().same_line(0.0);
+                    ui.inner()
+                        .text(im_str!("{} ({} nodes)", prefab.name, prefab.num_nodes()));
+                    id_token.pop(ui.inner());
+                }
+            }
+        });
+
+    if let Some(root) = to_register {
+        let name = world
+            .read_storage::<Name>()
+            .get(root)
+            .map(|name| name.0.clone())
+            .unwrap_or_else(|| format!("Prefab {}", root.id()));
+        register(world, root, name);
+    }
+    if let Some((handle, [x, y, z])) = to_instantiate {
+        instantiate(world, handle, Transform::pos(x, y, z));
+    }
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn instances_share_meshes_and_materials() {
+        let mut world = World::new();
+        crate::ecs::meta::register_all_components(&mut world);
+
+        let root = world
+            .create_entity()
+            .with(Name::from("Lamp"))
+            .with(Transform::pos(5.0, 0.0, 0.0))
+            .build();
+        let (vertex_buffer, index_buffer) = crate::render::geometry::box_mesh(1.0, 1.0, 1.0);
+        let mesh = SharedMesh::new(CpuMesh {
+            vertex_buffer,
+            index_buffer,
+            polygon_mode: trekanten::pipeline::PolygonMode::Fill,
+        });
+        let child = world
+            .create_entity()
+            .with(Transform::pos(0.0, 1.0, 0.0))
+            .with(mesh.clone())
+            .with(PhysicallyBased::default())
+            .build();
+        crate::graph::world::add_edge(&mut world, root, child);
+
+        let handle = register(&mut world, root, "Lamp");
+        let a = instantiate(&mut world, handle, Transform::pos(1.0, 0.0, 0.0)).unwrap();
+        let b = instantiate(&mut world, handle, Transform::pos(2.0, 0.0, 0.0)).unwrap();
+        assert_eq!(
+            world.read_storage::<Transform>().get(b).unwrap().position.x,
+            2.0
+        );
+        assert!(world.read_storage::<PrefabInstance>().get(a).is_some());
+
+        let children = world.read_storage::<graph::Children>();
+        let child_a = children.get(a).unwrap().children[0];
+        let child_b = children.get(b).unwrap().children[0];
+        let meshes = world.read_storage::<SharedMesh>();
+        assert!(Arc::ptr_eq(&meshes.get(child_a).unwrap().0, &mesh.0));
+        assert!(Arc::ptr_eq(&meshes.get(child_b).unwrap().0, &mesh.0));
+        let materials = world.read_storage::<SharedMaterial>();
+        assert!(Arc::ptr_eq(
+            &materials.get(child_a).unwrap().0,
+            &materials.get(child_b).unwrap().0
+        ));
+        assert_eq!(
+            world
+                .read_storage::<Transform>()
+                .get(child_b)
+                .unwrap()
+                .position
+                .y,
+            1.0
+        );
+    }
+}
@@ -4,10 +4,16 @@ use crate::ecs::prelude::*;
 use std::path::{Path, PathBuf};
 
 #[derive(Default, Component)]
-struct LoadRsfAsset {
+pub(crate) struct LoadRsfAsset {
     path: PathBuf,
 }
 
+impl LoadRsfAsset {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
 #[derive(Default, Component)]
 #[component(inspect)]
 pub struct RsfAsset {
@@ -76,5 +82,9 @@ impl<'a> System<'a> for RsfLoader {
 }
 
 pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
-    builder.with(RsfLoader, RsfLoader::ID, &[])
+    builder.with(
+        RsfLoader,
+        RsfLoader::ID,
+        &[super::commands::AssetCommandSys::ID],
+    )
 }
@@ -0,0 +1,865 @@
+//! Loads assets on worker threads, so that they can be requested ahead of time without stalling the
+//! frame. The AssetServer returns a typed handle immediately, which can be used to query the load
+//! state. An AssetEvent is sent when the asset is done, or failed to load.
+//!
+//! glTF scenes are imported on the workers and spawned with spawn_scene when they are loaded, which
+//! does not block as the GltfLoader uses the preloaded file. Textures are decoded on the workers and
+//! then uploaded by the trekanten loader. They are loaded when the upload is done.
+//!
+//! The workers take the queued jobs with the highest Priority first. Loads can be cancelled, e.g.
+//! when another scene is loaded, which skips the job if it hasn't started and drops its result
+//! otherwise.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use thiserror::Error;
+
+use trekanten::loader::{Loader, LoaderError, ResourceLoader as _};
+use trekanten::resource::{Async, Handle};
+use trekanten::texture::{MipMaps, TextureDescriptor};
+use trekanten::util::{Extent2D, Format};
+
+use super::timings::LoadTimings;
+use crate::ecs::prelude::*;
+use crate::file_watcher::WatchedFiles;
+
+const NUM_WORKERS: usize = 2;
+
+/// A glTF file that has been imported
+pub enum Scene {}
+/// An image file that has been uploaded to the gpu
+pub enum Texture {}
+
+/// Identifies an asset, regardless of its type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AssetId(u64);
+
+pub struct AssetHandle<T> {
+    id: AssetId,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> AssetHandle<T> {
+    fn new(id: AssetId) -> Self {
+        Self {
+            id,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn id(&self) -> AssetId {
+        self.id
+    }
+}
+
+// Implemented by hand, as the derives would require T to implement them
+impl<T> Clone for AssetHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for AssetHandle<T> {}
+impl<T> PartialEq for AssetHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+impl<T> Eq for AssetHandle<T> {}
+impl<T> Hash for AssetHandle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+impl<T> std::fmt::Debug for AssetHandle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AssetHandle({})", self.id.0)
+    }
+}
+
+/// The order that queued loads are started in. Loads with the same priority are started in the
+/// order they were requested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// E.g. preloading assets that may be needed later
+    Background,
+    Normal,
+    /// Needed by entities that are in view
+    Visible,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+/// Cancels a load. Shared between the AssetServer and the queued job.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadState {
+    Loading,
+    Loaded,
+    Failed(String),
+    /// The handle was unloaded
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+pub enum AssetEvent {
+    Loaded {
+        id: AssetId,
+        path: PathBuf,
+    },
+    Failed {
+        id: AssetId,
+        path: PathBuf,
+        error: String,
+    },
+    /// The load was cancelled before it was done. The handle is no longer valid.
+    Cancelled {
+        id: AssetId,
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum AssetError {
+    #[error("Failed to import glTF: {0}")]
+    Gltf(#[from] gltf::Error),
+    #[error("Failed to decode image: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("Failed to upload texture: {0}")]
+    Upload(#[from] LoaderError),
+}
+
+/// The contents of a glTF file, as returned by gltf::import
+pub(crate) struct ImportedGltf {
+    pub document: gltf::Document,
+    pub buffers: Arc<Vec<gltf::buffer::Data>>,
+    // The file and its buffer files, as they were when it was imported
+    sources: WatchedFiles<()>,
+    /// The import stages, done on the worker
+    pub timings: LoadTimings,
+}
+
+impl ImportedGltf {
+    /// False if any of the files have changed since it was imported
+    fn is_current(&self) -> bool {
+        self.sources.is_current()
+    }
+}
+
+enum Job {
+    Scene(PathBuf),
+    Texture(PathBuf, Format),
+}
+
+enum JobOutput {
+    Scene(ImportedGltf),
+    Texture(TextureDescriptor),
+}
+
+type JobResult = (AssetId, Result<JobOutput, AssetError>);
+
+struct QueuedJob {
+    priority: Priority,
+    /// The order it was requested in
+    seq: u64,
+    id: AssetId,
+    job: Job,
+    token: CancellationToken,
+}
+
+impl QueuedJob {
+    fn key(&self) -> (Priority, Reverse<u64>) {
+        (self.priority, Reverse(self.seq))
+    }
+}
+
+impl PartialEq for QueuedJob {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for QueuedJob {}
+
+impl PartialOrd for QueuedJob {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedJob {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    queued: BinaryHeap<QueuedJob>,
+    running: Vec<AssetId>,
+    /// The workers exit when this is set, without running the queued jobs
+    closed: bool,
+}
+
+/// The jobs that the workers haven't started, highest priority first
+#[derive(Default)]
+struct JobQueue {
+    state: Mutex<QueueState>,
+    available: Condvar,
+}
+
+impl JobQueue {
+    fn push(&self, job: QueuedJob) {
+        self.state
+            .lock()
+            .expect("Asset job queue poisoned")
+            .queued
+            .push(job);
+        self.available.notify_one();
+    }
+
+    /// Blocks until there is a job, None when the queue is closed
+    fn pop(&self) -> Option<(AssetId, Job, CancellationToken)> {
+        let mut state = self.state.lock().expect("Asset job queue poisoned");
+        loop {
+            if state.closed {
+                return None;
+            }
+            if let Some(QueuedJob { id, job, token, .. }) = state.queued.pop() {
+                state.running.push(id);
+                return Some((id, job, token));
+            }
+            state = self
+                .available
+                .wait(state)
+                .expect("Asset job queue poisoned");
+        }
+    }
+
+    fn done(&self, id: AssetId) {
+        let mut state = self.state.lock().expect("Asset job queue poisoned");
+        state.running.retain(|r| *r != id);
+    }
+
+    fn set_priority(&self, id: AssetId, priority: Priority) {
+        let mut state = self.state.lock().expect("Asset job queue poisoned");
+        let mut jobs = std::mem::take(&mut state.queued).into_vec();
+        for job in jobs.iter_mut().filter(|job| job.id == id) {
+            job.priority = priority;
+        }
+        state.queued = BinaryHeap::from(jobs);
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock().expect("Asset job queue poisoned");
+        state.closed = true;
+        state.queued.clear();
+        self.available.notify_all();
+    }
+}
+
+/// The state of the job queue of the AssetServer, for the ui
+#[derive(Debug, Clone, Default)]
+pub struct QueueInfo {
+    /// In the order they will be started
+    pub queued: Vec<(AssetId, Priority)>,
+    pub running: Vec<AssetId>,
+}
+
+fn run_job(job: Job) -> Result<JobOutput, AssetError> {
+    match job {
+        Job::Scene(path) => {
+            let mut sources = WatchedFiles::default();
+            sources.watch(&path, || ());
+            let mut timings = LoadTimings::default();
+            let (document, buffers, _images) = timings.time("parse", || {
+                profiling::scope!("parse gltf");
+                gltf::import(&path)
+            })?;
+            let parent_path = path.parent().expect("Invalid path");
+            for buffer in document.buffers() {
+                if let gltf::buffer::Source::Uri(uri) = buffer.source() {
+                    sources.watch(&parent_path.join(uri), || ());
+                }
+            }
+
+            Ok(JobOutput::Scene(ImportedGltf {
+                document,
+                buffers: Arc::new(buffers),
+                sources,
+                timings,
+            }))
+        }
+        Job::Texture(path, format) => {
+            profiling::scope!("decode image");
+            let image = trekanten::texture::load_image(&path)?;
+            let (width, height) = image.dimensions();
+            let extent = Extent2D { width, height };
+            Ok(JobOutput::Texture(TextureDescriptor::from_vec(
+                image.into_raw(),
+                extent,
+                format,
+                MipMaps::None,
+            )))
+        }
+    }
+}
+
+fn run_worker(jobs: Arc<JobQueue>, results: Sender<JobResult>) {
+    while let Some((id, job, token)) = jobs.pop() {
+        if token.is_cancelled() {
+            jobs.done(id);
+            continue;
+        }
+
+        let result = run_job(job);
+        jobs.done(id);
+        if results.send((id, result)).is_err() {
+            return;
+        }
+    }
+}
+
+enum Asset {
+    Scene(Arc<ImportedGltf>),
+    /// Waiting for the trekanten loader to upload it
+    PendingTexture(Handle<Async<trekanten::Texture>>),
+    Texture(Handle<trekanten::Texture>),
+}
+
+enum EntryState {
+    Loading,
+    Loaded(Asset),
+    Failed(String),
+}
+
+struct Entry {
+    path: PathBuf,
+    state: EntryState,
+    priority: Priority,
+    token: CancellationToken,
+}
+
+pub struct AssetServer {
+    jobs: Arc<JobQueue>,
+    results: Receiver<JobResult>,
+    workers: Vec<JoinHandle<()>>,
+    next_id: u64,
+    entries: HashMap<AssetId, Entry>,
+    scenes: HashMap<PathBuf, AssetId>,
+    textures: HashMap<(PathBuf, Format), AssetId>,
+    // Events are collected until the next poll, when they replace the events of the previous one
+    pending_events: Vec<AssetEvent>,
+    events: Vec<AssetEvent>,
+}
+
+impl Default for AssetServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AssetServer {
+    pub fn new() -> Self {
+        let jobs = Arc::new(JobQueue::default());
+        let (result_sender, results) = unbounded();
+
+        let workers = (0..NUM_WORKERS)
+            .map(|i| {
+                let jobs = Arc::clone(&jobs);
+                let results = result_sender.clone();
+                std::thread::Builder::new()
+                    .name(format!("ramneryd::asset_worker{}", i))
+                    .spawn(move || run_worker(jobs, results))
+                    .expect("Failed to start asset worker")
+            })
+            .collect();
+
+        Self {
+            jobs,
+            results,
+            workers,
+            next_id: 0,
+            entries: HashMap::new(),
+            scenes: HashMap::new(),
+            textures: HashMap::new(),
+            pending_events: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    fn start(&mut self, path: &Path, job: Job) -> AssetId {
+        let id = AssetId(self.next_id);
+        self.next_id += 1;
+
+        let priority = Priority::default();
+        let token = CancellationToken::default();
+        self.jobs.push(QueuedJob {
+            priority,
+            seq: id.0,
+            id,
+            job,
+            token: token.clone(),
+        });
+
+        self.entries.insert(
+            id,
+            Entry {
+                path: path.to_path_buf(),
+                state: EntryState::Loading,
+                priority,
+                token,
+            },
+        );
+        id
+    }
+
+    /// Start importing the glTF file. The same handle is returned for a file that is already loaded
+    /// or loading.
+    pub fn load_scene(&mut self, path: impl AsRef<Path>) -> AssetHandle<Scene> {
+        let path = path.as_ref();
+        if let Some(id) = self.scenes.get(path) {
+            return AssetHandle::new(*id);
+        }
+
+        let id = self.start(path, Job::Scene(path.to_path_buf()));
+        self.scenes.insert(path.to_path_buf(), id);
+        AssetHandle::new(id)
+    }
+
+    /// Start loading the image file as a texture with the format
+    pub fn load_texture(&mut self, path: impl AsRef<Path>, format: Format) -> AssetHandle<Texture> {
+        let path = path.as_ref();
+        let key = (path.to_path_buf(), format);
+        if let Some(id) = self.textures.get(&key) {
+            return AssetHandle::new(*id);
+        }
+
+        let id = self.start(path, Job::Texture(path.to_path_buf(), format));
+        self.textures.insert(key, id);
+        AssetHandle::new(id)
+    }
+
+    pub fn load_state<T>(&self, handle: AssetHandle<T>) -> LoadState {
+        match self.entries.get(&handle.id).map(|e| &e.state) {
+            None => LoadState::Unknown,
+            Some(EntryState::Loading) | Some(EntryState::Loaded(Asset::PendingTexture(_))) => {
+                LoadState::Loading
+            }
+            Some(EntryState::Loaded(_)) => LoadState::Loaded,
+            Some(EntryState::Failed(e)) => LoadState::Failed(e.clone()),
+        }
+    }
+
+    /// Moves the load in the queue, if it hasn't started
+    pub fn set_priority<T>(&mut self, handle: AssetHandle<T>, priority: Priority) {
+        if let Some(entry) = self.entries.get_mut(&handle.id) {
+            if let EntryState::Loading = entry.state {
+                entry.priority = priority;
+                self.jobs.set_priority(handle.id, priority);
+            }
+        }
+    }
+
+    pub fn priority<T>(&self, handle: AssetHandle<T>) -> Option<Priority> {
+        self.entries.get(&handle.id).map(|e| e.priority)
+    }
+
+    /// Stop loading the asset. The handle is no longer valid. Does nothing if it is already loaded.
+    pub fn cancel<T>(&mut self, handle: AssetHandle<T>) {
+        self.cancel_id(handle.id);
+    }
+
+    fn cancel_id(&mut self, id: AssetId) {
+        let is_loading = matches!(
+            self.entries.get(&id).map(|e| &e.state),
+            Some(EntryState::Loading)
+        );
+        if !is_loading {
+            return;
+        }
+
+        let entry = self.entries.remove(&id).expect("Just checked");
+        entry.token.cancel();
+        self.scenes.retain(|_, v| *v != id);
+        self.textures.retain(|_, v| *v != id);
+        self.pending_events.push(AssetEvent::Cancelled {
+            id,
+            path: entry.path,
+        });
+    }
+
+    /// Cancel all loads that haven't finished, e.g. when switching scenes. Textures that are being
+    /// uploaded by the trekanten loader are finished.
+    pub fn cancel_pending(&mut self) {
+        let loading: Vec<AssetId> = self
+            .entries
+            .iter()
+            .filter(|(_, e)| matches!(e.state, EntryState::Loading))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in loading {
+            self.cancel_id(id);
+        }
+    }
+
+    pub fn queue_info(&self) -> QueueInfo {
+        let state = self.jobs.state.lock().expect("Asset job queue poisoned");
+        let mut queued: Vec<&QueuedJob> = state
+            .queued
+            .iter()
+            .filter(|job| !job.token.is_cancelled())
+            .collect();
+        queued.sort_by(|a, b| b.cmp(a));
+        QueueInfo {
+            queued: queued.iter().map(|job| (job.id, job.priority)).collect(),
+            running: state.running.clone(),
+        }
+    }
+
+    pub fn is_loaded<T>(&self, handle: AssetHandle<T>) -> bool {
+        self.load_state(handle) == LoadState::Loaded
+    }
+
+    pub fn path<T>(&self, handle: AssetHandle<T>) -> Option<&Path> {
+        self.entries.get(&handle.id).map(|e| e.path.as_path())
+    }
+
+    /// The gpu texture, once it is loaded
+    pub fn texture(&self, handle: AssetHandle<Texture>) -> Option<Handle<trekanten::Texture>> {
+        match self.entries.get(&handle.id).map(|e| &e.state) {
+            Some(EntryState::Loaded(Asset::Texture(h))) => Some(*h),
+            _ => None,
+        }
+    }
+
+    /// The events since the previous poll, i.e. from the previous frame for systems that run after
+    /// the AssetServerSys
+    pub fn events(&self) -> &[AssetEvent] {
+        &self.events
+    }
+
+    /// Drop the loaded asset. The handle is no longer valid. A texture is not destroyed while it is
+    /// used elsewhere, e.g. by a material.
+    pub fn unload<T>(&mut self, handle: AssetHandle<T>) {
+        if let Some(entry) = self.entries.remove(&handle.id) {
+            entry.token.cancel();
+            self.scenes.retain(|_, id| *id != handle.id);
+            self.textures.retain(|_, id| *id != handle.id);
+        }
+    }
+
+    /// Loads the textures again, after they were lost with the device. Their handles stay valid and
+    /// they are loading until they have been uploaded again.
+    pub(crate) fn reload_textures(&mut self) {
+        for ((path, format), id) in self.textures.iter() {
+            let entry = match self.entries.get_mut(id) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            match entry.state {
+                EntryState::Loaded(Asset::Texture(_))
+                | EntryState::Loaded(Asset::PendingTexture(_)) => (),
+                _ => continue,
+            }
+
+            entry.state = EntryState::Loading;
+            self.jobs.push(QueuedJob {
+                priority: entry.priority,
+                seq: id.0,
+                id: *id,
+                job: Job::Texture(path.clone(), *format),
+                token: entry.token.clone(),
+            });
+        }
+    }
+
+    /// The imported contents of the glTF file, unless it has changed since it was imported
+    pub(crate) fn imported_gltf(&self, path: &Path) -> Option<Arc<ImportedGltf>> {
+        let id = self.scenes.get(path)?;
+        match &self.entries.get(id)?.state {
+            EntryState::Loaded(Asset::Scene(imported)) if imported.is_current() => {
+                Some(Arc::clone(imported))
+            }
+            _ => None,
+        }
+    }
+
+    /// Called with the handles of the textures that the trekanten loader has finished. Returns true
+    /// if the texture was loaded by the asset server.
+    pub(crate) fn resolve_texture(
+        &mut self,
+        old: Handle<Async<trekanten::Texture>>,
+        new: Handle<trekanten::Texture>,
+    ) -> bool {
+        for (id, entry) in self.entries.iter_mut() {
+            match entry.state {
+                EntryState::Loaded(Asset::PendingTexture(h)) if h == old => {
+                    entry.state = EntryState::Loaded(Asset::Texture(new));
+                    self.pending_events.push(AssetEvent::Loaded {
+                        id: *id,
+                        path: entry.path.clone(),
+                    });
+                    return true;
+                }
+                _ => (),
+            }
+        }
+        false
+    }
+
+    /// Receive the results of the workers and start uploading the decoded textures
+    fn poll(
+        &mut self,
+        mut upload: impl FnMut(
+            TextureDescriptor,
+        ) -> Result<Handle<Async<trekanten::Texture>>, LoaderError>,
+    ) {
+        for (id, result) in self.results.try_iter() {
+            let entry = match self.entries.get_mut(&id) {
+                Some(entry) => entry,
+                // Unloaded while it was loading
+                None => continue,
+            };
+
+            let result = result.and_then(|output| {
+                Ok(match output {
+                    JobOutput::Scene(imported) => Asset::Scene(Arc::new(imported)),
+                    JobOutput::Texture(desc) => Asset::PendingTexture(upload(desc)?),
+                })
+            });
+
+            match result {
+                Ok(asset) => {
+                    if let Asset::Scene(_) = asset {
+                        self.pending_events.push(AssetEvent::Loaded {
+                            id,
+                            path: entry.path.clone(),
+                        });
+                    }
+                    entry.state = EntryState::Loaded(asset);
+                }
+                Err(e) => {
+                    log::error!("Failed to load {}: {}", entry.path.display(), e);
+                    self.pending_events.push(AssetEvent::Failed {
+                        id,
+                        path: entry.path.clone(),
+                        error: e.to_string(),
+                    });
+                    entry.state = EntryState::Failed(e.to_string());
+                }
+            }
+        }
+
+        self.events = std::mem::take(&mut self.pending_events);
+    }
+}
+
+impl Drop for AssetServer {
+    /// Wait for the current jobs and skip the queued ones
+    fn drop(&mut self) {
+        self.jobs.close();
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                log::error!("Asset worker panicked");
+            }
+        }
+    }
+}
+
+/// Spawn the loaded scene in a new entity. None if the scene is not loaded yet.
+pub fn spawn_scene(world: &mut World, handle: AssetHandle<Scene>) -> Option<Entity> {
+    let path = {
+        let server = world.read_resource::<AssetServer>();
+        if !server.is_loaded(handle) {
+            return None;
+        }
+        server.path(handle)?.to_path_buf()
+    };
+
+    Some(
+        world
+            .create_entity()
+            .with(super::gltf::LoadGltfAsset::new(
+                path,
+                super::gltf::SceneSelection::Default,
+            ))
+            .build(),
+    )
+}
+
+pub(crate) fn build_ui<'a>(
+    world: &mut World,
+    ui: &crate::render::ui::UiFrame<'a>,
+    pos: [f32; 2],
+) -> [f32; 2] {
+    let size = [400.0, 200.0];
+
+    imgui::Window::new(imgui::im_str!("Asset loading"))
+        .position(pos, imgui::Condition::FirstUseEver)
+        .size(size, imgui::Condition::FirstUseEver)
+        .collapsed(true, imgui::Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let ui = ui.inner();
+            let mut server = world.write_resource::<AssetServer>();
+            let info = server.queue_info();
+            ui.text(imgui::im_str!(
+                "{} running, {} queued",
+                info.running.len(),
+                info.queued.len()
+            ));
+            if !info.queued.is_empty() {
+                ui.same_line(0.0);
+                if ui.button(imgui::im_str!("Cancel all"), [0.0, 0.0]) {
+                    server.cancel_pending();
+                    return;
+                }
+            }
+
+            let name = |server: &AssetServer, id: &AssetId| {
+                server
+                    .entries
+                    .get(id)
+                    .map(|e| e.path.display().to_string())
+                    .unwrap_or_default()
+            };
+            for id in info.running.iter() {
+                ui.text(imgui::im_str!("Running: {}", name(&server, id)));
+            }
+
+            let mut cancel = None;
+            let mut prioritize = None;
+            for (id, priority) in info.queued.iter() {
+                let token = ui.push_id(&imgui::im_str!("{}", id.0));
+                if ui.small_button(imgui::im_str!("Cancel")) {
+                    cancel = Some(*id);
+                }
+                if *priority != Priority::Visible {
+                    ui.same_line(0.0);
+                    if ui.small_button(imgui::im_str!("First")) {
+                        prioritize = Some(*id);
+                    }
+                }
+                ui.same_line(0.0);
+                ui.text(imgui::im_str!("{:?}: {}", priority, name(&server, id)));
+                token.pop(ui);
+            }
+
+            if let Some(id) = cancel {
+                server.cancel_id(id);
+            }
+            if let Some(id) = prioritize {
+                server.set_priority(AssetHandle::<()>::new(id), Priority::Visible);
+            }
+        });
+
+    size
+}
+
+pub struct AssetServerSys;
+
+impl AssetServerSys {
+    pub const ID: &'static str = "AssetServer";
+}
+
+impl<'a> System<'a> for AssetServerSys {
+    type SystemData = (Write<'a, AssetServer>, ReadExpect<'a, Loader>);
+
+    fn run(&mut self, (mut server, loader): Self::SystemData) {
+        server.poll(|desc| loader.load(desc));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn missing_scene_fails() {
+        let mut server = AssetServer::new();
+        let path = std::env::temp_dir().join("ramneryd_asset_server_missing.gltf");
+        let handle = server.load_scene(&path);
+
+        assert_eq!(server.load_scene(&path), handle);
+        assert_eq!(server.load_state(handle), LoadState::Loading);
+
+        let start = Instant::now();
+        while server.load_state(handle) == LoadState::Loading {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(1));
+            server.poll(|_| unreachable!("No textures are loaded"));
+        }
+
+        assert!(matches!(server.load_state(handle), LoadState::Failed(_)));
+        assert!(matches!(
+            server.events(),
+            [AssetEvent::Failed { id, .. }] if *id == handle.id()
+        ));
+        assert!(server.imported_gltf(&path).is_none());
+
+        server.unload(handle);
+        assert_eq!(server.load_state(handle), LoadState::Unknown);
+        assert_ne!(server.load_scene(&path), handle);
+    }
+
+    #[test]
+    fn higher_priority_jobs_run_first() {
+        let queue = JobQueue::default();
+        let job = |seq: u64, priority: Priority| QueuedJob {
+            priority,
+            seq,
+            id: AssetId(seq),
+            job: Job::Scene(PathBuf::new()),
+            token: CancellationToken::default(),
+        };
+        queue.push(job(0, Priority::Background));
+        queue.push(job(1, Priority::Normal));
+        queue.push(job(2, Priority::Normal));
+        queue.push(job(3, Priority::Background));
+        queue.set_priority(AssetId(3), Priority::Visible);
+
+        let order: Vec<u64> = (0..4).map(|_| queue.pop().unwrap().0 .0).collect();
+        assert_eq!(order, vec![3, 1, 2, 0]);
+    }
+
+    #[test]
+    fn cancelled_scene_is_dropped() {
+        let mut server = AssetServer::new();
+        let path = std::env::temp_dir().join("ramneryd_asset_server_cancelled.gltf");
+        let handle = server.load_scene(&path);
+        server.cancel_pending();
+        assert_eq!(server.load_state(handle), LoadState::Unknown);
+
+        // The result is dropped, if the job started before it was cancelled
+        server.poll(|_| unreachable!("No textures are loaded"));
+        assert!(matches!(
+            server.events(),
+            [AssetEvent::Cancelled { id, .. }] if *id == handle.id()
+        ));
+    }
+}
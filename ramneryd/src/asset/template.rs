@@ -0,0 +1,453 @@
+//! Templates describe an entity in a RON file, so that common objects can be spawned by name
+//! instead of being put together by hand. The templates are loaded from a directory, with one
+//! `.ron` file per template that is named after the file:
+//!
+//! ```ron
+//! (
+//!     mesh: Some(Sphere(radius: 0.5)),
+//!     material: Some((base_color_factor: (x: 1.0, y: 0.2, z: 0.2, w: 1.0), roughness_factor: 0.3)),
+//!     light: Some(Point(color: (r: 1.0, g: 0.8, b: 0.6), intensity: Lumens(800.0), range: 5.0)),
+//! )
+//! ```
+//!
+//! Templates are spawned through the TemplateCommands resource, from the ui, the `spawn` console
+//! command or any system. Unlike prefabs, see super::prefab, the mesh and material are created for
+//! each spawned entity.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use imgui::*;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::camera::Camera;
+use crate::common::Name;
+use crate::ecs::prelude::*;
+use crate::graph::{sys as graph, Children, Parent};
+use crate::math::{BoundingBox, Rgb, Transform, Vec3, Vec4};
+use crate::render::geometry::{self, PrimitiveOptions};
+use crate::render::light::Light;
+use crate::render::material::{PhysicallyBased, DEFAULT_IOR};
+use crate::render::mesh::CpuMesh;
+use crate::render::ui::UiFrame;
+
+use super::gltf::{LoadGltfAsset, SceneSelection};
+
+/// How far in front of the camera templates are spawned from the ui and the console
+const SPAWN_DISTANCE: f32 = 3.0;
+
+#[derive(Debug, Error)]
+pub enum TemplateError {
+    #[error("Failed to access {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Failed to parse template {0}: {1}")]
+    Ron(PathBuf, ron::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TemplateMesh {
+    /// Origin-centered, x, y and z are the lengths of the sides
+    Box {
+        x: f32,
+        y: f32,
+        z: f32,
+    },
+    Sphere {
+        radius: f32,
+    },
+    /// In the x/z-plane, facing +y
+    Plane {
+        x: f32,
+        z: f32,
+    },
+    /// A glTF file, that is loaded below the spawned entity
+    Asset(PathBuf),
+}
+
+impl TemplateMesh {
+    /// None for assets, which are loaded by the glTF loader
+    fn cpu_mesh(&self) -> Option<CpuMesh> {
+        let options = PrimitiveOptions::default();
+        let (vertex_buffer, index_buffer) = match *self {
+            Self::Box { x, y, z } => geometry::textured_box_mesh(x, y, z, &options),
+            Self::Sphere { radius } => geometry::textured_sphere_mesh(radius, &options),
+            Self::Plane { x, z } => geometry::textured_plane_mesh(x, z, &options),
+            Self::Asset(_) => return None,
+        };
+        Some(CpuMesh {
+            vertex_buffer,
+            index_buffer,
+            polygon_mode: trekanten::pipeline::PolygonMode::Fill,
+        })
+    }
+}
+
+/// The factors of the material of a primitive mesh. The ones that are left out of the file get
+/// the defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TemplateMaterial {
+    pub base_color_factor: Vec4,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub emissive_factor: Rgb,
+    pub transmission: f32,
+    pub ior: f32,
+}
+
+impl Default for TemplateMaterial {
+    fn default() -> Self {
+        Self {
+            base_color_factor: Vec4::new(1.0, 1.0, 1.0, 1.0),
+            metallic_factor: 0.0,
+            roughness_factor: 0.5,
+            emissive_factor: Rgb::new(0.0, 0.0, 0.0),
+            transmission: 0.0,
+            ior: DEFAULT_IOR,
+        }
+    }
+}
+
+impl TemplateMaterial {
+    pub(crate) fn material(&self) -> PhysicallyBased {
+        PhysicallyBased {
+            base_color_factor: self.base_color_factor,
+            metallic_factor: self.metallic_factor,
+            roughness_factor: self.roughness_factor,
+            normal_scale: 1.0,
+            normal_map: None,
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            emissive_factor: self.emissive_factor,
+            emissive_texture: None,
+            occlusion_strength: 1.0,
+            occlusion_texture: None,
+            transmission: self.transmission,
+            ior: self.ior,
+            has_vertex_colors: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Template {
+    #[serde(default)]
+    pub mesh: Option<TemplateMesh>,
+    /// Only used for primitive meshes, which get the default material if this is None
+    #[serde(default)]
+    pub material: Option<TemplateMaterial>,
+    #[serde(default)]
+    pub light: Option<Light>,
+}
+
+impl Template {
+    pub fn parse(contents: &str) -> Result<Self, ron::Error> {
+        ron::de::from_str(contents)
+    }
+}
+
+/// The templates that can be spawned, by name
+#[derive(Debug, Default)]
+pub struct Templates {
+    templates: BTreeMap<String, Template>,
+}
+
+impl Templates {
+    /// Loads the `.ron` files in `dir`, named after the files. Templates that fail to parse are
+    /// logged and skipped.
+    pub fn load_dir(dir: &Path) -> Result<Self, TemplateError> {
+        let entries =
+            std::fs::read_dir(dir).map_err(|e| TemplateError::Io(dir.to_path_buf(), e))?;
+
+        let mut templates = Self::default();
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            let name = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(name) if path.extension().map(|e| e == "ron").unwrap_or(false) => name,
+                _ => continue,
+            };
+            match Self::read(&path) {
+                Ok(template) => templates.insert(name, template),
+                Err(e) => log::error!("{}", e),
+            }
+        }
+
+        Ok(templates)
+    }
+
+    fn read(path: &Path) -> Result<Template, TemplateError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| TemplateError::Io(path.to_path_buf(), e))?;
+        Template::parse(&contents).map_err(|e| TemplateError::Ron(path.to_path_buf(), e))
+    }
+
+    /// Replaces the template with the same name, if there is one
+    pub fn insert(&mut self, name: impl Into<String>, template: Template) {
+        self.templates.insert(name.into(), template);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Template> {
+        self.templates.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.templates.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+}
+
+/// The queue of templates to spawn. Applied once per frame, in the order they were queued.
+#[derive(Debug, Default)]
+pub struct TemplateCommands {
+    queue: Vec<(String, Transform)>,
+}
+
+impl TemplateCommands {
+    /// The spawned entity is named after the template
+    pub fn spawn(&mut self, name: impl Into<String>, transform: Transform) {
+        self.queue.push((name.into(), transform));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    fn drain(&mut self) -> Vec<(String, Transform)> {
+        std::mem::take(&mut self.queue)
+    }
+}
+
+/// Where the ui and the console spawn templates, and the editor spawns primitives
+pub(crate) fn in_front_of_camera(world: &World) -> Transform {
+    let camera = crate::ecs::find_singleton_entity::<Camera>(world)
+        .and_then(|cam| world.read_storage::<Transform>().get(cam).copied())
+        .unwrap_or_else(Transform::identity);
+    let p = camera.position + camera.rotation * Vec3::new(0.0, 0.0, -SPAWN_DISTANCE);
+    Transform::pos(p.x, p.y, p.z)
+}
+
+/// `spawn name [x y z]` spawns a template, in front of the camera if there is no position.
+/// Without a name, the templates are listed.
+fn console_spawn(world: &World, arg: &str) -> Result<String, String> {
+    let mut args = arg.split_whitespace();
+    let name = match args.next() {
+        Some(name) => name,
+        None => {
+            let templates = world.read_resource::<Templates>();
+            return Ok(templates.names().collect::<Vec<_>>().join("\n"));
+        }
+    };
+    if world.read_resource::<Templates>().get(name).is_none() {
+        return Err(format!("Unknown template {}", name));
+    }
+
+    let coords = args
+        .map(|a| {
+            a.parse::<f32>()
+                .map_err(|_| format!("Invalid coordinate {}", a))
+        })
+        .collect::<Result<Vec<f32>, String>>()?;
+    let transform = match coords.as_slice() {
+        [] => in_front_of_camera(world),
+        [x, y, z] => Transform::pos(*x, *y, *z),
+        _ => return Err(String::from("Expected spawn name [x y z]")),
+    };
+    world
+        .write_resource::<TemplateCommands>()
+        .spawn(name, transform);
+    Ok(format!("Spawning {}", name))
+}
+
+pub struct TemplateCommandSys;
+
+impl TemplateCommandSys {
+    pub const ID: &'static str = "TemplateCommandSys";
+}
+
+#[derive(SystemData)]
+pub struct TemplateCommandData<'a> {
+    entities: Entities<'a>,
+    templates: Read<'a, Templates>,
+    commands: Write<'a, TemplateCommands>,
+    names: WriteStorage<'a, Name>,
+    transforms: WriteStorage<'a, Transform>,
+    bboxes: WriteStorage<'a, BoundingBox>,
+    meshes: WriteStorage<'a, CpuMesh>,
+    materials: WriteStorage<'a, PhysicallyBased>,
+    lights: WriteStorage<'a, Light>,
+    load_gltf: WriteStorage<'a, LoadGltfAsset>,
+    parents: WriteStorage<'a, Parent>,
+    children: WriteStorage<'a, Children>,
+}
+
+impl<'a> TemplateCommandData<'a> {
+    fn spawn(&mut self, name: String, template: &Template, transform: Transform) {
+        let mut builder = self
+            .entities
+            .build_entity()
+            .with(transform, &mut self.transforms);
+        if let Some(light) = &template.light {
+            builder = builder.with(light.clone(), &mut self.lights);
+        }
+        if let Some(mesh) = template.mesh.as_ref().and_then(TemplateMesh::cpu_mesh) {
+            if let Some(bbox) = BoundingBox::from_points(mesh.positions()) {
+                builder = builder.with(bbox, &mut self.bboxes);
+            }
+            let material = template.material.clone().unwrap_or_default();
+            builder = builder
+                .with(mesh, &mut self.meshes)
+                .with(material.material(), &mut self.materials);
+        }
+        let ent = builder.with(Name::from(name), &mut self.names).build();
+
+        if let Some(TemplateMesh::Asset(path)) = &template.mesh {
+            // The loader resets the transform of the asset root, so the transform stays on ent
+            let asset = self
+                .entities
+                .build_entity()
+                .with(
+                    LoadGltfAsset::new(path.clone(), SceneSelection::Default),
+                    &mut self.load_gltf,
+                )
+                .build();
+            graph::add_edge(&mut self.children, &mut self.parents, ent, asset);
+        }
+    }
+}
+
+impl<'a> System<'a> for TemplateCommandSys {
+    type SystemData = TemplateCommandData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        for (name, transform) in data.commands.drain() {
+            let template = match data.templates.get(&name) {
+                Some(template) => template.clone(),
+                None => {
+                    log::error!("Can't spawn {}, there is no template with that name", name);
+                    continue;
+                }
+            };
+            log::info!("Spawning {}", name);
+            data.spawn(name, &template, transform);
+        }
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        crate::cvar::register_command(
+            world,
+            "spawn",
+            "spawn name [x y z] spawns a template, without a name the templates are listed",
+            console_spawn,
+        );
+    }
+}
+
+/// Buttons that spawn the templates in front of the camera
+pub(crate) fn build_ui<'a>(world: &mut World, ui: &UiFrame<'a>, pos: [f32; 2]) -> [f32; 2] {
+    let size = [300.0, 200.0];
+
+    Window::new(im_str!("Templates"))
+        .position(pos, Condition::FirstUseEver)
+        .size(size, Condition::FirstUseEver)
+        .collapsed(true, Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let templates = world.read_resource::<Templates>();
+            if templates.is_empty() {
+                ui.inner().text(im_str!("No templates are loaded"));
+                return;
+            }
+
+            ui.inner().text(im_str!("Spawn in front of the camera:"));
+            let transform = in_front_of_camera(world);
+            let mut commands = world.write_resource::<TemplateCommands>();
+            for name in templates.names() {
+                if ui.inner().button(&im_str!("{}", name), [0.0, 0.0]) {
+                    commands.spawn(name, transform);
+                }
+            }
+        });
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use specs::RunNow;
+
+    #[test]
+    fn templates_spawn_their_components() {
+        let mut world = World::new();
+        crate::ecs::meta::register_all_components(&mut world);
+        TemplateCommandSys.setup(&mut world);
+
+        let lamp = Template::parse(
+            r#"(
+                mesh: Some(Sphere(radius: 0.5)),
+                material: Some((roughness_factor: 0.2)),
+                light: Some(Point(color: (r: 1.0, g: 1.0, b: 1.0), intensity: Lumens(800.0), range: 5.0)),
+            )"#,
+        )
+        .unwrap();
+        let statue = Template {
+            mesh: Some(TemplateMesh::Asset(PathBuf::from("statue.glb"))),
+            ..Default::default()
+        };
+        {
+            let mut templates = world.write_resource::<Templates>();
+            templates.insert("lamp", lamp);
+            templates.insert("statue", statue);
+        }
+
+        assert!(console_spawn(&world, "lamp 1 2 3").is_ok());
+        assert!(console_spawn(&world, "missing").is_err());
+        world
+            .write_resource::<TemplateCommands>()
+            .spawn("statue", Transform::identity());
+        TemplateCommandSys.run_now(&world);
+        world.maintain();
+        assert!(world.read_resource::<TemplateCommands>().is_empty());
+
+        let names = world.read_storage::<Name>();
+        let find = |name: &str| {
+            (&world.entities(), &names)
+                .join()
+                .find(|(_, n)| n.0 == name)
+                .map(|(ent, _)| ent)
+                .unwrap()
+        };
+        let lamp = find("lamp");
+        assert_eq!(
+            world
+                .read_storage::<Transform>()
+                .get(lamp)
+                .unwrap()
+                .position,
+            Vec3::new(1.0, 2.0, 3.0)
+        );
+        assert!(world.read_storage::<Light>().get(lamp).is_some());
+        assert!(world.read_storage::<CpuMesh>().get(lamp).is_some());
+        let bbox = *world.read_storage::<BoundingBox>().get(lamp).unwrap();
+        assert!((bbox.max.y - 0.5).abs() < 1e-5);
+        let material = world.read_storage::<PhysicallyBased>();
+        assert_eq!(material.get(lamp).unwrap().roughness_factor, 0.2);
+        assert_eq!(material.get(lamp).unwrap().metallic_factor, 0.0);
+
+        let statue = find("statue");
+        let asset = world
+            .read_storage::<Children>()
+            .get(statue)
+            .unwrap()
+            .children[0];
+        assert!(world.read_storage::<LoadGltfAsset>().get(asset).is_some());
+    }
+}
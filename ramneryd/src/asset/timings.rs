@@ -0,0 +1,168 @@
+//! Where the time goes when an asset is loaded. The stages of a load are timed into LoadTimings,
+//! which is logged when the load is done and shown in the glTF scenes window. The loaders also add
+//! profiling scopes for the stages, so they show up in the profiler when one is enabled.
+//!
+//! Stages that are started while another is running are nested in it, e.g. the tangents are
+//! generated while the meshes are built. Timing a stage again adds to it, so a stage that runs
+//! once per mesh is the sum for the whole load.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+struct Stage {
+    name: &'static str,
+    depth: usize,
+    duration: Duration,
+}
+
+/// Started by LoadTimings::start
+#[must_use]
+pub struct StageTimer {
+    stage: usize,
+    start: Instant,
+}
+
+/// The duration of each stage of a load, in the order they were first started
+#[derive(Debug, Clone, Default)]
+pub struct LoadTimings {
+    stages: Vec<Stage>,
+    depth: usize,
+}
+
+impl LoadTimings {
+    fn stage(&mut self, name: &'static str) -> usize {
+        match self.stages.iter().position(|s| s.name == name) {
+            Some(idx) => idx,
+            None => {
+                self.stages.push(Stage {
+                    name,
+                    depth: self.depth,
+                    duration: Duration::default(),
+                });
+                self.stages.len() - 1
+            }
+        }
+    }
+
+    /// Start timing the stage. Stages that are started before it is stopped are nested in it.
+    pub fn start(&mut self, name: &'static str) -> StageTimer {
+        let stage = self.stage(name);
+        self.depth += 1;
+        StageTimer {
+            stage,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn stop(&mut self, timer: StageTimer) {
+        self.stages[timer.stage].duration += timer.start.elapsed();
+        self.depth -= 1;
+    }
+
+    /// Time f as the stage
+    pub fn time<R>(&mut self, name: &'static str, f: impl FnOnce() -> R) -> R {
+        let timer = self.start(name);
+        let result = f();
+        self.stop(timer);
+        result
+    }
+
+    /// Add a stage that was timed elsewhere, e.g. on an asset worker
+    pub fn add(&mut self, name: &'static str, duration: Duration) {
+        let stage = self.stage(name);
+        self.stages[stage].duration += duration;
+    }
+
+    /// Add the stages of other, at the current depth
+    pub fn extend(&mut self, other: &LoadTimings) {
+        for s in other.stages.iter() {
+            let depth = self.depth + s.depth;
+            let stage = self.stage(s.name);
+            self.stages[stage].depth = depth;
+            self.stages[stage].duration += s.duration;
+        }
+    }
+
+    /// The name, nesting depth and duration of each stage
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, usize, Duration)> + '_ {
+        self.stages.iter().map(|s| (s.name, s.depth, s.duration))
+    }
+
+    /// The sum of the top level stages
+    pub fn total(&self) -> Duration {
+        self.stages
+            .iter()
+            .filter(|s| s.depth == 0)
+            .map(|s| s.duration)
+            .sum()
+    }
+}
+
+/// E.g. "parse 1.00 ms, scene 3.00 ms (meshes 2.00 ms (tangents 1.00 ms))"
+impl fmt::Display for LoadTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut depth = 0;
+        for (i, stage) in self.stages.iter().enumerate() {
+            if stage.depth > depth {
+                write!(f, " (")?;
+            } else {
+                for _ in stage.depth..depth {
+                    write!(f, ")")?;
+                }
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+            }
+            write!(
+                f,
+                "{} {:.2} ms",
+                stage.name,
+                stage.duration.as_secs_f32() * 1000.0
+            )?;
+            depth = stage.depth;
+        }
+        for _ in 0..depth {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_are_nested_and_accumulated() {
+        let mut timings = LoadTimings::default();
+        timings.add("parse", Duration::from_millis(1));
+        let scene = timings.start("scene");
+        for _ in 0..2 {
+            let meshes = timings.start("meshes");
+            timings.add("tangents", Duration::from_millis(1));
+            timings.stop(meshes);
+        }
+        timings.stop(scene);
+        timings.add("upload", Duration::from_millis(4));
+
+        let stages: Vec<(&str, usize)> = timings.iter().map(|(n, d, _)| (n, d)).collect();
+        assert_eq!(
+            stages,
+            vec![
+                ("parse", 0),
+                ("scene", 0),
+                ("meshes", 1),
+                ("tangents", 2),
+                ("upload", 0)
+            ]
+        );
+        let (_, _, tangents) = timings.iter().nth(3).unwrap();
+        assert_eq!(tangents, Duration::from_millis(2));
+        assert!(timings.total() >= Duration::from_millis(5));
+
+        let summary = timings.to_string();
+        assert!(summary.starts_with("parse 1.00 ms, scene "));
+        assert!(summary.contains("(tangents 2.00 ms)), upload 4.00 ms"));
+    }
+}
@@ -0,0 +1,455 @@
+//! Named camera poses that the camera can be moved back to, either from the ui or with the number
+//! keys, where bookmark i is bound to key i + 1. The camera is moved smoothly over
+//! CameraBookmarks::transition_duration by the CameraAnimation system.
+
+use super::{Camera, CameraRotationState};
+use crate::common::Name;
+use crate::ecs::prelude::*;
+use crate::io::input::{ActionId, Input, InputContext, InputContextError, MappedInput};
+use crate::math::{Transform, Vec3};
+use crate::settings::{self, KeyBindings};
+use crate::time::Time;
+
+/// The pose and projection of the camera
+#[derive(Debug, Clone)]
+pub struct CameraBookmark {
+    pub name: String,
+    pub position: Vec3,
+    pub rotation: CameraRotationState,
+    pub camera: Camera,
+}
+
+pub struct CameraBookmarks {
+    pub bookmarks: Vec<CameraBookmark>,
+    /// In seconds, 0 moves the camera immediately
+    pub transition_duration: f32,
+    /// The bookmark to move the camera to in the next frame
+    pub go_to: Option<usize>,
+}
+
+impl Default for CameraBookmarks {
+    fn default() -> Self {
+        Self {
+            bookmarks: Vec::new(),
+            transition_duration: 1.0,
+            go_to: None,
+        }
+    }
+}
+
+impl CameraBookmarks {
+    /// Bookmark the current pose of the camera
+    pub fn add(&mut self, world: &World, name: String) {
+        let camera_entity = match crate::ecs::find_singleton_entity::<Camera>(world) {
+            Some(ent) => ent,
+            None => {
+                log::error!("No camera to bookmark");
+                return;
+            }
+        };
+        let position = world
+            .read_storage::<Transform>()
+            .get(camera_entity)
+            .map(|t| t.position)
+            .unwrap_or_default();
+        let rotation = *world
+            .read_storage::<CameraRotationState>()
+            .get(camera_entity)
+            .expect("The camera has a rotation state");
+        let camera = *world
+            .read_storage::<Camera>()
+            .get(camera_entity)
+            .expect("The singleton camera entity has a camera");
+        self.bookmarks.push(CameraBookmark {
+            name,
+            position,
+            rotation,
+            camera,
+        });
+    }
+}
+
+/// Moves the camera from one pose to another
+#[derive(Debug, Clone, Component)]
+#[component(storage = "HashMapStorage")]
+pub struct CameraTransition {
+    from: CameraBookmark,
+    to: CameraBookmark,
+    elapsed: f32,
+    duration: f32,
+}
+
+impl CameraTransition {
+    /// The pose at t in [0, 1], eased in and out
+    fn pose_at(&self, t: f32) -> (Vec3, CameraRotationState, Camera) {
+        let t = t * t * (3.0 - 2.0 * t);
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        let (from, to) = (&self.from, &self.to);
+
+        // Turn the shortest way around
+        let tau = 2.0 * std::f32::consts::PI;
+        let mut yaw_delta = (to.rotation.yaw - from.rotation.yaw) % tau;
+        if yaw_delta > std::f32::consts::PI {
+            yaw_delta -= tau;
+        } else if yaw_delta < -std::f32::consts::PI {
+            yaw_delta += tau;
+        }
+        let rotation = CameraRotationState {
+            yaw: from.rotation.yaw + yaw_delta * t,
+            pitch: lerp(from.rotation.pitch, to.rotation.pitch),
+        };
+
+        let camera = Camera {
+            focal_length: lerp(from.camera.focal_length, to.camera.focal_length),
+            sensor_width: lerp(from.camera.sensor_width, to.camera.sensor_width),
+            lens_shift_x: lerp(from.camera.lens_shift_x, to.camera.lens_shift_x),
+            lens_shift_y: lerp(from.camera.lens_shift_y, to.camera.lens_shift_y),
+        };
+
+        let position = from.position + (to.position - from.position) * t;
+        (position, rotation, camera)
+    }
+}
+
+const BOOKMARK_KEYS: [&str; 9] = [
+    settings::CAMERA_BOOKMARK_1,
+    settings::CAMERA_BOOKMARK_2,
+    settings::CAMERA_BOOKMARK_3,
+    settings::CAMERA_BOOKMARK_4,
+    settings::CAMERA_BOOKMARK_5,
+    settings::CAMERA_BOOKMARK_6,
+    settings::CAMERA_BOOKMARK_7,
+    settings::CAMERA_BOOKMARK_8,
+    settings::CAMERA_BOOKMARK_9,
+];
+
+fn get_input_context(bindings: &KeyBindings) -> Result<InputContext, InputContextError> {
+    let mut builder = InputContext::builder(CameraBookmarkSys::ID)
+        .description("Input for moving the camera to a bookmark");
+    for (i, action) in BOOKMARK_KEYS.iter().enumerate() {
+        if let Some(button) = bindings.get(action) {
+            builder = builder.with_action(button, ActionId(i as u32))?;
+        }
+    }
+    Ok(builder.build())
+}
+
+struct CameraBookmarkSys {
+    input_entity: Option<Entity>,
+    bindings_version: u32,
+}
+
+impl CameraBookmarkSys {
+    pub const ID: &'static str = "CameraBookmarkSys";
+}
+
+impl<'a> System<'a> for CameraBookmarkSys {
+    type SystemData = (
+        Write<'a, CameraBookmarks>,
+        WriteStorage<'a, MappedInput>,
+        Read<'a, KeyBindings>,
+        WriteStorage<'a, InputContext>,
+        Entities<'a>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, CameraRotationState>,
+        WriteStorage<'a, CameraTransition>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            mut bookmarks,
+            mut inputs,
+            bindings,
+            mut contexts,
+            entities,
+            cameras,
+            transforms,
+            rotation_states,
+            mut transitions,
+        ) = data;
+
+        if bindings.version() != self.bindings_version {
+            match get_input_context(&bindings) {
+                Ok(ctx) => {
+                    contexts
+                        .insert(self.input_entity.unwrap(), ctx)
+                        .expect("Failed to replace the camera bookmark input context");
+                }
+                Err(e) => log::error!("Failed to rebind the camera bookmarks: {:?}", e),
+            }
+            self.bindings_version = bindings.version();
+        }
+
+        let inp = inputs
+            .get_mut(self.input_entity.unwrap())
+            .expect("Failed to get mapped input for CameraBookmarkSys");
+
+        let pressed = inp.iter().map(|i| match i {
+            Input::Action(ActionId(idx)) => *idx as usize,
+            i => unreachable!("{:?}", i),
+        });
+        let requested: Vec<usize> = bookmarks.go_to.take().into_iter().chain(pressed).collect();
+        for idx in requested {
+            let to = match bookmarks.bookmarks.get(idx) {
+                Some(to) => to.clone(),
+                None => {
+                    log::debug!("No camera bookmark {}", idx + 1);
+                    continue;
+                }
+            };
+
+            for (ent, camera, transform, rotation) in
+                (&entities, &cameras, &transforms, &rotation_states).join()
+            {
+                let from = CameraBookmark {
+                    name: String::new(),
+                    position: transform.position,
+                    rotation: *rotation,
+                    camera: *camera,
+                };
+                transitions
+                    .insert(
+                        ent,
+                        CameraTransition {
+                            from,
+                            to: to.clone(),
+                            elapsed: 0.0,
+                            duration: bookmarks.transition_duration,
+                        },
+                    )
+                    .expect("Camera is alive");
+            }
+        }
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        crate::cvar::register(
+            world,
+            "cam.bookmarks.duration",
+            "The time it takes to move the camera to a bookmark, in seconds",
+            Some((0.0, 60.0)),
+            |b: &mut CameraBookmarks| &mut b.transition_duration,
+        );
+        let ctx = {
+            let bindings = world.read_resource::<KeyBindings>();
+            self.bindings_version = bindings.version();
+            get_input_context(&bindings).expect("Failed to build camera bookmark input context")
+        };
+        self.input_entity = Some(
+            world
+                .create_entity()
+                .with(ctx)
+                .with(Name::from(CameraBookmarkSys::ID))
+                .build(),
+        );
+    }
+}
+
+/// Moves the camera along its CameraTransition, after the controllers so that it is not moved by
+/// them during the transition
+pub struct CameraAnimation;
+
+impl CameraAnimation {
+    pub const ID: &'static str = "CameraAnimation";
+}
+
+impl<'a> System<'a> for CameraAnimation {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, CameraTransition>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, CameraRotationState>,
+        WriteStorage<'a, Camera>,
+        ReadExpect<'a, Time>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, mut transitions, mut transforms, mut rotation_states, mut cameras, time) =
+            data;
+
+        let mut done = Vec::new();
+        for (ent, transition, transform, rotation, camera) in (
+            &entities,
+            &mut transitions,
+            &mut transforms,
+            &mut rotation_states,
+            &mut cameras,
+        )
+            .join()
+        {
+            transition.elapsed += time.delta_sim().as_secs();
+            let t = if transition.duration > 0.0 {
+                (transition.elapsed / transition.duration).min(1.0)
+            } else {
+                1.0
+            };
+            let (position, new_rotation, new_camera) = transition.pose_at(t);
+            transform.position = position;
+            *rotation = new_rotation;
+            *camera = new_camera;
+            if t >= 1.0 {
+                done.push(ent);
+            }
+        }
+
+        for ent in done {
+            transitions.remove(ent);
+        }
+    }
+}
+
+pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
+    builder
+        .with(
+            CameraBookmarkSys {
+                input_entity: None,
+                bindings_version: 0,
+            },
+            CameraBookmarkSys::ID,
+            &[],
+        )
+        .with(
+            CameraAnimation,
+            CameraAnimation::ID,
+            &[
+                CameraBookmarkSys::ID,
+                super::FREE_FLY_CAMERA_ID,
+                super::ORBIT_CAMERA_ID,
+            ],
+        )
+}
+
+#[derive(Default)]
+struct BookmarkUiState {
+    name: imgui::ImString,
+}
+
+pub fn build_ui<'a>(
+    world: &mut World,
+    ui: &crate::render::ui::UiFrame<'a>,
+    pos: [f32; 2],
+) -> [f32; 2] {
+    let size = [300.0, 200.0];
+
+    imgui::Window::new(imgui::im_str!("Camera bookmarks"))
+        .position(pos, imgui::Condition::FirstUseEver)
+        .size(size, imgui::Condition::FirstUseEver)
+        .collapsed(true, imgui::Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let mut delete = None;
+            {
+                let mut bookmarks = world.write_resource::<CameraBookmarks>();
+                for i in 0..bookmarks.bookmarks.len() {
+                    if ui.inner().small_button(&imgui::im_str!("Go##{}", i)) {
+                        bookmarks.go_to = Some(i);
+                    }
+                    ui.inner().same_line(0.0);
+                    if ui.inner().small_button(&imgui::im_str!("Delete##{}", i)) {
+                        delete = Some(i);
+                    }
+                    ui.inner().same_line(0.0);
+                    let name = &bookmarks.bookmarks[i].name;
+                    match BOOKMARK_KEYS
+                        .get(i)
+                        .and_then(|action| world.read_resource::<KeyBindings>().get(action))
+                    {
+                        Some(button) => ui.inner().text(imgui::im_str!("{} ({:?})", name, button)),
+                        None => ui.inner().text(name),
+                    }
+                }
+            }
+            if let Some(i) = delete {
+                world
+                    .write_resource::<CameraBookmarks>()
+                    .bookmarks
+                    .remove(i);
+            }
+
+            let mut storage = ui.storage();
+            let id = String::from("CameraBookmarks");
+            if !storage.contains_key(&id) {
+                storage.insert(id.clone(), BookmarkUiState::default());
+            }
+            let state: &mut BookmarkUiState = storage
+                .get_mut(&id)
+                .expect("Camera bookmark state was just inserted");
+            ui.inner()
+                .input_text(imgui::im_str!("Name"), &mut state.name)
+                .resize_buffer(true)
+                .build();
+            if ui
+                .inner()
+                .button(imgui::im_str!("Bookmark the camera"), [0.0, 0.0])
+            {
+                let name = state.name.to_str().to_string();
+                state.name.clear();
+                let mut bookmarks = world.write_resource::<CameraBookmarks>();
+                let name = if name.is_empty() {
+                    format!("Bookmark {}", bookmarks.bookmarks.len() + 1)
+                } else {
+                    name
+                };
+                bookmarks.add(world, name);
+            }
+            let mut bookmarks = world.write_resource::<CameraBookmarks>();
+            imgui::InputFloat::new(
+                ui.inner(),
+                imgui::im_str!("Duration (s)"),
+                &mut bookmarks.transition_duration,
+            )
+            .build();
+            bookmarks.transition_duration = bookmarks.transition_duration.max(0.0);
+        });
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bookmark(position: Vec3, yaw: f32, focal_length: f32) -> CameraBookmark {
+        CameraBookmark {
+            name: String::new(),
+            position,
+            rotation: CameraRotationState { yaw, pitch: 0.0 },
+            camera: Camera {
+                focal_length,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn transition_ends_at_bookmark() {
+        let transition = CameraTransition {
+            from: bookmark(Vec3::zero(), 0.0, 24.0),
+            to: bookmark(Vec3::new(2.0, 0.0, 0.0), 1.0, 50.0),
+            elapsed: 0.0,
+            duration: 1.0,
+        };
+        let (position, rotation, camera) = transition.pose_at(0.5);
+        assert!((position.x - 1.0).abs() < 1e-5);
+        assert!((rotation.yaw - 0.5).abs() < 1e-5);
+        assert!((camera.focal_length - 37.0).abs() < 1e-4);
+
+        let (position, rotation, camera) = transition.pose_at(1.0);
+        assert_eq!(position, Vec3::new(2.0, 0.0, 0.0));
+        assert!((rotation.yaw - 1.0).abs() < 1e-5);
+        assert_eq!(camera.focal_length, 50.0);
+    }
+
+    #[test]
+    fn transition_turns_the_shortest_way() {
+        let transition = CameraTransition {
+            from: bookmark(Vec3::zero(), 0.1, 24.0),
+            to: bookmark(Vec3::zero(), 2.0 * std::f32::consts::PI - 0.1, 24.0),
+            elapsed: 0.0,
+            duration: 1.0,
+        };
+        let (_, rotation, _) = transition.pose_at(0.5);
+        assert!(rotation.yaw.abs() < 1e-5);
+    }
+}
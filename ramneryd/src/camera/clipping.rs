@@ -0,0 +1,232 @@
+//! Keeps the camera from clipping into the meshes of the scene. Meshes that are closer to the eye
+//! than the near plane are cut open by it, which shows their insides. The meshes that the near
+//! plane cuts into can be highlighted, and the free fly camera can sweep a small sphere along its
+//! movement to stop before it gets that close.
+//!
+//! Like the character controller, this uses the bounding boxes of the meshes, see crate::collision.
+//! The sphere only stops the camera from entering a box, so it can still move around inside the
+//! box of e.g. a room that it is in.
+
+use crate::collision;
+use crate::ecs::prelude::*;
+use crate::math::{BoundingBox, ModelMatrix, Ray, Rgba, Transform, Vec3};
+use crate::render::debug_draw::DebugDraw;
+use crate::render::Hidden;
+
+use super::Camera;
+
+const EPS: f32 = 0.001;
+/// The number of times that the sphere slides along a box before it stops
+const MAX_SLIDES: usize = 3;
+
+pub struct CameraClipping {
+    /// Draw the bounding boxes of the meshes that the near plane cuts into
+    pub highlight: bool,
+    /// Stop the free fly camera before it gets closer to the meshes than the radius
+    pub collide: bool,
+    /// The radius of the sphere that is swept along the movement of the camera. Should be at least
+    /// Camera::near_plane_radius to keep the near plane out of the meshes.
+    pub radius: f32,
+    /// The meshes that the near plane cut into in the last frame
+    pub clipping: Vec<Entity>,
+}
+
+impl Default for CameraClipping {
+    fn default() -> Self {
+        Self {
+            highlight: false,
+            collide: false,
+            radius: 0.1,
+            clipping: Vec::new(),
+        }
+    }
+}
+
+fn expanded(bbox: &BoundingBox, radius: f32) -> BoundingBox {
+    BoundingBox {
+        min: bbox.min - Vec3::broadcast(radius),
+        max: bbox.max + Vec3::broadcast(radius),
+    }
+}
+
+/// The distance from p to the closest point on the surface of the box, also when p is inside it
+fn distance_to_surface(bbox: &BoundingBox, p: Vec3) -> f32 {
+    let outside = Vec3::partial_max(bbox.min - p, p - bbox.max);
+    if outside.x <= 0.0 && outside.y <= 0.0 && outside.z <= 0.0 {
+        -outside.reduce_partial_max()
+    } else {
+        Vec3::partial_max(outside, Vec3::zero()).magnitude()
+    }
+}
+
+/// Moves a sphere with the radius from `from` towards `to` and slides it along the world space
+/// boxes that it hits. Returns where it ends up. Boxes that the sphere already overlaps don't block
+/// it. The corners of the boxes are treated as square, so the sphere stops a bit early around them.
+pub(super) fn sweep(boxes: &[BoundingBox], from: Vec3, to: Vec3, radius: f32) -> Vec3 {
+    let mut position = from;
+    let mut movement = to - from;
+    for _ in 0..MAX_SLIDES {
+        let distance = movement.magnitude();
+        if distance < EPS {
+            break;
+        }
+
+        let direction = movement / distance;
+        let ray = Ray {
+            origin: position,
+            direction,
+        };
+        let hit = boxes
+            .iter()
+            .filter_map(|bbox| ray.intersect_with_normal(&expanded(bbox, radius)))
+            .filter(|(_, normal)| *normal != Vec3::zero())
+            .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        match hit {
+            Some((t, normal)) if t < distance => {
+                let allowed = (t - EPS).max(0.0);
+                position += direction * allowed;
+                let rest = movement - direction * allowed;
+                movement = rest - normal * rest.dot(normal);
+            }
+            _ => {
+                position += movement;
+                break;
+            }
+        }
+    }
+
+    position
+}
+
+/// The bounding boxes that the camera collides with, in world space
+pub(super) fn world_boxes<'a>(
+    entities: &Entities<'a>,
+    bboxes: &ReadStorage<'a, BoundingBox>,
+    model_matrices: &ReadStorage<'a, ModelMatrix>,
+    hidden: &ReadStorage<'a, Hidden>,
+) -> Vec<BoundingBox> {
+    collision::gather(entities, bboxes, model_matrices, |ent| {
+        !hidden.contains(ent)
+    })
+    .iter()
+    .map(|collider| collider.world_bbox())
+    .collect()
+}
+
+/// Finds the meshes that the near plane cuts into and highlights them
+pub struct DetectClipping;
+
+impl DetectClipping {
+    pub const ID: &'static str = "DetectClipping";
+}
+
+impl<'a> System<'a> for DetectClipping {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, BoundingBox>,
+        ReadStorage<'a, ModelMatrix>,
+        ReadStorage<'a, Hidden>,
+        Write<'a, CameraClipping>,
+        Write<'a, DebugDraw>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            entities,
+            cameras,
+            transforms,
+            bboxes,
+            model_matrices,
+            hidden,
+            mut clipping,
+            mut debug_draw,
+        ) = data;
+
+        clipping.clipping.clear();
+        for (camera, transform) in (&cameras, &transforms).join() {
+            let eye = transform.position;
+            let radius = camera.near_plane_radius();
+            for (ent, bbox, model, _) in (&entities, &bboxes, &model_matrices, !&hidden).join() {
+                let world_bbox = bbox.transformed(&model.0);
+                if distance_to_surface(&world_bbox, eye) < radius {
+                    clipping.clipping.push(ent);
+                    if clipping.highlight {
+                        debug_draw.draw_box(bbox, &model.0, Rgba::new(1.0, 0.5, 0.0, 1.0));
+                    }
+                }
+            }
+        }
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        use crate::cvar::register;
+        register(
+            world,
+            "cam.clipping.highlight",
+            "Draw the bounding boxes of the meshes that the near plane cuts into",
+            None,
+            |c: &mut CameraClipping| &mut c.highlight,
+        );
+        register(
+            world,
+            "cam.clipping.collide",
+            "Stop the free fly camera before it moves into the bounding box of a mesh",
+            None,
+            |c: &mut CameraClipping| &mut c.collide,
+        );
+        register(
+            world,
+            "cam.clipping.radius",
+            "How close the free fly camera can get to the bounding boxes of the meshes",
+            Some((0.0, 10.0)),
+            |c: &mut CameraClipping| &mut c.radius,
+        );
+    }
+}
+
+pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
+    // After the camera has been moved
+    builder.with(
+        DetectClipping,
+        DetectClipping::ID,
+        &[super::bookmarks::CameraAnimation::ID],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vek::approx::assert_abs_diff_eq;
+
+    fn unit_box(center: Vec3) -> BoundingBox {
+        BoundingBox {
+            min: center - Vec3::broadcast(0.5),
+            max: center + Vec3::broadcast(0.5),
+        }
+    }
+
+    #[test]
+    fn sphere_stops_before_boxes_and_slides_along_them() {
+        let wall = unit_box(Vec3::new(2.0, 0.0, 0.0));
+        let radius = 0.1;
+
+        // Straight into the wall
+        let end = sweep(&[wall], Vec3::zero(), Vec3::new(3.0, 0.0, 0.0), radius);
+        assert_abs_diff_eq!(end, Vec3::new(1.4, 0.0, 0.0), epsilon = 0.01);
+
+        // Diagonally, keeps the part along the wall
+        let end = sweep(&[wall], Vec3::zero(), Vec3::new(3.0, 0.0, 0.2), radius);
+        assert_abs_diff_eq!(end.x, 1.4, epsilon = 0.01);
+        assert_abs_diff_eq!(end.z, 0.2, epsilon = 0.01);
+
+        // Moving out of a box that the sphere is in is not blocked
+        let end = sweep(&[wall], Vec3::new(2.0, 0.0, 0.0), Vec3::zero(), radius);
+        assert_abs_diff_eq!(end, Vec3::zero(), epsilon = 0.01);
+
+        assert_abs_diff_eq!(distance_to_surface(&wall, Vec3::zero()), 1.5);
+        assert_abs_diff_eq!(distance_to_surface(&wall, Vec3::new(2.3, 0.0, 0.0)), 0.2);
+    }
+}
@@ -0,0 +1,651 @@
+use num_derive::FromPrimitive;
+
+use crate::common::Name;
+use crate::ecs;
+use crate::io::input::{
+    ActionId, DeviceAxis, Input, InputContext, InputContextError, MappedInput, RangeId,
+    Sensitivity, StateId,
+};
+use crate::math::{BoundingBox, Mat4, ModelMatrix, Transform, Vec3};
+use crate::render::Hidden;
+use crate::settings::{self, KeyBindings};
+use crate::time::Time;
+use ecs::prelude::*;
+
+use num_traits::cast::FromPrimitive;
+
+pub mod bookmarks;
+pub mod clipping;
+
+use clipping::CameraClipping;
+
+#[derive(Debug)]
+pub struct CameraOrientation {
+    pub up: Vec3,
+    pub view_direction: Vec3,
+}
+
+// Avoid gimbal-lock by clamping pitch
+const MAX_PITCH: f32 = 0.99 * std::f32::consts::FRAC_PI_2;
+const MIN_PITCH: f32 = 0.99 * -std::f32::consts::FRAC_PI_2;
+// TODO: Inheret clamping for the fields?
+// TOOD: Modulus for yaw?
+#[derive(Debug, Clone, Copy, Component, serde::Serialize, serde::Deserialize)]
+#[component(storage = "HashMapStorage", inspect)]
+pub struct CameraRotationState {
+    yaw: f32,
+    pitch: f32,
+}
+
+impl CameraRotationState {
+    fn clamp(&mut self) {
+        if self.pitch > MAX_PITCH {
+            self.pitch = MAX_PITCH;
+        }
+
+        if self.pitch < MIN_PITCH {
+            self.pitch = MIN_PITCH;
+        }
+    }
+}
+
+/// Settings of the free fly camera that can be changed at runtime, see crate::cvar
+pub struct FreeFlySettings {
+    /// In meters per second
+    pub movement_speed: f32,
+}
+
+impl Default for FreeFlySettings {
+    fn default() -> Self {
+        Self {
+            movement_speed: 2.0,
+        }
+    }
+}
+
+/// The distance from the eye to the near plane of the projection
+pub const NEAR_PLANE: f32 = 0.05;
+
+const ORBIT_DEFAULT_DISTANCE: f32 = 5.0;
+const ORBIT_MIN_DISTANCE: f32 = 0.05;
+const ORBIT_MAX_DISTANCE: f32 = 10000.0;
+// The distance is scaled by this for each line scrolled
+const ORBIT_ZOOM_FACTOR: f32 = 0.9;
+
+/// The controller that moves the camera. Both use CameraRotationState for the orientation, so the
+/// camera keeps its pose when switching.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ActiveCameraController {
+    FreeFly,
+    Orbit,
+}
+
+impl Default for ActiveCameraController {
+    fn default() -> Self {
+        Self::FreeFly
+    }
+}
+
+/// The orbit controller looks at a target point this far in front of the camera. The target is not
+/// stored, so that it follows when the pose of the camera is set from elsewhere, e.g. a scene.
+#[derive(Debug, Clone, Copy, Component)]
+#[component(storage = "HashMapStorage", inspect)]
+pub struct OrbitCameraState {
+    pub distance: f32,
+}
+
+impl OrbitCameraState {
+    /// Orbit around the point on the view ray that is closest to the origin, as that is where
+    /// loaded models usually are. Falls back to a fixed distance if the origin is behind the camera.
+    fn looking_from(pos: Vec3, view_direction: Vec3) -> Self {
+        let distance = -pos.dot(view_direction);
+        let distance = if distance > ORBIT_MIN_DISTANCE {
+            distance.min(ORBIT_MAX_DISTANCE)
+        } else {
+            ORBIT_DEFAULT_DISTANCE
+        };
+        Self { distance }
+    }
+}
+
+#[derive(Debug, Copy, Clone, FromPrimitive, PartialEq, Eq)]
+enum CameraMovement {
+    Forward,
+    Backward,
+    Left,
+    Right,
+    Up,
+    Down,
+
+    Move,
+    Pan,
+}
+
+impl From<StateId> for CameraMovement {
+    fn from(id: StateId) -> Self {
+        Self::from_u32(id.0).expect("Error in input context setup, can't convert to CameraMovement")
+    }
+}
+
+impl Into<StateId> for CameraMovement {
+    fn into(self) -> StateId {
+        StateId(self as u32)
+    }
+}
+
+#[derive(Debug, Copy, Clone, FromPrimitive, PartialEq, Eq)]
+enum CameraRotation {
+    YawDelta,
+    PitchDelta,
+    ZoomDelta,
+}
+
+impl From<RangeId> for CameraRotation {
+    fn from(id: RangeId) -> Self {
+        Self::from_u32(id.0).expect("Error in input context setup, can't convert to CameraRotation")
+    }
+}
+
+impl Into<RangeId> for CameraRotation {
+    fn into(self) -> RangeId {
+        RangeId(self as u32)
+    }
+}
+
+const SWITCH_CONTROLLER: ActionId = ActionId(0);
+
+/// Generic component for any camera type. The projection is described by the lens and sensor of a
+/// physical camera, so that renders can match the framing of a real one.
+#[derive(Debug, Clone, Copy, PartialEq, Component, serde::Serialize, serde::Deserialize)]
+#[component(storage = "HashMapStorage", inspect)]
+pub struct Camera {
+    /// Focal length of the lens, in mm
+    pub focal_length: f32,
+    /// Width of the sensor, in mm. The height follows from the aspect ratio of the image.
+    pub sensor_width: f32,
+    /// Horizontal offset of the lens from the center of the sensor, as a fraction of the sensor
+    /// width. Positive moves the framed area to the right.
+    pub lens_shift_x: f32,
+    /// Vertical offset of the lens, as a fraction of the sensor width. Positive moves the framed area
+    /// up, which keeps verticals straight when framing a tall building.
+    pub lens_shift_y: f32,
+}
+
+impl Default for Camera {
+    /// A wide angle lens on a full frame sensor, close to a 45 degree vertical field of view in
+    /// 16:9
+    fn default() -> Self {
+        Self {
+            focal_length: 24.0,
+            sensor_width: 36.0,
+            lens_shift_x: 0.0,
+            lens_shift_y: 0.0,
+        }
+    }
+}
+
+impl Camera {
+    pub fn vertical_fov(&self, aspect_ratio: f32) -> f32 {
+        let sensor_height = self.sensor_width / aspect_ratio;
+        2.0 * (sensor_height / (2.0 * self.focal_length)).atan()
+    }
+
+    /// The projection for an image with the aspect ratio. Lens shift moves the image without
+    /// changing the perspective, i.e. the projection becomes off-center.
+    pub fn projection(&self, aspect_ratio: f32, near: f32, far: f32) -> Mat4 {
+        let mut m =
+            crate::math::perspective_vk(self.vertical_fov(aspect_ratio), aspect_ratio, near, far);
+        // Offset the clip space position by the shift times w (which is -z). Normalized device
+        // coordinates span two sensor widths horizontally and y points down in vulkan.
+        m[(0, 2)] += 2.0 * self.lens_shift_x;
+        m[(1, 2)] -= 2.0 * self.lens_shift_y * aspect_ratio;
+        m
+    }
+
+    /// The distance from the eye to the farthest corner of the near plane, for images that are at
+    /// least as wide as they are high
+    pub fn near_plane_radius(&self) -> f32 {
+        let half_width = self.sensor_width * (0.5 + self.lens_shift_x.abs()) / self.focal_length;
+        let half_height = self.sensor_width * (0.5 + self.lens_shift_y.abs()) / self.focal_length;
+        NEAR_PLANE * (1.0 + half_width * half_width + half_height * half_height).sqrt()
+    }
+}
+
+/*
+impl Camera {
+    pub fn set_camera_state(w: &mut World, e: Entity, t: &Transform) {
+        FreeFlyCameraController::set_camera_state(w, e, t);
+    }
+}
+*/
+
+#[derive(Default)]
+pub struct FreeFlyCameraController {
+    bindings_version: u32,
+}
+
+impl FreeFlyCameraController {
+    pub fn get_orientation_from(rotation_state: &CameraRotationState) -> CameraOrientation {
+        // Disallow roll => y will only be a function of pitch
+        let view_direction =
+            crate::math::direction_from_yaw_pitch(rotation_state.yaw, rotation_state.pitch);
+
+        // This means Q/E will always be up/down in WORLD coordinates
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        // TODO: Move the code below to its own struct
+        /* Re-enable if we want Q/E to align to upwards/downwards from
+         * camera view direction. This needs to be clamped when looking
+         * straight down/up though.
+        let minus_z = -direction;
+        let right: Vec3 = glm::cross::<f32, glm::U3>(&up, &minus_z);
+        let up: Vec3= glm::cross::<f32, glm::U3>(&minus_z, &right);
+        */
+
+        CameraOrientation { view_direction, up }
+    }
+
+    pub fn get_view_matrix_from(pos: Vec3, rot_state: &CameraRotationState) -> Mat4 {
+        let ori = FreeFlyCameraController::get_orientation_from(rot_state);
+        crate::math::look_to(pos, ori.view_direction, ori.up)
+    }
+
+    /*
+    pub fn set_camera_state(w: &mut World, e: Entity, t: &Transform) {
+        log::debug!("Set camera state from transform: {:?}", t);
+        assert!(ecs::entity_has_component::<Camera>(w, e));
+        let mat: Mat4 = (*t).into();
+        // TODO: Move this to gltf-specific code. Take pos + view dir + up as args.
+        // camera is looking in negative z according to the spec (gltf)
+        let view_dir: Vec3 = (mat * Vec4::new(0.0, 0.0, -1.0, 0.0)).xyz().normalized();
+
+        let mut transforms = w.write_storage::<Transform>();
+        transforms
+            .insert(e, *t)
+            .expect("Could not set transform for camera!");
+
+        let (yaw, pitch) = crate::math::yaw_pitch_from_direction(view_dir);
+
+        let mut rot_states = w.write_storage::<CameraRotationState>();
+        rot_states
+            .insert(e, CameraRotationState { yaw, pitch })
+            .expect("Could not set rotation state for camera!");
+    }
+    */
+}
+
+const NAME: &str = "FreeFlyCamera";
+
+/// The camera entity has one input context that is shared by the controllers. Only the active one
+/// drains the mapped input.
+fn get_input_context(bindings: &KeyBindings) -> Result<InputContext, InputContextError> {
+    let sens = 0.005 as Sensitivity;
+    use CameraMovement::*;
+    let states = [
+        (settings::CAMERA_FORWARD, Forward),
+        (settings::CAMERA_BACKWARD, Backward),
+        (settings::CAMERA_LEFT, Left),
+        (settings::CAMERA_RIGHT, Right),
+        (settings::CAMERA_UP, Up),
+        (settings::CAMERA_DOWN, Down),
+        (settings::CAMERA_MOVE, Move),
+        (settings::CAMERA_PAN, Pan),
+    ];
+    let mut builder = InputContext::builder(&NAME)
+        .description("Input mapping for the free fly and orbit cameras");
+    for (action, state) in states.iter() {
+        if let Some(button) = bindings.get(action) {
+            builder = builder.with_state(button, *state)?;
+        }
+    }
+    if let Some(button) = bindings.get(settings::CAMERA_SWITCH_CONTROLLER) {
+        builder = builder.with_action(button, SWITCH_CONTROLLER)?;
+    }
+    Ok(builder
+        // Switch y since the delta is computed from top-left corner
+        .with_range(DeviceAxis::MouseX, CameraRotation::YawDelta, sens)?
+        .with_range(DeviceAxis::MouseY, CameraRotation::PitchDelta, -sens)?
+        .with_range(DeviceAxis::ScrollY, CameraRotation::ZoomDelta, 1.0)?
+        .build())
+}
+
+impl<'a> ecs::System<'a> for FreeFlyCameraController {
+    type SystemData = (
+        WriteStorage<'a, MappedInput>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, CameraRotationState>,
+        ReadExpect<'a, Time>,
+        Read<'a, KeyBindings>,
+        WriteStorage<'a, InputContext>,
+        Entities<'a>,
+        WriteStorage<'a, OrbitCameraState>,
+        Write<'a, ActiveCameraController>,
+        Read<'a, CameraClipping>,
+        ReadStorage<'a, BoundingBox>,
+        ReadStorage<'a, ModelMatrix>,
+        ReadStorage<'a, Hidden>,
+        Read<'a, FreeFlySettings>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            mut mapped_inputs,
+            mut transforms,
+            mut cam_rot_state,
+            time,
+            bindings,
+            mut contexts,
+            entities,
+            mut orbit_states,
+            mut active,
+            clipping,
+            bboxes,
+            model_matrices,
+            hidden,
+            free_fly,
+        ) = data;
+
+        if bindings.version() != self.bindings_version {
+            for (context, _) in (&mut contexts, &cam_rot_state).join() {
+                match get_input_context(&bindings) {
+                    Ok(ctx) => *context = ctx,
+                    Err(e) => log::error!("Failed to rebind the camera: {:?}", e),
+                }
+            }
+            self.bindings_version = bindings.version();
+        }
+
+        if *active != ActiveCameraController::FreeFly {
+            return;
+        }
+
+        for (ent, mi, transform, rotation_state) in (
+            &entities,
+            &mut mapped_inputs,
+            &mut transforms,
+            &mut cam_rot_state,
+        )
+            .join()
+        {
+            let start = transform.position;
+            let mut moving = false;
+            for input in mi.drain() {
+                match input {
+                    Input::Action(SWITCH_CONTROLLER) => {
+                        let ori = FreeFlyCameraController::get_orientation_from(&rotation_state);
+                        let orbit =
+                            OrbitCameraState::looking_from(transform.position, ori.view_direction);
+                        orbit_states
+                            .insert(ent, orbit)
+                            .expect("Failed to add the orbit state to the camera");
+                        *active = ActiveCameraController::Orbit;
+                    }
+                    Input::Range(id, val) => {
+                        if moving {
+                            log::trace!("Found range, applying");
+                            match id.into() {
+                                CameraRotation::YawDelta => rotation_state.yaw += val as f32,
+                                CameraRotation::PitchDelta => rotation_state.pitch += val as f32,
+                                CameraRotation::ZoomDelta => continue,
+                            }
+
+                            rotation_state.clamp();
+                        }
+                    }
+                    Input::State(id) => {
+                        match id.into() {
+                            CameraMovement::Move => {
+                                moving = true;
+                                continue;
+                            }
+                            CameraMovement::Pan => continue,
+                            _ => (),
+                        }
+
+                        let CameraOrientation { view_direction, up } =
+                            FreeFlyCameraController::get_orientation_from(&rotation_state);
+                        use CameraMovement::*;
+                        let dir = time.delta_sim()
+                            * free_fly.movement_speed
+                            * match id.into() {
+                                Forward => view_direction,
+                                Backward => -view_direction,
+                                Left => up.cross(view_direction).normalized(),
+                                Right => -up.cross(view_direction).normalized(),
+                                Up => up,
+                                Down => -up,
+                                Move | Pan => unreachable!("Handled separately"),
+                            };
+
+                        transform.position += dir;
+                    }
+                    _ => unreachable!("No other input for the free fly camera!"),
+                }
+            }
+
+            if clipping.collide && transform.position != start {
+                let boxes = clipping::world_boxes(&entities, &bboxes, &model_matrices, &hidden);
+                transform.position =
+                    clipping::sweep(&boxes, start, transform.position, clipping.radius);
+            }
+        }
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        crate::cvar::register(
+            world,
+            "cam.speed",
+            "How fast the free fly camera moves, in meters per second",
+            Some((0.0, 1000.0)),
+            |s: &mut FreeFlySettings| &mut s.movement_speed,
+        );
+        let mut t = Transform::default();
+        t.position = Vec3::new(2.0, 2.0, 2.0);
+        // TODO: Compute from bounding box
+        let rot_state = CameraRotationState {
+            yaw: 4.0,
+            pitch: -0.4,
+        };
+
+        let input_context = {
+            let bindings = world.read_resource::<KeyBindings>();
+            self.bindings_version = bindings.version();
+            get_input_context(&bindings).expect("Unable to create input context")
+        };
+
+        world
+            .create_entity()
+            .with(t)
+            .with(input_context)
+            .with(Camera::default())
+            .with(rot_state)
+            .with(Name::from(NAME))
+            .build();
+    }
+}
+
+/// Rotates the camera around a target in front of it with the move button, pans the target with
+/// the pan button and zooms with the scroll wheel. Convenient for looking at a single model.
+pub struct OrbitCameraController;
+
+impl OrbitCameraController {
+    /// The position of a camera with the rotation, that looks at target from distance
+    fn position(target: Vec3, rotation_state: &CameraRotationState, distance: f32) -> Vec3 {
+        let ori = FreeFlyCameraController::get_orientation_from(rotation_state);
+        target - ori.view_direction * distance
+    }
+}
+
+impl<'a> ecs::System<'a> for OrbitCameraController {
+    type SystemData = (
+        WriteStorage<'a, MappedInput>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, CameraRotationState>,
+        WriteStorage<'a, OrbitCameraState>,
+        Write<'a, ActiveCameraController>,
+        Entities<'a>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            mut mapped_inputs,
+            mut transforms,
+            mut cam_rot_state,
+            mut orbit_states,
+            mut active,
+            entities,
+        ) = data;
+
+        if *active != ActiveCameraController::Orbit {
+            return;
+        }
+
+        for (ent, mi, transform, rotation_state) in (
+            &entities,
+            &mut mapped_inputs,
+            &mut transforms,
+            &mut cam_rot_state,
+        )
+            .join()
+        {
+            let inputs = mi.drain();
+            if inputs.is_empty() {
+                continue;
+            }
+
+            let mut orbit = match orbit_states.get(ent) {
+                Some(orbit) => *orbit,
+                None => {
+                    let ori = FreeFlyCameraController::get_orientation_from(rotation_state);
+                    OrbitCameraState::looking_from(transform.position, ori.view_direction)
+                }
+            };
+            let ori = FreeFlyCameraController::get_orientation_from(rotation_state);
+            let mut target = transform.position + ori.view_direction * orbit.distance;
+            let cam_right = ori.view_direction.cross(ori.up).normalized();
+            let cam_up = cam_right.cross(ori.view_direction).normalized();
+
+            // States are mapped before ranges, but don't rely on it
+            let held: Vec<CameraMovement> = inputs
+                .iter()
+                .filter_map(|i| match i {
+                    Input::State(id) => Some((*id).into()),
+                    _ => None,
+                })
+                .collect();
+            let rotating = held.contains(&CameraMovement::Move);
+            let panning = held.contains(&CameraMovement::Pan);
+
+            for input in inputs {
+                match input {
+                    Input::Action(SWITCH_CONTROLLER) => {
+                        *active = ActiveCameraController::FreeFly;
+                    }
+                    Input::Range(id, val) => {
+                        let val = val as f32;
+                        match id.into() {
+                            // Panning takes precedence over rotating. The target moves against
+                            // the cursor, as if grabbing the scene.
+                            CameraRotation::YawDelta if panning => {
+                                target -= cam_right * val * orbit.distance;
+                            }
+                            CameraRotation::PitchDelta if panning => {
+                                target -= cam_up * val * orbit.distance;
+                            }
+                            CameraRotation::YawDelta if rotating => rotation_state.yaw += val,
+                            CameraRotation::PitchDelta if rotating => {
+                                rotation_state.pitch += val;
+                                rotation_state.clamp();
+                            }
+                            CameraRotation::ZoomDelta => {
+                                // Pixel deltas from touchpads are much larger than line deltas
+                                let lines = val.max(-5.0).min(5.0);
+                                orbit.distance = (orbit.distance * ORBIT_ZOOM_FACTOR.powf(lines))
+                                    .max(ORBIT_MIN_DISTANCE)
+                                    .min(ORBIT_MAX_DISTANCE);
+                            }
+                            _ => (),
+                        }
+                    }
+                    Input::State(_) => (),
+                    _ => unreachable!("No other input for the orbit camera!"),
+                }
+            }
+
+            transform.position =
+                OrbitCameraController::position(target, rotation_state, orbit.distance);
+            orbit_states
+                .insert(ent, orbit)
+                .expect("Failed to update the orbit state of the camera");
+        }
+    }
+}
+
+const FREE_FLY_CAMERA_ID: &str = "free_fly_camera";
+const ORBIT_CAMERA_ID: &str = "orbit_camera";
+
+pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
+    let builder = builder
+        .with(FreeFlyCameraController::default(), FREE_FLY_CAMERA_ID, &[])
+        // After the free fly camera, so that a switch is not undone in the same frame
+        .with(
+            OrbitCameraController,
+            ORBIT_CAMERA_ID,
+            &[FREE_FLY_CAMERA_ID],
+        );
+    let builder = bookmarks::register_systems(builder);
+    clipping::register_systems(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec4;
+
+    #[test]
+    fn default_lens_fov() {
+        let fov = Camera::default().vertical_fov(16.0 / 9.0);
+        assert!((fov.to_degrees() - 45.7).abs() < 0.1);
+    }
+
+    #[test]
+    fn lens_shift_moves_image() {
+        let camera = Camera {
+            lens_shift_x: 0.25,
+            lens_shift_y: 0.25,
+            ..Default::default()
+        };
+        let aspect_ratio = 2.0;
+        let clip = camera.projection(aspect_ratio, 0.1, 100.0) * Vec4::new(0.0, 0.0, -10.0, 1.0);
+        let ndc = clip.xyz() / clip.w;
+        // Half the width to the left and a whole height down, as y points down
+        assert!((ndc.x + 0.5).abs() < 1e-5);
+        assert!((ndc.y - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn orbit_around_origin() {
+        let rot = CameraRotationState {
+            yaw: 4.0,
+            pitch: -0.4,
+        };
+        let pos = Vec3::new(2.0, 2.0, 2.0);
+        let view_direction = FreeFlyCameraController::get_orientation_from(&rot).view_direction;
+        let orbit = OrbitCameraState::looking_from(pos, view_direction);
+        let target = pos + view_direction * orbit.distance;
+        assert!((target.dot(view_direction)).abs() < 1e-5);
+
+        let rotated = CameraRotationState {
+            yaw: 1.0,
+            pitch: 0.3,
+        };
+        let moved = OrbitCameraController::position(target, &rotated, orbit.distance);
+        assert!(((moved - target).magnitude() - orbit.distance).abs() < 1e-4);
+    }
+
+    #[test]
+    fn orbit_default_distance_when_origin_is_behind() {
+        let orbit = OrbitCameraState::looking_from(Vec3::new(0.0, 0.0, 2.0), Vec3::unit_z());
+        assert_eq!(orbit.distance, ORBIT_DEFAULT_DISTANCE);
+    }
+}
@@ -0,0 +1,311 @@
+//! A kinematic character controller for walk-mode and simple game prototypes. There is no physics
+//! engine, so the world is queried with raycasts against the bounding boxes of the meshes in it.
+
+use crate::collision::{self, raycast, Collider};
+use crate::ecs::prelude::*;
+use crate::graph::{sys::breadth_first, Children};
+use crate::math::{BoundingBox, ModelMatrix, Ray, Transform, Vec3};
+use crate::time::Time;
+
+use std::collections::HashSet;
+
+const EPS: f32 = 0.001;
+/// How far inside the capsule the rays from its perimeter start
+const PERIMETER_SKIN: f32 = 0.01;
+
+/// A capsule that walks on the bounding boxes in the scene. The position of the transform is the
+/// bottom of the capsule and characters are expected to be root entities.
+#[derive(Debug, Clone, Component)]
+#[component(inspect, snapshot)]
+pub struct CharacterController {
+    pub radius: f32,
+    pub height: f32,
+    /// Obstacles lower than this are stepped onto
+    pub step_height: f32,
+    /// The steepest ground that can be walked on, in radians
+    pub slope_limit: f32,
+    pub gravity: f32,
+    /// Only the horizontal part is used, vertical movement comes from gravity
+    pub desired_velocity: Vec3,
+    pub vertical_speed: f32,
+    pub grounded: bool,
+}
+
+impl Default for CharacterController {
+    fn default() -> Self {
+        Self {
+            radius: 0.3,
+            height: 1.8,
+            step_height: 0.3,
+            slope_limit: std::f32::consts::FRAC_PI_4,
+            gravity: 9.81,
+            desired_velocity: Vec3::zero(),
+            vertical_speed: 0.0,
+            grounded: false,
+        }
+    }
+}
+
+impl CharacterController {
+    fn walkable(&self, normal: Vec3) -> bool {
+        normal.y >= self.slope_limit.cos()
+    }
+
+    /// Moves the character for dt seconds and returns the new position
+    fn step(&mut self, position: Vec3, dt: f32, colliders: &[Collider]) -> Vec3 {
+        let up = Vec3::unit_y();
+
+        let mut movement = Vec3::new(self.desired_velocity.x, 0.0, self.desired_velocity.z) * dt;
+        let distance = movement.magnitude();
+        if distance > EPS {
+            let direction = movement / distance;
+            let side = up.cross(direction);
+            // The closest blocking hit of all rays, as the allowed movement and the wall normal
+            let mut closest: Option<(f32, Option<Vec3>)> = None;
+            // Right above the step height, so that anything lower is stepped onto by the ground
+            // check below, and at the top of the capsule.
+            for height in [self.step_height + EPS, self.height - self.radius].iter() {
+                // From the center line and from the front half of the perimeter, slightly inside
+                // so that walls that are slid along are not hit.
+                let center = position + up * *height;
+                let r = self.radius - PERIMETER_SKIN;
+                let diagonal = std::f32::consts::FRAC_1_SQRT_2 * r;
+                let origins = [
+                    (center, true),
+                    (center + side * r, false),
+                    (center + (direction + side) * diagonal, false),
+                    (center + (direction - side) * diagonal, false),
+                    (center - side * r, false),
+                ];
+                for (origin, is_center) in origins.iter() {
+                    let ray = Ray {
+                        origin: *origin,
+                        direction,
+                    };
+                    let (t, normal) = match raycast(colliders, &ray, std::f32::INFINITY) {
+                        // Ramps are walked up by the ground check
+                        Some((_, normal)) if self.walkable(normal) => continue,
+                        Some(hit) => hit,
+                        None => continue,
+                    };
+
+                    let wall_normal = Vec3::new(normal.x, 0.0, normal.z);
+                    let wall_normal = if wall_normal.magnitude_squared() > EPS {
+                        Some(wall_normal.normalized())
+                    } else {
+                        None
+                    };
+                    let approach = wall_normal.map_or(1.0, |n| -direction.dot(n));
+                    if approach <= EPS {
+                        continue;
+                    }
+                    let allowed = if *is_center {
+                        // Keep the radius to the wall, measured along its normal
+                        t - self.radius / approach
+                    } else {
+                        // The ray starts on the capsule, so it touches where the ray hits
+                        t - EPS
+                    }
+                    .max(0.0);
+                    if allowed < distance && closest.map_or(true, |(a, _)| allowed < a) {
+                        closest = Some((allowed, wall_normal));
+                    }
+                }
+            }
+
+            if let Some((allowed, wall_normal)) = closest {
+                // Slide along the wall with what is left of the movement
+                let blocked = direction * allowed;
+                let rest = movement - blocked;
+                movement = match wall_normal {
+                    Some(n) => blocked + rest - n * rest.dot(n),
+                    None => blocked,
+                };
+            }
+        }
+
+        let mut new_position = position + movement;
+
+        self.vertical_speed -= self.gravity * dt;
+        let fall = (-self.vertical_speed * dt).max(0.0);
+        // Keep to the ground when walking down slopes and steps
+        let snap = if self.grounded { self.step_height } else { 0.0 };
+        let ground_ray = Ray {
+            origin: new_position + up * self.step_height,
+            direction: -up,
+        };
+        match raycast(colliders, &ground_ray, self.step_height + fall.max(snap)) {
+            Some((t, normal)) if self.walkable(normal) => {
+                new_position.y = ground_ray.origin.y - t;
+                self.vertical_speed = 0.0;
+                self.grounded = true;
+            }
+            Some(_) => {
+                // Too steep to stand on, which blocks like a wall
+                new_position = position;
+                self.vertical_speed = 0.0;
+                self.grounded = false;
+            }
+            None => {
+                new_position.y += self.vertical_speed * dt;
+                self.grounded = false;
+            }
+        }
+
+        new_position
+    }
+}
+
+pub struct CharacterMovement;
+
+impl CharacterMovement {
+    pub const ID: &'static str = "CharacterMovement";
+}
+
+impl<'a> System<'a> for CharacterMovement {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, CharacterController>,
+        WriteStorage<'a, Transform>,
+        ReadStorage<'a, BoundingBox>,
+        ReadStorage<'a, ModelMatrix>,
+        ReadStorage<'a, Children>,
+        ReadExpect<'a, Time>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, mut controllers, mut transforms, bboxes, model_matrices, children, time) =
+            data;
+        let dt = time.delta_sim().as_secs();
+
+        for (ent, controller, transform) in (&entities, &mut controllers, &mut transforms).join() {
+            // The character doesn't collide with its own meshes
+            let mut own = HashSet::new();
+            breadth_first(&children, ent, |node| {
+                own.insert(node);
+            });
+
+            let colliders =
+                collision::gather(&entities, &bboxes, &model_matrices, |e| !own.contains(&e));
+
+            transform.position = controller.step(transform.position, dt, &colliders);
+        }
+    }
+}
+
+pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
+    builder.with(CharacterMovement, CharacterMovement::ID, &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Mat4;
+    use vek::approx::assert_abs_diff_eq;
+
+    const DT: f32 = 0.05;
+
+    fn floor() -> Collider {
+        let bbox = BoundingBox {
+            min: Vec3::new(-10.0, -1.0, -10.0),
+            max: Vec3::new(10.0, 0.0, 10.0),
+        };
+        Collider::new(bbox, Mat4::identity())
+    }
+
+    fn simulate(
+        controller: &mut CharacterController,
+        mut position: Vec3,
+        colliders: &[Collider],
+    ) -> Vec3 {
+        for _ in 0..60 {
+            position = controller.step(position, DT, colliders);
+        }
+        position
+    }
+
+    #[test]
+    fn falls_to_the_ground() {
+        let mut controller = CharacterController::default();
+        let position = simulate(&mut controller, Vec3::new(0.0, 2.0, 0.0), &[floor()]);
+        assert_abs_diff_eq!(position, Vec3::zero(), epsilon = EPS);
+        assert!(controller.grounded);
+    }
+
+    #[test]
+    fn blocked_by_walls() {
+        let wall = Collider::new(
+            BoundingBox {
+                min: Vec3::new(1.0, 0.0, -10.0),
+                max: Vec3::new(2.0, 3.0, 10.0),
+            },
+            Mat4::identity(),
+        );
+        let mut controller = CharacterController {
+            desired_velocity: Vec3::new(1.0, 0.0, 1.0),
+            ..Default::default()
+        };
+        let position = simulate(&mut controller, Vec3::zero(), &[floor(), wall]);
+        assert!(position.x <= 1.0 - controller.radius + EPS);
+        // Slides along the wall
+        assert!(position.z > 1.0);
+    }
+
+    #[test]
+    fn blocked_by_off_center_obstacles() {
+        // A post that misses the center line but not the capsule
+        let post = Collider::new(
+            BoundingBox {
+                min: Vec3::new(1.0, 0.0, 0.15),
+                max: Vec3::new(1.2, 3.0, 0.5),
+            },
+            Mat4::identity(),
+        );
+        let mut controller = CharacterController {
+            desired_velocity: Vec3::new(0.8, 0.0, 0.0),
+            ..Default::default()
+        };
+        let position = simulate(&mut controller, Vec3::zero(), &[floor(), post]);
+        assert!(position.x > 0.5);
+        assert!(position.x < 1.0 - controller.radius / 2.0);
+    }
+
+    #[test]
+    fn steps_onto_low_obstacles() {
+        let step = Collider::new(
+            BoundingBox {
+                min: Vec3::new(1.0, 0.0, -10.0),
+                max: Vec3::new(3.0, 0.2, 10.0),
+            },
+            Mat4::identity(),
+        );
+        let mut controller = CharacterController {
+            desired_velocity: Vec3::new(0.8, 0.0, 0.0),
+            ..Default::default()
+        };
+        let position = simulate(&mut controller, Vec3::zero(), &[floor(), step]);
+        assert!(position.x > 1.5);
+        assert_abs_diff_eq!(position.y, 0.2, epsilon = EPS);
+    }
+
+    #[test]
+    fn steep_slopes_block() {
+        // A box rotated 60 degrees around z, so that its top is too steep to walk on
+        let model = Mat4::translation_3d(Vec3::new(2.0, 0.0, 0.0))
+            * Mat4::rotation_z(std::f32::consts::FRAC_PI_3);
+        let slope = Collider::new(
+            BoundingBox {
+                min: Vec3::new(-1.0, -1.0, -10.0),
+                max: Vec3::new(1.0, 1.0, 10.0),
+            },
+            model,
+        );
+        let mut controller = CharacterController {
+            desired_velocity: Vec3::new(1.0, 0.0, 0.0),
+            ..Default::default()
+        };
+        let position = simulate(&mut controller, Vec3::zero(), &[floor(), slope]);
+        assert!(position.x < 1.0);
+        assert!(position.y < controller.step_height);
+    }
+}
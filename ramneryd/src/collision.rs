@@ -0,0 +1,63 @@
+//! Raycasts against the bounding boxes of the meshes in the scene. There is no physics engine,
+//! so these are the collision shapes that gameplay code has to work with.
+
+use crate::ecs::prelude::*;
+use crate::math::{BoundingBox, Mat4, ModelMatrix, Ray, Vec3, Vec4};
+
+/// A bounding box in model space, with the matrices needed to raycast against it in world space
+pub(crate) struct Collider {
+    bbox: BoundingBox,
+    model: Mat4,
+    inv_model: Mat4,
+    normal_matrix: Mat4,
+}
+
+impl Collider {
+    pub(crate) fn new(bbox: BoundingBox, model: Mat4) -> Self {
+        let inv_model = model.inverted();
+        Self {
+            bbox,
+            model,
+            inv_model,
+            normal_matrix: inv_model.transposed(),
+        }
+    }
+
+    pub(crate) fn world_bbox(&self) -> BoundingBox {
+        self.bbox.transformed(&self.model)
+    }
+}
+
+/// The colliders of all entities with a bounding box, except the ones that are filtered out
+pub(crate) fn gather<'a>(
+    entities: &Entities<'a>,
+    bboxes: &ReadStorage<'a, BoundingBox>,
+    model_matrices: &ReadStorage<'a, ModelMatrix>,
+    mut include: impl FnMut(Entity) -> bool,
+) -> Vec<Collider> {
+    (entities, bboxes, model_matrices)
+        .join()
+        .filter(|(ent, _, _)| include(*ent))
+        .map(|(_, bbox, model)| Collider::new(*bbox, model.0))
+        .collect()
+}
+
+/// The closest hit within max_distance and the normal of the surface that was hit. Rays that
+/// start inside a box don't hit it.
+pub(crate) fn raycast(colliders: &[Collider], ray: &Ray, max_distance: f32) -> Option<(f32, Vec3)> {
+    colliders
+        .iter()
+        .filter_map(|c| {
+            let (t, n) = ray
+                .transformed(c.inv_model)
+                .intersect_with_normal(&c.bbox)?;
+            if n == Vec3::zero() || t > max_distance {
+                return None;
+            }
+            let normal = (c.normal_matrix * Vec4::from_direction(n))
+                .xyz()
+                .normalized();
+            Some((t, normal))
+        })
+        .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+}
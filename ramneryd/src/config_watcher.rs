@@ -0,0 +1,91 @@
+//! Applies the edits to the config file (see cvar::load_config) and the key bindings file while the
+//! engine is running, for those who prefer a text editor over the ui. Only cvars and key bindings
+//! are applied, as both can change at any time. The changes are logged.
+
+use std::path::Path;
+
+use crate::cvar;
+use crate::ecs::prelude::*;
+use crate::file_watcher::{PollTimer, WatchedFiles};
+use crate::settings::KeyBindings;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Cvars,
+    KeyBindings,
+}
+
+pub struct ConfigWatcher {
+    timer: PollTimer,
+    files: WatchedFiles<Kind>,
+}
+
+impl ConfigWatcher {
+    pub fn new(config: Option<&Path>, key_bindings: Option<&Path>) -> Self {
+        let mut files = WatchedFiles::default();
+        if let Some(path) = config {
+            files.watch(path, || Kind::Cvars);
+        }
+        if let Some(path) = key_bindings {
+            files.watch(path, || Kind::KeyBindings);
+        }
+        Self {
+            timer: PollTimer::default(),
+            files,
+        }
+    }
+
+    /// Reloads the files that were modified. Called once per frame, before cvar::sync so that the
+    /// listeners of the cvars are notified in the same frame.
+    pub fn poll(&mut self, world: &World) {
+        if !self.timer.ready() {
+            return;
+        }
+
+        for (path, kind) in self.files.poll() {
+            // Files that were removed are not reloaded
+            if !path.exists() {
+                continue;
+            }
+            match *kind {
+                Kind::Cvars => reload_cvars(world, &path),
+                Kind::KeyBindings => reload_key_bindings(world, &path),
+            }
+        }
+    }
+}
+
+fn reload_cvars(world: &World, path: &Path) {
+    match cvar::reload_config(world, path) {
+        Ok(changes) if changes.is_empty() => {
+            log::info!("{} changed on disk, no cvars changed", path.display())
+        }
+        Ok(changes) => {
+            for change in changes {
+                log::info!(
+                    "{}: {} = {} (was {})",
+                    path.display(),
+                    change.name,
+                    change.new,
+                    change.old
+                );
+            }
+        }
+        Err(e) => log::error!("Failed to reload config {}: {}", path.display(), e),
+    }
+}
+
+fn reload_key_bindings(world: &World, path: &Path) {
+    // The ui writes the file when the bindings are saved, which changes nothing
+    match world.write_resource::<KeyBindings>().reload() {
+        Ok(changes) => {
+            for (action, button) in changes {
+                match button {
+                    Some(button) => log::info!("{}: {} = {:?}", path.display(), action, button),
+                    None => log::info!("{}: {} is unbound", path.display(), action),
+                }
+            }
+        }
+        Err(e) => log::error!("Failed to reload key bindings {}: {}", path.display(), e),
+    }
+}
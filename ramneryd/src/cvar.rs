@@ -0,0 +1,613 @@
+//! Console variables, named and typed settings such as `r.bloom.threshold` that can be set from the
+//! command line (`--set name=value`), from config files and from the console window. Each cvar is
+//! backed by a field of a resource, e.g. RenderSettings, so systems keep reading the resource as
+//! before. Values are validated and clamped to the range of the cvar, and listeners are notified
+//! when a value changes, regardless of where it was changed from.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use specs::shred::Resource;
+use thiserror::Error;
+
+use crate::ecs::prelude::*;
+
+#[derive(Debug, Error)]
+pub enum CvarError {
+    #[error("Unknown cvar {0}")]
+    Unknown(String),
+    #[error("Invalid value {value} for {name}, expected {expected}")]
+    InvalidValue {
+        name: String,
+        value: String,
+        expected: &'static str,
+    },
+    #[error("Expected name=value, got {0}")]
+    Syntax(String),
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+    /// A console command failed, see register_command
+    #[error("{0}")]
+    Command(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CvarValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+}
+
+impl std::fmt::Display for CvarValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Bool(v) => write!(f, "{}", v),
+            Self::Int(v) => write!(f, "{}", v),
+            Self::Float(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl CvarValue {
+    fn expected(&self) -> &'static str {
+        match self {
+            Self::Bool(_) => "true/false",
+            Self::Int(_) => "an integer",
+            Self::Float(_) => "a number",
+        }
+    }
+
+    /// Parse `s` as the same kind of value as self
+    fn parse_as(&self, s: &str) -> Option<Self> {
+        match self {
+            Self::Bool(_) => match s {
+                "1" | "true" | "on" => Some(Self::Bool(true)),
+                "0" | "false" | "off" => Some(Self::Bool(false)),
+                _ => None,
+            },
+            Self::Int(_) => s.parse().ok().map(Self::Int),
+            Self::Float(_) => s.parse().ok().map(Self::Float),
+        }
+    }
+
+    fn clamp(self, range: Option<(f64, f64)>) -> Self {
+        match (self, range) {
+            (Self::Int(v), Some((min, max))) => Self::Int(v.max(min as i64).min(max as i64)),
+            (Self::Float(v), Some((min, max))) => Self::Float(v.max(min).min(max)),
+            (v, _) => v,
+        }
+    }
+}
+
+/// The types of the fields that can back a cvar
+pub trait CvarType: Copy + Send + Sync + 'static {
+    fn to_value(self) -> CvarValue;
+    /// None if the value can't be represented by the type
+    fn from_value(value: CvarValue) -> Option<Self>;
+}
+
+impl CvarType for bool {
+    fn to_value(self) -> CvarValue {
+        CvarValue::Bool(self)
+    }
+
+    fn from_value(value: CvarValue) -> Option<Self> {
+        match value {
+            CvarValue::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl CvarType for f32 {
+    fn to_value(self) -> CvarValue {
+        CvarValue::Float(self as f64)
+    }
+
+    fn from_value(value: CvarValue) -> Option<Self> {
+        match value {
+            CvarValue::Float(v) => Some(v as f32),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! impl_cvar_type_int {
+    ($ty:ty) => {
+        impl CvarType for $ty {
+            fn to_value(self) -> CvarValue {
+                CvarValue::Int(self as i64)
+            }
+
+            fn from_value(value: CvarValue) -> Option<Self> {
+                use std::convert::TryFrom;
+                match value {
+                    CvarValue::Int(v) => <$ty>::try_from(v).ok(),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+impl_cvar_type_int!(u8);
+impl_cvar_type_int!(u32);
+impl_cvar_type_int!(i32);
+impl_cvar_type_int!(usize);
+
+/// Implements CvarType for a fieldless enum, with the variants as integers in declaration order.
+/// Requires num_derive::FromPrimitive.
+macro_rules! impl_cvar_type_enum {
+    ($ty:ty) => {
+        impl $crate::cvar::CvarType for $ty {
+            fn to_value(self) -> $crate::cvar::CvarValue {
+                $crate::cvar::CvarValue::Int(self as i64)
+            }
+
+            fn from_value(value: $crate::cvar::CvarValue) -> Option<Self> {
+                match value {
+                    $crate::cvar::CvarValue::Int(v) => num_traits::FromPrimitive::from_i64(v),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+type Callback = Arc<dyn Fn(&World, CvarValue) + Send + Sync>;
+
+struct Cvar {
+    description: &'static str,
+    default: CvarValue,
+    range: Option<(f64, f64)>,
+    get: Box<dyn Fn(&World) -> CvarValue + Send + Sync>,
+    set: Box<dyn Fn(&World, CvarValue) -> bool + Send + Sync>,
+    // The value the callbacks were last called with, to detect changes made through the resource
+    last: CvarValue,
+    callbacks: Vec<Callback>,
+}
+
+type Command = Arc<dyn Fn(&World, &str) -> Result<String, String> + Send + Sync>;
+
+struct ConsoleCommand {
+    description: &'static str,
+    run: Command,
+}
+
+#[derive(Default)]
+struct ConsoleState {
+    input: imgui::ImString,
+    log: Vec<String>,
+}
+
+/// The registered cvars, sorted by name
+#[derive(Default)]
+pub struct Cvars {
+    vars: BTreeMap<&'static str, Cvar>,
+    commands: BTreeMap<&'static str, ConsoleCommand>,
+    console: ConsoleState,
+}
+
+/// Registers a cvar backed by the field of the resource that `field` returns. The current value of
+/// the field is the default. `range` clamps numeric values.
+pub fn register<R: Resource, T: CvarType>(
+    world: &mut World,
+    name: &'static str,
+    description: &'static str,
+    range: Option<(f64, f64)>,
+    field: fn(&mut R) -> &mut T,
+) {
+    let get = move |world: &World| field(&mut *world.write_resource::<R>()).to_value();
+    let set = move |world: &World, value: CvarValue| match T::from_value(value) {
+        Some(v) => {
+            *field(&mut *world.write_resource::<R>()) = v;
+            true
+        }
+        None => false,
+    };
+
+    let default = get(world);
+    let cvar = Cvar {
+        description,
+        default,
+        range,
+        get: Box::new(get),
+        set: Box::new(set),
+        last: default,
+        callbacks: Vec::new(),
+    };
+
+    let mut cvars = world.entry::<Cvars>().or_insert_with(Cvars::default);
+    if cvars.vars.insert(name, cvar).is_some() {
+        log::warn!("The cvar {} was registered twice", name);
+    }
+}
+
+/// Calls `callback` with the new value when the value of the cvar changes. It is called from
+/// sync, at the start of the frame after the change.
+pub fn on_change(
+    world: &World,
+    name: &str,
+    callback: impl Fn(&World, CvarValue) + Send + Sync + 'static,
+) -> Result<(), CvarError> {
+    let mut cvars = world.write_resource::<Cvars>();
+    let cvar = cvars
+        .vars
+        .get_mut(name)
+        .ok_or_else(|| CvarError::Unknown(name.to_owned()))?;
+    cvar.callbacks.push(Arc::new(callback));
+    Ok(())
+}
+
+/// Registers a console command, that `execute` runs with the rest of the line as the argument.
+/// Commands take precedence over cvars with the same name.
+pub fn register_command(
+    world: &mut World,
+    name: &'static str,
+    description: &'static str,
+    run: impl Fn(&World, &str) -> Result<String, String> + Send + Sync + 'static,
+) {
+    let command = ConsoleCommand {
+        description,
+        run: Arc::new(run),
+    };
+    let mut cvars = world.entry::<Cvars>().or_insert_with(Cvars::default);
+    if cvars.commands.insert(name, command).is_some() {
+        log::warn!("The console command {} was registered twice", name);
+    }
+}
+
+pub fn get(world: &World, name: &str) -> Result<CvarValue, CvarError> {
+    let cvars = world.read_resource::<Cvars>();
+    let cvar = cvars
+        .vars
+        .get(name)
+        .ok_or_else(|| CvarError::Unknown(name.to_owned()))?;
+    Ok((cvar.get)(world))
+}
+
+/// Parses `value` as the type of the cvar and writes it, clamped to the range, to the backing
+/// field. Returns the value that was written.
+pub fn set(world: &World, name: &str, value: &str) -> Result<CvarValue, CvarError> {
+    let cvars = world.read_resource::<Cvars>();
+    let cvar = cvars
+        .vars
+        .get(name)
+        .ok_or_else(|| CvarError::Unknown(name.to_owned()))?;
+    let invalid = || CvarError::InvalidValue {
+        name: name.to_owned(),
+        value: value.to_owned(),
+        expected: cvar.default.expected(),
+    };
+
+    let parsed = cvar
+        .default
+        .parse_as(value.trim())
+        .ok_or_else(invalid)?
+        .clamp(cvar.range);
+    if (cvar.set)(world, parsed) {
+        Ok(parsed)
+    } else {
+        Err(invalid())
+    }
+}
+
+/// Sets a cvar from `name=value`, as given on the command line
+pub fn set_from_str(world: &World, assignment: &str) -> Result<CvarValue, CvarError> {
+    match assignment.find('=') {
+        Some(idx) => set(world, assignment[..idx].trim(), &assignment[idx + 1..]),
+        None => Err(CvarError::Syntax(assignment.to_owned())),
+    }
+}
+
+/// A cvar that was changed by a config file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigChange {
+    pub name: String,
+    pub old: CvarValue,
+    pub new: CvarValue,
+}
+
+/// Sets the cvars of the lines of a config file. Returns the number of cvars that were set and the
+/// ones whose value changed.
+fn apply_config(world: &World, path: &Path, contents: &str) -> (usize, Vec<ConfigChange>) {
+    let mut n_set = 0;
+    let mut changes = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let name = line.split('=').next().unwrap_or("").trim();
+        let old = get(world, name).ok();
+        match set_from_str(world, line) {
+            Ok(new) => {
+                n_set += 1;
+                if let Some(old) = old.filter(|old| *old != new) {
+                    changes.push(ConfigChange {
+                        name: name.to_owned(),
+                        old,
+                        new,
+                    });
+                }
+            }
+            Err(e) => log::warn!("{}:{}: {}", path.display(), i + 1, e),
+        }
+    }
+    (n_set, changes)
+}
+
+/// Sets the cvars of a config file, with one `name = value` per line and `#` starting a comment.
+/// Lines that fail are logged and skipped. Returns the number of cvars that were set.
+pub fn load_config(world: &World, path: &Path) -> Result<usize, CvarError> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(apply_config(world, path, &contents).0)
+}
+
+/// Like load_config, for a config file that was edited while running. Returns the cvars that
+/// changed. Cvars that were removed from the file keep their current value.
+pub fn reload_config(world: &World, path: &Path) -> Result<Vec<ConfigChange>, CvarError> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(apply_config(world, path, &contents).1)
+}
+
+/// Clamps values that were written directly to the backing fields, e.g. by the inspector, and
+/// calls the callbacks of the cvars that changed since the last sync. Called once per frame.
+pub fn sync(world: &World) {
+    let mut changed: Vec<(Vec<Callback>, CvarValue)> = Vec::new();
+    {
+        let mut cvars = world.write_resource::<Cvars>();
+        for (name, cvar) in cvars.vars.iter_mut() {
+            let current = (cvar.get)(world);
+            let clamped = current.clamp(cvar.range);
+            if clamped != current && !(cvar.set)(world, clamped) {
+                log::error!("Failed to clamp {} to {}", name, clamped);
+            }
+            if clamped != cvar.last {
+                log::debug!("{} = {}", name, clamped);
+                cvar.last = clamped;
+                changed.push((cvar.callbacks.clone(), clamped));
+            }
+        }
+    }
+
+    // Without holding on to the cvars, so that the callbacks can use them
+    for (callbacks, value) in changed {
+        for callback in callbacks {
+            callback(world, value);
+        }
+    }
+}
+
+/// Runs a line of the console: `name` shows the cvar, `name value` or `name=value` sets it and
+/// `list [prefix]` shows all cvars and commands starting with prefix. Registered commands are run
+/// with the rest of the line, see register_command.
+pub fn execute(world: &World, line: &str) -> Result<String, CvarError> {
+    let line = line.trim();
+    let (command, arg) = match line.find(|c: char| c == '=' || c.is_whitespace()) {
+        Some(idx) => (&line[..idx], Some(line[idx + 1..].trim())),
+        None => (line, None),
+    };
+
+    if command == "list" {
+        let cvars = world.read_resource::<Cvars>();
+        let prefix = arg.unwrap_or("");
+        let lines: Vec<String> = cvars
+            .vars
+            .iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, cvar)| format!("{} = {}", name, (cvar.get)(world)))
+            .chain(
+                cvars
+                    .commands
+                    .iter()
+                    .filter(|(name, _)| name.starts_with(prefix))
+                    .map(|(name, command)| format!("{}: {}", name, command.description)),
+            )
+            .collect();
+        return Ok(lines.join("\n"));
+    }
+
+    let run = world
+        .read_resource::<Cvars>()
+        .commands
+        .get(command)
+        .map(|command| Arc::clone(&command.run));
+    if let Some(run) = run {
+        // Without holding on to the cvars, so that the command can use them
+        return run(world, arg.unwrap_or("")).map_err(CvarError::Command);
+    }
+
+    match arg {
+        Some(value) if !value.is_empty() => {
+            let value = set(world, command, value)?;
+            Ok(format!("{} = {}", command, value))
+        }
+        _ => {
+            let cvars = world.read_resource::<Cvars>();
+            let cvar = cvars
+                .vars
+                .get(command)
+                .ok_or_else(|| CvarError::Unknown(command.to_owned()))?;
+            let mut out = format!(
+                "{} = {} (default {})",
+                command,
+                (cvar.get)(world),
+                cvar.default
+            );
+            if let Some((min, max)) = cvar.range {
+                out.push_str(&format!(", in [{}, {}]", min, max));
+            }
+            out.push_str(&format!("\n    {}", cvar.description));
+            Ok(out)
+        }
+    }
+}
+
+pub(crate) fn build_ui<'a>(
+    world: &mut World,
+    ui: &crate::render::ui::UiFrame<'a>,
+    pos: [f32; 2],
+) -> [f32; 2] {
+    let size = [300.0, 200.0];
+
+    imgui::Window::new(imgui::im_str!("Console"))
+        .position(pos, imgui::Condition::FirstUseEver)
+        .size(size, imgui::Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let mut cvars = world.write_resource::<Cvars>();
+            imgui::ChildWindow::new(imgui::im_str!("console_log"))
+                .size([0.0, -30.0])
+                .build(ui.inner(), || {
+                    for line in cvars.console.log.iter() {
+                        ui.inner().text(line);
+                    }
+                });
+            let entered = imgui::InputText::new(
+                ui.inner(),
+                imgui::im_str!("##console_input"),
+                &mut cvars.console.input,
+            )
+            .resize_buffer(true)
+            .enter_returns_true(true)
+            .build();
+            if !entered {
+                return;
+            }
+
+            let line = cvars.console.input.to_str().to_owned();
+            cvars.console.input.clear();
+            // execute needs to read the cvars
+            drop(cvars);
+            let out = match execute(world, &line) {
+                Ok(out) => out,
+                Err(e) => e.to_string(),
+            };
+            let mut cvars = world.write_resource::<Cvars>();
+            cvars.console.log.push(format!("> {}", line));
+            cvars.console.log.extend(out.lines().map(String::from));
+        });
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Settings {
+        enabled: bool,
+        count: u32,
+        scale: f32,
+    }
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.insert(Settings {
+            enabled: false,
+            count: 4,
+            scale: 1.0,
+        });
+        register(&mut world, "t.enabled", "", None, |s: &mut Settings| {
+            &mut s.enabled
+        });
+        register(
+            &mut world,
+            "t.count",
+            "",
+            Some((1.0, 8.0)),
+            |s: &mut Settings| &mut s.count,
+        );
+        register(
+            &mut world,
+            "t.scale",
+            "",
+            Some((0.0, 2.0)),
+            |s: &mut Settings| &mut s.scale,
+        );
+        world
+    }
+
+    #[test]
+    fn set_writes_through_and_clamps() {
+        let world = world();
+        set_from_str(&world, "t.enabled=on").unwrap();
+        set_from_str(&world, "t.count = 100").unwrap();
+        set(&world, "t.scale", "0.5").unwrap();
+
+        let settings = world.read_resource::<Settings>();
+        assert!(settings.enabled);
+        assert_eq!(settings.count, 8);
+        assert_eq!(settings.scale, 0.5);
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let world = world();
+        assert!(matches!(
+            set(&world, "t.count", "1.5"),
+            Err(CvarError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            set(&world, "t.missing", "1"),
+            Err(CvarError::Unknown(_))
+        ));
+        assert!(matches!(
+            set_from_str(&world, "t.count"),
+            Err(CvarError::Syntax(_))
+        ));
+        assert_eq!(world.read_resource::<Settings>().count, 4);
+    }
+
+    #[test]
+    fn sync_clamps_and_notifies() {
+        let world = world();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        on_change(&world, "t.scale", move |_, value| {
+            assert_eq!(value, CvarValue::Float(2.0));
+            counter.fetch_add(1, Ordering::SeqCst);
+        })
+        .unwrap();
+
+        sync(&world);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        // As if changed in the inspector
+        world.write_resource::<Settings>().scale = 5.0;
+        sync(&world);
+        sync(&world);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(world.read_resource::<Settings>().scale, 2.0);
+    }
+
+    #[test]
+    fn reloading_a_config_reports_the_changed_cvars() {
+        let world = world();
+        let path = Path::new("settings.cfg");
+        let contents = "t.enabled = true # on\nt.count = 4\n\nt.scale = 10\nt.missing = 1\n";
+        let (n_set, changes) = apply_config(&world, path, contents);
+        assert_eq!(n_set, 3);
+        assert_eq!(
+            changes,
+            vec![
+                ConfigChange {
+                    name: "t.enabled".to_owned(),
+                    old: CvarValue::Bool(false),
+                    new: CvarValue::Bool(true),
+                },
+                ConfigChange {
+                    name: "t.scale".to_owned(),
+                    old: CvarValue::Float(1.0),
+                    new: CvarValue::Float(2.0),
+                },
+            ]
+        );
+
+        let (_, changes) = apply_config(&world, path, contents);
+        assert!(changes.is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+//! Introspection of the executors: the systems, the resources they read and write and how they are
+//! ordered. Shown in the Systems window, to debug the order of the systems within a frame.
+//!
+//! The order can be adjusted without recompiling with a RON file, see RunOptions::system_order:
+//!
+//! ```ron
+//! (
+//!     // Extra dependencies, by system id. The dependencies have to be added before the system.
+//!     dependencies: { "orbit_camera": ["CameraBookmarkSys"] },
+//!     // Barriers are added before these systems, so they run after all systems added before them
+//!     barriers_before: ["ApplySettings"],
+//! )
+//! ```
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use thiserror::Error;
+
+use super::prelude::*;
+
+#[derive(Debug, Error)]
+pub enum OrderConfigError {
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("RON error: {0}")]
+    Ron(#[from] ron::Error),
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct OrderConfig {
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub barriers_before: Vec<String>,
+}
+
+impl OrderConfig {
+    pub fn load(path: &Path) -> Result<Self, OrderConfigError> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(ron::de::from_str(&contents)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SystemInfo {
+    pub id: String,
+    /// Including the ones from the OrderConfig
+    pub deps: Vec<String>,
+    pub reads: Vec<ResourceId>,
+    pub writes: Vec<ResourceId>,
+    /// The number of barriers before the system
+    pub stage: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExecutorInfo {
+    pub name: &'static str,
+    pub systems: Vec<SystemInfo>,
+}
+
+/// The executors of the engine, in the order they run in a frame
+#[derive(Debug, Default)]
+pub struct SystemGraph {
+    pub executors: Vec<ExecutorInfo>,
+}
+
+/// Two systems that access the same resource, where at least one of them writes it, that are not
+/// ordered by dependencies or barriers. They run in the order they were added, which is easy to
+/// change by accident.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnorderedConflict {
+    pub first: String,
+    pub second: String,
+    pub resource: ResourceId,
+}
+
+fn ancestors(systems: &[SystemInfo], idx: usize) -> HashSet<usize> {
+    let mut visited = HashSet::new();
+    let mut stack = vec![idx];
+    while let Some(i) = stack.pop() {
+        for dep in systems[i].deps.iter() {
+            if let Some(d) = systems.iter().position(|s| &s.id == dep) {
+                if visited.insert(d) {
+                    stack.push(d);
+                }
+            }
+        }
+    }
+    visited
+}
+
+pub fn unordered_conflicts(systems: &[SystemInfo]) -> Vec<UnorderedConflict> {
+    let ancestors: Vec<HashSet<usize>> =
+        (0..systems.len()).map(|i| ancestors(systems, i)).collect();
+    let mut conflicts = Vec::new();
+    for (i, a) in systems.iter().enumerate() {
+        for (j, b) in systems.iter().enumerate().skip(i + 1) {
+            if a.stage != b.stage || ancestors[i].contains(&j) || ancestors[j].contains(&i) {
+                continue;
+            }
+            let conflict = a
+                .writes
+                .iter()
+                .find(|w| b.reads.contains(w) || b.writes.contains(w))
+                .or_else(|| b.writes.iter().find(|w| a.reads.contains(w)));
+            if let Some(resource) = conflict {
+                conflicts.push(UnorderedConflict {
+                    first: a.id.clone(),
+                    second: b.id.clone(),
+                    resource: resource.clone(),
+                });
+            }
+        }
+    }
+    conflicts
+}
+
+macro_rules! named_resources {
+    ($($ty:ty),* $(,)?) => {
+        vec![$((ResourceId::new::<$ty>(), std::any::type_name::<$ty>())),*]
+    };
+}
+
+/// The name of the component storage or resource. Resources that are not components are only
+/// known by name if they are listed here.
+pub fn resource_name(id: &ResourceId) -> String {
+    if let Some(comp) = super::meta::ALL_COMPONENTS
+        .iter()
+        .find(|comp| (comp.storage_id)() == *id)
+    {
+        return comp.name.to_string();
+    }
+
+    let known = named_resources!(
+        specs::world::EntitiesRes,
+        specs::LazyUpdate,
+        crate::time::Time,
+        crate::settings::KeyBindings,
+        crate::game_state::GameState,
+        crate::play_mode::PlayMode,
+        crate::cvar::Cvars,
+        crate::io::input::CurrentFrameExternalInputs,
+        crate::camera::ActiveCameraController,
+        crate::camera::bookmarks::CameraBookmarks,
+        crate::render::debug_window::RenderSettings,
+    );
+    known
+        .into_iter()
+        .find(|(known, _)| known == id)
+        .map(|(_, name)| name.rsplit("::").next().unwrap_or(name).to_string())
+        .unwrap_or_else(|| String::from("(unnamed resource)"))
+}
+
+fn resource_list(ids: &[ResourceId]) -> String {
+    let names: Vec<String> = ids.iter().map(resource_name).collect();
+    names.join(", ")
+}
+
+pub(crate) fn build_ui<'a>(
+    world: &mut World,
+    ui: &crate::render::ui::UiFrame<'a>,
+    pos: [f32; 2],
+) -> [f32; 2] {
+    let size = [400.0, 300.0];
+
+    imgui::Window::new(imgui::im_str!("Systems"))
+        .position(pos, imgui::Condition::FirstUseEver)
+        .size(size, imgui::Condition::FirstUseEver)
+        .collapsed(true, imgui::Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let ui = ui.inner();
+            let graph = world.read_resource::<SystemGraph>();
+            for executor in graph.executors.iter() {
+                let header =
+                    imgui::im_str!("{} ({} systems)", executor.name, executor.systems.len());
+                if !imgui::CollapsingHeader::new(&header).build(ui) {
+                    continue;
+                }
+
+                for system in executor.systems.iter() {
+                    let label =
+                        imgui::im_str!("{} (stage {})##{}", system.id, system.stage, executor.name);
+                    imgui::TreeNode::new(&label).build(ui, || {
+                        if !system.deps.is_empty() {
+                            ui.text_wrapped(&imgui::im_str!("After: {}", system.deps.join(", ")));
+                        }
+                        ui.text_wrapped(&imgui::im_str!("Reads: {}", resource_list(&system.reads)));
+                        ui.text_wrapped(&imgui::im_str!(
+                            "Writes: {}",
+                            resource_list(&system.writes)
+                        ));
+                    });
+                }
+
+                let conflicts = unordered_conflicts(&executor.systems);
+                if !conflicts.is_empty() {
+                    ui.text_colored(
+                        [1.0, 0.8, 0.3, 1.0],
+                        imgui::im_str!("Only ordered by when they were added:"),
+                    );
+                    for c in conflicts {
+                        ui.text_wrapped(&imgui::im_str!(
+                            "{} -> {} ({})",
+                            c.first,
+                            c.second,
+                            resource_name(&c.resource)
+                        ));
+                    }
+                }
+            }
+        });
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct A;
+    #[derive(Default)]
+    struct B;
+
+    struct WritesA;
+    impl<'a> System<'a> for WritesA {
+        type SystemData = Write<'a, A>;
+        fn run(&mut self, _: Self::SystemData) {}
+    }
+
+    struct ReadsA;
+    impl<'a> System<'a> for ReadsA {
+        type SystemData = (Read<'a, A>, Write<'a, B>);
+        fn run(&mut self, _: Self::SystemData) {}
+    }
+
+    #[test]
+    fn unordered_writes_are_found() {
+        let executor = ExecutorBuilder::new()
+            .with(WritesA, "writer", &[])
+            .with(ReadsA, "reader", &[])
+            .build();
+        let conflicts = unordered_conflicts(executor.systems());
+        assert_eq!(
+            conflicts,
+            vec![UnorderedConflict {
+                first: String::from("writer"),
+                second: String::from("reader"),
+                resource: ResourceId::new::<A>(),
+            }]
+        );
+    }
+
+    #[test]
+    fn order_config_adds_dependencies_and_barriers() {
+        let config: OrderConfig = ron::de::from_str(
+            r#"(dependencies: { "reader": ["writer", "unknown"] }, barriers_before: ["last"])"#,
+        )
+        .unwrap();
+        let executor = ExecutorBuilder::new()
+            .with(WritesA, "writer", &[])
+            .with(ReadsA, "reader", &[])
+            .with(WritesA, "last", &[])
+            .with_order_config(config)
+            .build();
+
+        let systems = executor.systems();
+        assert_eq!(systems[1].deps, vec![String::from("writer")]);
+        assert_eq!(systems[1].stage, 0);
+        assert_eq!(systems[2].stage, 1);
+        assert!(unordered_conflicts(systems).is_empty());
+    }
+}
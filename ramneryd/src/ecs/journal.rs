@@ -0,0 +1,247 @@
+//! A log of when entities are created and deleted and when components are added to and removed from
+//! them, to debug systems that spawn or despawn the wrong entities. It is off by default, and is
+//! enabled in the Entity journal window, where the events can also be written to a file.
+//!
+//! The world is compared with the previous frame after it has been maintained, so an entity that is
+//! created and deleted within the same frame, or a component that is removed and added back, is not
+//! seen.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::meta::ALL_COMPONENTS;
+use super::prelude::*;
+
+use crate::render::ui::UiFrame;
+
+const DEFAULT_CAPACITY: usize = 4096;
+/// The window only lists the latest events, the rest are in the dump
+const MAX_SHOWN: usize = 200;
+const DUMP_PATH: &str = "entity_journal.txt";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalEventKind {
+    Created,
+    Deleted,
+    ComponentAdded(&'static str),
+    ComponentRemoved(&'static str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JournalEvent {
+    pub frame: u64,
+    /// Since the journal was created
+    pub time: Duration,
+    pub entity: Entity,
+    pub kind: JournalEventKind,
+}
+
+/// E.g. "frame 12 (3.250 s): 4v1 added Transform"
+impl fmt::Display for JournalEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "frame {} ({:.3} s): {}v{} ",
+            self.frame,
+            self.time.as_secs_f32(),
+            self.entity.id(),
+            self.entity.gen().id()
+        )?;
+        match self.kind {
+            JournalEventKind::Created => write!(f, "created"),
+            JournalEventKind::Deleted => write!(f, "deleted"),
+            JournalEventKind::ComponentAdded(name) => write!(f, "added {}", name),
+            JournalEventKind::ComponentRemoved(name) => write!(f, "removed {}", name),
+        }
+    }
+}
+
+/// The latest events, in the order they happened. The oldest are dropped when it is full.
+pub struct EntityJournal {
+    pub enabled: bool,
+    capacity: usize,
+    events: VecDeque<JournalEvent>,
+    /// Which of ALL_COMPONENTS each entity had when the journal last recorded. None while it is
+    /// disabled, so that enabling it does not report everything as created.
+    previous: Option<HashMap<Entity, Vec<bool>>>,
+    frame: u64,
+    start: Instant,
+}
+
+impl Default for EntityJournal {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl EntityJournal {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            enabled: false,
+            capacity,
+            events: VecDeque::with_capacity(capacity),
+            previous: None,
+            frame: 0,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = &JournalEvent> {
+        self.events.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    fn push(&mut self, entity: Entity, kind: JournalEventKind) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(JournalEvent {
+            frame: self.frame,
+            time: self.start.elapsed(),
+            entity,
+            kind,
+        });
+    }
+
+    /// Compares the entities and their components with the last time this was called
+    fn record(&mut self, world: &World) {
+        self.frame += 1;
+        if !self.enabled {
+            self.previous = None;
+            return;
+        }
+
+        let mut previous = self.previous.take();
+        let mut current = HashMap::new();
+        for ent in (&world.entities()).join() {
+            let has: Vec<bool> = ALL_COMPONENTS.iter().map(|c| (c.has)(world, ent)).collect();
+            if let Some(previous) = &mut previous {
+                let before = previous.remove(&ent);
+                if before.is_none() {
+                    self.push(ent, JournalEventKind::Created);
+                }
+                for (i, comp) in ALL_COMPONENTS.iter().enumerate() {
+                    let had = before.as_ref().map(|b| b[i]).unwrap_or(false);
+                    match (had, has[i]) {
+                        (false, true) => {
+                            self.push(ent, JournalEventKind::ComponentAdded(comp.name))
+                        }
+                        (true, false) => {
+                            self.push(ent, JournalEventKind::ComponentRemoved(comp.name))
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            current.insert(ent, has);
+        }
+
+        let mut deleted: Vec<Entity> = previous.iter().flat_map(|p| p.keys().copied()).collect();
+        deleted.sort();
+        for ent in deleted {
+            self.push(ent, JournalEventKind::Deleted);
+        }
+        self.previous = Some(current);
+    }
+
+    /// One event per line, oldest first
+    pub fn dump(&self, path: &Path) -> std::io::Result<()> {
+        let mut contents = String::new();
+        for event in self.events.iter() {
+            contents.push_str(&format!("{}\n", event));
+        }
+        std::fs::write(path, contents)
+    }
+}
+
+/// Called once per frame, after the world has been maintained
+pub fn record(world: &World) {
+    world.write_resource::<EntityJournal>().record(world);
+}
+
+pub(crate) fn build_ui<'a>(world: &mut World, ui: &UiFrame<'a>, pos: [f32; 2]) -> [f32; 2] {
+    let size = [400.0, 300.0];
+
+    imgui::Window::new(imgui::im_str!("Entity journal"))
+        .position(pos, imgui::Condition::FirstUseEver)
+        .size(size, imgui::Condition::FirstUseEver)
+        .collapsed(true, imgui::Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let ui = ui.inner();
+            let mut journal = world.write_resource::<EntityJournal>();
+            ui.checkbox(imgui::im_str!("Record"), &mut journal.enabled);
+            ui.same_line(0.0);
+            if ui.button(imgui::im_str!("Clear"), [0.0, 0.0]) {
+                journal.clear();
+            }
+            ui.same_line(0.0);
+            if ui.button(imgui::im_str!("Dump"), [0.0, 0.0]) {
+                match journal.dump(Path::new(DUMP_PATH)) {
+                    Ok(()) => log::info!("Wrote the entity journal to {}", DUMP_PATH),
+                    Err(e) => log::error!("Failed to write {}: {}", DUMP_PATH, e),
+                }
+            }
+            ui.text(imgui::im_str!(
+                "{} of at most {} events",
+                journal.events.len(),
+                journal.capacity
+            ));
+            ui.separator();
+
+            let skip = journal.events.len().saturating_sub(MAX_SHOWN);
+            for event in journal.events().skip(skip) {
+                ui.text(imgui::im_str!("{}", event));
+            }
+        });
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Name;
+
+    #[test]
+    fn lifecycle_events_are_recorded() {
+        let mut world = World::new();
+        super::super::meta::register_all_components(&mut world);
+        let existing = world.create_entity().build();
+        let mut journal = EntityJournal::with_capacity(16);
+        journal.enabled = true;
+        // Everything that exists when the journal is enabled is not reported
+        journal.record(&world);
+        assert_eq!(journal.events().count(), 0);
+
+        let ent = world.create_entity().with(Name::from("a")).build();
+        journal.record(&world);
+        world.write_storage::<Name>().remove(ent);
+        world.delete_entity(existing).unwrap();
+        world.maintain();
+        journal.record(&world);
+
+        let kinds: Vec<(u64, Entity, JournalEventKind)> = journal
+            .events()
+            .map(|e| (e.frame, e.entity, e.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (2, ent, JournalEventKind::Created),
+                (2, ent, JournalEventKind::ComponentAdded("Name")),
+                (3, ent, JournalEventKind::ComponentRemoved("Name")),
+                (3, existing, JournalEventKind::Deleted),
+            ]
+        );
+
+        for _ in 0..20 {
+            journal.push(ent, JournalEventKind::Created);
+        }
+        assert_eq!(journal.events().count(), 16);
+    }
+}
@@ -1,6 +1,9 @@
 pub type World = specs::World;
 pub use ramneryd_derive::Component;
 
+pub mod introspect;
+pub mod journal;
+
 pub mod prelude {
     pub use specs::prelude::ResourceId;
     pub use specs::SystemData;
@@ -144,6 +147,7 @@ where
 
 pub struct Executor<'a, 'b> {
     dispatcher: specs::Dispatcher<'a, 'b>,
+    systems: Vec<introspect::SystemInfo>,
 }
 
 impl<'a, 'b> Executor<'a, 'b> {
@@ -154,10 +158,31 @@ impl<'a, 'b> Executor<'a, 'b> {
     pub fn setup(&mut self, world: &mut specs::World) {
         self.dispatcher.setup(world);
     }
+
+    /// The systems in the order they were added, with their dependencies and resource accesses
+    pub fn systems(&self) -> &[introspect::SystemInfo] {
+        &self.systems
+    }
+}
+
+type AddSystem<'a, 'b> = Box<dyn FnOnce(&mut specs::DispatcherBuilder<'a, 'b>, &str, &[&str]) + 'a>;
+
+enum ExecutorEntry<'a, 'b> {
+    System {
+        id: String,
+        deps: Vec<String>,
+        reads: Vec<specs::prelude::ResourceId>,
+        writes: Vec<specs::prelude::ResourceId>,
+        add: AddSystem<'a, 'b>,
+    },
+    Barrier,
 }
 
+/// The systems are added to the dispatcher when the executor is built, so that the order can be
+/// adjusted by an introspect::OrderConfig first
 pub struct ExecutorBuilder<'a, 'b> {
-    builder: specs::DispatcherBuilder<'a, 'b>,
+    entries: Vec<ExecutorEntry<'a, 'b>>,
+    order_config: introspect::OrderConfig,
 }
 
 impl<'a, 'b> ExecutorBuilder<'a, 'b> {
@@ -165,24 +190,96 @@ impl<'a, 'b> ExecutorBuilder<'a, 'b> {
     where
         S: for<'c> System<'c> + Send + 'a + Sync,
     {
-        self.builder.add(SpecsSystem::new(s), id, deps);
+        use specs::SystemData as _;
+        self.entries.push(ExecutorEntry::System {
+            id: id.to_string(),
+            deps: deps.iter().map(|d| d.to_string()).collect(),
+            reads: <<S as System<'static>>::SystemData>::reads(),
+            writes: <<S as System<'static>>::SystemData>::writes(),
+            add: Box::new(
+                move |builder: &mut specs::DispatcherBuilder<'a, 'b>, id: &str, deps: &[&str]| {
+                    builder.add(SpecsSystem::new(s), id, deps)
+                },
+            ),
+        });
         self
     }
 
     pub fn build(self) -> Executor<'a, 'b> {
+        let config = self.order_config;
+        let mut builder = specs::DispatcherBuilder::new();
+        let mut systems: Vec<introspect::SystemInfo> = Vec::new();
+        let mut stage = 0;
+        let mut stage_is_empty = true;
+        for entry in self.entries {
+            let (id, mut deps, reads, writes, add) = match entry {
+                ExecutorEntry::Barrier => {
+                    builder.add_barrier();
+                    stage += 1;
+                    stage_is_empty = true;
+                    continue;
+                }
+                ExecutorEntry::System {
+                    id,
+                    deps,
+                    reads,
+                    writes,
+                    add,
+                } => (id, deps, reads, writes, add),
+            };
+
+            if config.barriers_before.contains(&id) && !stage_is_empty {
+                builder.add_barrier();
+                stage += 1;
+            }
+            for dep in config.dependencies.get(&id).into_iter().flatten() {
+                if deps.contains(dep) {
+                    continue;
+                }
+                if systems.iter().any(|s| &s.id == dep) {
+                    deps.push(dep.clone());
+                } else {
+                    log::warn!(
+                        "Ignoring the dependency of {} on {}, it has to be added before it",
+                        id,
+                        dep
+                    );
+                }
+            }
+
+            let dep_refs: Vec<&str> = deps.iter().map(|d| d.as_str()).collect();
+            add(&mut builder, &id, &dep_refs);
+            stage_is_empty = false;
+            systems.push(introspect::SystemInfo {
+                id,
+                deps,
+                reads,
+                writes,
+                stage,
+            });
+        }
+
         Executor {
-            dispatcher: self.builder.build(),
+            dispatcher: builder.build(),
+            systems,
         }
     }
 
     pub fn with_barrier(mut self) -> ExecutorBuilder<'a, 'b> {
-        self.builder.add_barrier();
+        self.entries.push(ExecutorEntry::Barrier);
+        self
+    }
+
+    /// Adjust the order of the systems when the executor is built
+    pub fn with_order_config(mut self, config: introspect::OrderConfig) -> Self {
+        self.order_config = config;
         self
     }
 
     pub fn new() -> Self {
         Self {
-            builder: specs::DispatcherBuilder::new(),
+            entries: Vec::new(),
+            order_config: introspect::OrderConfig::default(),
         }
     }
 }
@@ -193,6 +290,9 @@ pub mod meta {
     #[distributed_slice]
     pub static ALL_COMPONENTS: [Component] = [..];
 
+    /// Puts back the components that were copied by a snapshot
+    pub type Restore = Box<dyn FnOnce(&super::World) + Send + Sync>;
+
     pub struct Component {
         pub name: &'static str,
         pub size: usize,
@@ -202,6 +302,11 @@ pub mod meta {
         pub inspect: Option<
             fn(world: &mut super::World, ent: super::Entity, ui: &crate::render::ui::UiFrame<'_>),
         >,
+        /// The id of the storage of the component, which is what systems read and write
+        pub storage_id: fn() -> specs::prelude::ResourceId,
+        /// Copies the component of every entity, for components with `#[component(snapshot)]`, see
+        /// crate::play_mode
+        pub snapshot: Option<fn(world: &super::World) -> Restore>,
     }
 
     pub fn register_all_components(world: &mut super::World) {
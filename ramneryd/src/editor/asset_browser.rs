@@ -0,0 +1,190 @@
+use specs::prelude::*;
+
+use imgui::*;
+
+use std::path::{Path, PathBuf};
+
+use crate::asset::ContentDirectory;
+use crate::render::thumbnail::{self, Thumbnails, THUMBNAIL_SIZE};
+use crate::render::ui::UiFrame;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AssetKind {
+    Gltf,
+    Rsf,
+    Mesh,
+    Texture,
+}
+
+impl AssetKind {
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gltf") | Some("glb") => Some(AssetKind::Gltf),
+            Some("rsf") => Some(AssetKind::Rsf),
+            Some("obj") | Some("ply") => Some(AssetKind::Mesh),
+            _ if thumbnail::is_image(path) => Some(AssetKind::Texture),
+            _ => None,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            AssetKind::Gltf => "glTF",
+            AssetKind::Rsf => "rsf",
+            AssetKind::Mesh => "mesh",
+            AssetKind::Texture => "texture",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct AssetEntry {
+    path: PathBuf,
+    kind: AssetKind,
+}
+
+fn scan(dir: &Path, entries: &mut Vec<AssetEntry>) {
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) => {
+            log::error!("Failed to read {}: {}", dir.display(), e);
+            return;
+        }
+    };
+
+    for dir_entry in read_dir.filter_map(Result::ok) {
+        let path = dir_entry.path();
+        if path.is_dir() {
+            scan(&path, entries);
+        } else if let Some(kind) = AssetKind::from_path(&path) {
+            entries.push(AssetEntry { path, kind });
+        }
+    }
+}
+
+fn instantiate(world: &mut World, entry: &AssetEntry) {
+    match entry.kind {
+        AssetKind::Gltf => crate::asset::gltf::load_asset(world, &entry.path),
+        AssetKind::Rsf => crate::asset::rsf::load_asset(world, &entry.path),
+        AssetKind::Mesh => crate::asset::mesh_file::load_asset(world, &entry.path),
+        AssetKind::Texture => log::warn!(
+            "Can't instantiate a texture on its own: {}",
+            entry.path.display()
+        ),
+    }
+}
+
+#[derive(Default)]
+struct AssetBrowserState {
+    /// The directory that the entries were scanned from
+    scanned: Option<PathBuf>,
+    entries: Vec<AssetEntry>,
+    dragged: Option<AssetEntry>,
+}
+
+/// Lists the assets in the content directory. Dragging an entry and dropping it outside of the ui
+/// windows instantiates it in the scene.
+pub(crate) fn build_ui<'a>(world: &mut World, ui: &UiFrame<'a>, pos: [f32; 2]) -> [f32; 2] {
+    let size = [400.0, 300.0];
+
+    Window::new(im_str!("Asset browser"))
+        .position(pos, Condition::FirstUseEver)
+        .size(size, Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let content_dir = match world.try_fetch::<ContentDirectory>() {
+                Some(dir) => dir.0.clone(),
+                None => {
+                    ui.inner().text(im_str!("No content directory"));
+                    return;
+                }
+            };
+
+            let id = String::from("AssetBrowser");
+            let mut storage = ui.storage();
+            if !storage.contains_key(&id) {
+                storage.insert(id.clone(), AssetBrowserState::default());
+            }
+            let state: &mut AssetBrowserState = storage
+                .get_mut(&id)
+                .expect("Asset browser state was just inserted");
+
+            ui.inner().text(im_str!("{}", content_dir.display()));
+            let rescan = ui.inner().button(im_str!("Rescan"), [0.0, 0.0]);
+            if rescan || state.scanned.as_ref() != Some(&content_dir) {
+                state.entries.clear();
+                scan(&content_dir, &mut state.entries);
+                state.entries.sort_by(|a, b| a.path.cmp(&b.path));
+                state.scanned = Some(content_dir.clone());
+            }
+            ui.inner().separator();
+
+            let mut thumbnails = world.write_resource::<Thumbnails>();
+            let button_size = [THUMBNAIL_SIZE as f32, THUMBNAIL_SIZE as f32];
+            for (i, entry) in state.entries.iter().enumerate() {
+                let id_token = ui.inner().push_id(i as i32);
+                match thumbnails.get(&entry.path) {
+                    Some(texture_id) => {
+                        ImageButton::new(texture_id, button_size).build(ui.inner());
+                    }
+                    None => {
+                        ui.inner()
+                            .button(&im_str!("{}", entry.kind.label()), button_size);
+                    }
+                }
+
+                if state.dragged.is_none()
+                    && ui.inner().is_item_active()
+                    && ui.inner().is_mouse_dragging(MouseButton::Left)
+                {
+                    state.dragged = Some(entry.clone());
+                }
+
+                ui.inner().same_line(0.0);
+                let name = entry.path.strip_prefix(&content_dir).unwrap_or(&entry.path);
+                ui.inner().text(im_str!("{}", name.display()));
+                id_token.pop(ui.inner());
+            }
+            drop(thumbnails);
+
+            let mut dropped = None;
+            if let Some(dragged) = &state.dragged {
+                ui.inner()
+                    .tooltip_text(format!("{}", dragged.path.display()));
+                if ui.inner().is_mouse_released(MouseButton::Left) {
+                    if !ui
+                        .inner()
+                        .is_window_hovered_with_flags(WindowHoveredFlags::ANY_WINDOW)
+                    {
+                        dropped = state.dragged.take();
+                    } else {
+                        state.dragged = None;
+                    }
+                }
+            }
+            drop(storage);
+
+            if let Some(entry) = dropped {
+                log::info!("Instantiating {}", entry.path.display());
+                instantiate(world, &entry);
+            }
+        });
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asset_kind() {
+        let kind = |p: &str| AssetKind::from_path(Path::new(p));
+        assert_eq!(kind("a/b.gltf"), Some(AssetKind::Gltf));
+        assert_eq!(kind("b.glb"), Some(AssetKind::Gltf));
+        assert_eq!(kind("c.rsf"), Some(AssetKind::Rsf));
+        assert_eq!(kind("scan.ply"), Some(AssetKind::Mesh));
+        assert_eq!(kind("d.png"), Some(AssetKind::Texture));
+        assert_eq!(kind("e.bin"), None);
+        assert_eq!(kind("f"), None);
+    }
+}
@@ -65,6 +65,7 @@ macro_rules! impl_inspect_vec {
     };
 }
 
+impl_inspect_vec!(Vec2, InputFloat2, 2);
 impl_inspect_vec!(Vec3, InputFloat3, 3);
 impl_inspect_vec!(Rgb, InputFloat3, 3);
 impl_inspect_vec!(Vec4, InputFloat4, 4);
@@ -162,6 +163,30 @@ impl<T: Inspect> Inspect for Vec<T> {
     }
 }
 
+impl<T: Inspect> Inspect for std::collections::BTreeMap<String, T> {
+    fn inspect<'a>(&self, ui: &Ui<'a>, name: &str) {
+        if !name.is_empty() {
+            ui.inner().text(im_str!("{}:", name));
+        }
+        ui.inner().indent();
+        for (k, v) in self.iter() {
+            v.inspect(ui, k);
+        }
+        ui.inner().unindent();
+    }
+
+    fn inspect_mut<'a>(&mut self, ui: &Ui<'a>, name: &str) {
+        if !name.is_empty() {
+            ui.inner().text(im_str!("{}:", name));
+        }
+        ui.inner().indent();
+        for (k, v) in self.iter_mut() {
+            v.inspect_mut(ui, k);
+        }
+        ui.inner().unindent();
+    }
+}
+
 impl Inspect for String {
     fn inspect<'a>(&self, ui: &Ui<'a>, name: &str) {
         ui.inner().text(im_str!("{}: {}", name, &self));
@@ -336,6 +361,38 @@ impl Inspect for trekanten::pipeline::PolygonMode {
     }
 }
 
+impl Inspect for crate::render::light::LightIntensity {
+    fn inspect<'a>(&self, ui: &Ui<'a>, name: &str) {
+        use crate::render::light::LightIntensity;
+        match self {
+            LightIntensity::Lumens(v) => ui.inner().text(&im_str!("{}: {} lm", name, v)),
+            LightIntensity::Candela(v) => ui.inner().text(&im_str!("{}: {} cd", name, v)),
+        }
+    }
+
+    fn inspect_mut<'a>(&mut self, ui: &Ui<'a>, name: &str) {
+        use crate::render::light::LightIntensity;
+        let (mut v, mut unit) = match *self {
+            LightIntensity::Lumens(v) => (v, 0),
+            LightIntensity::Candela(v) => (v, 1),
+        };
+
+        let token = ui.inner().push_id(name);
+        imgui::InputFloat::new(ui.inner(), &im_str!("{}", name), &mut v).build();
+        imgui::ComboBox::new(im_str!("unit")).build_simple_string(
+            ui.inner(),
+            &mut unit,
+            &[im_str!("lumens"), im_str!("candela")],
+        );
+        token.pop(ui.inner());
+
+        *self = match unit {
+            0 => LightIntensity::Lumens(v),
+            _ => LightIntensity::Candela(v),
+        };
+    }
+}
+
 // TODO: A generic impl_inspect_display here
 impl Inspect for usize {
     fn inspect<'a>(&self, ui: &Ui<'a>, name: &str) {
@@ -0,0 +1,190 @@
+use specs::prelude::*;
+
+use imgui::*;
+
+use crate::editor::Inspect as _;
+use crate::render::material::{self, MaterialId, MaterialLibrary, UsesMaterial};
+use crate::render::mesh::{CpuMesh, GpuMesh};
+use crate::render::thumbnail::{Thumbnails, THUMBNAIL_SIZE};
+use crate::render::ui::UiFrame;
+
+const STATE_ID: &str = "MaterialLibrary";
+
+#[derive(Default)]
+struct MaterialLibraryState {
+    selected: Option<MaterialId>,
+    dragged: Option<MaterialId>,
+    name: ImString,
+}
+
+fn preview<'a>(
+    ui: &UiFrame<'a>,
+    thumbnails: &mut Thumbnails,
+    id: MaterialId,
+    entry: &material::LibraryMaterial,
+) {
+    let size = [THUMBNAIL_SIZE as f32 / 2.0, THUMBNAIL_SIZE as f32 / 2.0];
+    // The color is shown until the thumbnail has been rendered
+    let thumbnail = thumbnails.material(id, &entry.material);
+    match thumbnail {
+        Some(texture_id) => {
+            ImageButton::new(texture_id, size).build(ui.inner());
+        }
+        None => {
+            let color = entry.material.base_color_factor.into_array();
+            ColorButton::new(im_str!("##preview"), color)
+                .size(size)
+                .build(ui.inner());
+        }
+    }
+}
+
+/// Lists the materials in the MaterialLibrary. Dragging a material onto an entity in the scene
+/// window or in the viewport assigns it to the entity, see drop_material.
+pub(crate) fn build_ui<'a>(world: &mut World, ui: &UiFrame<'a>, pos: [f32; 2]) -> [f32; 2] {
+    let size = [400.0, 300.0];
+
+    Window::new(im_str!("Material library"))
+        .position(pos, Condition::FirstUseEver)
+        .size(size, Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let mut storage = ui.storage();
+            let id = String::from(STATE_ID);
+            if !storage.contains_key(&id) {
+                storage.insert(id.clone(), MaterialLibraryState::default());
+            }
+            let state: &mut MaterialLibraryState = storage
+                .get_mut(&id)
+                .expect("Material library state was just inserted");
+
+            let mut library = world.write_resource::<MaterialLibrary>();
+            let mut thumbnails = world.write_resource::<Thumbnails>();
+            let mut select = None;
+            for (i, (mat_id, entry)) in library.iter().enumerate() {
+                let id_token = ui.inner().push_id(i as i32);
+                preview(ui, &mut thumbnails, mat_id, entry);
+                ui.inner().same_line(0.0);
+                let is_selected = state.selected == Some(mat_id);
+                if Selectable::new(&im_str!("{}", entry.name))
+                    .selected(is_selected)
+                    .build(ui.inner())
+                {
+                    select = Some(mat_id);
+                }
+
+                if state.dragged.is_none()
+                    && ui.inner().is_item_active()
+                    && ui.inner().is_mouse_dragging(MouseButton::Left)
+                {
+                    state.dragged = Some(mat_id);
+                }
+                id_token.pop(ui.inner());
+            }
+            drop(thumbnails);
+
+            if let Some(mat_id) = select {
+                state.selected = Some(mat_id);
+                let name = library.get(mat_id).map(|e| e.name.as_str()).unwrap_or("");
+                state.name = ImString::new(name);
+            }
+
+            let selected = match state.selected {
+                Some(selected) => selected,
+                None => return,
+            };
+            ui.inner().separator();
+
+            if ui
+                .inner()
+                .input_text(im_str!("Name"), &mut state.name)
+                .resize_buffer(true)
+                .build()
+            {
+                if let Some(entry) = library.get_mut(selected) {
+                    entry.name = state.name.to_string();
+                }
+            }
+
+            if ui.inner().button(im_str!("Duplicate"), [0.0, 0.0]) {
+                if let Some(copy) = library.duplicate(selected) {
+                    state.selected = Some(copy);
+                    let name = library.get(copy).map(|e| e.name.as_str()).unwrap_or("");
+                    state.name = ImString::new(name);
+                }
+            }
+            ui.inner().same_line(0.0);
+            let apply = ui.inner().button(im_str!("Apply to entities"), [0.0, 0.0]);
+
+            if let Some(entry) = library.get_mut(state.selected.unwrap_or(selected)) {
+                entry.material.inspect_mut(ui, "material");
+            }
+            drop(library);
+            drop(storage);
+
+            // Edits are only applied to the entities when asked to, as all of their gpu resources
+            // have to be recreated
+            if apply {
+                let users: Vec<Entity> = (&world.entities(), &world.read_storage::<UsesMaterial>())
+                    .join()
+                    .filter(|(_, uses)| uses.0 == selected)
+                    .map(|(ent, _)| ent)
+                    .collect();
+                for ent in users {
+                    material::assign(world, ent, selected);
+                }
+            }
+        });
+
+    size
+}
+
+/// Assigns the material that is being dragged from the library when it is released over an entity,
+/// either `hovered` in the scene window or the one under the cursor in the viewport.
+pub(crate) fn drop_material<'a>(world: &mut World, ui: &UiFrame<'a>, hovered: Option<Entity>) {
+    let dragged = {
+        let mut storage = ui.storage();
+        let state: &mut MaterialLibraryState = match storage.get_mut(&String::from(STATE_ID)) {
+            Some(state) => state,
+            None => return,
+        };
+        let dragged = match state.dragged {
+            Some(dragged) => dragged,
+            None => return,
+        };
+
+        if let Some(entry) = world.read_resource::<MaterialLibrary>().get(dragged) {
+            ui.inner().tooltip_text(&entry.name);
+        }
+        if !ui.inner().is_mouse_released(MouseButton::Left) {
+            return;
+        }
+        state.dragged = None;
+        dragged
+    };
+
+    let io = ui.inner().io();
+    let target = if hovered.is_some() {
+        hovered
+    } else if !ui
+        .inner()
+        .is_window_hovered_with_flags(WindowHoveredFlags::ANY_WINDOW)
+    {
+        super::picking::pick(world, io.mouse_pos, io.display_size)
+    } else {
+        None
+    };
+
+    let target = match target {
+        Some(target) => target,
+        None => return,
+    };
+    let has_mesh = world.read_storage::<CpuMesh>().contains(target)
+        || world.read_storage::<GpuMesh>().contains(target);
+    if !has_mesh {
+        log::warn!("Can't assign a material to {:?}, it has no mesh", target);
+        return;
+    }
+
+    log::info!("Assigning material {:?} to {:?}", dragged, target);
+    material::assign(world, target, dragged);
+}
@@ -3,39 +3,92 @@ use specs::prelude::*;
 use crate::common::Name;
 use crate::ecs;
 use crate::graph;
+use crate::render::Hidden;
 use imgui::*;
 
+mod asset_browser;
 pub(crate) mod inspect;
+mod material_library;
+mod picking;
+mod primitives;
+mod selection;
+mod texture_swap;
 pub use inspect::Inspect;
 
+pub(crate) use selection::Selection;
+
 fn name(world: &World, ent: Entity) -> String {
     let names = world.read_component::<Name>();
     let name: &str = names.get(ent).map(|n| n.0.as_str()).unwrap_or("");
     format!("{} ({}, {})", name, ent.id(), ent.gen().id())
 }
 
+/// What was done with the nodes in the scene window this frame
+#[derive(Default)]
+struct TreeEvents {
+    /// The entity whose node is under the cursor
+    hovered: Option<Entity>,
+    /// The entity whose node started to be dragged
+    dragged: Option<Entity>,
+    /// The entity whose node was clicked
+    clicked: Option<Entity>,
+}
+
+impl TreeEvents {
+    /// Checks the last item, the tree node of ent
+    fn track_node<'a>(&mut self, ui: &crate::render::ui::UiFrame<'a>, ent: Entity) {
+        // The other nodes are blocked by the active item while one is being dragged
+        if ui
+            .inner()
+            .is_item_hovered_with_flags(ItemHoveredFlags::ALLOW_WHEN_BLOCKED_BY_ACTIVE_ITEM)
+        {
+            self.hovered = Some(ent);
+        }
+        if ui.inner().is_item_clicked(MouseButton::Left) {
+            self.clicked = Some(ent);
+        }
+        if ui.inner().is_item_active() && ui.inner().is_mouse_dragging(MouseButton::Left) {
+            self.dragged = Some(ent);
+        }
+    }
+}
+
+/// The node of the entity, with a checkbox that hides or shows it and everything below it
 fn build_tree<'a>(
     world: &World,
     ui: &crate::render::ui::UiFrame<'a>,
     ent: specs::Entity,
-) -> Option<specs::Entity> {
-    let mut inspected = None;
+    events: &mut TreeEvents,
+) {
+    let id_token = ui.inner().push_id(ent.id() as i32);
+    let mut visible = !world.read_storage::<Hidden>().contains(ent);
+    if ui.inner().checkbox(im_str!("##visible"), &mut visible) {
+        selection::toggle_hidden(world, &[ent]);
+    }
+    id_token.pop(ui.inner());
+    ui.inner().same_line(0.0);
 
     let name = im_str!("{}", name(world, ent));
-    TreeNode::new(&name).build(ui.inner(), || {
-        let pressed = ui.inner().small_button(im_str!("inspect"));
-        if pressed {
-            inspected = Some(ent);
-        }
-        if let Some(children) = world.read_component::<graph::Children>().get(ent) {
-            for child in children.iter() {
-                let new = build_tree(world, ui, *child);
-                inspected = inspected.or(new);
+    let is_selected = world.read_resource::<Selection>().contains(ent);
+    let is_leaf = !world.read_storage::<graph::Children>().contains(ent);
+    let mut is_open = false;
+    TreeNode::new(&name)
+        .selected(is_selected)
+        .leaf(is_leaf)
+        .open_on_arrow(true)
+        .build(ui.inner(), || {
+            is_open = true;
+            events.track_node(ui, ent);
+            if let Some(children) = world.read_component::<graph::Children>().get(ent) {
+                for child in children.iter() {
+                    build_tree(world, ui, *child, events);
+                }
             }
-        }
-    });
-
-    inspected
+        });
+    // The node is the last item only if it is closed
+    if !is_open {
+        events.track_node(ui, ent);
+    }
 }
 
 fn build_inspector<'a>(world: &mut World, ui: &crate::render::ui::UiFrame<'a>, ent: specs::Entity) {
@@ -50,6 +103,7 @@ fn build_inspector<'a>(world: &mut World, ui: &crate::render::ui::UiFrame<'a>, e
             .insert(ent, ReloadMaterial {})
             .expect("Failed to write!");
     }
+    texture_swap::build_ui(world, ui, ent);
     ui.inner().separator();
 
     for comp in ecs::meta::ALL_COMPONENTS {
@@ -69,17 +123,195 @@ fn build_inspector<'a>(world: &mut World, ui: &crate::render::ui::UiFrame<'a>, e
     }
 }
 
-struct SelectedEntity {
-    entity: specs::Entity,
+fn build_frame_stats<'a>(frame: &UiFrame<'a>, stats: &trekanten::FrameStats) {
+    let ms = |d: std::time::Duration| d.as_secs_f64() * 1000.0;
+    let latest = stats.latest().copied().unwrap_or_default();
+    let avg = stats.average();
+    let max = stats.max();
+    let ui = frame.inner();
+    ui.text(im_str!("Frames in flight: {}", stats.frames_in_flight()));
+    ui.text(im_str!("(ms)               latest / avg / max"));
+    let rows = [
+        (
+            "Fence wait",
+            latest.fence_wait,
+            avg.fence_wait,
+            max.fence_wait,
+        ),
+        ("Acquire", latest.acquire, avg.acquire, max.acquire),
+        (
+            "Image in use wait",
+            latest.image_in_use_wait,
+            avg.image_in_use_wait,
+            max.image_in_use_wait,
+        ),
+        (
+            "next_frame",
+            latest.next_frame,
+            avg.next_frame,
+            max.next_frame,
+        ),
+        (
+            "Acquire -> present",
+            latest.acquire_to_present,
+            avg.acquire_to_present,
+            max.acquire_to_present,
+        ),
+    ];
+    for (label, l, a, m) in rows.iter() {
+        ui.text(im_str!(
+            "{:<18} {:.3} / {:.3} / {:.3}",
+            label,
+            ms(*l),
+            ms(*a),
+            ms(*m)
+        ));
+    }
+
+    let gpu_timings = stats.gpu_timings();
+    if !gpu_timings.is_empty() {
+        ui.text(im_str!(
+            "Gpu (ms), {} frames old:",
+            stats.frames_in_flight()
+        ));
+        for timing in gpu_timings {
+            ui.text(im_str!("  {:<18} {:.3}", timing.name, ms(timing.duration)));
+        }
+        let total: std::time::Duration = gpu_timings.iter().map(|t| t.duration).sum();
+        ui.text(im_str!("  {:<18} {:.3}", "Total", ms(total)));
+    }
 }
 
+fn build_resource_counts<'a>(frame: &UiFrame<'a>, counts: &trekanten::ResourceCounts) {
+    let ui = frame.inner();
+    ui.text(im_str!("Gpu resources:"));
+    let rows = [
+        ("Textures", counts.textures),
+        ("Vertex buffers", counts.vertex_buffers),
+        ("Index buffers", counts.index_buffers),
+        ("Uniform buffers", counts.uniform_buffers),
+        ("Storage buffers", counts.storage_buffers),
+        ("Graphics pipelines", counts.graphics_pipelines),
+        ("Descriptor sets", counts.descriptor_sets),
+        ("  transient", counts.transient_descriptor_sets),
+        ("Descriptor pools", counts.descriptor_pools),
+        ("Pending destruction", counts.pending_destruction),
+    ];
+    for (label, count) in rows.iter() {
+        ui.text(im_str!("  {:<20} {}", label, count));
+    }
+}
+
+/// The entity that was last selected, if it is still alive
+pub(crate) fn selected_entity(world: &World) -> Option<Entity> {
+    world
+        .try_fetch::<Selection>()
+        .and_then(|selection| selection.primary())
+        .filter(|ent| world.is_alive(*ent))
+}
+
+/// Dragging less than this many pixels is a click
+const BOX_SELECT_MIN_DRAG: f32 = 4.0;
+
 #[derive(Default)]
-pub struct EditorUiModule {}
+pub struct EditorUiModule {
+    /// Where the left mouse button was pressed in the scene, outside of the ui windows
+    box_select_start: Option<[f32; 2]>,
+    /// The entity whose node is being dragged in the scene window, to give it another parent
+    dragged_node: Option<Entity>,
+}
+
+impl EditorUiModule {
+    /// Clicking in the scene selects the entity under the cursor and dragging selects the entities
+    /// inside the rectangle. With ctrl, the selection is added to instead.
+    fn select_in_viewport<'a>(&mut self, world: &World, frame: &UiFrame<'a>) {
+        let ui = frame.inner();
+        let io = ui.io();
+        if !io.want_capture_mouse && ui.is_mouse_clicked(MouseButton::Left) {
+            self.box_select_start = Some(io.mouse_pos);
+        }
+
+        let start = match self.box_select_start {
+            Some(start) => start,
+            None => return,
+        };
+        let end = io.mouse_pos;
+        let min = [start[0].min(end[0]), start[1].min(end[1])];
+        let max = [start[0].max(end[0]), start[1].max(end[1])];
+        let is_box = max[0] - min[0] > BOX_SELECT_MIN_DRAG || max[1] - min[1] > BOX_SELECT_MIN_DRAG;
+
+        if !ui.is_mouse_released(MouseButton::Left) {
+            if is_box {
+                let draw_list = ui.get_foreground_draw_list();
+                draw_list
+                    .add_rect(min, max, [0.3, 0.6, 1.0, 0.2])
+                    .filled(true)
+                    .build();
+                draw_list.add_rect(min, max, [0.3, 0.6, 1.0, 1.0]).build();
+            }
+            return;
+        }
+        self.box_select_start = None;
+
+        let add = io.key_ctrl;
+        let mut selection = world.write_resource::<Selection>();
+        if is_box {
+            let inside = picking::pick_rect(world, min, max, io.display_size);
+            if !add {
+                selection.clear();
+            }
+            selection.extend(inside);
+        } else {
+            match picking::pick(world, end, io.display_size) {
+                Some(ent) => selection.click(ent, add),
+                None if !add => selection.clear(),
+                None => (),
+            }
+        }
+    }
+
+    /// Gives the entity whose node is being dragged in the scene window the one it is released
+    /// over as parent, or makes it a root if it is released outside of the nodes
+    fn drop_node<'a>(
+        &mut self,
+        world: &mut World,
+        frame: &UiFrame<'a>,
+        hovered: Option<Entity>,
+        scene_window_hovered: bool,
+    ) {
+        let dragged = match self.dragged_node {
+            Some(dragged) if world.is_alive(dragged) => dragged,
+            _ => {
+                self.dragged_node = None;
+                return;
+            }
+        };
+
+        frame.inner().tooltip_text(name(world, dragged));
+        if !frame.inner().is_mouse_released(MouseButton::Left) {
+            return;
+        }
+        self.dragged_node = None;
+
+        let parent = match hovered {
+            Some(target) if target == dragged => return,
+            Some(target) => Some(target),
+            None if scene_window_hovered => None,
+            None => return,
+        };
+        if !graph::world::reparent(world, dragged, parent) {
+            log::warn!("Can't attach {} below itself", name(world, dragged));
+        }
+    }
+}
 
 use crate::render::ui::{UIModule, UiFrame};
 
 impl UIModule for EditorUiModule {
     fn draw(&mut self, world: &mut World, frame: &UiFrame) {
+        if !world.has_value::<Selection>() {
+            world.insert(Selection::default());
+        }
         let dt = world.read_resource::<crate::time::Time>().delta_sim();
         let size = [400.0, 300.0];
         let pos = [0.0, 0.0];
@@ -88,6 +320,12 @@ impl UIModule for EditorUiModule {
             .position(pos, imgui::Condition::FirstUseEver)
             .build(frame.inner(), || {
                 frame.inner().text(im_str!("FPS: {:.3}", dt.as_fps()));
+                if let Some(stats) = world.try_fetch::<trekanten::FrameStats>() {
+                    build_frame_stats(frame, &stats);
+                }
+                if let Some(counts) = world.try_fetch::<trekanten::ResourceCounts>() {
+                    build_resource_counts(frame, &counts);
+                }
                 let mut p = crate::render::camera_pos(world).into_array();
 
                 InputFloat3::new(frame.inner(), im_str!("Camera pos"), &mut p)
@@ -106,8 +344,30 @@ impl UIModule for EditorUiModule {
             let mut y_offset = 0.0;
             let funcs = [
                 crate::render::debug_window::build_ui,
+                crate::render::view_mode::build_ui,
+                crate::render::post_effects::build_ui,
+                crate::asset::gltf::build_ui,
+                crate::asset::server::build_ui,
+                crate::scene::build_ui,
+                asset_browser::build_ui,
+                crate::asset::commands::build_ui,
+                crate::asset::prefab::build_ui,
+                crate::asset::template::build_ui,
+                primitives::build_ui,
+                crate::render::light_commands::build_ui,
+                material_library::build_ui,
+                selection::build_ui,
+                crate::render::review::build_ui,
+                crate::render::shadow_bake::build_ui,
+                crate::camera::bookmarks::build_ui,
                 crate::game_state::build_ui,
+                crate::play_mode::build_ui,
                 crate::io::input::build_ui,
+                crate::settings::build_ui,
+                crate::cvar::build_ui,
+                crate::ecs::introspect::build_ui,
+                crate::ecs::journal::build_ui,
+                crate::warnings::build_ui,
             ];
             for func in funcs.iter() {
                 let size = func(world, frame, [0.0, y_offset]);
@@ -119,7 +379,8 @@ impl UIModule for EditorUiModule {
         let scene_window_size = [300.0, 500.0];
         let scene_window_pos = [width - scene_window_size[0], 0.0];
 
-        let mut inspected: Option<specs::Entity> = None;
+        let mut events = TreeEvents::default();
+        let mut scene_window_hovered = false;
 
         {
             let parent_storage = world.read_storage::<graph::Parent>();
@@ -130,29 +391,46 @@ impl UIModule for EditorUiModule {
                 .size(scene_window_size, Condition::Always)
                 .build(frame.inner(), || {
                     for (ent, _root) in (&entities, !&parent_storage).join() {
-                        inspected = inspected.or_else(|| build_tree(world, frame, ent));
+                        build_tree(world, frame, ent, &mut events);
                     }
+                    scene_window_hovered = frame.inner().is_window_hovered_with_flags(
+                        WindowHoveredFlags::ALLOW_WHEN_BLOCKED_BY_ACTIVE_ITEM,
+                    );
                 });
+        }
+        let TreeEvents {
+            hovered,
+            dragged,
+            clicked,
+        } = events;
+        if self.dragged_node.is_none() {
+            self.dragged_node = dragged;
+        }
+        self.drop_node(world, frame, hovered, scene_window_hovered);
 
-            if world.has_value::<SelectedEntity>() && inspected.is_none() {
-                inspected = Some(world.read_resource::<SelectedEntity>().entity);
-            }
+        if let Some(ent) = clicked {
+            let add = frame.inner().io().key_ctrl;
+            world.write_resource::<Selection>().click(ent, add);
+        } else {
+            self.select_in_viewport(world, frame);
         }
 
+        material_library::drop_material(world, frame, hovered);
+
         let inspected_window_size = [scene_window_size[0], 300.0];
         let inspected_window_pos = [scene_window_pos[0], scene_window_size[1]];
-        if let Some(ent) = inspected {
+        if let Some(ent) = selected_entity(world) {
             imgui::Window::new(im_str!("Inspector"))
                 .position(inspected_window_pos, Condition::FirstUseEver)
                 .size(inspected_window_size, Condition::FirstUseEver)
                 .build(frame.inner(), || {
                     build_inspector(world, frame, ent);
                 });
-            world.insert(SelectedEntity { entity: ent });
         }
     }
 }
 
-pub fn ui_module() -> Box<dyn UIModule> {
+pub fn ui_module(world: &mut World) -> Box<dyn UIModule> {
+    selection::register_cvars(world);
     Box::new(EditorUiModule::default())
 }
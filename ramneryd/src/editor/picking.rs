@@ -0,0 +1,144 @@
+use specs::prelude::*;
+
+use crate::math::{self, BoundingBox, Mat4, ModelMatrix, Ray, Vec3, Vec4};
+use crate::render::visibility::{self, Pass, RenderLayer, Visibility};
+use crate::render::Hidden;
+
+/// The ray through the cursor, in world space. Cursor and display size are in pixels with the
+/// origin in the top-left corner, the same as imgui.
+fn cursor_ray(cursor: [f32; 2], display_size: [f32; 2], view_proj: Mat4) -> Ray {
+    let inv_view_proj = view_proj.inverted();
+    let ndc = math::ndc_from_pixel(cursor, display_size);
+
+    // The far plane is very far away so use a point in between for better precision
+    let origin = math::unproject(&inv_view_proj, Vec3::new(ndc.x, ndc.y, 0.0));
+    let direction =
+        (math::unproject(&inv_view_proj, Vec3::new(ndc.x, ndc.y, 0.5)) - origin).normalized();
+    Ray { origin, direction }
+}
+
+/// The position of the world space point on the screen, in pixels. None if it is behind the camera.
+fn project(p: Vec3, display_size: [f32; 2], view_proj: Mat4) -> Option<[f32; 2]> {
+    math::project(&view_proj, p).map(|ndc| math::pixel_from_ndc(ndc.xy(), display_size))
+}
+
+fn view_proj(world: &World, display_size: [f32; 2]) -> Mat4 {
+    let aspect_ratio = display_size[0] / display_size[1];
+    let (view, _) = crate::render::get_view_data(world);
+    crate::render::get_proj_matrix(world, aspect_ratio) * view
+}
+
+/// The closest entity with a bounding box under the cursor
+pub(crate) fn pick(world: &World, cursor: [f32; 2], display_size: [f32; 2]) -> Option<Entity> {
+    let ray = cursor_ray(cursor, display_size, view_proj(world, display_size));
+
+    let entities = world.entities();
+    let bboxes = world.read_storage::<BoundingBox>();
+    let model_matrices = world.read_storage::<ModelMatrix>();
+    let hidden = world.read_storage::<Hidden>();
+    let visibilities = world.read_storage::<Visibility>();
+    let layers = world.read_storage::<RenderLayer>();
+    let camera_layers = visibility::pass_layers(world, Pass::Camera);
+
+    // The bounding boxes are in model space
+    (
+        &entities,
+        &bboxes,
+        &model_matrices,
+        visibilities.maybe(),
+        layers.maybe(),
+        !&hidden,
+    )
+        .join()
+        .filter(|(_, _, _, visibility, layer, _)| {
+            visibility::is_drawn(*visibility, *layer, Pass::Camera, camera_layers)
+        })
+        .filter_map(|(ent, bbox, model, _, _, _)| {
+            let t = ray.transformed(model.0.inverted()).intersect(bbox)?;
+            Some((ent, t))
+        })
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(ent, _)| ent)
+}
+
+/// The entities with a bounding box whose center is inside the rectangle on the screen
+pub(crate) fn pick_rect(
+    world: &World,
+    min: [f32; 2],
+    max: [f32; 2],
+    display_size: [f32; 2],
+) -> Vec<Entity> {
+    let view_proj = view_proj(world, display_size);
+
+    let entities = world.entities();
+    let bboxes = world.read_storage::<BoundingBox>();
+    let model_matrices = world.read_storage::<ModelMatrix>();
+    let hidden = world.read_storage::<Hidden>();
+    let visibilities = world.read_storage::<Visibility>();
+    let layers = world.read_storage::<RenderLayer>();
+    let camera_layers = visibility::pass_layers(world, Pass::Camera);
+
+    (
+        &entities,
+        &bboxes,
+        &model_matrices,
+        visibilities.maybe(),
+        layers.maybe(),
+        !&hidden,
+    )
+        .join()
+        .filter(|(_, _, _, visibility, layer, _)| {
+            visibility::is_drawn(*visibility, *layer, Pass::Camera, camera_layers)
+        })
+        .filter_map(|(ent, bbox, model, _, _, _)| {
+            let center = (model.0 * Vec4::from_point((bbox.min + bbox.max) * 0.5)).xyz();
+            let [x, y] = project(center, display_size, view_proj)?;
+            let inside = x >= min[0] && x <= max[0] && y >= min[1] && y <= max[1];
+            if inside {
+                Some(ent)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vek::approx::assert_abs_diff_eq;
+
+    const EPS: f32 = 0.0001;
+
+    #[test]
+    fn ray_through_cursor() {
+        let proj = crate::math::perspective_vk(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+        let size = [100.0, 100.0];
+
+        let center = cursor_ray([50.0, 50.0], size, proj);
+        assert_abs_diff_eq!(center.origin, Vec3::new(0.0, 0.0, -0.1), epsilon = EPS);
+        assert_abs_diff_eq!(center.direction, -Vec3::unit_z(), epsilon = EPS);
+
+        // 90 degree fov, so the corners are at 45 degrees. The y-axis of the screen points down.
+        let top_left = cursor_ray([0.0, 0.0], size, proj);
+        assert_abs_diff_eq!(
+            top_left.direction,
+            Vec3::new(-1.0, 1.0, -1.0).normalized(),
+            epsilon = EPS
+        );
+    }
+
+    #[test]
+    fn projection_matches_cursor_ray() {
+        let proj = crate::math::perspective_vk(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+        let size = [100.0, 100.0];
+
+        let cursor = [20.0, 70.0];
+        let ray = cursor_ray(cursor, size, proj);
+        let p = project(ray.origin + ray.direction * 10.0, size, proj).unwrap();
+        assert_abs_diff_eq!(p[0], cursor[0], epsilon = 0.01);
+        assert_abs_diff_eq!(p[1], cursor[1], epsilon = 0.01);
+
+        assert!(project(Vec3::new(0.0, 0.0, 1.0), size, proj).is_none());
+    }
+}
@@ -0,0 +1,158 @@
+use specs::prelude::*;
+
+use imgui::*;
+
+use crate::asset::template::{self, TemplateMaterial};
+use crate::common::Name;
+use crate::math::BoundingBox;
+use crate::render::geometry::{Primitive, PrimitiveOptions};
+use crate::render::mesh::CpuMesh;
+use crate::render::ui::UiFrame;
+
+use super::Selection;
+
+const STATE_ID: &str = "Primitives";
+
+/// The primitive that is picked and the sizes of each of them, so that they are kept when
+/// switching between them
+struct PrimitivesState {
+    selected: usize,
+    primitives: [Primitive; 7],
+    subdivisions: i32,
+    tangents: bool,
+}
+
+impl Default for PrimitivesState {
+    fn default() -> Self {
+        Self {
+            selected: 0,
+            primitives: Primitive::ALL,
+            subdivisions: 1,
+            tangents: true,
+        }
+    }
+}
+
+fn input_size<'a>(ui: &UiFrame<'a>, label: &ImStr, v: &mut f32) {
+    InputFloat::new(ui.inner(), label, v).build();
+    *v = v.max(0.001);
+}
+
+fn input_count<'a>(ui: &UiFrame<'a>, label: &ImStr, v: &mut u32) {
+    let mut edit = *v as i32;
+    if InputInt::new(ui.inner(), label, &mut edit).build() {
+        *v = edit.max(1) as u32;
+    }
+}
+
+fn edit<'a>(ui: &UiFrame<'a>, primitive: &mut Primitive) {
+    match primitive {
+        Primitive::Plane { x, z } => {
+            input_size(ui, im_str!("x"), x);
+            input_size(ui, im_str!("z"), z);
+        }
+        Primitive::Box { x, y, z } => {
+            input_size(ui, im_str!("x"), x);
+            input_size(ui, im_str!("y"), y);
+            input_size(ui, im_str!("z"), z);
+        }
+        Primitive::UvSphere { radius } | Primitive::Icosphere { radius } => {
+            input_size(ui, im_str!("radius"), radius);
+        }
+        Primitive::Cylinder { radius, height } | Primitive::Cone { radius, height } => {
+            input_size(ui, im_str!("radius"), radius);
+            input_size(ui, im_str!("height"), height);
+        }
+        Primitive::Grid {
+            x,
+            z,
+            cells_x,
+            cells_z,
+        } => {
+            input_size(ui, im_str!("x"), x);
+            input_size(ui, im_str!("z"), z);
+            input_count(ui, im_str!("cells x"), cells_x);
+            input_count(ui, im_str!("cells z"), cells_z);
+        }
+    }
+}
+
+/// The mesh gets the same default material as the templates, and is selected
+fn spawn(world: &mut World, primitive: &Primitive, options: &PrimitiveOptions) {
+    let (vertex_buffer, index_buffer) = primitive.mesh(options);
+    let mesh = CpuMesh {
+        vertex_buffer,
+        index_buffer,
+        polygon_mode: trekanten::pipeline::PolygonMode::Fill,
+    };
+    let transform = template::in_front_of_camera(world);
+
+    let mut builder = world.create_entity();
+    if let Some(bbox) = BoundingBox::from_points(mesh.positions()) {
+        builder = builder.with(bbox);
+    }
+    let ent = builder
+        .with(mesh)
+        .with(TemplateMaterial::default().material())
+        .with(transform)
+        .with(Name::from(primitive.name()))
+        .build();
+    world.write_resource::<Selection>().select(ent);
+}
+
+/// Spawns procedural primitives in front of the camera
+pub(crate) fn build_ui<'a>(world: &mut World, ui: &UiFrame<'a>, pos: [f32; 2]) -> [f32; 2] {
+    let size = [300.0, 250.0];
+
+    let mut to_spawn = None;
+    Window::new(im_str!("Primitives"))
+        .position(pos, Condition::FirstUseEver)
+        .size(size, Condition::FirstUseEver)
+        .collapsed(true, Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let mut storage = ui.storage();
+            let id = String::from(STATE_ID);
+            if !storage.contains_key(&id) {
+                storage.insert(id.clone(), PrimitivesState::default());
+            }
+            let state: &mut PrimitivesState = storage
+                .get_mut(&id)
+                .expect("Primitives state was just inserted");
+
+            let names: Vec<ImString> = state
+                .primitives
+                .iter()
+                .map(|p| ImString::new(p.name()))
+                .collect();
+            let items: Vec<&ImStr> = names.iter().map(|name| name.as_ref()).collect();
+            ComboBox::new(im_str!("Primitive")).build_simple_string(
+                ui.inner(),
+                &mut state.selected,
+                &items,
+            );
+            edit(ui, &mut state.primitives[state.selected]);
+
+            ui.inner().separator();
+            if InputInt::new(ui.inner(), im_str!("Subdivisions"), &mut state.subdivisions).build() {
+                // Each icosphere subdivision has four times the triangles
+                state.subdivisions = state.subdivisions.max(1).min(6);
+            }
+            ui.inner()
+                .checkbox(im_str!("Tangents"), &mut state.tangents);
+
+            if ui.inner().button(im_str!("Spawn"), [0.0, 0.0]) {
+                let options = PrimitiveOptions {
+                    subdivisions: state.subdivisions as u32,
+                    tangents: state.tangents,
+                    ..Default::default()
+                };
+                to_spawn = Some((state.primitives[state.selected], options));
+            }
+        });
+
+    if let Some((primitive, options)) = to_spawn {
+        spawn(world, &primitive, &options);
+    }
+
+    size
+}
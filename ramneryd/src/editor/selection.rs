@@ -0,0 +1,570 @@
+use specs::prelude::*;
+
+use imgui::*;
+
+use crate::collision::{self, raycast};
+use crate::graph::{self, Parent};
+use crate::math::{BoundingBox, ModelMatrix, Quat, Ray, Transform, Vec3, Vec4};
+use crate::render::ui::UiFrame;
+use crate::render::{Hidden, ReloadMaterial};
+
+/// The entities that are selected in the editor, in the order they were selected. The last one is
+/// the one that is inspected.
+#[derive(Debug, Default)]
+pub(crate) struct Selection {
+    entities: Vec<Entity>,
+}
+
+impl Selection {
+    pub fn select(&mut self, ent: Entity) {
+        self.entities.clear();
+        self.entities.push(ent);
+    }
+
+    /// Add the entity, or remove it if it is already selected
+    pub fn toggle(&mut self, ent: Entity) {
+        match self.entities.iter().position(|e| *e == ent) {
+            Some(idx) => {
+                self.entities.remove(idx);
+            }
+            None => self.entities.push(ent),
+        }
+    }
+
+    /// Add the entities that are not already selected
+    pub fn extend(&mut self, ents: impl IntoIterator<Item = Entity>) {
+        for ent in ents {
+            if !self.contains(ent) {
+                self.entities.push(ent);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entities.clear();
+    }
+
+    pub fn contains(&self, ent: Entity) -> bool {
+        self.entities.contains(&ent)
+    }
+
+    pub fn primary(&self) -> Option<Entity> {
+        self.entities.last().copied()
+    }
+
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn retain_alive(&mut self, world: &World) {
+        self.entities.retain(|ent| world.is_alive(*ent));
+    }
+
+    /// Select ent on its own or, when adding, toggle it
+    pub fn click(&mut self, ent: Entity, add: bool) {
+        if add {
+            self.toggle(ent);
+        } else {
+            self.select(ent);
+        }
+    }
+}
+
+/// How group edits in the selection panel are snapped
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Snapping {
+    pub grid: bool,
+    pub grid_size: f32,
+    pub angle: bool,
+    pub angle_step_degrees: f32,
+    /// Use the center of the combined bounds of the selection as the pivot instead of the average
+    /// of their origins
+    pub pivot_at_bounds_center: bool,
+}
+
+impl Default for Snapping {
+    fn default() -> Self {
+        Self {
+            grid: false,
+            grid_size: 0.5,
+            angle: false,
+            angle_step_degrees: 15.0,
+            pivot_at_bounds_center: false,
+        }
+    }
+}
+
+/// Inserts the snapping settings and makes them cvars, so that they can be set from the console
+/// and the config
+pub(crate) fn register_cvars(world: &mut World) {
+    use crate::cvar::register;
+    type S = Snapping;
+
+    world.insert(Snapping::default());
+    register(
+        world,
+        "editor.snap.grid",
+        "Snap the positions of group edits to a grid",
+        None,
+        |s: &mut S| &mut s.grid,
+    );
+    register(
+        world,
+        "editor.snap.grid_size",
+        "The size of the cells of the snapping grid",
+        Some((0.0, 100.0)),
+        |s: &mut S| &mut s.grid_size,
+    );
+    register(
+        world,
+        "editor.snap.angle",
+        "Snap the rotations of group edits to steps of editor.snap.angle_step",
+        None,
+        |s: &mut S| &mut s.angle,
+    );
+    register(
+        world,
+        "editor.snap.angle_step",
+        "The step of the angle snapping, in degrees",
+        Some((0.0, 180.0)),
+        |s: &mut S| &mut s.angle_step_degrees,
+    );
+    register(
+        world,
+        "editor.snap.pivot_at_bounds_center",
+        "Rotate and scale group edits around the center of their bounds",
+        None,
+        |s: &mut S| &mut s.pivot_at_bounds_center,
+    );
+}
+
+/// The closest multiple of step, or v if step is not positive
+fn snap(v: f32, step: f32) -> f32 {
+    if step > 0.0 {
+        (v / step).round() * step
+    } else {
+        v
+    }
+}
+
+impl Snapping {
+    fn position(&self, p: Vec3) -> Vec3 {
+        if self.grid {
+            p.map(|c| snap(c, self.grid_size))
+        } else {
+            p
+        }
+    }
+
+    fn angle_degrees(&self, angle: f32) -> f32 {
+        if self.angle {
+            snap(angle, self.angle_step_degrees)
+        } else {
+            angle
+        }
+    }
+}
+
+/// The selected entities that do not have a selected ancestor, so that group edits are only applied
+/// once to each hierarchy.
+fn selection_roots(world: &World, selection: &Selection) -> Vec<Entity> {
+    selection
+        .entities()
+        .iter()
+        .copied()
+        .filter(|ent| {
+            graph::node_to_root_path(world, *ent)
+                .skip(1)
+                .all(|ancestor| !selection.contains(ancestor))
+        })
+        .collect()
+}
+
+fn world_position(model: &ModelMatrix) -> Vec3 {
+    model.0.cols.w.xyz()
+}
+
+/// The combined world space bounds of the entities and everything below them
+fn world_bounds(world: &World, entities: &[Entity]) -> Option<BoundingBox> {
+    let bboxes = world.read_storage::<BoundingBox>();
+    let model_matrices = world.read_storage::<ModelMatrix>();
+    let mut bounds: Option<BoundingBox> = None;
+    for ent in entities {
+        graph::world::breadth_first(world, *ent, |node| {
+            if let (Some(bbox), Some(model)) = (bboxes.get(node), model_matrices.get(node)) {
+                let mut node_bounds = bbox.transformed(&model.0);
+                if let Some(b) = bounds {
+                    node_bounds.combine(b);
+                }
+                bounds = Some(node_bounds);
+            }
+        });
+    }
+    bounds
+}
+
+fn pivot(world: &World, entities: &[Entity], snapping: &Snapping) -> Option<Vec3> {
+    if snapping.pivot_at_bounds_center {
+        world_bounds(world, entities).map(|b| (b.min + b.max) / 2.0)
+    } else {
+        origins_center(world, entities)
+    }
+}
+
+/// The average of the world positions of the entities
+fn origins_center(world: &World, entities: &[Entity]) -> Option<Vec3> {
+    let model_matrices = world.read_storage::<ModelMatrix>();
+    let positions: Vec<Vec3> = entities
+        .iter()
+        .filter_map(|ent| model_matrices.get(*ent))
+        .map(world_position)
+        .collect();
+    if positions.is_empty() {
+        return None;
+    }
+
+    let sum = positions.iter().fold(Vec3::zero(), |acc, p| acc + *p);
+    Some(sum / positions.len() as f32)
+}
+
+/// Move the entities by the world space delta. Transforms are relative to the parent so the delta is
+/// brought into the space of each parent.
+fn translate(world: &World, entities: &[Entity], delta: Vec3) {
+    let parents = world.read_storage::<Parent>();
+    let model_matrices = world.read_storage::<ModelMatrix>();
+    let mut transforms = world.write_storage::<Transform>();
+    for ent in entities {
+        let local_delta = match parents.get(*ent).and_then(|p| model_matrices.get(p.parent)) {
+            Some(parent_model) => (parent_model.0.inverted() * Vec4::from_direction(delta)).xyz(),
+            None => delta,
+        };
+        match transforms.get_mut(*ent) {
+            Some(transform) => transform.position += local_delta,
+            None => {
+                transforms
+                    .insert(
+                        *ent,
+                        Transform::pos(local_delta.x, local_delta.y, local_delta.z),
+                    )
+                    .expect("Selected entities are alive");
+            }
+        }
+    }
+}
+
+/// Rotate the entities around the world space up axis through the pivot
+fn rotate_y(world: &World, entities: &[Entity], pivot: Vec3, angle_radians: f32) {
+    let rotation = Quat::rotation_y(angle_radians);
+    let parents = world.read_storage::<Parent>();
+    let model_matrices = world.read_storage::<ModelMatrix>();
+    let mut transforms = world.write_storage::<Transform>();
+    for ent in entities {
+        let pos = match model_matrices.get(*ent) {
+            Some(model) => world_position(model),
+            None => continue,
+        };
+        let delta = pivot + rotation * (pos - pivot) - pos;
+        // The rotation around the up axis, in the space of the parent
+        let (local_delta, local_up) =
+            match parents.get(*ent).and_then(|p| model_matrices.get(p.parent)) {
+                Some(parent_model) => {
+                    let parent_rotation = Transform::from_matrix(&parent_model.0).rotation;
+                    (
+                        (parent_model.0.inverted() * Vec4::from_direction(delta)).xyz(),
+                        parent_rotation.conjugate() * Vec3::unit_y(),
+                    )
+                }
+                None => (delta, Vec3::unit_y()),
+            };
+
+        let transform = transforms
+            .entry(*ent)
+            .expect("Selected entities are alive")
+            .or_insert_with(Transform::identity);
+        transform.position += local_delta;
+        transform.rotation =
+            (Quat::rotation_3d(angle_radians, local_up) * transform.rotation).normalized();
+    }
+}
+
+/// Move each entity straight down, or up, so that the bottom of its bounds rests on whatever is below
+/// its top. Returns the entities that did not hit anything.
+fn align_to_ground(world: &World, entities: &[Entity]) -> Vec<Entity> {
+    let mut missed = Vec::new();
+    for ent in entities {
+        let bounds = match world_bounds(world, &[*ent]) {
+            Some(b) => b,
+            None => {
+                missed.push(*ent);
+                continue;
+            }
+        };
+
+        let mut own = Vec::new();
+        graph::world::breadth_first(world, *ent, |node| own.push(node));
+        let colliders = collision::gather(
+            &world.entities(),
+            &world.read_storage::<BoundingBox>(),
+            &world.read_storage::<ModelMatrix>(),
+            |other| !own.contains(&other),
+        );
+
+        let center = (bounds.min + bounds.max) / 2.0;
+        let ray = Ray {
+            origin: Vec3::new(center.x, bounds.max.y, center.z),
+            direction: -Vec3::unit_y(),
+        };
+        match raycast(&colliders, &ray, std::f32::INFINITY) {
+            Some((t, _normal)) => {
+                let ground = bounds.max.y - t;
+                translate(world, &[*ent], Vec3::new(0.0, ground - bounds.min.y, 0.0));
+            }
+            None => missed.push(*ent),
+        }
+    }
+    missed
+}
+
+/// Toggles the visibility of the entities and everything below them, based on the first entity
+pub(super) fn toggle_hidden(world: &World, entities: &[Entity]) {
+    let mut hidden = world.write_storage::<Hidden>();
+    let hide = match entities.first() {
+        Some(first) => !hidden.contains(*first),
+        None => return,
+    };
+
+    for ent in entities {
+        graph::world::breadth_first(world, *ent, |node| {
+            if hide {
+                hidden
+                    .insert(node, Hidden)
+                    .expect("Selected entities are alive");
+            } else {
+                hidden.remove(node);
+            }
+        });
+    }
+}
+
+fn reload_materials(world: &World, entities: &[Entity]) {
+    let mut reload = world.write_storage::<ReloadMaterial>();
+    for ent in entities {
+        graph::world::breadth_first(world, *ent, |node| {
+            reload
+                .insert(node, ReloadMaterial)
+                .expect("Selected entities are alive");
+        });
+    }
+}
+
+/// Edits of all the selected entities at once
+pub(crate) fn build_ui<'a>(world: &mut World, ui: &UiFrame<'a>, pos: [f32; 2]) -> [f32; 2] {
+    let size = [300.0, 250.0];
+
+    Window::new(im_str!("Selection"))
+        .position(pos, Condition::FirstUseEver)
+        .size(size, Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let mut selection = world.write_resource::<Selection>();
+            selection.retain_alive(world);
+            ui.inner()
+                .text(im_str!("{} selected (ctrl-click to add)", selection.len()));
+
+            let mut snapping = world.write_resource::<Snapping>();
+            ui.inner()
+                .checkbox(im_str!("Grid snap"), &mut snapping.grid);
+            ui.inner().same_line(0.0);
+            InputFloat::new(ui.inner(), im_str!("Grid size"), &mut snapping.grid_size).build();
+            ui.inner()
+                .checkbox(im_str!("Angle snap"), &mut snapping.angle);
+            ui.inner().same_line(0.0);
+            InputFloat::new(
+                ui.inner(),
+                im_str!("Angle step"),
+                &mut snapping.angle_step_degrees,
+            )
+            .build();
+            ui.inner().checkbox(
+                im_str!("Pivot at center of bounds"),
+                &mut snapping.pivot_at_bounds_center,
+            );
+
+            if selection.is_empty() {
+                return;
+            }
+
+            let roots = selection_roots(world, &selection);
+            if let Some(pivot) = pivot(world, &roots, &snapping) {
+                let mut p = pivot.into_array();
+                if InputFloat3::new(ui.inner(), im_str!("Pivot"), &mut p).build() {
+                    let target = snapping.position(Vec3::from(p));
+                    translate(world, &roots, target - pivot);
+                }
+
+                let id = String::from("SelectionRotateY");
+                let stored: Option<f32> = ui.storage().get(&id).copied();
+                let mut angle = stored.unwrap_or(0.0);
+                InputFloat::new(ui.inner(), im_str!("Degrees"), &mut angle).build();
+                ui.storage().insert(id, angle);
+                ui.inner().same_line(0.0);
+                if ui.inner().button(im_str!("Rotate Y"), [0.0, 0.0]) {
+                    let snapped = snapping.angle_degrees(angle);
+                    rotate_y(world, &roots, pivot, snapped.to_radians());
+                }
+            }
+
+            if ui.inner().button(im_str!("Align to ground"), [0.0, 0.0]) {
+                for ent in align_to_ground(world, &roots) {
+                    log::warn!("Found no ground below {:?}", ent);
+                }
+            }
+            ui.inner().same_line(0.0);
+            if ui.inner().button(im_str!("Snap to grid"), [0.0, 0.0]) {
+                let model_matrices = world.read_storage::<ModelMatrix>();
+                let deltas: Vec<(Entity, Vec3)> = roots
+                    .iter()
+                    .filter_map(|ent| model_matrices.get(*ent).map(|m| (*ent, world_position(m))))
+                    .map(|(ent, pos)| (ent, snapping.position(pos) - pos))
+                    .collect();
+                drop(model_matrices);
+                for (ent, delta) in deltas {
+                    translate(world, &[ent], delta);
+                }
+            }
+
+            if let Some(pivot) = pivot(world, &roots, &snapping) {
+                let id = String::from("SelectionRadius");
+                let mut radius: f32 = ui.storage().get(&id).copied().unwrap_or(5.0);
+                InputFloat::new(ui.inner(), im_str!("Radius"), &mut radius).build();
+                ui.storage().insert(id, radius);
+                ui.inner().same_line(0.0);
+                if ui.inner().button(im_str!("Select within"), [0.0, 0.0]) {
+                    let index = world.read_resource::<crate::spatial::SpatialIndex>();
+                    selection.extend(index.overlap_sphere(pivot, radius));
+                }
+            }
+
+            if ui.inner().button(im_str!("Toggle visibility"), [0.0, 0.0]) {
+                toggle_hidden(world, &roots);
+            }
+            ui.inner().same_line(0.0);
+            if ui.inner().button(im_str!("Reload materials"), [0.0, 0.0]) {
+                reload_materials(world, &roots);
+            }
+            ui.inner().same_line(0.0);
+            if ui.inner().button(im_str!("Clear"), [0.0, 0.0]) {
+                selection.clear();
+            }
+        });
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entities(n: usize) -> (World, Vec<Entity>) {
+        let mut world = World::new();
+        let ents = (0..n).map(|_| world.create_entity().build()).collect();
+        (world, ents)
+    }
+
+    #[test]
+    fn click_selects_and_toggles() {
+        let (_world, ents) = entities(3);
+        let mut selection = Selection::default();
+
+        selection.click(ents[0], false);
+        selection.click(ents[1], true);
+        assert_eq!(selection.entities(), &[ents[0], ents[1]]);
+        assert_eq!(selection.primary(), Some(ents[1]));
+
+        selection.click(ents[0], true);
+        assert_eq!(selection.entities(), &[ents[1]]);
+
+        selection.extend(vec![ents[1], ents[2]]);
+        assert_eq!(selection.entities(), &[ents[1], ents[2]]);
+
+        selection.click(ents[0], false);
+        assert_eq!(selection.entities(), &[ents[0]]);
+    }
+
+    #[test]
+    fn snapping() {
+        let mut snapping = Snapping::default();
+        let p = Vec3::new(0.3, -0.2, 1.7);
+        assert_eq!(snapping.position(p), p);
+        assert_eq!(snapping.angle_degrees(22.0), 22.0);
+
+        snapping.grid = true;
+        snapping.grid_size = 0.5;
+        snapping.angle = true;
+        snapping.angle_step_degrees = 15.0;
+        assert_eq!(snapping.position(p), Vec3::new(0.5, 0.0, 1.5));
+        assert_eq!(snapping.angle_degrees(22.0), 15.0);
+        assert_eq!(snapping.angle_degrees(23.0), 30.0);
+    }
+
+    #[test]
+    fn align_to_ground_rests_on_box_below() {
+        let mut world = World::new();
+        world.register::<graph::Children>();
+        world.register::<Parent>();
+        world.register::<BoundingBox>();
+        world.register::<ModelMatrix>();
+        world.register::<Transform>();
+
+        let unit = BoundingBox {
+            min: Vec3::new(-0.5, -0.5, -0.5),
+            max: Vec3::new(0.5, 0.5, 0.5),
+        };
+        let ground = world
+            .create_entity()
+            .with(BoundingBox {
+                min: Vec3::new(-10.0, -1.0, -10.0),
+                max: Vec3::new(10.0, 0.0, 10.0),
+            })
+            .with(ModelMatrix::from(crate::math::Mat4::identity()))
+            .build();
+        let floating = world
+            .create_entity()
+            .with(unit)
+            .with(ModelMatrix::from(crate::math::Mat4::translation_3d(
+                Vec3::new(1.0, 3.0, 0.0),
+            )))
+            .with(Transform::pos(1.0, 3.0, 0.0))
+            .build();
+
+        assert!(align_to_ground(&world, &[floating]).is_empty());
+        let transforms = world.read_storage::<Transform>();
+        assert_eq!(
+            transforms.get(floating).unwrap().position,
+            Vec3::new(1.0, 0.5, 0.0)
+        );
+        drop(transforms);
+
+        // Nothing below the ground itself
+        assert_eq!(align_to_ground(&world, &[ground]), vec![ground]);
+    }
+
+    #[test]
+    fn roots_skip_selected_descendants() {
+        let (mut world, ents) = entities(3);
+        world.register::<graph::Children>();
+        world.register::<Parent>();
+        graph::world::add_edge(&mut world, ents[0], ents[1]);
+
+        let mut selection = Selection::default();
+        selection.extend(ents.clone());
+        assert_eq!(selection_roots(&world, &selection), vec![ents[0], ents[2]]);
+    }
+}
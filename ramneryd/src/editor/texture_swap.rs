@@ -0,0 +1,153 @@
+use specs::prelude::*;
+
+use imgui::*;
+
+use std::path::{Path, PathBuf};
+
+use crate::asset::ContentDirectory;
+use crate::render::material::{self, PhysicallyBased, TextureSlot};
+use crate::render::thumbnail;
+use crate::render::ui::UiFrame;
+
+const STATE_ID: &str = "TexturePicker";
+
+/// State of the file picker for the textures of the inspected material
+#[derive(Default)]
+struct TexturePickerState {
+    slot: Option<TextureSlot>,
+    dir: Option<PathBuf>,
+    /// Sub-directories and images in `dir`, directories first
+    entries: Vec<PathBuf>,
+}
+
+impl TexturePickerState {
+    fn set_dir(&mut self, dir: PathBuf) {
+        self.entries.clear();
+        match std::fs::read_dir(&dir) {
+            Ok(read_dir) => {
+                self.entries.extend(
+                    read_dir
+                        .filter_map(Result::ok)
+                        .map(|e| e.path())
+                        .filter(|p| p.is_dir() || thumbnail::is_image(p)),
+                );
+            }
+            Err(e) => log::error!("Failed to read {}: {}", dir.display(), e),
+        }
+        self.entries
+            .sort_by(|a, b| b.is_dir().cmp(&a.is_dir()).then_with(|| a.cmp(b)));
+        self.dir = Some(dir);
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_else(|| path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Lists the textures of the material of the entity, each with a button that opens a file picker
+/// to replace it. The picker starts in the directory of the current texture.
+pub(super) fn build_ui<'a>(world: &mut World, ui: &UiFrame<'a>, ent: Entity) {
+    let textures: Vec<(TextureSlot, Option<PathBuf>)> =
+        match world.read_storage::<PhysicallyBased>().get(ent) {
+            Some(material) => TextureSlot::ALL
+                .iter()
+                .map(|&slot| {
+                    let path = material
+                        .texture(slot)
+                        .and_then(material::TextureUse2::path)
+                        .map(Path::to_path_buf);
+                    (slot, path)
+                })
+                .collect(),
+            None => return,
+        };
+
+    if !CollapsingHeader::new(im_str!("Textures")).build(ui.inner()) {
+        return;
+    }
+
+    let modal_id = im_str!("Pick texture");
+    let mut picked = None;
+    {
+        let id = String::from(STATE_ID);
+        let mut storage = ui.storage();
+        if !storage.contains_key(&id) {
+            storage.insert(id.clone(), TexturePickerState::default());
+        }
+        let state: &mut TexturePickerState = storage
+            .get_mut(&id)
+            .expect("Texture picker state was just inserted");
+
+        for (i, (slot, path)) in textures.iter().enumerate() {
+            let id_token = ui.inner().push_id(i as i32);
+            if ui.inner().button(im_str!("..."), [0.0, 0.0]) {
+                let start = path
+                    .as_ref()
+                    .and_then(|p| p.parent())
+                    .map(Path::to_path_buf)
+                    .or_else(|| world.try_fetch::<ContentDirectory>().map(|d| d.0.clone()))
+                    .or_else(|| std::env::current_dir().ok())
+                    .unwrap_or_else(|| PathBuf::from("."));
+                state.slot = Some(*slot);
+                state.set_dir(start);
+                ui.inner().open_popup(modal_id);
+            }
+            ui.inner().same_line(0.0);
+            let file = path.as_deref().map(file_name);
+            ui.inner().text(im_str!(
+                "{}: {}",
+                slot.name(),
+                file.as_deref().unwrap_or("none")
+            ));
+            id_token.pop(ui.inner());
+        }
+
+        ui.inner().popup_modal(modal_id).build(|| {
+            let dir = match (&state.dir, state.slot) {
+                (Some(dir), Some(slot)) => {
+                    ui.inner()
+                        .text(im_str!("{} texture in {}", slot.name(), dir.display()));
+                    dir.clone()
+                }
+                _ => {
+                    ui.inner().close_current_popup();
+                    return;
+                }
+            };
+            ui.inner().separator();
+
+            let mut next_dir = None;
+            if let Some(parent) = dir.parent() {
+                if Selectable::new(im_str!("..")).build(ui.inner()) {
+                    next_dir = Some(parent.to_path_buf());
+                }
+            }
+            for entry in &state.entries {
+                if entry.is_dir() {
+                    let label = im_str!("{}/", file_name(entry));
+                    if Selectable::new(&label).build(ui.inner()) {
+                        next_dir = Some(entry.clone());
+                    }
+                } else if Selectable::new(&im_str!("{}", file_name(entry))).build(ui.inner()) {
+                    picked = state.slot.map(|slot| (slot, entry.clone()));
+                    ui.inner().close_current_popup();
+                }
+            }
+            if let Some(next_dir) = next_dir {
+                state.set_dir(next_dir);
+            }
+
+            ui.inner().separator();
+            if ui.inner().button(im_str!("Cancel"), [0.0, 0.0]) {
+                ui.inner().close_current_popup();
+            }
+        });
+    }
+
+    if let Some((slot, path)) = picked {
+        material::swap_texture(world, ent, slot, path);
+    }
+}
@@ -0,0 +1,152 @@
+//! Polls files on disk for changes by their modification times, for the watchers that reload
+//! assets, shaders and config files. There is no notification api, so the watchers poll at most
+//! once per POLL_INTERVAL.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// None if the file doesn't exist or the platform doesn't record modification times
+pub fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Limits how often the files are polled
+#[derive(Debug)]
+pub struct PollTimer {
+    last_poll: Instant,
+}
+
+impl Default for PollTimer {
+    fn default() -> Self {
+        Self {
+            last_poll: Instant::now(),
+        }
+    }
+}
+
+impl PollTimer {
+    /// True if POLL_INTERVAL has passed since it last returned true
+    pub fn ready(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+struct WatchedFile<T> {
+    modified: Option<SystemTime>,
+    data: T,
+}
+
+/// Files and their modification times when they were last polled, with data for each file
+#[derive(Debug, Clone)]
+pub struct WatchedFiles<T> {
+    files: HashMap<PathBuf, WatchedFile<T>>,
+}
+
+impl<T> Default for WatchedFiles<T> {
+    fn default() -> Self {
+        Self {
+            files: HashMap::new(),
+        }
+    }
+}
+
+impl<T> WatchedFiles<T> {
+    /// Starts watching the file, if it isn't already, and returns its data. Changes are reported
+    /// from now on.
+    pub fn watch(&mut self, path: &Path, data: impl FnOnce() -> T) -> &mut T {
+        &mut self
+            .files
+            .entry(path.to_path_buf())
+            .or_insert_with(|| WatchedFile {
+                modified: modified(path),
+                data: data(),
+            })
+            .data
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&T> {
+        self.files.get(path).map(|f| &f.data)
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Stops watching the files that `keep` returns false for
+    pub fn retain(&mut self, mut keep: impl FnMut(&Path, &mut T) -> bool) {
+        self.files.retain(|path, file| keep(path, &mut file.data));
+    }
+
+    /// False if any of the files changed since they were last polled
+    pub fn is_current(&self) -> bool {
+        self.files
+            .iter()
+            .all(|(path, file)| modified(path) == file.modified)
+    }
+
+    /// Returns the files that changed, or were removed, since they were last polled
+    pub fn poll(&mut self) -> Vec<(PathBuf, &mut T)> {
+        let mut changed = Vec::new();
+        for (path, file) in self.files.iter_mut() {
+            let modified = modified(path);
+            if modified != file.modified {
+                file.modified = modified;
+                changed.push((path.clone(), &mut file.data));
+            }
+        }
+        changed
+    }
+
+    /// Reports the file as changed at the next poll, for tests that can't wait for the file
+    /// system to record a new modification time
+    #[cfg(test)]
+    pub fn touch(&mut self, path: &Path) {
+        if let Some(file) = self.files.get_mut(path) {
+            file.modified = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_and_removed_files_are_reported() {
+        let path = std::env::temp_dir().join("ramneryd_file_watcher_test.txt");
+        std::fs::write(&path, b"").unwrap();
+
+        let mut files = WatchedFiles::default();
+        *files.watch(&path, || 0) += 1;
+        *files.watch(&path, || 0) += 1;
+        assert_eq!(files.get(&path), Some(&2));
+        assert!(files.is_current());
+        assert!(files.poll().is_empty());
+
+        files.touch(&path);
+        assert!(!files.is_current());
+        let changed: Vec<_> = files.poll().into_iter().map(|(p, d)| (p, *d)).collect();
+        assert_eq!(changed, vec![(path.clone(), 2)]);
+        assert!(files.poll().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(files.poll().len(), 1);
+        assert!(files.poll().is_empty());
+
+        files.retain(|_, _| false);
+        assert!(files.is_empty());
+    }
+}
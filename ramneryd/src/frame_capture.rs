@@ -0,0 +1,53 @@
+//! Frame captures through the RenderDoc in-application API. Captures only work when the engine is
+//! built with the `renderdoc` feature and RenderDoc is loaded into the process, e.g. when the
+//! application is launched from the RenderDoc UI or with `renderdoccmd capture`.
+
+pub(crate) struct FrameCapture {
+    #[cfg(feature = "renderdoc")]
+    api: Option<renderdoc::RenderDoc<renderdoc::V110>>,
+}
+
+impl FrameCapture {
+    /// Has to be created before the renderer, as RenderDoc needs to hook the Vulkan instance
+    pub(crate) fn new() -> Self {
+        #[cfg(feature = "renderdoc")]
+        {
+            let api = match renderdoc::RenderDoc::new() {
+                Ok(api) => Some(api),
+                Err(e) => {
+                    log::info!(
+                        "RenderDoc is not available, frames can't be captured: {}",
+                        e
+                    );
+                    None
+                }
+            };
+            Self { api }
+        }
+
+        #[cfg(not(feature = "renderdoc"))]
+        Self {}
+    }
+
+    /// Start capturing everything that is rendered until `end`. Returns false if no capture was
+    /// started.
+    pub(crate) fn start(&mut self) -> bool {
+        #[cfg(feature = "renderdoc")]
+        if let Some(api) = &mut self.api {
+            // Null device and window mean whatever the application is rendering with
+            api.start_frame_capture(std::ptr::null(), std::ptr::null());
+            return true;
+        }
+
+        log::warn!("Can't capture frame, RenderDoc is not available");
+        false
+    }
+
+    pub(crate) fn end(&mut self) {
+        #[cfg(feature = "renderdoc")]
+        if let Some(api) = &mut self.api {
+            api.end_frame_capture(std::ptr::null(), std::ptr::null());
+            log::info!("Captured frame with RenderDoc");
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Limits the frame rate to RenderSettings::max_fps and keeps a history of the frame times, to
+//! diagnose stutter. The history is shown as a graph in the render debug window.
+//!
+//! The limiter sleeps until shortly before the next frame is due and spins for the rest, as sleeping
+//! is often only accurate to a millisecond or more.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::time::DeltaTime;
+
+/// The number of frames in the frame time graph
+pub const FRAME_TIME_HISTORY_LEN: usize = 240;
+/// The histogram bins are this many milliseconds wide
+pub const HISTOGRAM_BIN_MS: f32 = 2.0;
+/// The last bin also counts all frames that are slower than this
+pub const HISTOGRAM_BINS: usize = 25;
+/// Sleep until this long before the next frame is due and spin for the rest
+const SPIN_MARGIN: Duration = Duration::from_millis(1);
+
+/// The times of the most recent frames, in milliseconds, and a histogram of them
+#[derive(Debug, Clone)]
+pub struct FrameTimes {
+    times: VecDeque<f32>,
+    pub bins: [f32; HISTOGRAM_BINS],
+}
+
+impl Default for FrameTimes {
+    fn default() -> Self {
+        Self {
+            times: VecDeque::with_capacity(FRAME_TIME_HISTORY_LEN),
+            bins: [0.0; HISTOGRAM_BINS],
+        }
+    }
+}
+
+fn bin(ms: f32) -> usize {
+    ((ms / HISTOGRAM_BIN_MS) as usize).min(HISTOGRAM_BINS - 1)
+}
+
+impl FrameTimes {
+    pub fn push(&mut self, dt: DeltaTime) {
+        let ms = dt.as_ms();
+        if self.times.len() == FRAME_TIME_HISTORY_LEN {
+            if let Some(oldest) = self.times.pop_front() {
+                self.bins[bin(oldest)] -= 1.0;
+            }
+        }
+        self.times.push_back(ms);
+        self.bins[bin(ms)] += 1.0;
+    }
+
+    /// Oldest first, for imgui plots which take a slice
+    pub fn times(&mut self) -> &[f32] {
+        self.times.make_contiguous()
+    }
+
+    pub fn average(&self) -> f32 {
+        if self.times.is_empty() {
+            return 0.0;
+        }
+        self.times.iter().sum::<f32>() / self.times.len() as f32
+    }
+
+    pub fn max(&self) -> f32 {
+        self.times.iter().copied().fold(0.0, f32::max)
+    }
+
+    /// The frame time that this fraction of the frames are faster than, e.g. 0.99 for the 99th
+    /// percentile
+    pub fn percentile(&self, p: f32) -> f32 {
+        if self.times.is_empty() {
+            return 0.0;
+        }
+        let mut sorted: Vec<f32> = self.times.iter().copied().collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let idx = ((sorted.len() - 1) as f32 * p.max(0.0).min(1.0)).round() as usize;
+        sorted[idx]
+    }
+}
+
+/// The time when the frame after one that was due at `deadline` is due. If the frame was late,
+/// the next one is paced from now instead, so that the limiter doesn't try to catch up with a
+/// burst of frames.
+fn next_deadline(deadline: Instant, now: Instant, frame_time: Duration) -> Instant {
+    if now > deadline {
+        now + frame_time
+    } else {
+        deadline + frame_time
+    }
+}
+
+pub struct FrameLimiter {
+    deadline: Instant,
+}
+
+impl Default for FrameLimiter {
+    fn default() -> Self {
+        Self {
+            deadline: Instant::now(),
+        }
+    }
+}
+
+impl FrameLimiter {
+    /// Waits until the next frame is due. Call after presenting. A max_fps of 0 means no limit.
+    #[profiling::function]
+    pub fn wait(&mut self, max_fps: u32) {
+        let now = Instant::now();
+        if max_fps == 0 {
+            self.deadline = now;
+            return;
+        }
+
+        if let Some(sleep) = self.deadline.checked_duration_since(now + SPIN_MARGIN) {
+            std::thread::sleep(sleep);
+        }
+        while Instant::now() < self.deadline {
+            std::hint::spin_loop();
+        }
+
+        let frame_time = Duration::from_secs_f64(1.0 / max_fps as f64);
+        self.deadline = next_deadline(self.deadline, Instant::now(), frame_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_times_are_binned_and_rolled() {
+        let mut times = FrameTimes::default();
+        for _ in 0..FRAME_TIME_HISTORY_LEN {
+            times.push(Duration::from_millis(16).into());
+        }
+        times.push(Duration::from_millis(100).into());
+        assert_eq!(times.times().len(), FRAME_TIME_HISTORY_LEN);
+        assert_eq!(times.bins[8], (FRAME_TIME_HISTORY_LEN - 1) as f32);
+        assert_eq!(times.bins[HISTOGRAM_BINS - 1], 1.0);
+        assert!((times.percentile(0.5) - 16.0).abs() < 0.01);
+        assert!((times.max() - 100.0).abs() < 0.01);
+
+        let frame = Duration::from_millis(10);
+        let start = Instant::now();
+        assert_eq!(next_deadline(start, start, frame), start + frame);
+        let late = start + Duration::from_millis(25);
+        assert_eq!(next_deadline(start, late, frame), late + frame);
+    }
+}
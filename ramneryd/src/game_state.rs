@@ -1,7 +1,10 @@
-use crate::io::input::{ActionId, InputContext, InputContextPriority, MappedInput};
+use crate::io::input::{
+    ActionId, InputContext, InputContextError, InputContextPriority, MappedInput,
+};
 
 use crate::common::Name;
 use crate::io::input;
+use crate::settings::{self, KeyBindings};
 
 use crate::ecs::prelude::*;
 
@@ -21,16 +24,43 @@ const GAME_STATE_SWITCH: ActionId = ActionId(0);
 
 struct GameStateSwitcher {
     input_entity: Option<specs::Entity>,
+    bindings_version: u32,
 }
 
 const NAME: &str = "GameStateSwitcher";
 
-impl<'a> System<'a> for GameStateSwitcher {
-    type SystemData = (Write<'a, GameState>, WriteStorage<'a, MappedInput>);
+fn get_input_context(bindings: &KeyBindings) -> Result<InputContext, InputContextError> {
+    let mut builder = InputContext::builder(&NAME)
+        .description("Global top-level escape catcher for game state switcher")
+        .priority(InputContextPriority::First);
+    if let Some(button) = bindings.get(settings::GAME_STATE_SWITCH) {
+        builder = builder.with_action(button, GAME_STATE_SWITCH)?;
+    }
+    Ok(builder.build())
+}
 
-    fn run(&mut self, (mut state, mut inputs): Self::SystemData) {
+impl<'a> System<'a> for GameStateSwitcher {
+    type SystemData = (
+        Write<'a, GameState>,
+        WriteStorage<'a, MappedInput>,
+        Read<'a, KeyBindings>,
+        WriteStorage<'a, InputContext>,
+    );
+
+    fn run(&mut self, (mut state, mut inputs, bindings, mut contexts): Self::SystemData) {
         use crate::io::input::Input;
         log::trace!("GameStateSwitcher: run");
+        if bindings.version() != self.bindings_version {
+            match get_input_context(&bindings) {
+                Ok(ctx) => {
+                    contexts
+                        .insert(self.input_entity.unwrap(), ctx)
+                        .expect("Failed to replace the game state input context");
+                }
+                Err(e) => log::error!("Failed to rebind the game state switch: {:?}", e),
+            }
+            self.bindings_version = bindings.version();
+        }
 
         let ent = self.input_entity.unwrap();
         let inp = inputs.get_mut(ent).unwrap();
@@ -52,12 +82,11 @@ impl<'a> System<'a> for GameStateSwitcher {
     fn setup(&mut self, world: &mut World) {
         Self::SystemData::setup(world);
         world.insert(GameState::default());
-        let escape_catcher = InputContext::builder(&NAME)
-            .description("Global top-level escape catcher for game state switcher")
-            .priority(InputContextPriority::First)
-            .with_action(input::KeyCode::Escape, GAME_STATE_SWITCH)
-            .expect("Could not insert Escape action for GameStateSwitcher")
-            .build();
+        let escape_catcher = {
+            let bindings = world.read_resource::<KeyBindings>();
+            self.bindings_version = bindings.version();
+            get_input_context(&bindings).expect("Could not insert action for GameStateSwitcher")
+        };
 
         self.input_entity = Some(
             world
@@ -71,7 +100,10 @@ impl<'a> System<'a> for GameStateSwitcher {
 
 pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
     builder.with(
-        GameStateSwitcher { input_entity: None },
+        GameStateSwitcher {
+            input_entity: None,
+            bindings_version: 0,
+        },
         "game_state_switcher",
         &[input::INPUT_MANAGER_SYSTEM_ID],
     )
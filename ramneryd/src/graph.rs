@@ -110,6 +110,43 @@ pub mod sys {
             .expect("Failed to get entry!");
     }
 
+    /// Detaches the child from its parent, if it has one. The parent keeps its Children only if
+    /// it has other children.
+    pub fn remove_edge<'a>(
+        children_storage: &mut WriteStorage<'a, Children>,
+        parent_storage: &mut WriteStorage<'a, Parent>,
+        child: Entity,
+    ) {
+        let parent = match parent_storage.remove(child) {
+            Some(Parent { parent }) => parent,
+            None => return,
+        };
+
+        if let Some(siblings) = children_storage.get_mut(parent) {
+            siblings.children.retain(|c| *c != child);
+            if siblings.children.is_empty() {
+                children_storage.remove(parent);
+            }
+        }
+    }
+
+    /// If `ancestor` is `node` or on the path from `node` to its root
+    pub fn is_ancestor<PS>(parent_storage: PS, ancestor: Entity, node: Entity) -> bool
+    where
+        PS: storage::GenericReadStorage<Component = Parent>,
+    {
+        let mut cur = node;
+        loop {
+            if cur == ancestor {
+                return true;
+            }
+            match parent_storage.get(cur) {
+                Some(Parent { parent }) => cur = *parent,
+                None => return false,
+            }
+        }
+    }
+
     pub fn breadth_first<CS>(children_storage: CS, root: Entity, mut visit_node: impl FnMut(Entity))
     where
         CS: storage::GenericReadStorage<Component = Children>,
@@ -160,6 +197,52 @@ pub mod world {
         super::sys::add_edge(&mut children_storage, &mut parent_storage, parent, child);
     }
 
+    /// The transform of the entity relative to its root, entities without a Transform count as
+    /// the identity
+    pub fn world_transform(world: &World, ent: Entity) -> Transform {
+        let transforms = world.read_storage::<Transform>();
+        root_to_node_path(world, ent).fold(Transform::identity(), |acc, node| {
+            acc * transforms
+                .get(node)
+                .copied()
+                .unwrap_or_else(Transform::identity)
+        })
+    }
+
+    /// Attaches the child to the parent, or makes it a root if parent is None. The local transform
+    /// of the child is changed so that it stays where it is in the world. Returns false, and does
+    /// nothing, if the parent is the child or one of its descendants.
+    pub fn reparent(world: &mut World, child: Entity, parent: Option<Entity>) -> bool {
+        if let Some(parent) = parent {
+            if super::sys::is_ancestor(&world.read_storage::<Parent>(), child, parent) {
+                return false;
+            }
+        }
+
+        let child_world = world_transform(world, child);
+        let parent_world = parent
+            .map(|parent| world_transform(world, parent))
+            .unwrap_or_else(Transform::identity);
+
+        let mut children_storage = world.write_storage::<Children>();
+        let mut parent_storage = world.write_storage::<Parent>();
+        super::sys::remove_edge(&mut children_storage, &mut parent_storage, child);
+        if let Some(parent) = parent {
+            super::sys::add_edge(&mut children_storage, &mut parent_storage, parent, child);
+        }
+        world
+            .write_storage::<Transform>()
+            .insert(child, parent_world.inverse() * child_world)
+            .expect("Failed to set transform of reparented entity");
+        true
+    }
+
+    /// Makes the child a root, keeping where it is in the world
+    #[allow(dead_code)]
+    pub fn detach(world: &mut World, child: Entity) {
+        reparent(world, child, None);
+    }
+
     #[allow(dead_code)]
     pub fn breadth_first(world: &World, root: Entity, visit_node: impl FnMut(Entity)) {
         let nodes_storage = world.read_storage::<Children>();
@@ -326,6 +409,58 @@ mod tests {
         assert_eq!(order, vec![1, 2, 3, 4, 5, 6, 7]);
     }
 
+    #[test]
+    fn reparent_keeps_world_transform() {
+        use crate::math::{Quat, Transform, Vec3};
+        use vek::approx::assert_abs_diff_eq;
+
+        let mut w = setup_world();
+        w.register::<Transform>();
+        let root = setup_graph(&mut w);
+        let mut order = Vec::new();
+        breadth_first(&w, root, |ent| order.push(ent));
+        // 5 is a child of 2 and 7 of 4
+        let (two, four, five, seven) = (order[1], order[3], order[4], order[6]);
+        {
+            let mut transforms = w.write_storage::<Transform>();
+            let tfm = Transform {
+                position: Vec3::new(1.0, 2.0, 3.0),
+                rotation: Quat::rotation_y(0.5),
+                scale: 2.0,
+            };
+            transforms.insert(two, tfm).unwrap();
+            transforms
+                .insert(five, Transform::pos(1.0, 0.0, 0.0))
+                .unwrap();
+            transforms
+                .insert(four, Transform::pos(0.0, -1.0, 0.0))
+                .unwrap();
+        }
+
+        let before = world_transform(&w, five);
+        assert!(reparent(&mut w, five, Some(four)));
+        assert_eq!(w.read_storage::<Parent>().get(five).unwrap().parent, four);
+        assert!(!w
+            .read_storage::<Children>()
+            .get(two)
+            .unwrap()
+            .contains(&five));
+        assert!(w
+            .read_storage::<Children>()
+            .get(four)
+            .unwrap()
+            .contains(&five));
+        assert_abs_diff_eq!(world_transform(&w, five), before, epsilon = 0.0001);
+
+        // A node can't be attached below itself
+        assert!(!reparent(&mut w, four, Some(seven)));
+        assert!(!reparent(&mut w, four, Some(four)));
+
+        detach(&mut w, five);
+        assert!(w.read_storage::<Parent>().get(five).is_none());
+        assert_abs_diff_eq!(world_transform(&w, five), before, epsilon = 0.0001);
+    }
+
     #[test]
     fn depth_first_traversal() {
         let mut w = setup_world();
@@ -8,7 +8,6 @@ use winit::event::ElementState;
 use winit::event::MouseScrollDelta;
 use winit::event::WindowEvent;
 
-// TODO: Handle resized here as well
 #[derive(Debug)]
 pub enum Event {
     Quit,
@@ -16,6 +15,12 @@ pub enum Event {
     Unfocus,
     Resize(trekanten::util::Extent2D),
     Input(Vec<ExternalInput>),
+    /// The window was moved to a monitor with a different DPI, or the DPI setting changed
+    ScaleFactor(f64),
+    /// The application was suspended by the OS, e.g. minimized on mobile. The surface may be gone
+    /// until Resume.
+    Suspend,
+    Resume,
 }
 
 impl Default for Event {
@@ -33,38 +38,43 @@ impl Event {
             (Unfocus, _) => Unfocus,
             (Focus, Unfocus) => Focus,
             (_, Unfocus) => Unfocus,
-            (Resize(e), _) => Resize(e),
             (Input(mut new), Input(mut old)) => Input({
                 old.append(&mut new);
                 old
             }),
-            (Input(vec), Resize(_)) => Input(vec),
             (Input(vec), Focus) => Input(vec),
             (Focus, Input(v)) => {
                 log::warn!("Spurios focus event received, ignoring");
                 Input(v)
             }
-            // Ignore resize
-            (Focus, Resize(_)) => Focus,
             // Sometimes there are several focus events in a row
             (Focus, Focus) => Focus,
+            // Sent on their own, see EventManager::window_events
+            (Resize(_), _) | (ScaleFactor(_), _) | (Suspend, _) | (Resume, _) => {
+                unreachable!("Window events are not merged")
+            }
+            (_, Resize(_)) | (_, ScaleFactor(_)) | (_, Suspend) | (_, Resume) => {
+                unreachable!("Window events are not merged")
+            }
         }
     }
 }
 
 #[derive(Debug)]
 pub enum EventLoopControl {
-    SendEvent(Event),
+    /// In the order they should be handled
+    SendEvents(Vec<Event>),
     Continue,
     Quit,
 }
 
 pub struct EventManager {
     action: Event,
+    /// Sent before the action, as they can't be merged with it
+    window_events: Vec<Event>,
 }
 
 // TODO:
-// * Don't send resize events as soon as we get them but combine them and send when we don't get any further ones
 // * Inputs should be appended if there is already an input in the queue
 // * Quit/focus/unfocus should be pushed as fast as possible
 
@@ -72,9 +82,18 @@ impl EventManager {
     pub fn new() -> Self {
         Self {
             action: Event::Input(Vec::new()),
+            window_events: Vec::new(),
         }
     }
 
+    /// Only the last size is sent, as resizing the swapchain for each step of a drag is slow
+    fn resized(&mut self, width: u32, height: u32) {
+        self.window_events
+            .retain(|e| !matches!(e, Event::Resize(_)));
+        self.window_events
+            .push(Event::Resize(trekanten::util::Extent2D { width, height }));
+    }
+
     fn update_action(&mut self, action: Event) {
         let cur = std::mem::replace(&mut self.action, Event::Quit);
         self.action = cur.update_with(action);
@@ -100,7 +119,30 @@ impl EventManager {
             WinEvent::WindowEvent {
                 event: WindowEvent::Resized(winit::dpi::PhysicalSize { width, height }),
                 ..
-            } => self.update_action(Event::Resize(trekanten::util::Extent2D { width, height })),
+            } => self.resized(width, height),
+            WinEvent::WindowEvent {
+                event:
+                    WindowEvent::ScaleFactorChanged {
+                        scale_factor,
+                        new_inner_size,
+                    },
+                ..
+            } => {
+                log::debug!("Scale factor changed to {}", scale_factor);
+                self.window_events.push(Event::ScaleFactor(scale_factor));
+                let winit::dpi::PhysicalSize { width, height } = *new_inner_size;
+                self.resized(width, height);
+            }
+            WinEvent::Suspended => {
+                log::debug!("Suspended");
+                self.window_events.push(Event::Suspend);
+                resolve = true;
+            }
+            WinEvent::Resumed => {
+                log::debug!("Resumed");
+                self.window_events.push(Event::Resume);
+                resolve = true;
+            }
             WinEvent::WindowEvent {
                 event: WindowEvent::Focused(is_focused),
                 ..
@@ -205,14 +247,22 @@ impl EventManager {
                 Event::Quit => Event::Quit,
                 Event::Focus => Event::Focus,
                 Event::Unfocus => Event::Unfocus,
-                Event::Resize(e) => Event::Resize(*e),
+                Event::Resize(_) | Event::ScaleFactor(_) | Event::Suspend | Event::Resume => {
+                    unreachable!("Window events are not merged")
+                }
             };
 
             let old = std::mem::replace(&mut self.action, new);
+            let mut events = std::mem::take(&mut self.window_events);
             match old {
-                Event::Input(v) if v.is_empty() => EventLoopControl::Continue,
-                Event::Quit => EventLoopControl::Quit,
-                _ => EventLoopControl::SendEvent(old),
+                Event::Quit => return EventLoopControl::Quit,
+                Event::Input(v) if v.is_empty() => (),
+                _ => events.push(old),
+            }
+            if events.is_empty() {
+                EventLoopControl::Continue
+            } else {
+                EventLoopControl::SendEvents(events)
             }
         } else {
             EventLoopControl::Continue
@@ -247,9 +297,11 @@ pub fn event_thread_work(
     *control_flow = winit::event_loop::ControlFlow::Wait;
 
     match event_manager.collect_event(winit_event) {
-        EventLoopControl::SendEvent(event) => {
-            log::info!("Sending event on queue: {:?}", event);
-            event_queue.push(event)
+        EventLoopControl::SendEvents(events) => {
+            for event in events {
+                log::info!("Sending event on queue: {:?}", event);
+                event_queue.push(event)
+            }
         }
         EventLoopControl::Continue => (),
         EventLoopControl::Quit => {
@@ -66,7 +66,7 @@ pub enum DeviceAxis {
     ScrollY,
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum Button {
     Key(KeyCode),
     Mouse(MouseButton),
@@ -1,5 +1,6 @@
 pub mod event;
 pub mod input;
+pub mod platform;
 use crate::ecs::prelude::*;
 
 use winit::window::Window;
@@ -16,6 +17,9 @@ pub type CommandQueue = std::sync::mpsc::Receiver<Command>;
 
 pub struct MainWindow {
     window: Window,
+    /// From the last resize event, rather than queried from the window, so that it matches what
+    /// the swapchain was resized to
+    extent: trekanten::util::Extent2D,
 }
 
 pub fn window_extents(window: &winit::window::Window) -> trekanten::util::Extent2D {
@@ -33,13 +37,26 @@ impl MainWindow {
     }
 
     pub fn extents(&self) -> trekanten::util::Extent2D {
-        window_extents(&self.window)
+        self.extent
+    }
+
+    pub(crate) fn set_extents(&mut self, extent: trekanten::util::Extent2D) {
+        self.extent = extent;
+    }
+
+    /// Minimized windows have no area, and nothing can be rendered to them
+    pub fn is_minimized(&self) -> bool {
+        self.extent.width == 0 || self.extent.height == 0
     }
 }
 
-pub fn setup(world: &mut World, window: winit::window::Window) {
+/// The window is None when running headless
+pub fn setup(world: &mut World, window: Option<winit::window::Window>) {
     world.insert(input::CurrentFrameExternalInputs(Vec::new()));
-    world.insert(MainWindow { window });
+    if let Some(window) = window {
+        let extent = window_extents(&window);
+        world.insert(MainWindow { window, extent });
+    }
 }
 
 pub fn post_frame(world: &mut World) {
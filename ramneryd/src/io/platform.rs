@@ -0,0 +1,45 @@
+//! The window and event loop of the platform, so that the rest of the engine doesn't depend on how
+//! winit runs its event loop. The event loop has to run on the main thread on some platforms and
+//! never returns, so the engine runs on its own thread and gets the events through the EventQueue.
+
+use std::sync::Arc;
+
+use winit::window::Window;
+
+use super::event::{self, EventManager};
+use super::{CommandQueue, EventQueue};
+
+pub struct EventLoop {
+    inner: winit::event_loop::EventLoop<()>,
+}
+
+impl EventLoop {
+    /// Has to be called on the main thread
+    pub fn new() -> Self {
+        Self {
+            inner: winit::event_loop::EventLoop::new(),
+        }
+    }
+
+    pub fn create_window(&self) -> Result<Window, winit::error::OsError> {
+        winit::window::WindowBuilder::new()
+            .with_maximized(true)
+            .build(&self.inner)
+    }
+
+    /// Sends the events to the engine thread until the window is closed or the engine sends
+    /// Command::Quit
+    pub fn run(self, event_queue: Arc<EventQueue>, commands: CommandQueue) -> ! {
+        profiling::register_thread!("ramneryd::input");
+        let mut event_manager = EventManager::new();
+        self.inner.run(move |winit_event, _, control_flow| {
+            event::event_thread_work(
+                &mut event_manager,
+                Arc::clone(&event_queue),
+                &commands,
+                winit_event,
+                control_flow,
+            );
+        })
+    }
+}
@@ -1,18 +1,35 @@
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 #[macro_use]
 mod macros;
+mod anim;
 pub mod asset;
 mod camera;
+pub mod character;
+mod collision;
 pub mod common;
+mod config_watcher;
+#[macro_use]
+pub mod cvar;
 pub mod ecs;
 mod editor;
+mod file_watcher;
+mod frame_capture;
+mod frame_pacing;
 mod game_state;
 mod graph;
 mod io;
 pub mod math;
+pub mod metadata;
+pub mod navmesh;
+mod play_mode;
 pub mod render;
+pub mod scene;
+mod settings;
+pub mod spatial;
 mod time;
+pub mod warnings;
 
 use time::Time;
 
@@ -25,6 +42,11 @@ use io::event::Event;
 enum State {
     Focused,
     Unfocused,
+    /// Nothing is rendered, as the surface may be gone. Keeps whether the window has focus, which
+    /// is restored on resume.
+    Suspended {
+        focused: bool,
+    },
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -40,10 +62,33 @@ struct Engine {
     ui: render::ui::UIContext,
     state: State,
     control_systems: ecs::Executor<'static, 'static>,
+    simulation_systems: ecs::Executor<'static, 'static>,
     engine_systems: ecs::Executor<'static, 'static>,
     renderer: trekanten::Renderer,
+    frame_limit: Option<usize>,
+    dump_image: Option<PathBuf>,
+    dump_aovs: bool,
+    dump_hdr_image: Option<PathBuf>,
+    panorama: Option<PathBuf>,
+    panorama_eye_separation: Option<f32>,
+    save_scene: Option<PathBuf>,
+    frame_capture: frame_capture::FrameCapture,
+    capture_frame: Option<usize>,
+    frame_limiter: frame_pacing::FrameLimiter,
+    // The trace of the recorded commands of all frames, when tracing
+    command_trace: Option<String>,
+    write_command_trace: Option<PathBuf>,
+    expected_command_trace: Option<PathBuf>,
+    n_frames: usize,
+    /// The number of frames in a row that failed to draw
+    failed_frames: u32,
+    config_watcher: config_watcher::ConfigWatcher,
 }
 
+/// The engine stops if this many frames in a row fail to draw, as the renderer is unlikely to
+/// recover after that
+const MAX_FAILED_FRAMES: u32 = 30;
+
 /* Unused for now
 impl Engine {
     fn take_cursor(&mut self) {
@@ -63,43 +108,179 @@ impl Engine {
 */
 
 impl Engine {
-    fn init_dispatchers<'a, 'b>() -> (Executor<'a, 'b>, Executor<'a, 'b>) {
-        let control_builder = ExecutorBuilder::new();
-        // Input needs to go before as most systems depends on it
-        let control = register_module_systems!(control_builder, io::input, game_state).build();
+    fn new(
+        mut renderer: trekanten::Renderer,
+        frame_capture: frame_capture::FrameCapture,
+        window: Option<winit::window::Window>,
+        event_queue: Arc<io::EventQueue>,
+        modules: Modules,
+        options: &RunOptions,
+    ) -> Result<Self, render::RenderError> {
+        let mut world = World::new();
+        let order_config = match &options.system_order {
+            Some(path) => ecs::introspect::OrderConfig::load(path).unwrap_or_else(|e| {
+                log::error!("Failed to load the system order {}: {}", path.display(), e);
+                Default::default()
+            }),
+            None => Default::default(),
+        };
+        let (mut control_systems, mut simulation_systems, mut engine_systems) =
+            Engine::init_dispatchers(&order_config);
+        world.insert(ecs::introspect::SystemGraph {
+            executors: vec![
+                ecs::introspect::ExecutorInfo {
+                    name: "control",
+                    systems: control_systems.systems().to_vec(),
+                },
+                ecs::introspect::ExecutorInfo {
+                    name: "simulation",
+                    systems: simulation_systems.systems().to_vec(),
+                },
+                ecs::introspect::ExecutorInfo {
+                    name: "engine",
+                    systems: engine_systems.systems().to_vec(),
+                },
+            ],
+        });
+
+        ecs::meta::register_all_components(&mut world);
+
+        world.insert(Time::default());
+        world.insert(frame_pacing::FrameTimes::default());
+        world.insert(cvar::Cvars::default());
+        world.insert(ecs::journal::EntityJournal::default());
+        world.insert(warnings::Warnings::default());
+        world.insert(asset::server::AssetServer::new());
+        world.insert(if options.edit_mode {
+            play_mode::PlayMode::editing()
+        } else {
+            play_mode::PlayMode::default()
+        });
+        ecs::serde::setup_resources(&mut world);
+
+        // The systems build their input contexts from the key bindings in setup
+        let key_bindings = match &options.key_bindings {
+            Some(path) => settings::KeyBindings::load(path).unwrap_or_else(|e| {
+                log::error!("Failed to load key bindings {}: {}", path.display(), e);
+                settings::KeyBindings::default()
+            }),
+            None => settings::KeyBindings::default(),
+        };
+        world.insert(key_bindings);
+
+        control_systems.setup(&mut world);
+        simulation_systems.setup(&mut world);
+        engine_systems.setup(&mut world);
+        let scale_factor = window.as_ref().map(|w| w.scale_factor());
+        io::setup(&mut world, window);
+        render::setup_resources(&mut world, &mut renderer)?;
+        let ui_modules = vec![editor::ui_module(&mut world)];
+        let mut ui = render::ui::UIContext::new(&mut renderer, &mut world, ui_modules);
+        if let Some(scale_factor) = scale_factor {
+            ui.set_scale_factor(scale_factor);
+        }
 
-        let engine_builder = ExecutorBuilder::new();
-        let engine = register_module_systems!(engine_builder, asset, camera, render)
-            .with_barrier()
-            .with(
-                graph::TransformPropagation,
-                graph::TransformPropagation::ID,
-                &[],
-            )
-            .build();
+        if let Some(dir) = &options.templates {
+            match asset::template::Templates::load_dir(dir) {
+                Ok(templates) => {
+                    log::info!(
+                        "Loaded {} templates from {}",
+                        templates.len(),
+                        dir.display()
+                    );
+                    world.insert(templates);
+                }
+                Err(e) => log::error!("Failed to load templates: {}", e),
+            }
+        }
 
-        (control, engine)
-    }
+        for mut m in modules.0.into_iter() {
+            m.init(&mut world);
+        }
 
-    fn next_event(&self) -> Option<Event> {
-        let mut all_inputs = Vec::with_capacity(self.event_queue.len());
-        while let Ok(event) = self.event_queue.pop() {
-            match event {
-                Event::Input(mut inputs) => all_inputs.append(&mut inputs),
-                e => return Some(e),
+        if let Some(path) = &options.config {
+            match cvar::load_config(&world, path) {
+                Ok(n) => log::info!("Set {} cvars from {}", n, path.display()),
+                Err(e) => log::error!("Failed to load config {}: {}", path.display(), e),
             }
         }
 
-        if all_inputs.is_empty() {
-            None
-        } else {
-            Some(Event::Input(all_inputs))
+        for assignment in options.cvars.iter() {
+            if let Err(e) = cvar::set_from_str(&world, assignment) {
+                log::error!("Failed to set {}: {}", assignment, e);
+            }
         }
+
+        if let Some(path) = &options.load_scene {
+            if let Err(e) = scene::load(&mut world, path) {
+                log::error!("Failed to load scene {}: {}", path.display(), e);
+            }
+        }
+
+        let tracing = options.command_trace.is_some() || options.expected_command_trace.is_some();
+        renderer.set_command_tracing(tracing);
+
+        Ok(Engine {
+            world,
+            ui,
+            event_queue,
+            state: State::Focused,
+            control_systems,
+            simulation_systems,
+            engine_systems,
+            renderer,
+            frame_limit: options.frame_limit,
+            dump_image: options.dump_image.clone(),
+            dump_aovs: options.dump_aovs,
+            dump_hdr_image: options.dump_hdr_image.clone(),
+            panorama: options.panorama.clone(),
+            panorama_eye_separation: options.panorama_eye_separation,
+            save_scene: options.save_scene.clone(),
+            frame_capture,
+            capture_frame: options.capture_frame,
+            frame_limiter: frame_pacing::FrameLimiter::default(),
+            command_trace: if tracing { Some(String::new()) } else { None },
+            write_command_trace: options.command_trace.clone(),
+            expected_command_trace: options.expected_command_trace.clone(),
+            n_frames: 0,
+            failed_frames: 0,
+            config_watcher: config_watcher::ConfigWatcher::new(
+                options.config.as_deref(),
+                options.key_bindings.as_deref(),
+            ),
+        })
+    }
+
+    fn init_dispatchers<'a, 'b>(
+        order_config: &ecs::introspect::OrderConfig,
+    ) -> (Executor<'a, 'b>, Executor<'a, 'b>, Executor<'a, 'b>) {
+        let control_builder = ExecutorBuilder::new().with_order_config(order_config.clone());
+        // Input needs to go before as most systems depends on it
+        let control = register_module_systems!(control_builder, io::input, game_state).build();
+
+        // Only runs while simulating, see play_mode
+        let simulation_builder = ExecutorBuilder::new().with_order_config(order_config.clone());
+        let simulation = register_module_systems!(simulation_builder, anim, character).build();
+
+        let engine_builder = ExecutorBuilder::new().with_order_config(order_config.clone());
+        let engine =
+            register_module_systems!(engine_builder, asset, camera, navmesh, scene, render)
+                .with_barrier()
+                .with(
+                    graph::TransformPropagation,
+                    graph::TransformPropagation::ID,
+                    &[],
+                );
+        let engine = anim::register_post_transform_systems(engine);
+        let engine = spatial::register_post_transform_systems(engine).build();
+
+        (control, simulation, engine)
     }
 
     #[profiling::function]
     fn post_frame(&mut self) {
         self.world.maintain();
+        ecs::journal::record(&self.world);
         io::post_frame(&mut self.world);
     }
 
@@ -107,26 +288,59 @@ impl Engine {
     fn pre_frame(&mut self) -> Action {
         self.world.write_resource::<Time>().tick();
 
-        match self.next_event() {
-            Some(Event::Quit) => return Action::Quit,
-            Some(Event::Focus) => self.state = State::Focused,
-            Some(Event::Unfocus) => {
-                self.state = State::Unfocused;
-                *self.world.write_resource::<GameState>() = GameState::Paused;
-            }
-            Some(Event::Input(input)) => {
-                let mut cur_inputs = self
-                    .world
-                    .write_resource::<io::input::CurrentFrameExternalInputs>();
-                *cur_inputs = io::input::CurrentFrameExternalInputs(input);
+        let mut inputs = Vec::new();
+        while let Ok(event) = self.event_queue.pop() {
+            match event {
+                Event::Quit => return Action::Quit,
+                Event::Focus => match &mut self.state {
+                    State::Suspended { focused } => *focused = true,
+                    state => *state = State::Focused,
+                },
+                Event::Unfocus => {
+                    match &mut self.state {
+                        State::Suspended { focused } => *focused = false,
+                        state => *state = State::Unfocused,
+                    }
+                    *self.world.write_resource::<GameState>() = GameState::Paused;
+                }
+                Event::Suspend => {
+                    let focused = match self.state {
+                        State::Focused => true,
+                        State::Unfocused => false,
+                        State::Suspended { focused } => focused,
+                    };
+                    self.state = State::Suspended { focused };
+                    *self.world.write_resource::<GameState>() = GameState::Paused;
+                }
+                Event::Resume => {
+                    if let State::Suspended { focused } = self.state {
+                        self.state = if focused {
+                            State::Focused
+                        } else {
+                            State::Unfocused
+                        };
+                    }
+                }
+                Event::ScaleFactor(scale_factor) => self.ui.set_scale_factor(scale_factor),
+                Event::Input(mut input) => inputs.append(&mut input),
+                Event::Resize(extent) => self.resize(extent),
             }
-            // TODO: Don't ignore resizes
-            None | Some(Event::Resize(_)) => (),
+        }
+        if !inputs.is_empty() {
+            *self
+                .world
+                .write_resource::<io::input::CurrentFrameExternalInputs>() =
+                io::input::CurrentFrameExternalInputs(inputs);
         }
 
         let focused = self.state == State::Focused;
+        let minimized = self
+            .world
+            .try_fetch::<io::MainWindow>()
+            .map(|w| w.is_minimized())
+            .unwrap_or(false);
 
-        if !focused {
+        if !focused || minimized {
             return Action::SkipFrame;
         }
 
@@ -135,25 +349,250 @@ impl Engine {
         Action::ContinueFrame
     }
 
+    /// Resizes the swapchain right away, instead of when presenting fails, so that the projection
+    /// and the ui match the window from the next frame
+    fn resize(&mut self, extent: trekanten::util::Extent2D) {
+        log::debug!("Window resized to {}", extent);
+        if let Some(mut window) = self.world.try_fetch_mut::<io::MainWindow>() {
+            window.set_extents(extent);
+        }
+
+        if extent.width == 0 || extent.height == 0 || extent == self.renderer.swapchain_extent() {
+            return;
+        }
+
+        if let Err(e) = self.renderer.resize(extent) {
+            log::error!("Failed to resize the renderer to {}: {}", extent, e);
+        }
+    }
+
+    fn write_presented_image(&self, path: &Path) {
+        match self.renderer.read_presented_image() {
+            Ok(Some(image)) => match image.save(path) {
+                Ok(()) => log::info!("Wrote the last frame to {}", path.display()),
+                Err(e) => log::error!("Failed to write {}: {}", path.display(), e),
+            },
+            Ok(None) => log::error!(
+                "No image to write to {}, only headless rendering can be dumped",
+                path.display()
+            ),
+            Err(e) => log::error!("Failed to read back the last frame: {}", e),
+        }
+    }
+
+    fn write_aovs(&mut self, screenshot: &Path) {
+        let paths = render::aov::capture(
+            &mut self.world,
+            &mut self.ui,
+            &mut self.renderer,
+            screenshot,
+        );
+        match paths {
+            Ok(paths) => {
+                for path in paths {
+                    log::info!("Wrote {}", path.display());
+                }
+            }
+            Err(e) => log::error!("Failed to write the auxiliary images: {}", e),
+        }
+    }
+
+    fn write_panorama(&mut self, path: &Path) {
+        let panorama = render::panorama::capture(
+            &mut self.world,
+            &mut self.ui,
+            &mut self.renderer,
+            self.panorama_eye_separation,
+        );
+        match panorama {
+            Ok(image) => match image.save(path) {
+                Ok(()) => log::info!("Wrote the panorama to {}", path.display()),
+                Err(e) => log::error!("Failed to write {}: {}", path.display(), e),
+            },
+            Err(e) => log::error!("Failed to capture the panorama: {}", e),
+        }
+    }
+
+    /// Writes the trace and compares it to the expected one. Returns false if they differ.
+    fn finish_command_trace(&self) -> bool {
+        let trace = match &self.command_trace {
+            Some(trace) => trace,
+            None => return true,
+        };
+
+        if let Some(path) = &self.write_command_trace {
+            match std::fs::write(path, trace) {
+                Ok(()) => log::info!("Wrote the command trace to {}", path.display()),
+                Err(e) => log::error!("Failed to write {}: {}", path.display(), e),
+            }
+        }
+
+        let path = match &self.expected_command_trace {
+            Some(path) => path,
+            None => return true,
+        };
+        let expected = match std::fs::read_to_string(path) {
+            Ok(expected) => expected,
+            Err(e) => {
+                log::error!("Failed to read {}: {}", path.display(), e);
+                return false;
+            }
+        };
+        match trekanten::trace::compare(&expected, trace) {
+            Ok(()) => {
+                log::info!("The command trace matches {}", path.display());
+                true
+            }
+            Err(e) => {
+                log::error!("The command trace differs from {}: {}", path.display(), e);
+                false
+            }
+        }
+    }
+
+    /// Returns false if rendering failed or the command trace differs from the expected one
     #[profiling::function]
-    fn run(&mut self) {
+    fn run(&mut self) -> bool {
+        let rendered = self.main_loop();
+        let trace_matches = self.finish_command_trace();
+
+        if let Some(path) = self.dump_image.clone() {
+            self.write_presented_image(&path);
+            if self.dump_aovs {
+                self.write_aovs(&path);
+            }
+        } else if self.dump_aovs {
+            log::error!("Can't write the auxiliary images without an image to dump");
+        }
+
+        if let Some(path) = &self.dump_hdr_image {
+            match render::hdr_export::save(&self.world, &self.renderer, path) {
+                Ok(()) => log::info!("Wrote the scene color to {}", path.display()),
+                Err(e) => log::error!("Failed to write {}: {}", path.display(), e),
+            }
+        }
+
+        if let Some(path) = self.panorama.clone() {
+            self.write_panorama(&path);
+        }
+
+        if let Some(path) = &self.save_scene {
+            match scene::save(&self.world, path) {
+                Ok(()) => log::info!("Saved the scene to {}", path.display()),
+                Err(e) => log::error!("Failed to save scene {}: {}", path.display(), e),
+            }
+        }
+
+        rendered && trace_matches
+    }
+
+    /// Skips the frame that failed to draw and recovers the renderer for the next one, with a new
+    /// device if it was lost. The error is listed in the Warnings window. Returns false if the
+    /// renderer could not be recovered or too many frames in a row have failed.
+    fn recover(&mut self, e: render::RenderError) -> bool {
+        self.failed_frames += 1;
+        log::error!("Skipping frame {}: {}", self.n_frames, e);
+        self.world
+            .write_resource::<warnings::Warnings>()
+            .push("Render", None, e.to_string());
+        if self.failed_frames >= MAX_FAILED_FRAMES {
+            log::error!("Giving up after {} failed frames", self.failed_frames);
+            return false;
+        }
+
+        if self.renderer.is_device_lost() {
+            log::error!("The device was lost");
+            if let Err(e) =
+                render::recreate_device(&mut self.world, &mut self.ui, &mut self.renderer)
+            {
+                log::error!("{}", e);
+                return false;
+            }
+            return true;
+        }
+
+        let extent = match self.world.try_fetch::<io::MainWindow>() {
+            Some(window) => window.extents(),
+            None => self.renderer.swapchain_extent(),
+        };
+        if let Err(e) = self.renderer.recover(extent) {
+            log::error!("Failed to recover the renderer: {}", e);
+            return false;
+        }
+        true
+    }
+
+    /// Returns false if it stopped because rendering failed
+    fn main_loop(&mut self) -> bool {
         loop {
             profiling::scope!("main_loop");
+            if matches!(self.frame_limit, Some(limit) if self.n_frames >= limit) {
+                log::info!("Reached the frame limit of {}", self.n_frames);
+                return true;
+            }
             match self.pre_frame() {
-                Action::Quit => return,
+                Action::Quit => return true,
                 Action::SkipFrame => continue,
                 Action::ContinueFrame => (),
             }
+            let dt = self.world.read_resource::<Time>().delta_sim();
+            self.world
+                .write_resource::<frame_pacing::FrameTimes>()
+                .push(dt);
+
+            self.config_watcher.poll(&self.world);
+            cvar::sync(&self.world);
 
             self.control_systems.execute(&self.world);
             let state = *self.world.read_resource::<GameState>();
             if let GameState::Running = state {
+                let simulating = self
+                    .world
+                    .read_resource::<play_mode::PlayMode>()
+                    .is_simulating();
+                if simulating {
+                    self.simulation_systems.execute(&self.world);
+                }
                 self.engine_systems.execute(&self.world);
             }
-            render::draw_frame(&mut self.world, &mut self.ui, &mut self.renderer);
+
+            let requested = std::mem::take(
+                &mut self
+                    .world
+                    .write_resource::<render::debug_window::RenderSettings>()
+                    .capture_frame,
+            );
+            let capturing = (requested || self.capture_frame == Some(self.n_frames))
+                && self.frame_capture.start();
+            let drawn = render::draw_frame(&mut self.world, &mut self.ui, &mut self.renderer);
+            if capturing {
+                self.frame_capture.end();
+            }
+            match drawn {
+                Ok(()) => self.failed_frames = 0,
+                Err(e) => {
+                    if !self.recover(e) {
+                        return false;
+                    }
+                }
+            }
+            if let (Some(trace), Some(commands)) = (
+                self.command_trace.as_mut(),
+                self.renderer.take_command_trace(),
+            ) {
+                trace.push_str(&format!("frame {}\n", self.n_frames));
+                trace.push_str(&trekanten::trace::format_frame(&commands));
+            }
 
             self.post_frame();
+            let max_fps = self
+                .world
+                .read_resource::<render::debug_window::RenderSettings>()
+                .max_fps;
+            self.frame_limiter.wait(max_fps);
             profiling::finish_frame!();
+
+            self.n_frames += 1;
         }
     }
 }
@@ -165,22 +604,145 @@ pub trait Module: Send {
 
 pub struct Modules(pub Vec<Box<dyn Module>>);
 
+/// Controls how the engine runs, as opposed to the modules that control what it runs
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Render to offscreen images instead of a window. No window or surface is created.
+    pub headless: bool,
+    /// Exit after this many frames. With 0, it exits before rendering any.
+    pub frame_limit: Option<usize>,
+    /// Write the last rendered frame to this file on exit. Requires `headless`.
+    pub dump_image: Option<PathBuf>,
+    /// Also write the linear depth, world normals and entity ids of the view next to `dump_image`,
+    /// as OpenEXR files, see render::aov
+    pub dump_aovs: bool,
+    /// Write the scene color of the last frame, before tonemapping, to this .exr or .hdr file on
+    /// exit
+    pub dump_hdr_image: Option<PathBuf>,
+    /// Write a 360 degree equirectangular panorama from the camera to this file on exit. Requires
+    /// `headless`.
+    pub panorama: Option<PathBuf>,
+    /// Write the panorama as a top-bottom stereo pair, left eye on top, with this distance between
+    /// the eyes
+    pub panorama_eye_separation: Option<f32>,
+    /// Load this scene after the modules have been initialized
+    pub load_scene: Option<PathBuf>,
+    /// Save the scene to this file on exit
+    pub save_scene: Option<PathBuf>,
+    /// Capture this frame, counting from 0, with RenderDoc
+    pub capture_frame: Option<usize>,
+    /// Set the cvars of this config file, see cvar::load_config. Edits to it are applied while
+    /// running, see config_watcher.
+    pub config: Option<PathBuf>,
+    /// `name=value` pairs of cvars to set, after the config file
+    pub cvars: Vec<String>,
+    /// Load the entity templates in this directory, see asset::template
+    pub templates: Option<PathBuf>,
+    /// Load the key bindings from this RON file, see settings::KeyBindings. Rebinding in the ui
+    /// saves them to it.
+    pub key_bindings: Option<PathBuf>,
+    /// Start with the simulation stopped, until Play is pressed in the editor, see play_mode
+    pub edit_mode: bool,
+    /// Adjust the order of the systems with this RON file, see ecs::introspect::OrderConfig
+    pub system_order: Option<PathBuf>,
+    /// Write the commands recorded for each frame to this file on exit, see trekanten::trace
+    pub command_trace: Option<PathBuf>,
+    /// Compare the commands recorded for each frame to this trace on exit. A headless run exits
+    /// with an error if they differ.
+    pub expected_command_trace: Option<PathBuf>,
+    /// Enable the vulkan validation layers and name the vulkan objects, see
+    /// trekanten::RendererOptions
+    pub vk_debug: bool,
+}
+
+const HEADLESS_EXTENT: trekanten::util::Extent2D = trekanten::util::Extent2D {
+    width: 1280,
+    height: 720,
+};
+
 pub fn run(modules: Modules) -> ! {
+    run_with_options(modules, RunOptions::default())
+}
+
+pub fn run_with_options(modules: Modules, options: RunOptions) -> ! {
     env_logger::init();
 
     #[cfg(feature = "profile-with-puffin")]
     profiling::puffin::set_scopes_on(true);
 
-    let event_loop = winit::event_loop::EventLoop::new();
-    let window = winit::window::WindowBuilder::new()
-        .with_maximized(true)
-        .build(&event_loop)
-        .expect("Failed to create window");
+    if options.headless {
+        run_headless(modules, options)
+    } else {
+        run_windowed(modules, options)
+    }
+}
+
+/// Keep the vulkan pipeline cache between runs so that the driver does not have to compile the
+/// pipelines again
+fn use_pipeline_cache_file(renderer: &mut trekanten::Renderer) {
+    let path = std::env::temp_dir().join("ramneryd-pipeline-cache.bin");
+    if let Err(e) = renderer.set_pipeline_cache_file(&path) {
+        log::warn!(
+            "Failed to load the pipeline cache from {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+fn renderer_options(options: &RunOptions) -> trekanten::RendererOptions {
+    trekanten::RendererOptions {
+        vk_debug: options.vk_debug,
+    }
+}
+
+fn run_headless(modules: Modules, options: RunOptions) -> ! {
+    if options.frame_limit.is_none() {
+        log::warn!("Running headless without a frame limit, this will not exit on its own");
+    }
+
+    let frame_capture = frame_capture::FrameCapture::new();
+    let mut renderer =
+        trekanten::Renderer::new_headless_with_options(HEADLESS_EXTENT, renderer_options(&options))
+            .expect("Failed to create headless renderer");
+    use_pipeline_cache_file(&mut renderer);
+    // Without a window, nothing is ever pushed to the queue
+    let event_queue = Arc::new(io::EventQueue::new());
+
+    profiling::register_thread!("ramneryd::engine");
+    let trace_matches = match Engine::new(
+        renderer,
+        frame_capture,
+        None,
+        event_queue,
+        modules,
+        &options,
+    ) {
+        Ok(mut engine) => engine.run(),
+        Err(e) => {
+            log::error!("{}", e);
+            false
+        }
+    };
+
+    log::info!("Headless run exiting");
+    std::process::exit(if trace_matches { 0 } else { 1 })
+}
+
+fn run_windowed(modules: Modules, options: RunOptions) -> ! {
+    let event_loop = io::platform::EventLoop::new();
+    let window = event_loop.create_window().expect("Failed to create window");
 
     let event_queue_recv = Arc::new(io::EventQueue::new());
     let event_queue_send = Arc::clone(&event_queue_recv);
-    let mut renderer = trekanten::Renderer::new(&window, io::window_extents(&window))
-        .expect("Failed to create renderer");
+    let frame_capture = frame_capture::FrameCapture::new();
+    let mut renderer = trekanten::Renderer::new_with_options(
+        &window,
+        io::window_extents(&window),
+        renderer_options(&options),
+    )
+    .expect("Failed to create renderer");
+    use_pipeline_cache_file(&mut renderer);
     let (send, recv) = std::sync::mpsc::channel();
 
     // Thread runs the app while main takes the event loop
@@ -190,35 +752,19 @@ pub fn run(modules: Modules) -> ! {
         .spawn(move || {
             profiling::register_thread!("ramneryd::engine");
 
-            let mut world = World::new();
-            let (mut control_systems, mut engine_systems) = Engine::init_dispatchers();
-
-            ecs::meta::register_all_components(&mut world);
-
-            world.insert(Time::default());
-            ecs::serde::setup_resources(&mut world);
-
-            control_systems.setup(&mut world);
-            engine_systems.setup(&mut world);
-            io::setup(&mut world, window);
-            render::setup_resources(&mut world, &mut renderer);
-            let ui_modules = vec![editor::ui_module()];
-            let ui = render::ui::UIContext::new(&mut renderer, &mut world, ui_modules);
-
-            for mut m in modules.0.into_iter() {
-                m.init(&mut world);
-            }
-
-            Engine {
-                world,
-                ui,
-                event_queue: event_queue_recv,
-                state: State::Focused,
-                control_systems,
-                engine_systems,
+            match Engine::new(
                 renderer,
+                frame_capture,
+                Some(window),
+                event_queue_recv,
+                modules,
+                &options,
+            ) {
+                Ok(mut engine) => {
+                    engine.run();
+                }
+                Err(e) => log::error!("{}", e),
             }
-            .run();
 
             if let Err(e) = send.send(io::Command::Quit) {
                 log::error!("Failed to send quit command to event thread: {}", e);
@@ -227,15 +773,5 @@ pub fn run(modules: Modules) -> ! {
         })
         .expect("Failed to start engine thread");
 
-    profiling::register_thread!("ramneryd::input");
-    let mut event_manager = io::event::EventManager::new();
-    event_loop.run(move |winit_event, _, control_flow| {
-        io::event::event_thread_work(
-            &mut event_manager,
-            Arc::clone(&event_queue_send),
-            &recv,
-            winit_event,
-            control_flow,
-        );
-    });
+    event_loop.run(event_queue_send, recv)
 }
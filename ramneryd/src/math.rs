@@ -1,7 +1,20 @@
+//! Math types and the coordinate conventions of the engine:
+//!
+//! - World space is right-handed with y up.
+//! - In view space the eye is at the origin and looks down -z, with x to the right and y up.
+//!   world_to_view and look_to give the matrix from world to view space.
+//! - Clip space is the vulkan one, see perspective_vk: depth goes from 0 at the near plane to 1 at
+//!   the far plane and y points down, so the top-left corner of the screen is (-1, -1) in
+//!   normalized device coordinates. ndc_from_pixel converts from pixels with the origin in the
+//!   top-left corner.
+//! - Yaw and pitch are in radians. Yaw 0 looks along +x and increases towards +z, pitch is the
+//!   angle above the horizon, see direction_from_yaw_pitch.
+
 use crate::ecs::prelude::*;
 
 use serde::{Deserialize, Serialize};
 
+pub type Vec2 = vek::Vec2<f32>;
 pub type Vec3 = vek::Vec3<f32>;
 pub type Vec4 = vek::Vec4<f32>;
 pub type Mat4 = vek::Mat4<f32>;
@@ -10,7 +23,7 @@ pub type Rgb = vek::Rgb<f32>;
 pub type Rgba = vek::Rgba<f32>;
 
 #[derive(Debug, Copy, Component, Clone, PartialEq, Serialize, Deserialize)]
-#[component(inspect)]
+#[component(inspect, snapshot)]
 pub struct Transform {
     pub position: Vec3,
     pub rotation: Quat,
@@ -32,6 +45,52 @@ impl Transform {
             ..Self::identity()
         }
     }
+
+    /// Decomposes a matrix made of a translation, a rotation and a uniform scale, like a
+    /// ModelMatrix. A non-uniform scale is averaged over the axes.
+    pub fn from_matrix(m: &Mat4) -> Self {
+        let axes = [m.cols.x.xyz(), m.cols.y.xyz(), m.cols.z.xyz()];
+        let scale = axes.iter().map(|a| a.magnitude()).sum::<f32>() / 3.0;
+        let [x, y, z] = axes;
+        Self {
+            position: m.cols.w.xyz(),
+            rotation: rotation_from_axes(x.normalized(), y.normalized(), z.normalized()),
+            scale,
+        }
+    }
+
+    /// t * t.inverse() is the identity
+    pub fn inverse(&self) -> Self {
+        let rotation = self.rotation.normalized().conjugate();
+        let scale = 1.0 / self.scale;
+        Self {
+            position: rotation * (-self.position * scale),
+            rotation,
+            scale,
+        }
+    }
+}
+
+/// The rotation that takes the x, y and z axes to the orthonormal x, y and z. Based on "Converting
+/// a Rotation Matrix to a Quaternion" by Mike Day, which avoids dividing by small numbers.
+fn rotation_from_axes(x: Vec3, y: Vec3, z: Vec3) -> Quat {
+    let (q, t) = if z.z < 0.0 {
+        if x.x > y.y {
+            let t = 1.0 + x.x - y.y - z.z;
+            (Quat::from_xyzw(t, x.y + y.x, z.x + x.z, y.z - z.y), t)
+        } else {
+            let t = 1.0 - x.x + y.y - z.z;
+            (Quat::from_xyzw(x.y + y.x, t, y.z + z.y, z.x - x.z), t)
+        }
+    } else if x.x < -y.y {
+        let t = 1.0 - x.x - y.y + z.z;
+        (Quat::from_xyzw(z.x + x.z, y.z + z.y, t, x.y - y.x), t)
+    } else {
+        let t = 1.0 + x.x + y.y + z.z;
+        (Quat::from_xyzw(y.z - z.y, z.x - x.z, x.y - y.x, t), t)
+    };
+
+    (q * (0.5 / t.sqrt())).normalized()
 }
 
 impl vek::approx::AbsDiffEq for Transform {
@@ -142,6 +201,141 @@ impl BoundingBox {
         self.min = Vec3::partial_min(self.min, other.min);
         self.max = Vec3::partial_max(self.max, other.max);
     }
+
+    /// None if there are no points
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        points.into_iter().fold(None, |acc, p| {
+            let mut bbox = BoundingBox { min: p, max: p };
+            if let Some(acc) = acc {
+                bbox.combine(acc);
+            }
+            Some(bbox)
+        })
+    }
+
+    /// The box that contains this box after it has been transformed by m. Unlike
+    /// Mat4 * BoundingBox, all corners are transformed so this is also correct for rotations.
+    pub fn transformed(&self, m: &Mat4) -> Self {
+        let corners = (0..8).map(|i| {
+            let corner = Vec3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            );
+            (*m * Vec4::from_point(corner)).xyz()
+        });
+
+        Self::from_points(corners).expect("A box has corners")
+    }
+
+    pub fn volume(&self) -> f32 {
+        let size = self.max - self.min;
+        size.x * size.y * size.z
+    }
+
+    /// The sphere through the corners
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere {
+            center: (self.min + self.max) * 0.5,
+            radius: (self.max - self.min).magnitude() * 0.5,
+        }
+    }
+}
+
+/// A sphere around a mesh, in its model space like BoundingBox. Tighter than the box for round
+/// meshes and cheaper to test against a frustum, see BoundingVolume.
+#[derive(Debug, Clone, Copy, PartialEq, Component)]
+#[component(inspect)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Ritter's approximation, which is usually 5-20% larger than the smallest sphere. None if
+    /// there are no points.
+    pub fn from_points(points: &[Vec3]) -> Option<Self> {
+        let farthest_from = |from: Vec3| {
+            points.iter().copied().fold(from, |acc, p| {
+                if (p - from).magnitude_squared() > (acc - from).magnitude_squared() {
+                    p
+                } else {
+                    acc
+                }
+            })
+        };
+        let a = farthest_from(*points.first()?);
+        let b = farthest_from(a);
+        let mut sphere = Self {
+            center: (a + b) * 0.5,
+            radius: (b - a).magnitude() * 0.5,
+        };
+
+        // Grow the sphere to just contain the points that are outside it
+        for p in points {
+            let d = (*p - sphere.center).magnitude();
+            if d > sphere.radius {
+                let radius = (sphere.radius + d) * 0.5;
+                sphere.center += (*p - sphere.center) * ((radius - sphere.radius) / d);
+                sphere.radius = radius;
+            }
+        }
+
+        Some(sphere)
+    }
+
+    /// The sphere that contains this sphere after it has been transformed by m. The radius is
+    /// scaled by the largest scale of m.
+    pub fn transformed(&self, m: &Mat4) -> Self {
+        let scale = m
+            .cols
+            .x
+            .xyz()
+            .magnitude()
+            .max(m.cols.y.xyz().magnitude())
+            .max(m.cols.z.xyz().magnitude());
+        Self {
+            center: (*m * Vec4::from_point(self.center)).xyz(),
+            radius: self.radius * scale,
+        }
+    }
+
+    pub fn volume(&self) -> f32 {
+        4.0 / 3.0 * std::f32::consts::PI * self.radius.powi(3)
+    }
+}
+
+/// Whichever of the bounds of a mesh is tighter
+#[derive(Debug, Clone, Copy)]
+pub enum BoundingVolume {
+    Box(BoundingBox),
+    Sphere(BoundingSphere),
+}
+
+impl BoundingVolume {
+    /// The bound with the smallest volume. Compare them in the space that they are tested in, e.g.
+    /// world space, as the box grows when it is rotated but the sphere does not.
+    pub fn tightest(bbox: BoundingBox, sphere: Option<BoundingSphere>) -> Self {
+        match sphere {
+            Some(sphere) if sphere.volume() < bbox.volume() => Self::Sphere(sphere),
+            _ => Self::Box(bbox),
+        }
+    }
+
+    /// The volume transformed by m, see BoundingBox::transformed and BoundingSphere::transformed
+    pub fn transformed(&self, m: &Mat4) -> Self {
+        match self {
+            Self::Box(bbox) => Self::Box(bbox.transformed(m)),
+            Self::Sphere(sphere) => Self::Sphere(sphere.transformed(m)),
+        }
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        match self {
+            Self::Box(bbox) => bbox.bounding_sphere(),
+            Self::Sphere(sphere) => *sphere,
+        }
+    }
 }
 
 impl std::ops::Mul<BoundingBox> for Mat4 {
@@ -164,6 +358,115 @@ where
     }
 }
 
+/// The planes of a view frustum, with normals pointing inwards. xyz is the normal and w the
+/// distance.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a projection matrix with a [0, 1] depth range, e.g. from
+    /// perspective_vk. Based on Gribb & Hartmann, "Fast Extraction of Viewing Frustum Planes from
+    /// the World-View-Projection Matrix".
+    pub fn from_view_proj(m: Mat4) -> Self {
+        let row = |i: usize| m.transposed().cols[i];
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+        let mut planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2];
+        for p in planes.iter_mut() {
+            *p /= p.xyz().magnitude();
+        }
+
+        Self { planes }
+    }
+
+    pub fn intersects_sphere(&self, center: Vec3, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|p| p.xyz().dot(center) + p.w >= -radius)
+    }
+
+    /// Conservative, a box outside the frustum but close to a corner of it may intersect
+    pub fn intersects_box(&self, bbox: &BoundingBox) -> bool {
+        self.planes.iter().all(|p| {
+            // The corner that is furthest along the normal
+            let corner = Vec3::new(
+                if p.x >= 0.0 { bbox.max.x } else { bbox.min.x },
+                if p.y >= 0.0 { bbox.max.y } else { bbox.min.y },
+                if p.z >= 0.0 { bbox.max.z } else { bbox.min.z },
+            );
+            p.xyz().dot(corner) + p.w >= 0.0
+        })
+    }
+
+    pub fn intersects_volume(&self, volume: &BoundingVolume) -> bool {
+        match volume {
+            BoundingVolume::Box(bbox) => self.intersects_box(bbox),
+            BoundingVolume::Sphere(sphere) => self.intersects_sphere(sphere.center, sphere.radius),
+        }
+    }
+
+    /// If the volume that a sphere sweeps when it moves from start to end intersects. Conservative,
+    /// like intersects_box.
+    pub fn intersects_swept_sphere(&self, start: Vec3, end: Vec3, radius: f32) -> bool {
+        self.planes.iter().all(|p| {
+            let d = (p.xyz().dot(start) + p.w).max(p.xyz().dot(end) + p.w);
+            d >= -radius
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+impl Ray {
+    /// Not normalized, so that distances along the transformed ray are the same as along this one
+    pub fn transformed(&self, m: Mat4) -> Self {
+        Self {
+            origin: (m * Vec4::from_point(self.origin)).xyz(),
+            direction: (m * Vec4::from_direction(self.direction)).xyz(),
+        }
+    }
+
+    /// Distance along the ray to the closest intersection with the box, if any
+    pub fn intersect(&self, bbox: &BoundingBox) -> Option<f32> {
+        self.intersect_with_normal(bbox).map(|(t, _)| t)
+    }
+
+    /// Also returns the normal of the side of the box that is hit, which is zero if the ray starts
+    /// inside the box.
+    pub fn intersect_with_normal(&self, bbox: &BoundingBox) -> Option<(f32, Vec3)> {
+        let mut t_min = 0.0f32;
+        let mut t_max = std::f32::INFINITY;
+        let mut normal = Vec3::zero();
+        for i in 0..3 {
+            let inv_d = 1.0 / self.direction[i];
+            let t0 = (bbox.min[i] - self.origin[i]) * inv_d;
+            let t1 = (bbox.max[i] - self.origin[i]) * inv_d;
+            let (t0, t1, side) = if inv_d < 0.0 {
+                (t1, t0, 1.0)
+            } else {
+                (t0, t1, -1.0)
+            };
+            if t0 > t_min {
+                t_min = t0;
+                normal = Vec3::zero();
+                normal[i] = side;
+            }
+            t_max = t_max.min(t1);
+            if t_max < t_min {
+                return None;
+            }
+        }
+
+        Some((t_min, normal))
+    }
+}
+
+/// The view to clip space projection, with the vulkan depth range and y direction
 pub fn perspective_vk(fov_y_radians: f32, aspect_ratio: f32, near: f32, far: f32) -> Mat4 {
     let mut m = Mat4::perspective_rh_zo(fov_y_radians, aspect_ratio, near, far);
     // vulkan has the y-axis
@@ -173,10 +476,98 @@ pub fn perspective_vk(fov_y_radians: f32, aspect_ratio: f32, near: f32, far: f32
     m
 }
 
+/// The world to view matrix of an eye with the transform, e.g. a camera or a spotlight. It looks
+/// down its local -z.
+pub fn world_to_view(eye: &Transform) -> Mat4 {
+    Mat4::from(eye.inverse())
+}
+
+/// The world to view matrix of an eye at pos that looks in view_dir. up can't be parallel to
+/// view_dir.
+pub fn look_to(pos: Vec3, view_dir: Vec3, up: Vec3) -> Mat4 {
+    // Gram-Schmidt, see https://learnopengl.com/Getting-started/Camera
+    let right = view_dir.cross(up).normalized();
+    let up = right.cross(view_dir).normalized();
+
+    // The inverse of the rotation is its transpose, with -z along view_dir
+    let rotation_inv = Mat4::new(
+        right.x,
+        right.y,
+        right.z,
+        0.0,
+        up.x,
+        up.y,
+        up.z,
+        0.0,
+        -view_dir.x,
+        -view_dir.y,
+        -view_dir.z,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        1.0,
+    );
+
+    rotation_inv * Mat4::translation_3d(-pos)
+}
+
+/// The direction of an eye with the yaw and pitch, without roll. Normalized.
+pub fn direction_from_yaw_pitch(yaw: f32, pitch: f32) -> Vec3 {
+    Vec3::new(
+        yaw.cos() * pitch.cos(),
+        pitch.sin(),
+        yaw.sin() * pitch.cos(),
+    )
+}
+
+/// The inverse of direction_from_yaw_pitch. The yaw is in [-pi, pi], and 0 when looking straight up
+/// or down.
+pub fn yaw_pitch_from_direction(dir: Vec3) -> (f32, f32) {
+    let dir = dir.normalized();
+    let yaw = if dir.x == 0.0 && dir.z == 0.0 {
+        0.0
+    } else {
+        dir.z.atan2(dir.x)
+    };
+    (yaw, dir.y.max(-1.0).min(1.0).asin())
+}
+
+/// Normalized device coordinates from a position in pixels, with the origin in the top-left
+/// corner like imgui and the window
+pub fn ndc_from_pixel(pixel: [f32; 2], size: [f32; 2]) -> Vec2 {
+    Vec2::new(
+        2.0 * pixel[0] / size[0] - 1.0,
+        2.0 * pixel[1] / size[1] - 1.0,
+    )
+}
+
+/// The inverse of ndc_from_pixel
+pub fn pixel_from_ndc(ndc: Vec2, size: [f32; 2]) -> [f32; 2] {
+    [(ndc.x + 1.0) * 0.5 * size[0], (ndc.y + 1.0) * 0.5 * size[1]]
+}
+
+/// The normalized device coordinates of the world space point. None if it is behind the eye.
+pub fn project(view_proj: &Mat4, p: Vec3) -> Option<Vec3> {
+    let clip = *view_proj * Vec4::from_point(p);
+    if clip.w <= 0.0 {
+        return None;
+    }
+
+    Some(clip.xyz() / clip.w)
+}
+
+/// The world space point at the normalized device coordinates, where z is the depth.
+/// inv_view_proj is the inverse of the view projection.
+pub fn unproject(inv_view_proj: &Mat4, ndc: Vec3) -> Vec3 {
+    let p = *inv_view_proj * Vec4::from_point(ndc);
+    p.xyz() / p.w
+}
+
 #[cfg(test)]
 mod tests {
 
-    use super::{Mat4, Quat, Transform, Vec3};
+    use super::*;
     use vek::approx::assert_abs_diff_eq;
     const EPS: f32 = 0.00001;
 
@@ -207,6 +598,17 @@ mod tests {
         assert_abs_diff_eq!(vek_m, n, epsilon = EPS);
     }
 
+    #[test]
+    fn inverse() {
+        let t = Transform {
+            position: Vec3::new(1.0, -2.0, 3.0),
+            rotation: Quat::rotation_y(0.7) * Quat::rotation_x(-0.3),
+            scale: 2.0,
+        };
+        assert_abs_diff_eq!(t * t.inverse(), Transform::identity(), epsilon = EPS);
+        assert_abs_diff_eq!(t.inverse() * t, Transform::identity(), epsilon = EPS);
+    }
+
     #[test]
     fn compose_pos() {
         let lhs = Transform::pos(1.0, 2.0, 3.0);
@@ -387,4 +789,203 @@ mod tests {
 
         verify_composed(&lhs, &rhs, &result);
     }
+
+    #[test]
+    fn frustum_sphere_intersection() {
+        let proj = perspective_vk(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+        let frustum = Frustum::from_view_proj(proj);
+
+        assert!(frustum.intersects_sphere(Vec3::new(0.0, 0.0, -10.0), 1.0));
+        // Behind the camera
+        assert!(!frustum.intersects_sphere(Vec3::new(0.0, 0.0, 10.0), 1.0));
+        assert!(frustum.intersects_sphere(Vec3::new(0.0, 0.0, 0.5), 1.0));
+        // Beyond the far plane
+        assert!(!frustum.intersects_sphere(Vec3::new(0.0, 0.0, -200.0), 1.0));
+        // To the sides, the planes are at 45 degrees
+        assert!(!frustum.intersects_sphere(Vec3::new(20.0, 0.0, -10.0), 1.0));
+        assert!(!frustum.intersects_sphere(Vec3::new(0.0, -20.0, -10.0), 1.0));
+        assert!(frustum.intersects_sphere(Vec3::new(10.5, 0.0, -10.0), 1.0));
+
+        // Moving the camera moves the frustum
+        let view = Mat4::translation_3d(Vec3::new(-20.0, 0.0, 0.0));
+        let frustum = Frustum::from_view_proj(proj * view);
+        assert!(frustum.intersects_sphere(Vec3::new(20.0, 0.0, -10.0), 1.0));
+    }
+
+    #[test]
+    fn frustum_box_and_swept_sphere_intersection() {
+        let proj = perspective_vk(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+        let frustum = Frustum::from_view_proj(proj);
+        let bbox = |center: Vec3| BoundingBox {
+            min: center - Vec3::broadcast(1.0),
+            max: center + Vec3::broadcast(1.0),
+        };
+
+        assert!(frustum.intersects_box(&bbox(Vec3::new(0.0, 0.0, -10.0))));
+        assert!(!frustum.intersects_box(&bbox(Vec3::new(0.0, 0.0, 10.0))));
+        assert!(!frustum.intersects_box(&bbox(Vec3::new(20.0, 0.0, -10.0))));
+        assert!(frustum.intersects_box(&bbox(Vec3::new(10.5, 0.0, -10.0))));
+
+        // Behind the camera, but its shadow is cast into view
+        let start = Vec3::new(0.0, 0.0, 10.0);
+        assert!(!frustum.intersects_swept_sphere(start, start, 1.0));
+        assert!(frustum.intersects_swept_sphere(start, Vec3::new(0.0, 0.0, -10.0), 1.0));
+        assert!(!frustum.intersects_swept_sphere(start, Vec3::new(0.0, 0.0, 20.0), 1.0));
+    }
+
+    #[test]
+    fn decompose_matrix() {
+        let t = Transform {
+            position: Vec3::new(1.0, -2.0, 3.0),
+            rotation: Quat::rotation_y(2.5) * Quat::rotation_x(-1.3),
+            scale: 0.5,
+        };
+        let decomposed = Transform::from_matrix(&Mat4::from(t));
+        assert_abs_diff_eq!(decomposed.position, t.position, epsilon = EPS);
+        assert_abs_diff_eq!(decomposed.scale, t.scale, epsilon = EPS);
+        // q and -q are the same rotation
+        assert_abs_diff_eq!(
+            decomposed.rotation.dot(t.rotation).abs(),
+            1.0,
+            epsilon = EPS
+        );
+
+        // All the branches of the rotation
+        for angle in [0.0f32, 1.0, 2.0, 3.0, -2.0].iter() {
+            for axis in [Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()].iter() {
+                let rotation = Quat::rotation_3d(*angle, *axis);
+                let m = Mat4::from(rotation);
+                let decomposed = Transform::from_matrix(&m);
+                assert_abs_diff_eq!(Mat4::from(decomposed), m, epsilon = 0.0001);
+            }
+        }
+    }
+
+    #[test]
+    fn view_space_conventions() {
+        let eye = Vec3::new(1.0, 2.0, 3.0);
+        let (yaw, pitch) = (0.7, -0.4);
+        let dir = direction_from_yaw_pitch(yaw, pitch);
+        let (yaw2, pitch2) = yaw_pitch_from_direction(dir);
+        assert_abs_diff_eq!(yaw2, yaw, epsilon = EPS);
+        assert_abs_diff_eq!(pitch2, pitch, epsilon = EPS);
+        // Yaw 0 looks along +x, a quarter turn along +z
+        assert_abs_diff_eq!(direction_from_yaw_pitch(0.0, 0.0), Vec3::unit_x());
+        assert_abs_diff_eq!(
+            direction_from_yaw_pitch(std::f32::consts::FRAC_PI_2, 0.0),
+            Vec3::unit_z(),
+            epsilon = EPS
+        );
+
+        // The point in front of the eye is on -z in view space
+        let view = look_to(eye, dir, Vec3::unit_y());
+        let in_view = (view * Vec4::from_point(eye + dir * 5.0)).xyz();
+        assert_abs_diff_eq!(in_view, Vec3::new(0.0, 0.0, -5.0), epsilon = 0.0001);
+
+        // Same for the transform of an eye that is rotated to look along dir
+        let transform = Transform {
+            position: eye,
+            rotation: Quat::rotation_from_to_3d(-Vec3::unit_z(), dir),
+            scale: 1.0,
+        };
+        let in_view = (world_to_view(&transform) * Vec4::from_point(eye + dir * 5.0)).xyz();
+        assert_abs_diff_eq!(in_view, Vec3::new(0.0, 0.0, -5.0), epsilon = 0.0001);
+
+        // Up in the world is up on the screen, which is -y in vulkan
+        let view_proj = perspective_vk(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0) * view;
+        let above = project(&view_proj, eye + dir * 5.0 + Vec3::unit_y()).unwrap();
+        assert!(above.y < 0.0);
+        let top_left = pixel_from_ndc(Vec2::new(-1.0, -1.0), [640.0, 480.0]);
+        assert_eq!(top_left, [0.0, 0.0]);
+        assert_abs_diff_eq!(
+            ndc_from_pixel([640.0, 240.0], [640.0, 480.0]),
+            Vec2::unit_x()
+        );
+
+        let behind = project(&view_proj, eye - dir);
+        assert!(behind.is_none());
+        let ndc = project(&view_proj, eye + dir * 5.0 + Vec3::unit_y()).unwrap();
+        let p = unproject(&view_proj.inverted(), ndc);
+        assert_abs_diff_eq!(p, eye + dir * 5.0 + Vec3::unit_y(), epsilon = 0.001);
+    }
+
+    #[test]
+    fn bounding_sphere_is_chosen_when_tighter() {
+        // The corners of a box and points on a sphere
+        let corners: Vec<Vec3> = (0..8)
+            .map(|i| {
+                Vec3::new(
+                    if i & 1 == 0 { -1.0 } else { 1.0 },
+                    if i & 2 == 0 { -1.0 } else { 1.0 },
+                    if i & 4 == 0 { -1.0 } else { 1.0 },
+                )
+            })
+            .collect();
+        let ball: Vec<Vec3> = (0..64)
+            .map(|i| {
+                let (yaw, pitch) = (i as f32 * 0.7, (i as f32 * 0.3).sin());
+                direction_from_yaw_pitch(yaw, pitch) * 2.0 + Vec3::unit_x()
+            })
+            .collect();
+
+        for points in [&corners, &ball].iter() {
+            let sphere = BoundingSphere::from_points(points).unwrap();
+            for p in points.iter() {
+                assert!((*p - sphere.center).magnitude() <= sphere.radius + EPS);
+            }
+        }
+        let ball_sphere = BoundingSphere::from_points(&ball).unwrap();
+        assert!(ball_sphere.radius < 2.0 * 1.2);
+        assert!(BoundingSphere::from_points(&[]).is_none());
+
+        let tightest = |points: &[Vec3]| {
+            BoundingVolume::tightest(
+                BoundingBox::from_points(points.iter().copied()).unwrap(),
+                BoundingSphere::from_points(points),
+            )
+        };
+        assert!(matches!(tightest(&corners), BoundingVolume::Box(_)));
+        assert!(matches!(tightest(&ball), BoundingVolume::Sphere(_)));
+
+        let proj = perspective_vk(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+        let frustum = Frustum::from_view_proj(proj);
+        let m = Mat4::translation_3d(Vec3::new(0.0, 0.0, -10.0)) * Mat4::scaling_3d(2.0);
+        let in_view = tightest(&ball).transformed(&m);
+        assert!(frustum.intersects_volume(&in_view));
+        assert_abs_diff_eq!(in_view.bounding_sphere().radius, ball_sphere.radius * 2.0);
+        let behind = in_view.transformed(&Mat4::translation_3d(Vec3::new(0.0, 0.0, 30.0)));
+        assert!(!frustum.intersects_volume(&behind));
+    }
+
+    #[test]
+    fn ray_box_intersection() {
+        let bbox = BoundingBox {
+            min: Vec3::new(-1.0, -1.0, -1.0),
+            max: Vec3::new(1.0, 1.0, 1.0),
+        };
+        let ray = Ray {
+            origin: Vec3::new(0.0, 0.0, 5.0),
+            direction: -Vec3::unit_z(),
+        };
+        assert_abs_diff_eq!(ray.intersect(&bbox).unwrap(), 4.0, epsilon = EPS);
+        let (_, normal) = ray.intersect_with_normal(&bbox).unwrap();
+        assert_abs_diff_eq!(normal, Vec3::unit_z());
+
+        let miss = Ray {
+            origin: Vec3::new(2.0, 0.0, 5.0),
+            ..ray
+        };
+        assert_eq!(miss.intersect(&bbox), None);
+
+        let behind = Ray {
+            direction: Vec3::unit_z(),
+            ..ray
+        };
+        assert_eq!(behind.intersect(&bbox), None);
+
+        // Scaling the box up by two and transforming the ray to its model space keeps the distance
+        let model = Mat4::scaling_3d(Vec3::broadcast(2.0));
+        let t = ray.transformed(model.inverted()).intersect(&bbox).unwrap();
+        assert_abs_diff_eq!(t, 3.0, epsilon = EPS);
+    }
 }
@@ -0,0 +1,207 @@
+//! User data per entity, as string keys to typed values. Loaded from the "extras" of glTF nodes,
+//! where many pipelines put things like gameplay tags or spawn parameters. Nested objects and arrays
+//! in the extras are flattened into dotted keys, e.g. `{"spawn": {"count": 2}}` becomes
+//! `spawn.count`.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ecs::prelude::*;
+use crate::render::ui::UiFrame;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MetadataValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl From<bool> for MetadataValue {
+    fn from(v: bool) -> Self {
+        Self::Bool(v)
+    }
+}
+
+impl From<i64> for MetadataValue {
+    fn from(v: i64) -> Self {
+        Self::Int(v)
+    }
+}
+
+impl From<f64> for MetadataValue {
+    fn from(v: f64) -> Self {
+        Self::Float(v)
+    }
+}
+
+impl From<&str> for MetadataValue {
+    fn from(v: &str) -> Self {
+        Self::String(String::from(v))
+    }
+}
+
+impl From<String> for MetadataValue {
+    fn from(v: String) -> Self {
+        Self::String(v)
+    }
+}
+
+impl crate::editor::Inspect for MetadataValue {
+    fn inspect<'a>(&self, ui: &UiFrame<'a>, name: &str) {
+        match self {
+            Self::Bool(v) => v.inspect(ui, name),
+            Self::Int(v) => ui.inner().text(&imgui::im_str!("{}: {}", name, v)),
+            Self::Float(v) => ui.inner().text(&imgui::im_str!("{}: {}", name, v)),
+            Self::String(v) => v.inspect(ui, name),
+        }
+    }
+
+    fn inspect_mut<'a>(&mut self, ui: &UiFrame<'a>, name: &str) {
+        match self {
+            Self::Bool(v) => v.inspect_mut(ui, name),
+            Self::Int(v) => {
+                // imgui only edits i32
+                let mut edit = (*v).clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+                if imgui::InputInt::new(ui.inner(), &imgui::im_str!("{}", name), &mut edit).build()
+                {
+                    *v = edit as i64;
+                }
+            }
+            Self::Float(v) => {
+                let mut edit = *v as f32;
+                if imgui::InputFloat::new(ui.inner(), &imgui::im_str!("{}", name), &mut edit)
+                    .build()
+                {
+                    *v = edit as f64;
+                }
+            }
+            Self::String(v) => v.inspect_mut(ui, name),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Component, Serialize, Deserialize)]
+#[component(inspect)]
+pub struct Metadata(pub BTreeMap<String, MetadataValue>);
+
+impl Metadata {
+    pub fn get(&self, key: &str) -> Option<&MetadataValue> {
+        self.0.get(key)
+    }
+
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<MetadataValue>) {
+        self.0.insert(key.into(), value.into());
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<MetadataValue> {
+        self.0.remove(key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.0.contains_key(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &MetadataValue)> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)? {
+            MetadataValue::Bool(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn get_int(&self, key: &str) -> Option<i64> {
+        match self.get(key)? {
+            MetadataValue::Int(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Ints are converted, as exporters often write whole numbers without a decimal point
+    pub fn get_float(&self, key: &str) -> Option<f64> {
+        match self.get(key)? {
+            MetadataValue::Float(v) => Some(*v),
+            MetadataValue::Int(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            MetadataValue::String(v) => Some(v.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Flattens a JSON value, e.g. glTF extras. Nulls are skipped and a value that is not an
+    /// object is stored under `value`.
+    pub fn from_json(json: &serde_json::Value) -> Self {
+        let mut metadata = Self::default();
+        match json {
+            serde_json::Value::Object(map) => {
+                for (key, v) in map.iter() {
+                    metadata.insert_json(key.clone(), v);
+                }
+            }
+            v => metadata.insert_json(String::from("value"), v),
+        }
+        metadata
+    }
+
+    fn insert_json(&mut self, key: String, json: &serde_json::Value) {
+        use serde_json::Value;
+        match json {
+            Value::Null => (),
+            Value::Bool(v) => self.insert(key, *v),
+            Value::Number(n) => match n.as_i64() {
+                Some(v) => self.insert(key, v),
+                None => self.insert(key, n.as_f64().unwrap_or(f64::NAN)),
+            },
+            Value::String(v) => self.insert(key, v.as_str()),
+            Value::Array(arr) => {
+                for (i, v) in arr.iter().enumerate() {
+                    self.insert_json(format!("{}.{}", key, i), v);
+                }
+            }
+            Value::Object(map) => {
+                for (k, v) in map.iter() {
+                    self.insert_json(format!("{}.{}", key, k), v);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_is_flattened_to_typed_values() {
+        let json: serde_json::Value = serde_json::from_str(
+            r#"{"tag": "door", "locked": true, "health": 10, "speed": 1.5,
+                "spawn": {"count": 2, "points": [0.5, null]}, "unused": null}"#,
+        )
+        .unwrap();
+        let metadata = Metadata::from_json(&json);
+
+        assert_eq!(metadata.get_str("tag"), Some("door"));
+        assert_eq!(metadata.get_bool("locked"), Some(true));
+        assert_eq!(metadata.get_int("health"), Some(10));
+        assert_eq!(metadata.get_float("health"), Some(10.0));
+        assert_eq!(metadata.get_float("speed"), Some(1.5));
+        assert_eq!(metadata.get_int("speed"), None);
+        assert_eq!(metadata.get_int("spawn.count"), Some(2));
+        assert_eq!(metadata.get_float("spawn.points.0"), Some(0.5));
+        assert!(!metadata.contains_key("spawn.points.1"));
+        assert!(!metadata.contains_key("unused"));
+    }
+}
@@ -0,0 +1,542 @@
+//! Navigation meshes for walking agents. The colliders in the scene are voxelized into a
+//! heightfield: a grid in the xz-plane where each cell has the surfaces in it that can be stood on.
+//! Surfaces in neighbouring cells that can be stepped between are connected, and paths are searched
+//! for in the resulting graph.
+
+use crate::collision::{self, raycast, Collider};
+use crate::common::Name;
+use crate::ecs::prelude::*;
+use crate::graph::{self, Children, Parent};
+use crate::math::{BoundingBox, ModelMatrix, Ray, Rgba, Transform, Vec3};
+use crate::render::geometry;
+use crate::render::material::Unlit;
+use crate::render::mesh::CpuMesh;
+
+use ramneryd_derive::Inspect;
+use trekanten::pipeline::PolygonMode;
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+const EPS: f32 = 0.001;
+/// Upper bound of the number of surfaces on top of each other in a cell
+const MAX_SURFACES_PER_CELL: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Inspect)]
+pub struct NavMeshSettings {
+    pub cell_size: f32,
+    /// The free space that is needed above a surface for it to be walkable
+    pub agent_height: f32,
+    /// The largest height difference between neighbouring cells that can be walked
+    pub step_height: f32,
+    /// The steepest surface that can be walked on, in radians
+    pub slope_limit: f32,
+}
+
+impl Default for NavMeshSettings {
+    fn default() -> Self {
+        Self {
+            cell_size: 0.25,
+            agent_height: 1.8,
+            step_height: 0.3,
+            slope_limit: std::f32::consts::FRAC_PI_4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    x: usize,
+    z: usize,
+    height: f32,
+}
+
+#[derive(Debug, Clone, Default)]
+struct NavGrid {
+    min: Vec3,
+    cell_size: f32,
+    width: usize,
+    depth: usize,
+    nodes: Vec<Node>,
+    /// The nodes in each cell, indexed by z * width + x
+    cells: Vec<Vec<usize>>,
+    neighbours: Vec<Vec<usize>>,
+}
+
+impl NavGrid {
+    fn build(colliders: &[Collider], settings: &NavMeshSettings) -> Self {
+        let bounds = colliders.iter().map(Collider::world_bbox).fold(
+            None,
+            |acc: Option<BoundingBox>, mut bbox| {
+                if let Some(acc) = acc {
+                    bbox.combine(acc);
+                }
+                Some(bbox)
+            },
+        );
+        let bounds = match bounds {
+            Some(bounds) => bounds,
+            None => return Self::default(),
+        };
+
+        let cell_size = settings.cell_size.max(EPS);
+        let extent = bounds.max - bounds.min;
+        let width = ((extent.x / cell_size).ceil() as usize).max(1);
+        let depth = ((extent.z / cell_size).ceil() as usize).max(1);
+        let up = Vec3::unit_y();
+        let max_slope_cos = settings.slope_limit.cos();
+
+        let mut nodes = Vec::new();
+        let mut cells = Vec::with_capacity(width * depth);
+        for z in 0..depth {
+            for x in 0..width {
+                let mut origin = Vec3::new(
+                    bounds.min.x + (x as f32 + 0.5) * cell_size,
+                    bounds.max.y + 1.0,
+                    bounds.min.z + (z as f32 + 0.5) * cell_size,
+                );
+
+                let mut cell = Vec::new();
+                for _ in 0..MAX_SURFACES_PER_CELL {
+                    let ray = Ray {
+                        origin,
+                        direction: -up,
+                    };
+                    let (t, normal) = match raycast(colliders, &ray, std::f32::INFINITY) {
+                        Some(hit) => hit,
+                        None => break,
+                    };
+                    let surface = origin - up * t;
+                    // Continue from inside the box that was hit, which makes the next ray skip it
+                    origin = surface - up * EPS;
+
+                    if normal.y < max_slope_cos {
+                        continue;
+                    }
+
+                    let clearance = Ray {
+                        origin: surface + up * EPS,
+                        direction: up,
+                    };
+                    if raycast(colliders, &clearance, settings.agent_height).is_some() {
+                        continue;
+                    }
+
+                    cell.push(nodes.len());
+                    nodes.push(Node {
+                        x,
+                        z,
+                        height: surface.y,
+                    });
+                }
+                cells.push(cell);
+            }
+        }
+
+        let mut grid = Self {
+            min: bounds.min,
+            cell_size,
+            width,
+            depth,
+            nodes,
+            cells,
+            neighbours: Vec::new(),
+        };
+        grid.neighbours = (0..grid.nodes.len())
+            .map(|i| grid.find_neighbours(i, settings.step_height))
+            .collect();
+        grid
+    }
+
+    fn cell(&self, x: isize, z: isize) -> Option<&[usize]> {
+        if x < 0 || z < 0 || x as usize >= self.width || z as usize >= self.depth {
+            return None;
+        }
+        Some(&self.cells[z as usize * self.width + x as usize])
+    }
+
+    /// The node in the cell that can be stepped to from the given height
+    fn step_target(&self, x: isize, z: isize, height: f32, step_height: f32) -> Option<usize> {
+        self.cell(x, z)?
+            .iter()
+            .copied()
+            .filter(|&n| (self.nodes[n].height - height).abs() <= step_height)
+            .min_by(|&a, &b| {
+                let da = (self.nodes[a].height - height).abs();
+                let db = (self.nodes[b].height - height).abs();
+                da.partial_cmp(&db).unwrap_or(Ordering::Equal)
+            })
+    }
+
+    fn find_neighbours(&self, node: usize, step_height: f32) -> Vec<usize> {
+        let Node { x, z, height } = self.nodes[node];
+        let (x, z) = (x as isize, z as isize);
+        let mut neighbours = Vec::new();
+        for dz in -1..=1 {
+            for dx in -1..=1 {
+                if dx == 0 && dz == 0 {
+                    continue;
+                }
+                // Diagonals are only walkable if they don't cut a corner
+                if dx != 0
+                    && dz != 0
+                    && (self.step_target(x + dx, z, height, step_height).is_none()
+                        || self.step_target(x, z + dz, height, step_height).is_none())
+                {
+                    continue;
+                }
+                if let Some(n) = self.step_target(x + dx, z + dz, height, step_height) {
+                    neighbours.push(n);
+                }
+            }
+        }
+        neighbours
+    }
+
+    fn position(&self, node: usize) -> Vec3 {
+        let Node { x, z, height } = self.nodes[node];
+        Vec3::new(
+            self.min.x + (x as f32 + 0.5) * self.cell_size,
+            height,
+            self.min.z + (z as f32 + 0.5) * self.cell_size,
+        )
+    }
+
+    /// The surface in the cell under p that is closest to it
+    fn closest_node(&self, p: Vec3) -> Option<usize> {
+        let x = ((p.x - self.min.x) / self.cell_size).floor() as isize;
+        let z = ((p.z - self.min.z) / self.cell_size).floor() as isize;
+        self.cell(x, z)?.iter().copied().min_by(|&a, &b| {
+            let da = (self.nodes[a].height - p.y).abs();
+            let db = (self.nodes[b].height - p.y).abs();
+            da.partial_cmp(&db).unwrap_or(Ordering::Equal)
+        })
+    }
+
+    /// A* from start to goal, including both
+    fn search(&self, start: usize, goal: usize) -> Option<Vec<usize>> {
+        let goal_pos = self.position(goal);
+        let mut cost = vec![std::f32::INFINITY; self.nodes.len()];
+        let mut came_from = vec![None; self.nodes.len()];
+        let mut open = BinaryHeap::new();
+        cost[start] = 0.0;
+        open.push(Open {
+            estimate: self.position(start).distance(goal_pos),
+            node: start,
+        });
+
+        while let Some(Open { node, .. }) = open.pop() {
+            if node == goal {
+                let mut path = vec![goal];
+                let mut cur = goal;
+                while let Some(prev) = came_from[cur] {
+                    path.push(prev);
+                    cur = prev;
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            let pos = self.position(node);
+            for &n in self.neighbours[node].iter() {
+                let n_pos = self.position(n);
+                let n_cost = cost[node] + pos.distance(n_pos);
+                if n_cost < cost[n] {
+                    cost[n] = n_cost;
+                    came_from[n] = Some(node);
+                    open.push(Open {
+                        estimate: n_cost + n_pos.distance(goal_pos),
+                        node: n,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    /// An outline of each walkable cell
+    fn debug_lines(&self) -> Vec<[Vec3; 2]> {
+        let half = self.cell_size * 0.45;
+        let mut lines = Vec::with_capacity(self.nodes.len() * 4);
+        for i in 0..self.nodes.len() {
+            let c = self.position(i) + Vec3::unit_y() * 0.02;
+            let corners = [
+                c + Vec3::new(-half, 0.0, -half),
+                c + Vec3::new(half, 0.0, -half),
+                c + Vec3::new(half, 0.0, half),
+                c + Vec3::new(-half, 0.0, half),
+            ];
+            for j in 0..4 {
+                lines.push([corners[j], corners[(j + 1) % 4]]);
+            }
+        }
+        lines
+    }
+}
+
+/// Entry in the A* open set, ordered so that BinaryHeap pops the lowest estimate first
+#[derive(Debug, PartialEq)]
+struct Open {
+    estimate: f32,
+    node: usize,
+}
+
+impl Eq for Open {}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .partial_cmp(&self.estimate)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A navigation mesh for the colliders in the scene. It is built the first time there is
+/// something to walk on and is in world space, so the transform of its entity should be identity.
+#[derive(Component)]
+#[component(inspect)]
+pub struct NavMesh {
+    pub settings: NavMeshSettings,
+    /// Build the navmesh again, e.g. after the scene has changed
+    pub rebuild: bool,
+    /// Draw the walkable cells
+    pub show: bool,
+    #[inspect(ignore)]
+    grid: Option<NavGrid>,
+}
+
+impl NavMesh {
+    pub fn new(settings: NavMeshSettings) -> Self {
+        Self {
+            settings,
+            rebuild: false,
+            show: true,
+            grid: None,
+        }
+    }
+
+    pub fn is_built(&self) -> bool {
+        self.grid.is_some()
+    }
+
+    /// Points to walk through from `from` to `to`, which are snapped to the closest walkable
+    /// surfaces below them. None if the navmesh isn't built or there is no path.
+    pub fn find_path(&self, from: Vec3, to: Vec3) -> Option<Vec<Vec3>> {
+        let grid = self.grid.as_ref()?;
+        let start = grid.closest_node(from)?;
+        let goal = grid.closest_node(to)?;
+        let path = grid.search(start, goal)?;
+        Some(path.into_iter().map(|n| grid.position(n)).collect())
+    }
+}
+
+pub fn create(world: &mut World, settings: NavMeshSettings) -> Entity {
+    world
+        .create_entity()
+        .with(Name::from("NavMesh"))
+        .with(Transform::identity())
+        .with(NavMesh::new(settings))
+        .build()
+}
+
+#[derive(Default, Component)]
+#[component(storage = "NullStorage")]
+pub struct NavMeshRenderer;
+
+pub struct BuildNavMesh;
+
+impl BuildNavMesh {
+    pub const ID: &'static str = "BuildNavMesh";
+}
+
+#[derive(SystemData)]
+pub struct BuildNavMeshData<'a> {
+    entities: Entities<'a>,
+    navmeshes: WriteStorage<'a, NavMesh>,
+    bboxes: ReadStorage<'a, BoundingBox>,
+    model_matrices: ReadStorage<'a, ModelMatrix>,
+    renderers: WriteStorage<'a, NavMeshRenderer>,
+    children_storage: WriteStorage<'a, Children>,
+    parent_storage: WriteStorage<'a, Parent>,
+    transforms: WriteStorage<'a, Transform>,
+    names: WriteStorage<'a, Name>,
+    meshes: WriteStorage<'a, CpuMesh>,
+    unlit: WriteStorage<'a, Unlit>,
+}
+
+impl<'a> System<'a> for BuildNavMesh {
+    type SystemData = BuildNavMeshData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let BuildNavMeshData {
+            entities,
+            mut navmeshes,
+            bboxes,
+            model_matrices,
+            mut renderers,
+            mut children_storage,
+            mut parent_storage,
+            mut transforms,
+            mut names,
+            mut meshes,
+            mut unlit,
+        } = data;
+
+        for (ent, navmesh) in (&entities, &mut navmeshes).join() {
+            let mut built = false;
+            if navmesh.grid.is_none() || navmesh.rebuild {
+                let colliders = collision::gather(&entities, &bboxes, &model_matrices, |_| true);
+                // The scene might still be loading
+                if colliders.is_empty() {
+                    continue;
+                }
+
+                let grid = NavGrid::build(&colliders, &navmesh.settings);
+                log::info!(
+                    "Built a navmesh with {} walkable cells from {} colliders",
+                    grid.nodes.len(),
+                    colliders.len()
+                );
+                navmesh.grid = Some(grid);
+                navmesh.rebuild = false;
+                built = true;
+            }
+
+            let mut has_renderer = false;
+            if let Some(children) = children_storage.get_mut(ent) {
+                children.children.retain(|child| {
+                    if renderers.get(*child).is_none() {
+                        true
+                    } else if built || !navmesh.show {
+                        entities.delete(*child).unwrap();
+                        false
+                    } else {
+                        has_renderer = true;
+                        true
+                    }
+                });
+            }
+
+            let lines = match (&navmesh.grid, navmesh.show && !has_renderer) {
+                (Some(grid), true) => grid.debug_lines(),
+                _ => continue,
+            };
+            if lines.is_empty() {
+                continue;
+            }
+
+            let (vertex_buffer, index_buffer) = geometry::line_list_mesh(&lines);
+            let child = entities
+                .build_entity()
+                .with(Name::from("NavMeshRenderer"), &mut names)
+                .with(Transform::identity(), &mut transforms)
+                .with(NavMeshRenderer, &mut renderers)
+                .with(
+                    CpuMesh {
+                        vertex_buffer,
+                        index_buffer,
+                        polygon_mode: PolygonMode::Line,
+                    },
+                    &mut meshes,
+                )
+                .with(
+                    Unlit {
+                        color: Rgba::new(0.0, 0.8, 1.0, 1.0),
+                    },
+                    &mut unlit,
+                )
+                .build();
+            graph::sys::add_edge(&mut children_storage, &mut parent_storage, ent, child);
+        }
+    }
+}
+
+pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
+    builder.with(BuildNavMesh, BuildNavMesh::ID, &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Mat4;
+
+    fn collider(min: [f32; 3], max: [f32; 3]) -> Collider {
+        let bbox = BoundingBox {
+            min: Vec3::from(min),
+            max: Vec3::from(max),
+        };
+        Collider::new(bbox, Mat4::identity())
+    }
+
+    fn navmesh(colliders: &[Collider]) -> NavMesh {
+        let mut navmesh = NavMesh::new(NavMeshSettings {
+            cell_size: 0.5,
+            ..Default::default()
+        });
+        navmesh.grid = Some(NavGrid::build(colliders, &navmesh.settings));
+        navmesh
+    }
+
+    #[test]
+    fn walkable_surfaces() {
+        // A floor with a table that is too low to stand under
+        let colliders = [
+            collider([0.0, -1.0, 0.0], [4.0, 0.0, 4.0]),
+            collider([1.0, 0.8, 1.0], [2.0, 1.0, 2.0]),
+        ];
+        let grid = NavGrid::build(&colliders, &NavMeshSettings::default());
+        let heights = |x, z| {
+            grid.cell(x, z)
+                .unwrap()
+                .iter()
+                .map(|&n| grid.nodes[n].height)
+                .collect::<Vec<_>>()
+        };
+        // The grid covers the floor with 0.25 cells
+        assert_eq!(grid.width, 16);
+        assert_eq!(grid.depth, 16);
+        assert_eq!(heights(0, 0), vec![0.0]);
+        assert_eq!(heights(5, 5), vec![1.0]);
+    }
+
+    #[test]
+    fn path_around_wall() {
+        let colliders = [
+            collider([0.0, -1.0, 0.0], [5.0, 0.0, 5.0]),
+            // A wall along x with a gap at the end
+            collider([2.0, 0.0, 0.0], [3.0, 2.0, 4.0]),
+        ];
+        let navmesh = navmesh(&colliders);
+        let path = navmesh
+            .find_path(Vec3::new(0.75, 0.0, 0.75), Vec3::new(4.25, 0.0, 0.75))
+            .unwrap();
+        assert!(path.iter().any(|p| p.z > 4.0));
+        assert!(path.iter().all(|p| p.y == 0.0));
+    }
+
+    #[test]
+    fn steps_and_blocked_paths() {
+        let colliders = [
+            collider([0.0, -1.0, 0.0], [2.0, 0.0, 2.0]),
+            collider([2.0, -1.0, 0.0], [4.0, 0.2, 2.0]),
+            collider([4.0, -1.0, 0.0], [6.0, 1.0, 2.0]),
+        ];
+        let navmesh = navmesh(&colliders);
+        let path = navmesh
+            .find_path(Vec3::new(0.25, 0.0, 0.25), Vec3::new(3.75, 0.2, 0.25))
+            .unwrap();
+        assert_eq!(path.last().unwrap().y, 0.2);
+        // Too high to step up to
+        assert!(navmesh
+            .find_path(Vec3::new(0.25, 0.0, 0.25), Vec3::new(5.75, 1.0, 0.25))
+            .is_none());
+    }
+}
@@ -0,0 +1,209 @@
+//! Play mode for the editor. Pressing Play copies the components that the simulation changes, i.e.
+//! the ones with `#[component(snapshot)]` such as Transform and Animation, and starts the
+//! simulation systems (animation and character movement). Stop puts the copies back, so that
+//! experimenting while playing doesn't change the scene. The camera is left where it is.
+//!
+//! Entities that were created while playing are deleted on Stop. Entities that were deleted can't
+//! be brought back, as the entity ids can't be reused.
+
+use std::collections::HashSet;
+
+use crate::camera::Camera;
+use crate::ecs::{self, meta::Restore, prelude::*};
+use crate::math::Transform;
+
+/// The components of the world when Play was pressed
+pub struct Snapshot {
+    entities: HashSet<Entity>,
+    restores: Vec<Restore>,
+}
+
+impl Snapshot {
+    pub fn take(world: &World) -> Self {
+        let entities = world.entities().join().collect();
+        let restores = ecs::meta::ALL_COMPONENTS
+            .iter()
+            .filter_map(|comp| comp.snapshot.map(|snapshot| snapshot(world)))
+            .collect();
+        Self { entities, restores }
+    }
+
+    pub fn restore(self, world: &World) {
+        let camera = ecs::find_singleton_entity::<Camera>(world).and_then(|ent| {
+            world
+                .read_storage::<Transform>()
+                .get(ent)
+                .map(|tfm| (ent, *tfm))
+        });
+
+        for restore in self.restores {
+            restore(world);
+        }
+
+        let entities = world.entities();
+        let created: Vec<Entity> = entities
+            .join()
+            .filter(|ent| !self.entities.contains(ent))
+            .collect();
+        for ent in created {
+            entities
+                .delete(ent)
+                .expect("Entity was just found to be alive");
+        }
+        let deleted = self
+            .entities
+            .iter()
+            .filter(|ent| !entities.is_alive(**ent))
+            .count();
+        if deleted > 0 {
+            log::warn!(
+                "{} entities were deleted while playing and are gone",
+                deleted
+            );
+        }
+
+        if let Some((ent, tfm)) = camera {
+            world
+                .write_storage::<Transform>()
+                .insert(ent, tfm)
+                .expect("Camera is alive");
+        }
+    }
+}
+
+/// Whether the simulation systems run. The simulation runs from the start unless
+/// RunOptions::edit_mode is set, in which case it waits for Play.
+pub struct PlayMode {
+    simulating: bool,
+    snapshot: Option<Snapshot>,
+}
+
+impl Default for PlayMode {
+    fn default() -> Self {
+        Self {
+            simulating: true,
+            snapshot: None,
+        }
+    }
+}
+
+impl PlayMode {
+    pub fn editing() -> Self {
+        Self {
+            simulating: false,
+            snapshot: None,
+        }
+    }
+
+    pub fn is_simulating(&self) -> bool {
+        self.simulating
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.snapshot.is_some()
+    }
+}
+
+/// Take a snapshot of the world and start the simulation
+pub fn play(world: &World) {
+    let snapshot = Snapshot::take(world);
+    let mut mode = world.write_resource::<PlayMode>();
+    mode.snapshot = Some(snapshot);
+    mode.simulating = true;
+}
+
+/// Stop the simulation and restore the snapshot from when Play was pressed
+pub fn stop(world: &World) {
+    let snapshot = {
+        let mut mode = world.write_resource::<PlayMode>();
+        mode.simulating = false;
+        mode.snapshot.take()
+    };
+    if let Some(snapshot) = snapshot {
+        snapshot.restore(world);
+    }
+}
+
+pub fn build_ui<'a>(
+    world: &mut World,
+    ui: &crate::render::ui::UiFrame<'a>,
+    pos: [f32; 2],
+) -> [f32; 2] {
+    let size = [300.0, 60.0];
+
+    imgui::Window::new(imgui::im_str!("Play mode"))
+        .position(pos, imgui::Condition::FirstUseEver)
+        .size(size, imgui::Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let (simulating, playing) = {
+                let mode = world.read_resource::<PlayMode>();
+                (mode.is_simulating(), mode.is_playing())
+            };
+            if playing {
+                if ui.inner().button(imgui::im_str!("Stop"), [0.0, 0.0]) {
+                    stop(world);
+                }
+            } else if ui.inner().button(imgui::im_str!("Play"), [0.0, 0.0]) {
+                play(world);
+            }
+            ui.inner().same_line(0.0);
+            if playing {
+                ui.inner().text(imgui::im_str!("Playing"));
+            } else if simulating {
+                ui.inner()
+                    .text(imgui::im_str!("Simulating, Play to restore on Stop"));
+            } else {
+                ui.inner().text(imgui::im_str!("Editing"));
+            }
+        });
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec3;
+
+    #[test]
+    fn stop_restores_transforms_and_deletes_created_entities() {
+        let mut world = World::new();
+        ecs::meta::register_all_components(&mut world);
+        world.insert(PlayMode::editing());
+        let ent = world
+            .create_entity()
+            .with(Transform::pos(1.0, 0.0, 0.0))
+            .build();
+        let camera = world
+            .create_entity()
+            .with(Camera::default())
+            .with(Transform::default())
+            .build();
+
+        play(&world);
+        assert!(world.read_resource::<PlayMode>().is_simulating());
+        world
+            .write_storage::<Transform>()
+            .insert(ent, Transform::pos(5.0, 0.0, 0.0))
+            .unwrap();
+        world
+            .write_storage::<Transform>()
+            .insert(camera, Transform::pos(0.0, 3.0, 0.0))
+            .unwrap();
+        let created = world.create_entity().with(Transform::default()).build();
+
+        stop(&world);
+        world.maintain();
+        assert!(!world.read_resource::<PlayMode>().is_simulating());
+        let transforms = world.read_storage::<Transform>();
+        assert_eq!(
+            transforms.get(ent).unwrap().position,
+            Vec3::new(1.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            transforms.get(camera).unwrap().position,
+            Vec3::new(0.0, 3.0, 0.0)
+        );
+        assert!(!world.is_alive(created));
+    }
+}
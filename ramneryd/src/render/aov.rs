@@ -0,0 +1,535 @@
+//! Auxiliary images of the view for compositing: the linear depth, the world space normals and the
+//! entity ids of each pixel. They are rendered by a separate pass over the meshes that the camera
+//! draws, at the size of the scene, and are written as OpenEXR files next to the color screenshot.
+//!
+//! The pass reads the positions and normals of the meshes with PBR materials, after they were
+//! skinned and morphed by the deformation pre-pass, and meshes with unlit materials are left out.
+//! The ids are stored as floats, so they are exact up to 2^24.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use trekanten::descriptor::DescriptorSet;
+use trekanten::mem::{IndexBuffer, VertexBuffer};
+use trekanten::pipeline::{
+    GraphicsPipeline, GraphicsPipelineDescriptor, ShaderDescriptor, ShaderStage,
+};
+use trekanten::raw_vk;
+use trekanten::resource::Handle;
+use trekanten::texture::{SamplerDescriptor, Texture, TextureDescriptor, TextureUsage};
+use trekanten::util::{self, Extent2D};
+use trekanten::vertex::VertexFormat;
+use trekanten::{BufferHandle, CommandBuffer, Frame, RenderPass, RenderTarget, Renderer};
+
+use crate::ecs::prelude::*;
+use crate::math::{ModelMatrix, Vec3};
+
+use super::hdr_export::{self, HdrImage};
+use super::material::GpuMaterial;
+use super::mesh::GpuMesh;
+use super::pipeline::{self, pbr_gltf};
+use super::ui::UIContext;
+use super::visibility::{self, Pass, RenderLayer, Visibility};
+use super::{uniform, Hidden, MaterialError};
+
+const AOV_FORMAT: util::Format = util::Format::FLOAT4;
+
+#[derive(Debug, Error)]
+pub enum AovError {
+    #[error("There is no camera to render the auxiliary images from")]
+    NoCamera,
+    #[error("Failed to draw the auxiliary images: {0}")]
+    Draw(#[from] super::RenderError),
+    #[error("Failed to read back the auxiliary images: {0}")]
+    Readback(#[from] trekanten::RenderError),
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+struct DrawItem {
+    pipeline: Handle<GraphicsPipeline>,
+    vertex_buffer: BufferHandle<VertexBuffer>,
+    index_buffer: BufferHandle<IndexBuffer>,
+    object: uniform::AovObject,
+}
+
+fn aov_render_pass(renderer: &mut Renderer) -> Handle<RenderPass> {
+    let color_attach = raw_vk::AttachmentDescription {
+        format: AOV_FORMAT.into(),
+        samples: raw_vk::SampleCountFlags::TYPE_1,
+        load_op: raw_vk::AttachmentLoadOp::CLEAR,
+        store_op: raw_vk::AttachmentStoreOp::STORE,
+        stencil_load_op: raw_vk::AttachmentLoadOp::DONT_CARE,
+        stencil_store_op: raw_vk::AttachmentStoreOp::DONT_CARE,
+        initial_layout: raw_vk::ImageLayout::UNDEFINED,
+        // Renderer::read_texture expects this
+        final_layout: raw_vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        flags: raw_vk::AttachmentDescriptionFlags::empty(),
+    };
+    let depth_attach = raw_vk::AttachmentDescription {
+        format: util::Format::D16_UNORM.into(),
+        samples: raw_vk::SampleCountFlags::TYPE_1,
+        load_op: raw_vk::AttachmentLoadOp::CLEAR,
+        store_op: raw_vk::AttachmentStoreOp::DONT_CARE,
+        stencil_load_op: raw_vk::AttachmentLoadOp::DONT_CARE,
+        stencil_store_op: raw_vk::AttachmentStoreOp::DONT_CARE,
+        initial_layout: raw_vk::ImageLayout::UNDEFINED,
+        final_layout: raw_vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        flags: raw_vk::AttachmentDescriptionFlags::empty(),
+    };
+
+    let color_refs = [raw_vk::AttachmentReference {
+        attachment: 0,
+        layout: raw_vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    }];
+    let depth_ref = raw_vk::AttachmentReference {
+        attachment: 1,
+        layout: raw_vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+
+    let subpass = raw_vk::SubpassDescription::builder()
+        .pipeline_bind_point(raw_vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_refs)
+        .depth_stencil_attachment(&depth_ref);
+
+    let deps = [
+        raw_vk::SubpassDependency {
+            // A previous capture might still be writing to the targets
+            src_subpass: raw_vk::SUBPASS_EXTERNAL,
+            src_stage_mask: raw_vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | raw_vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            src_access_mask: raw_vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | raw_vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dst_subpass: 0,
+            dst_stage_mask: raw_vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | raw_vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            dst_access_mask: raw_vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | raw_vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dependency_flags: raw_vk::DependencyFlags::empty(),
+        },
+        raw_vk::SubpassDependency {
+            // The images are copied to the host
+            src_subpass: 0,
+            src_stage_mask: raw_vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            src_access_mask: raw_vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_subpass: raw_vk::SUBPASS_EXTERNAL,
+            dst_stage_mask: raw_vk::PipelineStageFlags::TRANSFER,
+            dst_access_mask: raw_vk::AccessFlags::TRANSFER_READ,
+            dependency_flags: raw_vk::DependencyFlags::empty(),
+        },
+    ];
+
+    let attachments = [color_attach, depth_attach];
+    let subpasses = [subpass.build()];
+    let create_info = raw_vk::RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(&subpasses)
+        .dependencies(&deps);
+
+    renderer
+        .create_render_pass(&create_info)
+        .expect("Failed to create aov render pass")
+}
+
+struct AovTargets {
+    extent: Extent2D,
+    color: Handle<Texture>,
+    depth: Handle<Texture>,
+    render_target: Handle<RenderTarget>,
+}
+
+impl AovTargets {
+    fn new(renderer: &mut Renderer, render_pass: &Handle<RenderPass>) -> Self {
+        let extent = renderer.swapchain_extent();
+        let mut texture = |format, usage| {
+            renderer
+                .create_texture(TextureDescriptor::Empty {
+                    extent,
+                    format,
+                    usage,
+                    sampler: SamplerDescriptor::default(),
+                })
+                .expect("Failed to create aov target")
+        };
+        let color = texture(
+            AOV_FORMAT,
+            TextureUsage::COLOR_ATTACHMENT | TextureUsage::TRANSFER_SRC,
+        );
+        let depth = texture(
+            util::Format::D16_UNORM,
+            TextureUsage::DEPTH_STENCIL_ATTACHMENT,
+        );
+        let render_target = renderer
+            .create_render_target(render_pass, &[&color, &depth])
+            .expect("Failed to create aov render target");
+
+        Self {
+            extent,
+            color,
+            depth,
+            render_target,
+        }
+    }
+
+    fn destroy(self, renderer: &mut Renderer) -> Result<(), trekanten::RenderError> {
+        renderer.destroy_render_target(self.render_target)?;
+        renderer.destroy_texture(self.color)?;
+        renderer.destroy_texture(self.depth)
+    }
+}
+
+struct AovPass {
+    render_pass: Handle<RenderPass>,
+    targets: AovTargets,
+    pipelines: HashMap<VertexFormat, Handle<GraphicsPipeline>>,
+    items: Vec<DrawItem>,
+}
+
+impl AovPass {
+    fn new(renderer: &mut Renderer) -> Self {
+        let render_pass = aov_render_pass(renderer);
+        let targets = AovTargets::new(renderer, &render_pass);
+        Self {
+            render_pass,
+            targets,
+            pipelines: HashMap::new(),
+            items: Vec::new(),
+        }
+    }
+
+    fn resize(&mut self, renderer: &mut Renderer) {
+        if self.targets.extent == renderer.swapchain_extent() {
+            return;
+        }
+
+        let targets = AovTargets::new(renderer, &self.render_pass);
+        let old = std::mem::replace(&mut self.targets, targets);
+        if let Err(e) = old.destroy(renderer) {
+            log::error!("Failed to destroy aov targets: {}", e);
+        }
+    }
+
+    fn pipeline(
+        &mut self,
+        shader_compiler: &pipeline::ShaderCompiler,
+        renderer: &mut Renderer,
+        vertex_format: VertexFormat,
+    ) -> Result<Handle<GraphicsPipeline>, MaterialError> {
+        if let Some(pipeline) = self.pipelines.get(&vertex_format) {
+            return Ok(*pipeline);
+        }
+
+        let (vert, frag) = pbr_gltf::compile_aov(shader_compiler)?;
+        let desc = GraphicsPipelineDescriptor::builder()
+            .vert(ShaderDescriptor::FromRawSpirv(vert.data()))
+            .frag(ShaderDescriptor::FromRawSpirv(frag.data()))
+            .vertex_format(vertex_format.clone())
+            .build()?;
+        let pipeline = renderer.create_gfx_pipeline(desc, &self.render_pass)?;
+        self.pipelines.insert(vertex_format, pipeline);
+        Ok(pipeline)
+    }
+}
+
+/// The pass is only rendered in the frames that the images are captured from
+#[derive(Default)]
+pub struct Aovs {
+    /// Created for the first capture
+    pass: Option<AovPass>,
+    requested: bool,
+    /// The pass has been prepared for the frame that is being rendered
+    prepared: bool,
+}
+
+impl Aovs {
+    /// Collects the meshes that the camera draws
+    fn prepare(&mut self, world: &World, renderer: &mut Renderer) {
+        let pass = self.pass.get_or_insert_with(|| AovPass::new(renderer));
+        pass.resize(renderer);
+        pass.items.clear();
+
+        // The linear depth is the negated z of the view space position
+        let (view, _) = super::get_view_data(world);
+        let depth_plane = [-view[(2, 0)], -view[(2, 1)], -view[(2, 2)], -view[(2, 3)]];
+
+        let shader_compiler = world.read_resource::<pipeline::ShaderCompiler>();
+        let meshes = world.read_storage::<GpuMesh>();
+        let materials = world.read_storage::<GpuMaterial>();
+        let model_matrices = world.read_storage::<ModelMatrix>();
+        let hidden = world.read_storage::<Hidden>();
+        let visibilities = world.read_storage::<Visibility>();
+        let layers = world.read_storage::<RenderLayer>();
+        let deformations = world.read_storage::<super::deformation::GpuDeformation>();
+        let camera_layers = visibility::pass_layers(world, Pass::Camera);
+        for (ent, mesh, mat, mtx, deformation, visibility, layer, _) in (
+            &world.entities(),
+            &meshes,
+            &materials,
+            &model_matrices,
+            deformations.maybe(),
+            visibilities.maybe(),
+            layers.maybe(),
+            !&hidden,
+        )
+            .join()
+        {
+            if !visibility::is_drawn(visibility, layer, Pass::Camera, camera_layers) {
+                continue;
+            }
+            if let GpuMaterial::Unlit { .. } = mat {
+                continue;
+            }
+
+            let vertex_format = super::vertex_format(renderer, mesh);
+            let pipeline = match pass.pipeline(&shader_compiler, renderer, vertex_format) {
+                Ok(pipeline) => pipeline,
+                Err(e) => {
+                    log::error!("Failed to create aov pipeline: {}", e);
+                    continue;
+                }
+            };
+
+            pass.items.push(DrawItem {
+                pipeline,
+                vertex_buffer: deformation.map_or(mesh.vertex_buffer, |d| d.vertex_buffer),
+                index_buffer: mesh.index_buffer,
+                object: uniform::AovObject {
+                    model: mtx.0.into_col_array(),
+                    depth_plane,
+                    entity: [ent.id() as f32 + 1.0, 0.0, 0.0, 0.0],
+                },
+            });
+        }
+        self.prepared = true;
+    }
+
+    /// Renders the pass, if the images are captured from this frame. Expects the view data to be
+    /// bound in the shader resource group.
+    pub(super) fn record(
+        &self,
+        frame: &Frame,
+        cmd_buffer: CommandBuffer,
+        shader_resource_group: &Handle<DescriptorSet>,
+    ) -> CommandBuffer {
+        let pass = match &self.pass {
+            Some(pass) if self.prepared => pass,
+            _ => return cmd_buffer,
+        };
+
+        let clear_values = [
+            raw_vk::ClearValue {
+                color: raw_vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 0.0],
+                },
+            },
+            raw_vk::ClearValue {
+                depth_stencil: raw_vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        let mut render_pass = frame
+            .begin_render_pass(
+                cmd_buffer,
+                &pass.render_pass,
+                &pass.targets.render_target,
+                pass.targets.extent,
+                &clear_values,
+            )
+            .expect("Failed to begin aov pass");
+
+        let mut bound = None;
+        for item in pass.items.iter() {
+            if bound != Some(item.pipeline) {
+                render_pass
+                    .bind_graphics_pipeline(&item.pipeline)
+                    .bind_shader_resource_group(0u32, shader_resource_group, &item.pipeline);
+                bound = Some(item.pipeline);
+            }
+
+            render_pass
+                .bind_vertex_buffer(&item.vertex_buffer)
+                .bind_index_buffer(&item.index_buffer)
+                .bind_push_constant(&item.pipeline, ShaderStage::VERTEX, &item.object)
+                .draw_indexed(
+                    item.index_buffer.n_elems(),
+                    item.index_buffer.idx(),
+                    item.vertex_buffer.idx() as i32,
+                );
+        }
+
+        render_pass.end().expect("Failed to end aov pass")
+    }
+}
+
+/// Prepares the pass if the images are captured from this frame. Has to run before the frame is
+/// started, as it might create the targets and pipelines.
+pub(super) fn prepare(world: &World, renderer: &mut Renderer) {
+    let mut aovs = world.write_resource::<Aovs>();
+    aovs.prepared = false;
+    if aovs.requested {
+        aovs.prepare(world, renderer);
+    }
+}
+
+/// The inverse of octahedral() in aov/frag.glsl
+fn decode_normal(x: f32, y: f32) -> Vec3 {
+    let z = 1.0 - x.abs() - y.abs();
+    let (x, y) = if z < 0.0 {
+        ((1.0 - y.abs()) * x.signum(), (1.0 - x.abs()) * y.signum())
+    } else {
+        (x, y)
+    };
+    Vec3::new(x, y, z).normalized()
+}
+
+/// Splits the pass into the depth, normal and entity images. Where nothing was drawn, all channels
+/// are zero, and elsewhere the alpha is one.
+fn decode(extent: Extent2D, data: &[u8]) -> [HdrImage; 3] {
+    let n = extent.width as usize * extent.height as usize;
+    let mut depth = Vec::with_capacity(n);
+    let mut normal = Vec::with_capacity(n);
+    let mut entity = Vec::with_capacity(n);
+    for texel in data.chunks_exact(16) {
+        let c = |i: usize| {
+            f32::from_le_bytes([
+                texel[4 * i],
+                texel[4 * i + 1],
+                texel[4 * i + 2],
+                texel[4 * i + 3],
+            ])
+        };
+        if c(1) == 0.0 {
+            depth.push([0.0; 4]);
+            normal.push([0.0; 4]);
+            entity.push([0.0; 4]);
+            continue;
+        }
+
+        let d = c(0);
+        let nor = decode_normal(c(2), c(3));
+        let id = c(1) - 1.0;
+        depth.push([d, d, d, 1.0]);
+        normal.push([nor.x, nor.y, nor.z, 1.0]);
+        entity.push([id, id, id, 1.0]);
+    }
+
+    let image = |pixels| HdrImage {
+        width: extent.width,
+        height: extent.height,
+        pixels,
+    };
+    [image(depth), image(normal), image(entity)]
+}
+
+/// E.g. shot_depth.exr for shot.png
+fn aov_path(screenshot: &Path, name: &str) -> PathBuf {
+    let stem = screenshot
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    screenshot.with_file_name(format!("{}_{}.exr", stem, name))
+}
+
+/// Renders a frame with the pass and writes the depth, normals and entity ids next to the
+/// screenshot, as <name>_depth.exr, <name>_normal.exr and <name>_entity.exr. Returns the paths of
+/// the files.
+pub(crate) fn capture(
+    world: &mut World,
+    ui: &mut UIContext,
+    renderer: &mut Renderer,
+    screenshot: &Path,
+) -> Result<Vec<PathBuf>, AovError> {
+    {
+        let mut aovs = world.write_resource::<Aovs>();
+        aovs.requested = true;
+        aovs.prepared = false;
+    }
+    super::draw_frame(world, ui, renderer)?;
+    let color = {
+        let mut aovs = world.write_resource::<Aovs>();
+        aovs.requested = false;
+        match &aovs.pass {
+            Some(pass) if aovs.prepared => pass.targets.color,
+            _ => return Err(AovError::NoCamera),
+        }
+    };
+
+    let (extent, _, data) = renderer.read_texture(&color)?;
+    let images = decode(extent, &data);
+    let mut paths = Vec::with_capacity(images.len());
+    for (name, image) in ["depth", "normal", "entity"].iter().zip(images.iter()) {
+        let path = aov_path(screenshot, name);
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+        hdr_export::write_exr(&mut file, image)?;
+        file.flush()?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vek::approx::assert_abs_diff_eq;
+
+    /// octahedral() in aov/frag.glsl
+    fn encode_normal(n: Vec3) -> (f32, f32) {
+        let n = n / (n.x.abs() + n.y.abs() + n.z.abs());
+        if n.z < 0.0 {
+            let sign = |v: f32| if v >= 0.0 { 1.0 } else { -1.0 };
+            ((1.0 - n.y.abs()) * sign(n.x), (1.0 - n.x.abs()) * sign(n.y))
+        } else {
+            (n.x, n.y)
+        }
+    }
+
+    #[test]
+    fn pass_is_decoded_to_images() {
+        let normals = [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.3, -0.8, -0.5).normalized(),
+        ];
+        let mut data = Vec::new();
+        for (i, n) in normals.iter().enumerate() {
+            let (x, y) = encode_normal(*n);
+            for v in [2.5 * (i + 1) as f32, 7.0 + i as f32, x, y].iter() {
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        // Nothing drawn
+        data.extend_from_slice(&[0; 16]);
+
+        let extent = Extent2D {
+            width: 3,
+            height: 1,
+        };
+        let [depth, normal, entity] = decode(extent, &data);
+        assert_eq!(
+            depth.pixels,
+            vec![[2.5, 2.5, 2.5, 1.0], [5.0, 5.0, 5.0, 1.0], [0.0; 4]]
+        );
+        assert_eq!(
+            entity.pixels,
+            vec![[6.0, 6.0, 6.0, 1.0], [7.0, 7.0, 7.0, 1.0], [0.0; 4]]
+        );
+        for (n, pixel) in normals.iter().zip(normal.pixels.iter()) {
+            assert_abs_diff_eq!(
+                Vec3::new(pixel[0], pixel[1], pixel[2]),
+                *n,
+                epsilon = 0.0001
+            );
+        }
+        assert_eq!(normal.pixels[2], [0.0; 4]);
+
+        assert_eq!(
+            aov_path(Path::new("out/shot.png"), "depth"),
+            PathBuf::from("out/shot_depth.exr")
+        );
+    }
+}
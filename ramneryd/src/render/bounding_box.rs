@@ -1,103 +1,112 @@
-use crate::common::Name;
+use std::collections::HashMap;
+
+use crate::anim::DeformedBounds;
 use crate::ecs::prelude::*;
-use crate::graph::sys as graph;
-use crate::math::{BoundingBox, Rgba, Transform, Vec3};
+use crate::math::{BoundingBox, BoundingSphere, ModelMatrix, Rgba};
 
-use super::mesh::CpuMesh;
+use super::debug_draw::DebugDraw;
+use super::debug_window::RenderSettings;
+use super::mesh::{CpuMesh, SharedMesh};
 
 #[derive(Default, Component)]
 #[component(storage = "NullStorage")]
 pub struct RenderBoundingBox;
 
-#[derive(Default, Component)]
-#[component(storage = "NullStorage")]
-pub struct BoundingBoxRenderer;
+/// Adds a BoundingSphere to the entities with a BoundingBox, around the vertices of their mesh or
+/// otherwise around the box. Deformed meshes only have the box, as DeformedBounds keeps it up to
+/// date but not the sphere.
+pub struct ComputeBoundingSpheres;
+
+impl ComputeBoundingSpheres {
+    pub const ID: &'static str = "ComputeBoundingSpheres";
+}
 
-pub struct CreateRenderedBoundingBoxes;
-impl<'a> System<'a> for CreateRenderedBoundingBoxes {
+impl<'a> System<'a> for ComputeBoundingSpheres {
     type SystemData = (
         Entities<'a>,
         ReadStorage<'a, BoundingBox>,
-        WriteStorage<'a, RenderBoundingBox>,
-        WriteStorage<'a, graph::Children>,
-        WriteStorage<'a, graph::Parent>,
-        WriteStorage<'a, Transform>,
-        WriteStorage<'a, Name>,
-        WriteStorage<'a, BoundingBoxRenderer>,
-        WriteStorage<'a, CpuMesh>,
-        WriteStorage<'a, super::material::Unlit>,
+        ReadStorage<'a, CpuMesh>,
+        ReadStorage<'a, SharedMesh>,
+        ReadStorage<'a, DeformedBounds>,
+        WriteStorage<'a, BoundingSphere>,
     );
 
-    fn run(
-        &mut self,
-        (
-            entities,
-            bounding_box_storage,
-            command_markers,
-            mut children_storage,
-            mut parent_storage,
-            mut transforms,
-            mut names,
-            mut renderer_markers,
-            mut meshes,
-            mut materials,
-        ): Self::SystemData,
-    ) {
-        for (ent, bbox, _) in (&entities, &bounding_box_storage, &command_markers).join() {
-            let mut found = false;
-            graph::breadth_first(&children_storage, ent, |node| {
-                found = renderer_markers.get(node).is_some()
-            });
-            if found {
-                continue;
-            }
-            let dims = bbox.max - bbox.min;
-            let (vertex_buffer, index_buffer) = super::geometry::box_mesh(dims.x, dims.y, dims.z);
-            let mesh = CpuMesh {
-                vertex_buffer,
-                index_buffer,
-                polygon_mode: trekanten::pipeline::PolygonMode::Line,
-            };
-
-            let material = super::material::Unlit {
-                color: Rgba::new(1.0, 0.0, 0.0, 1.0),
-            };
-
-            let mut tfm = Transform::identity();
-            tfm.position = Vec3 {
-                x: dims.x / 2.0,
-                y: dims.y / 2.0,
-                z: dims.z / 2.0,
-            };
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, bboxes, meshes, shared_meshes, deformed, mut spheres) = data;
 
-            let child = entities
-                .build_entity()
-                .with(Name::from("BoundingBoxRenderer"), &mut names)
-                .with(tfm, &mut transforms)
-                .with(BoundingBoxRenderer, &mut renderer_markers)
-                .with(mesh, &mut meshes)
-                .with(material, &mut materials)
-                .build();
+        // Instances of a mesh share the sphere, as it is in model space
+        let mut shared_spheres: HashMap<usize, Option<BoundingSphere>> = HashMap::new();
+        let missing: Vec<(Entity, BoundingSphere)> = (
+            &entities,
+            &bboxes,
+            meshes.maybe(),
+            shared_meshes.maybe(),
+            !&deformed,
+            !&spheres,
+        )
+            .join()
+            .map(|(ent, bbox, mesh, shared, _, _)| {
+                let from_mesh = match (shared, mesh) {
+                    (Some(shared), _) => *shared_spheres
+                        .entry(shared.key())
+                        .or_insert_with(|| BoundingSphere::from_points(&shared.0.positions())),
+                    (None, Some(mesh)) => BoundingSphere::from_points(&mesh.positions()),
+                    (None, None) => None,
+                };
+                (ent, from_mesh.unwrap_or_else(|| bbox.bounding_sphere()))
+            })
+            .collect();
 
-            graph::add_edge(&mut children_storage, &mut parent_storage, ent, child);
+        for (ent, sphere) in missing {
+            spheres.insert(ent, sphere).expect("This is alive");
         }
+    }
+}
 
-        for (ent, _marker) in (&entities, &renderer_markers).join() {
-            if let Some(graph::Parent { parent }) = parent_storage.get(ent) {
-                if command_markers.get(*parent).is_none() {
-                    entities.delete(ent).unwrap();
-                }
-            } else {
-                entities.delete(ent).unwrap();
+pub struct DrawBoundingBoxes;
+impl<'a> System<'a> for DrawBoundingBoxes {
+    type SystemData = (
+        ReadStorage<'a, BoundingBox>,
+        ReadStorage<'a, BoundingSphere>,
+        ReadStorage<'a, RenderBoundingBox>,
+        ReadStorage<'a, ModelMatrix>,
+        Read<'a, RenderSettings>,
+        Write<'a, DebugDraw>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (bounding_boxes, bounding_spheres, markers, model_matrices, settings, mut debug_draw) =
+            data;
+        let color = Rgba::new(1.0, 0.0, 0.0, 1.0);
+        let sphere_color = Rgba::new(1.0, 1.0, 0.0, 1.0);
+        for (bbox, sphere, _, mtx) in (
+            &bounding_boxes,
+            bounding_spheres.maybe(),
+            &markers,
+            &model_matrices,
+        )
+            .join()
+        {
+            if settings.render_bounding_box {
+                debug_draw.draw_box(bbox, &mtx.0, color);
+            }
+            if let (true, Some(sphere)) = (settings.render_bounding_spheres, sphere) {
+                let sphere = sphere.transformed(&mtx.0);
+                debug_draw.draw_sphere(sphere.center, sphere.radius, sphere_color);
             }
         }
     }
 }
 
 pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
-    builder.with(
-        CreateRenderedBoundingBoxes,
-        std::any::type_name::<CreateRenderedBoundingBoxes>(),
-        &[crate::render::debug_window::ApplySettings::ID],
-    )
+    builder
+        .with(ComputeBoundingSpheres, ComputeBoundingSpheres::ID, &[])
+        .with(
+            DrawBoundingBoxes,
+            std::any::type_name::<DrawBoundingBoxes>(),
+            &[
+                crate::render::debug_window::ApplySettings::ID,
+                ComputeBoundingSpheres::ID,
+            ],
+        )
 }
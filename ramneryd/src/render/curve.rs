@@ -0,0 +1,449 @@
+use crate::common::Name;
+use crate::ecs::prelude::*;
+use crate::graph::sys as graph;
+use crate::math::{Rgb, Rgba, Transform, Vec2, Vec3, Vec4};
+
+use ramneryd_derive::Inspect;
+use trekanten::pipeline::PolygonMode;
+
+use super::geometry::{self, PrimitiveOptions};
+use super::material::{PhysicallyBased, Unlit, DEFAULT_IOR};
+use super::mesh::CpuMesh;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Inspect)]
+pub enum SplineKind {
+    /// Piecewise cubic. Every third control point is on the curve and the two in between are the
+    /// tangent handles, i.e. 4, 7, 10... control points.
+    Bezier,
+    /// Passes through all the control points
+    CatmullRom,
+}
+
+#[derive(Debug, Clone, PartialEq, Inspect)]
+pub enum CurveShape {
+    Line {
+        color: Rgba,
+    },
+    /// A circle extruded along the curve, e.g. for pipes
+    Tube {
+        radius: f32,
+        segments: u32,
+        color: Rgba,
+    },
+    /// A closed, CCW profile extruded along the curve, e.g. for roads. The x-axis of the profile
+    /// points to the side of the curve and the y-axis up.
+    Extrude {
+        profile: Vec<Vec2>,
+        smooth: bool,
+        color: Rgba,
+    },
+}
+
+/// A spline in the local space of the entity
+#[derive(Debug, Clone, PartialEq, Component)]
+#[component(inspect)]
+pub struct Curve {
+    pub kind: SplineKind,
+    pub control_points: Vec<Vec3>,
+    /// Number of samples per segment of the spline when it is rendered
+    pub resolution: u32,
+    pub shape: CurveShape,
+    pub show_control_points: bool,
+}
+
+impl Default for Curve {
+    fn default() -> Self {
+        Self {
+            kind: SplineKind::CatmullRom,
+            control_points: vec![
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 1.0),
+                Vec3::new(2.0, 0.0, 0.0),
+                Vec3::new(3.0, 0.0, 1.0),
+            ],
+            resolution: 16,
+            shape: CurveShape::Line {
+                color: Rgba::new(1.0, 1.0, 1.0, 1.0),
+            },
+            show_control_points: true,
+        }
+    }
+}
+
+fn bezier(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let s = 1.0 - t;
+    p0 * (s * s * s) + p1 * (3.0 * s * s * t) + p2 * (3.0 * s * t * t) + p3 * (t * t * t)
+}
+
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+impl Curve {
+    pub fn n_segments(&self) -> usize {
+        let n = self.control_points.len();
+        match self.kind {
+            SplineKind::Bezier if n >= 4 => (n - 1) / 3,
+            SplineKind::CatmullRom if n >= 2 => n - 1,
+            _ => 0,
+        }
+    }
+
+    /// t is in [0, 1] within the segment
+    fn segment_point(&self, segment: usize, t: f32) -> Vec3 {
+        let p = &self.control_points;
+        match self.kind {
+            SplineKind::Bezier => {
+                let i = segment * 3;
+                bezier(p[i], p[i + 1], p[i + 2], p[i + 3], t)
+            }
+            SplineKind::CatmullRom => {
+                // The end points are repeated to make the curve reach them
+                let last = p.len() - 1;
+                let i = segment;
+                catmull_rom(
+                    p[i.saturating_sub(1)],
+                    p[i],
+                    p[i + 1],
+                    p[(i + 2).min(last)],
+                    t,
+                )
+            }
+        }
+    }
+
+    /// The point at t, in [0, 1] over the whole curve. Each segment covers an equal part of the
+    /// range, regardless of its length. None if there are too few control points.
+    pub fn sample(&self, t: f32) -> Option<Vec3> {
+        let n = self.n_segments();
+        if n == 0 {
+            return None;
+        }
+
+        let t = t.max(0.0).min(1.0) * n as f32;
+        let segment = (t as usize).min(n - 1);
+        Some(self.segment_point(segment, t - segment as f32))
+    }
+
+    /// Points along the curve, resolution per segment
+    pub fn tessellate(&self) -> Vec<Vec3> {
+        let n = self.n_segments();
+        if n == 0 {
+            return Vec::new();
+        }
+
+        let resolution = self.resolution.max(1);
+        let mut points = Vec::with_capacity(n * resolution as usize + 1);
+        for segment in 0..n {
+            for i in 0..resolution {
+                points.push(self.segment_point(segment, i as f32 / resolution as f32));
+            }
+        }
+        points.push(self.segment_point(n - 1, 1.0));
+
+        points
+    }
+
+    /// A small cross at each control point and, for Bezier curves, lines to the tangent handles
+    fn handle_lines(&self) -> Vec<[Vec3; 2]> {
+        const HANDLE_SIZE: f32 = 0.05;
+        let mut lines = Vec::new();
+        for p in self.control_points.iter() {
+            for axis in [Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()].iter() {
+                lines.push([*p - *axis * HANDLE_SIZE, *p + *axis * HANDLE_SIZE]);
+            }
+        }
+
+        if self.kind == SplineKind::Bezier {
+            let p = &self.control_points;
+            for segment in 0..self.n_segments() {
+                let i = segment * 3;
+                lines.push([p[i], p[i + 1]]);
+                lines.push([p[i + 2], p[i + 3]]);
+            }
+        }
+
+        lines
+    }
+}
+
+/// Marks the children of a curve entity that render it. Holds the curve that they were created
+/// from so that they can be recreated when it is edited.
+#[derive(Component)]
+pub struct CurveRenderer {
+    source: Curve,
+}
+
+fn pbr_material(color: Rgba) -> PhysicallyBased {
+    PhysicallyBased {
+        base_color_factor: Vec4::new(color.r, color.g, color.b, color.a),
+        metallic_factor: 0.0,
+        roughness_factor: 0.5,
+        normal_scale: 1.0,
+        normal_map: None,
+        base_color_texture: None,
+        metallic_roughness_texture: None,
+        emissive_factor: Rgb::new(0.0, 0.0, 0.0),
+        emissive_texture: None,
+        occlusion_strength: 1.0,
+        occlusion_texture: None,
+        transmission: 0.0,
+        ior: DEFAULT_IOR,
+        has_vertex_colors: false,
+    }
+}
+
+fn circle(radius: f32, segments: u32) -> Vec<Vec2> {
+    let segments = segments.max(3);
+    (0..segments)
+        .map(|i| {
+            let angle = i as f32 / segments as f32 * std::f32::consts::PI * 2.0;
+            Vec2::new(radius * angle.cos(), radius * angle.sin())
+        })
+        .collect()
+}
+
+pub struct CurveRendering;
+impl CurveRendering {
+    pub const ID: &'static str = "CurveRendering";
+}
+
+#[derive(SystemData)]
+pub struct CurveRenderingData<'a> {
+    entities: Entities<'a>,
+    curves: ReadStorage<'a, Curve>,
+    renderers: WriteStorage<'a, CurveRenderer>,
+    children_storage: WriteStorage<'a, graph::Children>,
+    parent_storage: WriteStorage<'a, graph::Parent>,
+    transforms: WriteStorage<'a, Transform>,
+    names: WriteStorage<'a, Name>,
+    meshes: WriteStorage<'a, CpuMesh>,
+    unlit: WriteStorage<'a, Unlit>,
+    pbr: WriteStorage<'a, PhysicallyBased>,
+}
+
+impl<'a> System<'a> for CurveRendering {
+    type SystemData = CurveRenderingData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let Self::SystemData {
+            entities,
+            curves,
+            mut renderers,
+            mut children_storage,
+            mut parent_storage,
+            mut transforms,
+            mut names,
+            mut meshes,
+            mut unlit,
+            mut pbr,
+        } = data;
+
+        for (ent, curve) in (&entities, &curves).join() {
+            let mut up_to_date = false;
+            if let Some(children) = children_storage.get_mut(ent) {
+                children
+                    .children
+                    .retain(|child| match renderers.get(*child) {
+                        Some(renderer) if renderer.source == *curve => {
+                            up_to_date = true;
+                            true
+                        }
+                        Some(_) => {
+                            entities.delete(*child).unwrap();
+                            false
+                        }
+                        None => true,
+                    });
+            }
+            if up_to_date {
+                continue;
+            }
+
+            let points = curve.tessellate();
+            let mut children = Vec::new();
+            if points.len() >= 2 {
+                let child = entities
+                    .build_entity()
+                    .with(Name::from("CurveRenderer"), &mut names)
+                    .with(Transform::identity(), &mut transforms);
+
+                let child = match &curve.shape {
+                    CurveShape::Line { color } => {
+                        let lines = points.windows(2).map(|w| [w[0], w[1]]).collect::<Vec<_>>();
+                        let (vertex_buffer, index_buffer) = geometry::line_list_mesh(&lines);
+                        let mesh = CpuMesh {
+                            vertex_buffer,
+                            index_buffer,
+                            polygon_mode: PolygonMode::Line,
+                        };
+                        child
+                            .with(mesh, &mut meshes)
+                            .with(Unlit { color: *color }, &mut unlit)
+                    }
+                    CurveShape::Tube {
+                        radius,
+                        segments,
+                        color,
+                    } => {
+                        let profile = circle(*radius, *segments);
+                        let (vertex_buffer, index_buffer) = geometry::extrude_mesh(
+                            &points,
+                            &profile,
+                            true,
+                            &PrimitiveOptions::default(),
+                        );
+                        let mesh = CpuMesh {
+                            vertex_buffer,
+                            index_buffer,
+                            polygon_mode: PolygonMode::Fill,
+                        };
+                        child
+                            .with(mesh, &mut meshes)
+                            .with(pbr_material(*color), &mut pbr)
+                    }
+                    CurveShape::Extrude {
+                        profile,
+                        smooth,
+                        color,
+                    } if profile.len() >= 2 => {
+                        let (vertex_buffer, index_buffer) = geometry::extrude_mesh(
+                            &points,
+                            profile,
+                            *smooth,
+                            &PrimitiveOptions::default(),
+                        );
+                        let mesh = CpuMesh {
+                            vertex_buffer,
+                            index_buffer,
+                            polygon_mode: PolygonMode::Fill,
+                        };
+                        child
+                            .with(mesh, &mut meshes)
+                            .with(pbr_material(*color), &mut pbr)
+                    }
+                    CurveShape::Extrude { .. } => child,
+                };
+
+                children.push(
+                    child
+                        .with(
+                            CurveRenderer {
+                                source: curve.clone(),
+                            },
+                            &mut renderers,
+                        )
+                        .build(),
+                );
+            }
+
+            // The control points are edited in the inspector, this shows where they are
+            if curve.show_control_points && !curve.control_points.is_empty() {
+                let (vertex_buffer, index_buffer) = geometry::line_list_mesh(&curve.handle_lines());
+                let mesh = CpuMesh {
+                    vertex_buffer,
+                    index_buffer,
+                    polygon_mode: PolygonMode::Line,
+                };
+                let child = entities
+                    .build_entity()
+                    .with(Name::from("CurveHandles"), &mut names)
+                    .with(Transform::identity(), &mut transforms)
+                    .with(mesh, &mut meshes)
+                    .with(
+                        Unlit {
+                            color: Rgba::new(1.0, 1.0, 0.0, 1.0),
+                        },
+                        &mut unlit,
+                    )
+                    .with(
+                        CurveRenderer {
+                            source: curve.clone(),
+                        },
+                        &mut renderers,
+                    )
+                    .build();
+                children.push(child);
+            }
+
+            for child in children {
+                graph::add_edge(&mut children_storage, &mut parent_storage, ent, child);
+            }
+        }
+
+        for (ent, _renderer) in (&entities, &renderers).join() {
+            let has_curve = parent_storage
+                .get(ent)
+                .map(|graph::Parent { parent }| curves.get(*parent).is_some())
+                .unwrap_or(false);
+            if !has_curve {
+                entities.delete(ent).unwrap();
+            }
+        }
+    }
+}
+
+pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
+    builder.with(CurveRendering, CurveRendering::ID, &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vek::approx::assert_abs_diff_eq;
+
+    const EPS: f32 = 0.00001;
+
+    fn curve(kind: SplineKind, n: usize) -> Curve {
+        Curve {
+            kind,
+            control_points: (0..n)
+                .map(|i| Vec3::new(i as f32, (i % 2) as f32, 0.0))
+                .collect(),
+            resolution: 4,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_control_points() {
+        let c = curve(SplineKind::CatmullRom, 5);
+        assert_eq!(c.n_segments(), 4);
+        let points = c.tessellate();
+        assert_eq!(points.len(), 4 * 4 + 1);
+        for (i, p) in c.control_points.iter().enumerate() {
+            assert_abs_diff_eq!(points[i * 4], *p, epsilon = EPS);
+            assert_abs_diff_eq!(c.sample(i as f32 / 4.0).unwrap(), *p, epsilon = EPS);
+        }
+    }
+
+    #[test]
+    fn bezier_passes_through_every_third_point() {
+        let c = curve(SplineKind::Bezier, 8);
+        // The last point doesn't complete a segment
+        assert_eq!(c.n_segments(), 2);
+        let p = &c.control_points;
+        assert_abs_diff_eq!(c.sample(0.0).unwrap(), p[0], epsilon = EPS);
+        assert_abs_diff_eq!(c.sample(0.5).unwrap(), p[3], epsilon = EPS);
+        assert_abs_diff_eq!(c.sample(1.0).unwrap(), p[6], epsilon = EPS);
+        // The tangent handles pull the curve
+        assert_abs_diff_eq!(
+            c.sample(0.25).unwrap(),
+            (p[0] + p[1] * 3.0 + p[2] * 3.0 + p[3]) / 8.0,
+            epsilon = EPS
+        );
+    }
+
+    #[test]
+    fn too_few_control_points() {
+        assert_eq!(curve(SplineKind::Bezier, 3).sample(0.5), None);
+        assert_eq!(curve(SplineKind::CatmullRom, 1).sample(0.5), None);
+        assert!(curve(SplineKind::CatmullRom, 0).tessellate().is_empty());
+    }
+}
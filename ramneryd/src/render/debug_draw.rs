@@ -0,0 +1,266 @@
+//! Immediate mode drawing of lines, for debugging. Any system can add lines to the DebugDraw
+//! resource. They are drawn, depth tested, in the scene pass of the next rendered frame and are
+//! then cleared, so they have to be added again every frame that they should be visible.
+
+use trekanten::descriptor::DescriptorSet;
+use trekanten::mem::{BufferMutability, OwningVertexBufferDescriptor, VertexBuffer};
+use trekanten::pipeline::{
+    GraphicsPipeline, GraphicsPipelineDescriptor, PrimitiveTopology, ShaderDescriptor, ShaderStage,
+    TriangleCulling,
+};
+use trekanten::resource::Handle;
+use trekanten::util::Format;
+use trekanten::vertex::{VertexDefinition, VertexFormat};
+use trekanten::{BufferHandle, Frame, RenderPass, RenderPassEncoder, Renderer};
+
+use crate::math::{BoundingBox, Mat4, Rgba, Vec3};
+
+use super::{pipeline, uniform, MaterialError};
+
+const SPHERE_SEGMENTS: usize = 32;
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct LineVertex {
+    pos: [f32; 3],
+    color: [f32; 4],
+}
+
+impl VertexDefinition for LineVertex {
+    fn format() -> VertexFormat {
+        VertexFormat::builder()
+            .add_attribute(Format::FLOAT3)
+            .add_attribute(Format::FLOAT4)
+            .build()
+    }
+}
+
+/// The lines to draw this frame, in world space
+#[derive(Default)]
+pub struct DebugDraw {
+    vertices: Vec<LineVertex>,
+}
+
+impl DebugDraw {
+    pub fn draw_line(&mut self, from: Vec3, to: Vec3, color: Rgba) {
+        let color = color.into_array();
+        self.vertices.push(LineVertex {
+            pos: from.into_array(),
+            color,
+        });
+        self.vertices.push(LineVertex {
+            pos: to.into_array(),
+            color,
+        });
+    }
+
+    pub fn draw_aabb(&mut self, bbox: &BoundingBox, color: Rgba) {
+        self.draw_box(bbox, &Mat4::identity(), color);
+    }
+
+    /// The edges of the box, transformed by the matrix. E.g. the bounding box of a mesh, with the
+    /// model matrix of its entity.
+    pub fn draw_box(&mut self, bbox: &BoundingBox, transform: &Mat4, color: Rgba) {
+        let corner = |i: usize| {
+            let x = if i & 1 == 0 { bbox.min.x } else { bbox.max.x };
+            let y = if i & 2 == 0 { bbox.min.y } else { bbox.max.y };
+            let z = if i & 4 == 0 { bbox.min.z } else { bbox.max.z };
+            transform.mul_point(Vec3::new(x, y, z))
+        };
+
+        // The corners that differ in exactly one of the coordinates share an edge
+        for i in 0..8 {
+            for axis in &[1, 2, 4] {
+                if i & axis == 0 {
+                    self.draw_line(corner(i), corner(i | axis), color);
+                }
+            }
+        }
+    }
+
+    /// A circle around each of the axes
+    pub fn draw_sphere(&mut self, center: Vec3, radius: f32, color: Rgba) {
+        let point = |i: usize| {
+            let angle = i as f32 / SPHERE_SEGMENTS as f32 * std::f32::consts::PI * 2.0;
+            (radius * angle.cos(), radius * angle.sin())
+        };
+
+        for i in 0..SPHERE_SEGMENTS {
+            let (a0, b0) = point(i);
+            let (a1, b1) = point(i + 1);
+            self.draw_line(
+                center + Vec3::new(a0, b0, 0.0),
+                center + Vec3::new(a1, b1, 0.0),
+                color,
+            );
+            self.draw_line(
+                center + Vec3::new(a0, 0.0, b0),
+                center + Vec3::new(a1, 0.0, b1),
+                color,
+            );
+            self.draw_line(
+                center + Vec3::new(0.0, a0, b0),
+                center + Vec3::new(0.0, a1, b1),
+                color,
+            );
+        }
+    }
+
+    /// The x, y and z axes of the transform in red, green and blue
+    pub fn draw_axes(&mut self, transform: &Mat4, length: f32) {
+        let origin = transform.mul_point(Vec3::zero());
+        let axes = [
+            (Vec3::unit_x(), Rgba::red()),
+            (Vec3::unit_y(), Rgba::green()),
+            (Vec3::unit_z(), Rgba::blue()),
+        ];
+        for (axis, color) in axes.iter() {
+            let end = transform.mul_point(*axis * length);
+            self.draw_line(origin, end, *color);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+}
+
+fn line_pipeline(
+    shader_compiler: &pipeline::ShaderCompiler,
+    renderer: &mut Renderer,
+    render_pass: &Handle<RenderPass>,
+) -> Result<Handle<GraphicsPipeline>, MaterialError> {
+    let mut defines = pipeline::Defines::empty();
+    defines.push((String::from("HAS_VERTEX_COLOR"), String::from("1")));
+    let vertex =
+        shader_compiler.compile(&defines, "pos_only_vert.glsl", pipeline::ShaderType::Vertex)?;
+    let fragment = shader_compiler.compile(
+        &defines,
+        "uniform_color_frag.glsl",
+        pipeline::ShaderType::Fragment,
+    )?;
+
+    let desc = GraphicsPipelineDescriptor::builder()
+        .vert(ShaderDescriptor::FromRawSpirv(vertex.data()))
+        .frag(ShaderDescriptor::FromRawSpirv(fragment.data()))
+        .vertex_format(LineVertex::format())
+        .culling(TriangleCulling::None)
+        .topology(PrimitiveTopology::LineList)
+        .build()?;
+
+    Ok(renderer.create_gfx_pipeline(desc, render_pass)?)
+}
+
+/// Draws the lines of the DebugDraw resource with the unlit shaders
+pub(super) struct DebugDrawRenderer {
+    pipeline: Handle<GraphicsPipeline>,
+    vertex_buffer: Option<BufferHandle<VertexBuffer>>,
+    n_vertices: u32,
+}
+
+impl DebugDrawRenderer {
+    pub fn new(
+        shader_compiler: &pipeline::ShaderCompiler,
+        renderer: &mut Renderer,
+        scene_render_pass: &Handle<RenderPass>,
+    ) -> Result<Self, MaterialError> {
+        Ok(Self {
+            pipeline: line_pipeline(shader_compiler, renderer, scene_render_pass)?,
+            vertex_buffer: None,
+            n_vertices: 0,
+        })
+    }
+
+    pub fn recreate_pipeline(
+        &mut self,
+        shader_compiler: &pipeline::ShaderCompiler,
+        renderer: &mut Renderer,
+        scene_render_pass: &Handle<RenderPass>,
+    ) -> Result<(), MaterialError> {
+        let pipeline = line_pipeline(shader_compiler, renderer, scene_render_pass)?;
+        renderer.destroy_deferred(std::mem::replace(&mut self.pipeline, pipeline));
+        Ok(())
+    }
+
+    /// Uploads the lines for this frame and clears them
+    pub fn upload(&mut self, frame: &mut Frame, debug_draw: &mut DebugDraw) {
+        self.n_vertices = debug_draw.vertices.len() as u32;
+        if debug_draw.vertices.is_empty() {
+            return;
+        }
+
+        let vertices = std::mem::take(&mut debug_draw.vertices);
+        let desc = OwningVertexBufferDescriptor::from_vec(vertices, BufferMutability::Mutable);
+        if let Some(vertex_buffer) = self.vertex_buffer {
+            frame
+                .recreate_resource_blocking(vertex_buffer, desc)
+                .expect("Bad debug draw vertex buffer handle");
+        } else {
+            self.vertex_buffer = Some(
+                frame
+                    .create_resource_blocking(desc)
+                    .expect("Failed to create debug draw vertex buffer"),
+            );
+        }
+    }
+
+    /// Expects the view data to be bound in the shader resource group
+    pub fn record(
+        &self,
+        cmd_buf: &mut RenderPassEncoder,
+        shader_resource_group: &Handle<DescriptorSet>,
+    ) {
+        let vertex_buffer = match self.vertex_buffer {
+            Some(vertex_buffer) if self.n_vertices > 0 => vertex_buffer,
+            _ => return,
+        };
+
+        let identity = Mat4::identity().into_col_array();
+        let model = uniform::Model {
+            model: identity,
+            model_it: identity,
+        };
+        cmd_buf
+            .bind_graphics_pipeline(&self.pipeline)
+            .bind_shader_resource_group(0u32, shader_resource_group, &self.pipeline)
+            .bind_push_constant(&self.pipeline, ShaderStage::VERTEX, &model)
+            .bind_vertex_buffer(&vertex_buffer)
+            .draw(self.n_vertices);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_edges() {
+        let mut debug_draw = DebugDraw::default();
+        let bbox = BoundingBox {
+            min: Vec3::new(-1.0, -2.0, -3.0),
+            max: Vec3::new(1.0, 2.0, 3.0),
+        };
+        debug_draw.draw_aabb(&bbox, Rgba::red());
+
+        assert_eq!(debug_draw.vertices.len(), 24);
+        for line in debug_draw.vertices.chunks(2) {
+            let from = Vec3::from(line[0].pos);
+            let to = Vec3::from(line[1].pos);
+            let differs = (0..3).filter(|&i| from[i] != to[i]).count();
+            assert_eq!(differs, 1);
+        }
+    }
+
+    #[test]
+    fn sphere_points_are_on_the_sphere() {
+        let mut debug_draw = DebugDraw::default();
+        let center = Vec3::new(1.0, 2.0, 3.0);
+        debug_draw.draw_sphere(center, 2.0, Rgba::white());
+
+        assert_eq!(debug_draw.vertices.len(), SPHERE_SEGMENTS * 3 * 2);
+        for v in debug_draw.vertices.iter() {
+            let d = Vec3::from(v.pos).distance(center);
+            assert!((d - 2.0).abs() < 1e-5);
+        }
+    }
+}
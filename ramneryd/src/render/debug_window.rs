@@ -1,8 +1,11 @@
 use crate::common::Name;
 use crate::ecs::prelude::*;
-use crate::io::input::{ActionId, InputContext, InputContextError, KeyCode, MappedInput};
+use crate::io::input::{ActionId, InputContext, InputContextError, MappedInput};
 use crate::math::{Rgb, Transform};
 use crate::render;
+use crate::render::exposure::{self, ExposureView};
+use crate::render::wireframe::WireframeOverlay;
+use crate::settings::{self, KeyBindings};
 
 use crate::editor;
 use editor::Inspect as _;
@@ -16,6 +19,8 @@ pub enum RenderMode {
     Wireframe,
 }
 
+impl_cvar_type_enum!(RenderMode);
+
 #[derive(Default)]
 struct AddLightModalState {
     idx: usize,
@@ -27,6 +32,7 @@ struct AddLightModalState {
 #[derive(Default)]
 struct RenderSettingsState {
     add_light_modal: Option<AddLightModalState>,
+    supported_msaa_sample_counts: Vec<u8>,
 }
 
 #[derive(Inspect)]
@@ -34,40 +40,461 @@ pub struct RenderSettings {
     // Affects all entities
     pub render_mode: RenderMode,
     pub render_bounding_box: bool,
+    /// Draw the bounding sphere of every mesh, see math::BoundingSphere
+    pub render_bounding_spheres: bool,
+    /// Draw the edges of the triangles over the lit scene, see render::wireframe
+    pub wireframe_overlay: WireframeOverlay,
+    /// The depth that is shown as white by ViewMode::Depth, see render::view_mode
+    pub view_mode_depth_range: f32,
     pub reload_shaders: bool,
+    /// Capture the next frame with RenderDoc, see FrameCapture
+    pub capture_frame: bool,
     pub render_light_volumes: bool,
+    /// Leave out point and spot lights that are out of view or far away, see LightLod
+    pub cull_lights: bool,
+    /// Only draw the meshes that can cast a shadow into view into the shadow maps, and skip the
+    /// shadow maps of lights that don't light anything in view
+    pub cull_shadow_casters: bool,
+    /// Keep the shadow maps between frames and only render them again if something in them changed,
+    /// see light::ShadowMapCache
+    pub cache_shadow_maps: bool,
+    /// The RenderLayers that are drawn from the camera, see render::visibility
+    pub camera_layers: u32,
+    /// The RenderLayers that cast shadows
+    pub shadow_layers: u32,
+    /// Blur the brightest parts of the image and add them back, see render::post
+    pub bloom: bool,
+    /// Only the parts of the image that are brighter than this contribute to the bloom
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    /// Light shafts from spot lights with the Volumetric component, see render::volumetric
+    pub volumetric_lighting: bool,
+    /// The number of steps along each view ray. More gives smoother light shafts but is slower.
+    pub volumetric_samples: u32,
+    /// The most memory that textures streamed from files may use, see render::streaming
+    pub texture_budget_mb: u32,
+    /// Textures closer to the camera than this are streamed at full resolution
+    pub texture_full_resolution_distance: f32,
+    /// The most memory that the resident pages of virtual textures may use, see
+    /// render::virtual_texture
+    pub virtual_texture_budget_mb: u32,
+    /// The most that the loader uploads per frame, 0 for no limit. Loads that don't fit are
+    /// uploaded in later frames, see trekanten::loader::UploadBudget.
+    pub upload_budget_kb: u32,
+    /// The most time that the loader spends on uploads per frame, including decoding image files,
+    /// 0 for no limit
+    pub upload_budget_ms: f32,
+    /// Replaces the image with an analysis of its exposure, see render::exposure
+    pub exposure_view: ExposureView,
+    /// The zebra view stripes the parts with a luminance above this
+    pub zebra_threshold: f32,
+    /// Show the distribution of the luminance of the scene, in stops from middle gray
+    pub luminance_histogram: bool,
+    /// Read the model matrices from a storage buffer instead of push constants, see
+    /// render::object_data. Changing this recreates the pipelines of the entities.
+    pub object_buffer: bool,
+    /// The width and height of the spot light shadow maps. Changing this recreates them.
+    pub shadow_resolution: u32,
+    /// Scales the distances at which LodGroups switch to a lower level, see render::lod
+    pub lod_bias: f32,
+    /// Raise the LOD bias while the gpu time of the frames is over lod_gpu_budget_ms
+    pub lod_auto_bias: bool,
+    pub lod_gpu_budget_ms: f32,
+    /// The automatic bias doesn't go above this
+    pub lod_max_bias: f32,
+    /// Limit the frame rate to this, 0 for no limit, see crate::frame_pacing
+    pub max_fps: u32,
+    /// Trace a reference image of the view on the gpu, see render::path_trace. It is sampled more
+    /// every frame until the view or the scene changes.
+    pub path_trace: bool,
+    /// Show the path traced image instead of the rasterized scene
+    pub path_trace_show: bool,
+    /// The number of paths that are traced per frame. About one per pixel of the image at 1080p.
+    pub path_trace_rays_per_frame: u32,
+    /// The number of times that the paths bounce off the surfaces
+    pub path_trace_bounces: u32,
+    /// Changing this recreates the main render pass and all pipelines that use it
+    #[inspect(ignore)]
+    pub msaa_sample_count: u8,
 
     #[inspect(ignore)]
     state: RenderSettingsState,
 }
 
+impl RenderSettings {
+    /// The sample counts supported by the device. The current setting is lowered to the closest
+    /// supported count if it isn't supported.
+    pub(crate) fn set_supported_msaa_sample_counts(&mut self, supported: Vec<u8>) {
+        let requested = self.msaa_sample_count;
+        self.msaa_sample_count = supported
+            .iter()
+            .copied()
+            .filter(|&n| n <= requested)
+            .max()
+            .unwrap_or(1);
+        self.state.supported_msaa_sample_counts = supported;
+    }
+
+    pub(crate) fn upload_budget(&self) -> trekanten::loader::UploadBudget {
+        trekanten::loader::UploadBudget {
+            bytes: Some(self.upload_budget_kb as usize * 1024).filter(|&b| b > 0),
+            time: Some(self.upload_budget_ms)
+                .filter(|&ms| ms > 0.0)
+                .map(|ms| std::time::Duration::from_secs_f32(ms / 1000.0)),
+        }
+    }
+
+    fn msaa_ui<'a>(&mut self, ui: &crate::render::ui::UiFrame<'a>) {
+        let supported = &self.state.supported_msaa_sample_counts;
+        let labels: Vec<imgui::ImString> =
+            supported.iter().map(|n| imgui::im_str!("{}x", n)).collect();
+        let label_refs: Vec<&imgui::ImStr> = labels.iter().map(|l| l.as_ref()).collect();
+        let mut idx = supported
+            .iter()
+            .position(|&n| n == self.msaa_sample_count)
+            .unwrap_or(0);
+        if imgui::ComboBox::new(imgui::im_str!("MSAA")).build_simple_string(
+            ui.inner(),
+            &mut idx,
+            &label_refs,
+        ) {
+            self.msaa_sample_count = supported[idx];
+        }
+    }
+}
+
 impl Default for RenderSettings {
     fn default() -> Self {
         Self {
             render_mode: RenderMode::Opaque,
             render_bounding_box: false,
+            render_bounding_spheres: false,
+            wireframe_overlay: WireframeOverlay::Off,
+            view_mode_depth_range: 50.0,
             reload_shaders: false,
+            capture_frame: false,
             render_light_volumes: false,
+            cull_lights: true,
+            cull_shadow_casters: true,
+            cache_shadow_maps: true,
+            camera_layers: u32::MAX,
+            shadow_layers: u32::MAX,
+            bloom: true,
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.5,
+            volumetric_lighting: true,
+            volumetric_samples: 32,
+            texture_budget_mb: 512,
+            texture_full_resolution_distance: 10.0,
+            virtual_texture_budget_mb: 256,
+            upload_budget_kb: 0,
+            upload_budget_ms: 4.0,
+            exposure_view: ExposureView::Off,
+            zebra_threshold: 1.0,
+            luminance_histogram: false,
+            object_buffer: true,
+            shadow_resolution: 1024,
+            lod_bias: 0.0,
+            lod_auto_bias: false,
+            lod_gpu_budget_ms: 16.0,
+            lod_max_bias: 4.0,
+            max_fps: 0,
+            path_trace: false,
+            path_trace_show: true,
+            path_trace_rays_per_frame: 131072,
+            path_trace_bounces: 3,
+            msaa_sample_count: 8,
             state: RenderSettingsState::default(),
         }
     }
 }
 
-fn get_input_context() -> Result<InputContext, InputContextError> {
-    Ok(InputContext::builder(RenderSettingsSys::ID)
-        .description("Input for changing render settings")
-        .with_action(KeyCode::O, RENDER_MODE_SWITCH)?
-        .with_action(KeyCode::P, RENDER_BOUNDING_BOX_SWITCH)?
-        .with_action(KeyCode::R, RELOAD_SHADERS)?
-        .build())
+/// The settings that can be set by name, see crate::cvar. The actions, e.g. reload_shaders, are
+/// left out.
+fn register_cvars(world: &mut World) {
+    use crate::cvar::register;
+    type S = RenderSettings;
+
+    register(
+        world,
+        "r.mode",
+        "0 for opaque, 1 for wireframe",
+        None,
+        |s: &mut S| &mut s.render_mode,
+    );
+    register(
+        world,
+        "r.bounding_boxes",
+        "Draw the bounding box of every mesh",
+        None,
+        |s: &mut S| &mut s.render_bounding_box,
+    );
+    register(
+        world,
+        "r.bounding_spheres",
+        "Draw the bounding sphere of every mesh",
+        None,
+        |s: &mut S| &mut s.render_bounding_spheres,
+    );
+    register(
+        world,
+        "r.wireframe_overlay",
+        "0 for off, 1 for the selected entities, 2 for all meshes and 3 for LOD levels",
+        None,
+        |s: &mut S| &mut s.wireframe_overlay,
+    );
+    register(
+        world,
+        "r.view_mode.depth_range",
+        "The depth that the depth view mode shows as white",
+        Some((0.1, 10000.0)),
+        |s: &mut S| &mut s.view_mode_depth_range,
+    );
+    register(
+        world,
+        "r.light_volumes",
+        "Draw the volumes that the lights affect",
+        None,
+        |s: &mut S| &mut s.render_light_volumes,
+    );
+    register(
+        world,
+        "r.lights.cull",
+        "Leave out point and spot lights that are out of view or far away",
+        None,
+        |s: &mut S| &mut s.cull_lights,
+    );
+    register(
+        world,
+        "r.shadows.cull_casters",
+        "Only draw the meshes that can cast a shadow into view into the shadow maps",
+        None,
+        |s: &mut S| &mut s.cull_shadow_casters,
+    );
+    register(
+        world,
+        "r.shadows.cache",
+        "Only render the shadow maps again if something in them changed",
+        None,
+        |s: &mut S| &mut s.cache_shadow_maps,
+    );
+    register(
+        world,
+        "r.layers.camera",
+        "Bit mask of the render layers that are drawn from the camera",
+        Some((0.0, u32::MAX as f64)),
+        |s: &mut S| &mut s.camera_layers,
+    );
+    register(
+        world,
+        "r.layers.shadows",
+        "Bit mask of the render layers that cast shadows",
+        Some((0.0, u32::MAX as f64)),
+        |s: &mut S| &mut s.shadow_layers,
+    );
+    register(world, "r.bloom", "Enable bloom", None, |s: &mut S| {
+        &mut s.bloom
+    });
+    register(
+        world,
+        "r.bloom.threshold",
+        "Only the parts of the image brighter than this contribute to the bloom",
+        Some((0.0, 100.0)),
+        |s: &mut S| &mut s.bloom_threshold,
+    );
+    register(
+        world,
+        "r.bloom.intensity",
+        "How much of the bloom that is added to the image",
+        Some((0.0, 10.0)),
+        |s: &mut S| &mut s.bloom_intensity,
+    );
+    register(
+        world,
+        "r.volumetric",
+        "Light shafts from spot lights with the Volumetric component",
+        None,
+        |s: &mut S| &mut s.volumetric_lighting,
+    );
+    register(
+        world,
+        "r.volumetric.samples",
+        "The number of steps along each view ray",
+        Some((1.0, 256.0)),
+        |s: &mut S| &mut s.volumetric_samples,
+    );
+    register(
+        world,
+        "r.textures.budget_mb",
+        "The most memory that streamed textures may use",
+        Some((16.0, 65536.0)),
+        |s: &mut S| &mut s.texture_budget_mb,
+    );
+    register(
+        world,
+        "r.textures.full_resolution_distance",
+        "Textures closer to the camera than this are streamed at full resolution",
+        Some((0.0, 10000.0)),
+        |s: &mut S| &mut s.texture_full_resolution_distance,
+    );
+    register(
+        world,
+        "r.textures.virtual_budget_mb",
+        "The most memory that the resident pages of virtual textures may use",
+        Some((16.0, 65536.0)),
+        |s: &mut S| &mut s.virtual_texture_budget_mb,
+    );
+    register(
+        world,
+        "r.upload.budget_kb",
+        "The most that is uploaded to the gpu per frame, 0 for no limit",
+        Some((0.0, 1048576.0)),
+        |s: &mut S| &mut s.upload_budget_kb,
+    );
+    register(
+        world,
+        "r.upload.budget_ms",
+        "The most time that is spent on uploads per frame, 0 for no limit",
+        Some((0.0, 1000.0)),
+        |s: &mut S| &mut s.upload_budget_ms,
+    );
+    register(
+        world,
+        "r.exposure.view",
+        "0 for off, 1 for false color and 2 for zebra stripes",
+        None,
+        |s: &mut S| &mut s.exposure_view,
+    );
+    register(
+        world,
+        "r.exposure.zebra_threshold",
+        "The zebra view stripes the parts with a luminance above this",
+        Some((0.0, 100.0)),
+        |s: &mut S| &mut s.zebra_threshold,
+    );
+    register(
+        world,
+        "r.exposure.histogram",
+        "Show the luminance histogram in the render debug window",
+        None,
+        |s: &mut S| &mut s.luminance_histogram,
+    );
+    register(
+        world,
+        "r.object_buffer",
+        "Read the model matrices from a storage buffer instead of push constants",
+        None,
+        |s: &mut S| &mut s.object_buffer,
+    );
+    register(
+        world,
+        "r.shadow.resolution",
+        "The width and height of the spot light shadow maps, in texels",
+        Some((64.0, 8192.0)),
+        |s: &mut S| &mut s.shadow_resolution,
+    );
+    register(
+        world,
+        "r.lod.bias",
+        "Scales the distances at which meshes switch to a lower level of detail",
+        Some((0.0, 100.0)),
+        |s: &mut S| &mut s.lod_bias,
+    );
+    register(
+        world,
+        "r.lod.auto",
+        "Raise the LOD bias while the gpu time of the frames is over budget",
+        None,
+        |s: &mut S| &mut s.lod_auto_bias,
+    );
+    register(
+        world,
+        "r.lod.budget_ms",
+        "The gpu time of a frame that the automatic LOD bias aims for",
+        Some((1.0, 1000.0)),
+        |s: &mut S| &mut s.lod_gpu_budget_ms,
+    );
+    register(
+        world,
+        "r.lod.max_bias",
+        "The automatic LOD bias doesn't go above this",
+        Some((0.0, 100.0)),
+        |s: &mut S| &mut s.lod_max_bias,
+    );
+    register(
+        world,
+        "r.max_fps",
+        "Limit the frame rate to this, 0 for no limit",
+        Some((0.0, 1000.0)),
+        |s: &mut S| &mut s.max_fps,
+    );
+    register(
+        world,
+        "r.path_trace",
+        "Trace a reference image of the view on the gpu",
+        None,
+        |s: &mut S| &mut s.path_trace,
+    );
+    register(
+        world,
+        "r.path_trace.show",
+        "Show the path traced image instead of the rasterized scene",
+        None,
+        |s: &mut S| &mut s.path_trace_show,
+    );
+    register(
+        world,
+        "r.path_trace.rays_per_frame",
+        "The number of paths that are traced per frame",
+        Some((0.0, 1_000_000.0)),
+        |s: &mut S| &mut s.path_trace_rays_per_frame,
+    );
+    register(
+        world,
+        "r.path_trace.bounces",
+        "The number of times that the paths bounce off the surfaces",
+        Some((0.0, 16.0)),
+        |s: &mut S| &mut s.path_trace_bounces,
+    );
+    register(
+        world,
+        "r.msaa",
+        "The number of samples per pixel, lowered to what the device supports",
+        Some((1.0, 64.0)),
+        |s: &mut S| &mut s.msaa_sample_count,
+    );
+}
+
+fn get_input_context(bindings: &KeyBindings) -> Result<InputContext, InputContextError> {
+    let actions = [
+        (settings::RENDER_MODE_SWITCH, RENDER_MODE_SWITCH),
+        (
+            settings::RENDER_BOUNDING_BOX_SWITCH,
+            RENDER_BOUNDING_BOX_SWITCH,
+        ),
+        (settings::RELOAD_SHADERS, RELOAD_SHADERS),
+        (settings::CAPTURE_FRAME, CAPTURE_FRAME),
+        (settings::WIREFRAME_OVERLAY_SWITCH, WIREFRAME_OVERLAY_SWITCH),
+    ];
+    let mut builder = InputContext::builder(RenderSettingsSys::ID)
+        .description("Input for changing render settings");
+    for (action, id) in actions.iter() {
+        if let Some(button) = bindings.get(action) {
+            builder = builder.with_action(button, *id)?;
+        }
+    }
+    Ok(builder.build())
 }
 
 const RENDER_MODE_SWITCH: ActionId = ActionId(0);
 const RENDER_BOUNDING_BOX_SWITCH: ActionId = ActionId(1);
 const RELOAD_SHADERS: ActionId = ActionId(2);
+const CAPTURE_FRAME: ActionId = ActionId(3);
+const WIREFRAME_OVERLAY_SWITCH: ActionId = ActionId(4);
 
 struct RenderSettingsSys {
     input_entity: Option<specs::Entity>,
+    bindings_version: u32,
 }
 
 impl RenderSettingsSys {
@@ -75,11 +502,28 @@ impl RenderSettingsSys {
 }
 
 impl<'a> System<'a> for RenderSettingsSys {
-    type SystemData = (Write<'a, RenderSettings>, WriteStorage<'a, MappedInput>);
+    type SystemData = (
+        Write<'a, RenderSettings>,
+        WriteStorage<'a, MappedInput>,
+        Read<'a, KeyBindings>,
+        WriteStorage<'a, InputContext>,
+    );
 
-    fn run(&mut self, (mut r_settings, mut inputs): Self::SystemData) {
+    fn run(&mut self, (mut r_settings, mut inputs, bindings, mut contexts): Self::SystemData) {
         use crate::io::input::Input;
         log::trace!("RenderSettingsSys: run");
+        if bindings.version() != self.bindings_version {
+            match get_input_context(&bindings) {
+                Ok(ctx) => {
+                    contexts
+                        .insert(self.input_entity.unwrap(), ctx)
+                        .expect("Failed to replace the render settings input context");
+                }
+                Err(e) => log::error!("Failed to rebind the render settings: {:?}", e),
+            }
+            self.bindings_version = bindings.version();
+        }
+
         let inp = inputs
             .get_mut(self.input_entity.unwrap())
             .expect("Failed to get mapped input for RenderSettingsSys");
@@ -101,6 +545,19 @@ impl<'a> System<'a> for RenderSettingsSys {
                     log::debug!("Reload runtime shaders!");
                     r_settings.reload_shaders = true;
                 }
+                Input::Action(CAPTURE_FRAME) => {
+                    log::debug!("Capture frame!");
+                    r_settings.capture_frame = true;
+                }
+                Input::Action(WIREFRAME_OVERLAY_SWITCH) => {
+                    log::debug!("Wireframe overlay switch!");
+                    r_settings.wireframe_overlay = match r_settings.wireframe_overlay {
+                        WireframeOverlay::Off => WireframeOverlay::Selected,
+                        WireframeOverlay::Selected => WireframeOverlay::All,
+                        WireframeOverlay::All => WireframeOverlay::Lod,
+                        WireframeOverlay::Lod => WireframeOverlay::Off,
+                    };
+                }
                 i => unreachable!("{:?}", i),
             }
         }
@@ -109,7 +566,12 @@ impl<'a> System<'a> for RenderSettingsSys {
     fn setup(&mut self, world: &mut World) {
         Self::SystemData::setup(world);
         world.insert(RenderSettings::default());
-        let ctx = get_input_context().expect("Failed to build settings input context");
+        register_cvars(world);
+        let ctx = {
+            let bindings = world.read_resource::<KeyBindings>();
+            self.bindings_version = bindings.version();
+            get_input_context(&bindings).expect("Failed to build settings input context")
+        };
         self.input_entity = Some(
             world
                 .create_entity()
@@ -123,7 +585,10 @@ impl<'a> System<'a> for RenderSettingsSys {
 pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
     builder
         .with(
-            RenderSettingsSys { input_entity: None },
+            RenderSettingsSys {
+                input_entity: None,
+                bindings_version: 0,
+            },
             RenderSettingsSys::ID,
             &[],
         )
@@ -144,6 +609,86 @@ pub(crate) fn build_ui<'a>(
             {
                 let mut settings = world.write_resource::<RenderSettings>();
                 settings.inspect_mut(ui, "");
+                settings.msaa_ui(ui);
+                let usage = world
+                    .read_resource::<render::streaming::TextureStreaming>()
+                    .usage_bytes();
+                ui.inner().text(format!(
+                    "Streamed textures: {:.1} / {} MiB",
+                    usage as f64 / (1024.0 * 1024.0),
+                    settings.texture_budget_mb
+                ));
+                let usage = world
+                    .read_resource::<render::virtual_texture::VirtualTextures>()
+                    .resident_bytes();
+                ui.inner().text(format!(
+                    "Virtual texture pages: {:.1} / {} MiB",
+                    usage as f64 / (1024.0 * 1024.0),
+                    settings.virtual_texture_budget_mb
+                ));
+                let draws = *world.read_resource::<render::DrawStats>();
+                ui.inner().text(format!("Draws: {}", draws.draws));
+                ui.inner().text(format!(
+                    "Binds: {} pipelines, {} descriptor sets",
+                    draws.pipeline_binds, draws.descriptor_set_binds
+                ));
+                ui.inner().text(format!(
+                    "       {} vertex buffers, {} index buffers",
+                    draws.vertex_buffer_binds, draws.index_buffer_binds
+                ));
+                ui.inner()
+                    .text(format!("Culled shadow casters: {}", draws.culled));
+                {
+                    let shadows = world.read_resource::<render::light::ShadowMapCache>();
+                    ui.inner().text(format!(
+                        "Shadow maps: {} rendered, {} cached",
+                        shadows.rendered, shadows.reused
+                    ));
+                }
+                ui.inner().text(format!(
+                    "Uploads waiting for the budget: {}",
+                    world.read_resource::<trekanten::Loader>().num_queued()
+                ));
+                {
+                    let mut frame_times = world.write_resource::<crate::frame_pacing::FrameTimes>();
+                    ui.inner().text(format!(
+                        "Frame time (ms): avg {:.2}, 99% {:.2}, max {:.2}",
+                        frame_times.average(),
+                        frame_times.percentile(0.99),
+                        frame_times.max()
+                    ));
+                    imgui::PlotLines::new(
+                        ui.inner(),
+                        imgui::im_str!("Frame times"),
+                        frame_times.times(),
+                    )
+                    .scale_min(0.0)
+                    .graph_size([0.0, 60.0])
+                    .build();
+                    let label = imgui::im_str!(
+                        "Frame time histogram, {} ms bins",
+                        crate::frame_pacing::HISTOGRAM_BIN_MS
+                    );
+                    imgui::PlotHistogram::new(ui.inner(), &label, &frame_times.bins)
+                        .scale_min(0.0)
+                        .graph_size([0.0, 60.0])
+                        .build();
+                }
+                if settings.luminance_histogram {
+                    let histogram = world.read_resource::<exposure::LuminanceHistogram>();
+                    let label = imgui::im_str!(
+                        "Luminance, {} to {} EV",
+                        exposure::HISTOGRAM_MIN_EV,
+                        exposure::HISTOGRAM_MAX_EV
+                    );
+                    imgui::PlotHistogram::new(ui.inner(), &label, &histogram.bins)
+                        .scale_min(0.0)
+                        .graph_size([0.0, 80.0])
+                        .build();
+                }
+                world
+                    .write_resource::<render::inspector::RenderTargetInspector>()
+                    .build_ui(ui);
                 ui.inner().text("Lights");
                 let mut lights = world.write_storage::<render::light::Light>();
                 let mut transforms = world.write_storage::<crate::math::Transform>();
@@ -195,6 +740,7 @@ pub(crate) fn build_ui<'a>(
                                     g: 1.0,
                                     b: 1.0,
                                 },
+                                intensity: render::light::LightIntensity::default(),
                                 range: 5.0,
                             },
                             1 => render::light::Light::Directional {
@@ -210,7 +756,9 @@ pub(crate) fn build_ui<'a>(
                                     g: 1.0,
                                     b: 1.0,
                                 },
-                                angle: std::f32::consts::FRAC_PI_8,
+                                intensity: render::light::LightIntensity::default(),
+                                inner_angle: 0.0,
+                                outer_angle: std::f32::consts::FRAC_PI_8,
                                 range: 5.0,
                             },
                             3 => render::light::Light::Ambient {
@@ -254,11 +802,8 @@ pub(crate) fn build_ui<'a>(
                             choice, name, tfm, ..
                         } = modal_state.expect("create should only be set if this existed");
                         world
-                            .create_entity()
-                            .with(choice)
-                            .with(tfm)
-                            .with(name)
-                            .build();
+                            .write_resource::<render::light_commands::LightCommands>()
+                            .spawn_named(choice, tfm, name);
                     } else {
                         world
                             .write_resource::<RenderSettings>()
@@ -311,7 +856,7 @@ impl<'a> System<'a> for ApplySettings {
             render_settings.reload_shaders = false;
         }
 
-        if render_settings.render_bounding_box {
+        if render_settings.render_bounding_box || render_settings.render_bounding_spheres {
             for (ent, _bbox) in (&entities, &bounding_boxes).join() {
                 if render_bbox.get(ent).is_none() {
                     render_bbox
@@ -0,0 +1,259 @@
+//! Skinned meshes and meshes with morph targets are deformed in a compute pre-pass, that writes the
+//! deformed vertices into a buffer of each entity. All passes draw that buffer instead of the mesh,
+//! so e.g. shadows and the depth pre-pass see the same deformation as the lit pass.
+
+use trekanten::descriptor::DescriptorSet;
+use trekanten::mem::{BufferMutability, OwningVertexBufferDescriptor, VertexBuffer};
+use trekanten::pipeline::{
+    ComputePipeline, ComputePipelineDescriptor, ShaderDescriptor, ShaderStage,
+};
+use trekanten::resource::{Handle, ResourceManager as _};
+use trekanten::{BufferHandle, CommandBuffer, Frame, Renderer};
+
+use crate::anim::{MorphWeights, Skeleton};
+use crate::ecs::prelude::*;
+use crate::math::{Mat4, ModelMatrix};
+use crate::render::material::GpuMaterial;
+use crate::render::mesh::GpuMesh;
+use crate::render::pipeline::{Defines, ShaderCompiler, ShaderType};
+use crate::render::uniform::{
+    JointMatrices, MorphWeightData, UniformBlock as _, MAX_NUM_JOINTS, MAX_NUM_MORPH_TARGETS,
+};
+use crate::render::MaterialError;
+
+// Has to match local_size_x in deformation_comp.glsl
+const WORKGROUP_SIZE: u32 = 64;
+
+/// The push constants of deformation_comp.glsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+struct Params {
+    first_vertex: u32,
+    num_vertices: u32,
+    // In floats
+    stride: u32,
+    num_morph_targets: u32,
+    has_skin: u32,
+    has_tangents: u32,
+}
+
+/// Per-entity resources for meshes that are deformed, i.e. skinned meshes and/or meshes with morph
+/// targets. Recreated if the mesh changes, e.g. with its LOD.
+#[derive(Component)]
+#[component(inspect)]
+pub struct GpuDeformation {
+    /// The mesh vertices that are deformed
+    pub source: BufferHandle<VertexBuffer>,
+    /// The deformed vertices, in the same format as the mesh. Drawn instead of it.
+    pub vertex_buffer: BufferHandle<VertexBuffer>,
+    /// The joint matrices and morph weights are bound to slices of the transient buffer of each
+    /// frame, see update_deformations
+    pub descriptor_set: Handle<DescriptorSet>,
+    #[inspect(ignore)]
+    params: Params,
+}
+
+fn empty_joint_matrices() -> JointMatrices {
+    JointMatrices {
+        matrices: [Mat4::identity().into_col_array(); MAX_NUM_JOINTS],
+    }
+}
+
+fn morph_weight_data(weights: Option<&MorphWeights>) -> MorphWeightData {
+    let mut data = MorphWeightData {
+        weights: [0.0; MAX_NUM_MORPH_TARGETS],
+    };
+    if let Some(weights) = weights {
+        for (dst, src) in data.weights.iter_mut().zip(weights.0.iter()) {
+            *dst = *src;
+        }
+    }
+    data
+}
+
+/// The compute pipeline of the pre-pass
+pub(super) struct DeformationPass {
+    pipeline: Handle<ComputePipeline>,
+}
+
+impl DeformationPass {
+    pub fn new(
+        shader_compiler: &ShaderCompiler,
+        renderer: &mut Renderer,
+    ) -> Result<Self, MaterialError> {
+        let shader = shader_compiler.compile(
+            &Defines::empty(),
+            "deformation_comp.glsl",
+            ShaderType::Compute,
+        )?;
+        let pipeline = renderer.create_compute_pipeline(&ComputePipelineDescriptor {
+            shader: ShaderDescriptor::FromRawSpirv(shader.data()),
+        })?;
+        Ok(Self { pipeline })
+    }
+
+    /// Deforms the meshes of all entities with GpuDeformation, with the joint matrices and morph
+    /// weights from update_deformations
+    pub fn record(
+        &self,
+        world: &World,
+        frame: &Frame,
+        cmd_buffer: CommandBuffer,
+    ) -> Result<CommandBuffer, trekanten::RenderError> {
+        let deformations = world.read_storage::<GpuDeformation>();
+        let mut pass = frame.begin_compute_pass(cmd_buffer);
+        pass.bind_compute_pipeline(&self.pipeline);
+        for deformation in (&deformations).join() {
+            pass.bind_shader_resource_group(0, &deformation.descriptor_set, &self.pipeline)
+                .bind_push_constant(&self.pipeline, &deformation.params)
+                .dispatch(
+                    (deformation.params.num_vertices + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                    1,
+                    1,
+                );
+        }
+        Ok(pass.end()?)
+    }
+}
+
+fn params(
+    renderer: &Renderer,
+    mesh: &GpuMesh,
+    skeleton: Option<&Skeleton>,
+    weights: Option<&MorphWeights>,
+    material: Option<&GpuMaterial>,
+) -> Params {
+    let format_size = renderer
+        .get_resource(&mesh.vertex_buffer)
+        .expect("Invalid handle")
+        .format()
+        .size();
+    let num_morph_targets = weights.map_or(0, |w| w.0.len().min(MAX_NUM_MORPH_TARGETS));
+    // Tangents are only guaranteed to be in the mesh if they are generated for the normal map, see
+    // pipeline_key
+    let has_tangents = matches!(
+        material,
+        Some(GpuMaterial::PBR {
+            normal_map: Some(_),
+            ..
+        })
+    );
+    Params {
+        first_vertex: mesh.vertex_buffer.idx(),
+        num_vertices: mesh.vertex_buffer.n_elems(),
+        stride: format_size / std::mem::size_of::<f32>() as u32,
+        num_morph_targets: num_morph_targets as u32,
+        has_skin: skeleton.is_some() as u32,
+        has_tangents: has_tangents as u32,
+    }
+}
+
+/// The previous resources are released by resource_tracking when they are replaced
+#[profiling::function]
+pub fn create_deformations(renderer: &mut Renderer, world: &mut World) {
+    let meshes = world.read_storage::<GpuMesh>();
+    let materials = world.read_storage::<GpuMaterial>();
+    let skeletons = world.read_storage::<Skeleton>();
+    let morph_weights = world.read_storage::<MorphWeights>();
+    let mut deformations = world.write_storage::<GpuDeformation>();
+    let entities = world.entities();
+
+    for (ent, mesh, material, skeleton, weights) in (
+        &entities,
+        &meshes,
+        materials.maybe(),
+        skeletons.maybe(),
+        morph_weights.maybe(),
+    )
+        .join()
+    {
+        if skeleton.is_none() && weights.is_none() {
+            continue;
+        }
+
+        let params = params(renderer, mesh, skeleton, weights, material);
+        if let Some(prev) = deformations.get(ent) {
+            if prev.source == mesh.vertex_buffer && prev.params == params {
+                continue;
+            }
+        }
+
+        // Written by the pre-pass of each frame, so one copy for each frame in flight
+        let vertex_buffer = {
+            let format = renderer
+                .get_resource(&mesh.vertex_buffer)
+                .expect("Invalid handle")
+                .format()
+                .clone();
+            let size = (mesh.vertex_buffer.n_elems() * format.size()) as usize;
+            renderer
+                .create_resource_blocking(OwningVertexBufferDescriptor::from_raw(
+                    vec![0; size],
+                    format,
+                    BufferMutability::Mutable,
+                ))
+                .expect("Failed to create deformed vertex buffer")
+        };
+
+        // The bindings are added in order, see deformation_comp.glsl
+        let descriptor_set = DescriptorSet::builder(renderer)
+            .add_transient::<JointMatrices>(JointMatrices::BINDING, ShaderStage::COMPUTE)
+            .add_transient::<MorphWeightData>(MorphWeightData::BINDING, ShaderStage::COMPUTE)
+            .add_vertex_buffer(&mesh.vertex_buffer, 2, ShaderStage::COMPUTE)
+            .add_vertex_buffer(&vertex_buffer, 3, ShaderStage::COMPUTE)
+            .build();
+
+        deformations
+            .insert(
+                ent,
+                GpuDeformation {
+                    source: mesh.vertex_buffer,
+                    vertex_buffer,
+                    descriptor_set,
+                    params,
+                },
+            )
+            .expect("Failed to insert gpu deformation");
+    }
+}
+
+/// Writes the joint matrices and morph weights of this frame. Only what the shader reads is written,
+/// see Params.
+#[profiling::function]
+pub fn update_deformations(world: &World, frame: &mut Frame) -> Result<(), trekanten::RenderError> {
+    let skeletons = world.read_storage::<Skeleton>();
+    let morph_weights = world.read_storage::<MorphWeights>();
+    let deformations = world.read_storage::<GpuDeformation>();
+    let model_matrices = world.read_storage::<ModelMatrix>();
+
+    for (deformation, skeleton, weights, mesh_model) in (
+        &deformations,
+        skeletons.maybe(),
+        morph_weights.maybe(),
+        &model_matrices,
+    )
+        .join()
+    {
+        if let Some(skeleton) = skeleton {
+            let inv_mesh_model = mesh_model.0.inverted();
+            let mut data = empty_joint_matrices();
+            for (i, joint) in skeleton.joints.iter().take(MAX_NUM_JOINTS).enumerate() {
+                if let Some(joint_model) = model_matrices.get(*joint) {
+                    data.matrices[i] = skeleton
+                        .joint_matrix(i, &joint_model.0, &inv_mesh_model)
+                        .into_col_array();
+                }
+            }
+
+            let slice = frame.write_transient(&data)?;
+            frame.bind_transient(&deformation.descriptor_set, JointMatrices::BINDING, slice);
+        }
+
+        if weights.is_some() {
+            let slice = frame.write_transient(&morph_weight_data(weights))?;
+            frame.bind_transient(&deformation.descriptor_set, MorphWeightData::BINDING, slice);
+        }
+    }
+
+    Ok(())
+}
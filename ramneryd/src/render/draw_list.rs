@@ -0,0 +1,453 @@
+//! The meshes of one pass, sorted so that entities that share state are drawn after each other and
+//! only what changed between two draws is bound. RenderOrder overrides this, e.g. to draw decals or
+//! overlays after the geometry that they are on.
+
+use trekanten::descriptor::DescriptorSet;
+use trekanten::mem::{IndexBuffer, VertexBuffer};
+use trekanten::pipeline::{DepthTest, GraphicsPipeline, ShaderStage};
+use trekanten::resource::Handle;
+use trekanten::{BufferHandle, RenderPassEncoder};
+
+use crate::anim::{MorphWeights, Skeleton};
+use crate::ecs::prelude::*;
+use crate::math::{BoundingBox, BoundingSphere, BoundingVolume, ModelMatrix};
+
+use super::deformation::GpuDeformation;
+use super::material::PhysicallyBased;
+use super::mesh::GpuMesh;
+use super::object_data::ObjectData;
+use super::uniform::{self, UniformBlock as _};
+use super::visibility::{self, Pass, RenderLayer, Visibility};
+use super::{DrawMode, Hidden, RenderableMaterial};
+
+/// The number of draws and binds that were recorded for the entities last frame, in all passes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DrawStats {
+    pub draws: u32,
+    pub pipeline_binds: u32,
+    pub descriptor_set_binds: u32,
+    pub vertex_buffer_binds: u32,
+    pub index_buffer_binds: u32,
+    /// Entities that were left out by DrawList::build
+    pub culled: u32,
+}
+
+/// Entities with a higher order are drawn after the ones with a lower order in the same pass,
+/// whatever their pipelines and meshes are. Entities without it have order 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Component)]
+#[component(inspect)]
+pub struct RenderOrder(pub i32);
+
+impl RenderOrder {
+    /// Ordered entities are usually drawn over other geometry at the same depth, e.g. decals, so
+    /// their pipelines are depth tested with DepthTest::Overlay instead of hidden by it
+    pub(super) fn depth_testing(order: Option<&RenderOrder>) -> DepthTest {
+        match order {
+            Some(order) if *order != RenderOrder::default() => DepthTest::Overlay,
+            _ => DepthTest::Enabled,
+        }
+    }
+}
+
+/// The RenderOrder comes first, so the state of the draw only decides the order of the draws with
+/// the same RenderOrder
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct SortKey<S> {
+    order: RenderOrder,
+    state: S,
+}
+
+struct DrawItem {
+    order: RenderOrder,
+    pipeline: Handle<GraphicsPipeline>,
+    // None for the shadow and depth pipelines, as they don't use the material
+    material_descriptor_set: Option<Handle<DescriptorSet>>,
+    /// For the PBR pipelines that sample the textures of the material from the bindless array
+    bindless_descriptor_set: Option<Handle<DescriptorSet>>,
+    /// The deformed vertices for skinned and morphed meshes, see deformation
+    vertex_buffer: BufferHandle<VertexBuffer>,
+    index_buffer: BufferHandle<IndexBuffer>,
+    /// Pushed to the shaders, either the model matrices or their index in the object buffer
+    object: Object,
+    entity: Entity,
+}
+
+enum Object {
+    Model(uniform::Model),
+    Index(u32),
+}
+
+impl DrawItem {
+    fn sort_key(
+        &self,
+    ) -> SortKey<(
+        Handle<GraphicsPipeline>,
+        Option<Handle<DescriptorSet>>,
+        Handle<VertexBuffer>,
+        Handle<IndexBuffer>,
+        u32,
+    )> {
+        SortKey {
+            order: self.order,
+            state: (
+                self.pipeline,
+                self.material_descriptor_set,
+                *self.vertex_buffer.handle(),
+                *self.index_buffer.handle(),
+                // Entities that are equally far away are drawn in the order they are recorded, so
+                // this gives the same image between runs
+                self.entity.id(),
+            ),
+        }
+    }
+}
+
+/// The last value that was bound, so that binding the same one again can be skipped
+struct Bound<T>(Option<T>);
+
+impl<T> Default for Bound<T> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<T: PartialEq + Copy> Bound<T> {
+    /// Returns true if the value has to be bound
+    fn changed(&mut self, value: T) -> bool {
+        if self.0 == Some(value) {
+            false
+        } else {
+            self.0 = Some(value);
+            true
+        }
+    }
+
+    fn reset(&mut self) {
+        self.0 = None;
+    }
+}
+
+fn build(
+    world: &World,
+    mode: DrawMode,
+    visible: &dyn Fn(&BoundingVolume) -> bool,
+    stats: &mut DrawStats,
+) -> Vec<DrawItem> {
+    let model_matrices = world.read_storage::<ModelMatrix>();
+    let bboxes = world.read_storage::<BoundingBox>();
+    let spheres = world.read_storage::<BoundingSphere>();
+    let meshes = world.read_storage::<GpuMesh>();
+    let renderables = world.read_storage::<RenderableMaterial>();
+    let deformations = world.read_storage::<GpuDeformation>();
+    let hidden = world.read_storage::<Hidden>();
+    let visibilities = world.read_storage::<Visibility>();
+    let layers = world.read_storage::<RenderLayer>();
+    let orders = world.read_storage::<RenderOrder>();
+    let materials = world.read_storage::<PhysicallyBased>();
+    let object_data = world.read_resource::<ObjectData>();
+
+    let pass = match mode {
+        DrawMode::ShadowsOnly => Pass::Shadow,
+        DrawMode::Lit | DrawMode::Transmissive | DrawMode::Unlit | DrawMode::DepthOnly => {
+            Pass::Camera
+        }
+    };
+    let pass_layers = visibility::pass_layers(world, pass);
+
+    let mut items = Vec::new();
+    for (entity, mesh, renderable, mtx, bbox, sphere, deformation, visibility, layer, order, _) in (
+        &world.entities(),
+        &meshes,
+        &renderables,
+        &model_matrices,
+        bboxes.maybe(),
+        spheres.maybe(),
+        deformations.maybe(),
+        visibilities.maybe(),
+        layers.maybe(),
+        orders.maybe(),
+        !&hidden,
+    )
+        .join()
+    {
+        if !visibility::is_drawn(visibility, layer, pass, pass_layers) {
+            continue;
+        }
+
+        // Transmissive materials are drawn with the same pipelines as the other lit ones, but in a
+        // pass of their own
+        let transmissive = materials
+            .get(entity)
+            .map_or(false, PhysicallyBased::is_transmissive);
+        let mode = match mode {
+            DrawMode::Lit if transmissive => continue,
+            DrawMode::Transmissive if !transmissive => continue,
+            DrawMode::Transmissive => DrawMode::Lit,
+            mode => mode,
+        };
+
+        let (pipeline, material_descriptor_set, bindless_descriptor_set) = match (renderable, mode)
+        {
+            (
+                RenderableMaterial::PBR {
+                    shadow_pipeline, ..
+                },
+                DrawMode::ShadowsOnly,
+            ) => (*shadow_pipeline, None, None),
+            (RenderableMaterial::PBR { depth_pipeline, .. }, DrawMode::DepthOnly) => {
+                (*depth_pipeline, None, None)
+            }
+            (
+                RenderableMaterial::PBR {
+                    gfx_pipeline,
+                    material_descriptor_set,
+                    bindless_textures,
+                    ..
+                },
+                DrawMode::Lit,
+            ) => (
+                *gfx_pipeline,
+                Some(*material_descriptor_set),
+                bindless_textures.map(|b| b.descriptor_set()),
+            ),
+            (
+                RenderableMaterial::Unlit {
+                    gfx_pipeline,
+                    material_descriptor_set,
+                },
+                DrawMode::Unlit,
+            ) => (*gfx_pipeline, Some(*material_descriptor_set), None),
+            _ => continue,
+        };
+
+        // Entities without a bounding box are always drawn
+        if let Some(bbox) = bbox {
+            let volume = BoundingVolume::tightest(
+                bbox.transformed(&mtx.0),
+                sphere.map(|s| s.transformed(&mtx.0)),
+            );
+            if !visible(&volume) {
+                stats.culled += 1;
+                continue;
+            }
+        }
+
+        // The unlit pipelines always push the model matrices
+        let object = match (renderable, object_data.enabled()) {
+            (RenderableMaterial::PBR { .. }, true) => match object_data.index(entity) {
+                Some(idx) => Object::Index(idx),
+                None => continue,
+            },
+            _ => Object::Model(uniform::Model {
+                model: mtx.0.into_col_array(),
+                model_it: mtx.0.inverted().transposed().into_col_array(),
+            }),
+        };
+
+        items.push(DrawItem {
+            order: order.copied().unwrap_or_default(),
+            pipeline,
+            material_descriptor_set,
+            bindless_descriptor_set,
+            vertex_buffer: deformation.map_or(mesh.vertex_buffer, |d| d.vertex_buffer),
+            index_buffer: mesh.index_buffer,
+            object,
+            entity,
+        });
+    }
+
+    items.sort_by_key(DrawItem::sort_key);
+    items
+}
+
+/// The entities of a pass, sorted for drawing. Built separately from recording them so that a pass
+/// can be skipped if nothing in it changed since the last time, see signature.
+pub(super) struct DrawList {
+    items: Vec<DrawItem>,
+}
+
+impl DrawList {
+    /// Leaves out the entities whose world space bounds are not visible, e.g. shadow casters
+    /// outside the light, see light::ShadowCasterCulling. The tightest of the bounding box and
+    /// sphere is tested.
+    pub(super) fn build(
+        world: &World,
+        mode: DrawMode,
+        visible: &dyn Fn(&BoundingVolume) -> bool,
+        stats: &mut DrawStats,
+    ) -> Self {
+        Self {
+            items: build(world, mode, visible, stats),
+        }
+    }
+
+    /// Changes if an entity is added or removed, or if its mesh, pipeline or model matrix changes.
+    /// For deformed meshes, also if their joints move or their morph weights change.
+    pub(super) fn signature(&self, world: &World) -> u64 {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let model_matrices = world.read_storage::<ModelMatrix>();
+        let skeletons = world.read_storage::<Skeleton>();
+        let morph_weights = world.read_storage::<MorphWeights>();
+        let mut hasher = DefaultHasher::new();
+        let hash_matrix = |entity: Entity, hasher: &mut DefaultHasher| {
+            if let Some(mtx) = model_matrices.get(entity) {
+                for v in mtx.0.into_col_array().iter() {
+                    v.to_bits().hash(hasher);
+                }
+            }
+        };
+        for item in self.items.iter() {
+            item.entity.hash(&mut hasher);
+            item.pipeline.hash(&mut hasher);
+            item.vertex_buffer.hash(&mut hasher);
+            item.index_buffer.hash(&mut hasher);
+            hash_matrix(item.entity, &mut hasher);
+            if let Some(skeleton) = skeletons.get(item.entity) {
+                for joint in skeleton.joints.iter() {
+                    hash_matrix(*joint, &mut hasher);
+                }
+            }
+            if let Some(weights) = morph_weights.get(item.entity) {
+                for w in weights.0.iter() {
+                    w.to_bits().hash(&mut hasher);
+                }
+            }
+        }
+        hasher.finish()
+    }
+
+    /// Only what changed between two draws is bound
+    pub(super) fn record<'a>(
+        &self,
+        world: &World,
+        cmd_buf: &mut RenderPassEncoder<'a>,
+        stats: &mut DrawStats,
+    ) {
+        let mut pipeline = Bound::default();
+        let mut material_descriptor_set = Bound::default();
+        let mut object_descriptor_set = Bound::default();
+        let mut bindless_descriptor_set = Bound::default();
+        let mut vertex_buffer = Bound::default();
+        let mut index_buffer = Bound::default();
+
+        let object_data = world.read_resource::<ObjectData>();
+        for item in self.items.iter() {
+            let gfx_pipeline = &item.pipeline;
+            if pipeline.changed(item.pipeline) {
+                cmd_buf.bind_graphics_pipeline(gfx_pipeline);
+                stats.pipeline_binds += 1;
+                // The descriptor sets may not be compatible with the layout of the new pipeline
+                material_descriptor_set.reset();
+                object_descriptor_set.reset();
+                bindless_descriptor_set.reset();
+            }
+            if let Some(set) = &item.material_descriptor_set {
+                if material_descriptor_set.changed(*set) {
+                    cmd_buf.bind_shader_resource_group(1, set, gfx_pipeline);
+                    stats.descriptor_set_binds += 1;
+                }
+            }
+            if let Some(set) = &item.bindless_descriptor_set {
+                if bindless_descriptor_set.changed(*set) {
+                    cmd_buf.bind_shader_resource_group(
+                        uniform::BINDLESS_TEXTURES_SET,
+                        set,
+                        gfx_pipeline,
+                    );
+                    stats.descriptor_set_binds += 1;
+                }
+            }
+            // Meshes share the same buffers through sub-buffers, so the whole buffer is what is
+            // bound
+            if vertex_buffer.changed(*item.vertex_buffer.handle()) {
+                cmd_buf.bind_vertex_buffer(&item.vertex_buffer);
+                stats.vertex_buffer_binds += 1;
+            }
+            if index_buffer.changed(*item.index_buffer.handle()) {
+                cmd_buf.bind_index_buffer(&item.index_buffer);
+                stats.index_buffer_binds += 1;
+            }
+
+            match &item.object {
+                Object::Model(model) => {
+                    cmd_buf.bind_push_constant(gfx_pipeline, ShaderStage::VERTEX, model);
+                }
+                Object::Index(idx) => {
+                    let set = object_data.descriptor_set();
+                    if object_descriptor_set.changed(*set) {
+                        cmd_buf.bind_shader_resource_group(uniform::Model::SET, set, gfx_pipeline);
+                        stats.descriptor_set_binds += 1;
+                    }
+                    cmd_buf.bind_push_constant(gfx_pipeline, ShaderStage::VERTEX, idx);
+                }
+            }
+            cmd_buf.draw_indexed(
+                item.index_buffer.n_elems(),
+                item.index_buffer.idx(),
+                item.vertex_buffer.idx() as i32,
+            );
+            stats.draws += 1;
+        }
+    }
+}
+
+/// Record the entities that are drawn in the mode, sorted by RenderOrder, then pipeline, then
+/// descriptor set and then mesh
+pub(super) fn draw<'a>(
+    world: &World,
+    cmd_buf: &mut RenderPassEncoder<'a>,
+    mode: DrawMode,
+    stats: &mut DrawStats,
+) {
+    DrawList::build(world, mode, &|_| true, stats).record(world, cmd_buf, stats);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_value_is_not_bound_again() {
+        let mut bound = Bound::default();
+        assert!(bound.changed(1));
+        assert!(!bound.changed(1));
+        assert!(bound.changed(2));
+        bound.reset();
+        assert!(bound.changed(2));
+    }
+
+    #[test]
+    fn higher_orders_are_drawn_after_their_base_geometry() {
+        // The decal has the state that would be drawn first
+        let decal = SortKey {
+            order: RenderOrder(1),
+            state: 0,
+        };
+        let floor = SortKey {
+            order: RenderOrder::default(),
+            state: 2,
+        };
+        let wall = SortKey {
+            order: RenderOrder::default(),
+            state: 1,
+        };
+        let mut keys = vec![decal, floor, wall];
+        keys.sort();
+        assert_eq!(
+            keys.iter().map(|k| k.state).collect::<Vec<_>>(),
+            vec![1, 2, 0]
+        );
+
+        assert_eq!(RenderOrder::depth_testing(None), DepthTest::Enabled);
+        assert_eq!(
+            RenderOrder::depth_testing(Some(&RenderOrder::default())),
+            DepthTest::Enabled
+        );
+        assert_eq!(
+            RenderOrder::depth_testing(Some(&RenderOrder(-1))),
+            DepthTest::Overlay
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Analysis of the exposure of the HDR scene color, to help with lighting scenes within a sane
+//! dynamic range. The false color and zebra views replace the presented image in the composite
+//! pass, see post/false_color_frag.glsl. The luminance histogram is computed on the cpu from the
+//! scene color, which is read back a few times per second while it is enabled.
+
+use std::time::{Duration, Instant};
+
+use num_derive::FromPrimitive;
+use ramneryd_derive::Inspect;
+
+use trekanten::Renderer;
+
+use crate::ecs::prelude::*;
+
+use super::debug_window::RenderSettings;
+use super::hdr_export;
+use super::FrameData;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive, Inspect)]
+pub enum ExposureView {
+    Off,
+    /// Colors bands of exposure, in stops from middle gray
+    FalseColor,
+    /// Stripes over the parts that are brighter than the zebra threshold
+    Zebra,
+}
+
+impl_cvar_type_enum!(ExposureView);
+
+/// The push constant of post/false_color_frag.glsl
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub(super) struct ExposureParams {
+    params: [f32; 4],
+}
+
+impl ExposureParams {
+    pub(super) fn new(settings: &RenderSettings) -> Self {
+        let mode = match settings.exposure_view {
+            ExposureView::Zebra => 1.0,
+            ExposureView::Off | ExposureView::FalseColor => 0.0,
+        };
+        Self {
+            params: [mode, settings.zebra_threshold, 0.0, 0.0],
+        }
+    }
+}
+
+/// Exposure is measured in stops from this luminance
+pub const MIDDLE_GRAY: f32 = 0.18;
+pub const HISTOGRAM_MIN_EV: f32 = -10.0;
+pub const HISTOGRAM_MAX_EV: f32 = 10.0;
+// Half a stop each
+const HISTOGRAM_BINS: usize = 40;
+const HISTOGRAM_INTERVAL: Duration = Duration::from_millis(500);
+// Every n:th pixel is counted, which is plenty for a histogram
+const HISTOGRAM_STRIDE: usize = 7;
+
+/// The fraction of the pixels in each bin, from HISTOGRAM_MIN_EV to HISTOGRAM_MAX_EV. Empty until
+/// the histogram has been enabled in the render settings.
+#[derive(Default)]
+pub struct LuminanceHistogram {
+    pub bins: Vec<f32>,
+    updated: Option<Instant>,
+}
+
+fn luminance(p: &[f32; 4]) -> f32 {
+    0.2126 * p[0] + 0.7152 * p[1] + 0.0722 * p[2]
+}
+
+/// Pixels outside of the range are counted in the first and last bins
+fn histogram<'a>(pixels: impl Iterator<Item = &'a [f32; 4]>, n_bins: usize) -> Vec<f32> {
+    let mut bins = vec![0.0; n_bins];
+    let mut total = 0;
+    let range = HISTOGRAM_MAX_EV - HISTOGRAM_MIN_EV;
+    for p in pixels {
+        let ev = (luminance(p).max(1e-6) / MIDDLE_GRAY).log2();
+        let bin = ((ev - HISTOGRAM_MIN_EV) / range * n_bins as f32).floor();
+        let bin = bin.max(0.0).min((n_bins - 1) as f32) as usize;
+        bins[bin] += 1.0;
+        total += 1;
+    }
+
+    if total > 0 {
+        for bin in bins.iter_mut() {
+            *bin /= total as f32;
+        }
+    }
+    bins
+}
+
+/// Has to run before the frame is started, as it waits for the renderer to be idle
+pub(super) fn update_histogram(world: &World, renderer: &Renderer) {
+    if !world.read_resource::<RenderSettings>().luminance_histogram {
+        return;
+    }
+
+    let mut histogram_res = world.write_resource::<LuminanceHistogram>();
+    if let Some(updated) = histogram_res.updated {
+        if updated.elapsed() < HISTOGRAM_INTERVAL {
+            return;
+        }
+    }
+    histogram_res.updated = Some(Instant::now());
+
+    let scene_color = *world.read_resource::<FrameData>().post.scene_color();
+    let image = renderer
+        .read_texture(&scene_color)
+        .map_err(hdr_export::HdrExportError::from)
+        .and_then(|(extent, format, data)| hdr_export::decode(extent, format, &data));
+    match image {
+        Ok(image) => {
+            histogram_res.bins = histogram(
+                image.pixels.iter().step_by(HISTOGRAM_STRIDE),
+                HISTOGRAM_BINS,
+            )
+        }
+        Err(e) => log::error!("Failed to compute the luminance histogram: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_bins() {
+        let gray = [MIDDLE_GRAY; 4];
+        let black = [0.0; 4];
+        let sun = [1.0e6; 4];
+        let pixels = [gray, gray, black, sun];
+        let bins = histogram(pixels.iter(), 40);
+
+        assert_eq!(bins.len(), 40);
+        // Zero stops is in the middle of the range
+        assert_eq!(bins[20], 0.5);
+        assert_eq!(bins[0], 0.25);
+        assert_eq!(bins[39], 0.25);
+        assert_eq!(bins.iter().sum::<f32>(), 1.0);
+    }
+}
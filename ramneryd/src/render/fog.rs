@@ -0,0 +1,162 @@
+//! Local fog in world space. A fog volume is a box or a sphere placed by the transform of its
+//! entity. The fog along the view ray through each volume is accumulated in the PBR fragment
+//! shader, so only lit geometry is fogged and the fog itself is not lit.
+
+use crate::ecs::prelude::*;
+use crate::math::{Mat4, Rgb, Transform, Vec3};
+use crate::render::uniform::{FogData, PackedFogVolume, MAX_NUM_FOG_VOLUMES};
+
+use ramneryd_derive::Inspect;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize, Inspect)]
+pub enum FogShape {
+    Box { half_extents: Vec3 },
+    Sphere { radius: f32 },
+}
+
+impl FogShape {
+    const BOX: u32 = 0;
+    const SPHERE: u32 = 1;
+
+    fn packed(&self) -> u32 {
+        match self {
+            Self::Box { .. } => Self::BOX,
+            Self::Sphere { .. } => Self::SPHERE,
+        }
+    }
+
+    // Scales the unit cube, [-1, 1] on all axes, or the unit sphere to this shape
+    fn scale(&self) -> Vec3 {
+        match *self {
+            Self::Box { half_extents } => half_extents,
+            Self::Sphere { radius } => Vec3::broadcast(radius),
+        }
+    }
+}
+
+#[derive(Component, serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[component(inspect)]
+pub struct FogVolume {
+    pub shape: FogShape,
+    pub color: Rgb,
+    /// How much of the light is absorbed per meter. The fraction of light that passes through a
+    /// distance d of fog is exp(-density * d).
+    pub density: f32,
+}
+
+impl Default for FogVolume {
+    fn default() -> Self {
+        Self {
+            shape: FogShape::Box {
+                half_extents: Vec3::broadcast(1.0),
+            },
+            color: Rgb::new(0.5, 0.5, 0.5),
+            density: 0.5,
+        }
+    }
+}
+
+impl FogVolume {
+    fn local_to_world(&self, tfm: &Transform) -> Mat4 {
+        Mat4::from(*tfm) * Mat4::scaling_3d(self.shape.scale())
+    }
+
+    fn bounding_sphere(&self, tfm: &Transform) -> (Vec3, f32) {
+        let scale = self.shape.scale() * tfm.scale;
+        let radius = match self.shape {
+            FogShape::Box { .. } => scale.magnitude(),
+            FogShape::Sphere { .. } => scale.reduce_partial_max(),
+        };
+        (tfm.position, radius)
+    }
+
+    fn packed(&self, tfm: &Transform) -> PackedFogVolume {
+        PackedFogVolume {
+            world_to_local: self.local_to_world(tfm).inverted().into_col_array(),
+            color_density: [self.color.r, self.color.g, self.color.b, self.density],
+            shape: [self.shape.packed(); 4],
+        }
+    }
+}
+
+/// Uploads the fog volumes closest to the camera
+pub fn update_fog_data(
+    world: &World,
+    frame: &mut trekanten::Frame,
+    frame_resources: &super::FrameData,
+    view_pos: Vec3,
+) {
+    let volumes = world.read_storage::<FogVolume>();
+    let transforms = world.read_storage::<Transform>();
+
+    let mut closest: Vec<(f32, &FogVolume, &Transform)> = (&volumes, &transforms)
+        .join()
+        .map(|(volume, tfm)| {
+            let (center, radius) = volume.bounding_sphere(tfm);
+            ((center.distance(view_pos) - radius).max(0.0), volume, tfm)
+        })
+        .collect();
+
+    if closest.len() > MAX_NUM_FOG_VOLUMES {
+        log::warn!(
+            "Too many fog volumes ({}), only the {} closest are rendered",
+            closest.len(),
+            MAX_NUM_FOG_VOLUMES
+        );
+        closest.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        closest.truncate(MAX_NUM_FOG_VOLUMES);
+    }
+
+    let mut fog_data = FogData::default();
+    for (i, (_, volume, tfm)) in closest.iter().enumerate() {
+        fog_data.volumes[i] = volume.packed(tfm);
+    }
+    fog_data.num_volumes = closest.len() as u32;
+
+    frame
+        .update_uniform_blocking(&frame_resources.pbr_resources.fog_buffer, &fog_data)
+        .expect("Failed to update uniform for fog data");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec4;
+
+    fn to_local(volume: &FogVolume, tfm: &Transform, p: Vec3) -> Vec3 {
+        let m = Mat4::from_col_array(volume.packed(tfm).world_to_local);
+        Vec3::from(m * Vec4::from_point(p))
+    }
+
+    #[test]
+    fn box_maps_to_unit_cube() {
+        let volume = FogVolume {
+            shape: FogShape::Box {
+                half_extents: Vec3::new(2.0, 1.0, 4.0),
+            },
+            ..Default::default()
+        };
+        let tfm = Transform::pos(10.0, 0.0, 0.0);
+
+        let corner = to_local(&volume, &tfm, Vec3::new(12.0, 1.0, 4.0));
+        assert!((corner - Vec3::one()).magnitude() < 1e-5);
+        let center = to_local(&volume, &tfm, Vec3::new(10.0, 0.0, 0.0));
+        assert!(center.magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn sphere_maps_to_unit_sphere() {
+        let volume = FogVolume {
+            shape: FogShape::Sphere { radius: 3.0 },
+            ..Default::default()
+        };
+        let tfm = Transform::pos(0.0, 5.0, 0.0);
+
+        let surface = to_local(&volume, &tfm, Vec3::new(0.0, 8.0, 0.0));
+        assert!((surface.magnitude() - 1.0).abs() < 1e-5);
+        assert_eq!(
+            volume.bounding_sphere(&tfm),
+            (Vec3::new(0.0, 5.0, 0.0), 3.0)
+        );
+    }
+}
@@ -1,7 +1,11 @@
+use std::collections::HashMap;
+
 use trekanten::mem::{BufferMutability, OwningIndexBufferDescriptor, OwningVertexBufferDescriptor};
-use trekanten::util::Format;
+use trekanten::util::{self, Format};
 use trekanten::vertex::{VertexDefinition, VertexFormat};
 
+use crate::math::{Vec2, Vec3, Vec4};
+
 #[derive(Copy, Clone)]
 struct PosVertex {
     _pos: [f32; 3],
@@ -189,3 +193,1007 @@ pub fn cone_mesh(radius: f32, height: f32) -> Mesh {
     let indices = OwningIndexBufferDescriptor::from_vec(indices, BufferMutability::Immutable);
     (vertices, indices)
 }
+
+/// How texture coordinates are generated for the textured primitives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UvMapping {
+    /// Project along the dominant axis of the normal. Each side of a box covers the whole texture.
+    Box,
+    /// Longitude/latitude around the y-axis, with the seam at +x
+    Spherical,
+    /// Project onto the x/z-plane
+    Planar,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PrimitiveOptions {
+    /// Defaults to the natural mapping of the primitive, e.g. spherical for a sphere
+    pub uv_mapping: Option<UvMapping>,
+    /// Multiplies the number of faces along each side of a box or plane, the number of rings and
+    /// segments of a sphere or the number of rows and segments of a cylinder or cone. 1 gives the
+    /// same resolution as the untextured primitives. For icospheres, it is the number of times the
+    /// faces are split.
+    pub subdivisions: u32,
+    /// Generate tangents from the texture coordinates, needed for normal maps
+    pub tangents: bool,
+}
+
+impl Default for PrimitiveOptions {
+    fn default() -> Self {
+        Self {
+            uv_mapping: None,
+            subdivisions: 1,
+            tangents: true,
+        }
+    }
+}
+
+// (normal, u-axis, v-axis) with u x v = normal so that the faces are CCW from the outside
+const BOX_FACES: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+];
+
+fn box_face(normal: Vec3) -> (Vec3, Vec3, Vec3) {
+    let abs = normal.map(f32::abs);
+    let idx = if abs.x >= abs.y && abs.x >= abs.z {
+        0
+    } else if abs.y >= abs.z {
+        2
+    } else {
+        4
+    };
+    let idx = if normal[idx / 2] < 0.0 { idx + 1 } else { idx };
+
+    let (n, u, v) = BOX_FACES[idx];
+    (Vec3::from(n), Vec3::from(u), Vec3::from(v))
+}
+
+/// half_extents is half the size of the bounding box of the (origin-centered) primitive
+fn tex_coord(mapping: UvMapping, pos: Vec3, normal: Vec3, half_extents: Vec3) -> Vec2 {
+    match mapping {
+        UvMapping::Box => {
+            let (_, u_axis, v_axis) = box_face(normal);
+            let u_size = 2.0 * u_axis.map(f32::abs).dot(half_extents);
+            let v_size = 2.0 * v_axis.map(f32::abs).dot(half_extents);
+            Vec2::new(
+                0.5 + pos.dot(u_axis) / u_size,
+                0.5 - pos.dot(v_axis) / v_size,
+            )
+        }
+        UvMapping::Spherical => {
+            let dir = if pos.magnitude_squared() < f32::EPSILON {
+                normal
+            } else {
+                pos.normalized()
+            };
+            let u = (-dir.z).atan2(dir.x) / (2.0 * std::f32::consts::PI);
+            let u = if u < 0.0 { u + 1.0 } else { u };
+            let v = dir.y.max(-1.0).min(1.0).acos() / std::f32::consts::PI;
+            Vec2::new(u, v)
+        }
+        UvMapping::Planar => Vec2::new(
+            0.5 + pos.x / (2.0 * half_extents.x),
+            0.5 + pos.z / (2.0 * half_extents.z),
+        ),
+    }
+}
+
+/// Per-vertex tangents, with the handedness of the bitangent in w, as in glTF
+pub(crate) fn generate_tangents(
+    positions: &[Vec3],
+    normals: &[Vec3],
+    tex_coords: &[Vec2],
+    indices: &[u32],
+) -> Vec<Vec4> {
+    let mut tangents = vec![Vec3::zero(); positions.len()];
+    let mut bitangents = vec![Vec3::zero(); positions.len()];
+
+    for tri in indices.chunks_exact(3) {
+        let [i0, i1, i2] = [tri[0] as usize, tri[1] as usize, tri[2] as usize];
+        let e1 = positions[i1] - positions[i0];
+        let e2 = positions[i2] - positions[i0];
+        let d1 = tex_coords[i1] - tex_coords[i0];
+        let d2 = tex_coords[i2] - tex_coords[i0];
+
+        let det = d1.x * d2.y - d2.x * d1.y;
+        // Degenerate texture coordinates, e.g. at the poles of a sphere
+        if det.abs() < f32::EPSILON {
+            continue;
+        }
+
+        let r = 1.0 / det;
+        let t = (e1 * d2.y - e2 * d1.y) * r;
+        let b = (e2 * d1.x - e1 * d2.x) * r;
+        for &i in &[i0, i1, i2] {
+            tangents[i] += t;
+            bitangents[i] += b;
+        }
+    }
+
+    normals
+        .iter()
+        .zip(tangents.iter().zip(bitangents.iter()))
+        .map(|(&n, (&t, &b))| {
+            // Gram-Schmidt orthogonalize against the normal
+            let t = t - n * n.dot(t);
+            let t = if t.magnitude_squared() > f32::EPSILON {
+                t.normalized()
+            } else {
+                let other = if n.x.abs() < 0.9 {
+                    Vec3::unit_x()
+                } else {
+                    Vec3::unit_y()
+                };
+                n.cross(other).normalized()
+            };
+            let w = if n.cross(t).dot(b) < 0.0 { -1.0 } else { 1.0 };
+            Vec4::new(t.x, t.y, t.z, w)
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct TexturedGeometry {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    tex_coords: Vec<Vec2>,
+    indices: Vec<u32>,
+}
+
+impl TexturedGeometry {
+    /// A grid of n.0 x n.1 quads centered at center, spanning u_axis * u_size and v_axis * v_size
+    #[allow(clippy::too_many_arguments)]
+    fn push_face(
+        &mut self,
+        center: Vec3,
+        normal: Vec3,
+        u_axis: Vec3,
+        v_axis: Vec3,
+        u_size: f32,
+        v_size: f32,
+        n: (u32, u32),
+        tex_coord: impl Fn(Vec3, Vec3) -> Vec2,
+    ) {
+        let offset = self.positions.len() as u32;
+        let (nu, nv) = n;
+        for j in 0..=nv {
+            for i in 0..=nu {
+                let a = i as f32 / nu as f32 - 0.5;
+                let b = j as f32 / nv as f32 - 0.5;
+                let pos = center + u_axis * (a * u_size) + v_axis * (b * v_size);
+                self.positions.push(pos);
+                self.normals.push(normal);
+                self.tex_coords.push(tex_coord(pos, normal));
+            }
+        }
+
+        let idx = |i: u32, j: u32| offset + j * (nu + 1) + i;
+        for j in 0..nv {
+            for i in 0..nu {
+                self.indices
+                    .extend_from_slice(&[idx(i, j), idx(i + 1, j), idx(i + 1, j + 1)]);
+                self.indices
+                    .extend_from_slice(&[idx(i, j), idx(i + 1, j + 1), idx(i, j + 1)]);
+            }
+        }
+    }
+
+    /// Vertex layout: position, normal, texture coordinates and, if enabled, tangents. The same as
+    /// for glTF meshes.
+    fn into_mesh(self, options: &PrimitiveOptions) -> Mesh {
+        let mut format = VertexFormat::builder()
+            .add_attribute(Format::FLOAT3)
+            .add_attribute(Format::FLOAT3)
+            .add_attribute(Format::FLOAT2);
+
+        let tangents = if options.tangents {
+            format = format.add_attribute(Format::FLOAT4);
+            Some(generate_tangents(
+                &self.positions,
+                &self.normals,
+                &self.tex_coords,
+                &self.indices,
+            ))
+        } else {
+            None
+        };
+
+        let mut data = Vec::new();
+        for (i, (pos, nor)) in self.positions.iter().zip(self.normals.iter()).enumerate() {
+            data.extend_from_slice(util::as_bytes(&pos.into_array()));
+            data.extend_from_slice(util::as_bytes(&nor.into_array()));
+            data.extend_from_slice(util::as_bytes(&self.tex_coords[i].into_array()));
+            if let Some(tangents) = &tangents {
+                data.extend_from_slice(util::as_bytes(&tangents[i].into_array()));
+            }
+        }
+
+        let vertices = OwningVertexBufferDescriptor::from_raw(
+            data,
+            format.build(),
+            BufferMutability::Immutable,
+        );
+        let indices =
+            OwningIndexBufferDescriptor::from_vec(self.indices, BufferMutability::Immutable);
+
+        (vertices, indices)
+    }
+}
+
+fn textured_box_geometry(x: f32, y: f32, z: f32, options: &PrimitiveOptions) -> TexturedGeometry {
+    let half_extents = Vec3::new(x, y, z) * 0.5;
+    let mapping = options.uv_mapping.unwrap_or(UvMapping::Box);
+    let n = options.subdivisions.max(1);
+    let mut geometry = TexturedGeometry::default();
+    for &(normal, u_axis, v_axis) in BOX_FACES.iter() {
+        let (normal, u_axis, v_axis) = (Vec3::from(normal), Vec3::from(u_axis), Vec3::from(v_axis));
+        geometry.push_face(
+            normal * half_extents,
+            normal,
+            u_axis,
+            v_axis,
+            2.0 * u_axis.map(f32::abs).dot(half_extents),
+            2.0 * v_axis.map(f32::abs).dot(half_extents),
+            (n, n),
+            |pos, normal| tex_coord(mapping, pos, normal, half_extents),
+        );
+    }
+
+    geometry
+}
+
+/// Same as box_mesh but with normals, texture coordinates and tangents for lit, textured materials
+pub fn textured_box_mesh(x: f32, y: f32, z: f32, options: &PrimitiveOptions) -> Mesh {
+    textured_box_geometry(x, y, z, options).into_mesh(options)
+}
+
+/// Same as sphere_mesh but with normals, texture coordinates and tangents for lit, textured
+/// materials
+pub fn textured_sphere_mesh(radius: f32, options: &PrimitiveOptions) -> Mesh {
+    let subdivisions = options.subdivisions.max(1);
+    let n_phi_samples = 16 * subdivisions + 1;
+    let n_theta_samples = 8 * subdivisions + 1;
+    let half_extents = Vec3::broadcast(radius);
+    let mapping = options.uv_mapping.unwrap_or(UvMapping::Spherical);
+
+    let mut geometry = TexturedGeometry::default();
+    for i in 0..n_theta_samples {
+        for j in 0..n_phi_samples {
+            let theta_ratio = i as f32 / (n_theta_samples - 1) as f32;
+            let phi_ratio = j as f32 / (n_phi_samples - 1) as f32;
+
+            let phi = std::f32::consts::PI * 2.0 * phi_ratio;
+            let theta = std::f32::consts::PI * theta_ratio;
+
+            let normal = Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                -theta.sin() * phi.sin(),
+            );
+            let pos = normal * radius;
+            geometry.positions.push(pos);
+            geometry.normals.push(normal);
+            // The samples are used directly for spherical mapping so that the seam is duplicated
+            // instead of wrapping around
+            geometry.tex_coords.push(match mapping {
+                UvMapping::Spherical => Vec2::new(phi_ratio, theta_ratio),
+                _ => tex_coord(mapping, pos, normal, half_extents),
+            });
+
+            if i < n_theta_samples - 1 && j < n_phi_samples - 1 {
+                geometry.indices.extend_from_slice(&[
+                    n_phi_samples * i + j,
+                    n_phi_samples * (i + 1) + j,
+                    n_phi_samples * (i + 1) + (j + 1),
+                ]);
+                geometry.indices.extend_from_slice(&[
+                    n_phi_samples * i + j,
+                    n_phi_samples * (i + 1) + (j + 1),
+                    n_phi_samples * i + (j + 1),
+                ]);
+            }
+        }
+    }
+
+    geometry.into_mesh(options)
+}
+
+fn textured_plane_geometry(x: f32, z: f32, options: &PrimitiveOptions) -> TexturedGeometry {
+    let half_extents = Vec3::new(x, 0.0, z) * 0.5;
+    let mapping = options.uv_mapping.unwrap_or(UvMapping::Planar);
+    let n = options.subdivisions.max(1);
+    let mut geometry = TexturedGeometry::default();
+    geometry.push_face(
+        Vec3::zero(),
+        Vec3::unit_y(),
+        Vec3::unit_x(),
+        -Vec3::unit_z(),
+        x,
+        z,
+        (n, n),
+        |pos, normal| tex_coord(mapping, pos, normal, half_extents),
+    );
+
+    geometry
+}
+
+/// Origin-centered plane in x/z, facing +y. x,z are the length of the sides.
+pub fn textured_plane_mesh(x: f32, z: f32, options: &PrimitiveOptions) -> Mesh {
+    textured_plane_geometry(x, z, options).into_mesh(options)
+}
+
+fn textured_grid_geometry(
+    x: f32,
+    z: f32,
+    cells: (u32, u32),
+    options: &PrimitiveOptions,
+) -> TexturedGeometry {
+    let half_extents = Vec3::new(x, 0.0, z) * 0.5;
+    let (cells_x, cells_z) = (cells.0.max(1), cells.1.max(1));
+    let mut geometry = TexturedGeometry::default();
+    geometry.push_face(
+        Vec3::zero(),
+        Vec3::unit_y(),
+        Vec3::unit_x(),
+        -Vec3::unit_z(),
+        x,
+        z,
+        (cells_x, cells_z),
+        |pos, normal| match options.uv_mapping {
+            // Same orientation as planar mapping, but one unit per cell
+            None => Vec2::new(
+                (0.5 + pos.x / x) * cells_x as f32,
+                (0.5 + pos.z / z) * cells_z as f32,
+            ),
+            Some(mapping) => tex_coord(mapping, pos, normal, half_extents),
+        },
+    );
+
+    geometry
+}
+
+/// Same as textured_plane_mesh but split into cells.0 x cells.1 cells, with texture coordinates
+/// that go from 0 to the number of cells so that a repeating texture is repeated once per cell.
+/// The subdivisions option is not used.
+pub fn textured_grid_mesh(x: f32, z: f32, cells: (u32, u32), options: &PrimitiveOptions) -> Mesh {
+    textured_grid_geometry(x, z, cells, options).into_mesh(options)
+}
+
+// The corners of an icosahedron, which are not normalized, and its CCW faces
+const ICOSAHEDRON_GOLDEN_RATIO: f32 = 1.618_034;
+const ICOSAHEDRON_CORNERS: [[f32; 3]; 12] = [
+    [-1.0, ICOSAHEDRON_GOLDEN_RATIO, 0.0],
+    [1.0, ICOSAHEDRON_GOLDEN_RATIO, 0.0],
+    [-1.0, -ICOSAHEDRON_GOLDEN_RATIO, 0.0],
+    [1.0, -ICOSAHEDRON_GOLDEN_RATIO, 0.0],
+    [0.0, -1.0, ICOSAHEDRON_GOLDEN_RATIO],
+    [0.0, 1.0, ICOSAHEDRON_GOLDEN_RATIO],
+    [0.0, -1.0, -ICOSAHEDRON_GOLDEN_RATIO],
+    [0.0, 1.0, -ICOSAHEDRON_GOLDEN_RATIO],
+    [ICOSAHEDRON_GOLDEN_RATIO, 0.0, -1.0],
+    [ICOSAHEDRON_GOLDEN_RATIO, 0.0, 1.0],
+    [-ICOSAHEDRON_GOLDEN_RATIO, 0.0, -1.0],
+    [-ICOSAHEDRON_GOLDEN_RATIO, 0.0, 1.0],
+];
+const ICOSAHEDRON_FACES: [[u32; 3]; 20] = [
+    [0, 11, 5],
+    [0, 5, 1],
+    [0, 1, 7],
+    [0, 7, 10],
+    [0, 10, 11],
+    [1, 5, 9],
+    [5, 11, 4],
+    [11, 10, 2],
+    [10, 7, 6],
+    [7, 1, 8],
+    [3, 9, 4],
+    [3, 4, 2],
+    [3, 2, 6],
+    [3, 6, 8],
+    [3, 8, 9],
+    [4, 9, 5],
+    [2, 4, 11],
+    [6, 2, 10],
+    [8, 6, 7],
+    [9, 8, 1],
+];
+
+/// Spherical texture coordinates for the corners of a triangle. Triangles that cross the seam get
+/// u above 1 instead of wrapping around, and corners at the poles, where u is undefined, get the
+/// average u of the other corners.
+fn spherical_triangle_tex_coords(corners: [Vec3; 3]) -> [Vec2; 3] {
+    let is_pole = |p: Vec3| p.x * p.x + p.z * p.z < 0.00001;
+    let mut uvs = [Vec2::zero(); 3];
+    for (uv, &p) in uvs.iter_mut().zip(corners.iter()) {
+        *uv = tex_coord(UvMapping::Spherical, p, p, Vec3::one());
+    }
+
+    let us = (0..3).filter(|&i| !is_pole(corners[i])).map(|i| uvs[i].x);
+    let (min_u, max_u) = us.fold((1.0f32, 0.0f32), |(lo, hi), u| (lo.min(u), hi.max(u)));
+    if max_u - min_u > 0.5 {
+        for uv in uvs.iter_mut().filter(|uv| uv.x < 0.5) {
+            uv.x += 1.0;
+        }
+    }
+
+    for i in (0..3).filter(|&i| is_pole(corners[i])) {
+        let others: Vec<f32> = (0..3)
+            .filter(|&j| !is_pole(corners[j]))
+            .map(|j| uvs[j].x)
+            .collect();
+        uvs[i].x = others.iter().sum::<f32>() / others.len().max(1) as f32;
+    }
+
+    uvs
+}
+
+fn textured_icosphere_geometry(radius: f32, options: &PrimitiveOptions) -> TexturedGeometry {
+    let mut directions: Vec<Vec3> = ICOSAHEDRON_CORNERS
+        .iter()
+        .map(|&c| Vec3::from(c).normalized())
+        .collect();
+    let mut faces = ICOSAHEDRON_FACES.to_vec();
+
+    // Split each triangle into four, with the new vertices on the sphere. Edges are shared between
+    // triangles, and so are their midpoints.
+    for _ in 0..options.subdivisions.max(1) {
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            let key = (a.min(b), a.max(b));
+            *midpoints.entry(key).or_insert_with(|| {
+                let dir = (directions[a as usize] + directions[b as usize]).normalized();
+                directions.push(dir);
+                directions.len() as u32 - 1
+            })
+        };
+        faces = faces
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    let half_extents = Vec3::broadcast(radius);
+    let mut geometry = TexturedGeometry::default();
+    let mapping = match options.uv_mapping {
+        None | Some(UvMapping::Spherical) => None,
+        mapping => mapping,
+    };
+    for &dir in directions.iter() {
+        let pos = dir * radius;
+        geometry.positions.push(pos);
+        geometry.normals.push(dir);
+        geometry.tex_coords.push(match mapping {
+            Some(mapping) => tex_coord(mapping, pos, dir, half_extents),
+            None => tex_coord(UvMapping::Spherical, pos, dir, half_extents),
+        });
+    }
+
+    // With spherical mapping, the corners whose texture coordinates differ from the shared vertex,
+    // at the seam and the poles, get vertices of their own
+    let mut duplicates: HashMap<(u32, u32, u32), u32> = HashMap::new();
+    for face in faces {
+        if mapping.is_some() {
+            geometry.indices.extend_from_slice(&face);
+            continue;
+        }
+
+        let uvs = spherical_triangle_tex_coords([
+            directions[face[0] as usize],
+            directions[face[1] as usize],
+            directions[face[2] as usize],
+        ]);
+        for (&idx, &uv) in face.iter().zip(uvs.iter()) {
+            if geometry.tex_coords[idx as usize] == uv {
+                geometry.indices.push(idx);
+                continue;
+            }
+            let key = (idx, uv.x.to_bits(), uv.y.to_bits());
+            let positions = &mut geometry.positions;
+            let normals = &mut geometry.normals;
+            let tex_coords = &mut geometry.tex_coords;
+            let duplicate = *duplicates.entry(key).or_insert_with(|| {
+                positions.push(positions[idx as usize]);
+                normals.push(normals[idx as usize]);
+                tex_coords.push(uv);
+                positions.len() as u32 - 1
+            });
+            geometry.indices.push(duplicate);
+        }
+    }
+
+    geometry
+}
+
+/// A sphere made from an icosahedron, with triangles of about the same size everywhere, unlike the
+/// UV sphere of textured_sphere_mesh that is denser at the poles. The subdivisions option is the
+/// number of times that each triangle is split into four.
+pub fn textured_icosphere_mesh(radius: f32, options: &PrimitiveOptions) -> Mesh {
+    textured_icosphere_geometry(radius, options).into_mesh(options)
+}
+
+/// The side and the caps of a cylinder around the y-axis, from bottom_radius at y = -height / 2 to
+/// top_radius at y = height / 2. Caps with a radius of zero are left out. Without a uv mapping
+/// option, u goes around the side and v from the top to the bottom, and the caps are mapped
+/// planarly.
+fn textured_frustum_geometry(
+    bottom_radius: f32,
+    top_radius: f32,
+    height: f32,
+    options: &PrimitiveOptions,
+) -> TexturedGeometry {
+    let subdivisions = options.subdivisions.max(1);
+    let n_segments = 16 * subdivisions;
+    let n_rows = subdivisions;
+    let max_radius = bottom_radius.max(top_radius);
+    let half_extents = Vec3::new(max_radius, height * 0.5, max_radius);
+    let direction = |segment: u32| {
+        let angle = segment as f32 / n_segments as f32 * std::f32::consts::PI * 2.0;
+        Vec3::new(angle.cos(), 0.0, -angle.sin())
+    };
+
+    let mut geometry = TexturedGeometry::default();
+    for i in 0..=n_rows {
+        let t = i as f32 / n_rows as f32;
+        let y = height * (0.5 - t);
+        let radius = top_radius + t * (bottom_radius - top_radius);
+        for j in 0..=n_segments {
+            let dir = direction(j);
+            // Perpendicular to the slope of the side
+            let normal =
+                (dir * height + Vec3::unit_y() * (bottom_radius - top_radius)).normalized();
+            let pos = dir * radius + Vec3::unit_y() * y;
+            geometry.positions.push(pos);
+            geometry.normals.push(normal);
+            geometry.tex_coords.push(match options.uv_mapping {
+                None => Vec2::new(j as f32 / n_segments as f32, t),
+                Some(mapping) => tex_coord(mapping, pos, normal, half_extents),
+            });
+
+            let n = n_segments + 1;
+            if i < n_rows && j < n_segments {
+                geometry.indices.extend_from_slice(&[
+                    n * i + j,
+                    n * (i + 1) + j,
+                    n * (i + 1) + (j + 1),
+                ]);
+                geometry.indices.extend_from_slice(&[
+                    n * i + j,
+                    n * (i + 1) + (j + 1),
+                    n * i + (j + 1),
+                ]);
+            }
+        }
+    }
+
+    for &(radius, normal) in [
+        (top_radius, Vec3::unit_y()),
+        (bottom_radius, -Vec3::unit_y()),
+    ]
+    .iter()
+    {
+        if radius <= 0.0 {
+            continue;
+        }
+
+        let center = geometry.positions.len() as u32;
+        let cap_tex_coord = |pos: Vec3| match options.uv_mapping {
+            None => Vec2::new(0.5 + pos.x / (2.0 * radius), 0.5 + pos.z / (2.0 * radius)),
+            Some(mapping) => tex_coord(mapping, pos, normal, half_extents),
+        };
+        let center_pos = normal * height * 0.5;
+        geometry.positions.push(center_pos);
+        geometry.normals.push(normal);
+        geometry.tex_coords.push(cap_tex_coord(center_pos));
+        for j in 0..=n_segments {
+            let pos = direction(j) * radius + center_pos;
+            geometry.positions.push(pos);
+            geometry.normals.push(normal);
+            geometry.tex_coords.push(cap_tex_coord(pos));
+        }
+
+        // The segments go CCW around +y
+        for j in 0..n_segments {
+            let (a, b) = (center + 1 + j, center + 2 + j);
+            if normal.y > 0.0 {
+                geometry.indices.extend_from_slice(&[center, a, b]);
+            } else {
+                geometry.indices.extend_from_slice(&[center, b, a]);
+            }
+        }
+    }
+
+    geometry
+}
+
+/// Origin-centered cylinder around the y-axis, with caps
+pub fn textured_cylinder_mesh(radius: f32, height: f32, options: &PrimitiveOptions) -> Mesh {
+    textured_frustum_geometry(radius, radius, height, options).into_mesh(options)
+}
+
+/// Same as cone_mesh, with the circle in z/x and the tip in +y, but origin-centered like the other
+/// textured primitives
+pub fn textured_cone_mesh(radius: f32, height: f32, options: &PrimitiveOptions) -> Mesh {
+    textured_frustum_geometry(radius, 0.0, height, options).into_mesh(options)
+}
+
+/// The textured primitives and their sizes, e.g. to spawn them from the editor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Primitive {
+    Plane {
+        x: f32,
+        z: f32,
+    },
+    Box {
+        x: f32,
+        y: f32,
+        z: f32,
+    },
+    UvSphere {
+        radius: f32,
+    },
+    Icosphere {
+        radius: f32,
+    },
+    Cylinder {
+        radius: f32,
+        height: f32,
+    },
+    Cone {
+        radius: f32,
+        height: f32,
+    },
+    Grid {
+        x: f32,
+        z: f32,
+        cells_x: u32,
+        cells_z: u32,
+    },
+}
+
+impl Primitive {
+    /// One of each, about a unit in size
+    pub const ALL: [Primitive; 7] = [
+        Primitive::Plane { x: 1.0, z: 1.0 },
+        Primitive::Box {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        },
+        Primitive::UvSphere { radius: 0.5 },
+        Primitive::Icosphere { radius: 0.5 },
+        Primitive::Cylinder {
+            radius: 0.5,
+            height: 1.0,
+        },
+        Primitive::Cone {
+            radius: 0.5,
+            height: 1.0,
+        },
+        Primitive::Grid {
+            x: 10.0,
+            z: 10.0,
+            cells_x: 10,
+            cells_z: 10,
+        },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Plane { .. } => "Plane",
+            Self::Box { .. } => "Box",
+            Self::UvSphere { .. } => "UV sphere",
+            Self::Icosphere { .. } => "Icosphere",
+            Self::Cylinder { .. } => "Cylinder",
+            Self::Cone { .. } => "Cone",
+            Self::Grid { .. } => "Grid",
+        }
+    }
+
+    pub fn mesh(&self, options: &PrimitiveOptions) -> Mesh {
+        match *self {
+            Self::Plane { x, z } => textured_plane_mesh(x, z, options),
+            Self::Box { x, y, z } => textured_box_mesh(x, y, z, options),
+            Self::UvSphere { radius } => textured_sphere_mesh(radius, options),
+            Self::Icosphere { radius } => textured_icosphere_mesh(radius, options),
+            Self::Cylinder { radius, height } => textured_cylinder_mesh(radius, height, options),
+            Self::Cone { radius, height } => textured_cone_mesh(radius, height, options),
+            Self::Grid {
+                x,
+                z,
+                cells_x,
+                cells_z,
+            } => textured_grid_mesh(x, z, (cells_x, cells_z), options),
+        }
+    }
+}
+
+/// Each line is a degenerate triangle, so this is meant to be rendered with PolygonMode::Line
+pub fn line_list_mesh(lines: &[[Vec3; 2]]) -> Mesh {
+    let mut vertices = Vec::with_capacity(lines.len() * 2);
+    let mut indices = Vec::with_capacity(lines.len() * 3);
+    for [a, b] in lines.iter() {
+        let offset = vertices.len() as u32;
+        vertices.push(pos(a.x, a.y, a.z));
+        vertices.push(pos(b.x, b.y, b.z));
+        indices.extend_from_slice(&[offset, offset + 1, offset + 1]);
+    }
+
+    let vertices = OwningVertexBufferDescriptor::from_vec(vertices, BufferMutability::Immutable);
+    let indices = OwningIndexBufferDescriptor::from_vec(indices, BufferMutability::Immutable);
+    (vertices, indices)
+}
+
+struct ProfileVertex {
+    pos: Vec2,
+    normal: Vec2,
+    u: f32,
+}
+
+fn edge_normal(a: Vec2, b: Vec2) -> Vec2 {
+    let d = b - a;
+    Vec2::new(d.y, -d.x).normalized()
+}
+
+/// The vertices of a closed, CCW profile and the pairs of them that form its edges. Smooth
+/// profiles share vertices between edges, flat ones don't.
+fn profile_vertices(profile: &[Vec2], smooth: bool) -> (Vec<ProfileVertex>, Vec<(u32, u32)>) {
+    let n = profile.len();
+    let point = |i: usize| profile[i % n];
+    let mut lengths = vec![0.0];
+    for i in 0..n {
+        lengths.push(lengths[i] + point(i).distance(point(i + 1)));
+    }
+    let perimeter = lengths[n].max(std::f32::EPSILON);
+    let u = |i: usize| lengths[i] / perimeter;
+
+    let mut vertices = Vec::new();
+    let mut edges = Vec::new();
+    if smooth {
+        // The first vertex is duplicated at the end for the texture coordinate seam
+        for i in 0..=n {
+            let prev = edge_normal(point(i + n - 1), point(i + n));
+            let next = edge_normal(point(i), point(i + 1));
+            let sum = prev + next;
+            let normal = if sum.magnitude_squared() < std::f32::EPSILON {
+                next
+            } else {
+                sum.normalized()
+            };
+            vertices.push(ProfileVertex {
+                pos: point(i),
+                normal,
+                u: u(i),
+            });
+        }
+        edges.extend((0..n as u32).map(|i| (i, i + 1)));
+    } else {
+        for i in 0..n {
+            let normal = edge_normal(point(i), point(i + 1));
+            edges.push((vertices.len() as u32, vertices.len() as u32 + 1));
+            vertices.push(ProfileVertex {
+                pos: point(i),
+                normal,
+                u: u(i),
+            });
+            vertices.push(ProfileVertex {
+                pos: point(i + 1),
+                normal,
+                u: u(i + 1),
+            });
+        }
+    }
+
+    (vertices, edges)
+}
+
+/// Orthonormal (side, up) axes for the profile at each point of the path. The up axis starts as
+/// close to +y as possible and is then carried along the path to avoid twisting.
+fn path_frames(path: &[Vec3]) -> Vec<(Vec3, Vec3)> {
+    let n = path.len();
+    let mut frames = Vec::with_capacity(n);
+    let mut up = Vec3::unit_y();
+    for i in 0..n {
+        let tangent = (path[(i + 1).min(n - 1)] - path[i.saturating_sub(1)]).normalized();
+        if i == 0 && tangent.dot(up).abs() > 0.99 {
+            up = Vec3::unit_x();
+        }
+        let projected = up - tangent * up.dot(tangent);
+        if projected.magnitude_squared() > std::f32::EPSILON {
+            up = projected.normalized();
+        }
+        frames.push((up.cross(tangent), up));
+    }
+
+    frames
+}
+
+fn extrude_geometry(path: &[Vec3], profile: &[Vec2], smooth: bool) -> TexturedGeometry {
+    let (profile, edges) = profile_vertices(profile, smooth);
+    let frames = path_frames(path);
+
+    let mut geometry = TexturedGeometry::default();
+    let mut v = 0.0;
+    for (i, (point, (side, up))) in path.iter().zip(frames.iter()).enumerate() {
+        if i > 0 {
+            v += point.distance(path[i - 1]);
+        }
+        for p in profile.iter() {
+            geometry
+                .positions
+                .push(*point + *side * p.pos.x + *up * p.pos.y);
+            geometry.normals.push(*side * p.normal.x + *up * p.normal.y);
+            geometry.tex_coords.push(Vec2::new(p.u, v));
+        }
+    }
+
+    let ring = profile.len() as u32;
+    for i in 0..(path.len() as u32).saturating_sub(1) {
+        for &(a, b) in edges.iter() {
+            let (a0, b0) = (i * ring + a, i * ring + b);
+            let (a1, b1) = (a0 + ring, b0 + ring);
+            geometry
+                .indices
+                .extend_from_slice(&[a0, b0, b1, a0, b1, a1]);
+        }
+    }
+
+    geometry
+}
+
+/// Sweep a closed, CCW profile along a path. The x-axis of the profile points to the side of the
+/// path and the y-axis up. Texture coordinates go around the profile in u and along the path in
+/// v, one unit per unit of length. Only the tangents option is used.
+pub fn extrude_mesh(
+    path: &[Vec3],
+    profile: &[Vec2],
+    smooth: bool,
+    options: &PrimitiveOptions,
+) -> Mesh {
+    extrude_geometry(path, profile, smooth).into_mesh(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vek::approx::assert_abs_diff_eq;
+
+    const EPS: f32 = 0.00001;
+
+    #[test]
+    fn box_faces_are_ccw() {
+        for &(n, u, v) in BOX_FACES.iter() {
+            let (n, u, v) = (Vec3::from(n), Vec3::from(u), Vec3::from(v));
+            assert_abs_diff_eq!(u.cross(v), n, epsilon = EPS);
+            assert_eq!(box_face(n), (n, u, v));
+        }
+    }
+
+    #[test]
+    fn box_mapping_covers_each_face() {
+        let options = PrimitiveOptions {
+            subdivisions: 2,
+            ..Default::default()
+        };
+        let geometry = textured_box_geometry(1.0, 2.0, 3.0, &options);
+        assert_eq!(geometry.positions.len(), 6 * 9);
+        assert_eq!(geometry.indices.len(), 6 * 4 * 6);
+
+        for face in geometry.tex_coords.chunks_exact(9) {
+            assert_abs_diff_eq!(face[0], Vec2::new(0.0, 1.0), epsilon = EPS);
+            assert_abs_diff_eq!(face[4], Vec2::new(0.5, 0.5), epsilon = EPS);
+            assert_abs_diff_eq!(face[8], Vec2::new(1.0, 0.0), epsilon = EPS);
+        }
+    }
+
+    #[test]
+    fn plane_tangents_follow_u() {
+        let geometry = textured_plane_geometry(2.0, 2.0, &PrimitiveOptions::default());
+        let tangents = generate_tangents(
+            &geometry.positions,
+            &geometry.normals,
+            &geometry.tex_coords,
+            &geometry.indices,
+        );
+
+        for (t, n) in tangents.iter().zip(geometry.normals.iter()) {
+            assert_abs_diff_eq!(*t, Vec4::new(1.0, 0.0, 0.0, -1.0), epsilon = EPS);
+            // The bitangent points along increasing v, which is +z for planar mapping
+            assert_abs_diff_eq!(
+                n.cross(Vec3::new(t.x, t.y, t.z)) * t.w,
+                Vec3::unit_z(),
+                epsilon = EPS
+            );
+        }
+    }
+
+    #[test]
+    fn closed_primitives_face_outwards() {
+        let options = PrimitiveOptions {
+            subdivisions: 2,
+            ..Default::default()
+        };
+        let geometries = [
+            textured_icosphere_geometry(1.0, &options),
+            textured_frustum_geometry(1.0, 1.0, 2.0, &options),
+            textured_frustum_geometry(1.0, 0.0, 2.0, &options),
+        ];
+        for geometry in geometries.iter() {
+            for tri in geometry.indices.chunks_exact(3) {
+                let p = |i: usize| geometry.positions[tri[i] as usize];
+                let (a, b, c) = (p(0), p(1), p(2));
+                let face_normal = (b - a).cross(c - a);
+                // Half of the triangles at the tip of the cone have no area
+                if face_normal.magnitude_squared() < EPS {
+                    continue;
+                }
+                assert!(face_normal.dot((a + b + c) / 3.0) > 0.0);
+                for &i in tri {
+                    assert!(geometry.normals[i as usize].dot(face_normal) > 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn icosphere_and_grid_tex_coords() {
+        let sphere = textured_icosphere_geometry(2.0, &PrimitiveOptions::default());
+        assert_eq!(sphere.indices.len(), 80 * 3);
+        for (p, n) in sphere.positions.iter().zip(sphere.normals.iter()) {
+            assert_abs_diff_eq!(*p, *n * 2.0, epsilon = EPS);
+        }
+        // Triangles at the seam don't wrap around the whole texture
+        for tri in sphere.indices.chunks_exact(3) {
+            let u = |i: usize| sphere.tex_coords[tri[i] as usize].x;
+            let (lo, hi) = (u(0).min(u(1)).min(u(2)), u(0).max(u(1)).max(u(2)));
+            assert!(hi - lo < 0.5);
+        }
+
+        let grid = textured_grid_geometry(4.0, 2.0, (4, 2), &PrimitiveOptions::default());
+        assert_eq!(grid.positions.len(), 5 * 3);
+        assert_abs_diff_eq!(grid.positions[0], Vec3::new(-2.0, 0.0, 1.0), epsilon = EPS);
+        assert_abs_diff_eq!(grid.tex_coords[0], Vec2::new(0.0, 2.0), epsilon = EPS);
+        assert_abs_diff_eq!(grid.tex_coords[14], Vec2::new(4.0, 0.0), epsilon = EPS);
+    }
+
+    #[test]
+    fn extruded_square_faces_outwards() {
+        let path = [Vec3::zero(), Vec3::unit_z(), Vec3::unit_z() * 2.0];
+        let profile = [
+            Vec2::new(-1.0, -1.0),
+            Vec2::new(1.0, -1.0),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(-1.0, 1.0),
+        ];
+
+        let flat = extrude_geometry(&path, &profile, false);
+        assert_eq!(flat.positions.len(), 3 * 8);
+        assert_eq!(flat.indices.len(), 2 * 4 * 6);
+        for tri in flat.indices.chunks_exact(3) {
+            let p = |i: usize| flat.positions[tri[i] as usize];
+            let (a, b, c) = (p(0), p(1), p(2));
+            let face_normal = (b - a).cross(c - a).normalized();
+            // Both the winding and the vertex normals point away from the path
+            let center = Vec3::new(0.0, 0.0, a.z);
+            assert!(face_normal.dot(a - center) > 0.0);
+            assert_abs_diff_eq!(flat.normals[tri[0] as usize], face_normal, epsilon = EPS);
+        }
+
+        let smooth = extrude_geometry(&path, &profile, true);
+        assert_eq!(smooth.positions.len(), 3 * 5);
+        assert_abs_diff_eq!(
+            smooth.normals[0],
+            Vec3::new(-1.0, -1.0, 0.0).normalized(),
+            epsilon = EPS
+        );
+        assert_abs_diff_eq!(smooth.tex_coords[4], Vec2::new(1.0, 0.0), epsilon = EPS);
+        assert_abs_diff_eq!(smooth.tex_coords[14], Vec2::new(1.0, 2.0), epsilon = EPS);
+    }
+}
@@ -0,0 +1,237 @@
+//! Writes the scene color from before tonemapping, with its full dynamic range, to OpenEXR or
+//! Radiance HDR files for compositing or for looking at the lighting in other tools.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use image::Rgb;
+use thiserror::Error;
+
+use trekanten::util;
+use trekanten::Renderer;
+
+use crate::ecs::prelude::*;
+
+use super::FrameData;
+
+#[derive(Debug, Error)]
+pub enum HdrExportError {
+    #[error("Expected an .exr or .hdr file, got {0}")]
+    UnsupportedExtension(PathBuf),
+    #[error("Can't export scene color with format {0:?}")]
+    UnsupportedFormat(util::Format),
+    #[error("Failed to read back the scene color: {0}")]
+    Readback(#[from] trekanten::RenderError),
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("Failed to encode Radiance HDR: {0}")]
+    Radiance(#[from] image::ImageError),
+}
+
+pub(super) struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 4]>,
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+pub(super) fn decode(
+    extent: util::Extent2D,
+    format: util::Format,
+    data: &[u8],
+) -> Result<HdrImage, HdrExportError> {
+    let pixels = match format {
+        util::Format::RGBA_F16 => data
+            .chunks_exact(8)
+            .map(|p| {
+                let c = |i: usize| f16_to_f32(u16::from_le_bytes([p[2 * i], p[2 * i + 1]]));
+                [c(0), c(1), c(2), c(3)]
+            })
+            .collect(),
+        util::Format::FLOAT4 => data
+            .chunks_exact(16)
+            .map(|p| {
+                let c = |i: usize| {
+                    f32::from_le_bytes([p[4 * i], p[4 * i + 1], p[4 * i + 2], p[4 * i + 3]])
+                };
+                [c(0), c(1), c(2), c(3)]
+            })
+            .collect(),
+        _ => return Err(HdrExportError::UnsupportedFormat(format)),
+    };
+
+    Ok(HdrImage {
+        width: extent.width,
+        height: extent.height,
+        pixels,
+    })
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, ty: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(ty.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// Single-part scanline OpenEXR with uncompressed 32-bit float channels, one scanline per block
+pub(super) fn write_exr<W: Write>(w: &mut W, image: &HdrImage) -> std::io::Result<()> {
+    const MAGIC: u32 = 20000630;
+    const VERSION: u32 = 2;
+    const PIXEL_TYPE_FLOAT: i32 = 2;
+    // Channels are stored in alphabetical order
+    const CHANNELS: [(&str, usize); 4] = [("A", 3), ("B", 2), ("G", 1), ("R", 0)];
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&MAGIC.to_le_bytes());
+    header.extend_from_slice(&VERSION.to_le_bytes());
+
+    let mut channels = Vec::new();
+    for (name, _) in CHANNELS.iter() {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&PIXEL_TYPE_FLOAT.to_le_bytes());
+        // pLinear and reserved
+        channels.extend_from_slice(&[0, 0, 0, 0]);
+        // x and y sampling
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+    attribute(&mut header, "channels", "chlist", &channels);
+    attribute(&mut header, "compression", "compression", &[0]);
+
+    let window: Vec<u8> = [0, 0, image.width as i32 - 1, image.height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes().to_vec())
+        .collect();
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    // Increasing y
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    let line_size = image.width as u64 * CHANNELS.len() as u64 * 4;
+    let block_size = 8 + line_size;
+    let first_block = header.len() as u64 + image.height as u64 * 8;
+
+    w.write_all(&header)?;
+    for y in 0..image.height as u64 {
+        w.write_all(&(first_block + y * block_size).to_le_bytes())?;
+    }
+
+    for (y, row) in image.pixels.chunks(image.width as usize).enumerate() {
+        w.write_all(&(y as i32).to_le_bytes())?;
+        w.write_all(&(line_size as i32).to_le_bytes())?;
+        for (_, c) in CHANNELS.iter() {
+            for p in row {
+                w.write_all(&p[*c].to_le_bytes())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_radiance<W: Write>(w: W, image: &HdrImage) -> image::ImageResult<()> {
+    let rgb: Vec<Rgb<f32>> = image
+        .pixels
+        .iter()
+        .map(|p| Rgb([p[0].max(0.0), p[1].max(0.0), p[2].max(0.0)]))
+        .collect();
+    image::codecs::hdr::HdrEncoder::new(w).encode(&rgb, image.width as usize, image.height as usize)
+}
+
+/// Write the scene color of the last rendered frame. The format is chosen from the extension,
+/// .exr keeps alpha and negative values while .hdr does not.
+pub(crate) fn save(world: &World, renderer: &Renderer, path: &Path) -> Result<(), HdrExportError> {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    let write_exr_file = match ext.as_deref() {
+        Some("exr") => true,
+        Some("hdr") => false,
+        _ => return Err(HdrExportError::UnsupportedExtension(path.to_path_buf())),
+    };
+
+    let scene_color = *world.read_resource::<FrameData>().post.scene_color();
+    let (extent, format, data) = renderer.read_texture(&scene_color)?;
+    let image = decode(extent, format, &data)?;
+
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    if write_exr_file {
+        write_exr(&mut file, &image)?;
+    } else {
+        write_radiance(&mut file, &image)?;
+    }
+    file.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x7bff), 65504.0);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert!(f16_to_f32(0x7e00).is_nan());
+    }
+
+    #[test]
+    fn exr_layout() {
+        let image = HdrImage {
+            width: 2,
+            height: 1,
+            pixels: vec![[1.0, 2.0, 3.0, 4.0], [5.0, 6.0, 7.0, 8.0]],
+        };
+        let mut out = Vec::new();
+        write_exr(&mut out, &image).unwrap();
+
+        assert_eq!(&out[0..4], &[0x76, 0x2f, 0x31, 0x01]);
+        // One block of the scanline index, the data size and two pixels of four channels
+        let block = 8 + 2 * 4 * 4;
+        let offsets_start = out.len() - block - 8;
+        let mut offset = [0; 8];
+        offset.copy_from_slice(&out[offsets_start..offsets_start + 8]);
+        assert_eq!(u64::from_le_bytes(offset) as usize, offsets_start + 8);
+
+        let floats: Vec<f32> = out[out.len() - 32..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(floats, vec![4.0, 8.0, 3.0, 7.0, 2.0, 6.0, 1.0, 5.0]);
+    }
+}
@@ -0,0 +1,82 @@
+use trekanten::texture::Texture;
+use trekanten::Handle;
+
+struct Target {
+    name: String,
+    texture: Handle<Texture>,
+    depth: bool,
+    texture_id: Option<imgui::TextureId>,
+}
+
+/// Render targets that can be shown in the debug window, e.g. the spotlight shadow maps. The
+/// textures need to be in a shader read only layout when the ui is drawn. Their imgui texture ids
+/// are created before the next frame, see UIContext::register_inspected_targets.
+#[derive(Default)]
+pub struct RenderTargetInspector {
+    targets: Vec<Target>,
+    selected: Option<usize>,
+}
+
+impl RenderTargetInspector {
+    /// Depth targets are shown as grayscale images
+    pub fn register(&mut self, name: impl Into<String>, texture: Handle<Texture>, depth: bool) {
+        self.targets.push(Target {
+            name: name.into(),
+            texture,
+            depth,
+            texture_id: None,
+        });
+    }
+
+    /// Shows `new` instead of a texture that was recreated
+    pub fn replace(&mut self, old: &Handle<Texture>, new: Handle<Texture>) {
+        for target in self.targets.iter_mut().filter(|t| t.texture == *old) {
+            target.texture = new;
+            target.texture_id = None;
+        }
+    }
+
+    pub(crate) fn unregistered(&self) -> Vec<(usize, Handle<Texture>, bool)> {
+        self.targets
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.texture_id.is_none())
+            .map(|(i, t)| (i, t.texture, t.depth))
+            .collect()
+    }
+
+    pub(crate) fn set_texture_id(&mut self, idx: usize, id: imgui::TextureId) {
+        self.targets[idx].texture_id = Some(id);
+    }
+
+    pub(crate) fn build_ui<'a>(&mut self, ui: &crate::render::ui::UiFrame<'a>) {
+        let ui = ui.inner();
+        if self.targets.is_empty() {
+            return;
+        }
+
+        let mut labels = vec![imgui::ImString::new("None")];
+        labels.extend(self.targets.iter().map(|t| imgui::im_str!("{}", t.name)));
+        let label_refs: Vec<&imgui::ImStr> = labels.iter().map(|l| l.as_ref()).collect();
+        let mut idx = self.selected.map(|i| i + 1).unwrap_or(0);
+        if imgui::ComboBox::new(imgui::im_str!("Render target")).build_simple_string(
+            ui,
+            &mut idx,
+            &label_refs,
+        ) {
+            self.selected = idx.checked_sub(1);
+        }
+
+        let target = match self.selected.map(|i| &self.targets[i]) {
+            Some(target) => target,
+            None => return,
+        };
+        match target.texture_id {
+            Some(id) => {
+                let width = ui.content_region_avail()[0].max(64.0);
+                imgui::Image::new(id, [width, width]).build(ui);
+            }
+            None => ui.text("Not available yet"),
+        }
+    }
+}
@@ -1,29 +1,77 @@
 use crate::ecs::prelude::*;
-use crate::math::{perspective_vk, Mat4, Quat, Rgb, Rgba, Transform, Vec3};
+use crate::math::{
+    perspective_vk, world_to_view, BoundingSphere, BoundingVolume, Frustum, Mat4, Quat, Rgb, Rgba,
+    Transform, Vec3,
+};
 
 use trekanten::CommandBuffer;
 
 use crate::graph::{sys::add_edge, sys::breadth_first, Children, Parent};
 use crate::render::mesh::CpuMesh;
-use crate::render::uniform::{LightingData, PackedLight, ShadowMatrices, ViewData, MAX_NUM_LIGHTS};
+use crate::render::uniform::{
+    LightingData, PackedLight, ShadowMatrices, UniformBlock as _, ViewData, MAX_NUM_LIGHTS,
+};
 
 #[derive(Default, Component)]
 #[component(storage = "NullStorage")]
 pub struct RenderLightVolume;
 
-#[derive(Default, Component)]
-#[component(storage = "NullStorage")]
-pub struct LightVolumeRenderer;
+/// The mesh that shows the volume of the parent light, as it was when the mesh was created
+#[derive(Component)]
+pub struct LightVolumeRenderer {
+    light: Light,
+}
+
+/// How bright a point or spot light is
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LightIntensity {
+    /// Luminous power, the total amount of light that is emitted
+    Lumens(f32),
+    /// Luminous intensity, lumens per steradian
+    Candela(f32),
+}
 
-#[derive(Component, serde::Serialize, serde::Deserialize, Clone, Debug)]
+impl LightIntensity {
+    /// The luminous intensity of a light that spreads its power over this solid angle
+    fn candela(&self, steradians: f32) -> f32 {
+        match *self {
+            Self::Lumens(lm) => lm / steradians,
+            Self::Candela(cd) => cd,
+        }
+    }
+}
+
+impl Default for LightIntensity {
+    fn default() -> Self {
+        Self::Candela(1.0)
+    }
+}
+
+#[derive(Component, serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
 #[component(inspect)]
 pub enum Light {
     // Range is the radius of the sphere
-    Point { color: Rgb, range: f32 },
-    Directional { color: Rgb },
-    // Angle is from the center line of the cone & range the height of the cone
-    Spot { color: Rgb, angle: f32, range: f32 },
-    Ambient { color: Rgb, strength: f32 },
+    Point {
+        color: Rgb,
+        intensity: LightIntensity,
+        range: f32,
+    },
+    Directional {
+        color: Rgb,
+    },
+    // Angles are from the center line of the cone & range the height of the cone. The light falls
+    // off between the inner and the outer angle.
+    Spot {
+        color: Rgb,
+        intensity: LightIntensity,
+        inner_angle: f32,
+        outer_angle: f32,
+        range: f32,
+    },
+    Ambient {
+        color: Rgb,
+        strength: f32,
+    },
 }
 
 impl Light {
@@ -44,12 +92,150 @@ impl Default for Light {
                 g: 1.0,
                 b: 1.0,
             },
-            angle: std::f32::consts::FRAC_PI_8,
+            intensity: LightIntensity::default(),
+            inner_angle: 0.0,
+            outer_angle: std::f32::consts::FRAC_PI_8,
             range: 5.0,
         }
     }
 }
 
+impl Light {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Light::Point { .. } => "Point light",
+            Light::Directional { .. } => "Directional light",
+            Light::Spot { .. } => "Spot light",
+            Light::Ambient { .. } => "Ambient light",
+        }
+    }
+
+    pub fn color(&self) -> Rgb {
+        match self {
+            Light::Point { color, .. }
+            | Light::Directional { color }
+            | Light::Spot { color, .. }
+            | Light::Ambient { color, .. } => *color,
+        }
+    }
+
+    pub fn color_mut(&mut self) -> &mut Rgb {
+        match self {
+            Light::Point { color, .. }
+            | Light::Directional { color }
+            | Light::Spot { color, .. }
+            | Light::Ambient { color, .. } => color,
+        }
+    }
+
+    /// Only point and spot lights have an intensity
+    pub fn intensity(&self) -> Option<LightIntensity> {
+        match self {
+            Light::Point { intensity, .. } | Light::Spot { intensity, .. } => Some(*intensity),
+            Light::Directional { .. } | Light::Ambient { .. } => None,
+        }
+    }
+
+    pub fn intensity_mut(&mut self) -> Option<&mut LightIntensity> {
+        match self {
+            Light::Point { intensity, .. } | Light::Spot { intensity, .. } => Some(intensity),
+            Light::Directional { .. } | Light::Ambient { .. } => None,
+        }
+    }
+
+    /// The color scaled by the luminous intensity, which is what the shader uses. The power of a
+    /// spot light is not spread over its cone, so that changing the angle doesn't change how
+    /// bright it is.
+    pub(super) fn packed_color(&self) -> Rgb {
+        use std::f32::consts::PI;
+        match self {
+            Light::Point {
+                color, intensity, ..
+            } => *color * intensity.candela(4.0 * PI),
+            Light::Spot {
+                color, intensity, ..
+            } => *color * intensity.candela(PI),
+            Light::Directional { color } | Light::Ambient { color, .. } => *color,
+        }
+    }
+
+    /// The sphere that bounds the volume that is lit, for lights that have one
+    fn bounding_sphere(&self, tfm: &Transform) -> Option<(Vec3, f32)> {
+        match self {
+            Light::Point { range, .. } | Light::Spot { range, .. } => Some((tfm.position, *range)),
+            Light::Directional { .. } | Light::Ambient { .. } => None,
+        }
+    }
+}
+
+/// Makes the cone of a shadow-casting spot light visible, as if the air it passes through was
+/// dusty. Has no effect on other lights. See render::volumetric.
+#[derive(Debug, Clone, Copy, Component, serde::Serialize, serde::Deserialize)]
+#[component(inspect)]
+pub struct Volumetric {
+    /// The fraction of the light that is scattered towards the camera per meter
+    pub density: f32,
+}
+
+impl Default for Volumetric {
+    fn default() -> Self {
+        Self { density: 0.05 }
+    }
+}
+
+/// Distance-based level of detail for point and spot lights. A light is disabled when the camera
+/// is further away from its volume than cutoff times its range, and fades out before that.
+#[derive(Debug, Clone, Copy, Component)]
+#[component(inspect)]
+pub struct LightLod {
+    pub cutoff: f32,
+    /// The part of the cutoff distance, in [0, 1], over which the light fades out
+    pub fade: f32,
+}
+
+impl Default for LightLod {
+    fn default() -> Self {
+        Self {
+            cutoff: 10.0,
+            fade: 0.2,
+        }
+    }
+}
+
+impl LightLod {
+    /// How much of the light to keep, in [0, 1], at distance from its volume
+    fn factor(&self, range: f32, distance: f32) -> f32 {
+        let cutoff = self.cutoff * range;
+        let fade = (self.fade * cutoff).max(std::f32::EPSILON);
+        ((cutoff - distance) / fade).max(0.0).min(1.0)
+    }
+}
+
+/// Gives the lights that can be culled the default LightLod, so that it can be inspected
+pub struct AddLightLod;
+
+impl AddLightLod {
+    pub const ID: &'static str = "AddLightLod";
+}
+
+impl<'a> System<'a> for AddLightLod {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Light>,
+        WriteStorage<'a, LightLod>,
+    );
+
+    fn run(&mut self, (entities, lights, mut lods): Self::SystemData) {
+        for (ent, light) in (&entities, &lights).join() {
+            if let Light::Point { .. } | Light::Spot { .. } = light {
+                if let StorageEntry::Vacant(entry) = lods.entry(ent).unwrap() {
+                    entry.insert(LightLod::default());
+                }
+            }
+        }
+    }
+}
+
 pub struct RenderLightVolumes;
 
 impl<'a> System<'a> for RenderLightVolumes {
@@ -79,25 +265,35 @@ impl<'a> System<'a> for RenderLightVolumes {
         ) = data;
 
         for (ent, light, _) in (&entities, &lights, &command_markers).join() {
-            let mut found_child = false;
+            let mut volume = None;
             breadth_first(&children, ent, |node| {
-                found_child = renderer_markers.get(node).is_some()
+                if renderer_markers.get(node).is_some() {
+                    volume = Some(node);
+                }
             });
-            if found_child {
-                continue;
+            if let Some(volume) = volume {
+                if renderer_markers.get(volume).map(|r| &r.light) == Some(light) {
+                    continue;
+                }
+                // The light was edited, so the volume is created again
+                entities.delete(volume).unwrap();
+                if let Some(siblings) = children.get_mut(ent) {
+                    siblings.children.retain(|c| *c != volume);
+                }
             }
 
             let (vertex_buffer, index_buffer, color, tfm) = match light {
-                Light::Point { range, color } => {
+                Light::Point { range, color, .. } => {
                     let mesh = super::geometry::sphere_mesh(*range);
                     (mesh.0, mesh.1, color, Transform::identity())
                 }
                 Light::Spot {
                     color,
-                    angle,
+                    outer_angle,
                     range,
+                    ..
                 } => {
-                    let radius = angle.tan() * range;
+                    let radius = outer_angle.tan() * range;
                     let (v, i) = super::geometry::cone_mesh(radius, *range);
                     // Cone mesh has base at origin, apex at (0, range, 0). We want to have apex at origin (translation) and then
                     // rotated to Light::DEFAULT_FACING
@@ -128,7 +324,12 @@ impl<'a> System<'a> for RenderLightVolumes {
             let child = entities
                 .build_entity()
                 .with(tfm, &mut transforms)
-                .with(LightVolumeRenderer, &mut renderer_markers)
+                .with(
+                    LightVolumeRenderer {
+                        light: light.clone(),
+                    },
+                    &mut renderer_markers,
+                )
                 .with(mesh, &mut meshes)
                 .with(material, &mut materials)
                 .build();
@@ -147,12 +348,147 @@ impl<'a> System<'a> for RenderLightVolumes {
     }
 }
 
+/// The camera that the lights are culled against
+pub struct LightCullingView {
+    pub view_proj: Mat4,
+    pub view_pos: Vec3,
+}
+
+/// Which meshes are drawn into the shadow map of a spot light. A mesh has to be in the frustum of
+/// the light, and the shadow that it casts, away from the light and up to its range, has to be in
+/// the frustum of the camera, if there is one.
+struct ShadowCasterCulling {
+    light_frustum: Frustum,
+    light_pos: Vec3,
+    range: f32,
+    camera_frustum: Option<Frustum>,
+}
+
+impl ShadowCasterCulling {
+    fn casts_visible_shadow(&self, volume: &BoundingVolume) -> bool {
+        if !self.light_frustum.intersects_volume(volume) {
+            return false;
+        }
+        let camera_frustum = match &self.camera_frustum {
+            Some(frustum) => frustum,
+            None => return true,
+        };
+
+        let BoundingSphere { center, radius } = volume.bounding_sphere();
+        let to_caster = center - self.light_pos;
+        let distance = to_caster.magnitude();
+        if distance <= radius {
+            // The light is inside the caster
+            return true;
+        }
+
+        // The shadow widens with the distance from the light, so the sphere is swept with the
+        // radius it has at the end
+        let scale = (self.range / distance).max(1.0);
+        let end = self.light_pos + to_caster * scale;
+        camera_frustum.intersects_swept_sphere(center, end, radius * scale)
+    }
+}
+
+/// A shadow map that was rendered in an earlier frame
+#[derive(Debug, Clone, Copy)]
+struct CachedShadowMap {
+    light: Entity,
+    view_proj: super::uniform::Mat4,
+    /// See DrawList::signature
+    casters: u64,
+    /// The least recently used shadow map is the first to be given to another light
+    last_used: u64,
+}
+
+/// Keeps the shadow maps of the spot lights between frames. A light keeps its shadow map, which is
+/// only rendered again if the light has changed, or if any of the meshes that are drawn into it has
+/// been added, removed, moved or given another material.
+#[derive(Debug, Default)]
+pub struct ShadowMapCache {
+    slots: [Option<CachedShadowMap>; super::NUM_SPOTLIGHT_SHADOW_MAPS],
+    frame: u64,
+    /// The number of shadow maps that were rendered last frame
+    pub rendered: u32,
+    /// The number of shadow maps that were used again last frame, without being rendered
+    pub reused: u32,
+}
+
+impl ShadowMapCache {
+    fn begin_frame(&mut self, enabled: bool) {
+        if !enabled {
+            self.clear();
+        }
+        self.frame += 1;
+        self.rendered = 0;
+        self.reused = 0;
+    }
+
+    /// The shadow map of the light, or the one that was least recently used by another light.
+    /// None if all of them are used this frame.
+    fn slot(&self, light: Entity) -> Option<usize> {
+        let own = self
+            .slots
+            .iter()
+            .position(|slot| matches!(slot, Some(cached) if cached.light == light));
+        own.or_else(|| {
+            self.slots
+                .iter()
+                .enumerate()
+                .filter(|(_, slot)| slot.map_or(true, |cached| cached.last_used != self.frame))
+                .min_by_key(|(_, slot)| slot.map_or(0, |cached| cached.last_used))
+                .map(|(idx, _)| idx)
+        })
+    }
+
+    /// Gives the shadow map to the light for this frame. Returns true if it has to be rendered.
+    fn update(
+        &mut self,
+        slot: usize,
+        light: Entity,
+        view_proj: super::uniform::Mat4,
+        casters: u64,
+    ) -> bool {
+        let reuse = matches!(
+            self.slots[slot],
+            Some(cached) if cached.light == light
+                && cached.view_proj == view_proj
+                && cached.casters == casters
+        );
+        self.slots[slot] = Some(CachedShadowMap {
+            light,
+            view_proj,
+            casters,
+            last_used: self.frame,
+        });
+        if reuse {
+            self.reused += 1;
+        } else {
+            self.rendered += 1;
+        }
+        !reuse
+    }
+
+    /// Forgets the shadow maps, e.g. after they were recreated
+    pub(super) fn clear(&mut self) {
+        self.slots = Default::default();
+    }
+
+    /// If the shadow map has not been rendered in any frame since the cache was cleared
+    fn is_empty(&self, slot: usize) -> bool {
+        self.slots[slot].is_none()
+    }
+}
+
+/// Renders the shadow maps and writes the light data of the frame. On errors, the frame is dropped,
+/// see draw_frame.
 pub fn light_and_shadow_pass(
     world: &World,
     frame: &mut trekanten::Frame,
     frame_resources: &super::FrameData,
+    culling_view: &LightCullingView,
     mut cmd_buffer: CommandBuffer,
-) -> CommandBuffer {
+) -> Result<CommandBuffer, trekanten::RenderError> {
     use trekanten::raw_vk;
     let mut lighting_data = LightingData::default();
     let mut shadow_matrices = ShadowMatrices::default();
@@ -166,6 +502,22 @@ pub fn light_and_shadow_pass(
 
     let lights = world.read_storage::<Light>();
     let transforms = world.read_storage::<Transform>();
+    let lods = world.read_storage::<LightLod>();
+    let volumetrics = world.read_storage::<Volumetric>();
+    let shadow_masks = world.read_storage::<super::shadow_bake::ShadowMask>();
+    let shadow_mask = frame_resources.shadow_mask.baked(&shadow_masks);
+    let (cull_lights, cull_shadow_casters, cache_shadow_maps) = {
+        let settings = world.read_resource::<super::debug_window::RenderSettings>();
+        (
+            settings.cull_lights,
+            settings.cull_shadow_casters,
+            settings.cache_shadow_maps,
+        )
+    };
+    let mut shadow_cache = world.write_resource::<ShadowMapCache>();
+    shadow_cache.begin_frame(cache_shadow_maps);
+    let mut draw_stats = world.write_resource::<super::DrawStats>();
+    let frustum = Frustum::from_view_proj(culling_view.view_proj);
     let mut n_ambients = 0;
 
     let super::FrameData {
@@ -174,16 +526,24 @@ pub fn light_and_shadow_pass(
                 render_pass,
                 dummy_pipeline,
                 spotlights,
+                extent,
             },
         ..
     } = frame_resources;
 
-    for (idx, (light, tfm)) in (&lights, &transforms).join().enumerate() {
-        if idx >= MAX_NUM_LIGHTS {
-            log::warn!("Too many punctual lights, skipping remaining");
-            break;
-        }
+    if let Some(mask) = shadow_mask {
+        mask.write_lighting_data(&mut lighting_data);
+    }
 
+    for (ent, light, tfm, lod, volumetric) in (
+        &world.entities(),
+        &lights,
+        &transforms,
+        lods.maybe(),
+        volumetrics.maybe(),
+    )
+        .join()
+    {
         if let Light::Ambient { color, strength } = &light {
             if n_ambients > 0 {
                 log::warn!("Too many ambient lights, skipping all but first");
@@ -194,97 +554,143 @@ pub fn light_and_shadow_pass(
             continue;
         }
 
-        match light {
+        // Lights that don't light anything in view are left out of the light buffer and don't get
+        // shadow maps
+        let mut fade = 1.0;
+        if let (true, Some((center, radius))) = (cull_lights, light.bounding_sphere(tfm)) {
+            if !frustum.intersects_sphere(center, radius) {
+                continue;
+            }
+
+            let distance = (center.distance(culling_view.view_pos) - radius).max(0.0);
+            fade = lod.copied().unwrap_or_default().factor(radius, distance);
+            if fade <= 0.0 {
+                continue;
+            }
+        }
+
+        if lighting_data.num_lights as usize >= MAX_NUM_LIGHTS {
+            log::warn!("Too many punctual lights, skipping remaining");
+            break;
+        }
+
+        let color = light.packed_color() * fade;
+        let baked_channel = shadow_mask.and_then(|mask| mask.channel(ent));
+        let packed = match light {
             Light::Spot {
-                angle,
+                inner_angle,
+                outer_angle,
                 range,
-                color,
+                ..
             } => {
                 let direction = tfm.rotation * Light::DEFAULT_FACING;
-                let packed_light =
-                    &mut lighting_data.punctual_lights[lighting_data.num_lights as usize];
-
-                *packed_light = PackedLight {
-                    pos: [tfm.position.x, tfm.position.y, tfm.position.z, 1.0],
-                    dir_cutoff: [direction.x, direction.y, direction.z, angle.cos()],
-                    color_range: [color.r, color.g, color.b, *range],
-                    shadow_idx: [shadow_matrices.num_matrices; 4],
-                };
-                let shadow_idx = packed_light.shadow_idx[0] as usize;
-                shadow_matrices.num_matrices += 1;
-
-                let mut view_data = ViewData::default();
-                let proj = perspective_vk(angle * 2.0, 1.0, 1.0, *range);
-                let view = Mat4::from(*tfm).inverted();
-                view_data.view_pos = [tfm.position[0], tfm.position[1], tfm.position[2], 1.0];
-                view_data.view_proj = (proj * view).into_col_array();
-                shadow_matrices.matrices[shadow_idx] = view_data.view_proj;
-                frame
-                    .update_uniform_blocking(&spotlights[shadow_idx].view_data_buffer, &view_data)
-                    .expect("Failed to update view data for shadow pass");
-
-                let mut shadow_rp = frame
-                    .begin_render_pass(
-                        cmd_buffer,
-                        render_pass,
-                        &spotlights[shadow_idx].render_target,
-                        super::SHADOW_MAP_EXTENT,
-                        &clear_values,
-                    )
-                    .expect("Failed to shadow begin render pass");
-
-                shadow_rp
-                    .bind_graphics_pipeline(dummy_pipeline)
-                    .bind_shader_resource_group(
-                        0u32,
-                        &spotlights[shadow_idx].view_data_desc_set,
-                        dummy_pipeline,
+                let density = volumetric.map(|v| v.density.max(0.0)).unwrap_or(0.0);
+                let packed = PackedLight::spot(
+                    tfm.position.into_array(),
+                    direction.into_array(),
+                    color.into_array(),
+                    *range,
+                    (*inner_angle, *outer_angle),
+                )
+                .with_volumetric_density(density);
+                // Nothing that the light lights is in view, so it doesn't need a shadow map
+                let in_view = frustum.intersects_sphere(tfm.position, *range);
+                if baked_channel.is_some() || (cull_shadow_casters && !in_view) {
+                    packed
+                } else if let Some(shadow_idx) = shadow_cache.slot(ent) {
+                    let proj = perspective_vk(outer_angle * 2.0, 1.0, 1.0, *range);
+                    let view = world_to_view(tfm);
+                    let view_proj = (proj * view).into_col_array();
+                    shadow_matrices.matrices[shadow_idx] = view_proj;
+                    shadow_matrices.num_matrices =
+                        shadow_matrices.num_matrices.max(shadow_idx as u32 + 1);
+
+                    let culling = ShadowCasterCulling {
+                        light_frustum: Frustum::from_view_proj(proj * view),
+                        light_pos: tfm.position,
+                        range: *range,
+                        // A cached shadow map has to stay valid when the camera moves
+                        camera_frustum: if cache_shadow_maps {
+                            None
+                        } else {
+                            Some(frustum)
+                        },
+                    };
+                    let casters = super::draw_list::DrawList::build(
+                        world,
+                        super::DrawMode::ShadowsOnly,
+                        &|bounds| !cull_shadow_casters || culling.casts_visible_shadow(bounds),
+                        &mut draw_stats,
                     );
-                super::draw_entities(world, &mut shadow_rp, super::DrawMode::ShadowsOnly);
-                cmd_buffer = shadow_rp.end().expect("Failed to end shadow render pass");
+                    let signature = casters.signature(world);
+                    if shadow_cache.update(shadow_idx, ent, view_proj, signature) {
+                        let view_data = ViewData {
+                            view_proj,
+                            view_pos: [tfm.position[0], tfm.position[1], tfm.position[2], 1.0],
+                        };
+                        let view_data = frame.write_transient(&view_data)?;
+                        frame.bind_transient(
+                            &spotlights[shadow_idx].view_data_desc_set,
+                            ViewData::BINDING,
+                            view_data,
+                        );
+
+                        let mut shadow_rp = frame.begin_render_pass(
+                            cmd_buffer,
+                            render_pass,
+                            &spotlights[shadow_idx].render_target,
+                            *extent,
+                            &clear_values,
+                        )?;
+
+                        shadow_rp
+                            .bind_graphics_pipeline(dummy_pipeline)
+                            .bind_shader_resource_group(
+                                0u32,
+                                &spotlights[shadow_idx].view_data_desc_set,
+                                dummy_pipeline,
+                            );
+                        casters.record(world, &mut shadow_rp, &mut draw_stats);
+                        cmd_buffer = shadow_rp.end()?;
+                    }
+                    packed.with_shadow_idx(shadow_idx as u32)
+                } else {
+                    log::warn!(
+                        "Too many shadowed spot lights, skipping the shadow of {:?}",
+                        ent
+                    );
+                    packed
+                }
             }
-            Light::Directional { color } => {
+            Light::Directional { .. } => {
                 let direction = tfm.rotation * Light::DEFAULT_FACING;
-                lighting_data.punctual_lights[lighting_data.num_lights as usize] = PackedLight {
-                    pos: [0.0, 0.0, 0.0, 0.0],
-                    dir_cutoff: [direction.x, direction.y, direction.z, 0.0],
-                    color_range: [color.r, color.g, color.b, 0.0],
-                    ..Default::default()
-                }
+                PackedLight::directional(direction.into_array(), color.into_array())
             }
-            Light::Point { color, range } => {
-                lighting_data.punctual_lights[lighting_data.num_lights as usize] = PackedLight {
-                    pos: [tfm.position.x, tfm.position.y, tfm.position.z, 1.0],
-                    dir_cutoff: [0.0, 0.0, 0.0, 0.0],
-                    color_range: [color.r, color.g, color.b, *range],
-                    ..Default::default()
-                };
+            Light::Point { range, .. } => {
+                PackedLight::point(tfm.position.into_array(), color.into_array(), *range)
             }
             Light::Ambient { .. } => unreachable!("Should have been handled already"),
-        }
+        };
+        lighting_data.punctual_lights[lighting_data.num_lights as usize] = match baked_channel {
+            Some(channel) => packed.with_baked_shadow(channel),
+            None => packed,
+        };
         lighting_data.num_lights += 1;
     }
 
-    let num_shadows = shadow_matrices.num_matrices;
-
-    frame
-        .update_uniform_blocking(
-            &frame_resources.pbr_resources.shadow_matrices_buffer,
-            &shadow_matrices,
-        )
-        .expect("Failed to update matrices for shadow coords");
-    frame
-        .update_uniform_blocking(&frame_resources.pbr_resources.light_buffer, &lighting_data)
-        .expect("Failed to update uniform for lighting data");
+    let shadow_matrices = frame.write_transient(&shadow_matrices)?;
+    let lighting_data = frame.write_transient(&lighting_data)?;
+    frame_resources.bind_lighting(frame, lighting_data, shadow_matrices);
 
-    // transistion unused images to depth stencil read optimal as this won't be done by the render pass
+    // transistion unused images to depth stencil read optimal as this won't be done by the render pass.
+    // The cached ones are already in that layout, from the render pass that rendered them.
     // TODO(perf): Don't allocate, store a vector for reuse
-    let mut barriers = Vec::with_capacity(super::NUM_SPOTLIGHT_SHADOW_MAPS - num_shadows as usize);
-    for i in num_shadows as usize..super::NUM_SPOTLIGHT_SHADOW_MAPS {
+    let mut barriers = Vec::with_capacity(super::NUM_SPOTLIGHT_SHADOW_MAPS);
+    for i in (0..super::NUM_SPOTLIGHT_SHADOW_MAPS).filter(|i| shadow_cache.is_empty(*i)) {
         let handle = spotlights[i].texture;
         let vk_image = frame
             .get_texture(&handle)
-            .expect("Failed to get shadow texture for mem barrier")
+            .ok_or_else(|| trekanten::RenderError::InvalidHandle(handle.id()))?
             .vk_image();
         let barrier = raw_vk::ImageMemoryBarrier {
             old_layout: raw_vk::ImageLayout::UNDEFINED,
@@ -312,13 +718,118 @@ pub fn light_and_shadow_pass(
         raw_vk::PipelineStageFlags::FRAGMENT_SHADER,
     );
 
-    cmd_buffer
+    Ok(cmd_buffer)
 }
 
 pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
-    builder.with(
-        RenderLightVolumes,
-        std::any::type_name::<RenderLightVolumes>(),
-        &[crate::render::debug_window::ApplySettings::ID],
-    )
+    builder
+        .with(
+            RenderLightVolumes,
+            std::any::type_name::<RenderLightVolumes>(),
+            &[crate::render::debug_window::ApplySettings::ID],
+        )
+        .with(AddLightLod, AddLightLod::ID, &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::BoundingBox;
+    use crate::render::uniform::cone_scale_offset;
+    use vek::approx::assert_abs_diff_eq;
+
+    #[test]
+    fn spot_cone_attenuation() {
+        let (inner, outer) = (0.2f32, 0.4f32);
+        let (scale, offset) = cone_scale_offset(inner.cos(), outer.cos());
+        let attenuation = |angle: f32| (angle.cos() * scale + offset).max(0.0).min(1.0);
+        assert_abs_diff_eq!(attenuation(0.0), 1.0);
+        assert_abs_diff_eq!(attenuation(inner), 1.0, epsilon = 0.0001);
+        assert_abs_diff_eq!(attenuation(outer), 0.0, epsilon = 0.0001);
+        assert!(attenuation(0.3) > 0.0 && attenuation(0.3) < 1.0);
+    }
+
+    #[test]
+    fn shadow_casters_are_culled_by_light_and_camera() {
+        let frustum = |pos: Vec3| {
+            let proj = perspective_vk(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+            Frustum::from_view_proj(proj * Mat4::translation_3d(-pos))
+        };
+        // Both look down -z, the light from above the camera
+        let light_pos = Vec3::new(0.0, 0.0, 20.0);
+        let culling = ShadowCasterCulling {
+            light_frustum: frustum(light_pos),
+            light_pos,
+            range: 50.0,
+            camera_frustum: Some(frustum(Vec3::zero())),
+        };
+        let bbox = |center: Vec3| {
+            BoundingVolume::Box(BoundingBox {
+                min: center - Vec3::broadcast(0.5),
+                max: center + Vec3::broadcast(0.5),
+            })
+        };
+
+        assert!(culling.casts_visible_shadow(&bbox(Vec3::new(0.0, 0.0, -5.0))));
+        // Behind the camera, but the shadow falls into view
+        assert!(culling.casts_visible_shadow(&bbox(Vec3::new(0.0, 0.0, 10.0))));
+        // Outside the light frustum
+        assert!(!culling.casts_visible_shadow(&bbox(Vec3::new(0.0, 0.0, 30.0))));
+        // Lit, but the shadow is cast away from the camera frustum
+        assert!(!culling.casts_visible_shadow(&bbox(Vec3::new(8.0, 0.0, 10.0))));
+    }
+
+    #[test]
+    fn shadow_maps_are_rendered_again_when_changed() {
+        let mut world = World::new();
+        let lights: Vec<Entity> = (0..super::super::NUM_SPOTLIGHT_SHADOW_MAPS + 1)
+            .map(|_| world.create_entity().build())
+            .collect();
+        let mut cache = ShadowMapCache::default();
+        let view_proj = [0.0; 16];
+
+        cache.begin_frame(true);
+        let slot = cache.slot(lights[0]).unwrap();
+        assert!(cache.update(slot, lights[0], view_proj, 1));
+
+        cache.begin_frame(true);
+        assert_eq!(cache.slot(lights[0]), Some(slot));
+        assert!(!cache.update(slot, lights[0], view_proj, 1));
+        assert_eq!((cache.rendered, cache.reused), (0, 1));
+        // A caster moved
+        cache.begin_frame(true);
+        assert!(cache.update(slot, lights[0], view_proj, 2));
+
+        // All the maps are used this frame, so the last light doesn't get one
+        cache.begin_frame(true);
+        for light in &lights[..lights.len() - 1] {
+            let slot = cache.slot(*light).unwrap();
+            cache.update(slot, *light, view_proj, 2);
+        }
+        assert_eq!(cache.slot(*lights.last().unwrap()), None);
+        assert!(!cache.is_empty(slot));
+
+        cache.begin_frame(false);
+        assert!(cache.is_empty(slot));
+    }
+
+    #[test]
+    fn intensity_units() {
+        let white = Rgb::new(1.0, 1.0, 1.0);
+        let point = Light::Point {
+            color: white,
+            intensity: LightIntensity::Lumens(4.0 * std::f32::consts::PI),
+            range: 1.0,
+        };
+        assert_abs_diff_eq!(point.packed_color(), white);
+
+        let spot = Light::Spot {
+            color: white,
+            intensity: LightIntensity::Candela(2.0),
+            inner_angle: 0.0,
+            outer_angle: 0.5,
+            range: 1.0,
+        };
+        assert_abs_diff_eq!(spot.packed_color(), white * 2.0);
+    }
 }
@@ -0,0 +1,359 @@
+//! Create, edit and delete lights while the app is running. Commands are queued in the
+//! LightCommands resource, from the ui or any system, and applied by LightCommandSys. The ranges
+//! and cones of the lights are shown with the light volumes, see RenderSettings.
+
+use crate::ecs::prelude::*;
+
+use imgui::*;
+
+use crate::camera::Camera;
+use crate::common::Name;
+use crate::editor::Inspect as _;
+use crate::graph::{sys as graph, Children, Parent};
+use crate::math::{Rgb, Transform, Vec3};
+use crate::render::light::{Light, LightIntensity};
+use crate::render::ui::UiFrame;
+
+#[derive(Debug, Clone)]
+pub enum LightCommand {
+    Spawn {
+        light: Light,
+        transform: Transform,
+        name: Name,
+    },
+    SetColor(Entity, Rgb),
+    /// Only for point and spot lights
+    SetIntensity(Entity, LightIntensity),
+    SetTransform(Entity, Transform),
+    /// Delete the light and everything below it
+    Delete(Entity),
+}
+
+/// The queue of light commands. Applied once per frame, in the order they were queued.
+#[derive(Debug, Default)]
+pub struct LightCommands {
+    queue: Vec<LightCommand>,
+}
+
+impl LightCommands {
+    pub fn spawn(&mut self, light: Light, transform: Transform) {
+        let name = Name::from(light.name());
+        self.spawn_named(light, transform, name);
+    }
+
+    pub fn spawn_named(&mut self, light: Light, transform: Transform, name: Name) {
+        self.queue.push(LightCommand::Spawn {
+            light,
+            transform,
+            name,
+        });
+    }
+
+    pub fn spawn_point(&mut self, position: Vec3) {
+        let light = Light::Point {
+            color: Rgb::new(1.0, 1.0, 1.0),
+            intensity: LightIntensity::default(),
+            range: 5.0,
+        };
+        let mut transform = Transform::identity();
+        transform.position = position;
+        self.spawn(light, transform);
+    }
+
+    /// The light points along -z of the transform, see Light::DEFAULT_FACING
+    pub fn spawn_spot(&mut self, transform: Transform) {
+        self.spawn(Light::default(), transform);
+    }
+
+    /// Only the rotation of the transform matters for the light
+    pub fn spawn_directional(&mut self, transform: Transform) {
+        let light = Light::Directional {
+            color: Rgb::new(1.0, 1.0, 1.0),
+        };
+        self.spawn(light, transform);
+    }
+
+    pub fn set_color(&mut self, light: Entity, color: Rgb) {
+        self.queue.push(LightCommand::SetColor(light, color));
+    }
+
+    pub fn set_intensity(&mut self, light: Entity, intensity: LightIntensity) {
+        self.queue
+            .push(LightCommand::SetIntensity(light, intensity));
+    }
+
+    pub fn set_transform(&mut self, light: Entity, transform: Transform) {
+        self.queue
+            .push(LightCommand::SetTransform(light, transform));
+    }
+
+    pub fn delete(&mut self, light: Entity) {
+        self.queue.push(LightCommand::Delete(light));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    fn drain(&mut self) -> Vec<LightCommand> {
+        std::mem::take(&mut self.queue)
+    }
+}
+
+pub struct LightCommandSys;
+
+impl LightCommandSys {
+    pub const ID: &'static str = "LightCommandSys";
+}
+
+#[derive(SystemData)]
+pub struct LightCommandData<'a> {
+    entities: Entities<'a>,
+    commands: Write<'a, LightCommands>,
+    lights: WriteStorage<'a, Light>,
+    transforms: WriteStorage<'a, Transform>,
+    names: WriteStorage<'a, Name>,
+    parents: ReadStorage<'a, Parent>,
+    children: WriteStorage<'a, Children>,
+}
+
+impl<'a> LightCommandData<'a> {
+    fn light_mut(&mut self, ent: Entity) -> Option<&mut Light> {
+        if !self.entities.is_alive(ent) {
+            log::warn!("Can't edit light {:?}, it has been deleted", ent);
+            return None;
+        }
+        let light = self.lights.get_mut(ent);
+        if light.is_none() {
+            log::warn!("{:?} is not a light", ent);
+        }
+        light
+    }
+
+    fn delete(&mut self, ent: Entity) {
+        if !self.entities.is_alive(ent) {
+            log::warn!("Can't delete light {:?}, it has already been deleted", ent);
+            return;
+        }
+
+        if let Some(Parent { parent }) = self.parents.get(ent) {
+            if let Some(siblings) = self.children.get_mut(*parent) {
+                siblings.children.retain(|c| *c != ent);
+            }
+        }
+
+        let mut nodes = Vec::new();
+        graph::breadth_first(&self.children, ent, |node| nodes.push(node));
+        for node in nodes {
+            self.entities.delete(node).expect("Failed to delete entity");
+        }
+    }
+}
+
+impl<'a> System<'a> for LightCommandSys {
+    type SystemData = LightCommandData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        for cmd in data.commands.drain() {
+            match cmd {
+                LightCommand::Spawn {
+                    light,
+                    transform,
+                    name,
+                } => {
+                    log::info!("Spawning {}", name.0);
+                    data.entities
+                        .build_entity()
+                        .with(light, &mut data.lights)
+                        .with(transform, &mut data.transforms)
+                        .with(name, &mut data.names)
+                        .build();
+                }
+                LightCommand::SetColor(ent, color) => {
+                    if let Some(light) = data.light_mut(ent) {
+                        *light.color_mut() = color;
+                    }
+                }
+                LightCommand::SetIntensity(ent, intensity) => {
+                    if let Some(light) = data.light_mut(ent) {
+                        match light.intensity_mut() {
+                            Some(cur) => *cur = intensity,
+                            None => log::warn!("{:?} has no intensity", ent),
+                        }
+                    }
+                }
+                LightCommand::SetTransform(ent, transform) => {
+                    if data.light_mut(ent).is_some() {
+                        data.transforms
+                            .insert(ent, transform)
+                            .expect("The light is alive");
+                    }
+                }
+                LightCommand::Delete(ent) => data.delete(ent),
+            }
+        }
+    }
+}
+
+/// Buttons that spawn lights where the camera is, and the color, intensity and transform of each
+/// light. All edits go through LightCommands.
+pub(crate) fn build_ui<'a>(world: &mut World, ui: &UiFrame<'a>, pos: [f32; 2]) -> [f32; 2] {
+    let size = [300.0, 200.0];
+
+    Window::new(im_str!("Lights"))
+        .position(pos, Condition::FirstUseEver)
+        .size(size, Condition::FirstUseEver)
+        .collapsed(true, Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let camera = crate::ecs::find_singleton_entity::<Camera>(world)
+                .and_then(|cam| world.read_storage::<Transform>().get(cam).copied())
+                .unwrap_or_else(Transform::identity);
+            let mut commands = world.write_resource::<LightCommands>();
+
+            ui.inner().text(im_str!("Add at the camera:"));
+            if ui.inner().button(im_str!("Point"), [0.0, 0.0]) {
+                commands.spawn_point(camera.position);
+            }
+            ui.inner().same_line(0.0);
+            if ui.inner().button(im_str!("Spot"), [0.0, 0.0]) {
+                commands.spawn_spot(camera);
+            }
+            ui.inner().same_line(0.0);
+            if ui.inner().button(im_str!("Directional"), [0.0, 0.0]) {
+                commands.spawn_directional(camera);
+            }
+
+            {
+                let mut settings = world.write_resource::<super::debug_window::RenderSettings>();
+                ui.inner().checkbox(
+                    im_str!("Show ranges and cones"),
+                    &mut settings.render_light_volumes,
+                );
+            }
+            ui.inner().separator();
+
+            let entities = world.entities();
+            let lights = world.read_storage::<Light>();
+            let transforms = world.read_storage::<Transform>();
+            let names = world.read_storage::<Name>();
+            for (ent, light, tfm) in (&entities, &lights, transforms.maybe()).join() {
+                let id_token = ui.inner().push_id(ent.id() as i32);
+                let label = match names.get(ent) {
+                    Some(name) => im_str!("{}", name.0),
+                    None => im_str!("{} {}", light.name(), ent.id()),
+                };
+                TreeNode::new(&label).build(ui.inner(), || {
+                    let mut edited = light.clone();
+                    edited.color_mut().inspect_mut(ui, "color");
+                    if let Some(intensity) = edited.intensity_mut() {
+                        intensity.inspect_mut(ui, "intensity");
+                    }
+                    let mut transform = tfm.copied().unwrap_or_else(Transform::identity);
+                    transform.inspect_mut(ui, "transform");
+
+                    if edited.color() != light.color() {
+                        commands.set_color(ent, edited.color());
+                    }
+                    if let Some(intensity) = edited.intensity() {
+                        if Some(intensity) != light.intensity() {
+                            commands.set_intensity(ent, intensity);
+                        }
+                    }
+                    if Some(&transform) != tfm {
+                        commands.set_transform(ent, transform);
+                    }
+                    if ui.inner().button(im_str!("Delete"), [0.0, 0.0]) {
+                        commands.delete(ent);
+                    }
+                });
+                id_token.pop(ui.inner());
+            }
+        });
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use specs::RunNow;
+
+    #[test]
+    fn commands_spawn_edit_and_delete_lights() {
+        let mut world = World::new();
+        crate::ecs::meta::register_all_components(&mut world);
+        world.insert(LightCommands::default());
+
+        {
+            let mut commands = world.write_resource::<LightCommands>();
+            commands.spawn_point(Vec3::new(1.0, 2.0, 3.0));
+            commands.spawn_directional(Transform::identity());
+        }
+        LightCommandSys.run_now(&world);
+        world.maintain();
+        let point = (&world.entities(), &world.read_storage::<Light>())
+            .join()
+            .find(|(_, light)| matches!(light, Light::Point { .. }))
+            .map(|(ent, _)| ent)
+            .unwrap();
+        let directional = (&world.entities(), &world.read_storage::<Light>())
+            .join()
+            .find(|(_, light)| matches!(light, Light::Directional { .. }))
+            .map(|(ent, _)| ent)
+            .unwrap();
+        assert_eq!(
+            world
+                .read_storage::<Transform>()
+                .get(point)
+                .unwrap()
+                .position,
+            Vec3::new(1.0, 2.0, 3.0)
+        );
+        assert_eq!(
+            world.read_storage::<Name>().get(point).unwrap().0,
+            "Point light"
+        );
+
+        {
+            let mut commands = world.write_resource::<LightCommands>();
+            commands.set_color(point, Rgb::new(1.0, 0.0, 0.0));
+            commands.set_intensity(point, LightIntensity::Lumens(800.0));
+            // Ignored, directional lights have no intensity
+            commands.set_intensity(directional, LightIntensity::Lumens(800.0));
+            commands.set_transform(point, Transform::pos(0.0, 1.0, 0.0));
+        }
+        LightCommandSys.run_now(&world);
+        assert_eq!(
+            world.read_storage::<Light>().get(point),
+            Some(&Light::Point {
+                color: Rgb::new(1.0, 0.0, 0.0),
+                intensity: LightIntensity::Lumens(800.0),
+                range: 5.0,
+            })
+        );
+        assert_eq!(
+            world.read_storage::<Light>().get(directional),
+            Some(&Light::Directional {
+                color: Rgb::new(1.0, 1.0, 1.0),
+            })
+        );
+        assert_eq!(
+            world
+                .read_storage::<Transform>()
+                .get(point)
+                .unwrap()
+                .position,
+            Vec3::new(0.0, 1.0, 0.0)
+        );
+
+        let child = world.create_entity().build();
+        crate::graph::world::add_edge(&mut world, point, child);
+        world.write_resource::<LightCommands>().delete(point);
+        LightCommandSys.run_now(&world);
+        world.maintain();
+        assert!(!world.is_alive(point));
+        assert!(!world.is_alive(child));
+        assert!(world.is_alive(directional));
+        assert!(world.read_resource::<LightCommands>().is_empty());
+    }
+}
@@ -0,0 +1,209 @@
+//! Mesh levels of detail. An entity with a LodGroup shows one of its levels at a time, chosen by the
+//! distance to the camera, and the other levels and everything below them are Hidden. glTF nodes
+//! with children named `<name>_LOD0`, `<name>_LOD1`, ... get a LodGroup when they are loaded.
+//!
+//! The distances are scaled by the LOD bias of the render settings, so that a higher bias switches
+//! to the lower levels closer to the camera. With `r.lod.auto`, the bias is raised while the gpu
+//! time of the frames is over the budget and lowered again when there is room.
+
+use ramneryd_derive::Inspect;
+
+use crate::ecs::prelude::*;
+use crate::graph::sys as graph;
+use crate::math::ModelMatrix;
+
+use super::debug_window::RenderSettings;
+use super::Hidden;
+
+/// Added to or removed from the bias each frame that the gpu time is over or well under budget
+const BIAS_STEP: f32 = 0.05;
+/// The bias is lowered when the gpu time is below this fraction of the budget, so that it doesn't
+/// go back and forth around the budget
+const BUDGET_HEADROOM: f32 = 0.8;
+/// For the levels that don't have a distance, each level is used twice as far as the previous one
+pub const DEFAULT_FIRST_DISTANCE: f32 = 10.0;
+
+#[derive(Debug, Clone, Component)]
+#[component(inspect)]
+pub struct LodGroup {
+    /// Most detailed first
+    pub levels: Vec<Entity>,
+    /// Level i is used while the biased distance to the camera is below distances[i]. The last
+    /// level is used beyond all of them.
+    pub distances: Vec<f32>,
+}
+
+impl LodGroup {
+    pub fn new(levels: Vec<Entity>) -> Self {
+        let distances = (0..levels.len().saturating_sub(1))
+            .map(|i| DEFAULT_FIRST_DISTANCE * 2.0f32.powi(i as i32))
+            .collect();
+        Self { levels, distances }
+    }
+
+    pub fn select_level(&self, distance: f32, bias: f32) -> usize {
+        let last = self.levels.len().saturating_sub(1);
+        let distance = distance * (1.0 + bias.max(0.0));
+        self.distances
+            .iter()
+            .position(|&d| distance < d)
+            .unwrap_or(last)
+            .min(last)
+    }
+}
+
+/// Overrides the level selection of a LodGroup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Inspect)]
+#[component(inspect)]
+pub enum LodOverride {
+    /// Always show this level
+    Level(u32),
+    /// Select by distance but ignore the LOD bias, e.g. for the player character
+    IgnoreBias,
+}
+
+/// The level that the LodGroup currently shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+#[component(inspect)]
+pub struct ActiveLod(pub u32);
+
+/// The total of the gpu timers of the most recent frame that the gpu has finished
+fn gpu_frame_ms(stats: &trekanten::FrameStats) -> Option<f32> {
+    let timings = stats.gpu_timings();
+    if timings.is_empty() {
+        return None;
+    }
+    let total: std::time::Duration = timings.iter().map(|t| t.duration).sum();
+    Some(total.as_secs_f32() * 1000.0)
+}
+
+fn adjust_bias(bias: f32, gpu_ms: f32, budget_ms: f32, max_bias: f32) -> f32 {
+    let bias = if gpu_ms > budget_ms {
+        bias + BIAS_STEP
+    } else if gpu_ms < budget_ms * BUDGET_HEADROOM {
+        bias - BIAS_STEP
+    } else {
+        bias
+    };
+    bias.max(0.0).min(max_bias.max(0.0))
+}
+
+/// Updates the LOD bias and shows the selected level of each LodGroup. Levels are only hidden and
+/// shown when the selection changes.
+pub fn update(world: &mut World) {
+    let gpu_ms = world
+        .try_fetch::<trekanten::FrameStats>()
+        .and_then(|stats| gpu_frame_ms(&stats));
+    let bias = {
+        let mut settings = world.write_resource::<RenderSettings>();
+        if let (true, Some(gpu_ms)) = (settings.lod_auto_bias, gpu_ms) {
+            settings.lod_bias = adjust_bias(
+                settings.lod_bias,
+                gpu_ms,
+                settings.lod_gpu_budget_ms,
+                settings.lod_max_bias,
+            );
+        }
+        settings.lod_bias
+    };
+    let camera_pos = super::camera_pos(world);
+
+    let entities = world.entities();
+    let groups = world.read_storage::<LodGroup>();
+    let overrides = world.read_storage::<LodOverride>();
+    let model_matrices = world.read_storage::<ModelMatrix>();
+    let children = world.read_storage::<graph::Children>();
+    let mut hidden = world.write_storage::<Hidden>();
+    let mut active = world.write_storage::<ActiveLod>();
+
+    for (ent, group, mtx, lod_override) in
+        (&entities, &groups, &model_matrices, overrides.maybe()).join()
+    {
+        if group.levels.is_empty() {
+            continue;
+        }
+        // Hidden from the editor, the levels are shown again when the group is
+        if hidden.contains(ent) {
+            active.remove(ent);
+            continue;
+        }
+
+        let distance = (mtx.0.cols.w.xyz() - camera_pos).magnitude();
+        let level = match lod_override {
+            Some(LodOverride::Level(level)) => (*level as usize).min(group.levels.len() - 1),
+            Some(LodOverride::IgnoreBias) => group.select_level(distance, 0.0),
+            None => group.select_level(distance, bias),
+        };
+        if active.get(ent) == Some(&ActiveLod(level as u32)) {
+            continue;
+        }
+
+        for (i, &level_ent) in group.levels.iter().enumerate() {
+            if !entities.is_alive(level_ent) {
+                continue;
+            }
+            graph::breadth_first(&children, level_ent, |node| {
+                if i == level {
+                    hidden.remove(node);
+                } else {
+                    hidden
+                        .insert(node, Hidden)
+                        .expect("Children of alive entities are alive");
+                }
+            });
+        }
+        active
+            .insert(ent, ActiveLod(level as u32))
+            .expect("Joined entities are alive");
+    }
+}
+
+/// The active level of the LodGroup that each entity below the shown levels belongs to
+pub(super) fn mesh_levels(world: &World) -> std::collections::HashMap<Entity, u32> {
+    let groups = world.read_storage::<LodGroup>();
+    let active = world.read_storage::<ActiveLod>();
+    let children = world.read_storage::<graph::Children>();
+    let mut levels = std::collections::HashMap::new();
+    for (group, active) in (&groups, &active).join() {
+        if let Some(&level_ent) = group.levels.get(active.0 as usize) {
+            graph::breadth_first(&children, level_ent, |node| {
+                levels.insert(node, active.0);
+            });
+        }
+    }
+    levels
+}
+
+/// The index of the level in the name, e.g. 1 for `tree_LOD1`
+pub fn level_from_name(name: &str) -> Option<usize> {
+    let idx = name.rfind("_LOD")?;
+    name[idx + "_LOD".len()..].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bias_selects_lower_levels_closer() {
+        let mut world = World::new();
+        let levels = (0..3).map(|_| world.create_entity().build()).collect();
+        let group = LodGroup::new(levels);
+        assert_eq!(group.distances, vec![10.0, 20.0]);
+        assert_eq!(group.select_level(5.0, 0.0), 0);
+        assert_eq!(group.select_level(15.0, 0.0), 1);
+        assert_eq!(group.select_level(100.0, 0.0), 2);
+        assert_eq!(group.select_level(5.0, 1.5), 1);
+    }
+
+    #[test]
+    fn bias_follows_gpu_budget() {
+        assert_eq!(adjust_bias(0.0, 20.0, 16.0, 4.0), BIAS_STEP);
+        assert_eq!(adjust_bias(1.0, 15.0, 16.0, 4.0), 1.0);
+        assert_eq!(adjust_bias(1.0, 5.0, 16.0, 4.0), 1.0 - BIAS_STEP);
+        assert_eq!(adjust_bias(0.0, 5.0, 16.0, 4.0), 0.0);
+        assert_eq!(adjust_bias(4.0, 20.0, 16.0, 4.0), 4.0);
+        assert_eq!(level_from_name("tree_LOD2"), Some(2));
+        assert_eq!(level_from_name("tree"), None);
+    }
+}
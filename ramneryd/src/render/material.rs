@@ -1,8 +1,14 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
 use trekanten::texture::Texture;
+use trekanten::util::Format;
 use trekanten::{mem::UniformBuffer, texture::TextureDescriptor};
-use trekanten::{BufferHandle, Handle};
+use trekanten::{BufferHandle, Frame, Handle};
 
-use crate::math::{Rgba, Vec4};
+use crate::math::{Rgb, Rgba, Vec4};
+use crate::render::uniform::PBRMaterialData;
 use crate::render::Pending;
 
 use crate::ecs::prelude::*;
@@ -22,7 +28,22 @@ pub struct TextureUse2 {
     pub coord_set: u32,
 }
 
-#[derive(Debug, Component)]
+impl TextureUse2 {
+    /// The file the texture is loaded from, if any
+    pub fn path(&self) -> Option<&Path> {
+        match &self.desc {
+            TextureDescriptor::File { path, .. } | TextureDescriptor::FileMipLevel { path, .. } => {
+                Some(path)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The index of refraction of glass, which is also the default of glTF
+pub const DEFAULT_IOR: f32 = 1.5;
+
+#[derive(Debug, Clone, Component)]
 #[component(inspect)]
 pub struct PhysicallyBased {
     pub base_color_factor: Vec4,
@@ -32,17 +53,226 @@ pub struct PhysicallyBased {
     pub normal_map: Option<TextureUse2>,
     pub base_color_texture: Option<TextureUse2>,
     pub metallic_roughness_texture: Option<TextureUse2>,
+    /// Linear light that the surface emits. Multiplied with the emissive texture, if there is one.
+    pub emissive_factor: Rgb,
+    pub emissive_texture: Option<TextureUse2>,
+    /// How much of the ambient light the occlusion texture blocks, in [0, 1]. Only the red channel
+    /// of the texture is used.
+    pub occlusion_strength: f32,
+    pub occlusion_texture: Option<TextureUse2>,
+    /// How much of the light that is not reflected passes through the surface, in [0, 1], like
+    /// KHR_materials_transmission. What is behind the surface is taken from the scene color after
+    /// the opaque part of the scene is drawn, see PostProcessing::transmission_passes. Transmissive
+    /// surfaces are not seen through each other.
+    pub transmission: f32,
+    /// Index of refraction, offsets what is seen through transmissive surfaces
+    pub ior: f32,
     // TODO: Should this really be here?
     pub has_vertex_colors: bool,
 }
 
+/// One of the textures of a PhysicallyBased material
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureSlot {
+    BaseColor,
+    MetallicRoughness,
+    NormalMap,
+    Emissive,
+    Occlusion,
+}
+
+impl TextureSlot {
+    pub const ALL: [Self; 5] = [
+        Self::BaseColor,
+        Self::MetallicRoughness,
+        Self::NormalMap,
+        Self::Emissive,
+        Self::Occlusion,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::BaseColor => "Base color",
+            Self::MetallicRoughness => "Metallic roughness",
+            Self::NormalMap => "Normal map",
+            Self::Emissive => "Emissive",
+            Self::Occlusion => "Occlusion",
+        }
+    }
+
+    /// The same as the glTF loader uses, colors are sRGB and data is linear
+    pub fn format(self) -> Format {
+        match self {
+            Self::BaseColor | Self::Emissive => Format::RGBA_SRGB,
+            Self::MetallicRoughness | Self::NormalMap | Self::Occlusion => Format::RGBA_UNORM,
+        }
+    }
+}
+
+impl PhysicallyBased {
+    pub fn texture(&self, slot: TextureSlot) -> Option<&TextureUse2> {
+        match slot {
+            TextureSlot::BaseColor => self.base_color_texture.as_ref(),
+            TextureSlot::MetallicRoughness => self.metallic_roughness_texture.as_ref(),
+            TextureSlot::NormalMap => self.normal_map.as_ref(),
+            TextureSlot::Emissive => self.emissive_texture.as_ref(),
+            TextureSlot::Occlusion => self.occlusion_texture.as_ref(),
+        }
+    }
+
+    pub fn texture_mut(&mut self, slot: TextureSlot) -> &mut Option<TextureUse2> {
+        match slot {
+            TextureSlot::BaseColor => &mut self.base_color_texture,
+            TextureSlot::MetallicRoughness => &mut self.metallic_roughness_texture,
+            TextureSlot::NormalMap => &mut self.normal_map,
+            TextureSlot::Emissive => &mut self.emissive_texture,
+            TextureSlot::Occlusion => &mut self.occlusion_texture,
+        }
+    }
+
+    pub fn textures(&self) -> impl Iterator<Item = &TextureUse2> {
+        self.normal_map
+            .iter()
+            .chain(self.base_color_texture.iter())
+            .chain(self.metallic_roughness_texture.iter())
+            .chain(self.emissive_texture.iter())
+            .chain(self.occlusion_texture.iter())
+    }
+
+    /// Drawn after the rest of the scene, which it is seen through
+    pub fn is_transmissive(&self) -> bool {
+        self.transmission > 0.0
+    }
+
+    /// If the materials have the same factors and textures, so that one can be used for both.
+    /// Textures that are not loaded from files are never the same.
+    pub fn same_parameters(&self, other: &Self) -> bool {
+        let same_texture = |a: &Option<TextureUse2>, b: &Option<TextureUse2>| match (a, b) {
+            (None, None) => true,
+            (Some(a), Some(b)) => {
+                let key = texture_key(&a.desc);
+                a.coord_set == b.coord_set && key.is_some() && key == texture_key(&b.desc)
+            }
+            _ => false,
+        };
+
+        self.base_color_factor == other.base_color_factor
+            && self.metallic_factor == other.metallic_factor
+            && self.roughness_factor == other.roughness_factor
+            && self.normal_scale == other.normal_scale
+            && self.emissive_factor == other.emissive_factor
+            && self.occlusion_strength == other.occlusion_strength
+            && self.transmission == other.transmission
+            && self.ior == other.ior
+            && self.has_vertex_colors == other.has_vertex_colors
+            && same_texture(&self.normal_map, &other.normal_map)
+            && same_texture(&self.base_color_texture, &other.base_color_texture)
+            && same_texture(
+                &self.metallic_roughness_texture,
+                &other.metallic_roughness_texture,
+            )
+            && same_texture(&self.emissive_texture, &other.emissive_texture)
+            && same_texture(&self.occlusion_texture, &other.occlusion_texture)
+    }
+}
+
+/// The file, format and mip level of a texture that is loaded from a file
+type TextureKey = (PathBuf, Format, Option<u32>);
+
+fn texture_key(desc: &TextureDescriptor) -> Option<TextureKey> {
+    match desc {
+        TextureDescriptor::File { path, format, .. } => Some((path.clone(), *format, None)),
+        TextureDescriptor::FileMipLevel {
+            path,
+            format,
+            level,
+            ..
+        } => Some((path.clone(), *format, Some(*level))),
+        _ => None,
+    }
+}
+
+/// The textures that are loaded from files, so that the materials that use the same file in the
+/// same format share one texture instead of uploading it again
+#[derive(Default)]
+pub(super) struct TextureCache {
+    pending: HashMap<TextureKey, Handle<Async<Texture>>>,
+    loaded: HashMap<TextureKey, Handle<Texture>>,
+}
+
+impl TextureCache {
+    /// The texture of the descriptor, if it has been loaded or is being loaded
+    pub(super) fn get(
+        &self,
+        desc: &TextureDescriptor,
+    ) -> Option<Pending<Handle<Async<Texture>>, Handle<Texture>>> {
+        let key = texture_key(desc)?;
+        match self.loaded.get(&key) {
+            Some(h) => Some(Pending::Available(*h)),
+            None => self.pending.get(&key).copied().map(Pending::Pending),
+        }
+    }
+
+    pub(super) fn insert_pending(&mut self, desc: &TextureDescriptor, h: Handle<Async<Texture>>) {
+        if let Some(key) = texture_key(desc) {
+            self.pending.insert(key, h);
+        }
+    }
+
+    /// The loader is done with the texture
+    pub(super) fn resolve(&mut self, old: Handle<Async<Texture>>, new: Handle<Texture>) {
+        let keys: Vec<TextureKey> = self
+            .pending
+            .iter()
+            .filter(|(_, h)| **h == old)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            self.pending.remove(&key);
+            self.loaded.insert(key, new);
+        }
+    }
+
+    /// The texture was destroyed
+    pub(super) fn remove(&mut self, h: Handle<Texture>) {
+        self.loaded.retain(|_, loaded| *loaded != h);
+    }
+
+    /// The file changed, so the next load of it reads it again
+    pub(super) fn invalidate(&mut self, path: &Path) {
+        self.pending.retain(|(p, _, _), _| p != path);
+        self.loaded.retain(|(p, _, _), _| p != path);
+    }
+}
+
+/// The source files of these textures have changed and they should be uploaded again
+#[derive(Debug, Default, Component)]
+pub struct ReloadTextures(pub Vec<PathBuf>);
+
 #[derive(Debug, Clone, Inspect, PartialEq, Eq)]
 pub struct TextureUse<T> {
     pub handle: Handle<T>,
     pub coord_set: u32,
 }
 
-#[derive(Debug, Component)]
+/// A material that several entities use. They share its gpu resources, which are destroyed when the
+/// last of them is gone. The entity gets a PhysicallyBased with the same values, if it does not have
+/// one. Remove this, as well as the GpuMaterial, to change the material of only one of them.
+#[derive(Debug, Clone, Component)]
+pub struct SharedMaterial(pub Arc<PhysicallyBased>);
+
+impl SharedMaterial {
+    pub fn new(material: PhysicallyBased) -> Self {
+        Self(Arc::new(material))
+    }
+
+    /// The same for all entities that share the material
+    pub(super) fn key(&self) -> usize {
+        &*self.0 as *const PhysicallyBased as usize
+    }
+}
+
+#[derive(Debug, Clone, Component)]
 #[component(inspect)]
 pub enum GpuMaterial {
     Unlit {
@@ -53,11 +283,13 @@ pub enum GpuMaterial {
         normal_map: Option<TextureUse<Texture>>,
         base_color_texture: Option<TextureUse<Texture>>,
         metallic_roughness_texture: Option<TextureUse<Texture>>,
+        emissive_texture: Option<TextureUse<Texture>>,
+        occlusion_texture: Option<TextureUse<Texture>>,
         has_vertex_colors: bool,
     },
 }
 
-#[derive(Debug, Component)]
+#[derive(Debug, Clone, Component)]
 #[component(inspect)]
 pub enum PendingMaterial {
     Unlit {
@@ -69,10 +301,41 @@ pub enum PendingMaterial {
         base_color_texture: Option<Pending<TextureUse<Async<Texture>>, TextureUse<Texture>>>,
         metallic_roughness_texture:
             Option<Pending<TextureUse<Async<Texture>>, TextureUse<Texture>>>,
+        emissive_texture: Option<Pending<TextureUse<Async<Texture>>, TextureUse<Texture>>>,
+        occlusion_texture: Option<Pending<TextureUse<Async<Texture>>, TextureUse<Texture>>>,
         has_vertex_colors: bool,
     },
 }
 
+impl From<&GpuMaterial> for PendingMaterial {
+    fn from(material: &GpuMaterial) -> Self {
+        match material {
+            GpuMaterial::Unlit { color_uniform } => PendingMaterial::Unlit {
+                color_uniform: Pending::Available(*color_uniform),
+            },
+            GpuMaterial::PBR {
+                material_uniforms,
+                normal_map,
+                base_color_texture,
+                metallic_roughness_texture,
+                emissive_texture,
+                occlusion_texture,
+                has_vertex_colors,
+            } => PendingMaterial::PBR {
+                material_uniforms: Pending::Available(*material_uniforms),
+                normal_map: normal_map.clone().map(Pending::Available),
+                base_color_texture: base_color_texture.clone().map(Pending::Available),
+                metallic_roughness_texture: metallic_roughness_texture
+                    .clone()
+                    .map(Pending::Available),
+                emissive_texture: emissive_texture.clone().map(Pending::Available),
+                occlusion_texture: occlusion_texture.clone().map(Pending::Available),
+                has_vertex_colors: *has_vertex_colors,
+            },
+        }
+    }
+}
+
 impl PendingMaterial {
     pub fn is_done(&self) -> bool {
         match self {
@@ -84,6 +347,8 @@ impl PendingMaterial {
                 normal_map,
                 base_color_texture,
                 metallic_roughness_texture,
+                emissive_texture,
+                occlusion_texture,
                 ..
             } => {
                 let is_done = |t: &Option<
@@ -99,6 +364,8 @@ impl PendingMaterial {
                 is_done(normal_map)
                     && is_done(base_color_texture)
                     && is_done(metallic_roughness_texture)
+                    && is_done(emissive_texture)
+                    && is_done(occlusion_texture)
             }
             _ => false,
         }
@@ -114,6 +381,8 @@ impl PendingMaterial {
                 normal_map,
                 base_color_texture,
                 metallic_roughness_texture,
+                emissive_texture,
+                occlusion_texture,
                 has_vertex_colors,
             } => {
                 let map_tex = |pend_tex: Pending<
@@ -131,11 +400,15 @@ impl PendingMaterial {
                 let normal_map = normal_map.and_then(map_tex);
                 let base_color_texture = base_color_texture.and_then(map_tex);
                 let metallic_roughness_texture = metallic_roughness_texture.and_then(map_tex);
+                let emissive_texture = emissive_texture.and_then(map_tex);
+                let occlusion_texture = occlusion_texture.and_then(map_tex);
                 GpuMaterial::PBR {
                     material_uniforms,
                     normal_map,
                     base_color_texture,
                     metallic_roughness_texture,
+                    emissive_texture,
+                    occlusion_texture,
                     has_vertex_colors,
                 }
             }
@@ -143,3 +416,473 @@ impl PendingMaterial {
         }
     }
 }
+
+impl From<&PhysicallyBased> for PBRMaterialData {
+    fn from(material: &PhysicallyBased) -> Self {
+        Self {
+            base_color_factor: material.base_color_factor.into_array(),
+            metallic_factor: material.metallic_factor,
+            roughness_factor: material.roughness_factor,
+            normal_scale: material.normal_scale,
+            occlusion_strength: material.occlusion_strength,
+            emissive_factor: [
+                material.emissive_factor.r,
+                material.emissive_factor.g,
+                material.emissive_factor.b,
+                0.0,
+            ],
+            transmission_ior: [material.transmission, material.ior, 0.0, 0.0],
+            texture_indices: [[0; 4]; 2],
+        }
+    }
+}
+
+/// The material uniforms that were written for each entity, so that the ones whose PhysicallyBased
+/// component is edited, e.g. in the inspector, are written again. The uniform buffers have a copy
+/// per frame in flight, so a change is written in that many frames.
+#[derive(Default)]
+pub(super) struct MaterialUniforms {
+    written: HashMap<Entity, (PBRMaterialData, usize)>,
+}
+
+impl MaterialUniforms {
+    /// Returns true if the data has to be written this frame. `uploaded` is what the buffer of a new
+    /// entity was created with.
+    fn needs_write(
+        &mut self,
+        ent: Entity,
+        uploaded: PBRMaterialData,
+        data: PBRMaterialData,
+    ) -> bool {
+        let (written, frames_left) = self.written.entry(ent).or_insert((uploaded, 0));
+        if *written != data {
+            *written = data;
+            *frames_left = trekanten::MAX_FRAMES_IN_FLIGHT;
+        }
+
+        if *frames_left > 0 {
+            *frames_left -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Write the uniforms of the PBR materials that changed since they were uploaded, or whose textures
+/// were added to the bindless array
+#[profiling::function]
+pub(super) fn update_uniforms(world: &World, frame: &mut Frame) {
+    let entities = world.entities();
+    let materials = world.read_storage::<PhysicallyBased>();
+    let gpu_materials = world.read_storage::<GpuMaterial>();
+    let renderables = world.read_storage::<super::RenderableMaterial>();
+    let mut uniforms = world.write_resource::<MaterialUniforms>();
+
+    uniforms.written.retain(|ent, _| entities.is_alive(*ent));
+    for (ent, material, gpu_material) in (&entities, &materials, &gpu_materials).join() {
+        if let GpuMaterial::PBR {
+            material_uniforms, ..
+        } = gpu_material
+        {
+            let uploaded = PBRMaterialData::from(material);
+            let mut data = uploaded;
+            if let Some(super::RenderableMaterial::PBR {
+                bindless_textures: Some(bindless),
+                ..
+            }) = renderables.get(ent)
+            {
+                data.texture_indices = bindless.indices();
+            }
+            if uniforms.needs_write(ent, uploaded, data) {
+                frame
+                    .update_uniform_blocking(material_uniforms, &data)
+                    .expect("Failed to update material uniforms");
+            }
+        }
+    }
+}
+
+/// Index of a material in the MaterialLibrary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MaterialId(usize);
+
+#[derive(Debug, Clone)]
+pub struct LibraryMaterial {
+    pub name: String,
+    pub material: PhysicallyBased,
+    /// The file and index of the material it was imported from. None for materials that were
+    /// created in the editor.
+    source: Option<(PathBuf, Option<usize>)>,
+    // Shared by the entities that the material was assigned to, until it is changed
+    shared: Option<SharedMaterial>,
+}
+
+/// All imported materials, and the ones that were created from them in the editor. Materials are
+/// never removed so ids stay valid.
+#[derive(Debug, Default)]
+pub struct MaterialLibrary {
+    materials: Vec<LibraryMaterial>,
+}
+
+impl MaterialLibrary {
+    /// Adds a material from a file. If the same material was imported before, e.g. when the file
+    /// is reloaded, that entry is updated instead.
+    pub fn import(
+        &mut self,
+        name: String,
+        material: PhysicallyBased,
+        path: &Path,
+        index: Option<usize>,
+    ) -> MaterialId {
+        let source = Some((path.to_path_buf(), index));
+        let entry = LibraryMaterial {
+            name,
+            material,
+            source,
+            shared: None,
+        };
+        match self.materials.iter().position(|m| m.source == entry.source) {
+            Some(idx) => {
+                self.materials[idx] = entry;
+                MaterialId(idx)
+            }
+            None => {
+                self.materials.push(entry);
+                MaterialId(self.materials.len() - 1)
+            }
+        }
+    }
+
+    /// Adds a copy of the material, that is not tied to the file it was imported from
+    pub fn duplicate(&mut self, id: MaterialId) -> Option<MaterialId> {
+        let src = self.get(id)?;
+        let copy = LibraryMaterial {
+            name: format!("{} copy", src.name),
+            material: src.material.clone(),
+            source: None,
+            shared: None,
+        };
+        self.materials.push(copy);
+        Some(MaterialId(self.materials.len() - 1))
+    }
+
+    pub fn get(&self, id: MaterialId) -> Option<&LibraryMaterial> {
+        self.materials.get(id.0)
+    }
+
+    /// The entities that the material is assigned to after this no longer share it with the
+    /// previous ones, as it may change
+    pub fn get_mut(&mut self, id: MaterialId) -> Option<&mut LibraryMaterial> {
+        let entry = self.materials.get_mut(id.0)?;
+        entry.shared = None;
+        Some(entry)
+    }
+
+    /// The material, for the entities that it is assigned to. They share its gpu resources.
+    pub fn shared(&mut self, id: MaterialId) -> Option<SharedMaterial> {
+        let entry = self.materials.get_mut(id.0)?;
+        let material = &entry.material;
+        Some(
+            entry
+                .shared
+                .get_or_insert_with(|| SharedMaterial::new(material.clone()))
+                .clone(),
+        )
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (MaterialId, &LibraryMaterial)> {
+        self.materials
+            .iter()
+            .enumerate()
+            .map(|(i, m)| (MaterialId(i), m))
+    }
+}
+
+/// The material of the entity is this one from the MaterialLibrary
+#[derive(Debug, Clone, Copy, Component)]
+pub struct UsesMaterial(pub MaterialId);
+
+/// Replaces the material of the entity with one from the library. All GPU resources of the
+/// previous material are dropped and created again for the new one, the same as for a new entity.
+pub fn assign(world: &World, ent: Entity, id: MaterialId) {
+    let shared = match world.write_resource::<MaterialLibrary>().shared(id) {
+        Some(shared) => shared,
+        None => {
+            log::error!("No material with id {:?} in the library", id);
+            return;
+        }
+    };
+
+    let material = (*shared.0).clone();
+    let mut dependencies = world.write_resource::<crate::asset::dependencies::AssetDependencies>();
+    for path in material.textures().filter_map(TextureUse2::path) {
+        dependencies.add(
+            path,
+            crate::asset::dependencies::DependencyKind::Texture,
+            ent,
+        );
+    }
+
+    // The gpu resources of the previous material are destroyed by render::resource_tracking
+    world.write_storage::<Unlit>().remove(ent);
+    world.write_storage::<GpuMaterial>().remove(ent);
+    world.write_storage::<PendingMaterial>().remove(ent);
+    world
+        .write_storage::<super::RenderableMaterial>()
+        .remove(ent);
+    world
+        .write_storage::<super::streaming::StreamedTextures>()
+        .remove(ent);
+    world
+        .write_storage::<PhysicallyBased>()
+        .insert(ent, material)
+        .expect("Failed to insert material");
+    world
+        .write_storage::<SharedMaterial>()
+        .insert(ent, shared)
+        .expect("Failed to insert material");
+    world
+        .write_storage::<UsesMaterial>()
+        .insert(ent, UsesMaterial(id))
+        .expect("Failed to insert material");
+}
+
+/// Replaces the texture in the slot of the entity's material with one loaded from the file. Only
+/// the new texture is uploaded, and the descriptor set is recreated once it is done, the same as
+/// when a texture file changes on disk. The entity no longer shares the material with the others
+/// that used it. Returns false if the material can't be changed yet.
+pub fn swap_texture(world: &World, ent: Entity, slot: TextureSlot, path: PathBuf) -> bool {
+    let mut materials = world.write_storage::<PhysicallyBased>();
+    let material = match materials.get_mut(ent) {
+        Some(material) => material,
+        None => {
+            log::error!("Can't swap the texture of {:?}, it has no material", ent);
+            return false;
+        }
+    };
+    let has_gpu_material = world.read_storage::<GpuMaterial>().contains(ent);
+    if !has_gpu_material && world.read_storage::<PendingMaterial>().contains(ent) {
+        log::warn!("The material of {:?} is still being uploaded", ent);
+        return false;
+    }
+
+    log::info!(
+        "Using {} as the {} texture of {:?}",
+        path.display(),
+        slot.name(),
+        ent
+    );
+    let tex = material.texture_mut(slot);
+    let had_texture = tex.is_some();
+    let coord_set = tex.as_ref().map(|t| t.coord_set).unwrap_or(0);
+    *tex = Some(TextureUse2 {
+        desc: TextureDescriptor::file(
+            path.clone(),
+            slot.format(),
+            trekanten::texture::MipMaps::None,
+        ),
+        coord_set,
+    });
+
+    world
+        .write_resource::<crate::asset::dependencies::AssetDependencies>()
+        .add(
+            &path,
+            crate::asset::dependencies::DependencyKind::Texture,
+            ent,
+        );
+    world.write_storage::<SharedMaterial>().remove(ent);
+    world.write_storage::<UsesMaterial>().remove(ent);
+
+    if !has_gpu_material {
+        // Not uploaded yet, so it is uploaded with the new texture
+        return true;
+    }
+    if had_texture {
+        world
+            .write_storage::<ReloadTextures>()
+            .entry(ent)
+            .expect("Failed to get entry")
+            .or_insert_with(ReloadTextures::default)
+            .0
+            .push(path);
+    } else {
+        // A new texture needs another pipeline, so everything is created again
+        world.write_storage::<GpuMaterial>().remove(ent);
+        world
+            .write_storage::<super::RenderableMaterial>()
+            .remove(ent);
+        world
+            .write_storage::<super::streaming::StreamedTextures>()
+            .remove(ent);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn material(metallic_factor: f32) -> PhysicallyBased {
+        PhysicallyBased {
+            base_color_factor: Vec4::one(),
+            metallic_factor,
+            roughness_factor: 1.0,
+            normal_scale: 1.0,
+            normal_map: None,
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            emissive_factor: Rgb::black(),
+            emissive_texture: None,
+            occlusion_strength: 1.0,
+            occlusion_texture: None,
+            transmission: 0.0,
+            ior: DEFAULT_IOR,
+            has_vertex_colors: false,
+        }
+    }
+
+    #[test]
+    fn reimport_replaces_material() {
+        let mut library = MaterialLibrary::default();
+        let path = Path::new("a.gltf");
+        let first = library.import(String::from("a"), material(0.0), path, Some(0));
+        let other = library.import(String::from("b"), material(0.0), path, Some(1));
+        assert_ne!(first, other);
+
+        let reimported = library.import(String::from("a"), material(1.0), path, Some(0));
+        assert_eq!(first, reimported);
+        assert_eq!(library.iter().count(), 2);
+        assert_eq!(library.get(first).unwrap().material.metallic_factor, 1.0);
+    }
+
+    #[test]
+    fn duplicate_is_not_reimported() {
+        let mut library = MaterialLibrary::default();
+        let path = Path::new("a.gltf");
+        let id = library.import(String::from("a"), material(0.0), path, None);
+        let copy = library.duplicate(id).unwrap();
+        assert_eq!(library.get(copy).unwrap().name, "a copy");
+
+        library.import(String::from("a"), material(1.0), path, None);
+        assert_eq!(library.get(copy).unwrap().material.metallic_factor, 0.0);
+    }
+
+    #[test]
+    fn edited_uniforms_are_written_for_each_frame_in_flight() {
+        let mut uniforms = MaterialUniforms::default();
+        let ent = World::new().create_entity().build();
+        let uploaded = PBRMaterialData::from(&material(0.0));
+        assert!(!uniforms.needs_write(ent, uploaded, uploaded));
+        assert!(!uniforms.needs_write(ent, uploaded, uploaded));
+
+        let edited = PBRMaterialData::from(&material(1.0));
+        for _ in 0..trekanten::MAX_FRAMES_IN_FLIGHT {
+            assert!(uniforms.needs_write(ent, edited, edited));
+        }
+        assert!(!uniforms.needs_write(ent, edited, edited));
+    }
+
+    #[test]
+    fn bindless_texture_indices_are_written_to_new_uniforms() {
+        let mut uniforms = MaterialUniforms::default();
+        let ent = World::new().create_entity().build();
+        let uploaded = PBRMaterialData::from(&material(0.0));
+        let indexed = PBRMaterialData {
+            texture_indices: [[3, 0, 7, 0], [1, 0, 0, 0]],
+            ..uploaded
+        };
+        for _ in 0..trekanten::MAX_FRAMES_IN_FLIGHT {
+            assert!(uniforms.needs_write(ent, uploaded, indexed));
+        }
+        assert!(!uniforms.needs_write(ent, uploaded, indexed));
+    }
+
+    #[test]
+    fn occlusion_strength_is_written_to_the_uniforms() {
+        let occluded = PhysicallyBased {
+            occlusion_strength: 0.25,
+            ..material(0.0)
+        };
+        let data = PBRMaterialData::from(&occluded);
+        assert_eq!({ data.occlusion_strength }, 0.25);
+        assert!(!occluded.same_parameters(&material(0.0)));
+    }
+
+    #[test]
+    fn transmission_is_written_to_the_uniforms() {
+        let glass = PhysicallyBased {
+            transmission: 1.0,
+            ior: 1.33,
+            ..material(0.0)
+        };
+        let data = PBRMaterialData::from(&glass);
+        assert_eq!({ data.transmission_ior }, [1.0, 1.33, 0.0, 0.0]);
+        assert!(!glass.same_parameters(&material(0.0)));
+    }
+
+    #[test]
+    fn same_parameters_compare_factors_and_texture_files() {
+        let textured = |path: &str| PhysicallyBased {
+            base_color_texture: Some(TextureUse2 {
+                desc: TextureDescriptor::file(
+                    PathBuf::from(path),
+                    Format::RGBA_SRGB,
+                    trekanten::texture::MipMaps::None,
+                ),
+                coord_set: 0,
+            }),
+            ..material(0.0)
+        };
+        assert!(material(0.0).same_parameters(&material(0.0)));
+        assert!(!material(0.0).same_parameters(&material(1.0)));
+        assert!(textured("a.png").same_parameters(&textured("a.png")));
+        assert!(!textured("a.png").same_parameters(&textured("b.png")));
+        assert!(!textured("a.png").same_parameters(&material(0.0)));
+    }
+
+    #[test]
+    fn swapped_textures_are_no_longer_shared() {
+        let mut world = World::new();
+        crate::ecs::meta::register_all_components(&mut world);
+        world.insert(crate::asset::dependencies::AssetDependencies::default());
+        let ent = world
+            .create_entity()
+            .with(material(0.0))
+            .with(SharedMaterial::new(material(0.0)))
+            .with(UsesMaterial(MaterialId(0)))
+            .build();
+
+        let path = PathBuf::from("normals.png");
+        assert!(swap_texture(
+            &world,
+            ent,
+            TextureSlot::NormalMap,
+            path.clone()
+        ));
+        let materials = world.read_storage::<PhysicallyBased>();
+        let normal_map = materials.get(ent).unwrap().normal_map.as_ref().unwrap();
+        assert_eq!(
+            texture_key(&normal_map.desc),
+            Some((path.clone(), Format::RGBA_UNORM, None))
+        );
+        assert!(!world.read_storage::<SharedMaterial>().contains(ent));
+        assert!(!world.read_storage::<UsesMaterial>().contains(ent));
+        let dependencies = world.read_resource::<crate::asset::dependencies::AssetDependencies>();
+        assert_eq!(dependencies.dependents(&path), &[ent]);
+    }
+
+    #[test]
+    fn shared_until_changed() {
+        let mut library = MaterialLibrary::default();
+        let id = library.import(String::from("a"), material(0.0), Path::new("a.gltf"), None);
+        let first = library.shared(id).unwrap();
+        assert_eq!(first.key(), library.shared(id).unwrap().key());
+
+        library.get_mut(id).unwrap().material.metallic_factor = 1.0;
+        let changed = library.shared(id).unwrap();
+        assert_ne!(first.key(), changed.key());
+        assert_eq!(changed.0.metallic_factor, 1.0);
+    }
+}
@@ -1,7 +1,11 @@
+use std::convert::TryInto;
+use std::sync::Arc;
+
 use crate::ecs::prelude::*;
+use crate::math::Vec3;
 use crate::render::Pending;
 use trekanten::loader::{Loader, ResourceLoader};
-use trekanten::mem::{IndexBuffer, VertexBuffer};
+use trekanten::mem::{BufferDescriptor as _, IndexBuffer, VertexBuffer};
 use trekanten::resource::Async;
 use trekanten::BufferHandle;
 
@@ -21,7 +25,46 @@ pub struct CpuMesh {
     pub polygon_mode: trekanten::pipeline::PolygonMode,
 }
 
-#[derive(Component)]
+impl CpuMesh {
+    /// The vertex positions, if they are the first attribute and three floats, like in the meshes
+    /// of the PBR pipelines. Empty otherwise.
+    pub fn positions(&self) -> Vec<Vec3> {
+        let format = self.vertex_buffer.format();
+        let offset = match format.vk_attribute_description().first() {
+            Some(a) if a.format == trekanten::raw_vk::Format::R32G32B32_SFLOAT => a.offset as usize,
+            _ => return Vec::new(),
+        };
+        let stride = format.size() as usize;
+        let data = self.vertex_buffer.data();
+        let read = |start: usize| {
+            f32::from_ne_bytes(data[start..start + 4].try_into().expect("Four bytes"))
+        };
+        (0..self.vertex_buffer.n_elems() as usize)
+            .map(|i| {
+                let base = i * stride + offset;
+                Vec3::new(read(base), read(base + 4), read(base + 8))
+            })
+            .collect()
+    }
+}
+
+/// A mesh that several entities use. They share its gpu buffers, which are destroyed when the last
+/// of them is gone. The entity gets a CpuMesh with the same data, if it does not have one.
+#[derive(Component, Clone)]
+pub struct SharedMesh(pub Arc<CpuMesh>);
+
+impl SharedMesh {
+    pub fn new(mesh: CpuMesh) -> Self {
+        Self(Arc::new(mesh))
+    }
+
+    /// The same for all entities that share the mesh
+    pub(super) fn key(&self) -> usize {
+        &*self.0 as *const CpuMesh as usize
+    }
+}
+
+#[derive(Component, Clone)]
 #[component(inspect)]
 pub struct PendingMesh {
     pub vertex_buffer: Pending<BufferHandle<Async<VertexBuffer>>, BufferHandle<VertexBuffer>>,
@@ -29,6 +72,16 @@ pub struct PendingMesh {
     pub polygon_mode: trekanten::pipeline::PolygonMode,
 }
 
+impl From<&GpuMesh> for PendingMesh {
+    fn from(mesh: &GpuMesh) -> Self {
+        Self {
+            vertex_buffer: Pending::Available(mesh.vertex_buffer),
+            index_buffer: Pending::Available(mesh.index_buffer),
+            polygon_mode: mesh.polygon_mode,
+        }
+    }
+}
+
 impl PendingMesh {
     pub fn try_finish(&self) -> Option<GpuMesh> {
         match (&self.vertex_buffer, &self.index_buffer) {
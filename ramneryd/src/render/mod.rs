@@ -1,12 +1,15 @@
+use std::collections::{HashMap, HashSet};
 use std::mem::MaybeUninit;
 
 use thiserror::Error;
 
 use crate::ecs::prelude::*;
 
-use trekanten::mem::{BufferMutability, OwningUniformBufferDescriptor, UniformBuffer};
+use trekanten::mem::{
+    BufferMutability, OwningUniformBufferDescriptor, TransientSlice, UniformBuffer,
+};
 use trekanten::pipeline::{
-    GraphicsPipeline, GraphicsPipelineDescriptor, PipelineError, ShaderDescriptor,
+    DepthTest, GraphicsPipeline, GraphicsPipelineDescriptor, PipelineError, ShaderDescriptor,
 };
 use trekanten::resource::Handle;
 use trekanten::resource::ResourceManager;
@@ -20,24 +23,54 @@ use trekanten::{
     texture::TextureUsage,
 };
 
+pub mod aov;
 mod bounding_box;
+pub mod curve;
+pub mod debug_draw;
 pub mod debug_window;
+mod deformation;
+mod draw_list;
+pub mod exposure;
+pub mod fog;
 pub mod geometry;
+pub mod hdr_export;
+pub mod inspector;
 pub mod light;
+pub mod light_commands;
+pub mod lod;
 pub mod material;
 pub mod mesh;
+mod object_data;
+pub mod panorama;
+mod path_trace;
 pub mod pipeline;
+mod pipeline_jobs;
+pub mod post;
+pub mod post_effects;
+mod resource_tracking;
+pub mod review;
+pub mod shader_interface;
+mod shader_watcher;
+pub mod shadow_bake;
+pub mod streaming;
+pub mod thumbnail;
 pub mod ui;
 pub mod uniform;
+pub mod view_mode;
+pub mod virtual_texture;
+pub mod visibility;
+mod volumetric;
+pub mod wireframe;
 
-pub use light::Light;
+pub use draw_list::{DrawStats, RenderOrder};
+pub use light::{Light, LightIntensity};
 
 use mesh::GpuMesh;
 use mesh::PendingMesh;
 
 use crate::camera::*;
 use crate::ecs;
-use crate::math::{Mat4, ModelMatrix, Transform, Vec3};
+use crate::math::{Mat4, Transform, Vec3};
 use material::{GpuMaterial, PendingMaterial};
 use ramneryd_derive::Inspect;
 
@@ -52,7 +85,6 @@ pub fn camera_pos(world: &World) -> Vec3 {
 
 struct SpotlightShadow {
     render_target: Handle<trekanten::RenderTarget>,
-    view_data_buffer: BufferHandle<UniformBuffer>,
     view_data_desc_set: Handle<DescriptorSet>,
     texture: Handle<trekanten::Texture>,
 }
@@ -63,29 +95,95 @@ struct ShadowData {
     render_pass: Handle<trekanten::RenderPass>,
     dummy_pipeline: Handle<GraphicsPipeline>,
     spotlights: [SpotlightShadow; NUM_SPOTLIGHT_SHADOW_MAPS],
+    /// The size of the shadow maps, see RenderSettings::shadow_resolution
+    extent: trekanten::util::Extent2D,
 }
 
 struct UnlitFrameUniformResources {
     dummy_pipeline: Handle<GraphicsPipeline>,
     shader_resource_group: Handle<DescriptorSet>,
+    // The color of renderables that are waiting for their pipeline
+    fallback_color: BufferHandle<UniformBuffer>,
 }
 
 struct PhysicallyBasedUniformResources {
     dummy_pipeline: Handle<GraphicsPipeline>,
     shader_resource_group: Handle<DescriptorSet>,
-    light_buffer: BufferHandle<UniformBuffer>,
-    shadow_matrices_buffer: BufferHandle<UniformBuffer>,
+    fog_buffer: BufferHandle<UniformBuffer>,
 }
 
 pub struct FrameData {
     main_render_pass: Handle<trekanten::RenderPass>,
-    main_camera_view_data: BufferHandle<UniformBuffer>,
+    msaa_sample_count: u8,
+    post: post::PostProcessing,
     unlit_resources: UnlitFrameUniformResources,
     pbr_resources: PhysicallyBasedUniformResources,
     shadow: ShadowData,
+    shadow_mask: shadow_bake::ShadowMaskTexture,
+    volumetric: volumetric::VolumetricLighting,
+    debug_draw: debug_draw::DebugDrawRenderer,
+    wireframe: wireframe::WireframeRenderer,
+    review: review::ReviewRenderer,
+    view_modes: view_mode::ViewModeRenderer,
+    path_tracer: path_trace::PathTracer,
+    deformation: deformation::DeformationPass,
+    thumbnails: thumbnail::ThumbnailRenderer,
 }
 
-fn get_view_data(world: &World) -> (Mat4, Vec3) {
+impl FrameData {
+    /// Creates the set 0 of the PBR pipelines again, after any of its textures were recreated
+    fn recreate_pbr_resource_group(&mut self, renderer: &mut Renderer) {
+        let shader_resource_group = pbr_shader_resource_group(
+            renderer,
+            &self.pbr_resources.fog_buffer,
+            &self.shadow,
+            self.shadow_mask.texture(),
+            self.post.transmission(),
+        );
+        let prev = std::mem::replace(
+            &mut self.pbr_resources.shader_resource_group,
+            shader_resource_group,
+        );
+        renderer.destroy_deferred(prev);
+    }
+
+    /// Points the sets that read the view of the main camera at this frame's view data
+    fn bind_view_data(&self, frame: &mut trekanten::Frame, view_data: TransientSlice) {
+        for set in &[
+            &self.pbr_resources.shader_resource_group,
+            &self.unlit_resources.shader_resource_group,
+        ] {
+            frame.bind_transient(set, uniform::ViewData::BINDING, view_data);
+        }
+    }
+
+    /// Points the sets that read the lights at this frame's lights and shadow matrices
+    fn bind_lighting(
+        &self,
+        frame: &mut trekanten::Frame,
+        lighting: TransientSlice,
+        shadow_matrices: TransientSlice,
+    ) {
+        let set = &self.pbr_resources.shader_resource_group;
+        frame.bind_transient(set, uniform::LightingData::BINDING, lighting);
+        frame.bind_transient(set, uniform::ShadowMatrices::BINDING, shadow_matrices);
+        self.volumetric
+            .bind_lighting(frame, lighting, shadow_matrices);
+    }
+}
+
+/// Render from this view, with a 90 degree vertical field of view, instead of from the camera. The
+/// ui is not drawn while this resource exists. Used to capture the faces of a panorama.
+pub(crate) struct ViewOverride {
+    pub view: Mat4,
+    pub pos: Vec3,
+}
+
+pub(crate) fn get_view_data(world: &World) -> (Mat4, Vec3) {
+    if let Some(view_override) = world.try_fetch::<ViewOverride>() {
+        return (view_override.view, view_override.pos);
+    }
+
     let camera_entity = ecs::get_singleton_entity::<Camera>(world);
     let transforms = world.read_storage::<Transform>();
     let rots = world.read_storage::<CameraRotationState>();
@@ -107,21 +205,80 @@ fn get_view_data(world: &World) -> (Mat4, Vec3) {
     (view, cam_pos)
 }
 
-fn get_proj_matrix(aspect_ratio: f32) -> Mat4 {
-    crate::math::perspective_vk(std::f32::consts::FRAC_PI_4, aspect_ratio, 0.05, 1000000.0)
+pub(crate) fn get_proj_matrix(world: &World, aspect_ratio: f32) -> Mat4 {
+    get_proj_matrix_with_far(world, aspect_ratio, 1000000.0)
+}
+
+/// The camera projection, with another far plane
+pub(crate) fn get_proj_matrix_with_far(world: &World, aspect_ratio: f32, far: f32) -> Mat4 {
+    if world.has_value::<ViewOverride>() {
+        return crate::math::perspective_vk(
+            std::f32::consts::FRAC_PI_2,
+            aspect_ratio,
+            NEAR_PLANE,
+            far,
+        );
+    }
+
+    let camera_entity = ecs::get_singleton_entity::<Camera>(world);
+    let cameras = world.read_storage::<Camera>();
+    let camera = cameras
+        .get(camera_entity)
+        .expect("The singleton camera entity has a camera");
+    camera.projection(aspect_ratio, NEAR_PLANE, far)
 }
 
 #[derive(Component, Default)]
 #[component(storage = "NullStorage")]
 pub struct ReloadMaterial;
 
+/// The entity is not drawn, neither in the scene nor in the shadow maps
+#[derive(Component, Default)]
+#[component(storage = "NullStorage")]
+pub struct Hidden;
+
+/// The GpuMaterial of the entity was replaced, e.g. because a texture was reloaded, so the
+/// descriptor set of the renderable needs to be recreated.
+#[derive(Component, Default)]
+#[component(storage = "NullStorage")]
+pub struct MaterialUpdated;
+
+/// How far the gpu resources of an entity with a mesh have come. The entity is drawn with an unlit
+/// fallback until it is Ready, so that a material is never drawn before all of its textures and its
+/// pipeline are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+#[component(inspect)]
+pub enum RenderReadiness {
+    /// Some of the buffers or textures of the material are still being uploaded
+    WaitingForMaterial,
+    /// The pipeline of the material is being created on a worker thread
+    WaitingForPipeline,
+    Ready,
+    /// The pipeline could not be created, the fallback is kept
+    Failed,
+}
+
+impl RenderReadiness {
+    fn is_waiting(self) -> bool {
+        matches!(self, Self::WaitingForMaterial | Self::WaitingForPipeline)
+    }
+}
+
+/// The depth test of the material pipeline of the renderable. The pipeline is recreated when the
+/// RenderOrder of the entity changes it, see RenderOrder::depth_testing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub struct PipelineDepthTest(DepthTest);
+
 #[derive(Component)]
 #[component(inspect)]
 pub enum RenderableMaterial {
     PBR {
         gfx_pipeline: Handle<GraphicsPipeline>,
         shadow_pipeline: Handle<GraphicsPipeline>,
+        // Same as the shadow pipeline but culls back faces instead, for depth from the camera
+        depth_pipeline: Handle<GraphicsPipeline>,
         material_descriptor_set: Handle<DescriptorSet>,
+        bindless_textures: Option<BindlessTextures>,
     },
     Unlit {
         gfx_pipeline: Handle<GraphicsPipeline>,
@@ -130,12 +287,136 @@ pub enum RenderableMaterial {
 }
 
 impl RenderableMaterial {
+    fn gfx_pipeline(&self) -> Handle<GraphicsPipeline> {
+        match self {
+            RenderableMaterial::PBR { gfx_pipeline, .. } => *gfx_pipeline,
+            RenderableMaterial::Unlit { gfx_pipeline, .. } => *gfx_pipeline,
+        }
+    }
+
     fn set_pipeline(&mut self, h: Handle<GraphicsPipeline>) {
         match self {
             RenderableMaterial::PBR { gfx_pipeline, .. } => *gfx_pipeline = h,
             RenderableMaterial::Unlit { gfx_pipeline, .. } => *gfx_pipeline = h,
         }
     }
+
+    fn set_material_descriptor_set(&mut self, h: Handle<DescriptorSet>) {
+        match self {
+            RenderableMaterial::PBR {
+                material_descriptor_set,
+                ..
+            } => *material_descriptor_set = h,
+            RenderableMaterial::Unlit {
+                material_descriptor_set,
+                ..
+            } => *material_descriptor_set = h,
+        }
+    }
+}
+
+/// Where the PBR shaders find the textures of a material in the bindless array, instead of in its
+/// descriptor set, see uses_bindless_textures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Inspect)]
+pub struct BindlessTextures {
+    descriptor_set: Handle<DescriptorSet>,
+    base_color: u32,
+    metallic_roughness: u32,
+    normal: u32,
+    emissive: u32,
+    occlusion: u32,
+}
+
+impl BindlessTextures {
+    fn new(renderer: &mut Renderer, material: &GpuMaterial) -> Option<Self> {
+        if !uses_bindless_textures(renderer, material) {
+            return None;
+        }
+
+        let descriptor_set = *renderer.bindless_descriptor_set()?;
+        match material {
+            GpuMaterial::PBR {
+                normal_map,
+                base_color_texture,
+                metallic_roughness_texture,
+                emissive_texture,
+                occlusion_texture,
+                ..
+            } => {
+                // The slots of missing textures are never sampled, see the HAS_* defines
+                let mut index = |t: &Option<material::TextureUse<trekanten::Texture>>| {
+                    t.as_ref()
+                        .and_then(|t| renderer.bindless_texture_index(&t.handle))
+                        .unwrap_or(0)
+                };
+                Some(Self {
+                    descriptor_set,
+                    base_color: index(base_color_texture),
+                    metallic_roughness: index(metallic_roughness_texture),
+                    normal: index(normal_map),
+                    emissive: index(emissive_texture),
+                    occlusion: index(occlusion_texture),
+                })
+            }
+            GpuMaterial::Unlit { .. } => None,
+        }
+    }
+
+    fn descriptor_set(&self) -> Handle<DescriptorSet> {
+        self.descriptor_set
+    }
+
+    /// Same layout as PBRMaterialData::texture_indices
+    fn indices(&self) -> [[u32; 4]; 2] {
+        [
+            [
+                self.base_color,
+                self.metallic_roughness,
+                self.normal,
+                self.emissive,
+            ],
+            [self.occlusion, 0, 0, 0],
+        ]
+    }
+}
+
+/// The PBR pipelines sample the textures of a material from the bindless array if the device
+/// supports it, so that its descriptor set only has the uniforms and swapping a texture only
+/// changes an index. Sparse textures are sampled with their residency, so materials with one keep
+/// their textures in their descriptor set.
+fn uses_bindless_textures(renderer: &Renderer, material: &GpuMaterial) -> bool {
+    if !renderer.supports_bindless_textures()
+        || renderer.max_bound_descriptor_sets() <= uniform::BINDLESS_TEXTURES_SET
+    {
+        return false;
+    }
+
+    match material {
+        GpuMaterial::PBR {
+            normal_map,
+            base_color_texture,
+            metallic_roughness_texture,
+            emissive_texture,
+            occlusion_texture,
+            ..
+        } => {
+            let sparse_bc = base_color_texture
+                .as_ref()
+                .and_then(|t| renderer.get_texture(&t.handle))
+                .map_or(false, |t| t.sparse().is_some());
+            let has_tex = [
+                normal_map,
+                base_color_texture,
+                metallic_roughness_texture,
+                emissive_texture,
+                occlusion_texture,
+            ]
+            .iter()
+            .any(|t| t.is_some());
+            has_tex && !sparse_bc
+        }
+        GpuMaterial::Unlit { .. } => false,
+    }
 }
 
 // TODO: Bindings here need to match with shader
@@ -149,8 +430,11 @@ fn create_material_descriptor_set(
             normal_map,
             base_color_texture,
             metallic_roughness_texture,
+            emissive_texture,
+            occlusion_texture,
             ..
         } => {
+            let bindless = uses_bindless_textures(renderer, material);
             let mut desc_set_builder = DescriptorSet::builder(renderer);
 
             desc_set_builder = desc_set_builder.add_buffer(
@@ -159,6 +443,11 @@ fn create_material_descriptor_set(
                 trekanten::pipeline::ShaderStage::FRAGMENT,
             );
 
+            // The textures are in the bindless array instead, see BindlessTextures
+            if bindless {
+                return desc_set_builder.build();
+            }
+
             if let Some(bct) = &base_color_texture {
                 desc_set_builder = desc_set_builder.add_texture(
                     &bct.handle,
@@ -186,6 +475,24 @@ fn create_material_descriptor_set(
                 );
             }
 
+            if let Some(et) = &emissive_texture {
+                desc_set_builder = desc_set_builder.add_texture(
+                    &et.handle,
+                    4,
+                    trekanten::pipeline::ShaderStage::FRAGMENT,
+                    false,
+                );
+            }
+
+            if let Some(ot) = &occlusion_texture {
+                desc_set_builder = desc_set_builder.add_texture(
+                    &ot.handle,
+                    5,
+                    trekanten::pipeline::ShaderStage::FRAGMENT,
+                    false,
+                );
+            }
+
             desc_set_builder.build()
         }
         material::GpuMaterial::Unlit { color_uniform } => DescriptorSet::builder(renderer)
@@ -204,6 +511,35 @@ pub enum MaterialError {
     Pipeline(#[from] PipelineError),
     #[error("GLSL compiler error: {0}")]
     GlslCompiler(#[from] pipeline::CompilerError),
+    #[error("Pipeline creation failed on a worker thread")]
+    Worker,
+}
+
+/// Why the renderer could not be set up or a frame could not be drawn. A frame that fails is
+/// skipped and the main loop recovers the renderer before the next one, see
+/// trekanten::Renderer::recover.
+#[derive(Debug, Error)]
+pub enum RenderError {
+    #[error("Failed to set up the renderer: {0}")]
+    Setup(String),
+    #[error("Failed to upload resources: {0}")]
+    Upload(trekanten::RenderError),
+    #[error("Failed to acquire the next frame: {0}")]
+    NextFrame(trekanten::RenderError),
+    #[error("Failed to resize the swapchain: {0}")]
+    Resize(trekanten::RenderError),
+    #[error("Failed to record the frame: {0}")]
+    Record(trekanten::RenderError),
+    #[error("Failed to submit the frame: {0}")]
+    Submit(trekanten::RenderError),
+    #[error("Failed to recreate the lost device: {0}")]
+    RecreateDevice(trekanten::RenderError),
+}
+
+impl RenderError {
+    fn setup(what: &str, e: impl std::fmt::Display) -> Self {
+        RenderError::Setup(format!("{}: {}", what, e))
+    }
 }
 
 fn unlit_pipeline_desc(
@@ -231,76 +567,451 @@ fn unlit_pipeline_desc(
         .build()?)
 }
 
-fn get_pipeline_for(
+// Pipelines that are bound to set the frame-level shader resources
+fn pbr_dummy_pipeline(
+    shader_compiler: &pipeline::ShaderCompiler,
     renderer: &mut Renderer,
-    world: &World,
-    mesh: &GpuMesh,
-    mat: &material::GpuMaterial,
+    render_pass: &Handle<trekanten::RenderPass>,
+    object_buffer: bool,
+) -> Result<Handle<GraphicsPipeline>, MaterialError> {
+    let vertex_format = VertexFormat::builder()
+        .add_attribute(util::Format::FLOAT3)
+        .add_attribute(util::Format::FLOAT3)
+        .build();
+
+    let (vert, frag) = pipeline::pbr_gltf::compile_default(shader_compiler, object_buffer)?;
+    let desc = GraphicsPipelineDescriptor::builder()
+        .vert(ShaderDescriptor::FromRawSpirv(vert.data()))
+        .frag(ShaderDescriptor::FromRawSpirv(frag.data()))
+        .vertex_format(vertex_format)
+        .build()?;
+
+    Ok(renderer.create_gfx_pipeline(desc, render_pass)?)
+}
+
+fn unlit_dummy_pipeline(
+    shader_compiler: &pipeline::ShaderCompiler,
+    renderer: &mut Renderer,
+    render_pass: &Handle<trekanten::RenderPass>,
 ) -> Result<Handle<GraphicsPipeline>, MaterialError> {
+    let vertex_format = VertexFormat::builder()
+        .add_attribute(util::Format::FLOAT3)
+        .build();
+    let desc = unlit_pipeline_desc(
+        shader_compiler,
+        vertex_format,
+        trekanten::pipeline::PolygonMode::Line,
+    )?;
+
+    Ok(renderer.create_gfx_pipeline(desc, render_pass)?)
+}
+
+/// Recreates the scene render pass and the pipelines that are created for it, and the composite
+/// pipelines for `main_render_pass`
+fn recreate_msaa_resources(
+    world: &World,
+    renderer: &mut Renderer,
+    main_render_pass: &Handle<trekanten::RenderPass>,
+    msaa_sample_count: u8,
+) -> Result<(), RenderError> {
+    let shader_compiler = world.read_resource::<pipeline::ShaderCompiler>();
+    let object_buffer = world.read_resource::<object_data::ObjectData>().enabled();
+    let mut frame_data = world.write_resource::<FrameData>();
+    frame_data.post.set_msaa_sample_count(
+        &shader_compiler,
+        renderer,
+        main_render_pass,
+        msaa_sample_count,
+    )?;
+    let FrameData {
+        post, volumetric, ..
+    } = &mut *frame_data;
+    volumetric.recreate_targets(renderer, post.scene_color());
+    frame_data.recreate_pbr_resource_group(renderer);
+    let scene_render_pass = *frame_data.post.scene_render_pass();
+    let pbr_dummy = pbr_dummy_pipeline(
+        &shader_compiler,
+        renderer,
+        &scene_render_pass,
+        object_buffer,
+    )
+    .map_err(|e| RenderError::setup("Failed to recreate pbr dummy pipeline", e))?;
+    renderer.destroy_deferred(std::mem::replace(
+        &mut frame_data.pbr_resources.dummy_pipeline,
+        pbr_dummy,
+    ));
+    let unlit_dummy = unlit_dummy_pipeline(&shader_compiler, renderer, &scene_render_pass)
+        .map_err(|e| RenderError::setup("Failed to recreate unlit dummy pipeline", e))?;
+    renderer.destroy_deferred(std::mem::replace(
+        &mut frame_data.unlit_resources.dummy_pipeline,
+        unlit_dummy,
+    ));
+    frame_data
+        .debug_draw
+        .recreate_pipeline(&shader_compiler, renderer, &scene_render_pass)
+        .map_err(|e| RenderError::setup("Failed to recreate debug draw pipeline", e))?;
+    frame_data.wireframe.clear_pipelines(renderer);
+    frame_data.review.clear_pipelines(renderer);
+    frame_data.view_modes.clear_pipelines(renderer);
+    Ok(())
+}
+
+/// Recreates the main and scene render passes, and the pipelines that use them, if the MSAA setting
+/// changed
+fn apply_msaa_sample_count(
+    world: &mut World,
+    ui: &mut ui::UIContext,
+    renderer: &mut Renderer,
+) -> Result<(), RenderError> {
+    let msaa_sample_count = world
+        .read_resource::<debug_window::RenderSettings>()
+        .msaa_sample_count;
+    let current = world.read_resource::<FrameData>().msaa_sample_count;
+    if msaa_sample_count == current {
+        return Ok(());
+    }
+
+    log::info!(
+        "Changing MSAA sample count from {} to {}",
+        current,
+        msaa_sample_count
+    );
+    let main_render_pass = match renderer.presentation_render_pass(msaa_sample_count) {
+        Ok(render_pass) => render_pass,
+        Err(e) => {
+            log::error!("Failed to recreate the main render pass: {}", e);
+            world
+                .write_resource::<debug_window::RenderSettings>()
+                .msaa_sample_count = current;
+            return Ok(());
+        }
+    };
+
+    let recreated = recreate_msaa_resources(world, renderer, &main_render_pass, msaa_sample_count);
+    let (main_render_pass, msaa_sample_count) = match recreated {
+        Ok(()) => (main_render_pass, msaa_sample_count),
+        Err(e) => {
+            log::error!(
+                "Failed to change the MSAA sample count to {}: {}",
+                msaa_sample_count,
+                e
+            );
+            world
+                .write_resource::<debug_window::RenderSettings>()
+                .msaa_sample_count = current;
+            // The presentation render target was replaced along with the render pass
+            let restored = renderer
+                .presentation_render_pass(current)
+                .map_err(|e| RenderError::setup("Failed to recreate the main render pass", e))?;
+            renderer.destroy_deferred(main_render_pass);
+            recreate_msaa_resources(world, renderer, &restored, current)?;
+            (restored, current)
+        }
+    };
+    {
+        let mut frame_data = world.write_resource::<FrameData>();
+        renderer.destroy_deferred(std::mem::replace(
+            &mut frame_data.main_render_pass,
+            main_render_pass,
+        ));
+        frame_data.msaa_sample_count = msaa_sample_count;
+    }
+
+    ui.recreate_pipeline(world, renderer);
+
+    // Pipelines that are being created for the previous render pass are discarded
+    let factory =
+        renderer.pipeline_factory(world.read_resource::<FrameData>().post.scene_render_pass());
+    world
+        .write_resource::<pipeline_jobs::PipelineJobs>()
+        .release_finished(renderer);
+    world.insert(pipeline_jobs::PipelineJobs::new(factory));
+
+    // The old pipelines can't be used with the new render pass, so the renderables are recreated in
+    // create_renderables and drawn with the fallback until their pipelines are ready
+    world.write_storage::<RenderableMaterial>().clear();
+    Ok(())
+}
+
+/// Recreates the pipelines whose push constants and descriptor sets depend on whether the model
+/// matrices are read from the object buffer, if the setting changed
+fn apply_object_buffer(world: &mut World, renderer: &mut Renderer) -> Result<(), RenderError> {
+    let object_buffer = world
+        .read_resource::<debug_window::RenderSettings>()
+        .object_buffer;
+    if object_buffer == world.read_resource::<object_data::ObjectData>().enabled() {
+        return Ok(());
+    }
+
+    log::info!(
+        "Reading model matrices from the object buffer: {}",
+        object_buffer
+    );
+    world
+        .write_resource::<object_data::ObjectData>()
+        .set_enabled(object_buffer);
+    {
+        let shader_compiler = world.read_resource::<pipeline::ShaderCompiler>();
+        let mut frame_data = world.write_resource::<FrameData>();
+        let scene_render_pass = *frame_data.post.scene_render_pass();
+        let pbr_dummy = pbr_dummy_pipeline(
+            &shader_compiler,
+            renderer,
+            &scene_render_pass,
+            object_buffer,
+        )
+        .map_err(|e| RenderError::setup("Failed to recreate pbr dummy pipeline", e))?;
+        let prev = std::mem::replace(&mut frame_data.pbr_resources.dummy_pipeline, pbr_dummy);
+        renderer.destroy_deferred(prev);
+
+        let shadow_render_pass = frame_data.shadow.render_pass;
+        let shadow_dummy = shadow_dummy_pipeline(
+            &shader_compiler,
+            renderer,
+            &shadow_render_pass,
+            object_buffer,
+        )?;
+        let prev = std::mem::replace(&mut frame_data.shadow.dummy_pipeline, shadow_dummy);
+        renderer.destroy_deferred(prev);
+    }
+
+    // The renderables are recreated with the new pipelines in create_renderables
+    world.write_storage::<RenderableMaterial>().clear();
+    Ok(())
+}
+
+/// Recreates the spot light shadow maps, and the sets that sample them, when r.shadow.resolution
+/// has changed
+fn apply_shadow_resolution(world: &mut World, renderer: &mut Renderer) -> Result<(), RenderError> {
+    let extent = shadow_map_extent(
+        world
+            .read_resource::<debug_window::RenderSettings>()
+            .shadow_resolution,
+    );
+    if extent == world.read_resource::<FrameData>().shadow.extent {
+        return Ok(());
+    }
+
+    {
+        let mut frame_data = world.write_resource::<FrameData>();
+        let mut inspector = world.write_resource::<inspector::RenderTargetInspector>();
+        log::info!(
+            "Changing the shadow map resolution from {} to {}",
+            frame_data.shadow.extent.width,
+            extent.width
+        );
+        let render_pass = frame_data.shadow.render_pass;
+        for (i, spotlight) in frame_data.shadow.spotlights.iter_mut().enumerate() {
+            let (texture, render_target) = shadow_render_target(renderer, &render_pass, extent, i)?;
+            let prev_target = std::mem::replace(&mut spotlight.render_target, render_target);
+            let prev_texture = std::mem::replace(&mut spotlight.texture, texture);
+            inspector.replace(&prev_texture, texture);
+            renderer
+                .destroy_render_target(prev_target)
+                .map_err(|e| RenderError::setup("Failed to destroy shadow map target", e))?;
+            renderer
+                .destroy_texture(prev_texture)
+                .map_err(|e| RenderError::setup("Failed to destroy shadow map", e))?;
+        }
+        frame_data.shadow.extent = extent;
+        frame_data.recreate_pbr_resource_group(renderer);
+        let FrameData {
+            shadow, volumetric, ..
+        } = &mut *frame_data;
+        volumetric.recreate_inputs(renderer, shadow);
+    }
+
+    // The new shadow maps have not been rendered to
+    world.write_resource::<light::ShadowMapCache>().clear();
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum MaterialShaders {
+    PBR(pipeline::pbr_gltf::ShaderDefinition),
+    Unlit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    shaders: MaterialShaders,
+    vertex_format: VertexFormat,
+    polygon_mode: trekanten::pipeline::PolygonMode,
+    depth_testing: DepthTest,
+}
+
+/// The pipeline descriptors of the materials, keyed by what their shaders are compiled from, so that
+/// entities with the same kind of material do not compile the same shaders again. Has to be cleared
+/// when the shader sources change.
+#[derive(Default)]
+struct MaterialPipelineCache {
+    descriptors: HashMap<PipelineKey, GraphicsPipelineDescriptor>,
+    // Creating these failed on a worker, they are not retried until the shaders change
+    failed: HashSet<PipelineKey>,
+}
+
+impl MaterialPipelineCache {
+    fn clear(&mut self) {
+        self.descriptors.clear();
+        self.failed.clear();
+    }
+}
+
+fn vertex_format(renderer: &Renderer, mesh: &GpuMesh) -> VertexFormat {
     // TODO: Infer from spirv?
-    let vertex_format = renderer
+    renderer
         .get_resource(&mesh.vertex_buffer)
         .expect("Invalid handle")
         .format()
-        .clone();
+        .clone()
+}
 
-    let frame_data = world.read_resource::<FrameData>();
-    let shader_compiler = world.read_resource::<pipeline::ShaderCompiler>();
-    let pipe = match mat {
+fn pipeline_key(
+    renderer: &Renderer,
+    mesh: &GpuMesh,
+    mat: &material::GpuMaterial,
+    has_skin: bool,
+    num_morph_targets: u32,
+    object_buffer: bool,
+    depth_testing: DepthTest,
+) -> PipelineKey {
+    let vertex_format = vertex_format(renderer, mesh);
+
+    let shaders = match mat {
         material::GpuMaterial::PBR {
             normal_map,
             base_color_texture,
             metallic_roughness_texture,
+            emissive_texture,
+            occlusion_texture,
             has_vertex_colors,
             ..
         } => {
-            // TODO: Normal map does not infer tangents at all times
+            // Tangents are generated when loading meshes with normal maps but without tangents
             let has_nm = normal_map.is_some();
             let has_bc = base_color_texture.is_some();
             let has_mr = metallic_roughness_texture.is_some();
-            let def = pipeline::pbr_gltf::ShaderDefinition {
-                has_tex_coords: has_nm || has_bc || has_mr,
+            let has_em = emissive_texture.is_some();
+            let has_oc = occlusion_texture.is_some();
+            let sparse_bc = base_color_texture
+                .as_ref()
+                .and_then(|t| renderer.get_texture(&t.handle))
+                .map(|t| t.sparse().is_some())
+                .unwrap_or(false);
+            MaterialShaders::PBR(pipeline::pbr_gltf::ShaderDefinition {
+                has_skin,
+                num_morph_targets,
+                has_tex_coords: has_nm || has_bc || has_mr || has_em || has_oc,
                 has_vertex_colors: *has_vertex_colors,
                 has_tangents: has_nm,
                 has_base_color_texture: has_bc,
                 has_metallic_roughness_texture: has_mr,
                 has_normal_map: has_nm,
-            };
+                has_emissive_texture: has_em,
+                has_occlusion_texture: has_oc,
+                sparse_base_color_texture: sparse_bc,
+                object_buffer,
+                bindless_textures: uses_bindless_textures(renderer, mat),
+            })
+        }
+        material::GpuMaterial::Unlit { .. } => MaterialShaders::Unlit,
+    };
+    PipelineKey {
+        shaders,
+        vertex_format,
+        polygon_mode: mesh.polygon_mode,
+        depth_testing,
+    }
+}
 
-            let (vert, frag) = pipeline::pbr_gltf::compile(&*shader_compiler, &def)?;
-            let desc = GraphicsPipelineDescriptor::builder()
+/// Returns None if the pipeline is not ready yet, in which case it is created on a worker thread
+fn get_pipeline_for(
+    renderer: &mut Renderer,
+    world: &World,
+    key: &PipelineKey,
+) -> Result<Option<Handle<GraphicsPipeline>>, MaterialError> {
+    let frame_data = world.read_resource::<FrameData>();
+    let cache = world.read_resource::<MaterialPipelineCache>();
+    match cache.descriptors.get(key) {
+        // Identical descriptors share the same pipeline, with one reference for each call
+        Some(desc) => Ok(Some(renderer.create_gfx_pipeline(
+            desc.clone(),
+            frame_data.post.scene_render_pass(),
+        )?)),
+        None if cache.failed.contains(key) => Err(MaterialError::Worker),
+        None => {
+            world
+                .write_resource::<pipeline_jobs::PipelineJobs>()
+                .request(key);
+            Ok(None)
+        }
+    }
+}
+
+/// Compiles the pipeline on this thread if it is not in the cache
+fn get_pipeline_blocking(
+    renderer: &mut Renderer,
+    world: &World,
+    key: &PipelineKey,
+) -> Result<Handle<GraphicsPipeline>, MaterialError> {
+    let frame_data = world.read_resource::<FrameData>();
+    let mut cache = world.write_resource::<MaterialPipelineCache>();
+    let desc = match cache.descriptors.get(key) {
+        Some(desc) => desc.clone(),
+        None => {
+            let shader_compiler = world.read_resource::<pipeline::ShaderCompiler>();
+            let desc = material_pipeline_desc(&shader_compiler, key)?;
+            cache.descriptors.insert(key.clone(), desc.clone());
+            desc
+        }
+    };
+
+    Ok(renderer.create_gfx_pipeline(desc, frame_data.post.scene_render_pass())?)
+}
+
+fn material_pipeline_desc(
+    shader_compiler: &pipeline::ShaderCompiler,
+    key: &PipelineKey,
+) -> Result<GraphicsPipelineDescriptor, MaterialError> {
+    let desc = match &key.shaders {
+        MaterialShaders::PBR(def) => {
+            let (vert, frag) = pipeline::pbr_gltf::compile(shader_compiler, def)?;
+            GraphicsPipelineDescriptor::builder()
                 .vert(ShaderDescriptor::FromRawSpirv(vert.data()))
                 .frag(ShaderDescriptor::FromRawSpirv(frag.data()))
-                .vertex_format(vertex_format)
-                .polygon_mode(mesh.polygon_mode)
-                .build()?;
-
-            renderer.create_gfx_pipeline(desc, &frame_data.main_render_pass)?
+                .vertex_format(key.vertex_format.clone())
+                .polygon_mode(key.polygon_mode)
+                .depth_testing(key.depth_testing)
+                .build()?
         }
-        material::GpuMaterial::Unlit { .. } => {
-            let desc = unlit_pipeline_desc(&shader_compiler, vertex_format, mesh.polygon_mode)?;
-            renderer.create_gfx_pipeline(desc, &frame_data.main_render_pass)?
+        MaterialShaders::Unlit => {
+            let mut desc =
+                unlit_pipeline_desc(shader_compiler, key.vertex_format.clone(), key.polygon_mode)?;
+            desc.depth_testing = key.depth_testing;
+            desc
         }
     };
 
-    Ok(pipe)
+    Ok(desc)
 }
 
 fn shadow_pipeline_desc(
     shader_compiler: &pipeline::ShaderCompiler,
     format: VertexFormat,
+    culling: trekanten::pipeline::TriangleCulling,
+    object_buffer: bool,
 ) -> Result<GraphicsPipelineDescriptor, MaterialError> {
-    let no_defines = pipeline::Defines::empty();
-    let vert = shader_compiler.compile(
-        &no_defines,
-        "pos_only_vert.glsl",
-        pipeline::ShaderType::Vertex,
-    )?;
+    let mut defines = pipeline::Defines::empty();
+    if object_buffer {
+        defines.push((String::from(pipeline::OBJECT_BUFFER), String::from("1")));
+    }
+    let vert =
+        shader_compiler.compile(&defines, "pos_only_vert.glsl", pipeline::ShaderType::Vertex)?;
 
     Ok(GraphicsPipelineDescriptor::builder()
         .vertex_format(format)
         .vert(ShaderDescriptor::FromRawSpirv(vert.data()))
-        .culling(trekanten::pipeline::TriangleCulling::Front)
+        .culling(culling)
         .build()?)
 }
 
@@ -308,6 +1019,7 @@ fn get_shadow_pipeline_for(
     renderer: &mut Renderer,
     world: &World,
     mesh: &GpuMesh,
+    culling: trekanten::pipeline::TriangleCulling,
 ) -> Result<Handle<GraphicsPipeline>, MaterialError> {
     let shader_compiler = world.read_resource::<pipeline::ShaderCompiler>();
     let frame_data = world.read_resource::<FrameData>();
@@ -322,26 +1034,70 @@ fn get_shadow_pipeline_for(
         .add_attribute(trekanten::util::Format::FLOAT3) // pos
         .skip(vertex_format_size - trekanten::util::Format::FLOAT3.size())
         .build();
-    let descriptor = shadow_pipeline_desc(&shader_compiler, shadow_vertex_format)?;
+    let object_buffer = world.read_resource::<object_data::ObjectData>().enabled();
+    let descriptor = shadow_pipeline_desc(
+        &shader_compiler,
+        shadow_vertex_format,
+        culling,
+        object_buffer,
+    )?;
     Ok(renderer.create_gfx_pipeline(descriptor, &frame_data.shadow.render_pass)?)
 }
 
+/// A gray unlit material with the vertex format of the mesh, for entities whose pipeline is not ready
+fn create_fallback_renderable(
+    renderer: &mut Renderer,
+    world: &World,
+    key: &PipelineKey,
+) -> RenderableMaterial {
+    let fallback_key = PipelineKey {
+        shaders: MaterialShaders::Unlit,
+        vertex_format: key.vertex_format.clone(),
+        polygon_mode: key.polygon_mode,
+        depth_testing: key.depth_testing,
+    };
+    let gfx_pipeline = get_pipeline_blocking(renderer, world, &fallback_key)
+        .expect("Failed to create fallback pipeline");
+    let color_uniform = world
+        .read_resource::<FrameData>()
+        .unlit_resources
+        .fallback_color;
+    let material_descriptor_set =
+        create_material_descriptor_set(renderer, &GpuMaterial::Unlit { color_uniform });
+    RenderableMaterial::Unlit {
+        gfx_pipeline,
+        material_descriptor_set,
+    }
+}
+
 fn create_renderable(
     renderer: &mut Renderer,
     world: &World,
     mesh: &GpuMesh,
     material: &GpuMaterial,
+    gfx_pipeline: Handle<GraphicsPipeline>,
 ) -> RenderableMaterial {
     log::trace!("Creating renderable: {:?}", material);
     let material_descriptor_set = create_material_descriptor_set(renderer, material);
-    let gfx_pipeline =
-        get_pipeline_for(renderer, world, mesh, &material).expect("Failed to get pipeline");
     match material {
         material::GpuMaterial::PBR { .. } => RenderableMaterial::PBR {
             gfx_pipeline,
-            shadow_pipeline: get_shadow_pipeline_for(renderer, world, mesh)
-                .expect("Failed to create shadow pipeline"),
+            shadow_pipeline: get_shadow_pipeline_for(
+                renderer,
+                world,
+                mesh,
+                trekanten::pipeline::TriangleCulling::Front,
+            )
+            .expect("Failed to create shadow pipeline"),
+            depth_pipeline: get_shadow_pipeline_for(
+                renderer,
+                world,
+                mesh,
+                trekanten::pipeline::TriangleCulling::Back,
+            )
+            .expect("Failed to create depth pipeline"),
             material_descriptor_set,
+            bindless_textures: BindlessTextures::new(renderer, material),
         },
         material::GpuMaterial::Unlit { .. } => RenderableMaterial::Unlit {
             gfx_pipeline,
@@ -350,217 +1106,607 @@ fn create_renderable(
     }
 }
 
+fn set_readiness(
+    readiness: &mut WriteStorage<RenderReadiness>,
+    ent: Entity,
+    state: RenderReadiness,
+) {
+    readiness.insert(ent, state).expect("The entity is alive");
+}
+
+fn set_depth_test(
+    depth_tests: &mut WriteStorage<PipelineDepthTest>,
+    ent: Entity,
+    depth_testing: DepthTest,
+) {
+    depth_tests
+        .insert(ent, PipelineDepthTest(depth_testing))
+        .expect("The entity is alive");
+}
+
 #[profiling::function]
 fn create_renderables(renderer: &mut Renderer, world: &mut World) {
     use specs::storage::StorageEntry;
 
+    {
+        let mut cache = world.write_resource::<MaterialPipelineCache>();
+        world
+            .write_resource::<pipeline_jobs::PipelineJobs>()
+            .receive(renderer, &mut cache);
+    }
+
     let meshes = world.read_storage::<GpuMesh>();
     let materials = world.read_storage::<GpuMaterial>();
+    let skeletons = world.read_storage::<crate::anim::Skeleton>();
+    let morph_weights = world.read_storage::<crate::anim::MorphWeights>();
     let mut should_reload = world.write_storage::<ReloadMaterial>();
+    let mut updated = world.write_storage::<MaterialUpdated>();
+    let pending_materials = world.read_storage::<PendingMaterial>();
+    let mut readiness = world.write_storage::<RenderReadiness>();
+    let orders = world.read_storage::<RenderOrder>();
+    let mut depth_tests = world.write_storage::<PipelineDepthTest>();
     let mut renderables = world.write_storage::<RenderableMaterial>();
+    let mut tracker = world.write_resource::<resource_tracking::GpuResourceTracker>();
+    let object_buffer = world.read_resource::<object_data::ObjectData>().enabled();
     let entities = world.entities();
 
-    for (ent, mesh, mat) in (&entities, &meshes, &materials).join() {
+    // Reloads that wait for their pipeline keep the previous one until it is ready
+    let mut still_reloading = Vec::new();
+    for (ent, mesh, mat, skeleton, weights) in (
+        &entities,
+        &meshes,
+        &materials,
+        skeletons.maybe(),
+        morph_weights.maybe(),
+    )
+        .join()
+    {
+        let has_skin = skeleton.is_some();
+        let num_morph_targets = weights.map(|w| w.0.len() as u32).unwrap_or(0);
+        let depth_testing = RenderOrder::depth_testing(orders.get(ent));
         let entry = renderables.entry(ent).expect("Failed to get entry!");
         match entry {
             StorageEntry::Occupied(mut entry) => {
                 log::trace!("Using existing Renderable");
-                if should_reload.contains(ent) {
+                if readiness.get(ent).map_or(false, |r| r.is_waiting()) {
+                    let key = pipeline_key(
+                        renderer,
+                        mesh,
+                        mat,
+                        has_skin,
+                        num_morph_targets,
+                        object_buffer,
+                        depth_testing,
+                    );
+                    match get_pipeline_for(renderer, world, &key) {
+                        Ok(Some(pipeline)) => {
+                            log::trace!("Pipeline ready for {:?}", ent);
+                            // The fallback is released by resource_tracking::release_unused
+                            *entry.get_mut() =
+                                create_renderable(renderer, world, mesh, mat, pipeline);
+                            set_readiness(&mut readiness, ent, RenderReadiness::Ready);
+                            set_depth_test(&mut depth_tests, ent, depth_testing);
+                        }
+                        Ok(None) => {
+                            set_readiness(&mut readiness, ent, RenderReadiness::WaitingForPipeline)
+                        }
+                        Err(e) => {
+                            log::error!("Failed to compile pipeline: {}", e);
+                            set_readiness(&mut readiness, ent, RenderReadiness::Failed);
+                        }
+                    }
+                    // The descriptor set of the fallback does not depend on the material
+                    continue;
+                }
+
+                let reordered = depth_tests.get(ent).map_or(false, |d| d.0 != depth_testing);
+                if should_reload.contains(ent) || reordered {
                     log::trace!("Reloading shader for {:?}", ent);
-                    // TODO: Destroy the previous pipeline
-                    match get_pipeline_for(renderer, world, mesh, mat) {
-                        Ok(pipeline) => entry.get_mut().set_pipeline(pipeline),
+                    let key = pipeline_key(
+                        renderer,
+                        mesh,
+                        mat,
+                        has_skin,
+                        num_morph_targets,
+                        object_buffer,
+                        depth_testing,
+                    );
+                    // The previous pipeline is released by resource_tracking::release_unused
+                    match get_pipeline_for(renderer, world, &key) {
+                        Ok(Some(pipeline)) => {
+                            if entry.get().gfx_pipeline() == pipeline {
+                                // Unchanged, drop the extra reference from the pipeline cache
+                                renderer.destroy_deferred(pipeline);
+                            }
+                            entry.get_mut().set_pipeline(pipeline);
+                            set_depth_test(&mut depth_tests, ent, depth_testing);
+                        }
+                        Ok(None) => still_reloading.push(ent),
                         Err(e) => log::error!("Failed to compile pipeline: {}", e),
                     }
                 }
+                if updated.contains(ent) {
+                    if let RenderableMaterial::PBR {
+                        bindless_textures, ..
+                    } = entry.get_mut()
+                    {
+                        *bindless_textures = BindlessTextures::new(renderer, mat);
+                    }
+                    // With bindless textures, only the indices in the material uniforms change
+                    if !uses_bindless_textures(renderer, mat) {
+                        log::trace!("Updating material descriptor set for {:?}", ent);
+                        // The previous descriptor set is destroyed by
+                        // resource_tracking::release_unused
+                        let desc_set = create_material_descriptor_set(renderer, mat);
+                        entry.get_mut().set_material_descriptor_set(desc_set);
+                    }
+                }
             }
             StorageEntry::Vacant(entry) => {
                 log::trace!("No Renderable found, creating new");
-                let rend = create_renderable(renderer, world, mesh, mat);
+                tracker.release_removed_pipelines(ent, renderer);
+                let key = pipeline_key(
+                    renderer,
+                    mesh,
+                    mat,
+                    has_skin,
+                    num_morph_targets,
+                    object_buffer,
+                    depth_testing,
+                );
+                let (rend, state) = match get_pipeline_for(renderer, world, &key) {
+                    Ok(Some(pipeline)) => (
+                        create_renderable(renderer, world, mesh, mat, pipeline),
+                        RenderReadiness::Ready,
+                    ),
+                    Ok(None) => (
+                        create_fallback_renderable(renderer, world, &key),
+                        RenderReadiness::WaitingForPipeline,
+                    ),
+                    Err(e) => {
+                        log::error!("Failed to compile pipeline: {}", e);
+                        (
+                            create_fallback_renderable(renderer, world, &key),
+                            RenderReadiness::Failed,
+                        )
+                    }
+                };
                 entry.insert(rend);
+                set_readiness(&mut readiness, ent, state);
+                set_depth_test(&mut depth_tests, ent, depth_testing);
             }
         }
     }
 
+    // New entities whose material is still being uploaded. The renderable is created from the
+    // material above, once it is done.
+    for (ent, mesh, _, _) in (&entities, &meshes, &pending_materials, !&materials).join() {
+        if renderables.contains(ent) {
+            continue;
+        }
+        let key = PipelineKey {
+            shaders: MaterialShaders::Unlit,
+            vertex_format: vertex_format(renderer, mesh),
+            polygon_mode: mesh.polygon_mode,
+            depth_testing: RenderOrder::depth_testing(orders.get(ent)),
+        };
+        tracker.release_removed_pipelines(ent, renderer);
+        renderables
+            .insert(ent, create_fallback_renderable(renderer, world, &key))
+            .expect("Joined entities are alive");
+        set_readiness(&mut readiness, ent, RenderReadiness::WaitingForMaterial);
+        set_depth_test(&mut depth_tests, ent, key.depth_testing);
+    }
+
     should_reload.clear();
+    for ent in still_reloading {
+        should_reload
+            .insert(ent, ReloadMaterial)
+            .expect("Joined entities are alive");
+    }
+    updated.clear();
+
+    world
+        .write_resource::<pipeline_jobs::PipelineJobs>()
+        .release_finished(renderer);
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum DrawMode {
+    // Leaves out the transmissive materials
     Lit,
+    // Only the transmissive materials, see post::ScenePass
+    Transmissive,
     Unlit,
     ShadowsOnly,
+    // Depth from the camera, with the shadow render pass
+    DepthOnly,
 }
 
-#[profiling::function]
-fn draw_entities<'a>(world: &World, cmd_buf: &mut RenderPassEncoder<'a>, mode: DrawMode) {
-    let model_matrices = world.read_storage::<ModelMatrix>();
-    let meshes = world.read_storage::<GpuMesh>();
+/// If any of the drawn entities has a transmissive material
+fn has_transmissive(world: &World) -> bool {
+    let materials = world.read_storage::<material::PhysicallyBased>();
     let renderables = world.read_storage::<RenderableMaterial>();
-    use trekanten::pipeline::ShaderStage;
-
-    let mut prev_handle: Option<Handle<GraphicsPipeline>> = None;
-
-    // Only bind the pipeline if we need to
-    let mut bind_pipeline = |enc: &mut RenderPassEncoder<'a>, handle: &Handle<GraphicsPipeline>| {
-        if prev_handle.map(|h| h != *handle).unwrap_or(true) {
-            enc.bind_graphics_pipeline(handle);
-            prev_handle = Some(*handle);
-        }
-    };
+    let hidden = world.read_storage::<Hidden>();
+    (&materials, &renderables, !&hidden)
+        .join()
+        .any(|(material, _, _)| material.is_transmissive())
+}
 
-    for (mesh, renderable, mtx) in (&meshes, &renderables, &model_matrices).join() {
-        let tfm = uniform::Model {
-            model: mtx.0.into_col_array(),
-            model_it: mtx.0.inverted().transposed().into_col_array(),
-        };
+#[profiling::function]
+fn draw_entities<'a>(world: &World, cmd_buf: &mut RenderPassEncoder<'a>, mode: DrawMode) {
+    let mut stats = world.write_resource::<DrawStats>();
+    draw_list::draw(world, cmd_buf, mode, &mut stats);
+}
 
-        match (renderable, mode) {
-            (
-                RenderableMaterial::PBR {
-                    shadow_pipeline, ..
-                },
-                DrawMode::ShadowsOnly,
-            ) => {
-                bind_pipeline(cmd_buf, shadow_pipeline);
-                cmd_buf
-                    .bind_push_constant(shadow_pipeline, ShaderStage::VERTEX, &tfm)
-                    .draw_mesh(&mesh.vertex_buffer, &mesh.index_buffer);
-            }
-            (
-                RenderableMaterial::PBR {
-                    gfx_pipeline,
-                    material_descriptor_set,
-                    ..
-                },
-                DrawMode::Lit,
-            )
-            | (
-                RenderableMaterial::Unlit {
-                    gfx_pipeline,
-                    material_descriptor_set,
-                },
-                DrawMode::Unlit,
-            ) => {
-                bind_pipeline(cmd_buf, gfx_pipeline);
-                cmd_buf
-                    .bind_shader_resource_group(1, material_descriptor_set, gfx_pipeline)
-                    .bind_push_constant(gfx_pipeline, ShaderStage::VERTEX, &tfm)
-                    .draw_mesh(&mesh.vertex_buffer, &mesh.index_buffer);
-            }
-            _ => (),
-        }
+#[profiling::function]
+/// The textures that were replaced by the error texture, as their files could not be loaded, are
+/// listed in the Warnings window
+fn report_texture_failures(world: &World, renderer: &mut Renderer) {
+    let mut failures = renderer.take_texture_failures();
+    failures.extend(
+        world
+            .read_resource::<trekanten::Loader>()
+            .take_texture_failures(),
+    );
+    let mut warnings = world.write_resource::<crate::warnings::Warnings>();
+    for failure in failures {
+        warnings.push("Texture", Some(&failure.path), failure.error);
     }
 }
 
-#[profiling::function]
-pub fn draw_frame(world: &mut World, ui: &mut ui::UIContext, renderer: &mut Renderer) {
+/// Draws and submits a frame. Nothing is drawn without a camera. On errors, the frame is dropped
+/// part way and the renderer has to be recovered before the next one.
+pub fn draw_frame(
+    world: &mut World,
+    ui: &mut ui::UIContext,
+    renderer: &mut Renderer,
+) -> Result<(), RenderError> {
     let cam_entity = ecs::find_singleton_entity::<Camera>(world);
     if cam_entity.is_none() {
         log::warn!("Did not find a camera entity, can't render");
-        return;
+        return Ok(());
     }
 
-    GpuUpload::resolve_pending(world, renderer);
+    apply_msaa_sample_count(world, ui, renderer)?;
+    apply_object_buffer(world, renderer)?;
+    apply_shadow_resolution(world, renderer)?;
+    {
+        let frame_data = &mut *world.write_resource::<FrameData>();
+        let FrameData {
+            post, volumetric, ..
+        } = frame_data;
+        if post.resize(renderer) {
+            volumetric.recreate_targets(renderer, post.scene_color());
+            // For the transmission textures
+            frame_data.recreate_pbr_resource_group(renderer);
+        }
+    }
+    GpuUpload::resolve_pending(world, renderer)?;
+    report_texture_failures(world, renderer);
+    lod::update(world);
     create_renderables(renderer, world);
+    streaming::update(world);
+    virtual_texture::update(world, renderer);
+    aov::prepare(world, renderer);
+    deformation::create_deformations(renderer, world);
+    resource_tracking::release_unused(world, renderer);
+    {
+        let mut frame_data = world.write_resource::<FrameData>();
+        frame_data.thumbnails.release_recorded(renderer);
+        ui.generate_thumbnails(world, renderer, &mut frame_data.thumbnails);
+    }
+    ui.register_inspected_targets(world, renderer);
+    exposure::update_histogram(world, renderer);
+    let view_mode = view_mode::current(world);
+    {
+        let FrameData {
+            post,
+            wireframe,
+            review,
+            view_modes,
+            ..
+        } = &mut *world.write_resource::<FrameData>();
+        wireframe.prepare(world, renderer, post.scene_render_pass());
+        review.prepare(world, renderer, post.scene_render_pass());
+        view_modes.prepare(world, renderer, post.scene_render_pass(), view_mode);
+    }
+    let shaded = view_mode == view_mode::ViewMode::Shaded;
+    {
+        let frame_data = &mut *world.write_resource::<FrameData>();
+        if frame_data.shadow_mask.prepare(world, renderer) {
+            // The mask is bound with the lights
+            frame_data.recreate_pbr_resource_group(renderer);
+        }
+        frame_data.path_tracer.prepare(world, renderer);
+    }
+    world
+        .write_resource::<object_data::ObjectData>()
+        .prepare(world, renderer);
 
-    let aspect_ratio = renderer.aspect_ratio();
     let mut frame = match renderer.next_frame() {
         frame @ Ok(_) => frame,
         Err(trekanten::RenderError::NeedsResize(reason)) => {
             log::debug!("Resize reason: {:?}", reason);
             renderer
                 .resize(world.read_resource::<crate::io::MainWindow>().extents())
-                .expect("Failed to resize renderer");
+                .map_err(RenderError::Resize)?;
             renderer.next_frame()
         }
         e => e,
     }
-    .expect("Failed to get next frame");
+    .map_err(RenderError::NextFrame)?;
+    // After any resize in next_frame, so that the projection matches the swapchain
+    let aspect_ratio = {
+        let extent = frame.extent();
+        extent.width as f32 / extent.height as f32
+    };
+
+    world.insert(frame.frame_stats().clone());
+    world.insert(frame.resource_counts());
+    deformation::update_deformations(world, &mut frame).map_err(RenderError::Record)?;
+    material::update_uniforms(world, &mut frame);
+    world
+        .read_resource::<object_data::ObjectData>()
+        .upload(&mut frame);
+    world
+        .read_resource::<FrameData>()
+        .review
+        .upload(&mut frame)
+        .map_err(RenderError::Record)?;
+    world
+        .read_resource::<FrameData>()
+        .path_tracer
+        .upload(&mut frame)
+        .map_err(RenderError::Record)?;
 
     let ui_draw_commands = ui.build_ui(world, &mut frame);
+    // The ui shows the stats of the previous frame
+    *world.write_resource::<DrawStats>() = DrawStats::default();
+
+    world.write_resource::<FrameData>().debug_draw.upload(
+        &mut frame,
+        &mut world.write_resource::<debug_draw::DebugDraw>(),
+    );
 
     let frame_resources = &*world.write_resource::<FrameData>();
 
-    let cmd_buffer = frame
+    let mut cmd_buffer = frame
         .new_command_buffer()
-        .expect("Failed to create command buffer");
+        .map_err(|e| RenderError::Record(e.into()))?;
 
-    let mut cmd_buffer =
-        light::light_and_shadow_pass(world, &mut frame, &frame_resources, cmd_buffer);
+    let (view_matrix, view_pos) = get_view_data(world);
+    let view_proj = get_proj_matrix(world, aspect_ratio) * view_matrix;
+
+    let culling_view = light::LightCullingView {
+        view_proj,
+        view_pos,
+    };
+    // Before all passes that draw the deformed meshes
+    let timer = frame.begin_gpu_timer(&mut cmd_buffer, "Deformation");
+    cmd_buffer = frame_resources
+        .deformation
+        .record(world, &frame, cmd_buffer)
+        .map_err(RenderError::Record)?;
+    frame.end_gpu_timer(&mut cmd_buffer, timer);
+
+    // After the deformation, as the deformed meshes are traced with the vertices of this frame
+    let timer = frame.begin_gpu_timer(&mut cmd_buffer, "Path tracing");
+    cmd_buffer = frame_resources
+        .path_tracer
+        .record(&frame, cmd_buffer)
+        .map_err(RenderError::Record)?;
+    frame.end_gpu_timer(&mut cmd_buffer, timer);
+
+    let timer = frame.begin_gpu_timer(&mut cmd_buffer, "Shadows");
+    cmd_buffer = light::light_and_shadow_pass(
+        world,
+        &mut frame,
+        &frame_resources,
+        &culling_view,
+        cmd_buffer,
+    )
+    .map_err(RenderError::Record)?;
+    frame.end_gpu_timer(&mut cmd_buffer, timer);
+
+    // Before the ui samples them
+    cmd_buffer = frame_resources
+        .thumbnails
+        .record(&frame, cmd_buffer)
+        .map_err(RenderError::Record)?;
+
+    fog::update_fog_data(world, &mut frame, &frame_resources, view_pos);
 
     // View data main render pass
     {
-        let (view_matrix, view_pos) = get_view_data(world);
-        let view_proj = get_proj_matrix(aspect_ratio) * view_matrix;
         let view_data = uniform::ViewData {
             view_proj: view_proj.into_col_array(),
             view_pos: [view_pos.x, view_pos.y, view_pos.z, 1.0f32],
         };
 
-        frame
-            .update_uniform_blocking(&frame_resources.main_camera_view_data, &view_data)
-            .expect("Failed to update uniform");
+        let view_data = frame
+            .write_transient(&view_data)
+            .map_err(RenderError::Record)?;
+        frame_resources.bind_view_data(&mut frame, view_data);
     }
 
     {
-        // main render pass
+        let timer = frame.begin_gpu_timer(&mut cmd_buffer, "Virtual texture feedback");
+        cmd_buffer = world
+            .read_resource::<virtual_texture::VirtualTextures>()
+            .record_feedback(
+                &frame,
+                cmd_buffer,
+                &frame_resources.unlit_resources.shader_resource_group,
+            );
+        frame.end_gpu_timer(&mut cmd_buffer, timer);
+    }
+
+    cmd_buffer = world.read_resource::<aov::Aovs>().record(
+        &frame,
+        cmd_buffer,
+        &frame_resources.unlit_resources.shader_resource_group,
+    );
+
+    cmd_buffer = frame_resources
+        .post
+        .prepare_transmission(&frame, cmd_buffer);
+
+    {
+        // scene render pass
         let FrameData {
-            main_render_pass,
             unlit_resources,
             pbr_resources,
+            post,
+            debug_draw,
+            wireframe,
+            review,
+            view_modes,
             ..
         } = frame_resources;
-        let mut main_rp = frame
-            .begin_presentation_pass(cmd_buffer, main_render_pass)
-            .expect("Failed to begin render pass");
+        // The transmissive materials sample the rest of the scene, so they are drawn in a pass of
+        // their own after it
+        let transmissive = shaded && has_transmissive(world);
+        let first_pass = if transmissive {
+            post::ScenePass::Opaque
+        } else {
+            post::ScenePass::Whole
+        };
+        let timer = frame.begin_gpu_timer(&mut cmd_buffer, "Scene");
+        let mut scene_rp = post.begin_scene_pass(&frame, cmd_buffer, first_pass);
 
-        {
+        if shaded {
             let PhysicallyBasedUniformResources {
                 dummy_pipeline,
                 shader_resource_group,
                 ..
             } = &pbr_resources;
-            main_rp
+            scene_rp
                 .bind_graphics_pipeline(dummy_pipeline)
                 .bind_shader_resource_group(0u32, shader_resource_group, dummy_pipeline);
-            draw_entities(world, &mut main_rp, DrawMode::Lit);
+            draw_entities(world, &mut scene_rp, DrawMode::Lit);
         }
 
         {
             let UnlitFrameUniformResources {
                 dummy_pipeline,
                 shader_resource_group,
+                ..
             } = &unlit_resources;
-            main_rp
+            scene_rp
                 .bind_graphics_pipeline(dummy_pipeline)
                 .bind_shader_resource_group(0u32, shader_resource_group, dummy_pipeline);
-            draw_entities(world, &mut main_rp, DrawMode::Unlit);
+            if shaded {
+                draw_entities(world, &mut scene_rp, DrawMode::Unlit);
+            } else {
+                // The view mode replaces the pipelines of the entities
+                view_modes.record(&mut scene_rp, shader_resource_group);
+            }
+            wireframe.record(&mut scene_rp, shader_resource_group);
+            review.record(&mut scene_rp, shader_resource_group);
+            debug_draw.record(&mut scene_rp, shader_resource_group);
         }
 
+        cmd_buffer = scene_rp.end().map_err(|e| RenderError::Record(e.into()))?;
+
+        if transmissive {
+            let transmission_timer = frame.begin_gpu_timer(&mut cmd_buffer, "Transmission");
+            cmd_buffer = post.transmission_passes(&frame, cmd_buffer);
+            frame.end_gpu_timer(&mut cmd_buffer, transmission_timer);
+
+            let PhysicallyBasedUniformResources {
+                dummy_pipeline,
+                shader_resource_group,
+                ..
+            } = &pbr_resources;
+            let mut scene_rp =
+                post.begin_scene_pass(&frame, cmd_buffer, post::ScenePass::Transmissive);
+            scene_rp
+                .bind_graphics_pipeline(dummy_pipeline)
+                .bind_shader_resource_group(0u32, shader_resource_group, dummy_pipeline);
+            draw_entities(world, &mut scene_rp, DrawMode::Transmissive);
+            cmd_buffer = scene_rp.end().map_err(|e| RenderError::Record(e.into()))?;
+        }
+        frame.end_gpu_timer(&mut cmd_buffer, timer);
+    }
+
+    if shaded {
+        let timer = frame.begin_gpu_timer(&mut cmd_buffer, "Volumetric");
+        cmd_buffer = volumetric::volumetric_passes(
+            world,
+            &mut frame,
+            &frame_resources,
+            view_matrix,
+            view_pos,
+            aspect_ratio,
+            cmd_buffer,
+        );
+        frame.end_gpu_timer(&mut cmd_buffer, timer);
+    }
+
+    {
+        // main render pass
+        let FrameData {
+            main_render_pass,
+            post,
+            path_tracer,
+            ..
+        } = frame_resources;
+        let settings = world.read_resource::<debug_window::RenderSettings>();
+        let effects = world.read_resource::<post_effects::PostEffectStack>();
+        let time = world
+            .read_resource::<crate::time::Time>()
+            .elapsed_real()
+            .as_secs();
+        if shaded {
+            let timer = frame.begin_gpu_timer(&mut cmd_buffer, "Post-processing");
+            cmd_buffer = post.bloom_passes(&frame, cmd_buffer, &settings);
+            cmd_buffer = post.effect_passes(&frame, cmd_buffer, &settings, &effects, time);
+            frame.end_gpu_timer(&mut cmd_buffer, timer);
+        }
+        let reference = path_tracer
+            .shown(&settings)
+            .map(|texture| post::transient_source_descriptor_set(&mut frame, texture));
+        let mut main_rp = frame
+            .begin_presentation_pass(cmd_buffer, main_render_pass)
+            .map_err(|e| RenderError::Record(e.into()))?;
+
+        let timer = frame.begin_gpu_timer(main_rp.command_buffer_mut(), "Composite");
+        if shaded {
+            post.composite(&mut main_rp, &settings, &effects, reference.as_ref());
+        } else {
+            post.copy_scene(&mut main_rp);
+        }
+        frame.end_gpu_timer(main_rp.command_buffer_mut(), timer);
+
         if let Some(ui_draw_commands) = ui_draw_commands {
-            ui_draw_commands.record_draw_commands(&mut main_rp);
+            // Panorama faces are captured without the ui
+            if !world.has_value::<ViewOverride>() {
+                let timer = frame.begin_gpu_timer(main_rp.command_buffer_mut(), "UI");
+                ui_draw_commands.record_draw_commands(&mut main_rp);
+                frame.end_gpu_timer(main_rp.command_buffer_mut(), timer);
+            }
         }
 
-        cmd_buffer = main_rp.end().expect("Failed to end main presentation pass");
+        cmd_buffer = main_rp.end().map_err(|e| RenderError::Record(e.into()))?;
     }
     frame.add_command_buffer(cmd_buffer);
 
     let frame = frame.finish();
-    renderer
-        .submit(frame)
-        .or_else(|e| {
-            if let trekanten::RenderError::NeedsResize(reason) = e {
-                log::info!("Resize reason: {:?}", reason);
-                renderer.resize(world.read_resource::<crate::io::MainWindow>().extents())
-            } else {
-                Err(e)
-            }
-        })
-        .expect("Failed to submit frame");
+    match renderer.submit(frame) {
+        Err(trekanten::RenderError::NeedsResize(reason)) => {
+            log::info!("Resize reason: {:?}", reason);
+            renderer
+                .resize(world.read_resource::<crate::io::MainWindow>().extents())
+                .map_err(RenderError::Resize)
+        }
+        res => res.map_err(RenderError::Submit),
+    }
 }
 
-fn shadow_render_pass(renderer: &mut Renderer) -> Handle<trekanten::RenderPass> {
+fn shadow_render_pass(
+    renderer: &mut Renderer,
+) -> Result<Handle<trekanten::RenderPass>, RenderError> {
     use trekanten::raw_vk;
     let depth_attach = raw_vk::AttachmentDescription {
         format: raw_vk::Format::D16_UNORM,
@@ -625,21 +1771,23 @@ fn shadow_render_pass(renderer: &mut Renderer) -> Handle<trekanten::RenderPass>
 
     renderer
         .create_render_pass(&create_info)
-        .expect("Failed to create shadow render pass")
+        .map_err(|e| RenderError::setup("Failed to create shadow render pass", e))
 }
 
-// TODO: Runtime
-const SHADOW_MAP_EXTENT: trekanten::util::Extent2D = trekanten::util::Extent2D {
-    width: 1024,
-    height: 1024,
-};
+fn shadow_map_extent(resolution: u32) -> trekanten::util::Extent2D {
+    trekanten::util::Extent2D {
+        width: resolution,
+        height: resolution,
+    }
+}
 
 fn shadow_render_target(
     renderer: &mut Renderer,
     render_pass: &Handle<trekanten::RenderPass>,
-) -> (Handle<trekanten::Texture>, Handle<trekanten::RenderTarget>) {
+    extent: trekanten::util::Extent2D,
+    idx: usize,
+) -> Result<(Handle<trekanten::Texture>, Handle<trekanten::RenderTarget>), RenderError> {
     use trekanten::texture::{BorderColor, Filter, SamplerAddressMode};
-    let extent = SHADOW_MAP_EXTENT;
     let format = util::Format::D16_UNORM;
 
     let desc = TextureDescriptor::Empty {
@@ -655,216 +1803,326 @@ fn shadow_render_target(
     };
     let tex = renderer
         .create_texture(desc)
-        .expect("Failed to create texture for shadow map");
+        .map_err(|e| RenderError::setup("Failed to create texture for shadow map", e))?;
     let attachments = [&tex];
     let render_target = renderer
         .create_render_target(render_pass, &attachments)
-        .expect("Failed to create render target for shadow map");
-    (tex, render_target)
+        .map_err(|e| RenderError::setup("Failed to create render target for shadow map", e))?;
+    let name = format!("shadow map {}", idx);
+    renderer.set_debug_name(&tex, &name);
+    renderer.set_debug_name(&render_target, &name);
+    Ok((tex, render_target))
+}
+
+fn shadow_dummy_pipeline(
+    shader_compiler: &pipeline::ShaderCompiler,
+    renderer: &mut Renderer,
+    render_pass: &Handle<trekanten::RenderPass>,
+    object_buffer: bool,
+) -> Result<Handle<GraphicsPipeline>, RenderError> {
+    let pos_only_vertex_format = VertexFormat::builder()
+        .add_attribute(util::Format::FLOAT3)
+        .build();
+    let pipeline_desc = shadow_pipeline_desc(
+        shader_compiler,
+        pos_only_vertex_format,
+        trekanten::pipeline::TriangleCulling::Front,
+        object_buffer,
+    )
+    .map_err(|e| RenderError::setup("Failed to create pipeline descriptor for shadows", e))?;
+    renderer
+        .create_gfx_pipeline(pipeline_desc, render_pass)
+        .map_err(|e| RenderError::setup("Failed to create pipeline for shadows", e))
+}
+
+/// The view data, lights, shadows, fog and the scene behind transmissive materials that the PBR
+/// pipelines read from set 0
+fn pbr_shader_resource_group(
+    renderer: &mut Renderer,
+    fog_buffer: &BufferHandle<UniformBuffer>,
+    shadow_data: &ShadowData,
+    shadow_mask: &Handle<trekanten::Texture>,
+    transmission: [&Handle<trekanten::Texture>; 2],
+) -> Handle<DescriptorSet> {
+    use trekanten::pipeline::ShaderStage;
+    assert_eq!(uniform::LightingData::SET, uniform::ViewData::SET);
+    let texture_itr = shadow_data.spotlights.iter().map(|x| (x.texture, true));
+    DescriptorSet::builder(renderer)
+        .add_transient::<uniform::ViewData>(
+            uniform::ViewData::BINDING,
+            ShaderStage::VERTEX | ShaderStage::FRAGMENT,
+        )
+        .add_transient::<uniform::LightingData>(
+            uniform::LightingData::BINDING,
+            ShaderStage::FRAGMENT,
+        )
+        .add_textures(texture_itr, 2, ShaderStage::FRAGMENT)
+        .add_transient::<uniform::ShadowMatrices>(
+            uniform::ShadowMatrices::BINDING,
+            ShaderStage::VERTEX,
+        )
+        .add_buffer(fog_buffer, uniform::FogData::BINDING, ShaderStage::FRAGMENT)
+        .add_texture(shadow_mask, 5, ShaderStage::FRAGMENT, false)
+        .add_texture(transmission[0], 6, ShaderStage::FRAGMENT, false)
+        .add_texture(transmission[1], 7, ShaderStage::FRAGMENT, false)
+        .build()
 }
 
 fn build_shadow_data(
     shader_compiler: &pipeline::ShaderCompiler,
     renderer: &mut Renderer,
-) -> ShadowData {
+    object_buffer: bool,
+    resolution: u32,
+) -> Result<ShadowData, RenderError> {
     use uniform::UniformBlock as _;
 
-    let shadow_render_pass = shadow_render_pass(renderer);
-    let view_data = vec![
-        uniform::ViewData {
-            view_proj: [0.0; 16],
-            view_pos: [0.0; 4],
-        };
-        NUM_SPOTLIGHT_SHADOW_MAPS
-    ];
-    let view_data = OwningUniformBufferDescriptor::from_vec(view_data, BufferMutability::Mutable);
-    let view_data_buffer_handles = renderer
-        .create_resource_blocking(view_data)
-        .expect("FAIL")
-        .split();
+    let shadow_render_pass = shadow_render_pass(renderer)?;
+    renderer.set_debug_name(&shadow_render_pass, "shadow render pass");
+    let extent = shadow_map_extent(resolution);
     let spotlights: [SpotlightShadow; NUM_SPOTLIGHT_SHADOW_MAPS] = {
         let mut data: [MaybeUninit<SpotlightShadow>; NUM_SPOTLIGHT_SHADOW_MAPS] =
             unsafe { MaybeUninit::uninit().assume_init() };
         for i in 0..NUM_SPOTLIGHT_SHADOW_MAPS {
-            let (texture, render_target) = shadow_render_target(renderer, &shadow_render_pass);
-            let view_data_buffer = view_data_buffer_handles[i];
+            let (texture, render_target) =
+                shadow_render_target(renderer, &shadow_render_pass, extent, i)?;
+            // Written when the shadow map is rendered, see light_and_shadow_pass
             let sh_view_data_set = DescriptorSet::builder(renderer)
-                .add_buffer(
-                    &view_data_buffer,
+                .add_transient::<uniform::ViewData>(
                     uniform::ViewData::BINDING,
                     trekanten::pipeline::ShaderStage::VERTEX,
                 )
                 .build();
+            renderer.set_debug_name(&sh_view_data_set, &format!("shadow map {} view data", i));
             data[i] = MaybeUninit::new(SpotlightShadow {
                 texture,
                 render_target,
-                view_data_buffer,
                 view_data_desc_set: sh_view_data_set,
             });
         }
         unsafe { std::mem::transmute(data) }
     };
-    let shadow_dummy_pipeline = {
-        let pos_only_vertex_format = VertexFormat::builder()
-            .add_attribute(util::Format::FLOAT3)
-            .build();
-        let pipeline_desc = shadow_pipeline_desc(&shader_compiler, pos_only_vertex_format)
-            .expect("Failed to create graphics pipeline descriptor for shadows");
-        renderer
-            .create_gfx_pipeline(pipeline_desc, &shadow_render_pass)
-            .expect("Failed to create pipeline for shadow")
-    };
+    let dummy_pipeline = shadow_dummy_pipeline(
+        shader_compiler,
+        renderer,
+        &shadow_render_pass,
+        object_buffer,
+    )?;
 
-    ShadowData {
+    Ok(ShadowData {
         render_pass: shadow_render_pass,
-        dummy_pipeline: shadow_dummy_pipeline,
+        dummy_pipeline,
         spotlights,
-    }
+        extent,
+    })
 }
 
-pub fn setup_resources(world: &mut World, mut renderer: &mut Renderer) {
+/// Replaces the lost device of the renderer and creates the resources that were lost with it. The
+/// gpu components of the entities are removed, so that GpuUpload uploads their meshes and materials
+/// again from the cpu side components.
+pub fn recreate_device(
+    world: &mut World,
+    ui: &mut ui::UIContext,
+    renderer: &mut Renderer,
+) -> Result<(), RenderError> {
+    // These use the old device, so they have to be gone before it is destroyed
+    let loader = world.remove::<trekanten::Loader>();
+    drop(world.remove::<pipeline_jobs::PipelineJobs>());
+    renderer
+        .recreate_device(loader)
+        .map_err(RenderError::RecreateDevice)?;
+
+    world.write_storage::<GpuMesh>().clear();
+    world.write_storage::<PendingMesh>().clear();
+    world.write_storage::<GpuMaterial>().clear();
+    world.write_storage::<PendingMaterial>().clear();
+    world.write_storage::<MaterialUpdated>().clear();
+    world.write_storage::<RenderableMaterial>().clear();
+    world.write_storage::<RenderReadiness>().clear();
+    world.write_storage::<PipelineDepthTest>().clear();
+    world.write_storage::<deformation::GpuDeformation>().clear();
+    world.write_storage::<streaming::StreamedTextures>().clear();
+    world
+        .write_resource::<crate::asset::server::AssetServer>()
+        .reload_textures();
+
+    setup_resources(world, renderer)?;
+    ui.recreate_gpu_resources(world, renderer);
+    log::info!("Recreated the renderer resources after the device was lost");
+    Ok(())
+}
+
+/// Creates the resources that are used to draw every frame. The engine can't run without them.
+pub fn setup_resources(world: &mut World, mut renderer: &mut Renderer) -> Result<(), RenderError> {
     use trekanten::pipeline::ShaderStage;
     use uniform::UniformBlock as _;
 
     {
-        let shader_compiler =
-            pipeline::ShaderCompiler::new().expect("Failed to create shader compiler");
+        let shader_compiler = pipeline::ShaderCompiler::new()
+            .map_err(|e| RenderError::setup("Failed to create shader compiler", e))?;
+        let loader = renderer
+            .loader()
+            .ok_or_else(|| RenderError::Setup(String::from("The loader was already taken")))?;
 
         world.insert(shader_compiler);
-        world.insert(renderer.loader().unwrap());
+        world.insert(loader);
     }
+    world.insert(post_effects::PostEffectStack::default());
+    world.insert(streaming::TextureStreaming::new(renderer));
+    world.insert(virtual_texture::VirtualTextures::new());
+    world.insert(aov::Aovs::default());
+    world.insert(resource_tracking::GpuResourceTracker::default());
+    world.insert(MaterialPipelineCache::default());
+    world.insert(DrawStats::default());
+    world.insert(light::ShadowMapCache::default());
+    world.insert(material::MaterialUniforms::default());
+    world.insert(material::TextureCache::default());
+    world.insert(exposure::LuminanceHistogram::default());
+    world.insert(debug_draw::DebugDraw::default());
+    let (object_buffer, shadow_resolution) = {
+        let settings = world.read_resource::<debug_window::RenderSettings>();
+        (settings.object_buffer, settings.shadow_resolution)
+    };
+    world.insert(object_data::ObjectData::new(renderer, object_buffer));
 
     let frame_data = {
         let shader_compiler = world.read_resource::<pipeline::ShaderCompiler>();
 
         log::trace!("Creating frame gpu resources");
 
+        let msaa_sample_count = {
+            let mut settings = world.write_resource::<debug_window::RenderSettings>();
+            settings.set_supported_msaa_sample_counts(renderer.supported_msaa_sample_counts());
+            settings.msaa_sample_count
+        };
         let main_render_pass = renderer
-            .presentation_render_pass(8)
-            .expect("main render pass creation failed");
-
-        const N_VIEW_DATA: usize = 1;
-        let view_data = vec![
-            uniform::ViewData {
-                view_proj: [0.0; 16],
-                view_pos: [0.0; 4],
-            };
-            N_VIEW_DATA
-        ];
-        let view_data =
-            OwningUniformBufferDescriptor::from_vec(view_data, BufferMutability::Mutable);
-        let main_camera_view_data = renderer.create_resource_blocking(view_data).expect("FAIL");
-        let shadow_data = build_shadow_data(&shader_compiler, renderer);
+            .presentation_render_pass(msaa_sample_count)
+            .map_err(|e| RenderError::setup("Failed to create main render pass", e))?;
+
+        let shadow_data =
+            build_shadow_data(&shader_compiler, renderer, object_buffer, shadow_resolution)?;
+        let shadow_mask = shadow_bake::ShadowMaskTexture::new(renderer);
+        let post = post::PostProcessing::new(
+            &shader_compiler,
+            renderer,
+            &main_render_pass,
+            msaa_sample_count,
+        )
+        .map_err(|e| RenderError::setup("Failed to create post-processing", e))?;
 
         let pbr_resources = {
-            let vertex_format = VertexFormat::builder()
-                .add_attribute(util::Format::FLOAT3)
-                .add_attribute(util::Format::FLOAT3)
-                .build();
-
-            let result = pipeline::pbr_gltf::compile_default(&shader_compiler);
-            let (vert, frag) = match result {
-                Ok(r) => r,
-                Err(e) => {
-                    log::error!("{}", e);
-                    return;
-                }
-            };
-
-            let desc = GraphicsPipelineDescriptor::builder()
-                .vert(ShaderDescriptor::FromRawSpirv(vert.data()))
-                .frag(ShaderDescriptor::FromRawSpirv(frag.data()))
-                .vertex_format(vertex_format)
-                .build()
-                .expect("Failed to build graphics pipeline descriptor");
-            let dummy_pipeline = renderer
-                .create_gfx_pipeline(desc, &main_render_pass)
-                .expect("FAIL");
-
-            // TODO: Single elem uniform buffer here. Add to the same buffer?
-            let light_data = vec![uniform::LightingData {
-                punctual_lights: [uniform::PackedLight::default(); uniform::MAX_NUM_LIGHTS],
-                num_lights: 0,
-                ambient: [0.0; 4],
-            }];
-            let light_data =
-                OwningUniformBufferDescriptor::from_vec(light_data, BufferMutability::Mutable);
-            let light_buffer = renderer.create_resource_blocking(light_data).expect("FAIL");
-
-            let shadow_matrices = vec![uniform::ShadowMatrices {
-                matrices: [uniform::Mat4::default(); uniform::MAX_NUM_LIGHTS],
-                num_matrices: 0,
-            }];
-            let shadow_matrices =
-                OwningUniformBufferDescriptor::from_vec(shadow_matrices, BufferMutability::Mutable);
-            let shadow_matrices_buffer = renderer
-                .create_resource_blocking(shadow_matrices)
-                .expect("Failed to create shadow matrix uniform buffer");
-
-            assert_eq!(uniform::LightingData::SET, uniform::ViewData::SET);
-            let texture_itr = shadow_data.spotlights.iter().map(|x| (x.texture, true));
-            let shader_resource_group = DescriptorSet::builder(&mut renderer)
-                .add_buffer(
-                    &main_camera_view_data,
-                    uniform::ViewData::BINDING,
-                    ShaderStage::VERTEX | ShaderStage::FRAGMENT,
-                )
-                .add_buffer(
-                    &light_buffer,
-                    uniform::LightingData::BINDING,
-                    ShaderStage::FRAGMENT,
-                )
-                .add_textures(texture_itr, 2, ShaderStage::FRAGMENT)
-                .add_buffer(&shadow_matrices_buffer, 3, ShaderStage::VERTEX)
-                .build();
+            let dummy_pipeline = pbr_dummy_pipeline(
+                &shader_compiler,
+                renderer,
+                post.scene_render_pass(),
+                object_buffer,
+            )
+            .map_err(|e| RenderError::setup("Failed to create pbr dummy pipeline", e))?;
+
+            let fog_data = vec![uniform::FogData::default()];
+            let fog_data =
+                OwningUniformBufferDescriptor::from_vec(fog_data, BufferMutability::Mutable);
+            let fog_buffer = renderer
+                .create_resource_blocking(fog_data)
+                .map_err(|e| RenderError::setup("Failed to create fog uniform buffer", e))?;
+
+            let shader_resource_group = pbr_shader_resource_group(
+                renderer,
+                &fog_buffer,
+                &shadow_data,
+                shadow_mask.texture(),
+                post.transmission(),
+            );
 
             PhysicallyBasedUniformResources {
                 dummy_pipeline,
-                light_buffer,
-                shadow_matrices_buffer,
+                fog_buffer,
                 shader_resource_group,
             }
         };
 
         let unlit_resources = {
             let shader_resource_group = DescriptorSet::builder(&mut renderer)
-                .add_buffer(
-                    &main_camera_view_data,
-                    uniform::ViewData::BINDING,
-                    ShaderStage::VERTEX,
-                )
+                .add_transient::<uniform::ViewData>(uniform::ViewData::BINDING, ShaderStage::VERTEX)
                 .build();
 
-            let vertex_format = VertexFormat::builder()
-                .add_attribute(util::Format::FLOAT3)
-                .build();
-            let desc = unlit_pipeline_desc(
-                &shader_compiler,
-                vertex_format,
-                trekanten::pipeline::PolygonMode::Line,
-            )
-            .expect("Failed to create descriptor for unlit dummy pipeline");
-            let dummy_pipeline = renderer
-                .create_gfx_pipeline(desc, &main_render_pass)
-                .expect("Failed to create unlit dummy pipeline");
+            let dummy_pipeline =
+                unlit_dummy_pipeline(&shader_compiler, renderer, post.scene_render_pass())
+                    .map_err(|e| RenderError::setup("Failed to create unlit dummy pipeline", e))?;
+
+            let fallback_color = OwningUniformBufferDescriptor::from_vec(
+                vec![uniform::UnlitUniformData {
+                    color: [0.5, 0.5, 0.5, 1.0],
+                }],
+                BufferMutability::Immutable,
+            );
+            let fallback_color = renderer
+                .create_resource_blocking(fallback_color)
+                .map_err(|e| RenderError::setup("Failed to create fallback color", e))?;
 
             UnlitFrameUniformResources {
                 dummy_pipeline,
                 shader_resource_group,
+                fallback_color,
             }
         };
 
+        let volumetric = volumetric::VolumetricLighting::new(
+            &shader_compiler,
+            renderer,
+            &shadow_data,
+            post.scene_color(),
+        )
+        .map_err(|e| RenderError::setup("Failed to create volumetric lighting", e))?;
+
+        let debug_draw = debug_draw::DebugDrawRenderer::new(
+            &shader_compiler,
+            renderer,
+            post.scene_render_pass(),
+        )
+        .map_err(|e| RenderError::setup("Failed to create debug draw pipeline", e))?;
+        let wireframe = wireframe::WireframeRenderer::new(renderer);
+        let review = review::ReviewRenderer::default();
+        let deformation = deformation::DeformationPass::new(&shader_compiler, renderer)
+            .map_err(|e| RenderError::setup("Failed to create deformation pipeline", e))?;
+        let thumbnails = thumbnail::ThumbnailRenderer::new(&shader_compiler, renderer)
+            .map_err(|e| RenderError::setup("Failed to create thumbnail pipeline", e))?;
+        let path_tracer = path_trace::PathTracer::new(&shader_compiler, renderer)
+            .map_err(|e| RenderError::setup("Failed to create path tracing pipelines", e))?;
+
         FrameData {
             main_render_pass,
-            main_camera_view_data,
+            msaa_sample_count,
+            post,
             pbr_resources,
             unlit_resources,
             shadow: shadow_data,
+            shadow_mask,
+            volumetric,
+            debug_draw,
+            wireframe,
+            review,
+            view_modes: view_mode::ViewModeRenderer::default(),
+            path_tracer,
+            deformation,
+            thumbnails,
         }
     };
 
+    let mut inspector = inspector::RenderTargetInspector::default();
+    for (i, spotlight) in frame_data.shadow.spotlights.iter().enumerate() {
+        inspector.register(
+            format!("Spotlight shadow map {}", i),
+            spotlight.texture,
+            true,
+        );
+    }
+
+    let factory = renderer.pipeline_factory(frame_data.post.scene_render_pass());
+    world.insert(pipeline_jobs::PipelineJobs::new(factory));
     world.insert(frame_data);
+    world.insert(inspector);
     log::trace!("Done");
+    Ok(())
 }
 
 #[derive(Debug, Clone, Inspect)]
@@ -879,13 +2137,21 @@ impl GpuUpload {
 }
 impl GpuUpload {
     #[profiling::function]
-    fn resolve_pending(world: &mut World, renderer: &mut Renderer) {
+    fn resolve_pending(world: &mut World, renderer: &mut Renderer) -> Result<(), RenderError> {
         use trekanten::loader::HandleMapping;
         let mut loader = world.write_resource::<trekanten::Loader>();
         let mut pending_materials = world.write_storage::<PendingMaterial>();
         let mut pending_meshes = world.write_storage::<PendingMesh>();
         let mut materials = world.write_storage::<GpuMaterial>();
+        let mut updated_materials = world.write_storage::<MaterialUpdated>();
         let mut meshes = world.write_storage::<GpuMesh>();
+        let mut asset_server = world.write_resource::<crate::asset::server::AssetServer>();
+        let mut texture_cache = world.write_resource::<material::TextureCache>();
+        loader.set_upload_budget(
+            world
+                .read_resource::<debug_window::RenderSettings>()
+                .upload_budget(),
+        );
         let mut transfer_guard = loader.transfer(renderer);
         let mut generate_mipmaps = Vec::new();
         for mapping in transfer_guard.iter() {
@@ -918,7 +2184,12 @@ impl GpuUpload {
                                 .remove(ent)
                                 .expect("This is alive")
                                 .finish();
-                            materials.insert(ent, material).expect("This is alive");
+                            let prev = materials.insert(ent, material).expect("This is alive");
+                            if prev.is_some() {
+                                updated_materials
+                                    .insert(ent, MaterialUpdated)
+                                    .expect("This is alive");
+                            }
                         }
                     }
                 }
@@ -958,6 +2229,11 @@ impl GpuUpload {
                     }
                 }
                 HandleMapping::Texture { old, new } => {
+                    texture_cache.resolve(old, new);
+                    if asset_server.resolve_texture(old, new) {
+                        generate_mipmaps.push(new);
+                        continue;
+                    }
                     for (ent, _) in (&world.entities(), &pending_materials.mask().clone()).join() {
                         if let Some(pending) = pending_materials.get_mut(ent) {
                             match pending {
@@ -965,18 +2241,25 @@ impl GpuUpload {
                                     normal_map,
                                     base_color_texture,
                                     metallic_roughness_texture,
+                                    emissive_texture,
+                                    occlusion_texture,
                                     ..
                                 } => {
                                     for tex in &mut [
                                         normal_map,
                                         base_color_texture,
                                         metallic_roughness_texture,
+                                        emissive_texture,
+                                        occlusion_texture,
                                     ] {
                                         match tex {
                                             Some(Pending::Pending(tex_inner))
                                                 if tex_inner.handle == old =>
                                             {
-                                                generate_mipmaps.push(new);
+                                                // Shared textures are only generated once
+                                                if !generate_mipmaps.contains(&new) {
+                                                    generate_mipmaps.push(new);
+                                                }
                                                 **tex = Some(Pending::Available(
                                                     material::TextureUse {
                                                         handle: new,
@@ -1002,7 +2285,12 @@ impl GpuUpload {
                                 .expect("This is alive")
                                 .finish();
 
-                            materials.insert(ent, material).expect("This is alive");
+                            let prev = materials.insert(ent, material).expect("This is alive");
+                            if prev.is_some() {
+                                updated_materials
+                                    .insert(ent, MaterialUpdated)
+                                    .expect("This is alive");
+                            }
                         }
                     }
                 }
@@ -1011,7 +2299,7 @@ impl GpuUpload {
 
         renderer
             .generate_mipmaps(&generate_mipmaps)
-            .expect("Failed to generate mipmaps");
+            .map_err(RenderError::Upload)
     }
 }
 
@@ -1022,9 +2310,15 @@ impl<'a> System<'a> for GpuUpload {
         WriteStorage<'a, material::PhysicallyBased>,
         WriteStorage<'a, PendingMaterial>,
         WriteStorage<'a, GpuMaterial>,
+        WriteStorage<'a, material::ReloadTextures>,
         WriteStorage<'a, mesh::CpuMesh>,
         WriteStorage<'a, PendingMesh>,
         WriteStorage<'a, mesh::GpuMesh>,
+        ReadStorage<'a, mesh::SharedMesh>,
+        ReadStorage<'a, material::SharedMaterial>,
+        ReadExpect<'a, streaming::TextureStreaming>,
+        ReadStorage<'a, streaming::StreamedTextures>,
+        WriteExpect<'a, material::TextureCache>,
         Entities<'a>,
     );
 
@@ -1034,15 +2328,43 @@ impl<'a> System<'a> for GpuUpload {
         let (
             loader,
             unlit_materials,
-            physically_based_materials,
+            mut physically_based_materials,
             mut pending_mats,
-            gpu_materials,
-            cpu_meshes,
+            mut gpu_materials,
+            mut reload_textures,
+            mut cpu_meshes,
             mut pending_meshes,
-            gpu_meshes,
+            mut gpu_meshes,
+            shared_meshes,
+            shared_materials,
+            texture_streaming,
+            streamed_textures,
+            mut texture_cache,
             entities,
         ) = data;
 
+        {
+            // Entities with a shared mesh or material get their own copy of it, for the systems
+            // that read it
+            let missing: Vec<_> = (&entities, &shared_meshes, !&cpu_meshes)
+                .join()
+                .map(|(ent, shared, _)| (ent, (*shared.0).clone()))
+                .collect();
+            for (ent, mesh) in missing {
+                cpu_meshes.insert(ent, mesh).expect("This is alive");
+            }
+
+            let missing: Vec<_> = (&entities, &shared_materials, !&physically_based_materials)
+                .join()
+                .map(|(ent, shared, _)| (ent, (*shared.0).clone()))
+                .collect();
+            for (ent, material) in missing {
+                physically_based_materials
+                    .insert(ent, material)
+                    .expect("This is alive");
+            }
+        }
+
         {
             // Unlit
             let mut ubuf = Vec::new();
@@ -1054,13 +2376,20 @@ impl<'a> System<'a> for GpuUpload {
                 });
             }
 
-            if !ubuf.is_empty() {
-                let async_handle = loader
+            // The materials stay without a pending material if the load fails, so it is tried again
+            // in the next frame
+            let async_handle = if ubuf.is_empty() {
+                None
+            } else {
+                loader
                     .load(OwningUniformBufferDescriptor::from_vec(
                         ubuf,
                         BufferMutability::Immutable,
                     ))
-                    .expect("Failed to load uniform buffer");
+                    .map_err(|e| log::error!("Failed to load unlit material uniforms: {}", e))
+                    .ok()
+            };
+            if let Some(async_handle) = async_handle {
                 for (i, (ent, _unlit, _)) in (&entities, &unlit_materials, !&gpu_materials)
                     .join()
                     .enumerate()
@@ -1079,9 +2408,19 @@ impl<'a> System<'a> for GpuUpload {
         }
 
         {
-            // Physically based
-            let mut ubuf_pbr = Vec::new();
-            for (_, pb_mat, _, _) in (
+            // Physically based. Entities that share a material use the resources of one that has
+            // been, or is being, uploaded.
+            let mut shared_sources = HashMap::new();
+            for (ent, shared, _) in (&entities, &shared_materials, &pending_mats).join() {
+                shared_sources.insert(shared.key(), ent);
+            }
+            for (ent, shared, _) in (&entities, &shared_materials, &gpu_materials).join() {
+                shared_sources.insert(shared.key(), ent);
+            }
+
+            let mut upload = Vec::new();
+            let mut sharing = Vec::new();
+            for (ent, _, _, _) in (
                 &entities,
                 &physically_based_materials,
                 !&gpu_materials,
@@ -1089,73 +2428,259 @@ impl<'a> System<'a> for GpuUpload {
             )
                 .join()
             {
-                ubuf_pbr.push(uniform::PBRMaterialData {
-                    base_color_factor: pb_mat.base_color_factor.into_array(),
-                    metallic_factor: pb_mat.metallic_factor,
-                    roughness_factor: pb_mat.roughness_factor,
-                    normal_scale: pb_mat.normal_scale,
-                    _padding: 0.0,
-                });
+                match shared_materials.get(ent).map(material::SharedMaterial::key) {
+                    Some(key) if shared_sources.contains_key(&key) => sharing.push((ent, key)),
+                    Some(key) => {
+                        shared_sources.insert(key, ent);
+                        upload.push(ent);
+                    }
+                    None => upload.push(ent),
+                }
             }
 
-            let map_tex = |inp: &Option<material::TextureUse2>| -> Option<
-                Pending<
-                    material::TextureUse<resurs::Async<trekanten::texture::Texture>>,
-                    material::TextureUse<trekanten::texture::Texture>,
-                >,
-            > {
-                inp.as_ref().map(|tex| {
-                    let handle = loader
-                        .load(tex.desc.clone())
-                        .expect("Failed to load texture");
-                    Pending::Pending(material::TextureUse {
-                        coord_set: tex.coord_set,
-                        handle,
-                    })
-                })
+            let mut ubuf_pbr = Vec::new();
+            for ent in upload.iter() {
+                let pb_mat = physically_based_materials.get(*ent).expect("This is alive");
+                ubuf_pbr.push(uniform::PBRMaterialData::from(pb_mat));
+            }
+
+            // Changed files are read again, instead of using the cached texture
+            for reload in (&reload_textures).join() {
+                for path in reload.0.iter() {
+                    texture_cache.invalidate(path);
+                }
+            }
+
+            type PendingTexture = Pending<
+                material::TextureUse<resurs::Async<trekanten::texture::Texture>>,
+                material::TextureUse<trekanten::texture::Texture>,
+            >;
+            // Materials that use the same file share the texture. None if the texture could not be
+            // loaded, in which case the material is drawn without it.
+            let mut load_tex =
+                |tex: &material::TextureUse2, desc: TextureDescriptor| -> Option<PendingTexture> {
+                    let coord_set = tex.coord_set;
+                    match texture_cache.get(&desc) {
+                        Some(Pending::Available(handle)) => {
+                            Some(Pending::Available(material::TextureUse {
+                                coord_set,
+                                handle,
+                            }))
+                        }
+                        Some(Pending::Pending(handle)) => {
+                            Some(Pending::Pending(material::TextureUse { coord_set, handle }))
+                        }
+                        None => match loader.load(desc.clone()) {
+                            Ok(handle) => {
+                                texture_cache.insert_pending(&desc, handle);
+                                Some(Pending::Pending(material::TextureUse { coord_set, handle }))
+                            }
+                            Err(e) => {
+                                log::error!("Failed to load texture {:?}: {}", tex.path(), e);
+                                None
+                            }
+                        },
+                    }
+                };
+
+            // Streamed textures are uploaded later, see streaming::update
+            let mut map_tex = |pb_mat: &material::PhysicallyBased,
+                               slot: usize,
+                               inp: &Option<material::TextureUse2>|
+             -> Option<PendingTexture> {
+                match streaming::placeholder(&texture_streaming, pb_mat, slot) {
+                    Some(placeholder) => Some(Pending::Available(placeholder)),
+                    None => inp.as_ref().and_then(|tex| load_tex(tex, tex.desc.clone())),
+                }
             };
 
-            if !ubuf_pbr.is_empty() {
-                let async_handle = loader
+            // Mutable, so that edits of the materials can be written, see
+            // material::update_uniforms
+            let async_handle = if ubuf_pbr.is_empty() {
+                None
+            } else {
+                loader
                     .load(OwningUniformBufferDescriptor::from_vec(
                         ubuf_pbr,
-                        BufferMutability::Immutable,
+                        BufferMutability::Mutable,
                     ))
-                    .expect("Failed to load uniform buffer");
-                for (i, (ent, pb_mat, _)) in
-                    (&entities, &physically_based_materials, !&gpu_materials)
-                        .join()
-                        .enumerate()
+                    .map_err(|e| log::error!("Failed to load material uniforms: {}", e))
+                    .ok()
+            };
+            if let Some(async_handle) = async_handle {
+                for (i, ent) in upload.iter().enumerate() {
+                    let pb_mat = physically_based_materials.get(*ent).expect("This is alive");
+                    let pending = PendingMaterial::PBR {
+                        material_uniforms: Pending::Pending(BufferHandle::sub_buffer(
+                            async_handle,
+                            i as u32,
+                            1,
+                        )),
+                        normal_map: map_tex(pb_mat, 0, &pb_mat.normal_map),
+                        base_color_texture: map_tex(pb_mat, 1, &pb_mat.base_color_texture),
+                        metallic_roughness_texture: map_tex(
+                            pb_mat,
+                            2,
+                            &pb_mat.metallic_roughness_texture,
+                        ),
+                        emissive_texture: map_tex(pb_mat, 3, &pb_mat.emissive_texture),
+                        occlusion_texture: map_tex(pb_mat, 4, &pb_mat.occlusion_texture),
+                        has_vertex_colors: pb_mat.has_vertex_colors,
+                    };
+                    pending_mats.insert(*ent, pending).expect("This is alive");
+                }
+            }
+
+            for (ent, key) in sharing {
+                let source = shared_sources[&key];
+                let material = match (pending_mats.get(source), gpu_materials.get(source)) {
+                    (Some(pending), _) => pending.clone(),
+                    (None, Some(gpu)) => PendingMaterial::from(gpu),
+                    // The upload of the source failed and is tried again in the next frame
+                    (None, None) => continue,
+                };
+                if material.is_done() {
+                    gpu_materials
+                        .insert(ent, material.finish())
+                        .expect("This is alive");
+                } else {
+                    pending_mats.insert(ent, material).expect("This is alive");
+                }
+            }
+
+            // Only upload the textures that changed. The current GpuMaterial is used until they
+            // are available, after which it is replaced.
+            for (ent, pb_mat, gpu_mat, reload) in (
+                &entities,
+                &physically_based_materials,
+                &gpu_materials,
+                &reload_textures,
+            )
+                .join()
+            {
+                let is_changed = |tex: &material::TextureUse2| {
+                    tex.path()
+                        .map(|path| reload.0.iter().any(|p| p == path))
+                        .unwrap_or(false)
+                };
+                if !pb_mat.textures().any(is_changed) {
+                    continue;
+                }
+
+                if let GpuMaterial::PBR {
+                    material_uniforms,
+                    normal_map,
+                    base_color_texture,
+                    metallic_roughness_texture,
+                    emissive_texture,
+                    occlusion_texture,
+                    has_vertex_colors,
+                } = gpu_mat
                 {
-                    if let StorageEntry::Vacant(entry) = pending_mats.entry(ent).unwrap() {
-                        entry.insert(PendingMaterial::PBR {
-                            material_uniforms: Pending::Pending(BufferHandle::sub_buffer(
-                                async_handle,
-                                i as u32,
-                                1,
-                            )),
-                            normal_map: map_tex(&pb_mat.normal_map),
-                            base_color_texture: map_tex(&pb_mat.base_color_texture),
-                            metallic_roughness_texture: map_tex(&pb_mat.metallic_roughness_texture),
-                            has_vertex_colors: pb_mat.has_vertex_colors,
+                    // Streamed textures are reloaded at the mip level they are at
+                    let mut reload_tex = |slot: usize,
+                                          src: &Option<material::TextureUse2>,
+                                          cur: &Option<
+                        material::TextureUse<trekanten::texture::Texture>,
+                    >| {
+                        let changed = src.as_ref().map(is_changed).unwrap_or(false);
+                        let desc = src.as_ref().and_then(|tex| {
+                            streaming::reload_descriptor(streamed_textures.get(ent), tex, slot)
+                                .map(|desc| (tex, desc))
                         });
-                    }
+                        match (cur, desc) {
+                            (Some(cur), _) if !changed => Some(Pending::Available(cur.clone())),
+                            (cur, Some((tex, desc))) => {
+                                load_tex(tex, desc).or_else(|| cur.clone().map(Pending::Available))
+                            }
+                            (cur, None) => cur.clone().map(Pending::Available),
+                        }
+                    };
+
+                    pending_mats
+                        .insert(
+                            ent,
+                            PendingMaterial::PBR {
+                                material_uniforms: Pending::Available(*material_uniforms),
+                                normal_map: reload_tex(0, &pb_mat.normal_map, normal_map),
+                                base_color_texture: reload_tex(
+                                    1,
+                                    &pb_mat.base_color_texture,
+                                    base_color_texture,
+                                ),
+                                metallic_roughness_texture: reload_tex(
+                                    2,
+                                    &pb_mat.metallic_roughness_texture,
+                                    metallic_roughness_texture,
+                                ),
+                                emissive_texture: reload_tex(
+                                    3,
+                                    &pb_mat.emissive_texture,
+                                    emissive_texture,
+                                ),
+                                occlusion_texture: reload_tex(
+                                    4,
+                                    &pb_mat.occlusion_texture,
+                                    occlusion_texture,
+                                ),
+                                has_vertex_colors: *has_vertex_colors,
+                            },
+                        )
+                        .expect("This is alive");
                 }
             }
+            reload_textures.clear();
         }
 
+        // Entities that share a mesh use the buffers of one that has been, or is being, uploaded
+        let mut shared_sources = HashMap::new();
+        for (shared, pending) in (&shared_meshes, &pending_meshes).join() {
+            shared_sources.insert(shared.key(), pending.clone());
+        }
+        for (shared, gpu) in (&shared_meshes, &gpu_meshes).join() {
+            shared_sources.insert(shared.key(), PendingMesh::from(gpu));
+        }
+
+        let mut finished = Vec::new();
         for (ent, mesh, _) in (&entities, &cpu_meshes, !&gpu_meshes).join() {
             if let StorageEntry::Vacant(entry) = pending_meshes.entry(ent).unwrap() {
-                entry.insert(PendingMesh::load(&loader, &mesh));
+                let pending = match shared_meshes.get(ent) {
+                    Some(shared) => shared_sources
+                        .entry(shared.key())
+                        .or_insert_with(|| PendingMesh::load(&loader, &mesh))
+                        .clone(),
+                    None => PendingMesh::load(&loader, &mesh),
+                };
+                match pending.try_finish() {
+                    Some(gpu) => finished.push((ent, gpu)),
+                    None => {
+                        entry.insert(pending);
+                    }
+                }
             }
         }
+        for (ent, gpu) in finished {
+            gpu_meshes.insert(ent, gpu).expect("This is alive");
+        }
     }
 }
 
 pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
-    register_module_systems!(builder, debug_window, bounding_box, light).with(
-        GpuUpload,
-        GpuUpload::ID,
-        &[],
-    )
+    register_module_systems!(builder, debug_window, bounding_box, light, curve)
+        .with(
+            light_commands::LightCommandSys,
+            light_commands::LightCommandSys::ID,
+            &[],
+        )
+        .with(GpuUpload, GpuUpload::ID, &[])
+        .with(
+            shadow_bake::BakeShadowMask,
+            shadow_bake::BakeShadowMask::ID,
+            &[],
+        )
+        .with(
+            shader_watcher::ShaderWatcher::default(),
+            shader_watcher::ShaderWatcher::ID,
+            &[],
+        )
 }
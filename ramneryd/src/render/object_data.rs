@@ -0,0 +1,149 @@
+//! The model matrices of the entities that are drawn, written to a storage buffer once per frame.
+//! The vertex shaders that are compiled with OBJECT_BUFFER read their matrices from it, with the
+//! index of the entity as the only push constant, so the per-object data is not limited by the
+//! push constant size and is shared by all passes of the frame. This is used by the PBR, shadow and
+//! depth pipelines. With the `r.object_buffer` cvar off, the matrices are pushed before each draw
+//! instead.
+//!
+//! The buffer is bound at set 3, as the sets below it differ between the pipelines, and so it is
+//! bound again after each pipeline change, see draw_list.
+
+use std::collections::HashMap;
+
+use trekanten::descriptor::DescriptorSet;
+use trekanten::mem::{BufferMutability, OwningStorageBufferDescriptor, StorageBuffer};
+use trekanten::pipeline::ShaderStage;
+use trekanten::resource::{Handle, ResourceManager};
+use trekanten::{BufferHandle, Frame, Renderer};
+
+use crate::ecs::prelude::*;
+use crate::math::ModelMatrix;
+
+use super::mesh::GpuMesh;
+use super::uniform::{self, UniformBlock as _};
+use super::{Hidden, RenderableMaterial};
+
+const INITIAL_CAPACITY: usize = 1024;
+
+pub struct ObjectData {
+    enabled: bool,
+    capacity: usize,
+    buffer: BufferHandle<StorageBuffer>,
+    descriptor_set: Handle<DescriptorSet>,
+    models: Vec<uniform::Model>,
+    indices: HashMap<Entity, u32>,
+}
+
+fn create_buffer(
+    renderer: &mut Renderer,
+    capacity: usize,
+) -> (BufferHandle<StorageBuffer>, Handle<DescriptorSet>) {
+    let models = vec![
+        uniform::Model {
+            model: [0.0; 16],
+            model_it: [0.0; 16],
+        };
+        capacity
+    ];
+    let buffer = renderer
+        .create_resource_blocking(OwningStorageBufferDescriptor::from_vec(
+            models,
+            BufferMutability::Mutable,
+        ))
+        .expect("Failed to create object buffer");
+    let descriptor_set = DescriptorSet::builder(renderer)
+        .add_storage_buffer(&buffer, uniform::Model::BINDING, ShaderStage::VERTEX)
+        .build();
+
+    (buffer, descriptor_set)
+}
+
+impl ObjectData {
+    pub fn new(renderer: &mut Renderer, enabled: bool) -> Self {
+        let (buffer, descriptor_set) = create_buffer(renderer, INITIAL_CAPACITY);
+        Self {
+            enabled,
+            capacity: INITIAL_CAPACITY,
+            buffer,
+            descriptor_set,
+            models: Vec::new(),
+            indices: HashMap::new(),
+        }
+    }
+
+    /// Whether the pipelines of the entities read their model matrix from the buffer
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The pipelines that depend on this have to be recreated after changing it
+    pub(super) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.models.clear();
+        self.indices.clear();
+    }
+
+    /// Collects the model matrices of the entities that can be drawn this frame. The buffer is
+    /// recreated if they don't fit, so this has to be done before the frame is started.
+    pub(super) fn prepare(&mut self, world: &World, renderer: &mut Renderer) {
+        self.models.clear();
+        self.indices.clear();
+        if !self.enabled {
+            return;
+        }
+
+        let model_matrices = world.read_storage::<ModelMatrix>();
+        let meshes = world.read_storage::<GpuMesh>();
+        let renderables = world.read_storage::<RenderableMaterial>();
+        let hidden = world.read_storage::<Hidden>();
+        for (entity, mtx, _, _, _) in (
+            &world.entities(),
+            &model_matrices,
+            &meshes,
+            &renderables,
+            !&hidden,
+        )
+            .join()
+        {
+            self.indices.insert(entity, self.models.len() as u32);
+            self.models.push(uniform::Model {
+                model: mtx.0.into_col_array(),
+                model_it: mtx.0.inverted().transposed().into_col_array(),
+            });
+        }
+
+        if self.models.len() > self.capacity {
+            let capacity = self.models.len().next_power_of_two();
+            log::debug!(
+                "Growing the object buffer from {} to {} objects",
+                self.capacity,
+                capacity
+            );
+            renderer.destroy_deferred(self.descriptor_set);
+            renderer.destroy_deferred(self.buffer);
+            let (buffer, descriptor_set) = create_buffer(renderer, capacity);
+            self.buffer = buffer;
+            self.descriptor_set = descriptor_set;
+            self.capacity = capacity;
+        }
+    }
+
+    pub(super) fn upload(&self, frame: &mut Frame) {
+        if self.models.is_empty() {
+            return;
+        }
+
+        frame
+            .update_storage_blocking(&self.buffer, &self.models)
+            .expect("Failed to update the object buffer");
+    }
+
+    /// The index of the model matrix of the entity, if it was prepared this frame
+    pub fn index(&self, entity: Entity) -> Option<u32> {
+        self.indices.get(&entity).copied()
+    }
+
+    pub fn descriptor_set(&self) -> &Handle<DescriptorSet> {
+        &self.descriptor_set
+    }
+}
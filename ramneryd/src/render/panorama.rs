@@ -0,0 +1,268 @@
+//! 360 degree panoramas, captured by rendering the six faces of a cube around the camera and
+//! projecting them to an equirectangular image that panorama viewers can show.
+
+use image::{Rgba, RgbaImage};
+use thiserror::Error;
+
+use trekanten::Renderer;
+
+use crate::camera::{Camera, CameraRotationState, FreeFlyCameraController};
+use crate::ecs;
+use crate::ecs::prelude::*;
+use crate::math::{look_to, Transform, Vec3};
+
+use super::ui::UIContext;
+use super::ViewOverride;
+
+#[derive(Debug, Error)]
+pub enum PanoramaError {
+    #[error("There is no camera to capture the panorama from")]
+    NoCamera,
+    #[error("Only headless rendering can be captured")]
+    NotHeadless,
+    #[error("Failed to draw a cube face: {0}")]
+    Draw(#[from] super::RenderError),
+    #[error("Failed to read back a cube face: {0}")]
+    Readback(#[from] trekanten::RenderError),
+}
+
+/// One side of the cube, rendered with a 90 degree field of view
+struct CubeFace {
+    forward: Vec3,
+    up: Vec3,
+    image: RgbaImage,
+}
+
+impl CubeFace {
+    fn right(&self) -> Vec3 {
+        self.forward.cross(self.up).normalized()
+    }
+}
+
+/// The directions of the faces, starting with the center of the panorama. Top and bottom have the
+/// front below respectively above them.
+fn face_directions(forward: Vec3, up: Vec3) -> [(Vec3, Vec3); 6] {
+    let right = forward.cross(up).normalized();
+    [
+        (forward, up),
+        (right, up),
+        (-forward, up),
+        (-right, up),
+        (up, -forward),
+        (-up, forward),
+    ]
+}
+
+fn bilinear(image: &RgbaImage, x: f32, y: f32) -> Rgba<u8> {
+    let max_x = image.width() - 1;
+    let max_y = image.height() - 1;
+    let x = x.max(0.0).min(max_x as f32);
+    let y = y.max(0.0).min(max_y as f32);
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(max_x), (y0 + 1).min(max_y));
+    let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+
+    let mut out = [0u8; 4];
+    for (c, out) in out.iter_mut().enumerate() {
+        let at = |x, y| image.get_pixel(x, y)[c] as f32;
+        let top = at(x0, y0) * (1.0 - tx) + at(x1, y0) * tx;
+        let bottom = at(x0, y1) * (1.0 - tx) + at(x1, y1) * tx;
+        *out = (top * (1.0 - ty) + bottom * ty).round() as u8;
+    }
+    Rgba(out)
+}
+
+/// The color seen in the direction, from the face that it points most towards
+fn sample(faces: &[CubeFace], dir: Vec3) -> Rgba<u8> {
+    let face = faces
+        .iter()
+        .max_by(|a, b| {
+            a.forward
+                .dot(dir)
+                .partial_cmp(&b.forward.dot(dir))
+                .expect("Directions are finite")
+        })
+        .expect("There are faces to sample");
+
+    let right = face.right();
+    let up = right.cross(face.forward);
+    let depth = face.forward.dot(dir);
+    // In [-1, 1] across the face, as the field of view is 90 degrees
+    let x = right.dot(dir) / depth;
+    let y = up.dot(dir) / depth;
+
+    // The top row of the image is up
+    let size = face.image.width() as f32;
+    bilinear(
+        &face.image,
+        (x + 1.0) * 0.5 * size - 0.5,
+        (1.0 - y) * 0.5 * size - 0.5,
+    )
+}
+
+/// Project the faces to an image that is width wide and half as high, centered on the first face
+fn equirectangular(faces: &[CubeFace], width: u32) -> RgbaImage {
+    let height = width / 2;
+    let forward = faces[0].forward;
+    let up = faces[0].up;
+    let right = faces[0].right();
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let lon = ((x as f32 + 0.5) / width as f32 - 0.5) * 2.0 * std::f32::consts::PI;
+        let lat = (0.5 - (y as f32 + 0.5) / height as f32) * std::f32::consts::PI;
+        let dir = (forward * lon.cos() + right * lon.sin()) * lat.cos() + up * lat.sin();
+        sample(faces, dir)
+    })
+}
+
+fn render_faces(
+    world: &mut World,
+    ui: &mut UIContext,
+    renderer: &mut Renderer,
+    pos: Vec3,
+    forward: Vec3,
+    eye_offset: f32,
+) -> Result<Vec<CubeFace>, PanoramaError> {
+    let mut faces = Vec::with_capacity(6);
+    for (forward, up) in face_directions(forward, Vec3::unit_y()).iter().copied() {
+        let right = forward.cross(up).normalized();
+        let pos = pos + right * eye_offset;
+        world.insert(ViewOverride {
+            view: look_to(pos, forward, up),
+            pos,
+        });
+        super::draw_frame(world, ui, renderer)?;
+
+        let image = renderer
+            .read_presented_image()?
+            .ok_or(PanoramaError::NotHeadless)?;
+        // The vertical field of view is 90 degrees, so the center square is the face
+        let size = image.width().min(image.height());
+        let image = image::imageops::crop_imm(
+            &image,
+            (image.width() - size) / 2,
+            (image.height() - size) / 2,
+            size,
+            size,
+        )
+        .to_image();
+        faces.push(CubeFace { forward, up, image });
+    }
+
+    Ok(faces)
+}
+
+/// Render a panorama from the position of the camera, centered on the direction it is looking in.
+/// With an eye separation, the left and right eye panoramas are stacked top to bottom. Each face is
+/// rendered with the eyes offset along its own right direction, which approximates stereo well
+/// enough for viewers but is not correct towards the poles. Requires a headless renderer, as the
+/// faces are read back from the presented image.
+pub(crate) fn capture(
+    world: &mut World,
+    ui: &mut UIContext,
+    renderer: &mut Renderer,
+    eye_separation: Option<f32>,
+) -> Result<RgbaImage, PanoramaError> {
+    let camera_entity =
+        ecs::find_singleton_entity::<Camera>(world).ok_or(PanoramaError::NoCamera)?;
+    let pos = world
+        .read_storage::<Transform>()
+        .get(camera_entity)
+        .ok_or(PanoramaError::NoCamera)?
+        .position;
+    let view_dir = world
+        .read_storage::<CameraRotationState>()
+        .get(camera_entity)
+        .map(|rot| FreeFlyCameraController::get_orientation_from(rot).view_direction)
+        .ok_or(PanoramaError::NoCamera)?;
+    // Level with the horizon, so that the panorama is not tilted
+    let forward = Vec3::new(view_dir.x, 0.0, view_dir.z);
+    let forward = if forward.magnitude_squared() > 0.0001 {
+        forward.normalized()
+    } else {
+        -Vec3::unit_z()
+    };
+
+    let eye_offsets = match eye_separation {
+        Some(sep) => vec![-sep / 2.0, sep / 2.0],
+        None => vec![0.0],
+    };
+
+    let mut eyes = Vec::with_capacity(eye_offsets.len());
+    for offset in eye_offsets {
+        match render_faces(world, ui, renderer, pos, forward, offset) {
+            Ok(faces) => {
+                let width = faces[0].image.width() * 4;
+                eyes.push(equirectangular(&faces, width));
+            }
+            Err(e) => {
+                world.remove::<ViewOverride>();
+                return Err(e);
+            }
+        }
+    }
+    world.remove::<ViewOverride>();
+
+    let (width, height) = eyes[0].dimensions();
+    let mut out = RgbaImage::new(width, height * eyes.len() as u32);
+    for (i, eye) in eyes.iter().enumerate() {
+        image::imageops::replace(&mut out, eye, 0, i as u32 * height);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid_faces() -> Vec<CubeFace> {
+        let colors = [
+            [255, 0, 0, 255],
+            [0, 255, 0, 255],
+            [0, 0, 255, 255],
+            [255, 255, 0, 255],
+            [255, 255, 255, 255],
+            [0, 0, 0, 255],
+        ];
+        face_directions(-Vec3::unit_z(), Vec3::unit_y())
+            .iter()
+            .zip(colors.iter())
+            .map(|(&(forward, up), &color)| CubeFace {
+                forward,
+                up,
+                image: RgbaImage::from_pixel(8, 8, Rgba(color)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn equirectangular_layout() {
+        let faces = solid_faces();
+        let pano = equirectangular(&faces, 64);
+        assert_eq!(pano.dimensions(), (64, 32));
+
+        let equator = 16;
+        // Front in the center, right a quarter turn to the right and back at the edges
+        assert_eq!(pano.get_pixel(32, equator), faces[0].image.get_pixel(0, 0));
+        assert_eq!(pano.get_pixel(48, equator), faces[1].image.get_pixel(0, 0));
+        assert_eq!(pano.get_pixel(0, equator), faces[2].image.get_pixel(0, 0));
+        assert_eq!(pano.get_pixel(16, equator), faces[3].image.get_pixel(0, 0));
+        assert_eq!(pano.get_pixel(10, 0), faces[4].image.get_pixel(0, 0));
+        assert_eq!(pano.get_pixel(50, 31), faces[5].image.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn face_images_are_upright() {
+        let mut faces = solid_faces();
+        // Top half of the front face is white
+        for (_, y, p) in faces[0].image.enumerate_pixels_mut() {
+            if y < 4 {
+                *p = Rgba([255, 255, 255, 255]);
+            }
+        }
+        let pano = equirectangular(&faces, 64);
+        assert_eq!(*pano.get_pixel(32, 12), Rgba([255, 255, 255, 255]));
+        assert_eq!(*pano.get_pixel(32, 20), Rgba([255, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,1020 @@
+//! A progressive path tracer, for a reference image of the view to validate the PBR shader against.
+//! It traces the view of the camera at a quarter of the resolution, a budget of rays per frame, and
+//! averages the samples of each pixel until the view or the scene changes. The image can be shown
+//! instead of the rasterized scene, through the same composite pass, to compare the two, see
+//! RenderSettings::path_trace_show.
+//!
+//! The paths are traced by a compute shader, trace_comp.glsl, that adds each sample to the sum of
+//! its pixel in a storage buffer and writes the average to a storage image. The triangles of the
+//! meshes with a PhysicallyBased material are put in a spatial::Bvh in world space when the scene
+//! changes, which is flattened into a storage buffer together with the vertices, triangles and
+//! materials. The topology of the BVH is built from the rest pose. Skinned and morphed meshes are
+//! traced with the vertices of the deformation pre-pass, which pose_comp.glsl copies into the
+//! vertices of the tracer, after which refit_comp.glsl computes the boxes of the BVH again.
+//!
+//! The textures of the materials are sampled through the bindless array, if the device supports it,
+//! and only their factors are used otherwise. Transmission is ignored. The lights are sampled at
+//! every bounce with hard shadows, and the ambient light is what the rays that escape the scene
+//! see, so unlike the rasterized scene it is occluded.
+
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryInto;
+use std::hash::{Hash, Hasher};
+
+use trekanten::descriptor::DescriptorSet;
+use trekanten::mem::{
+    BufferDescriptor as _, BufferMutability, IndexSize, OwningStorageBufferDescriptor,
+    StorageBuffer, Uniform, VertexBuffer,
+};
+use trekanten::pipeline::{
+    ComputePipeline, ComputePipelineDescriptor, PolygonMode, ShaderDescriptor, ShaderStage,
+};
+use trekanten::raw_vk;
+use trekanten::resource::Handle;
+use trekanten::texture::{Texture, TextureDescriptor, TextureUsage};
+use trekanten::util::{Extent2D, Format};
+use trekanten::{BufferHandle, CommandBuffer, Destroyable, Frame, Renderer};
+
+use crate::anim::{MorphWeights, Skeleton};
+use crate::ecs::prelude::*;
+use crate::math::{BoundingBox, Mat4, ModelMatrix, Transform, Vec3, Vec4};
+use crate::spatial::{Bvh, FlatNodeKind};
+
+use super::debug_window::RenderSettings;
+use super::deformation::GpuDeformation;
+use super::light::Light;
+use super::material::{GpuMaterial, PhysicallyBased};
+use super::mesh::CpuMesh;
+use super::pipeline::{Defines, ShaderCompiler, ShaderType};
+use super::uniform::{PackedLight, PathTraceData, UniformBlock as _, MAX_NUM_LIGHTS};
+use super::{Hidden, MaterialError};
+
+/// The traced image is this many times smaller than the presented one, along each axis
+const RESOLUTION_DIVISOR: u32 = 4;
+/// The rays of a frame are capped to this many samples of each pixel, so that a budget far above
+/// the number of pixels doesn't record a dispatch for each pass
+const MAX_PASSES_PER_FRAME: u64 = 8;
+// Has to match local_size_x in the path_trace shaders
+const WORKGROUP_SIZE: u32 = 64;
+/// The set of the bindless texture array in trace_comp.glsl
+const BINDLESS_TEXTURES_SET: u32 = 1;
+/// The texture index of the materials that don't have the texture
+const NO_TEXTURE: u32 = u32::MAX;
+
+/// A vertex in world space. The normal is zero if the mesh has none, and the face normal is used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C, packed)]
+struct TraceVertex {
+    position: [f32; 4],
+    normal: [f32; 4],
+    /// .xy is the first set of texture coordinates
+    uv: [f32; 4],
+}
+
+impl Uniform for TraceVertex {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C, packed)]
+struct TraceTriangle {
+    /// .xyz are the vertices and .w the material
+    indices: [u32; 4],
+}
+
+impl Uniform for TraceTriangle {}
+
+/// A node of the flattened BVH, see spatial::Bvh::flatten. Inner nodes have a count of 0 and their
+/// children at first and first + 1, leaves the triangles first..first + count.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C, packed)]
+struct TraceNode {
+    min: [f32; 3],
+    first: u32,
+    max: [f32; 3],
+    count: u32,
+}
+
+impl Uniform for TraceNode {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C, packed)]
+struct TraceMaterial {
+    base_color_factor: [f32; 4],
+    emissive_factor: [f32; 4],
+    /// .x is the metallic and .y the roughness factor
+    metallic_roughness: [f32; 4],
+    /// The slots in the bindless array of the base color, metallic roughness and emissive textures,
+    /// or NO_TEXTURE
+    texture_indices: [u32; 4],
+}
+
+impl Uniform for TraceMaterial {}
+
+/// The sum of the samples of a pixel
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C, packed)]
+struct PixelSum {
+    sum: [f32; 4],
+}
+
+impl Uniform for PixelSum {}
+
+/// The push constants of pose_comp.glsl
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
+struct PoseParams {
+    model: [f32; 16],
+    num_vertices: u32,
+    // In floats
+    stride: u32,
+    /// Where the vertices of the mesh start in the vertices of the tracer
+    dst_first: u32,
+    has_normals: u32,
+}
+
+/// The push constants of refit_comp.glsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+struct RefitParams {
+    first_node: u32,
+    num_nodes: u32,
+}
+
+/// The push constants of trace_comp.glsl. Every pixel of the range has been sampled `pass` times
+/// before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+struct TraceParams {
+    first_pixel: u32,
+    num_pixels: u32,
+    pass: u32,
+}
+
+fn workgroups(invocations: u32) -> u32 {
+    (invocations + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE
+}
+
+fn read_f32(data: &[u8], offset: usize) -> f32 {
+    f32::from_ne_bytes(data[offset..offset + 4].try_into().expect("Four bytes"))
+}
+
+fn read_vec3(data: &[u8], offset: usize) -> Vec3 {
+    let f = |i: usize| read_f32(data, offset + i * 4);
+    Vec3::new(f(0), f(1), f(2))
+}
+
+/// The vertices of a mesh in object space, as positions, normals and texture coordinates, and the
+/// corners of its triangles
+struct MeshGeometry {
+    positions: Vec<Vec3>,
+    normals: Option<Vec<Vec3>>,
+    uvs: Option<Vec<[f32; 2]>>,
+    triangles: Vec<[u32; 3]>,
+}
+
+fn mesh_geometry(mesh: &CpuMesh) -> Option<MeshGeometry> {
+    let format = mesh.vertex_buffer.format();
+    let attributes = format.vk_attribute_description();
+    let offset_of = |i: usize, vk_format: raw_vk::Format| {
+        attributes
+            .get(i)
+            .filter(|a| a.format == vk_format)
+            .map(|a| a.offset as usize)
+    };
+    // The meshes of the PBR pipelines have the position first and the normal second. The texture
+    // coordinates are the only attribute with two components, see asset::gltf.
+    let position_offset = offset_of(0, raw_vk::Format::R32G32B32_SFLOAT)?;
+    let normal_offset = offset_of(1, raw_vk::Format::R32G32B32_SFLOAT);
+    let uv_offset = attributes
+        .iter()
+        .find(|a| a.format == raw_vk::Format::R32G32_SFLOAT)
+        .map(|a| a.offset as usize);
+    let stride = format.size() as usize;
+    let vertices = mesh.vertex_buffer.data();
+    let n_vertices = mesh.vertex_buffer.n_elems() as usize;
+    let attribute = |offset: usize| -> Vec<Vec3> {
+        (0..n_vertices)
+            .map(|i| read_vec3(vertices, i * stride + offset))
+            .collect()
+    };
+
+    let index_data = mesh.index_buffer.data();
+    let indices: Vec<u32> = match mesh.index_buffer.index_size() {
+        IndexSize::Size16 => index_data
+            .chunks_exact(2)
+            .map(|b| u16::from_ne_bytes([b[0], b[1]]) as u32)
+            .collect(),
+        IndexSize::Size32 => index_data
+            .chunks_exact(4)
+            .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+    };
+
+    Some(MeshGeometry {
+        positions: attribute(position_offset),
+        normals: normal_offset.map(attribute),
+        uvs: uv_offset.map(|offset| {
+            (0..n_vertices)
+                .map(|i| {
+                    let base = i * stride + offset;
+                    [read_f32(vertices, base), read_f32(vertices, base + 4)]
+                })
+                .collect()
+        }),
+        triangles: indices
+            .chunks_exact(3)
+            .filter(|tri| tri.iter().all(|&i| (i as usize) < n_vertices))
+            .map(|tri| [tri[0], tri[1], tri[2]])
+            .collect(),
+    })
+}
+
+fn trace_material(
+    renderer: &mut Renderer,
+    material: &PhysicallyBased,
+    gpu_material: Option<&GpuMaterial>,
+) -> TraceMaterial {
+    let mut texture_indices = [NO_TEXTURE; 4];
+    // Sparse textures are only sampled with their residency, see uses_bindless_textures
+    let bindless = gpu_material.filter(|m| super::uses_bindless_textures(renderer, m));
+    if let Some(GpuMaterial::PBR {
+        base_color_texture,
+        metallic_roughness_texture,
+        emissive_texture,
+        ..
+    }) = bindless
+    {
+        let textures = [
+            base_color_texture,
+            metallic_roughness_texture,
+            emissive_texture,
+        ];
+        for (index, texture) in texture_indices.iter_mut().zip(textures.iter()) {
+            if let Some(idx) = texture
+                .as_ref()
+                .and_then(|t| renderer.bindless_texture_index(&t.handle))
+            {
+                *index = idx;
+            }
+        }
+    }
+
+    TraceMaterial {
+        base_color_factor: material.base_color_factor.into_array(),
+        emissive_factor: [
+            material.emissive_factor.r,
+            material.emissive_factor.g,
+            material.emissive_factor.b,
+            0.0,
+        ],
+        metallic_roughness: [
+            material.metallic_factor,
+            material.roughness_factor,
+            0.0,
+            0.0,
+        ],
+        texture_indices,
+    }
+}
+
+/// A mesh that is deformed by the pre-pass, whose vertices are copied by pose_comp.glsl
+struct PosedMesh {
+    /// The deformed vertices of the frame and the vertices of the tracer
+    descriptor_set: Handle<DescriptorSet>,
+    params: PoseParams,
+}
+
+/// The scene on the cpu, before it is uploaded, see SceneData::gather
+#[derive(Default)]
+struct SceneData {
+    nodes: Vec<TraceNode>,
+    triangles: Vec<TraceTriangle>,
+    vertices: Vec<TraceVertex>,
+    materials: Vec<TraceMaterial>,
+    /// The nodes of each depth of the BVH, from the root down
+    levels: Vec<RefitParams>,
+    /// The deformed vertex buffers and the parameters of pose_comp.glsl
+    posed: Vec<(BufferHandle<VertexBuffer>, PoseParams)>,
+}
+
+impl SceneData {
+    fn gather(world: &World, renderer: &mut Renderer) -> Self {
+        let meshes = world.read_storage::<CpuMesh>();
+        let model_matrices = world.read_storage::<ModelMatrix>();
+        let materials = world.read_storage::<PhysicallyBased>();
+        let gpu_materials = world.read_storage::<GpuMaterial>();
+        let deformations = world.read_storage::<GpuDeformation>();
+        let hidden = world.read_storage::<Hidden>();
+
+        let mut data = Self::default();
+        let mut triangles = Vec::new();
+        for (mesh, mtx, material, gpu_material, deformation, _) in (
+            &meshes,
+            &model_matrices,
+            &materials,
+            gpu_materials.maybe(),
+            deformations.maybe(),
+            !&hidden,
+        )
+            .join()
+        {
+            if mesh.polygon_mode != PolygonMode::Fill {
+                continue;
+            }
+            let geometry = match mesh_geometry(mesh) {
+                Some(geometry) => geometry,
+                None => continue,
+            };
+
+            let material_idx = data.materials.len() as u32;
+            data.materials
+                .push(trace_material(renderer, material, gpu_material));
+            let first_vertex = data.vertices.len() as u32;
+            let normal_matrix = mtx.0.inverted().transposed();
+            for (i, position) in geometry.positions.iter().enumerate() {
+                let normal = geometry.normals.as_ref().map_or(Vec3::zero(), |normals| {
+                    (normal_matrix * Vec4::from_direction(normals[i]))
+                        .xyz()
+                        .normalized()
+                });
+                let uv = geometry.uvs.as_ref().map_or([0.0; 2], |uvs| uvs[i]);
+                data.vertices.push(TraceVertex {
+                    position: (mtx.0 * Vec4::from_point(*position)).into_array(),
+                    normal: Vec4::from_direction(normal).into_array(),
+                    uv: [uv[0], uv[1], 0.0, 0.0],
+                });
+            }
+            for [a, b, c] in geometry.triangles {
+                triangles.push(TraceTriangle {
+                    indices: [
+                        first_vertex + a,
+                        first_vertex + b,
+                        first_vertex + c,
+                        material_idx,
+                    ],
+                });
+            }
+
+            // The deformed vertices are in the same order as the mesh, unless the pre-pass deforms
+            // another level of detail of it
+            if let Some(deformation) = deformation {
+                let num_vertices = geometry.positions.len() as u32;
+                if deformation.vertex_buffer.n_elems() == num_vertices {
+                    let format = mesh.vertex_buffer.format();
+                    data.posed.push((
+                        deformation.vertex_buffer,
+                        PoseParams {
+                            model: mtx.0.into_col_array(),
+                            num_vertices,
+                            stride: format.size() / std::mem::size_of::<f32>() as u32,
+                            dst_first: first_vertex,
+                            has_normals: geometry.normals.is_some() as u32,
+                        },
+                    ));
+                }
+            }
+        }
+
+        let bbox = |tri: &TraceTriangle| {
+            let indices = tri.indices;
+            let [a, b, c, _] = indices;
+            BoundingBox::from_points(
+                [a, b, c]
+                    .iter()
+                    .map(|&i| Vec4::from(data.vertices[i as usize].position).xyz()),
+            )
+            .expect("A triangle has corners")
+        };
+        let bvh = Bvh::new(
+            triangles
+                .iter()
+                .enumerate()
+                .map(|(i, tri)| (i, bbox(tri)))
+                .collect(),
+        );
+        // The leaves index the triangles in the order of the items
+        data.triangles = bvh.items().iter().map(|(i, _)| triangles[*i]).collect();
+        for node in bvh.flatten() {
+            let (first, count) = match node.kind {
+                FlatNodeKind::Leaf { start, end } => (start as u32, (end - start) as u32),
+                FlatNodeKind::Inner { first_child } => (first_child as u32, 0),
+            };
+            if node.depth == data.levels.len() {
+                data.levels.push(RefitParams {
+                    first_node: data.nodes.len() as u32,
+                    num_nodes: 0,
+                });
+            }
+            data.levels[node.depth].num_nodes += 1;
+            data.nodes.push(TraceNode {
+                min: node.bbox.min.into_array(),
+                first,
+                max: node.bbox.max.into_array(),
+                count,
+            });
+        }
+
+        data
+    }
+}
+
+/// Immutable, as the buffers are only written by the compute shaders. Empty buffers can't be bound,
+/// so they get one element that is never read.
+fn storage_buffer<T: Copy + Uniform + 'static>(
+    renderer: &mut Renderer,
+    mut data: Vec<T>,
+    empty: T,
+) -> BufferHandle<StorageBuffer> {
+    if data.is_empty() {
+        data.push(empty);
+    }
+    renderer
+        .create_resource_blocking(OwningStorageBufferDescriptor::from_vec(
+            data,
+            BufferMutability::Immutable,
+        ))
+        .expect("Failed to create path tracer buffer")
+}
+
+/// The scene on the gpu
+struct TraceScene {
+    nodes: BufferHandle<StorageBuffer>,
+    triangles: BufferHandle<StorageBuffer>,
+    vertices: BufferHandle<StorageBuffer>,
+    materials: BufferHandle<StorageBuffer>,
+    num_triangles: u32,
+    levels: Vec<RefitParams>,
+    /// The nodes, triangles and vertices for refit_comp.glsl
+    refit_set: Handle<DescriptorSet>,
+    posed: Vec<PosedMesh>,
+}
+
+impl TraceScene {
+    fn create(world: &World, renderer: &mut Renderer) -> Self {
+        let data = SceneData::gather(world, renderer);
+        let num_triangles = data.triangles.len() as u32;
+        let empty_vertex = TraceVertex {
+            position: [0.0; 4],
+            normal: [0.0; 4],
+            uv: [0.0; 4],
+        };
+        let nodes = storage_buffer(
+            renderer,
+            data.nodes,
+            TraceNode {
+                min: [0.0; 3],
+                first: 0,
+                max: [0.0; 3],
+                count: 0,
+            },
+        );
+        let triangles = storage_buffer(renderer, data.triangles, TraceTriangle { indices: [0; 4] });
+        let vertices = storage_buffer(renderer, data.vertices, empty_vertex);
+        let materials = storage_buffer(
+            renderer,
+            data.materials,
+            TraceMaterial {
+                base_color_factor: [0.0; 4],
+                emissive_factor: [0.0; 4],
+                metallic_roughness: [0.0; 4],
+                texture_indices: [NO_TEXTURE; 4],
+            },
+        );
+
+        // The bindings are added in order, see refit_comp.glsl
+        let refit_set = DescriptorSet::builder(renderer)
+            .add_storage_buffer(&nodes, 0, ShaderStage::COMPUTE)
+            .add_storage_buffer(&triangles, 1, ShaderStage::COMPUTE)
+            .add_storage_buffer(&vertices, 2, ShaderStage::COMPUTE)
+            .build();
+        let posed = data
+            .posed
+            .into_iter()
+            .map(|(vertex_buffer, params)| PosedMesh {
+                descriptor_set: DescriptorSet::builder(renderer)
+                    .add_vertex_buffer(&vertex_buffer, 0, ShaderStage::COMPUTE)
+                    .add_storage_buffer(&vertices, 1, ShaderStage::COMPUTE)
+                    .build(),
+                params,
+            })
+            .collect();
+
+        Self {
+            nodes,
+            triangles,
+            vertices,
+            materials,
+            num_triangles,
+            levels: data.levels,
+            refit_set,
+            posed,
+        }
+    }
+
+    fn destroy(self, renderer: &mut Renderer) {
+        let mut resources: Vec<Destroyable> = vec![
+            self.nodes.into(),
+            self.triangles.into(),
+            self.vertices.into(),
+            self.materials.into(),
+            self.refit_set.into(),
+        ];
+        resources.extend(self.posed.into_iter().map(|p| p.descriptor_set.into()));
+        for resource in resources {
+            renderer.destroy_deferred(resource);
+        }
+    }
+}
+
+/// The image and the sums of its pixels
+struct TraceTarget {
+    extent: Extent2D,
+    image: Handle<Texture>,
+    sums: BufferHandle<StorageBuffer>,
+}
+
+impl TraceTarget {
+    fn create(renderer: &mut Renderer, extent: Extent2D) -> Self {
+        let image = renderer
+            .create_texture(TextureDescriptor::Empty {
+                extent,
+                format: Format::RGBA_F16,
+                usage: TextureUsage::STORAGE,
+                sampler: super::post::sampler(),
+            })
+            .expect("Failed to create path traced texture");
+        let empty = PixelSum { sum: [0.0; 4] };
+        let sums = storage_buffer(
+            renderer,
+            vec![empty; (extent.width * extent.height) as usize],
+            empty,
+        );
+        Self {
+            extent,
+            image,
+            sums,
+        }
+    }
+
+    fn destroy(self, renderer: &mut Renderer) {
+        renderer.destroy_deferred(self.image);
+        renderer.destroy_deferred(self.sums);
+    }
+}
+
+/// The lights, as they are written to the light buffer but without the culling and the shadows,
+/// and the ambient light
+fn gather_lights(world: &World) -> (Vec<PackedLight>, Vec3) {
+    let lights = world.read_storage::<Light>();
+    let transforms = world.read_storage::<Transform>();
+    let mut packed_lights = Vec::new();
+    let mut ambient = None;
+    for (light, tfm) in (&lights, &transforms).join() {
+        let direction = (tfm.rotation * Light::DEFAULT_FACING).into_array();
+        let color = light.packed_color().into_array();
+        let packed = match light {
+            Light::Ambient { color, strength } => {
+                ambient.get_or_insert(Vec3::from(color.into_array()) * *strength);
+                continue;
+            }
+            Light::Directional { .. } => PackedLight::directional(direction, color),
+            Light::Point { range, .. } => {
+                PackedLight::point(tfm.position.into_array(), color, *range)
+            }
+            Light::Spot {
+                range,
+                inner_angle,
+                outer_angle,
+                ..
+            } => PackedLight::spot(
+                tfm.position.into_array(),
+                direction,
+                color,
+                *range,
+                (*inner_angle, *outer_angle),
+            ),
+        };
+        if packed_lights.len() == MAX_NUM_LIGHTS {
+            log::warn!("Too many punctual lights for the path tracer, skipping remaining");
+            break;
+        }
+        packed_lights.push(packed);
+    }
+    (packed_lights, ambient.unwrap_or_else(Vec3::zero))
+}
+
+fn hash_floats(hasher: &mut DefaultHasher, floats: &[f32]) {
+    for f in floats {
+        f.to_bits().hash(hasher);
+    }
+}
+
+/// Changes when a mesh or material is added, removed, moved or changed, or when a mesh starts to
+/// be deformed. The lights are compared separately, as they don't need the BVH to be built again.
+fn scene_signature(world: &World) -> u64 {
+    let meshes = world.read_storage::<CpuMesh>();
+    let model_matrices = world.read_storage::<ModelMatrix>();
+    let materials = world.read_storage::<PhysicallyBased>();
+    let gpu_materials = world.read_storage::<GpuMaterial>();
+    let deformations = world.read_storage::<GpuDeformation>();
+    let hidden = world.read_storage::<Hidden>();
+
+    let mut hasher = DefaultHasher::new();
+    for (ent, mesh, mtx, material, gpu_material, deformation, _) in (
+        &world.entities(),
+        &meshes,
+        &model_matrices,
+        &materials,
+        gpu_materials.maybe(),
+        deformations.maybe(),
+        !&hidden,
+    )
+        .join()
+    {
+        ent.id().hash(&mut hasher);
+        mesh.vertex_buffer.n_elems().hash(&mut hasher);
+        mesh.index_buffer.n_elems().hash(&mut hasher);
+        hash_floats(&mut hasher, &mtx.0.into_col_array());
+        hash_floats(&mut hasher, &material.base_color_factor.into_array());
+        hash_floats(&mut hasher, &material.emissive_factor.into_array());
+        hash_floats(
+            &mut hasher,
+            &[material.metallic_factor, material.roughness_factor],
+        );
+        if let Some(GpuMaterial::PBR {
+            base_color_texture,
+            metallic_roughness_texture,
+            emissive_texture,
+            ..
+        }) = gpu_material
+        {
+            for texture in [
+                base_color_texture,
+                metallic_roughness_texture,
+                emissive_texture,
+            ]
+            .iter()
+            {
+                texture.as_ref().map(|t| t.handle).hash(&mut hasher);
+            }
+        }
+        deformation.map(|d| d.vertex_buffer).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Changes when the deformed meshes change pose, i.e. when their joints move or their morph
+/// weights change
+fn pose_signature(world: &World) -> u64 {
+    let deformations = world.read_storage::<GpuDeformation>();
+    let skeletons = world.read_storage::<Skeleton>();
+    let morph_weights = world.read_storage::<MorphWeights>();
+    let model_matrices = world.read_storage::<ModelMatrix>();
+
+    let mut hasher = DefaultHasher::new();
+    for (_, skeleton, weights) in (&deformations, skeletons.maybe(), morph_weights.maybe()).join() {
+        for joint in skeleton.iter().flat_map(|s| s.joints.iter()) {
+            if let Some(mtx) = model_matrices.get(*joint) {
+                hash_floats(&mut hasher, &mtx.0.into_col_array());
+            }
+        }
+        if let Some(weights) = weights {
+            hash_floats(&mut hasher, &weights.0);
+        }
+    }
+    hasher.finish()
+}
+
+/// The dispatches of trace_comp.glsl for the rays from `first_ray` on, in the order that they are
+/// traced. The rays go through the pixels row by row, and start over at the first pixel once all
+/// have been sampled, so a dispatch never samples a pixel twice.
+fn trace_dispatches(first_ray: u64, rays: u64, num_pixels: u32) -> Vec<TraceParams> {
+    let n = num_pixels as u64;
+    let mut dispatches = Vec::new();
+    let mut next = first_ray;
+    while n > 0 && next < first_ray + rays {
+        let first_pixel = next % n;
+        let count = (n - first_pixel).min(first_ray + rays - next);
+        dispatches.push(TraceParams {
+            first_pixel: first_pixel as u32,
+            num_pixels: count as u32,
+            pass: (next / n) as u32,
+        });
+        next += count;
+    }
+    dispatches
+}
+
+/// Builds the scene buffers and the image when tracing is turned on, and records the dispatches
+/// that sample the view each frame, see the module docs
+pub(super) struct PathTracer {
+    pose: Handle<ComputePipeline>,
+    refit: Handle<ComputePipeline>,
+    trace: Handle<ComputePipeline>,
+    /// Bound at BINDLESS_TEXTURES_SET, if trace_comp.glsl samples the textures
+    bindless_textures: Option<Handle<DescriptorSet>>,
+    scene: Option<TraceScene>,
+    signature: u64,
+    target: Option<TraceTarget>,
+    /// The scene, the image and the uniforms for trace_comp.glsl. Created again with either of the
+    /// first two.
+    trace_set: Option<Handle<DescriptorSet>>,
+    data: PathTraceData,
+    /// Of everything that the samples depend on, see prepare
+    accumulation_key: u64,
+    /// The number of rays that have been traced since the samples were last thrown away
+    rays: u64,
+    /// If the image is traced from scratch this frame, which samples every pixel once
+    restarted: bool,
+    /// If the deformed meshes are posed and the BVH refit this frame
+    refit_this_frame: bool,
+    dispatches: Vec<TraceParams>,
+}
+
+impl PathTracer {
+    pub fn new(
+        shader_compiler: &ShaderCompiler,
+        renderer: &mut Renderer,
+    ) -> Result<Self, MaterialError> {
+        let bindless = renderer.supports_bindless_textures()
+            && renderer.max_bound_descriptor_sets() > BINDLESS_TEXTURES_SET;
+        let bindless_textures = if bindless {
+            renderer.bindless_descriptor_set().copied()
+        } else {
+            None
+        };
+        let mut create = |defines: &Defines, path: &str| -> Result<_, MaterialError> {
+            let shader = shader_compiler.compile(defines, path, ShaderType::Compute)?;
+            Ok(
+                renderer.create_compute_pipeline(&ComputePipelineDescriptor {
+                    shader: ShaderDescriptor::FromRawSpirv(shader.data()),
+                })?,
+            )
+        };
+
+        let pose = create(&Defines::empty(), "path_trace/pose_comp.glsl")?;
+        let refit = create(&Defines::empty(), "path_trace/refit_comp.glsl")?;
+        let mut trace_defines = Defines::empty();
+        if bindless {
+            trace_defines.push((String::from("BINDLESS_TEXTURES"), String::from("1")));
+            trace_defines.push((
+                String::from("BINDLESS_TEXTURE_COUNT"),
+                format!("{}", trekanten::bindless::BINDLESS_TEXTURE_COUNT),
+            ));
+        }
+        let trace = create(&trace_defines, "path_trace/trace_comp.glsl")?;
+
+        Ok(Self {
+            pose,
+            refit,
+            trace,
+            bindless_textures,
+            scene: None,
+            signature: 0,
+            target: None,
+            trace_set: None,
+            data: PathTraceData::default(),
+            accumulation_key: 0,
+            rays: 0,
+            restarted: false,
+            refit_this_frame: false,
+            dispatches: Vec::new(),
+        })
+    }
+
+    fn destroy_trace_set(&mut self, renderer: &mut Renderer) {
+        if let Some(set) = self.trace_set.take() {
+            renderer.destroy_deferred(set);
+        }
+    }
+
+    /// Creates the scene buffers when the scene changes, and the image when the extent does, and
+    /// starts over if anything that the samples depend on changed. Plans the dispatches of this
+    /// frame, which are recorded by `record`.
+    pub(super) fn prepare(&mut self, world: &World, renderer: &mut Renderer) {
+        self.dispatches.clear();
+        self.restarted = false;
+        self.refit_this_frame = false;
+
+        let settings = world.read_resource::<RenderSettings>();
+        if !settings.path_trace {
+            self.destroy_trace_set(renderer);
+            if let Some(scene) = self.scene.take() {
+                scene.destroy(renderer);
+            }
+            if let Some(target) = self.target.take() {
+                target.destroy(renderer);
+            }
+            self.rays = 0;
+            return;
+        }
+
+        let signature = scene_signature(world);
+        if self.scene.is_none() || signature != self.signature {
+            self.signature = signature;
+            self.destroy_trace_set(renderer);
+            let scene = TraceScene::create(world, renderer);
+            if let Some(old) = self.scene.replace(scene) {
+                old.destroy(renderer);
+            }
+        }
+
+        let swapchain = renderer.swapchain_extent();
+        let extent = Extent2D {
+            width: (swapchain.width / RESOLUTION_DIVISOR).max(1),
+            height: (swapchain.height / RESOLUTION_DIVISOR).max(1),
+        };
+        if self.target.as_ref().map(|t| t.extent) != Some(extent) {
+            self.destroy_trace_set(renderer);
+            if let Some(old) = self.target.replace(TraceTarget::create(renderer, extent)) {
+                old.destroy(renderer);
+            }
+        }
+
+        let scene = self.scene.as_ref().expect("The scene was created");
+        let target = self.target.as_ref().expect("The target was created");
+        if self.trace_set.is_none() {
+            // The bindings are added in order, see trace_comp.glsl
+            self.trace_set = Some(
+                DescriptorSet::builder(renderer)
+                    .add_transient::<PathTraceData>(PathTraceData::BINDING, ShaderStage::COMPUTE)
+                    .add_storage_buffer(&scene.nodes, 1, ShaderStage::COMPUTE)
+                    .add_storage_buffer(&scene.triangles, 2, ShaderStage::COMPUTE)
+                    .add_storage_buffer(&scene.vertices, 3, ShaderStage::COMPUTE)
+                    .add_storage_buffer(&scene.materials, 4, ShaderStage::COMPUTE)
+                    .add_storage_buffer(&target.sums, 5, ShaderStage::COMPUTE)
+                    .add_storage_image(&target.image, 6, ShaderStage::COMPUTE)
+                    .build(),
+            );
+            // The sums of the previous image are not in the new buffers
+            self.rays = 0;
+        }
+
+        let aspect_ratio = swapchain.width as f32 / swapchain.height.max(1) as f32;
+        let (view, eye) = super::get_view_data(world);
+        let view_proj: Mat4 = super::get_proj_matrix(world, aspect_ratio) * view;
+        let (lights, ambient) = gather_lights(world);
+        let mut data = PathTraceData {
+            inv_view_proj: view_proj.inverted().into_col_array(),
+            eye: [eye.x, eye.y, eye.z, 1.0],
+            ambient: [ambient.x, ambient.y, ambient.z, 0.0],
+            extent_bounces: [
+                extent.width,
+                extent.height,
+                settings.path_trace_bounces,
+                lights.len() as u32,
+            ],
+            scene: [scene.num_triangles, 0, 0, 0],
+            ..Default::default()
+        };
+        data.lights[..lights.len()].copy_from_slice(&lights);
+        self.data = data;
+
+        let mut hasher = DefaultHasher::new();
+        trekanten::util::as_bytes(&self.data).hash(&mut hasher);
+        signature.hash(&mut hasher);
+        let pose = pose_signature(world);
+        pose.hash(&mut hasher);
+        let key = hasher.finish();
+        if key != self.accumulation_key {
+            self.accumulation_key = key;
+            self.rays = 0;
+        }
+
+        let num_pixels = extent.width * extent.height;
+        let mut rays = settings.path_trace_rays_per_frame as u64;
+        self.restarted = self.rays == 0;
+        if self.restarted {
+            // Every pixel of the image is written before it is shown
+            rays = rays.max(num_pixels as u64);
+            self.refit_this_frame = !scene.posed.is_empty();
+        }
+        rays = rays.min(num_pixels as u64 * MAX_PASSES_PER_FRAME);
+        self.dispatches = trace_dispatches(self.rays, rays, num_pixels);
+        self.rays += rays;
+    }
+
+    /// Writes the view and the lights of this frame, after `prepare`
+    pub(super) fn upload(&self, frame: &mut Frame) -> Result<(), trekanten::RenderError> {
+        if let (Some(set), false) = (&self.trace_set, self.dispatches.is_empty()) {
+            let slice = frame.write_transient(&self.data)?;
+            frame.bind_transient(set, PathTraceData::BINDING, slice);
+        }
+        Ok(())
+    }
+
+    /// Poses the deformed meshes and traces the rays of this frame. After the deformation pre-pass,
+    /// as it reads the deformed vertices.
+    pub(super) fn record(
+        &self,
+        frame: &Frame,
+        cmd_buffer: CommandBuffer,
+    ) -> Result<CommandBuffer, trekanten::RenderError> {
+        let (scene, target, trace_set) = match (&self.scene, &self.target, &self.trace_set) {
+            (Some(scene), Some(target), Some(set)) if !self.dispatches.is_empty() => {
+                (scene, target, set)
+            }
+            _ => return Ok(cmd_buffer),
+        };
+
+        let mut pass = frame.begin_compute_pass(cmd_buffer);
+        // The pre-pass wrote the deformed vertices, and the previous frame the buffers of the tracer
+        pass.barrier();
+        if self.refit_this_frame {
+            pass.bind_compute_pipeline(&self.pose);
+            for posed in scene.posed.iter() {
+                pass.bind_shader_resource_group(0, &posed.descriptor_set, &self.pose)
+                    .bind_push_constant(&self.pose, &posed.params)
+                    .dispatch(workgroups(posed.params.num_vertices), 1, 1);
+            }
+            pass.barrier();
+
+            // The boxes of each depth are computed from the one below it
+            pass.bind_compute_pipeline(&self.refit)
+                .bind_shader_resource_group(0, &scene.refit_set, &self.refit);
+            for level in scene.levels.iter().rev() {
+                pass.bind_push_constant(&self.refit, level)
+                    .dispatch(workgroups(level.num_nodes), 1, 1)
+                    .barrier();
+            }
+        }
+
+        pass.begin_storage_image(&target.image, !self.restarted)
+            .bind_compute_pipeline(&self.trace)
+            .bind_shader_resource_group(0, trace_set, &self.trace);
+        if let Some(set) = &self.bindless_textures {
+            pass.bind_shader_resource_group(BINDLESS_TEXTURES_SET, set, &self.trace);
+        }
+        for (i, dispatch) in self.dispatches.iter().enumerate() {
+            // The next pass over the pixels adds to the sums of this one
+            if i > 0 {
+                pass.barrier();
+            }
+            pass.bind_push_constant(&self.trace, dispatch).dispatch(
+                workgroups(dispatch.num_pixels),
+                1,
+                1,
+            );
+        }
+        pass.end_storage_image(&target.image);
+
+        Ok(pass.end()?)
+    }
+
+    /// The image, if it replaces the rasterized scene
+    pub(super) fn shown(&self, settings: &RenderSettings) -> Option<&Handle<Texture>> {
+        if settings.path_trace && settings.path_trace_show && self.rays > 0 {
+            self.target.as_ref().map(|target| &target.image)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dispatches_sample_each_pixel_once_per_pass() {
+        let num_pixels = 10;
+        let dispatches = trace_dispatches(7, 25, num_pixels);
+        assert_eq!(
+            dispatches,
+            vec![
+                TraceParams {
+                    first_pixel: 7,
+                    num_pixels: 3,
+                    pass: 0,
+                },
+                TraceParams {
+                    first_pixel: 0,
+                    num_pixels: 10,
+                    pass: 1,
+                },
+                TraceParams {
+                    first_pixel: 0,
+                    num_pixels: 10,
+                    pass: 2,
+                },
+                TraceParams {
+                    first_pixel: 0,
+                    num_pixels: 2,
+                    pass: 3,
+                },
+            ]
+        );
+
+        // Continues where the previous frame stopped
+        let next = trace_dispatches(32, 4, num_pixels);
+        assert_eq!(
+            next,
+            vec![TraceParams {
+                first_pixel: 2,
+                num_pixels: 4,
+                pass: 3,
+            }]
+        );
+        assert!(trace_dispatches(0, 0, num_pixels).is_empty());
+        assert!(trace_dispatches(0, 5, 0).is_empty());
+    }
+}
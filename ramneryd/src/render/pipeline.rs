@@ -24,28 +24,116 @@ impl Defines {
     }
 }
 
+/// Vertex shaders with this define read the model matrices from the object buffer, see
+/// render::object_data
+pub const OBJECT_BUFFER: &str = "OBJECT_BUFFER";
+
 pub mod pbr_gltf {
     use super::*;
 
+    /// The defines that select a permutation of the PBR shaders, with a description of each. The
+    /// `*_LOC` defines are vertex attribute locations.
+    pub const DEFINES: &[(&str, &str)] = &[
+        (
+            "HAS_SKIN",
+            "Vertices have joints and weights, which the deformation pre-pass has applied",
+        ),
+        (
+            "JOINTS_LOC",
+            "Joint indices, directly after positions and normals",
+        ),
+        ("WEIGHTS_LOC", "Joint weights"),
+        (
+            "HAS_MORPH_TARGETS",
+            "Vertices have morph targets, which the deformation pre-pass has applied",
+        ),
+        ("NUM_MORPH_TARGETS", "Number of morph targets"),
+        (
+            "MORPH_POS_LOC",
+            "The first of NUM_MORPH_TARGETS position deltas",
+        ),
+        (
+            "MORPH_NOR_LOC",
+            "The first of NUM_MORPH_TARGETS normal deltas",
+        ),
+        ("HAS_TEX_COORDS", "Vertices have texture coordinates"),
+        ("TEX_COORDS_LOC", "Texture coordinates"),
+        (
+            "HAS_VERTEX_COLOR",
+            "Vertices have colors that multiply the base color",
+        ),
+        ("VCOL_LOC", "Vertex colors"),
+        ("HAS_TANGENTS", "Vertices have tangents and bitangents"),
+        ("TAN_LOC", "Tangents"),
+        ("BITAN_LOC", "Bitangents"),
+        (
+            "HAS_BASE_COLOR_TEXTURE",
+            "Base color texture at set 1, binding 1, unless BINDLESS_TEXTURES",
+        ),
+        (
+            "HAS_METALLIC_ROUGHNESS_TEXTURE",
+            "Metallic-roughness texture at set 1, binding 2, unless BINDLESS_TEXTURES",
+        ),
+        (
+            "HAS_NORMAL_MAP",
+            "Normal map at set 1, binding 3, unless BINDLESS_TEXTURES",
+        ),
+        (
+            "HAS_EMISSIVE_TEXTURE",
+            "Emissive texture at set 1, binding 4, unless BINDLESS_TEXTURES",
+        ),
+        (
+            "HAS_OCCLUSION_TEXTURE",
+            "Occlusion texture at set 1, binding 5, unless BINDLESS_TEXTURES",
+        ),
+        (
+            "BASE_COLOR_TEXTURE_SPARSE",
+            "The base color texture is a virtual texture that is partially resident",
+        ),
+        (
+            "OBJECT_BUFFER",
+            "The model matrices are read from the object buffer at set 3, binding 0",
+        ),
+        (
+            "BINDLESS_TEXTURES",
+            "Textures are sampled from the bindless array at set 4, binding 0, by material index",
+        ),
+        ("BINDLESS_TEXTURE_COUNT", "The length of the bindless array"),
+    ];
+
     #[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
     pub struct ShaderDefinition {
+        pub has_skin: bool,
+        pub num_morph_targets: u32,
         pub has_tex_coords: bool,
         pub has_vertex_colors: bool,
         pub has_tangents: bool,
         pub has_base_color_texture: bool,
         pub has_metallic_roughness_texture: bool,
         pub has_normal_map: bool,
+        pub has_emissive_texture: bool,
+        pub has_occlusion_texture: bool,
+        pub sparse_base_color_texture: bool,
+        pub object_buffer: bool,
+        pub bindless_textures: bool,
     }
 
     impl ShaderDefinition {
         const fn empty() -> Self {
             Self {
+                has_skin: false,
+                num_morph_targets: 0,
                 has_tex_coords: false,
                 has_vertex_colors: false,
                 has_tangents: false,
                 has_base_color_texture: false,
                 has_metallic_roughness_texture: false,
                 has_normal_map: false,
+                has_emissive_texture: false,
+                has_occlusion_texture: false,
+                sparse_base_color_texture: false,
+                object_buffer: false,
+                bindless_textures: false,
             }
         }
         fn iter(&self) -> impl Iterator<Item = bool> {
@@ -56,6 +144,10 @@ pub mod pbr_gltf {
                 .chain(once(self.has_base_color_texture))
                 .chain(once(self.has_metallic_roughness_texture))
                 .chain(once(self.has_normal_map))
+                .chain(once(self.has_emissive_texture))
+                .chain(once(self.has_occlusion_texture))
+                .chain(once(self.sparse_base_color_texture))
+                .chain(once(self.object_buffer))
         }
 
         fn defines(&self) -> Defines {
@@ -63,6 +155,33 @@ pub mod pbr_gltf {
 
             let mut attribute_count = 2; // Positions and normals are assumed to exist
 
+            // Joints, weights and morph targets are placed directly after positions and normals
+            if self.has_skin {
+                defines.push((String::from("HAS_SKIN"), String::from("1")));
+                defines.push((String::from("JOINTS_LOC"), format!("{}", attribute_count)));
+                defines.push((
+                    String::from("WEIGHTS_LOC"),
+                    format!("{}", attribute_count + 1),
+                ));
+                attribute_count += 2;
+            }
+
+            // All position deltas, followed by all normal deltas
+            if self.num_morph_targets > 0 {
+                let n = self.num_morph_targets;
+                defines.push((String::from("HAS_MORPH_TARGETS"), String::from("1")));
+                defines.push((String::from("NUM_MORPH_TARGETS"), format!("{}", n)));
+                defines.push((
+                    String::from("MORPH_POS_LOC"),
+                    format!("{}", attribute_count),
+                ));
+                defines.push((
+                    String::from("MORPH_NOR_LOC"),
+                    format!("{}", attribute_count + n),
+                ));
+                attribute_count += 2 * n;
+            }
+
             let all_defines = [
                 ("HAS_TEX_COORDS", vec!["TEX_COORDS_LOC"]),
                 ("HAS_VERTEX_COLOR", vec!["VCOL_LOC"]),
@@ -70,6 +189,10 @@ pub mod pbr_gltf {
                 ("HAS_BASE_COLOR_TEXTURE", vec![]),
                 ("HAS_METALLIC_ROUGHNESS_TEXTURE", vec![]),
                 ("HAS_NORMAL_MAP", vec![]),
+                ("HAS_EMISSIVE_TEXTURE", vec![]),
+                ("HAS_OCCLUSION_TEXTURE", vec![]),
+                ("BASE_COLOR_TEXTURE_SPARSE", vec![]),
+                (OBJECT_BUFFER, vec![]),
             ];
 
             for (_cond, (has_define, loc_defines)) in self
@@ -84,13 +207,27 @@ pub mod pbr_gltf {
                 }
             }
 
+            if self.bindless_textures {
+                defines.push((String::from("BINDLESS_TEXTURES"), String::from("1")));
+                defines.push((
+                    String::from("BINDLESS_TEXTURE_COUNT"),
+                    format!("{}", trekanten::bindless::BINDLESS_TEXTURE_COUNT),
+                ));
+            }
+
             defines
         }
 
         fn is_valid(&self) -> bool {
+            if self.num_morph_targets as usize > crate::render::uniform::MAX_NUM_MORPH_TARGETS {
+                return false;
+            }
+
             let uses_tex = self.has_normal_map
                 || self.has_base_color_texture
-                || self.has_metallic_roughness_texture;
+                || self.has_metallic_roughness_texture
+                || self.has_emissive_texture
+                || self.has_occlusion_texture;
             if uses_tex && !self.has_tex_coords {
                 return false;
             }
@@ -99,6 +236,15 @@ pub mod pbr_gltf {
                 return false;
             }
 
+            if self.sparse_base_color_texture && !self.has_base_color_texture {
+                return false;
+            }
+
+            // Sparse textures are sampled with their residency, which needs the material's set
+            if self.bindless_textures && (!uses_tex || self.sparse_base_color_texture) {
+                return false;
+            }
+
             true
         }
     }
@@ -115,10 +261,72 @@ pub mod pbr_gltf {
         Ok((vert, frag))
     }
 
+    /// The shaders of the feedback pass of virtual textures, see render::virtual_texture. Only the
+    /// vertex attribute locations of the definition are used.
+    pub fn compile_virtual_texture_feedback(
+        compiler: &ShaderCompiler,
+        def: &ShaderDefinition,
+    ) -> Result<(SpvBinary, SpvBinary), CompilerError> {
+        assert!(def.is_valid());
+        let defines = def.defines();
+        let vert = compiler.compile(
+            &defines,
+            Path::new("virtual_texture/feedback_vert.glsl"),
+            ShaderType::Vertex,
+        )?;
+        let frag = compiler.compile(
+            &defines,
+            Path::new("virtual_texture/feedback_frag.glsl"),
+            ShaderType::Fragment,
+        )?;
+
+        Ok((vert, frag))
+    }
+
+    /// The shaders of the pass that renders the depth, normals and entity ids of the view, see
+    /// render::aov. They only read the positions and normals, which all PBR vertex formats start
+    /// with.
+    pub fn compile_aov(compiler: &ShaderCompiler) -> Result<(SpvBinary, SpvBinary), CompilerError> {
+        let defines = Defines::default();
+        let vert = compiler.compile(&defines, Path::new("aov/vert.glsl"), ShaderType::Vertex)?;
+        let frag = compiler.compile(&defines, Path::new("aov/frag.glsl"), ShaderType::Fragment)?;
+
+        Ok((vert, frag))
+    }
+
+    /// The shaders of the draft view modes, see render::view_mode. Without normals, the normals of
+    /// the triangles are used.
+    pub fn compile_view_mode(
+        compiler: &ShaderCompiler,
+        has_normals: bool,
+    ) -> Result<(SpvBinary, SpvBinary), CompilerError> {
+        let mut defines = Defines::default();
+        if has_normals {
+            defines.push((String::from("HAS_NORMALS"), String::from("1")));
+        }
+        let vert = compiler.compile(
+            &defines,
+            Path::new("view_mode/vert.glsl"),
+            ShaderType::Vertex,
+        )?;
+        let frag = compiler.compile(
+            &defines,
+            Path::new("view_mode/frag.glsl"),
+            ShaderType::Fragment,
+        )?;
+
+        Ok((vert, frag))
+    }
+
     pub fn compile_default(
         compiler: &ShaderCompiler,
+        object_buffer: bool,
     ) -> Result<(SpvBinary, SpvBinary), CompilerError> {
-        compile(compiler, &ShaderDefinition::empty())
+        let def = ShaderDefinition {
+            object_buffer,
+            ..ShaderDefinition::empty()
+        };
+        compile(compiler, &def)
     }
 }
 
@@ -144,6 +352,7 @@ unsafe impl Sync for ShaderCompiler {}
 pub enum ShaderType {
     Vertex,
     Fragment,
+    Compute,
 }
 
 #[cfg(windows)]
@@ -152,6 +361,23 @@ const SHADER_PATH: &str = concat!(env!("OUT_DIR"), "\\builtin-shaders");
 #[cfg(not(windows))]
 const SHADER_PATH: &str = concat!(env!("OUT_DIR"), "/builtin-shaders");
 
+#[cfg(windows)]
+const SHADER_SOURCE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "\\src\\render\\shaders");
+
+#[cfg(not(windows))]
+const SHADER_SOURCE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/render/shaders");
+
+/// The directory that shaders are compiled from. The sources are preferred over the copies made by
+/// the build script, if they are still around, so that edits to them are picked up when reloading.
+pub fn shader_dir() -> PathBuf {
+    let src = PathBuf::from(SHADER_SOURCE_PATH);
+    if src.is_dir() {
+        src
+    } else {
+        PathBuf::from(SHADER_PATH)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum CompilerError {
     #[error("Failed to initialize")]
@@ -198,9 +424,10 @@ impl ShaderCompiler {
         let stage = match ty {
             ShaderType::Fragment => shaderc::ShaderKind::Fragment,
             ShaderType::Vertex => shaderc::ShaderKind::Vertex,
+            ShaderType::Compute => shaderc::ShaderKind::Compute,
         };
 
-        let path = PathBuf::from(SHADER_PATH).join(rel_path);
+        let path = shader_dir().join(rel_path);
 
         let source = std::fs::read_to_string(path)?;
 
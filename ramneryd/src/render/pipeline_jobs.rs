@@ -0,0 +1,163 @@
+//! Compiles the shaders of materials and creates their pipelines on worker threads, so that new
+//! materials do not stall the frame. Renderables are drawn with a fallback until their pipeline is
+//! ready, see create_renderables.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use crossbeam::channel::{unbounded, Receiver, Sender};
+
+use trekanten::pipeline::{GraphicsPipeline, GraphicsPipelineDescriptor, PipelineFactory};
+use trekanten::resource::Handle;
+use trekanten::Renderer;
+
+use super::pipeline::ShaderCompiler;
+use super::{material_pipeline_desc, MaterialError, MaterialPipelineCache, PipelineKey};
+
+const NUM_WORKERS: usize = 2;
+
+type JobResult = (
+    PipelineKey,
+    Result<(GraphicsPipelineDescriptor, GraphicsPipeline), MaterialError>,
+);
+
+fn run_worker(
+    factory: PipelineFactory,
+    jobs: Receiver<PipelineKey>,
+    results: Sender<JobResult>,
+    cancelled: Arc<AtomicBool>,
+) {
+    let compiler = match ShaderCompiler::new() {
+        Ok(compiler) => compiler,
+        Err(e) => {
+            log::error!(
+                "Failed to create shader compiler for pipeline worker: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    loop {
+        let key = match jobs.recv() {
+            Ok(key) => key,
+            // The queue is closed
+            Err(_) => return,
+        };
+        if cancelled.load(Ordering::Acquire) {
+            return;
+        }
+
+        let result = material_pipeline_desc(&compiler, &key).and_then(|desc| {
+            let pipeline = factory.create(&desc)?;
+            Ok((desc, pipeline))
+        });
+        if results.send((key, result)).is_err() {
+            return;
+        }
+    }
+}
+
+/// The pipelines of the materials that are being created, for one render pass
+pub(super) struct PipelineJobs {
+    jobs: Option<Sender<PipelineKey>>,
+    results: Receiver<JobResult>,
+    workers: Vec<JoinHandle<()>>,
+    cancelled: Arc<AtomicBool>,
+    in_flight: HashSet<PipelineKey>,
+    // A reference to each pipeline that finished this frame, so they are not destroyed before the
+    // renderables that wait for them have taken their own
+    finished: Vec<Handle<GraphicsPipeline>>,
+}
+
+impl PipelineJobs {
+    pub fn new(factory: PipelineFactory) -> Self {
+        let (job_sender, job_receiver) = unbounded();
+        let (result_sender, results) = unbounded();
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let workers = (0..NUM_WORKERS)
+            .map(|i| {
+                let factory = factory.clone();
+                let jobs = job_receiver.clone();
+                let results = result_sender.clone();
+                let cancelled = Arc::clone(&cancelled);
+                std::thread::Builder::new()
+                    .name(format!("ramneryd::pipeline_worker{}", i))
+                    .spawn(move || run_worker(factory, jobs, results, cancelled))
+                    .expect("Failed to start pipeline worker")
+            })
+            .collect();
+
+        Self {
+            jobs: Some(job_sender),
+            results,
+            workers,
+            cancelled,
+            in_flight: HashSet::new(),
+            finished: Vec::new(),
+        }
+    }
+
+    /// Start creating the pipeline, unless it is already being created
+    pub fn request(&mut self, key: &PipelineKey) {
+        if self.in_flight.contains(key) {
+            return;
+        }
+
+        let sent = self
+            .jobs
+            .as_ref()
+            .map(|jobs| jobs.send(key.clone()).is_ok())
+            .unwrap_or(false);
+        if sent {
+            self.in_flight.insert(key.clone());
+        } else {
+            log::error!(
+                "Pipeline workers have stopped, can't create pipeline for {:?}",
+                key
+            );
+        }
+    }
+
+    /// Add the pipelines that are done to the renderer and their descriptors to the cache, so that
+    /// get_pipeline_for returns them without blocking.
+    pub fn receive(&mut self, renderer: &mut Renderer, cache: &mut MaterialPipelineCache) {
+        for (key, result) in self.results.try_iter() {
+            self.in_flight.remove(&key);
+            match result {
+                Ok((desc, pipeline)) => {
+                    let handle = renderer.add_gfx_pipeline(desc.clone(), pipeline);
+                    self.finished.push(handle);
+                    cache.descriptors.insert(key, desc);
+                }
+                Err(e) => {
+                    log::error!("Failed to create pipeline for {:?}: {}", key, e);
+                    cache.failed.insert(key);
+                }
+            }
+        }
+    }
+
+    /// Drop the references that were held for the renderables, once they have been created
+    pub fn release_finished(&mut self, renderer: &mut Renderer) {
+        for h in self.finished.drain(..) {
+            renderer.destroy_deferred(h);
+        }
+    }
+}
+
+impl Drop for PipelineJobs {
+    /// The workers may use the render pass, so wait for the current jobs and skip the queued ones
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Release);
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                log::error!("Pipeline worker panicked");
+            }
+        }
+    }
+}
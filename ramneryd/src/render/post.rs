@@ -0,0 +1,878 @@
+//! Post-processing of the rendered scene. The scene is rendered to an HDR texture instead of the
+//! presented image. For bloom, the parts of it that are brighter than a threshold are extracted and
+//! blurred at half resolution. Finally, the scene and the bloom are composited onto the presented
+//! image in the main render pass. If any effects of the PostEffectStack are enabled, the scene and
+//! the bloom are instead composited to a full resolution texture that the effects are applied to,
+//! ping-ponging between two textures, and the result is copied to the presented image.
+//!
+//! If there are materials that are seen through, the scene pass is split in two. The opaque part of
+//! the scene is drawn first, and its color is copied and blurred for the transmissive materials that
+//! are drawn on top of it in the second, see PostProcessing::transmission_passes.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use trekanten::descriptor::DescriptorSet;
+use trekanten::pipeline::{BlendState, ShaderStage, TriangleCulling};
+use trekanten::pipeline::{GraphicsPipeline, GraphicsPipelineDescriptor, ShaderDescriptor};
+use trekanten::raw_vk;
+use trekanten::resource::Handle;
+use trekanten::texture::{
+    BorderColor, Filter, SamplerAddressMode, SamplerDescriptor, TextureDescriptor, TextureUsage,
+};
+use trekanten::util;
+use trekanten::vertex::VertexFormat;
+use trekanten::{
+    CommandBuffer, Destroyable, Frame, RenderPass, RenderPassEncoder, RenderTarget, Renderer,
+};
+
+use super::exposure::{ExposureParams, ExposureView};
+use super::post_effects::{PostEffect, PostEffectStack};
+use super::{debug_window::RenderSettings, pipeline, MaterialError, RenderError};
+
+pub(super) const HDR_FORMAT: util::Format = util::Format::RGBA_F16;
+
+struct FullscreenTarget {
+    texture: Handle<trekanten::Texture>,
+    render_target: Handle<RenderTarget>,
+    // Samples the texture of this target
+    source: Handle<DescriptorSet>,
+}
+
+// Everything that depends on the size of the presented image
+struct Targets {
+    extent: util::Extent2D,
+    bloom_extent: util::Extent2D,
+    scene_color: Handle<trekanten::Texture>,
+    scene_target: Handle<RenderTarget>,
+    // The bright pass writes to the first, the blur ping-pongs between them and ends in the first
+    bloom: [FullscreenTarget; 2],
+    // The composite writes to the first, the effects ping-pong between them
+    effects: [FullscreenTarget; 2],
+    // The opaque part of the scene for transmissive materials, sharp at full resolution and
+    // blurred at half. The blur ping-pongs between the blurred targets and ends in the first.
+    transmission: FullscreenTarget,
+    transmission_blurred: [FullscreenTarget; 2],
+    scene_source: Handle<DescriptorSet>,
+    composite_source: Handle<DescriptorSet>,
+}
+
+/// What a scene pass draws, see PostProcessing::begin_scene_pass
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScenePass {
+    Whole,
+    /// The first of the split passes, everything but the transmissive materials
+    Opaque,
+    /// The second of the split passes, continues on the opaque part
+    Transmissive,
+}
+
+pub struct PostProcessing {
+    scene_render_pass: Handle<RenderPass>,
+    // The scene render pass split in two, for transmissive materials
+    split_scene_render_passes: [Handle<RenderPass>; 2],
+    bloom_render_pass: Handle<RenderPass>,
+    bright_pass_pipeline: Handle<GraphicsPipeline>,
+    blur_pipeline: Handle<GraphicsPipeline>,
+    composite_pipeline: Handle<GraphicsPipeline>,
+    // Used when bloom is disabled, or to copy the result of the effects
+    copy_pipeline: Handle<GraphicsPipeline>,
+    // Same as above, for the first effect target
+    offscreen_composite_pipeline: Handle<GraphicsPipeline>,
+    offscreen_copy_pipeline: Handle<GraphicsPipeline>,
+    // Replaces the composite when an exposure view is enabled
+    false_color_pipeline: Handle<GraphicsPipeline>,
+    // Indexed by PostEffect::index
+    effect_pipelines: Vec<Handle<GraphicsPipeline>>,
+    targets: Targets,
+    // If the transmission textures can be sampled since the targets were created, see
+    // prepare_transmission
+    transmission_ready: AtomicBool,
+}
+
+/// The push constant of post/effect_frag.glsl
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct EffectParams {
+    params: [f32; 4],
+    texel_time: [f32; 4],
+}
+
+pub(super) fn sampler() -> SamplerDescriptor {
+    SamplerDescriptor {
+        filter: Filter::Linear,
+        address_mode: SamplerAddressMode::ClampToEdge,
+        max_anisotropy: None,
+        border_color: BorderColor::FloatOpaqueBlack,
+    }
+}
+
+fn destroy_all(renderer: &mut Renderer, resources: Vec<Destroyable>) {
+    for resource in resources {
+        renderer.destroy_deferred(resource);
+    }
+}
+
+pub(super) fn color_texture(
+    renderer: &mut Renderer,
+    extent: util::Extent2D,
+) -> Handle<trekanten::Texture> {
+    let desc = TextureDescriptor::Empty {
+        extent,
+        format: HDR_FORMAT,
+        // Readable so that the scene color can be exported, see render::hdr_export
+        usage: TextureUsage::COLOR_ATTACHMENT | TextureUsage::TRANSFER_SRC,
+        sampler: sampler(),
+    };
+    renderer
+        .create_texture(desc)
+        .expect("Failed to create post-processing texture")
+}
+
+pub(super) fn source_descriptor_set(
+    renderer: &mut Renderer,
+    texture: &Handle<trekanten::Texture>,
+) -> Handle<DescriptorSet> {
+    DescriptorSet::builder(renderer)
+        .add_texture(texture, 0, ShaderStage::FRAGMENT, false)
+        .build()
+}
+
+/// Same as source_descriptor_set, for a texture that is replaced every frame. The set is only valid
+/// in this frame.
+pub(super) fn transient_source_descriptor_set(
+    frame: &mut Frame,
+    texture: &Handle<trekanten::Texture>,
+) -> Handle<DescriptorSet> {
+    DescriptorSet::transient_builder(frame)
+        .add_texture(texture, 0, ShaderStage::FRAGMENT, false)
+        .build()
+}
+
+// Single-sampled and color only, for passes that draw a fullscreen triangle each. If `load` is
+// set, the previous contents are kept, e.g. for blending, and are expected to be ready for sampling.
+pub(super) fn fullscreen_render_pass(renderer: &mut Renderer, load: bool) -> Handle<RenderPass> {
+    let (load_op, initial_layout) = if load {
+        (
+            raw_vk::AttachmentLoadOp::LOAD,
+            raw_vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+    } else {
+        // Every pixel is written
+        (
+            raw_vk::AttachmentLoadOp::DONT_CARE,
+            raw_vk::ImageLayout::UNDEFINED,
+        )
+    };
+    let color_attach = raw_vk::AttachmentDescription {
+        format: HDR_FORMAT.into(),
+        samples: raw_vk::SampleCountFlags::TYPE_1,
+        load_op,
+        store_op: raw_vk::AttachmentStoreOp::STORE,
+        stencil_load_op: raw_vk::AttachmentLoadOp::DONT_CARE,
+        stencil_store_op: raw_vk::AttachmentStoreOp::DONT_CARE,
+        initial_layout,
+        final_layout: raw_vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        flags: raw_vk::AttachmentDescriptionFlags::empty(),
+    };
+
+    let color_refs = [raw_vk::AttachmentReference {
+        attachment: 0,
+        layout: raw_vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    }];
+
+    let subpass = raw_vk::SubpassDescription::builder()
+        .pipeline_bind_point(raw_vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_refs);
+
+    let deps = [
+        raw_vk::SubpassDependency {
+            // The previous pass might be reading from or writing to this target
+            src_subpass: raw_vk::SUBPASS_EXTERNAL,
+            src_stage_mask: raw_vk::PipelineStageFlags::FRAGMENT_SHADER
+                | raw_vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            src_access_mask: raw_vk::AccessFlags::SHADER_READ
+                | raw_vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_subpass: 0,
+            dst_stage_mask: raw_vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            dst_access_mask: raw_vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | raw_vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dependency_flags: raw_vk::DependencyFlags::empty(),
+        },
+        raw_vk::SubpassDependency {
+            // The next pass samples the result
+            src_subpass: 0,
+            src_stage_mask: raw_vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            src_access_mask: raw_vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_subpass: raw_vk::SUBPASS_EXTERNAL,
+            dst_stage_mask: raw_vk::PipelineStageFlags::FRAGMENT_SHADER,
+            dst_access_mask: raw_vk::AccessFlags::SHADER_READ,
+            dependency_flags: raw_vk::DependencyFlags::empty(),
+        },
+    ];
+
+    let attachments = [color_attach];
+    let subpasses = [subpass.build()];
+    let create_info = raw_vk::RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(&subpasses)
+        .dependencies(&deps);
+
+    renderer
+        .create_render_pass(&create_info)
+        .expect("Failed to create fullscreen render pass")
+}
+
+pub(super) fn fullscreen_pipeline(
+    shader_compiler: &pipeline::ShaderCompiler,
+    renderer: &mut Renderer,
+    render_pass: &Handle<RenderPass>,
+    fragment_shader: &str,
+    defines: &pipeline::Defines,
+    blend_state: BlendState,
+) -> Result<Handle<GraphicsPipeline>, MaterialError> {
+    let vertex = shader_compiler.compile(
+        &pipeline::Defines::empty(),
+        "post/fullscreen_vert.glsl",
+        pipeline::ShaderType::Vertex,
+    )?;
+    let fragment =
+        shader_compiler.compile(defines, fragment_shader, pipeline::ShaderType::Fragment)?;
+
+    let desc = GraphicsPipelineDescriptor::builder()
+        .vert(ShaderDescriptor::FromRawSpirv(vertex.data()))
+        .frag(ShaderDescriptor::FromRawSpirv(fragment.data()))
+        .vertex_format(VertexFormat::empty())
+        .culling(TriangleCulling::None)
+        .depth_testing(trekanten::pipeline::DepthTest::Disabled)
+        .blend_state(blend_state)
+        .build()?;
+
+    Ok(renderer.create_gfx_pipeline(desc, render_pass)?)
+}
+
+impl Targets {
+    fn new(
+        renderer: &mut Renderer,
+        scene_render_pass: &Handle<RenderPass>,
+        bloom_render_pass: &Handle<RenderPass>,
+    ) -> Self {
+        let extent = renderer.swapchain_extent();
+        let scene_color = color_texture(renderer, extent);
+        let scene_target = renderer
+            .create_offscreen_render_target(scene_render_pass, &scene_color)
+            .expect("Failed to create scene render target");
+
+        let bloom_extent = util::Extent2D {
+            width: (extent.width / 2).max(1),
+            height: (extent.height / 2).max(1),
+        };
+        let mut fullscreen_target = |extent| {
+            let texture = color_texture(renderer, extent);
+            let render_target = renderer
+                .create_render_target(bloom_render_pass, &[&texture])
+                .expect("Failed to create post-processing render target");
+            let source = source_descriptor_set(renderer, &texture);
+            FullscreenTarget {
+                texture,
+                render_target,
+                source,
+            }
+        };
+        let bloom = [
+            fullscreen_target(bloom_extent),
+            fullscreen_target(bloom_extent),
+        ];
+        let effects = [fullscreen_target(extent), fullscreen_target(extent)];
+        let transmission = fullscreen_target(extent);
+        let transmission_blurred = [
+            fullscreen_target(bloom_extent),
+            fullscreen_target(bloom_extent),
+        ];
+
+        let scene_source = source_descriptor_set(renderer, &scene_color);
+        let composite_source = DescriptorSet::builder(renderer)
+            .add_texture(&scene_color, 0, ShaderStage::FRAGMENT, false)
+            .add_texture(&bloom[0].texture, 1, ShaderStage::FRAGMENT, false)
+            .build();
+
+        Self {
+            extent,
+            bloom_extent,
+            scene_color,
+            scene_target,
+            bloom,
+            effects,
+            transmission,
+            transmission_blurred,
+            scene_source,
+            composite_source,
+        }
+    }
+
+    fn destroy(self, renderer: &mut Renderer) -> Result<(), trekanten::RenderError> {
+        renderer.destroy_descriptor_set(self.composite_source)?;
+        renderer.destroy_descriptor_set(self.scene_source)?;
+        let targets = self
+            .bloom
+            .iter()
+            .chain(self.effects.iter())
+            .chain(std::iter::once(&self.transmission))
+            .chain(self.transmission_blurred.iter());
+        for target in targets {
+            renderer.destroy_descriptor_set(target.source)?;
+            renderer.destroy_render_target(target.render_target)?;
+            renderer.destroy_texture(target.texture)?;
+        }
+        renderer.destroy_render_target(self.scene_target)?;
+        renderer.destroy_texture(self.scene_color)
+    }
+}
+
+impl PostProcessing {
+    /// The scene is composited in `main_render_pass`
+    pub fn new(
+        shader_compiler: &pipeline::ShaderCompiler,
+        renderer: &mut Renderer,
+        main_render_pass: &Handle<RenderPass>,
+        msaa_sample_count: u8,
+    ) -> Result<Self, MaterialError> {
+        let scene_render_pass = renderer
+            .offscreen_render_pass(HDR_FORMAT, msaa_sample_count)
+            .expect("Failed to create scene render pass");
+        let split_scene_render_passes = renderer
+            .split_offscreen_render_passes(HDR_FORMAT, msaa_sample_count)
+            .expect("Failed to create split scene render passes");
+        let bloom_render_pass = fullscreen_render_pass(renderer, false);
+
+        let no_defines = pipeline::Defines::empty();
+        let bright_pass_pipeline = fullscreen_pipeline(
+            shader_compiler,
+            renderer,
+            &bloom_render_pass,
+            "post/bright_pass_frag.glsl",
+            &no_defines,
+            BlendState::Disabled,
+        )?;
+        let blur_pipeline = fullscreen_pipeline(
+            shader_compiler,
+            renderer,
+            &bloom_render_pass,
+            "post/blur_frag.glsl",
+            &no_defines,
+            BlendState::Disabled,
+        )?;
+        let (composite_pipeline, copy_pipeline) =
+            Self::composite_pipelines(shader_compiler, renderer, main_render_pass)?;
+        let (offscreen_composite_pipeline, offscreen_copy_pipeline) =
+            Self::composite_pipelines(shader_compiler, renderer, &bloom_render_pass)?;
+        let false_color_pipeline =
+            Self::false_color_pipeline(shader_compiler, renderer, main_render_pass)?;
+
+        let mut effect_pipelines = Vec::with_capacity(PostEffect::COUNT);
+        for i in 0..PostEffect::COUNT {
+            let mut defines = pipeline::Defines::empty();
+            for j in 0..PostEffect::COUNT {
+                let value = if i == j { "1" } else { "0" };
+                defines.push((String::from(PostEffect::define(j)), String::from(value)));
+            }
+            effect_pipelines.push(fullscreen_pipeline(
+                shader_compiler,
+                renderer,
+                &bloom_render_pass,
+                "post/effect_frag.glsl",
+                &defines,
+                BlendState::Disabled,
+            )?);
+        }
+
+        let targets = Targets::new(renderer, &scene_render_pass, &bloom_render_pass);
+
+        Ok(Self {
+            scene_render_pass,
+            split_scene_render_passes,
+            bloom_render_pass,
+            bright_pass_pipeline,
+            blur_pipeline,
+            composite_pipeline,
+            copy_pipeline,
+            offscreen_composite_pipeline,
+            offscreen_copy_pipeline,
+            false_color_pipeline,
+            effect_pipelines,
+            targets,
+            transmission_ready: AtomicBool::new(false),
+        })
+    }
+
+    fn composite_pipelines(
+        shader_compiler: &pipeline::ShaderCompiler,
+        renderer: &mut Renderer,
+        render_pass: &Handle<RenderPass>,
+    ) -> Result<(Handle<GraphicsPipeline>, Handle<GraphicsPipeline>), MaterialError> {
+        let mut bloom = pipeline::Defines::empty();
+        bloom.push((String::from("HAS_BLOOM"), String::from("1")));
+        let composite = fullscreen_pipeline(
+            shader_compiler,
+            renderer,
+            render_pass,
+            "post/composite_frag.glsl",
+            &bloom,
+            BlendState::Disabled,
+        )?;
+
+        let mut no_bloom = pipeline::Defines::empty();
+        no_bloom.push((String::from("HAS_BLOOM"), String::from("0")));
+        let copy = fullscreen_pipeline(
+            shader_compiler,
+            renderer,
+            render_pass,
+            "post/composite_frag.glsl",
+            &no_bloom,
+            BlendState::Disabled,
+        )?;
+
+        Ok((composite, copy))
+    }
+
+    fn false_color_pipeline(
+        shader_compiler: &pipeline::ShaderCompiler,
+        renderer: &mut Renderer,
+        render_pass: &Handle<RenderPass>,
+    ) -> Result<Handle<GraphicsPipeline>, MaterialError> {
+        fullscreen_pipeline(
+            shader_compiler,
+            renderer,
+            render_pass,
+            "post/false_color_frag.glsl",
+            &pipeline::Defines::empty(),
+            BlendState::Disabled,
+        )
+    }
+
+    /// The render pass that the scene is drawn in
+    pub fn scene_render_pass(&self) -> &Handle<RenderPass> {
+        &self.scene_render_pass
+    }
+
+    /// Recreates the scene render pass with the new sample count. Pipelines created for the previous
+    /// render pass have to be recreated. The composite pipelines are recreated here, for
+    /// `main_render_pass`, and the previous ones are destroyed along with the previous render
+    /// passes. Nothing is changed if any of them can't be created.
+    pub fn set_msaa_sample_count(
+        &mut self,
+        shader_compiler: &pipeline::ShaderCompiler,
+        renderer: &mut Renderer,
+        main_render_pass: &Handle<RenderPass>,
+        msaa_sample_count: u8,
+    ) -> Result<(), RenderError> {
+        let (composite, copy) =
+            Self::composite_pipelines(shader_compiler, renderer, main_render_pass)
+                .map_err(|e| RenderError::setup("Failed to create composite pipelines", e))?;
+        let mut created: Vec<Destroyable> = vec![composite.into(), copy.into()];
+        let false_color =
+            match Self::false_color_pipeline(shader_compiler, renderer, main_render_pass) {
+                Ok(false_color) => false_color,
+                Err(e) => {
+                    destroy_all(renderer, created);
+                    return Err(RenderError::setup(
+                        "Failed to create false color pipeline",
+                        e,
+                    ));
+                }
+            };
+        created.push(false_color.into());
+        let scene_render_pass = match renderer.offscreen_render_pass(HDR_FORMAT, msaa_sample_count)
+        {
+            Ok(render_pass) => render_pass,
+            Err(e) => {
+                destroy_all(renderer, created);
+                return Err(RenderError::setup("Failed to create scene render pass", e));
+            }
+        };
+        created.push(scene_render_pass.into());
+        let split_scene_render_passes =
+            match renderer.split_offscreen_render_passes(HDR_FORMAT, msaa_sample_count) {
+                Ok(render_passes) => render_passes,
+                Err(e) => {
+                    destroy_all(renderer, created);
+                    return Err(RenderError::setup(
+                        "Failed to create split scene render passes",
+                        e,
+                    ));
+                }
+            };
+
+        renderer.destroy_deferred(std::mem::replace(&mut self.composite_pipeline, composite));
+        renderer.destroy_deferred(std::mem::replace(&mut self.copy_pipeline, copy));
+        renderer.destroy_deferred(std::mem::replace(
+            &mut self.false_color_pipeline,
+            false_color,
+        ));
+        renderer.destroy_deferred(std::mem::replace(
+            &mut self.scene_render_pass,
+            scene_render_pass,
+        ));
+        let old = std::mem::replace(
+            &mut self.split_scene_render_passes,
+            split_scene_render_passes,
+        );
+        for render_pass in old.iter() {
+            renderer.destroy_deferred(*render_pass);
+        }
+        self.recreate_targets(renderer);
+        Ok(())
+    }
+
+    /// The HDR texture that the scene is resolved to. It is recreated when the targets are.
+    pub fn scene_color(&self) -> &Handle<trekanten::Texture> {
+        &self.targets.scene_color
+    }
+
+    /// Recreates the render targets if the presented image has changed size. Returns true if they
+    /// were recreated.
+    pub fn resize(&mut self, renderer: &mut Renderer) -> bool {
+        if self.targets.extent != renderer.swapchain_extent() {
+            self.recreate_targets(renderer);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn recreate_targets(&mut self, renderer: &mut Renderer) {
+        let targets = Targets::new(renderer, &self.scene_render_pass, &self.bloom_render_pass);
+        let old = std::mem::replace(&mut self.targets, targets);
+        if let Err(e) = old.destroy(renderer) {
+            log::error!("Failed to destroy post-processing targets: {}", e);
+        }
+        self.transmission_ready.store(false, Ordering::Relaxed);
+    }
+
+    /// The opaque part of the scene, sharp and blurred, that transmissive materials sample. They are
+    /// recreated when the targets are.
+    pub fn transmission(&self) -> [&Handle<trekanten::Texture>; 2] {
+        [
+            &self.targets.transmission.texture,
+            &self.targets.transmission_blurred[0].texture,
+        ]
+    }
+
+    /// The color and depth are cleared, except in ScenePass::Transmissive
+    pub fn begin_scene_pass<'a>(
+        &self,
+        frame: &'a Frame<'a>,
+        cmd_buffer: CommandBuffer,
+        pass: ScenePass,
+    ) -> RenderPassEncoder<'a> {
+        let [opaque, transmissive] = &self.split_scene_render_passes;
+        let render_pass = match pass {
+            ScenePass::Whole => &self.scene_render_pass,
+            ScenePass::Opaque => opaque,
+            ScenePass::Transmissive => transmissive,
+        };
+        let clear_values = [
+            raw_vk::ClearValue {
+                color: raw_vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 1.0],
+                },
+            },
+            raw_vk::ClearValue {
+                depth_stencil: raw_vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        frame
+            .begin_render_pass(
+                cmd_buffer,
+                render_pass,
+                &self.targets.scene_target,
+                self.targets.extent,
+                &clear_values,
+            )
+            .expect("Failed to begin scene render pass")
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn fullscreen_pass<P: Copy>(
+        &self,
+        frame: &Frame<'_>,
+        cmd_buffer: CommandBuffer,
+        target: &FullscreenTarget,
+        extent: util::Extent2D,
+        pipeline: &Handle<GraphicsPipeline>,
+        source: &Handle<DescriptorSet>,
+        params: &P,
+    ) -> CommandBuffer {
+        let mut pass = frame
+            .begin_render_pass(
+                cmd_buffer,
+                &self.bloom_render_pass,
+                &target.render_target,
+                extent,
+                &[],
+            )
+            .expect("Failed to begin post-processing render pass");
+        pass.bind_graphics_pipeline(pipeline)
+            .bind_shader_resource_group(0, source, pipeline);
+        // The copy doesn't have any parameters
+        if std::mem::size_of::<P>() > 0 {
+            pass.bind_push_constant(pipeline, ShaderStage::FRAGMENT, params);
+        }
+        pass.draw(3);
+        pass.end()
+            .expect("Failed to end post-processing render pass")
+    }
+
+    /// The transmission textures have to be ready for sampling whenever the scene is drawn, also
+    /// before anything transmissive has been. Records empty passes for that the first time after the
+    /// targets are created. Has to be recorded before the scene pass.
+    pub fn prepare_transmission(
+        &self,
+        frame: &Frame<'_>,
+        mut cmd_buffer: CommandBuffer,
+    ) -> CommandBuffer {
+        if self.transmission_ready.swap(true, Ordering::Relaxed) {
+            return cmd_buffer;
+        }
+
+        let targets = &self.targets;
+        let passes = [
+            (&targets.transmission, targets.extent),
+            (&targets.transmission_blurred[0], targets.bloom_extent),
+        ];
+        for (target, extent) in passes.iter() {
+            cmd_buffer = frame
+                .begin_render_pass(
+                    cmd_buffer,
+                    &self.bloom_render_pass,
+                    &target.render_target,
+                    *extent,
+                    &[],
+                )
+                .expect("Failed to begin transmission render pass")
+                .end()
+                .expect("Failed to end transmission render pass");
+        }
+        cmd_buffer
+    }
+
+    /// Copies the scene color to the transmission textures and blurs the copy, see transmission.
+    /// Has to be recorded between the ScenePass::Opaque and ScenePass::Transmissive passes.
+    pub fn transmission_passes(
+        &self,
+        frame: &Frame<'_>,
+        mut cmd_buffer: CommandBuffer,
+    ) -> CommandBuffer {
+        let targets = &self.targets;
+        let [first, second] = &targets.transmission_blurred;
+        cmd_buffer = self.fullscreen_pass(
+            frame,
+            cmd_buffer,
+            &targets.transmission,
+            targets.extent,
+            &self.offscreen_copy_pipeline,
+            &targets.scene_source,
+            &(),
+        );
+
+        let extent = targets.bloom_extent;
+        cmd_buffer = self.fullscreen_pass(
+            frame,
+            cmd_buffer,
+            first,
+            extent,
+            &self.offscreen_copy_pipeline,
+            &targets.transmission.source,
+            &(),
+        );
+        // Every other texel, for a wider blur than the bloom
+        let step = [2.0 / extent.width as f32, 2.0 / extent.height as f32];
+        cmd_buffer = self.fullscreen_pass(
+            frame,
+            cmd_buffer,
+            second,
+            extent,
+            &self.blur_pipeline,
+            &first.source,
+            &[step[0], 0.0],
+        );
+        self.fullscreen_pass(
+            frame,
+            cmd_buffer,
+            first,
+            extent,
+            &self.blur_pipeline,
+            &second.source,
+            &[0.0, step[1]],
+        )
+    }
+
+    /// Records the bright pass and the blur, if bloom is enabled
+    pub fn bloom_passes(
+        &self,
+        frame: &Frame<'_>,
+        mut cmd_buffer: CommandBuffer,
+        settings: &RenderSettings,
+    ) -> CommandBuffer {
+        if !settings.bloom {
+            return cmd_buffer;
+        }
+
+        let extent = self.targets.bloom_extent;
+        let [first, second] = &self.targets.bloom;
+        cmd_buffer = self.fullscreen_pass(
+            frame,
+            cmd_buffer,
+            first,
+            extent,
+            &self.bright_pass_pipeline,
+            &self.targets.scene_source,
+            &settings.bloom_threshold,
+        );
+
+        let texel = [1.0 / extent.width as f32, 1.0 / extent.height as f32];
+        cmd_buffer = self.fullscreen_pass(
+            frame,
+            cmd_buffer,
+            second,
+            extent,
+            &self.blur_pipeline,
+            &first.source,
+            &[texel[0], 0.0],
+        );
+        self.fullscreen_pass(
+            frame,
+            cmd_buffer,
+            first,
+            extent,
+            &self.blur_pipeline,
+            &second.source,
+            &[0.0, texel[1]],
+        )
+    }
+
+    /// Records the composite to the first effect target and the enabled effects, if there are any.
+    /// `time` is in seconds and animates the film grain.
+    pub fn effect_passes(
+        &self,
+        frame: &Frame<'_>,
+        mut cmd_buffer: CommandBuffer,
+        settings: &RenderSettings,
+        effects: &PostEffectStack,
+        time: f32,
+    ) -> CommandBuffer {
+        if effects.enabled().next().is_none() {
+            return cmd_buffer;
+        }
+
+        let extent = self.targets.extent;
+        let [first, second] = &self.targets.effects;
+        cmd_buffer = if settings.bloom {
+            self.fullscreen_pass(
+                frame,
+                cmd_buffer,
+                first,
+                extent,
+                &self.offscreen_composite_pipeline,
+                &self.targets.composite_source,
+                &settings.bloom_intensity,
+            )
+        } else {
+            self.fullscreen_pass(
+                frame,
+                cmd_buffer,
+                first,
+                extent,
+                &self.offscreen_copy_pipeline,
+                &self.targets.scene_source,
+                &(),
+            )
+        };
+
+        let texel = [1.0 / extent.width as f32, 1.0 / extent.height as f32];
+        let (mut src, mut dst) = (first, second);
+        for effect in effects.enabled() {
+            let params = EffectParams {
+                params: effect.params(),
+                texel_time: [texel[0], texel[1], time, 0.0],
+            };
+            cmd_buffer = self.fullscreen_pass(
+                frame,
+                cmd_buffer,
+                dst,
+                extent,
+                &self.effect_pipelines[effect.index()],
+                &src.source,
+                &params,
+            );
+            std::mem::swap(&mut src, &mut dst);
+        }
+
+        cmd_buffer
+    }
+
+    /// The scene as it was drawn, without bloom or effects, for the view modes, see
+    /// render::view_mode
+    pub fn copy_scene(&self, pass: &mut RenderPassEncoder<'_>) {
+        let pipeline = &self.copy_pipeline;
+        pass.bind_graphics_pipeline(pipeline)
+            .bind_shader_resource_group(0, &self.targets.scene_source, pipeline)
+            .draw(3);
+    }
+
+    /// Draws the scene, with bloom if enabled, in the current render pass. If there are enabled
+    /// effects, the result of effect_passes is drawn instead. An exposure view replaces both, as it
+    /// shows the scene color before bloom and effects.
+    pub fn composite(
+        &self,
+        pass: &mut RenderPassEncoder<'_>,
+        settings: &RenderSettings,
+        effects: &PostEffectStack,
+        reference: Option<&Handle<DescriptorSet>>,
+    ) {
+        // The path traced image replaces the scene, see path_trace
+        if let Some(reference) = reference {
+            let pipeline = &self.copy_pipeline;
+            pass.bind_graphics_pipeline(pipeline)
+                .bind_shader_resource_group(0, reference, pipeline)
+                .draw(3);
+            return;
+        }
+
+        if settings.exposure_view != ExposureView::Off {
+            let pipeline = &self.false_color_pipeline;
+            pass.bind_graphics_pipeline(pipeline)
+                .bind_shader_resource_group(0, &self.targets.scene_source, pipeline)
+                .bind_push_constant(
+                    pipeline,
+                    ShaderStage::FRAGMENT,
+                    &ExposureParams::new(settings),
+                )
+                .draw(3);
+            return;
+        }
+
+        let n_effects = effects.enabled().count();
+        if n_effects > 0 {
+            // The last effect wrote to the first target if there was an even number of them
+            let pipeline = &self.copy_pipeline;
+            let result = &self.targets.effects[n_effects % 2];
+            pass.bind_graphics_pipeline(pipeline)
+                .bind_shader_resource_group(0, &result.source, pipeline)
+                .draw(3);
+        } else if settings.bloom {
+            let pipeline = &self.composite_pipeline;
+            pass.bind_graphics_pipeline(pipeline)
+                .bind_shader_resource_group(0, &self.targets.composite_source, pipeline)
+                .bind_push_constant(pipeline, ShaderStage::FRAGMENT, &settings.bloom_intensity)
+                .draw(3);
+        } else {
+            let pipeline = &self.copy_pipeline;
+            pass.bind_graphics_pipeline(pipeline)
+                .bind_shader_resource_group(0, &self.targets.scene_source, pipeline)
+                .draw(3);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Artistic effects that are applied to the final HDR image, after the bloom, one fullscreen pass
+//! each. They run in the order of the PostEffectStack resource, which can be edited in the
+//! post-processing window.
+
+use imgui::im_str;
+
+use crate::ecs::prelude::*;
+use crate::editor::Inspect as _;
+use crate::render::ui::UiFrame;
+
+use ramneryd_derive::Inspect;
+
+#[derive(Debug, Clone, Copy, PartialEq, Inspect)]
+pub enum PostEffect {
+    /// Splits the color channels towards the edges of the image, as a cheap lens would
+    ChromaticAberration {
+        strength: f32,
+    },
+    /// Darkens the image outside of radius, which is relative to the corners of the image
+    Vignette {
+        intensity: f32,
+        radius: f32,
+        smoothness: f32,
+    },
+    FilmGrain {
+        intensity: f32,
+    },
+    Sharpen {
+        strength: f32,
+    },
+}
+
+impl PostEffect {
+    pub const COUNT: usize = 4;
+
+    /// One of each effect, in the default order
+    pub fn all() -> [Self; Self::COUNT] {
+        [
+            Self::Sharpen { strength: 0.2 },
+            Self::ChromaticAberration { strength: 0.005 },
+            Self::Vignette {
+                intensity: 0.5,
+                radius: 0.6,
+                smoothness: 0.4,
+            },
+            Self::FilmGrain { intensity: 0.05 },
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ChromaticAberration { .. } => "Chromatic aberration",
+            Self::Vignette { .. } => "Vignette",
+            Self::FilmGrain { .. } => "Film grain",
+            Self::Sharpen { .. } => "Sharpen",
+        }
+    }
+
+    /// Each effect has its own pipeline, this is the index of it
+    pub(super) fn index(&self) -> usize {
+        match self {
+            Self::ChromaticAberration { .. } => 0,
+            Self::Vignette { .. } => 1,
+            Self::FilmGrain { .. } => 2,
+            Self::Sharpen { .. } => 3,
+        }
+    }
+
+    /// Selects the effect in post/effect_frag.glsl
+    pub(super) fn define(index: usize) -> &'static str {
+        ["CHROMATIC_ABERRATION", "VIGNETTE", "FILM_GRAIN", "SHARPEN"][index]
+    }
+
+    /// The parameters of the effect, in the order of the fields
+    pub(super) fn params(&self) -> [f32; 4] {
+        match *self {
+            Self::ChromaticAberration { strength } | Self::Sharpen { strength } => {
+                [strength, 0.0, 0.0, 0.0]
+            }
+            Self::Vignette {
+                intensity,
+                radius,
+                smoothness,
+            } => [intensity, radius, smoothness, 0.0],
+            Self::FilmGrain { intensity } => [intensity, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PostEffectStage {
+    pub enabled: bool,
+    pub effect: PostEffect,
+}
+
+/// The effects, in the order they are applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostEffectStack {
+    pub stages: Vec<PostEffectStage>,
+}
+
+impl Default for PostEffectStack {
+    /// All effects, disabled
+    fn default() -> Self {
+        Self {
+            stages: PostEffect::all()
+                .iter()
+                .map(|&effect| PostEffectStage {
+                    enabled: false,
+                    effect,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl PostEffectStack {
+    pub fn enabled(&self) -> impl Iterator<Item = &PostEffect> {
+        self.stages
+            .iter()
+            .filter(|stage| stage.enabled)
+            .map(|stage| &stage.effect)
+    }
+}
+
+pub(crate) fn build_ui<'a>(world: &mut World, ui: &UiFrame<'a>, pos: [f32; 2]) -> [f32; 2] {
+    let size = [300.0, 85.0];
+
+    imgui::Window::new(im_str!("Post-processing"))
+        .position(pos, imgui::Condition::FirstUseEver)
+        .size(size, imgui::Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let mut stack = world.write_resource::<PostEffectStack>();
+            let n_stages = stack.stages.len();
+            let mut swap = None;
+            for (i, stage) in stack.stages.iter_mut().enumerate() {
+                let id_token = ui.inner().push_id(i as i32);
+                ui.inner()
+                    .checkbox(&im_str!("{}", stage.effect.name()), &mut stage.enabled);
+                ui.inner().same_line(0.0);
+                if ui.inner().button(im_str!("Up"), [0.0, 0.0]) && i > 0 {
+                    swap = Some((i - 1, i));
+                }
+                ui.inner().same_line(0.0);
+                if ui.inner().button(im_str!("Down"), [0.0, 0.0]) && i + 1 < n_stages {
+                    swap = Some((i, i + 1));
+                }
+                if stage.enabled {
+                    stage.effect.inspect_mut(ui, "");
+                }
+                id_token.pop(ui.inner());
+            }
+
+            if let Some((a, b)) = swap {
+                stack.stages.swap(a, b);
+            }
+        });
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_pipeline_per_effect() {
+        let mut indices = PostEffect::all()
+            .iter()
+            .map(PostEffect::index)
+            .collect::<Vec<_>>();
+        indices.sort_unstable();
+        assert_eq!(indices, (0..PostEffect::COUNT).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn only_enabled_effects_run_in_order() {
+        let mut stack = PostEffectStack::default();
+        assert_eq!(stack.enabled().count(), 0);
+
+        stack.stages[3].enabled = true;
+        stack.stages[1].enabled = true;
+        stack.stages.swap(1, 3);
+        let names = stack.enabled().map(PostEffect::name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["Film grain", "Chromatic aberration"]);
+    }
+}
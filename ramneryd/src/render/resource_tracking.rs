@@ -0,0 +1,213 @@
+//! Destroys the gpu resources of entities once they are no longer referenced by any component, either
+//! because the entity was deleted or because the component was removed or replaced.
+
+use specs::prelude::*;
+
+use trekanten::descriptor::DescriptorSet;
+use trekanten::mem::{IndexBuffer, UniformBuffer, VertexBuffer};
+use trekanten::pipeline::GraphicsPipeline;
+use trekanten::resource::Handle;
+use trekanten::{BufferHandle, Renderer, Texture};
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use super::deformation::GpuDeformation;
+use super::material::{GpuMaterial, TextureCache};
+use super::mesh::GpuMesh;
+use super::streaming::TextureStreaming;
+use super::RenderableMaterial;
+
+/// The resources that the components of one entity refer to
+#[derive(Default)]
+struct Owned {
+    textures: Vec<Handle<Texture>>,
+    descriptor_sets: Vec<Handle<DescriptorSet>>,
+    vertex_buffers: Vec<BufferHandle<VertexBuffer>>,
+    index_buffers: Vec<BufferHandle<IndexBuffer>>,
+    uniform_buffers: Vec<BufferHandle<UniformBuffer>>,
+    // One reference each, as they are shared through the pipeline cache
+    pipelines: Vec<Handle<GraphicsPipeline>>,
+}
+
+fn collect_owned(world: &World) -> HashMap<Entity, Owned> {
+    let entities = world.entities();
+    let meshes = world.read_storage::<GpuMesh>();
+    let materials = world.read_storage::<GpuMaterial>();
+    let renderables = world.read_storage::<RenderableMaterial>();
+    let deformations = world.read_storage::<GpuDeformation>();
+
+    let mut owned = HashMap::<Entity, Owned>::new();
+    for (ent, mesh) in (&entities, &meshes).join() {
+        let o = owned.entry(ent).or_default();
+        o.vertex_buffers.push(mesh.vertex_buffer);
+        o.index_buffers.push(mesh.index_buffer);
+    }
+
+    for (ent, mat) in (&entities, &materials).join() {
+        let o = owned.entry(ent).or_default();
+        match mat {
+            GpuMaterial::Unlit { color_uniform } => o.uniform_buffers.push(*color_uniform),
+            GpuMaterial::PBR {
+                material_uniforms,
+                normal_map,
+                base_color_texture,
+                metallic_roughness_texture,
+                emissive_texture,
+                occlusion_texture,
+                ..
+            } => {
+                o.uniform_buffers.push(*material_uniforms);
+                o.textures.extend(
+                    [
+                        normal_map,
+                        base_color_texture,
+                        metallic_roughness_texture,
+                        emissive_texture,
+                        occlusion_texture,
+                    ]
+                    .iter()
+                    .copied()
+                    .flatten()
+                    .map(|t| t.handle),
+                );
+            }
+        }
+    }
+
+    for (ent, renderable) in (&entities, &renderables).join() {
+        let o = owned.entry(ent).or_default();
+        match renderable {
+            RenderableMaterial::PBR {
+                gfx_pipeline,
+                shadow_pipeline,
+                depth_pipeline,
+                material_descriptor_set,
+                ..
+            } => {
+                o.pipelines
+                    .extend_from_slice(&[*gfx_pipeline, *shadow_pipeline, *depth_pipeline]);
+                o.descriptor_sets.push(*material_descriptor_set);
+            }
+            RenderableMaterial::Unlit {
+                gfx_pipeline,
+                material_descriptor_set,
+            } => {
+                o.pipelines.push(*gfx_pipeline);
+                o.descriptor_sets.push(*material_descriptor_set);
+            }
+        }
+    }
+
+    for (ent, deformation) in (&entities, &deformations).join() {
+        let o = owned.entry(ent).or_default();
+        o.descriptor_sets.push(deformation.descriptor_set);
+        o.vertex_buffers.push(deformation.vertex_buffer);
+    }
+
+    owned
+}
+
+/// The handles that were referenced in prev but are not in cur, deduplicated by key
+fn unreferenced<T, K, F, Key>(
+    prev: &HashMap<Entity, Owned>,
+    cur: &HashMap<Entity, Owned>,
+    field: F,
+    key: Key,
+) -> Vec<T>
+where
+    T: Copy,
+    K: Hash + Eq,
+    F: Fn(&Owned) -> &Vec<T>,
+    Key: Fn(&T) -> K,
+{
+    let live: HashSet<K> = cur
+        .values()
+        .flat_map(|o| field(o).iter().map(&key))
+        .collect();
+    let mut released = HashMap::new();
+    for h in prev.values().flat_map(|o| field(o).iter()) {
+        let k = key(h);
+        if !live.contains(&k) {
+            released.entry(k).or_insert(*h);
+        }
+    }
+    released.into_iter().map(|(_, h)| h).collect()
+}
+
+fn same<T>(h: &Handle<T>) -> Handle<T> {
+    *h
+}
+
+// Sub-buffers of the same buffer are shared between materials, so the whole buffer is destroyed
+// once none of them are used.
+fn whole_buffer<T>(h: &BufferHandle<T>) -> Handle<T> {
+    *h.handle()
+}
+
+/// The gpu resources referred to by the components of each entity last frame
+#[derive(Default)]
+pub struct GpuResourceTracker {
+    owned: HashMap<Entity, Owned>,
+}
+
+impl GpuResourceTracker {
+    /// A renderable that was removed since the last frame is being recreated. The new one holds
+    /// new references to the pipelines so the old ones are released, as they will look unchanged.
+    pub fn release_removed_pipelines(&mut self, ent: Entity, renderer: &mut Renderer) {
+        if let Some(owned) = self.owned.get_mut(&ent) {
+            for h in owned.pipelines.drain(..) {
+                renderer.destroy_deferred(h);
+            }
+        }
+    }
+}
+
+/// Has to run after all components with gpu resources have been created or replaced for this frame
+/// but before the frame is recorded. The resources that are no longer used are destroyed once the
+/// frames in flight are done with them.
+#[profiling::function]
+pub fn release_unused(world: &mut World, renderer: &mut Renderer) {
+    let cur = collect_owned(world);
+    let mut tracker = world.write_resource::<GpuResourceTracker>();
+    let streaming = world.read_resource::<TextureStreaming>();
+    let mut texture_cache = world.write_resource::<TextureCache>();
+    let prev = std::mem::take(&mut tracker.owned);
+
+    for h in unreferenced(&prev, &cur, |o| &o.textures, same) {
+        if !streaming.is_placeholder(&h) {
+            texture_cache.remove(h);
+            renderer.destroy_deferred(h);
+        }
+    }
+    for h in unreferenced(&prev, &cur, |o| &o.descriptor_sets, same) {
+        renderer.destroy_deferred(h);
+    }
+    for h in unreferenced(&prev, &cur, |o| &o.vertex_buffers, whole_buffer) {
+        renderer.destroy_deferred(h);
+    }
+    for h in unreferenced(&prev, &cur, |o| &o.index_buffers, whole_buffer) {
+        renderer.destroy_deferred(h);
+    }
+    for h in unreferenced(&prev, &cur, |o| &o.uniform_buffers, whole_buffer) {
+        renderer.destroy_deferred(h);
+    }
+
+    // Pipelines are released once for each reference that an entity dropped
+    for (ent, prev_owned) in prev.iter() {
+        let mut still_used = cur
+            .get(ent)
+            .map(|o| o.pipelines.clone())
+            .unwrap_or_default();
+        for h in prev_owned.pipelines.iter() {
+            match still_used.iter().position(|used| used == h) {
+                Some(idx) => {
+                    still_used.swap_remove(idx);
+                }
+                None => renderer.destroy_deferred(*h),
+            }
+        }
+    }
+
+    tracker.owned = cur;
+}
@@ -0,0 +1,309 @@
+//! Display overrides for reviewing models in the viewer. The meshes of an entity with a
+//! ReviewDisplay, and of the entities below it, are drawn again by the review pass after the scene:
+//! as their edges in a chosen color and line width, or as an x-ray that shows through everything in
+//! front of them. The closest ReviewDisplay above a mesh is used.
+//!
+//! The meshes with an override are left out of the wireframe overlay, so ReviewMode::Solid keeps
+//! them as they are, e.g. to keep the finished parts of a model out of the way. They are drawn with
+//! the overlay pipelines of the wireframe.
+
+use std::collections::HashMap;
+
+use ramneryd_derive::Inspect;
+
+use trekanten::descriptor::DescriptorSet;
+use trekanten::pipeline::ShaderStage;
+use trekanten::resource::Handle;
+use trekanten::{Frame, RenderPass, RenderPassEncoder, Renderer};
+
+use crate::ecs::prelude::*;
+use crate::editor::Inspect;
+use crate::graph;
+use crate::math::{ModelMatrix, Rgba};
+use crate::render::ui::UiFrame;
+
+use super::deformation::GpuDeformation;
+use super::mesh::GpuMesh;
+use super::wireframe::{record_items, DrawItem, OverlayPipelines, OverlayStyle};
+use super::{pipeline, uniform, Hidden};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Inspect)]
+pub enum ReviewMode {
+    /// Drawn as usual and left out of the wireframe overlay
+    Solid,
+    /// The edges over the lit meshes
+    Wireframe,
+    /// Transparent, in front of everything else
+    XRay,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Component, Inspect)]
+#[component(inspect)]
+pub struct ReviewDisplay {
+    pub mode: ReviewMode,
+    /// The alpha is used by the x-ray
+    pub color: Rgba,
+    /// In pixels, limited to what the device supports
+    pub line_width: u8,
+}
+
+impl ReviewDisplay {
+    pub fn new(mode: ReviewMode) -> Self {
+        Self {
+            mode,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for ReviewDisplay {
+    fn default() -> Self {
+        Self {
+            mode: ReviewMode::Wireframe,
+            color: Rgba::new(0.0, 0.8, 1.0, 0.4),
+            line_width: 1,
+        }
+    }
+}
+
+fn depth(parents: &ReadStorage<graph::Parent>, mut ent: Entity) -> usize {
+    let mut depth = 0;
+    while let Some(parent) = parents.get(ent) {
+        ent = parent.parent;
+        depth += 1;
+    }
+    depth
+}
+
+/// The ReviewDisplay that applies to each entity, the closest one above it
+pub(super) fn review_displays(world: &World) -> HashMap<Entity, ReviewDisplay> {
+    let displays = world.read_storage::<ReviewDisplay>();
+    let parents = world.read_storage::<graph::Parent>();
+    let children = world.read_storage::<graph::Children>();
+
+    let mut roots: Vec<(usize, Entity, ReviewDisplay)> = (&world.entities(), &displays)
+        .join()
+        .map(|(ent, display)| (depth(&parents, ent), ent, *display))
+        .collect();
+    // The deeper ones overwrite the ones above them
+    roots.sort_by_key(|(depth, _, _)| *depth);
+
+    let mut applied = HashMap::new();
+    for (_, root, display) in roots {
+        graph::breadth_first(&children, root, |node| {
+            applied.insert(node, display);
+        });
+    }
+    applied
+}
+
+#[derive(Default)]
+pub(super) struct ReviewRenderer {
+    pipelines: OverlayPipelines,
+    /// Reused between frames, there is one for each distinct color of the frame. The colors are
+    /// bound to slices of the transient buffer of the frame, see upload.
+    color_sets: Vec<Handle<DescriptorSet>>,
+    colors: Vec<[f32; 4]>,
+    wireframe_items: Vec<DrawItem>,
+    /// Drawn after the wireframes, so that they are not drawn over it
+    x_ray_items: Vec<DrawItem>,
+}
+
+fn color_set(renderer: &mut Renderer) -> Handle<DescriptorSet> {
+    DescriptorSet::builder(renderer)
+        .add_transient::<uniform::UnlitUniformData>(0, ShaderStage::FRAGMENT)
+        .build()
+}
+
+impl ReviewRenderer {
+    /// The pipelines are created for the scene render pass, so they have to be recreated when it is
+    pub fn clear_pipelines(&mut self, renderer: &mut Renderer) {
+        self.pipelines.clear(renderer);
+        self.wireframe_items.clear();
+        self.x_ray_items.clear();
+    }
+
+    /// The descriptor set of the color, there is one per distinct color of the frame
+    fn color_set(&mut self, renderer: &mut Renderer, color: Rgba) -> Handle<DescriptorSet> {
+        let color = color.into_array();
+        let idx = match self.colors.iter().position(|c| *c == color) {
+            Some(idx) => idx,
+            None => {
+                self.colors.push(color);
+                self.colors.len() - 1
+            }
+        };
+        if idx == self.color_sets.len() {
+            self.color_sets.push(color_set(renderer));
+        }
+        self.color_sets[idx]
+    }
+
+    /// Collects the meshes to draw this frame and creates the pipelines and colors for them. Has to
+    /// be called before the frame is started.
+    pub fn prepare(
+        &mut self,
+        world: &World,
+        renderer: &mut Renderer,
+        scene_render_pass: &Handle<RenderPass>,
+    ) {
+        self.wireframe_items.clear();
+        self.x_ray_items.clear();
+        self.colors.clear();
+        let displays = review_displays(world);
+        if displays.is_empty() {
+            return;
+        }
+
+        let max_line_width = renderer.max_line_width().max(1.0).min(u8::MAX as f32) as u8;
+        let shader_compiler = world.read_resource::<pipeline::ShaderCompiler>();
+        let meshes = world.read_storage::<GpuMesh>();
+        let model_matrices = world.read_storage::<ModelMatrix>();
+        let hidden = world.read_storage::<Hidden>();
+        let deformations = world.read_storage::<GpuDeformation>();
+        for (ent, mesh, mtx, deformation, _) in (
+            &world.entities(),
+            &meshes,
+            &model_matrices,
+            deformations.maybe(),
+            !&hidden,
+        )
+            .join()
+        {
+            let display = match displays.get(&ent) {
+                Some(display) if display.mode != ReviewMode::Solid => display,
+                _ => continue,
+            };
+
+            let style = match display.mode {
+                ReviewMode::XRay => OverlayStyle::XRay,
+                _ => OverlayStyle::Lines {
+                    width: display.line_width.max(1).min(max_line_width),
+                },
+            };
+            let vertex_format = renderer
+                .get_resource(&mesh.vertex_buffer)
+                .expect("Invalid handle")
+                .format()
+                .clone();
+            let pipeline = match self.pipelines.get(
+                &shader_compiler,
+                renderer,
+                scene_render_pass,
+                vertex_format,
+                style,
+            ) {
+                Ok(pipeline) => pipeline,
+                Err(e) => {
+                    log::error!("Failed to create review pipeline: {}", e);
+                    continue;
+                }
+            };
+
+            let color = self.color_set(renderer, display.color);
+            let item = DrawItem::new(pipeline, mesh, deformation, mtx, color);
+            match style {
+                OverlayStyle::XRay => self.x_ray_items.push(item),
+                OverlayStyle::Lines { .. } => self.wireframe_items.push(item),
+            }
+        }
+    }
+
+    pub fn upload(&self, frame: &mut Frame) -> Result<(), trekanten::RenderError> {
+        for (color, set) in self.colors.iter().zip(self.color_sets.iter()) {
+            let slice = frame.write_transient(&uniform::UnlitUniformData { color: *color })?;
+            frame.bind_transient(set, 0, slice);
+        }
+        Ok(())
+    }
+
+    /// Expects the view data to be bound in the shader resource group
+    pub fn record(
+        &self,
+        cmd_buf: &mut RenderPassEncoder,
+        shader_resource_group: &Handle<DescriptorSet>,
+    ) {
+        record_items(cmd_buf, shader_resource_group, &self.wireframe_items);
+        record_items(cmd_buf, shader_resource_group, &self.x_ray_items);
+    }
+}
+
+pub(crate) fn build_ui<'a>(world: &mut World, ui: &UiFrame<'a>, pos: [f32; 2]) -> [f32; 2] {
+    let size = [300.0, 150.0];
+
+    imgui::Window::new(imgui::im_str!("Review"))
+        .position(pos, imgui::Condition::FirstUseEver)
+        .size(size, imgui::Condition::FirstUseEver)
+        .collapsed(true, imgui::Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let selected: Vec<Entity> = world
+                .try_fetch::<crate::editor::Selection>()
+                .map(|selection| selection.entities().to_vec())
+                .unwrap_or_default();
+            let mut displays = world.write_storage::<ReviewDisplay>();
+            let n_overrides = (&displays).join().count();
+            ui.inner().text(imgui::im_str!(
+                "{} overrides, {} selected",
+                n_overrides,
+                selected.len()
+            ));
+            if ui.inner().button(imgui::im_str!("Clear all"), [0.0, 0.0]) {
+                displays.clear();
+            }
+
+            let selected: Vec<Entity> = selected
+                .into_iter()
+                .filter(|ent| world.is_alive(*ent))
+                .collect();
+            if selected.is_empty() {
+                return;
+            }
+
+            let modes = [
+                (imgui::im_str!("Solid"), ReviewMode::Solid),
+                (imgui::im_str!("Wireframe"), ReviewMode::Wireframe),
+                (imgui::im_str!("X-ray"), ReviewMode::XRay),
+            ];
+            for (label, mode) in modes.iter() {
+                if ui.inner().button(label, [0.0, 0.0]) {
+                    for &ent in selected.iter() {
+                        let display = displays.get(ent).copied().unwrap_or_default();
+                        displays
+                            .insert(
+                                ent,
+                                ReviewDisplay {
+                                    mode: *mode,
+                                    ..display
+                                },
+                            )
+                            .expect("Selected entities are alive");
+                    }
+                }
+                ui.inner().same_line(0.0);
+            }
+            if ui.inner().button(imgui::im_str!("Clear"), [0.0, 0.0]) {
+                for &ent in selected.iter() {
+                    displays.remove(ent);
+                }
+            }
+
+            // Edits the first selected override and applies it to the others
+            let first = match selected.iter().find_map(|&ent| displays.get(ent).copied()) {
+                Some(display) => display,
+                None => return,
+            };
+            let mut edited = first;
+            edited.color.inspect_mut(ui, "color");
+            edited.line_width.inspect_mut(ui, "line width");
+            if edited != first {
+                for &ent in selected.iter() {
+                    if let Some(display) = displays.get_mut(ent) {
+                        display.color = edited.color;
+                        display.line_width = edited.line_width;
+                    }
+                }
+            }
+        });
+
+    size
+}
@@ -0,0 +1,250 @@
+//! Describes the interface of the built-in shaders, i.e. the defines that select permutations and
+//! the uniform blocks, textures and push constants each pipeline expects, as reflected from the
+//! compiled shaders. Meant for users writing their own materials.
+
+use std::io::Write;
+
+use thiserror::Error;
+use trekanten::pipeline::{reflect_interface, BlockMember, ShaderInterface, SpirvError};
+use trekanten::raw_vk;
+
+use super::pipeline::{
+    pbr_gltf, CompilerError, Defines, ShaderCompiler, ShaderType, OBJECT_BUFFER,
+};
+
+#[derive(Debug, Error)]
+pub enum ShaderInterfaceError {
+    #[error("GLSL compiler error: {0}")]
+    GlslCompiler(#[from] CompilerError),
+    #[error("Failed to reflect shader: {0}")]
+    Reflection(#[from] SpirvError),
+    #[error("Failed to write: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+// Exactly one is set to 1, the others to 0
+const EFFECT_DEFINES: &[(&str, &str)] = &[
+    (
+        "CHROMATIC_ABERRATION",
+        "The color channels are split towards the edges",
+    ),
+    ("VIGNETTE", "The image is darkened towards the corners"),
+    ("FILM_GRAIN", "Noise that changes every 1/60 s is added"),
+    ("SHARPEN", "An unsharp mask is applied"),
+];
+
+struct BuiltinPipeline {
+    name: &'static str,
+    // Path and interface of each stage
+    stages: Vec<(&'static str, ShaderInterface)>,
+}
+
+fn reflect(
+    compiler: &ShaderCompiler,
+    defines: &Defines,
+    path: &'static str,
+    ty: ShaderType,
+) -> Result<(&'static str, ShaderInterface), ShaderInterfaceError> {
+    let spv = compiler.compile(defines, path, ty)?;
+    Ok((path, reflect_interface(&spv.data())?))
+}
+
+fn builtin_pipelines(
+    compiler: &ShaderCompiler,
+) -> Result<Vec<BuiltinPipeline>, ShaderInterfaceError> {
+    use ShaderType::{Compute, Fragment, Vertex};
+
+    // Every optional input enabled, to show all bindings
+    let pbr = {
+        let def = pbr_gltf::ShaderDefinition {
+            has_skin: true,
+            num_morph_targets: 1,
+            has_tex_coords: true,
+            has_vertex_colors: true,
+            has_tangents: true,
+            has_base_color_texture: true,
+            has_metallic_roughness_texture: true,
+            has_normal_map: true,
+            has_emissive_texture: true,
+            has_occlusion_texture: true,
+            sparse_base_color_texture: false,
+            object_buffer: true,
+            bindless_textures: false,
+        };
+        let (vert, frag) = pbr_gltf::compile(compiler, &def)?;
+        BuiltinPipeline {
+            name: "PBR (all defines enabled)",
+            stages: vec![
+                ("pbr/vert.glsl", reflect_interface(&vert.data())?),
+                ("pbr/frag.glsl", reflect_interface(&frag.data())?),
+            ],
+        }
+    };
+
+    let none = Defines::empty();
+    let mut object_buffer = Defines::empty();
+    object_buffer.push((String::from(OBJECT_BUFFER), String::from("1")));
+    let mut bloom = Defines::empty();
+    bloom.push((String::from("HAS_BLOOM"), String::from("1")));
+    let mut vignette = Defines::empty();
+    for (name, _) in EFFECT_DEFINES {
+        let value = if *name == "VIGNETTE" { "1" } else { "0" };
+        vignette.push((String::from(*name), String::from(value)));
+    }
+
+    Ok(vec![
+        pbr,
+        BuiltinPipeline {
+            name: "Unlit",
+            stages: vec![
+                reflect(compiler, &none, "pos_only_vert.glsl", Vertex)?,
+                reflect(compiler, &none, "uniform_color_frag.glsl", Fragment)?,
+            ],
+        },
+        BuiltinPipeline {
+            name: "Shadow",
+            stages: vec![reflect(
+                compiler,
+                &object_buffer,
+                "pos_only_vert.glsl",
+                Vertex,
+            )?],
+        },
+        BuiltinPipeline {
+            name: "Deformation",
+            stages: vec![reflect(compiler, &none, "deformation_comp.glsl", Compute)?],
+        },
+        BuiltinPipeline {
+            name: "UI",
+            stages: vec![
+                reflect(compiler, &none, "imgui/vert.glsl", Vertex)?,
+                reflect(compiler, &none, "imgui/frag.glsl", Fragment)?,
+            ],
+        },
+        BuiltinPipeline {
+            name: "Bloom bright pass",
+            stages: vec![
+                reflect(compiler, &none, "post/fullscreen_vert.glsl", Vertex)?,
+                reflect(compiler, &none, "post/bright_pass_frag.glsl", Fragment)?,
+            ],
+        },
+        BuiltinPipeline {
+            name: "Bloom blur",
+            stages: vec![
+                reflect(compiler, &none, "post/fullscreen_vert.glsl", Vertex)?,
+                reflect(compiler, &none, "post/blur_frag.glsl", Fragment)?,
+            ],
+        },
+        BuiltinPipeline {
+            name: "Post effect (one of the effect defines = 1)",
+            stages: vec![
+                reflect(compiler, &none, "post/fullscreen_vert.glsl", Vertex)?,
+                reflect(compiler, &vignette, "post/effect_frag.glsl", Fragment)?,
+            ],
+        },
+        BuiltinPipeline {
+            name: "Volumetric scattering",
+            stages: vec![
+                reflect(compiler, &none, "post/fullscreen_vert.glsl", Vertex)?,
+                reflect(compiler, &none, "post/volumetric_frag.glsl", Fragment)?,
+            ],
+        },
+        BuiltinPipeline {
+            name: "Composite (HAS_BLOOM = 1)",
+            stages: vec![
+                reflect(compiler, &none, "post/fullscreen_vert.glsl", Vertex)?,
+                reflect(compiler, &bloom, "post/composite_frag.glsl", Fragment)?,
+            ],
+        },
+    ])
+}
+
+fn descriptor_type_name(ty: raw_vk::DescriptorType) -> String {
+    match ty {
+        raw_vk::DescriptorType::UNIFORM_BUFFER => String::from("uniform buffer"),
+        raw_vk::DescriptorType::COMBINED_IMAGE_SAMPLER => String::from("sampler"),
+        ty => format!("{:?}", ty),
+    }
+}
+
+fn write_members(w: &mut dyn Write, members: &[BlockMember]) -> std::io::Result<()> {
+    for m in members {
+        writeln!(
+            w,
+            "      {:<24} offset {:>4}, size {:>4}",
+            m.name, m.offset, m.size
+        )?;
+    }
+    Ok(())
+}
+
+fn write_stage(w: &mut dyn Write, path: &str, interface: &ShaderInterface) -> std::io::Result<()> {
+    writeln!(w, "  {:?} shader {}", interface.stage, path)?;
+    for b in &interface.bindings {
+        let array = if b.count > 1 {
+            format!("[{}]", b.count)
+        } else {
+            String::new()
+        };
+        writeln!(
+            w,
+            "    set {}, binding {}: {} {}{} ({})",
+            b.set,
+            b.binding,
+            b.type_name,
+            b.name,
+            array,
+            descriptor_type_name(b.descriptor_type)
+        )?;
+        write_members(w, &b.members)?;
+    }
+    for pc in &interface.push_constants {
+        writeln!(
+            w,
+            "    push constant {}: offset {}, size {}",
+            pc.name, pc.offset, pc.size
+        )?;
+        write_members(w, &pc.members)?;
+    }
+    Ok(())
+}
+
+/// Compiles all built-in shaders and writes their interface to `w`
+pub fn write(w: &mut dyn Write) -> Result<(), ShaderInterfaceError> {
+    let compiler = ShaderCompiler::new()?;
+
+    writeln!(w, "PBR shader defines (pbr/vert.glsl, pbr/frag.glsl):")?;
+    for (name, description) in pbr_gltf::DEFINES {
+        writeln!(w, "  {:<32} {}", name, description)?;
+    }
+    writeln!(w)?;
+    writeln!(w, "Composite shader defines (post/composite_frag.glsl):")?;
+    writeln!(
+        w,
+        "  {:<32} {}",
+        "HAS_BLOOM", "The bloom texture is added to the scene"
+    )?;
+
+    writeln!(w)?;
+    writeln!(w, "Post effect shader defines (post/effect_frag.glsl):")?;
+    for (name, description) in EFFECT_DEFINES {
+        writeln!(w, "  {:<32} {}", name, description)?;
+    }
+
+    for p in builtin_pipelines(&compiler)? {
+        writeln!(w)?;
+        writeln!(w, "Pipeline: {}", p.name)?;
+        for (path, interface) in &p.stages {
+            write_stage(w, path, interface)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the shader interface to stdout
+pub fn print() -> Result<(), ShaderInterfaceError> {
+    let stdout = std::io::stdout();
+    let mut lock = stdout.lock();
+    write(&mut lock)
+}
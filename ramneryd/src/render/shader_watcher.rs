@@ -0,0 +1,198 @@
+use std::path::{Path, PathBuf};
+
+use crate::ecs::prelude::*;
+use crate::file_watcher::{PollTimer, WatchedFiles};
+
+use super::pipeline;
+use super::{MaterialPipelineCache, ReloadMaterial, RenderableMaterial};
+
+/// The renderables that are compiled from a shader file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Affected {
+    PBR,
+    Unlit,
+    /// Not used by any material, e.g. post processing or ui shaders
+    None,
+}
+
+fn affected(rel_path: &Path) -> Affected {
+    if rel_path.starts_with("pbr") {
+        Affected::PBR
+    } else if rel_path == Path::new("pos_only_vert.glsl")
+        || rel_path == Path::new("uniform_color_frag.glsl")
+    {
+        Affected::Unlit
+    } else {
+        Affected::None
+    }
+}
+
+fn find_glsl(dir: &Path, out: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("Failed to read shader dir {}: {}", dir.display(), e);
+            return;
+        }
+    };
+
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.is_dir() {
+            find_glsl(&path, out);
+        } else if path.extension().map(|ext| ext == "glsl").unwrap_or(false) {
+            out.push(path);
+        }
+    }
+}
+
+/// The glsl files below a directory
+#[derive(Debug)]
+struct ShaderFiles {
+    dir: PathBuf,
+    files: WatchedFiles<()>,
+}
+
+impl ShaderFiles {
+    fn new(dir: PathBuf) -> Self {
+        let mut paths = Vec::new();
+        find_glsl(&dir, &mut paths);
+        let mut files = WatchedFiles::default();
+        for path in paths {
+            files.watch(&path, || ());
+        }
+        Self { dir, files }
+    }
+
+    /// Returns the files, relative to the directory, that were added or modified since the last poll
+    fn poll(&mut self) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        find_glsl(&self.dir, &mut paths);
+
+        let mut changed = Vec::new();
+        for path in paths {
+            if !self.files.contains(&path) {
+                self.files.watch(&path, || ());
+                changed.push(path);
+            }
+        }
+        changed.extend(self.files.poll().into_iter().map(|(path, _)| path));
+        // Removed files are not reloaded
+        changed.retain(|path| path.exists());
+
+        changed
+            .into_iter()
+            .map(|path| match path.strip_prefix(&self.dir) {
+                Ok(rel) => rel.to_path_buf(),
+                Err(_) => path,
+            })
+            .collect()
+    }
+}
+
+/// Polls the shader directory and reloads the materials of the renderables that use a shader that
+/// changed
+pub struct ShaderWatcher {
+    timer: PollTimer,
+    files: ShaderFiles,
+}
+
+impl ShaderWatcher {
+    pub const ID: &'static str = "ShaderWatcher";
+}
+
+impl Default for ShaderWatcher {
+    fn default() -> Self {
+        Self {
+            timer: PollTimer::default(),
+            files: ShaderFiles::new(pipeline::shader_dir()),
+        }
+    }
+}
+
+#[derive(SystemData)]
+pub struct ShaderWatcherData<'a> {
+    entities: Entities<'a>,
+    renderables: ReadStorage<'a, RenderableMaterial>,
+    reload: WriteStorage<'a, ReloadMaterial>,
+    pipeline_cache: Write<'a, MaterialPipelineCache>,
+}
+
+impl<'a> System<'a> for ShaderWatcher {
+    type SystemData = ShaderWatcherData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        if !self.timer.ready() {
+            return;
+        }
+
+        let Self::SystemData {
+            entities,
+            renderables,
+            mut reload,
+            mut pipeline_cache,
+        } = data;
+
+        for rel_path in self.files.poll() {
+            let affected = affected(&rel_path);
+            if affected == Affected::None {
+                log::info!(
+                    "Shader {} changed on disk but is not used by any material, restart to apply it",
+                    rel_path.display()
+                );
+                continue;
+            }
+
+            pipeline_cache.clear();
+            log::info!(
+                "Shader {} changed on disk, reloading {:?} materials",
+                rel_path.display(),
+                affected
+            );
+            for (ent, renderable) in (&entities, &renderables).join() {
+                let matches = match renderable {
+                    RenderableMaterial::PBR { .. } => affected == Affected::PBR,
+                    RenderableMaterial::Unlit { .. } => affected == Affected::Unlit,
+                };
+                if matches {
+                    reload
+                        .insert(ent, ReloadMaterial)
+                        .expect("Joined entities are alive");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shaders_map_to_materials() {
+        assert_eq!(affected(Path::new("pbr/frag.glsl")), Affected::PBR);
+        assert_eq!(
+            affected(Path::new("uniform_color_frag.glsl")),
+            Affected::Unlit
+        );
+        assert_eq!(affected(Path::new("post/blur_frag.glsl")), Affected::None);
+    }
+
+    #[test]
+    fn modified_shaders_are_reported() {
+        let dir = std::env::temp_dir().join("ramneryd_shader_watcher_test");
+        std::fs::create_dir_all(dir.join("pbr")).unwrap();
+        let path = dir.join("pbr").join("frag.glsl");
+        std::fs::write(&path, b"").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"").unwrap();
+
+        let mut files = ShaderFiles::new(dir.clone());
+        assert!(files.poll().is_empty());
+
+        // Pretend the file was written after the last poll
+        files.files.touch(&path);
+        assert_eq!(files.poll(), vec![Path::new("pbr").join("frag.glsl")]);
+        assert!(files.poll().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
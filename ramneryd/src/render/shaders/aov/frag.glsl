@@ -0,0 +1,23 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec3 world_normal;
+layout(location = 1) in float depth;
+layout(location = 2) flat in float entity;
+
+// Linear depth, id of the entity + 1 and the octahedral encoding of the world normal. Zero where
+// nothing was drawn.
+layout(location = 0) out vec4 aov;
+
+vec2 octahedral(vec3 n) {
+    n /= abs(n.x) + abs(n.y) + abs(n.z);
+    if (n.z < 0.0) {
+        vec2 signs = vec2(n.x >= 0.0 ? 1.0 : -1.0, n.y >= 0.0 ? 1.0 : -1.0);
+        return (1.0 - abs(n.yx)) * signs;
+    }
+    return n.xy;
+}
+
+void main() {
+    aov = vec4(depth, entity, octahedral(normalize(world_normal)));
+}
@@ -0,0 +1,32 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform ViewData {
+    mat4 view_proj;
+    vec4 view_pos;
+} view_data;
+
+layout(push_constant) uniform Object {
+    mat4 model;
+    // The linear depth is dot(depth_plane.xyz, world_pos) + depth_plane.w
+    vec4 depth_plane;
+    // .x is the id of the entity + 1
+    vec4 entity;
+} object;
+
+// The locations are the same as for pbr/vert.glsl. The vertices of skinned and morphed meshes
+// were already deformed, see render::deformation.
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+
+layout(location = 0) out vec3 out_world_normal;
+layout(location = 1) out float out_depth;
+layout(location = 2) flat out float out_entity;
+
+void main() {
+    vec4 world_pos = object.model * vec4(position, 1.0);
+    out_world_normal = transpose(inverse(mat3(object.model))) * normal;
+    out_depth = dot(object.depth_plane.xyz, world_pos.xyz) + object.depth_plane.w;
+    out_entity = object.entity.x;
+    gl_Position = view_data.view_proj * world_pos;
+}
@@ -0,0 +1,105 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Writes the morphed and skinned vertices of a mesh into a buffer with the same vertex format,
+// that is drawn instead of the mesh, see render::deformation. The attributes that are not deformed
+// are copied as they are. Tangents are skinned but not morphed, as the tangent deltas of the morph
+// targets are not loaded, see asset::gltf.
+
+#define MAX_NUM_JOINTS (128)
+#define MAX_NUM_MORPH_TARGETS (4)
+
+layout(local_size_x = 64) in;
+
+layout(set = 0, binding = 0) uniform JointMatrices {
+    mat4 matrices[MAX_NUM_JOINTS];
+} joint_matrices;
+
+layout(set = 0, binding = 1) uniform MorphWeights {
+    vec4 weights;
+} morph_weights;
+
+// The whole vertex buffer, the mesh starts at first_vertex
+layout(std430, set = 0, binding = 2) readonly buffer Source {
+    float data[];
+} src;
+
+layout(std430, set = 0, binding = 3) writeonly buffer Destination {
+    float data[];
+} dst;
+
+// See deformation::Params
+layout(push_constant) uniform Params {
+    uint first_vertex;
+    uint num_vertices;
+    uint stride; // In floats
+    uint num_morph_targets;
+    uint has_skin;
+    uint has_tangents; // The last four floats of each vertex
+} params;
+
+// The attribute order of the meshes is: position, normal, joints, weights, morph target position
+// deltas, morph target normal deltas, uv, color, tangent, see asset::gltf.
+#define POS_OFFSET (0)
+#define NOR_OFFSET (3)
+#define JOINTS_OFFSET (6)
+#define WEIGHTS_OFFSET (10)
+
+vec3 read_vec3(uint base) {
+    return vec3(src.data[base], src.data[base + 1], src.data[base + 2]);
+}
+
+vec4 read_vec4(uint base) {
+    return vec4(src.data[base], src.data[base + 1], src.data[base + 2], src.data[base + 3]);
+}
+
+void write_vec3(uint base, vec3 v) {
+    dst.data[base] = v.x;
+    dst.data[base + 1] = v.y;
+    dst.data[base + 2] = v.z;
+}
+
+void main() {
+    uint vertex = gl_GlobalInvocationID.x;
+    if (vertex >= params.num_vertices) {
+        return;
+    }
+
+    uint src_base = (params.first_vertex + vertex) * params.stride;
+    uint dst_base = vertex * params.stride;
+    for (uint i = 0; i < params.stride; ++i) {
+        dst.data[dst_base + i] = src.data[src_base + i];
+    }
+
+    vec3 pos = read_vec3(src_base + POS_OFFSET);
+    vec3 normal = read_vec3(src_base + NOR_OFFSET);
+
+    // All position deltas, followed by all normal deltas
+    uint morph_pos = params.has_skin != 0 ? WEIGHTS_OFFSET + 4 : JOINTS_OFFSET;
+    uint morph_nor = morph_pos + 3 * params.num_morph_targets;
+    for (uint i = 0; i < min(params.num_morph_targets, MAX_NUM_MORPH_TARGETS); ++i) {
+        pos += morph_weights.weights[i] * read_vec3(src_base + morph_pos + 3 * i);
+        normal += morph_weights.weights[i] * read_vec3(src_base + morph_nor + 3 * i);
+    }
+
+    if (params.has_skin != 0) {
+        // Joint indices are stored as floats
+        vec4 joints = read_vec4(src_base + JOINTS_OFFSET);
+        vec4 weights = read_vec4(src_base + WEIGHTS_OFFSET);
+        mat4 skin = weights.x * joint_matrices.matrices[int(joints.x)]
+            + weights.y * joint_matrices.matrices[int(joints.y)]
+            + weights.z * joint_matrices.matrices[int(joints.z)]
+            + weights.w * joint_matrices.matrices[int(joints.w)];
+        pos = (skin * vec4(pos, 1.0)).xyz;
+        normal = mat3(skin) * normal;
+
+        if (params.has_tangents != 0) {
+            uint tan_offset = params.stride - 4;
+            vec3 tangent = mat3(skin) * read_vec3(src_base + tan_offset);
+            write_vec3(dst_base + tan_offset, tangent);
+        }
+    }
+
+    write_vec3(dst_base + POS_OFFSET, pos);
+    write_vec3(dst_base + NOR_OFFSET, normal);
+}
@@ -6,7 +6,19 @@ layout(set = 0, binding = 0) uniform sampler2D u_texture;
 layout(location = 0) in vec4 f_color;
 layout(location = 1) in vec2 f_uv;
 
+#if DEPTH_TEXTURE
+// Perspective depth is close to 1 for most of the range, raise it to a power to make the
+// differences visible.
+const float DEPTH_CONTRAST = 32.0;
+#endif
+
 void main()
 {
+#if DEPTH_TEXTURE
+    float depth = texture(u_texture, f_uv).r;
+    float gray = pow(depth, DEPTH_CONTRAST);
+    color = f_color * vec4(gray, gray, gray, 1.0);
+#else
     color = f_color * texture(u_texture, f_uv);
-}
\ No newline at end of file
+#endif
+}
@@ -0,0 +1,58 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Writes the deformed vertices of a mesh into the vertices of the path tracer, in world space, see
+// render::path_trace. The vertices are read from the buffer that the deformation pre-pass wrote
+// this frame, which has the same vertex format as the mesh.
+
+layout(local_size_x = 64) in;
+
+// The deformed vertices of the entity, see deformation::GpuDeformation
+layout(std430, set = 0, binding = 0) readonly buffer Source {
+    float data[];
+} src;
+
+// See path_trace::TraceVertex
+struct TraceVertex {
+    vec4 position;
+    vec4 normal;
+    vec4 uv;
+};
+
+layout(std430, set = 0, binding = 1) buffer Vertices {
+    TraceVertex vertices[];
+} dst;
+
+// See path_trace::PoseParams
+layout(push_constant) uniform Params {
+    mat4 model;
+    uint num_vertices;
+    uint stride; // In floats
+    uint dst_first; // Where the vertices of the mesh start in dst
+    uint has_normals;
+} params;
+
+// The meshes start with the position and the normal, see deformation_comp.glsl
+#define POS_OFFSET (0)
+#define NOR_OFFSET (3)
+
+vec3 read_vec3(uint base) {
+    return vec3(src.data[base], src.data[base + 1], src.data[base + 2]);
+}
+
+void main() {
+    uint vertex = gl_GlobalInvocationID.x;
+    if (vertex >= params.num_vertices) {
+        return;
+    }
+
+    uint src_base = vertex * params.stride;
+    uint dst_idx = params.dst_first + vertex;
+    dst.vertices[dst_idx].position = params.model * vec4(read_vec3(src_base + POS_OFFSET), 1.0);
+    // A zero normal is replaced by the face normal when the triangle is hit
+    if (params.has_normals != 0) {
+        mat3 normal_matrix = transpose(inverse(mat3(params.model)));
+        vec3 normal = normal_matrix * read_vec3(src_base + NOR_OFFSET);
+        dst.vertices[dst_idx].normal = vec4(normalize(normal), 0.0);
+    }
+}
@@ -0,0 +1,70 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Computes the boxes of one depth of the BVH of the path tracer again, after the deformed vertices
+// have moved, see render::path_trace. The depths are refit from the deepest one up, as the boxes of
+// inner nodes are the union of the boxes of their children.
+
+layout(local_size_x = 64) in;
+
+// See path_trace::TraceNode. Inner nodes have a count of 0 and their children at first and
+// first + 1, leaves the triangles first..first + count.
+struct Node {
+    vec3 min;
+    uint first;
+    vec3 max;
+    uint count;
+};
+
+layout(std430, set = 0, binding = 0) buffer Nodes {
+    Node nodes[];
+};
+
+// .xyz are the vertices and .w the material
+layout(std430, set = 0, binding = 1) readonly buffer Triangles {
+    uvec4 triangles[];
+};
+
+// See path_trace::TraceVertex
+struct TraceVertex {
+    vec4 position;
+    vec4 normal;
+    vec4 uv;
+};
+
+layout(std430, set = 0, binding = 2) readonly buffer Vertices {
+    TraceVertex vertices[];
+};
+
+// The nodes of the depth, see spatial::Bvh::flatten
+layout(push_constant) uniform Params {
+    uint first_node;
+    uint num_nodes;
+} params;
+
+void main() {
+    if (gl_GlobalInvocationID.x >= params.num_nodes) {
+        return;
+    }
+
+    uint idx = params.first_node + gl_GlobalInvocationID.x;
+    Node node = nodes[idx];
+    vec3 lo = vec3(1e30);
+    vec3 hi = vec3(-1e30);
+    if (node.count == 0) {
+        for (uint i = 0; i < 2; ++i) {
+            lo = min(lo, nodes[node.first + i].min);
+            hi = max(hi, nodes[node.first + i].max);
+        }
+    } else {
+        for (uint t = node.first; t < node.first + node.count; ++t) {
+            for (uint i = 0; i < 3; ++i) {
+                vec3 p = vertices[triangles[t][i]].position.xyz;
+                lo = min(lo, p);
+                hi = max(hi, p);
+            }
+        }
+    }
+    nodes[idx].min = lo;
+    nodes[idx].max = hi;
+}
@@ -0,0 +1,461 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#if BINDLESS_TEXTURES
+#extension GL_EXT_nonuniform_qualifier : require
+#endif
+
+// Traces one path from the camera through each pixel of a range and adds it to the sum of the
+// pixel, see render::path_trace. The average of the samples so far is written to the image. The
+// BRDF is the same as in the PBR shader. After the lights are sampled at a hit, the path continues
+// in either the specular or the diffuse lobe, picked by how much each reflects.
+
+layout(local_size_x = 64) in;
+
+#define M_PI (3.1415926535897932384626433832795)
+// How far from a surface the rays that leave it start
+#define EPS (0.001)
+#define INF (1e30)
+#define NO_TEXTURE (0xFFFFFFFFu)
+
+#define MAX_NUM_LIGHTS (16)
+#define LIGHT_KIND_DIRECTIONAL (0)
+#define LIGHT_KIND_POINT (1)
+#define LIGHT_KIND_SPOT (2)
+// See uniform::PackedLight
+struct PackedLight {
+    vec4 pos_range; // .w is the range
+    uvec4 dir_color; // halves: dir.xy, dir.z + volumetric density, color.rg, color.b
+    uvec4 cone_shadow_kind; // .x is cos(outer), cos(inner) as unorm16, .y the shadow index, .z the kind
+};
+
+// See uniform::PathTraceData
+layout(set = 0, binding = 0) uniform PathTraceData {
+    mat4 inv_view_proj;
+    vec4 eye;
+    PackedLight lights[MAX_NUM_LIGHTS];
+    vec4 ambient;
+    uvec4 extent_bounces; // .xy is the extent of the image, .z the bounces and .w the number of lights
+    uvec4 scene; // .x is the number of triangles, nothing is hit if it is 0
+} data;
+
+// See path_trace::TraceNode. Inner nodes have a count of 0 and their children at first and
+// first + 1, leaves the triangles first..first + count. The root is the first node.
+struct Node {
+    vec3 min;
+    uint first;
+    vec3 max;
+    uint count;
+};
+
+layout(std430, set = 0, binding = 1) readonly buffer Nodes {
+    Node nodes[];
+};
+
+// .xyz are the vertices and .w the material
+layout(std430, set = 0, binding = 2) readonly buffer Triangles {
+    uvec4 triangles[];
+};
+
+// See path_trace::TraceVertex. A zero normal means that the face normal is used.
+struct TraceVertex {
+    vec4 position;
+    vec4 normal;
+    vec4 uv;
+};
+
+layout(std430, set = 0, binding = 3) readonly buffer Vertices {
+    TraceVertex vertices[];
+};
+
+// See path_trace::TraceMaterial
+struct Material {
+    vec4 base_color_factor;
+    vec4 emissive_factor;
+    vec4 metallic_roughness; // .x is the metallic and .y the roughness factor
+    uvec4 texture_indices; // In the bindless array: base color, metallic roughness and emissive
+};
+
+layout(std430, set = 0, binding = 4) readonly buffer Materials {
+    Material materials[];
+};
+
+// The sum of the samples of each pixel, row by row from the top
+layout(std430, set = 0, binding = 5) buffer Sums {
+    vec4 sums[];
+};
+
+layout(set = 0, binding = 6, rgba16f) uniform writeonly image2D image;
+
+#if BINDLESS_TEXTURES
+layout(set = 1, binding = 0) uniform sampler2D bindless_textures[BINDLESS_TEXTURE_COUNT];
+#endif
+
+// See path_trace::TraceParams. Every pixel of the range has been sampled `pass` times before.
+layout(push_constant) uniform Params {
+    uint first_pixel;
+    uint num_pixels;
+    uint pass;
+} params;
+
+vec3 light_direction(PackedLight l) {
+    return vec3(unpackHalf2x16(l.dir_color.x), unpackHalf2x16(l.dir_color.y).x);
+}
+
+vec3 light_color(PackedLight l) {
+    return vec3(unpackHalf2x16(l.dir_color.z), unpackHalf2x16(l.dir_color.w).x);
+}
+
+// Same as in the PBR shader
+float cone_attenuation(PackedLight l, vec3 light_vec) {
+    vec2 cos_outer_inner = unpackUnorm2x16(l.cone_shadow_kind.x);
+    float scale = 1.0 / max(cos_outer_inner.y - cos_outer_inner.x, 0.001);
+    float offset = -cos_outer_inner.x * scale;
+    float cos_angle = dot(normalize(light_vec), normalize(-light_direction(l)));
+    float attenuation = clamp(cos_angle * scale + offset, 0.0, 1.0);
+    return attenuation * attenuation;
+}
+
+// Same as in the PBR shader
+float distance_attenuation(vec3 light_vec, float light_range) {
+    float dist_sqr = dot(light_vec, light_vec);
+    float range_sqr = pow(light_range, 2.0);
+    float attenuation = 1.0 / max(dist_sqr, pow(0.01, 2.0));
+    float smooth_factor = pow(clamp(1.0 - pow(dist_sqr / range_sqr, 2.0), 0.0, 1.0), 2.0);
+
+    return attenuation * smooth_factor;
+}
+
+// Xorshift, seeded with a hash so that neighbouring pixels and passes are not correlated
+uint rng_state;
+
+void seed(uint pixel, uint pass) {
+    // Wang hash
+    uint s = (pixel * 9781u) ^ (pass * 6271u);
+    s = (s ^ 61u) ^ (s >> 16);
+    s *= 9u;
+    s ^= s >> 4;
+    s *= 0x27d4eb2du;
+    s ^= s >> 15;
+    rng_state = max(s, 1u);
+}
+
+// In [0, 1)
+float rand() {
+    rng_state ^= rng_state << 13;
+    rng_state ^= rng_state >> 17;
+    rng_state ^= rng_state << 5;
+    return float(rng_state >> 8) / float(1u << 24);
+}
+
+struct Ray {
+    vec3 origin;
+    vec3 direction;
+};
+
+// If the ray passes through the box before max_t
+bool hits_box(Ray ray, vec3 inv_dir, Node node, float max_t) {
+    vec3 t0 = (node.min - ray.origin) * inv_dir;
+    vec3 t1 = (node.max - ray.origin) * inv_dir;
+    vec3 t_min = min(t0, t1);
+    vec3 t_max = max(t0, t1);
+    float enter = max(max(t_min.x, t_min.y), max(t_min.z, 0.0));
+    float exit = min(min(t_max.x, t_max.y), t_max.z);
+    return enter <= exit && enter < max_t;
+}
+
+// The distance along the ray and the barycentric coordinates of the second and third corner, see
+// Möller & Trumbore, "Fast, Minimum Storage Ray/Triangle Intersection". t is INF if there is no hit.
+vec3 intersect(Ray ray, uvec4 tri) {
+    vec3 p0 = vertices[tri.x].position.xyz;
+    vec3 e1 = vertices[tri.y].position.xyz - p0;
+    vec3 e2 = vertices[tri.z].position.xyz - p0;
+    vec3 p = cross(ray.direction, e2);
+    float det = dot(e1, p);
+    if (abs(det) < 1e-8) {
+        return vec3(INF, 0.0, 0.0);
+    }
+
+    float inv_det = 1.0 / det;
+    vec3 s = ray.origin - p0;
+    float u = dot(s, p) * inv_det;
+    if (u < 0.0 || u > 1.0) {
+        return vec3(INF, 0.0, 0.0);
+    }
+    vec3 q = cross(s, e1);
+    float v = dot(ray.direction, q) * inv_det;
+    if (v < 0.0 || u + v > 1.0) {
+        return vec3(INF, 0.0, 0.0);
+    }
+    float t = dot(e2, q) * inv_det;
+    return vec3(t > 0.0 ? t : INF, u, v);
+}
+
+#define STACK_SIZE (64)
+
+// The closest triangle that the ray hits before max_t, or any triangle if any_hit is set, e.g. for
+// shadow rays. Returns the triangle, or -1 for no hit, and the distance and barycentrics in hit.
+int trace(Ray ray, float max_t, bool any_hit, out vec3 hit) {
+    hit = vec3(max_t, 0.0, 0.0);
+    if (data.scene.x == 0) {
+        return -1;
+    }
+
+    // Axis aligned directions would give NaNs at the box planes
+    bvec3 aligned = lessThan(abs(ray.direction), vec3(1e-8));
+    vec3 inv_dir = 1.0 / mix(ray.direction, vec3(1e-8), aligned);
+    int closest = -1;
+    uint stack[STACK_SIZE];
+    uint top = 0;
+    stack[top++] = 0;
+    while (top > 0) {
+        Node node = nodes[stack[--top]];
+        if (!hits_box(ray, inv_dir, node, hit.x)) {
+            continue;
+        }
+        if (node.count == 0) {
+            // The BVH is balanced, so the stack only overflows for absurd scenes
+            if (top + 2 <= STACK_SIZE) {
+                stack[top++] = node.first;
+                stack[top++] = node.first + 1;
+            }
+            continue;
+        }
+        for (uint t = node.first; t < node.first + node.count; ++t) {
+            vec3 h = intersect(ray, triangles[t]);
+            if (h.x < hit.x) {
+                hit = h;
+                closest = int(t);
+                if (any_hit) {
+                    return closest;
+                }
+            }
+        }
+    }
+    return closest;
+}
+
+#if BINDLESS_TEXTURES
+vec4 sample_texture(uint idx, vec2 uv, vec4 fallback) {
+    if (idx == NO_TEXTURE) {
+        return fallback;
+    }
+    return textureLod(bindless_textures[nonuniformEXT(idx)], uv, 0.0);
+}
+#else
+vec4 sample_texture(uint idx, vec2 uv, vec4 fallback) {
+    return fallback;
+}
+#endif
+
+// The inputs of the BRDF, computed from the factors and textures like in the PBR shader
+struct Surface {
+    vec3 diffuse_color;
+    vec3 fresnel_0;
+    float alpha_roughness;
+    vec3 emissive;
+};
+
+Surface surface(Material material, vec2 uv) {
+    vec4 base_color = material.base_color_factor
+        * sample_texture(material.texture_indices.x, uv, vec4(1.0));
+    // Roughness is in the green and metallic in the blue channel
+    vec4 metallic_roughness = sample_texture(material.texture_indices.y, uv, vec4(1.0));
+    float metallic = material.metallic_roughness.x * metallic_roughness.b;
+    float roughness = material.metallic_roughness.y * metallic_roughness.g;
+    vec3 emissive = material.emissive_factor.rgb
+        * sample_texture(material.texture_indices.z, uv, vec4(1.0)).rgb;
+
+    float dielectric_specular = 0.04;
+    Surface s;
+    s.diffuse_color = mix(base_color.rgb * (1.0 - dielectric_specular), vec3(0.0), metallic);
+    s.fresnel_0 = mix(vec3(dielectric_specular), base_color.rgb, metallic);
+    // Perfect mirrors can't be sampled
+    s.alpha_roughness = max(roughness * roughness, 0.001);
+    s.emissive = emissive;
+    return s;
+}
+
+// Schlick, like in the PBR shader
+vec3 fresnel(vec3 fresnel_0, float cos_angle) {
+    return fresnel_0 + (vec3(1.0) - fresnel_0) * pow(max(1.0 - cos_angle, 0.0), 5.0);
+}
+
+// GGX / Trowbridge-Reitz, like in the PBR shader
+float normal_distribution(float n_dot_h, float alpha_roughness) {
+    if (n_dot_h <= 0.0) {
+        return 0.0;
+    }
+    float a2 = alpha_roughness * alpha_roughness;
+    return a2 / (M_PI * pow(1.0 + n_dot_h * n_dot_h * (a2 - 1.0), 2.0));
+}
+
+// The height-correlated Smith masking-shadowing term, divided by 4 * n_dot_l * n_dot_v
+float visibility(float n_dot_l, float n_dot_v, float alpha_roughness) {
+    float a2 = alpha_roughness * alpha_roughness;
+    float divisor_0 = n_dot_v * sqrt(a2 + n_dot_l * (n_dot_l - a2 * n_dot_l));
+    float divisor_1 = n_dot_l * sqrt(a2 + n_dot_v * (n_dot_v - a2 * n_dot_v));
+    return 0.5 / max(divisor_0 + divisor_1, 1e-7);
+}
+
+// The light from a punctual light that is reflected towards v, the same as for each light in the
+// PBR shader
+vec3 shade(Surface s, vec3 n, vec3 v, vec3 l, vec3 light) {
+    float n_dot_l = clamp(dot(n, l), 0.0, 1.0);
+    if (n_dot_l <= 0.0) {
+        return vec3(0.0);
+    }
+
+    vec3 h = normalize(v + l);
+    float n_dot_v = clamp(dot(n, v), 0.0, 1.0);
+    vec3 f = fresnel(s.fresnel_0, clamp(dot(h, l), 0.0, 1.0));
+    vec3 diffuse = (vec3(1.0) - f) * s.diffuse_color / M_PI;
+    vec3 specular = f * visibility(n_dot_l, n_dot_v, s.alpha_roughness)
+        * normal_distribution(dot(n, h), s.alpha_roughness);
+    return (diffuse + specular) * n_dot_l * light * M_PI;
+}
+
+// The direction in world space, for a direction around n where z is along n
+vec3 to_world(vec3 local, vec3 n) {
+    vec3 up = abs(n.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+    vec3 t = normalize(cross(up, n));
+    vec3 b = cross(n, t);
+    return t * local.x + b * local.y + n * local.z;
+}
+
+// The light that arrives along the ray
+vec3 trace_path(Ray ray) {
+    vec3 radiance = vec3(0.0);
+    vec3 throughput = vec3(1.0);
+    uint bounces = data.extent_bounces.z;
+    for (uint bounce = 0; bounce <= bounces; ++bounce) {
+        vec3 hit;
+        int tri_idx = trace(ray, INF, false, hit);
+        if (tri_idx < 0) {
+            radiance += throughput * data.ambient.rgb;
+            break;
+        }
+
+        uvec4 tri = triangles[tri_idx];
+        TraceVertex v0 = vertices[tri.x];
+        TraceVertex v1 = vertices[tri.y];
+        TraceVertex v2 = vertices[tri.z];
+        vec3 bary = vec3(1.0 - hit.y - hit.z, hit.y, hit.z);
+        vec2 uv = v0.uv.xy * bary.x + v1.uv.xy * bary.y + v2.uv.xy * bary.z;
+        Surface s = surface(materials[tri.w], uv);
+
+        vec3 p = ray.origin + ray.direction * hit.x;
+        vec3 v = -ray.direction;
+        vec3 n = v0.normal.xyz * bary.x + v1.normal.xyz * bary.y + v2.normal.xyz * bary.z;
+        if (dot(n, n) < 1e-12) {
+            n = cross(v1.position.xyz - v0.position.xyz, v2.position.xyz - v0.position.xyz);
+        }
+        n = normalize(n);
+        if (dot(n, v) < 0.0) {
+            n = -n;
+        }
+        vec3 origin = p + n * EPS;
+
+        radiance += throughput * s.emissive;
+        for (uint i = 0; i < min(data.extent_bounces.w, MAX_NUM_LIGHTS); ++i) {
+            PackedLight light = data.lights[i];
+            vec3 l;
+            float light_distance;
+            vec3 color = light_color(light);
+            if (light.cone_shadow_kind.z == LIGHT_KIND_DIRECTIONAL) {
+                l = normalize(-light_direction(light));
+                light_distance = INF;
+            } else {
+                vec3 light_vec = light.pos_range.xyz - p;
+                light_distance = length(light_vec);
+                l = light_vec / max(light_distance, 1e-7);
+                float attenuation = distance_attenuation(light_vec, light.pos_range.w);
+                if (light.cone_shadow_kind.z == LIGHT_KIND_SPOT) {
+                    attenuation *= cone_attenuation(light, light_vec);
+                }
+                if (attenuation <= 0.0) {
+                    continue;
+                }
+                color *= attenuation;
+            }
+            if (dot(n, l) <= 0.0) {
+                continue;
+            }
+
+            vec3 shadow_hit;
+            if (trace(Ray(origin, l), light_distance, true, shadow_hit) < 0) {
+                radiance += throughput * shade(s, n, v, l, color);
+            }
+        }
+
+        if (bounce == bounces) {
+            break;
+        }
+
+        float n_dot_v = max(dot(n, v), EPS);
+        vec3 f = fresnel(s.fresnel_0, n_dot_v);
+        float specular_weight = (f.r + f.g + f.b) / 3.0;
+        vec3 diffuse = (vec3(1.0) - f) * s.diffuse_color;
+        float diffuse_weight = (diffuse.r + diffuse.g + diffuse.b) / 3.0;
+        if (specular_weight + diffuse_weight <= 0.0) {
+            break;
+        }
+        float p_specular = clamp(specular_weight / (specular_weight + diffuse_weight), 0.1, 0.9);
+
+        float u1 = rand();
+        float u2 = rand();
+        vec3 direction;
+        if (rand() < p_specular) {
+            // Samples the half vector by D(h) * n_dot_h, which the specular term divided by the
+            // pdf of the reflected direction cancels with
+            float a2 = s.alpha_roughness * s.alpha_roughness;
+            float cos_theta = sqrt((1.0 - u1) / (1.0 + (a2 - 1.0) * u1));
+            float sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+            float phi = 2.0 * M_PI * u2;
+            vec3 h = to_world(vec3(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta), n);
+            vec3 l = h * 2.0 * dot(v, h) - v;
+            float n_dot_l = dot(n, l);
+            if (n_dot_l <= 0.0) {
+                break;
+            }
+            float v_dot_h = max(dot(v, h), EPS);
+            vec3 weight = fresnel(s.fresnel_0, v_dot_h)
+                * visibility(n_dot_l, n_dot_v, s.alpha_roughness) * 4.0 * v_dot_h * n_dot_l
+                / max(dot(n, h), EPS);
+            throughput *= weight / p_specular;
+            direction = l;
+        } else {
+            // Cosine weighted, which the lambertian term divided by the pdf cancels with
+            float r = sqrt(u1);
+            float phi = 2.0 * M_PI * u2;
+            throughput *= diffuse / (1.0 - p_specular);
+            direction = to_world(vec3(r * cos(phi), r * sin(phi), sqrt(max(1.0 - u1, 0.0))), n);
+        }
+
+        ray = Ray(origin, direction);
+    }
+
+    return radiance;
+}
+
+void main() {
+    if (gl_GlobalInvocationID.x >= params.num_pixels) {
+        return;
+    }
+
+    uint pixel = params.first_pixel + gl_GlobalInvocationID.x;
+    uvec2 extent = data.extent_bounces.xy;
+    uvec2 xy = uvec2(pixel % extent.x, pixel / extent.x);
+    seed(pixel, params.pass);
+
+    // Same as math::ndc_from_pixel and math::unproject of the near plane
+    vec2 jittered = vec2(xy) + vec2(rand(), rand());
+    vec2 ndc = 2.0 * jittered / vec2(extent) - 1.0;
+    vec4 near_point = data.inv_view_proj * vec4(ndc, 0.0, 1.0);
+    Ray ray = Ray(data.eye.xyz, normalize(near_point.xyz / near_point.w - data.eye.xyz));
+
+    vec3 sample_radiance = trace_path(ray);
+    vec3 sum = params.pass == 0 ? sample_radiance : sums[pixel].rgb + sample_radiance;
+    sums[pixel] = vec4(sum, 1.0);
+    vec3 average = min(sum / float(params.pass + 1), vec3(65504.0));
+    imageStore(image, ivec2(xy), vec4(average, 1.0));
+}
@@ -1,5 +1,8 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
+#if BASE_COLOR_TEXTURE_SPARSE
+#extension GL_ARB_sparse_texture2 : require
+#endif
 
 // Implementation is largely based on Real-time Rendering, 4th edition
 
@@ -11,17 +14,45 @@ layout(set = 0, binding = 0) uniform ViewData {
 } view_data;
 
 #define MAX_NUM_LIGHTS (16)
+#define LIGHT_KIND_DIRECTIONAL (0)
+#define LIGHT_KIND_POINT (1)
+#define LIGHT_KIND_SPOT (2)
+// See uniform::PackedLight
 struct PackedLight {
-    vec4 pos;
-    vec4 dir_cutoff;
-    vec4 color_range; // .w is the range
-    uvec4 shadow_idx; // x is the index
+    vec4 pos_range; // .w is the range
+    uvec4 dir_color; // halves: dir.xy, dir.z + volumetric density, color.rg, color.b
+    uvec4 cone_shadow_kind; // .x is cos(outer), cos(inner) as unorm16, .y the shadow index, .z the kind
 };
 
+vec3 light_direction(PackedLight l) {
+    return vec3(unpackHalf2x16(l.dir_color.x), unpackHalf2x16(l.dir_color.y).x);
+}
+
+vec3 light_color(PackedLight l) {
+    return vec3(unpackHalf2x16(l.dir_color.z), unpackHalf2x16(l.dir_color.w).x);
+}
+
+float light_volumetric_density(PackedLight l) {
+    return unpackHalf2x16(l.dir_color.y).y;
+}
+
+// Cone attenuation, as recommended by KHR_lights_punctual. It is 1.0 inside the inner angle and
+// falls off to 0.0 at the outer angle. See uniform::cone_scale_offset.
+float cone_attenuation(PackedLight l, vec3 light_vec) {
+    vec2 cos_outer_inner = unpackUnorm2x16(l.cone_shadow_kind.x);
+    float scale = 1.0 / max(cos_outer_inner.y - cos_outer_inner.x, 0.001);
+    float offset = -cos_outer_inner.x * scale;
+    float cos_angle = dot(normalize(light_vec), normalize(-light_direction(l)));
+    float attenuation = clamp(cos_angle * scale + offset, 0.0, 1.0);
+    return attenuation * attenuation;
+}
+
 layout(set = 0, binding = 1) uniform LightingData {
     PackedLight lights[MAX_NUM_LIGHTS];
     vec4 ambient; // vec3 color + float strength
     uint num_lights; // The number of lights in the array
+    vec4 shadow_mask_min; // .xyz is the corner of the baked shadow mask, .w the voxel size
+    uvec4 shadow_mask_dims; // The voxels along each axis, .w is 0 if there is no mask
 } lighting_data;
 
 uint num_lights() {
@@ -38,6 +69,15 @@ struct Light {
 #define NUM_SPOTLIGHT_SHADOW_MAPS (16)
 layout(set = 0, binding = 2) uniform sampler2D spotlight_shadow_maps[NUM_SPOTLIGHT_SHADOW_MAPS];
 
+// See render::shadow_bake. The xy-slices of the volume are side by side along x.
+#define BAKED_SHADOW_BIT (0x80000000u)
+layout(set = 0, binding = 5) uniform sampler2D shadow_mask;
+
+// The opaque part of the scene for transmissive materials, sharp and blurred, see
+// post::PostProcessing::transmission
+layout(set = 0, binding = 6) uniform sampler2D transmission_sharp;
+layout(set = 0, binding = 7) uniform sampler2D transmission_blurred;
+
 // This is based on the recommended impl for KHR_punctual_lights:
 // https://github.com/KhronosGroup/glTF/blob/master/extensions/2.0/Khronos/KHR_lights_punctual/README.md#range-property
 // It does not contain the square for the smooth factor though.
@@ -56,42 +96,89 @@ float distance_attenuation(vec3 light_vec, float light_range) {
     return attenuation * smooth_factor;
 }
 
-float remap(float x, float old_low, float old_high, float new_low, float new_high) {
-    return ((x - old_low) / (old_high - old_low)) * (new_high - new_low) + new_low;
-}
-
 Light unpack_light(PackedLight l, vec3 world_pos) {
     Light r;
-    r.color = l.color_range.xyz;
-    r.shadow_idx = l.shadow_idx.x;
-    if (l.pos.w == 0.0) {
-        // Directional
-        r.direction = normalize(-l.dir_cutoff.xyz);
+    r.color = light_color(l);
+    r.shadow_idx = l.cone_shadow_kind.y;
+    uint kind = l.cone_shadow_kind.z;
+    if (kind == LIGHT_KIND_DIRECTIONAL) {
+        r.direction = normalize(-light_direction(l));
         r.attenuation = 1.0;
-    } else if (l.dir_cutoff.w == 0.0) {
-        // Point
-        vec3 direction_unnormalized = l.pos.xyz - world_pos;
-        r.direction = normalize(direction_unnormalized);
-        r.attenuation = distance_attenuation(direction_unnormalized, l.color_range.w);
     } else {
-        // Spot
-        vec3 direction_unnormalized = l.pos.xyz - world_pos;
+        vec3 direction_unnormalized = l.pos_range.xyz - world_pos;
         r.direction = normalize(direction_unnormalized);
-        r.attenuation = 0.0;
-        vec3 spot_dir = normalize(-l.dir_cutoff.xyz);
-        float cos_angle = dot(r.direction, spot_dir);
-        if (cos_angle > l.dir_cutoff.w) {
-            r.attenuation = distance_attenuation(direction_unnormalized, l.color_range.w);
-            // Cone attenuation
-            float cos_angle_norm = remap(cos_angle, l.dir_cutoff.w, 1.0, 0.0, 1.0); 
-            // This is a function that has a steep incline at first and then goes to 1.0
-            // Making this depend on cutoff means that the wider the code, the earlier the cutoff starts
-            r.attenuation *= smoothstep(l.dir_cutoff.w, 1.0, cos_angle_norm + l.dir_cutoff.w);
+        r.attenuation = distance_attenuation(direction_unnormalized, l.pos_range.w);
+        if (kind == LIGHT_KIND_SPOT) {
+            r.attenuation *= cone_attenuation(l, direction_unnormalized);
         }
     }
     return r;
 }
 
+#define MAX_NUM_FOG_VOLUMES (8)
+struct PackedFogVolume {
+    mat4 world_to_local; // Maps the volume to the unit cube or the unit sphere
+    vec4 color_density;
+    uvec4 shape; // x is 0 for boxes and 1 for spheres
+};
+
+layout(set = 0, binding = 4) uniform FogData {
+    PackedFogVolume volumes[MAX_NUM_FOG_VOLUMES];
+    uint num_volumes;
+} fog_data;
+
+// The interval, as fractions of the segment from origin to origin + dir, that is inside the cube
+// [-1, 1]^3. The interval is empty if x > y.
+vec2 unit_cube_interval(vec3 origin, vec3 dir) {
+    vec3 inv_dir = 1.0 / dir;
+    vec3 t0 = (vec3(-1.0) - origin) * inv_dir;
+    vec3 t1 = (vec3(1.0) - origin) * inv_dir;
+    vec3 t_min = min(t0, t1);
+    vec3 t_max = max(t0, t1);
+    return vec2(max(max(t_min.x, t_min.y), t_min.z), min(min(t_max.x, t_max.y), t_max.z));
+}
+
+// Same as above, for the unit sphere
+vec2 unit_sphere_interval(vec3 origin, vec3 dir) {
+    float a = dot(dir, dir);
+    float b = dot(origin, dir);
+    float c = dot(origin, origin) - 1.0;
+    float discriminant = b * b - a * c;
+    if (discriminant < 0.0) {
+        return vec2(1.0, 0.0);
+    }
+    float s = sqrt(discriminant);
+    return vec2((-b - s) / a, (-b + s) / a);
+}
+
+// Blends in the fog of all volumes that the view ray passes through before reaching world_pos
+vec3 apply_fog(vec3 color, vec3 world_pos) {
+    vec3 view_pos = view_data.view_pos.xyz;
+    float ray_length = length(world_pos - view_pos);
+    float optical_depth = 0.0;
+    vec3 fog_color = vec3(0.0);
+    uint n = min(fog_data.num_volumes, MAX_NUM_FOG_VOLUMES);
+    for (uint i = 0; i < n; ++i) {
+        PackedFogVolume v = fog_data.volumes[i];
+        // The fractions along the ray are the same in local space as the transform is affine
+        vec3 origin = (v.world_to_local * vec4(view_pos, 1.0)).xyz;
+        vec3 dir = (v.world_to_local * vec4(world_pos - view_pos, 0.0)).xyz;
+        vec2 interval = v.shape.x == 0 ? unit_cube_interval(origin, dir) : unit_sphere_interval(origin, dir);
+        interval = clamp(interval, 0.0, 1.0);
+        float depth = max(interval.y - interval.x, 0.0) * ray_length * v.color_density.w;
+        optical_depth += depth;
+        fog_color += v.color_density.rgb * depth;
+    }
+
+    if (optical_depth <= 0.0) {
+        return color;
+    }
+
+    // Overlapping volumes are weighted by how much each contributes
+    float transmittance = exp(-optical_depth);
+    return mix(fog_color / optical_depth, color, transmittance);
+}
+
 layout(location = 0) in VsOut {
     vec3 world_normal;
     vec3 world_pos;
@@ -126,6 +213,29 @@ float sample_shadow_map(uint idx, float n_dot_l) {
     return (coords.z - bias) < depth ? 1.0 : 0.0;
 }
 
+// The visibility of a baked light. Sampled one voxel out along the normal, so that the voxels
+// inside the surface are not filtered in.
+float sample_shadow_mask(uint channel, vec3 world_pos, vec3 normal) {
+    if (lighting_data.shadow_mask_dims.w == 0u) {
+        return 1.0;
+    }
+
+    vec3 dims = vec3(lighting_data.shadow_mask_dims.xyz);
+    float voxel_size = lighting_data.shadow_mask_min.w;
+    vec3 pos = world_pos + normal * voxel_size;
+    // Voxel centers are at whole coordinates. Clamped so that the filtering stays within a slice.
+    vec3 voxel = (pos - lighting_data.shadow_mask_min.xyz) / voxel_size - 0.5;
+    voxel = clamp(voxel, vec3(0.0), dims - 1.0);
+    float z0 = floor(voxel.z);
+    float z1 = min(z0 + 1.0, dims.z - 1.0);
+    vec2 atlas_size = vec2(dims.x * dims.z, dims.y);
+    vec2 uv0 = (vec2(voxel.x + z0 * dims.x, voxel.y) + 0.5) / atlas_size;
+    vec2 uv1 = (vec2(voxel.x + z1 * dims.x, voxel.y) + 0.5) / atlas_size;
+    vec4 mask = mix(textureLod(shadow_mask, uv0, 0.0), textureLod(shadow_mask, uv1, 0.0),
+                    voxel.z - z0);
+    return mask[channel];
+}
+
 layout(location = 0) out vec4 out_color;
 
 // PBR uniforms
@@ -140,9 +250,26 @@ layout(set = 1, binding = 0) uniform PBRMaterialData {
     float roughness_factor;
     // For the normal map, 1.0 if there is no map
     float normal_scale;
-    float _padding;
+    // How much the occlusion texture darkens the ambient light, 0.0 is none
+    float occlusion_strength;
+    // .xyz is the emitted light, multiplied with the emissive texture if there is one
+    vec4 emissive_factor;
+    // .x is how much of the light passes through the surface, .y the index of refraction
+    vec4 transmission_ior;
+    // Where the textures are in the bindless array: [0] is base color, metallic roughness, normal
+    // and emissive, [1].x occlusion
+    uvec4 texture_indices[2];
 } material_data;
 
+#if BINDLESS_TEXTURES
+layout(set = 4, binding = 0) uniform sampler2D bindless_textures[BINDLESS_TEXTURE_COUNT];
+#define base_color_texture bindless_textures[material_data.texture_indices[0].x]
+#define metallic_roughness_texture bindless_textures[material_data.texture_indices[0].y]
+#define normal_map bindless_textures[material_data.texture_indices[0].z]
+#define emissive_texture bindless_textures[material_data.texture_indices[0].w]
+#define occlusion_texture bindless_textures[material_data.texture_indices[1].x]
+#else
+
 #if HAS_BASE_COLOR_TEXTURE
 layout(set = 1, binding = 1) uniform sampler2D base_color_texture;
 #endif
@@ -155,6 +282,34 @@ layout(set = 1, binding = 2) uniform sampler2D metallic_roughness_texture;
 layout(set = 1, binding = 3) uniform sampler2D normal_map;
 #endif
 
+#if HAS_EMISSIVE_TEXTURE
+layout(set = 1, binding = 4) uniform sampler2D emissive_texture;
+#endif
+
+#if HAS_OCCLUSION_TEXTURE
+layout(set = 1, binding = 5) uniform sampler2D occlusion_texture;
+#endif
+
+#endif
+
+#if HAS_BASE_COLOR_TEXTURE
+#if BASE_COLOR_TEXTURE_SPARSE
+// Only some pages of the texture are resident, so fall back to coarser levels until a resident one
+// is found. The mip tail is always resident.
+vec4 sample_sparse(sampler2D tex, vec2 uv) {
+    int levels = textureQueryLevels(tex);
+    float lod = textureQueryLod(tex, uv).y;
+    vec4 texel = vec4(1.0);
+    for (int level = int(floor(lod)); level < levels; ++level) {
+        if (sparseTexelsResidentARB(sparseTextureLodARB(tex, uv, float(level), texel))) {
+            return texel;
+        }
+    }
+    return texel;
+}
+#endif
+#endif
+
 // Schlick approx. cos_angle is the angle between the normal and the light.
 vec3 fresnel(vec3 fresnel_0, float cos_angle) {
     return fresnel_0 + (vec3(1.0) - fresnel_0) * pow(1.0 - cos_angle, 5.0);
@@ -205,8 +360,12 @@ void main() {
     vec3 black = vec3(0.0);
 
 #if HAS_BASE_COLOR_TEXTURE
+#if BASE_COLOR_TEXTURE_SPARSE
+    base_color *= sample_sparse(base_color_texture, vs_out.tex_coords_0).rgb;
+#else
     base_color *= texture(base_color_texture, vs_out.tex_coords_0).rgb;
 #endif
+#endif
 
 #if HAS_METALLIC_ROUGHNESS_TEXTURE
     vec4 mr_tex = texture(metallic_roughness_texture, vs_out.tex_coords_0);
@@ -244,9 +403,16 @@ void main() {
     /* ----------------- SHADING ------------------ */
 
     vec3 color = vec3(0);
-
-    // Ambient
-    color += lighting_data.ambient.xyz * diffuse_color * lighting_data.ambient.w;
+    // Only the specular part of the lights, as transmission replaces the diffuse part
+    vec3 specular = vec3(0);
+
+    // Ambient, the occlusion is only in .r and only applies to the indirect light
+    float occlusion = 1.0;
+#if HAS_OCCLUSION_TEXTURE
+    float ao = texture(occlusion_texture, vs_out.tex_coords_0).r;
+    occlusion = mix(1.0, ao, material_data.occlusion_strength);
+#endif
+    color += lighting_data.ambient.xyz * diffuse_color * lighting_data.ambient.w * occlusion;
 
     // Lights
     for (uint i = 0; i < num_lights(); ++i) {
@@ -263,8 +429,12 @@ void main() {
         float h_dot_l = clamp(dot(bisect_light_view, light_dir), 0.0, 1.0);
 
         float shadow_factor = 1.0;
-        if (light.shadow_idx != 0xFFFFFFFF)
-            shadow_factor = sample_shadow_map(light.shadow_idx, n_dot_l);
+        if (light.shadow_idx != 0xFFFFFFFF) {
+            vec3 geometric_normal = normalize(vs_out.world_normal);
+            shadow_factor = (light.shadow_idx & BAKED_SHADOW_BIT) != 0u
+                ? sample_shadow_mask(light.shadow_idx & 3u, vs_out.world_pos, geometric_normal)
+                : sample_shadow_map(light.shadow_idx, n_dot_l);
+        }
 
         if (shadow_factor == 0.0)
             continue;
@@ -299,11 +469,40 @@ void main() {
         // If the light is modeled as rays, the distance between the points where the light
         // rays hit the surface decreases (=> light intensity increases) as the light vector
         // approaches the normal.
-        color += (f_diffuse + f_specular) * n_dot_l * light_color * attenuation * shadow_factor;
+        vec3 light_factor = n_dot_l * light_color * attenuation * shadow_factor;
+        color += (f_diffuse + f_specular) * light_factor;
+        specular += f_specular * light_factor;
     }
     // Punctual lights means the integral in the reflectance equation simplifies down to PI,
     // see real-time rendering 4, p. 316 eq. 9.14
     color *= M_PI;
+    specular *= M_PI;
+
+    // Thin glass: the scene behind is sampled along the refracted view ray, a fixed distance into
+    // the surface, and is blurred more the rougher the surface is. What is not reflected passes
+    // through, tinted by the base color.
+    float transmission = material_data.transmission_ior.x;
+    if (transmission > 0.0) {
+        float ior = max(material_data.transmission_ior.y, 1.0);
+        vec3 refracted = refract(-view_dir, normal, 1.0 / ior);
+        vec3 behind = vs_out.world_pos + refracted * 0.1;
+        vec4 clip = view_data.view_proj * vec4(behind, 1.0);
+        vec2 uv = clamp(clip.xy / clip.w * 0.5 + 0.5, vec2(0.0), vec2(1.0));
+        vec3 sharp = textureLod(transmission_sharp, uv, 0.0).rgb;
+        vec3 blurred = textureLod(transmission_blurred, uv, 0.0).rgb;
+        vec3 background = mix(sharp, blurred, clamp(roughness * 2.0, 0.0, 1.0));
+        vec3 transmitted = background * base_color * (1.0 - fresnel(fresnel_0, n_dot_v)) * (1.0 - metallic);
+        color = mix(color, specular + transmitted, transmission);
+    }
+
+    // Emitted light is not affected by the lights, so it is added last
+    vec3 emissive = material_data.emissive_factor.xyz;
+#if HAS_EMISSIVE_TEXTURE
+    emissive *= texture(emissive_texture, vs_out.tex_coords_0).rgb;
+#endif
+    color += emissive;
+
+    color = apply_fog(color, vs_out.world_pos);
 
     out_color = vec4(color, 1.0);
 }
@@ -17,13 +17,33 @@ uint num_shadow_matrices() {
     return min(MAX_NUM_LIGHTS, shadow_matrices.num_matrices);
 }
 
+#if OBJECT_BUFFER
+struct Model {
+    mat4 model;
+    mat4 model_it; // inverse transpose of model matrix
+};
+
+layout(std430, set = 3, binding = 0) readonly buffer Objects {
+    Model objects[];
+} object_data;
+
+layout(push_constant) uniform ObjectIndex {
+    uint idx;
+} object_index;
+
+#define model_tfm (object_data.objects[object_index.idx])
+#else
 layout(push_constant) uniform Model {
     mat4 model;
     mat4 model_it; // inverse transpose of model matrix
 } model_tfm;
+#endif
 
+// Skinned and morphed meshes are drawn with the vertices that the deformation pre-pass wrote, see
+// deformation_comp.glsl, so their joints, weights and morph targets are not read here
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec3 normal;
+
 #if HAS_TEX_COORDS
 layout(location = TEX_COORDS_LOC) in vec2 tex_coords;
 #endif
@@ -6,13 +6,37 @@ layout(set = 0, binding = 0) uniform ViewData {
     vec4 view_pos;
 } view_data;
 
+#if OBJECT_BUFFER
+struct Model {
+    mat4 model;
+    mat4 model_it;
+};
+
+layout(std430, set = 3, binding = 0) readonly buffer Objects {
+    Model objects[];
+} object_data;
+
+layout(push_constant) uniform ObjectIndex {
+    uint idx;
+} object_index;
+
+#define model_tfm (object_data.objects[object_index.idx])
+#else
 layout(push_constant) uniform Model {
     mat4 model;
     mat4 model_it;
 } model_tfm;
+#endif
 
 layout(location = 0) in vec3 position;
+#if HAS_VERTEX_COLOR
+layout(location = 1) in vec4 color;
+layout(location = 0) out vec4 vertex_color;
+#endif
 
 void main() {
     gl_Position = view_data.view_proj * model_tfm.model * vec4(position, 1.0);
+#if HAS_VERTEX_COLOR
+    vertex_color = color;
+#endif
 }
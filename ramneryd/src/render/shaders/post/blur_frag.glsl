@@ -0,0 +1,26 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform sampler2D source;
+
+// One texel in the direction of the blur
+layout(push_constant) uniform Blur {
+    vec2 direction;
+} params;
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 out_color;
+
+// Gaussian, applied once horizontally and once vertically
+const float weights[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+void main() {
+    vec3 result = texture(source, uv).rgb * weights[0];
+    for (int i = 1; i < 5; ++i) {
+        vec2 offset = params.direction * float(i);
+        result += texture(source, uv + offset).rgb * weights[i];
+        result += texture(source, uv - offset).rgb * weights[i];
+    }
+    out_color = vec4(result, 1.0);
+}
@@ -0,0 +1,20 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform sampler2D scene_color;
+
+layout(push_constant) uniform BrightPass {
+    float threshold;
+} params;
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    vec3 color = texture(scene_color, uv).rgb;
+    float brightness = max(color.r, max(color.g, color.b));
+    // Keep the hue but only the part of the brightness above the threshold
+    float contribution = max(brightness - params.threshold, 0.0) / max(brightness, 0.0001);
+    out_color = vec4(color * contribution, 1.0);
+}
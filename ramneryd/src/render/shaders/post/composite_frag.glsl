@@ -0,0 +1,24 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform sampler2D scene_color;
+
+#if HAS_BLOOM
+layout(set = 0, binding = 1) uniform sampler2D bloom;
+
+layout(push_constant) uniform Composite {
+    float intensity;
+} params;
+#endif
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    vec3 color = texture(scene_color, uv).rgb;
+#if HAS_BLOOM
+    color += texture(bloom, uv).rgb * params.intensity;
+#endif
+    out_color = vec4(color, 1.0);
+}
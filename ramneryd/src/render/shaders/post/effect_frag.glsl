@@ -0,0 +1,56 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Exactly one of CHROMATIC_ABERRATION, VIGNETTE, FILM_GRAIN and SHARPEN is set to 1
+
+layout(set = 0, binding = 0) uniform sampler2D source;
+
+layout(push_constant) uniform Effect {
+    vec4 params; // The fields of the effect, see render::post_effects::PostEffect
+    vec4 texel_time; // .xy is the size of a texel and .z the time in seconds
+} effect;
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 out_color;
+
+float hash(vec2 p) {
+    return fract(sin(dot(p, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+void main() {
+#if CHROMATIC_ABERRATION
+    // Grows towards the edges
+    vec2 offset = (uv - 0.5) * effect.params.x;
+    vec3 color = vec3(
+        texture(source, uv + offset).r,
+        texture(source, uv).g,
+        texture(source, uv - offset).b
+    );
+#elif VIGNETTE
+    vec3 color = texture(source, uv).rgb;
+    // 1.0 in the corners
+    float dist = length(uv - 0.5) * sqrt(2.0);
+    float radius = effect.params.y;
+    float darkening = smoothstep(radius, radius + max(effect.params.z, 0.0001), dist);
+    color *= 1.0 - effect.params.x * darkening;
+#elif FILM_GRAIN
+    vec3 color = texture(source, uv).rgb;
+    // A new pattern every 1/60 s
+    vec2 seed = uv / effect.texel_time.xy + floor(effect.texel_time.z * 60.0);
+    float noise = hash(seed) - 0.5;
+    color *= 1.0 + noise * effect.params.x;
+#elif SHARPEN
+    vec2 texel = effect.texel_time.xy;
+    vec3 center = texture(source, uv).rgb;
+    vec3 neighbours = texture(source, uv + vec2(texel.x, 0.0)).rgb
+        + texture(source, uv - vec2(texel.x, 0.0)).rgb
+        + texture(source, uv + vec2(0.0, texel.y)).rgb
+        + texture(source, uv - vec2(0.0, texel.y)).rgb;
+    // Unsharp mask
+    vec3 color = max(center + (center * 4.0 - neighbours) * effect.params.x, vec3(0.0));
+#else
+    vec3 color = texture(source, uv).rgb;
+#endif
+    out_color = vec4(color, 1.0);
+}
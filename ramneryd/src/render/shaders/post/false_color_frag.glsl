@@ -0,0 +1,60 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Exposure analysis of the HDR scene color, see render::exposure
+
+layout(set = 0, binding = 0) uniform sampler2D scene_color;
+
+layout(push_constant) uniform Exposure {
+    vec4 params; // .x is 0 for false color and 1 for zebra, .y the zebra threshold
+} exposure;
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 out_color;
+
+const float MIDDLE_GRAY = 0.18;
+// Where 1.0, the brightest value a display shows, is
+const float CLIP_EV = log2(1.0 / MIDDLE_GRAY);
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+vec3 false_color(float ev, float gray) {
+    if (ev < -6.0) {
+        return vec3(0.5, 0.0, 0.6); // Crushed blacks
+    } else if (ev < -4.5) {
+        return vec3(0.0, 0.3, 1.0); // Just above black
+    } else if (ev < -0.5) {
+        return vec3(gray);
+    } else if (ev < 0.5) {
+        return vec3(0.2, 0.8, 0.2); // Middle gray
+    } else if (ev < 1.5) {
+        return vec3(1.0, 0.5, 0.7); // Skin tones, one stop over
+    } else if (ev < CLIP_EV - 0.5) {
+        return vec3(gray);
+    } else if (ev < CLIP_EV) {
+        return vec3(1.0, 1.0, 0.0); // Close to clipping
+    }
+    return vec3(1.0, 0.0, 0.0); // Clipped
+}
+
+void main() {
+    vec3 color = texture(scene_color, uv).rgb;
+    float lum = luminance(color);
+
+    if (exposure.params.x < 0.5) {
+        float ev = log2(max(lum, 1e-6) / MIDDLE_GRAY);
+        // The gray bands keep some of the image to show what is what
+        float gray = clamp(lum, 0.0, 1.0) * 0.6 + 0.2;
+        out_color = vec4(false_color(ev, gray), 1.0);
+    } else {
+        // Diagonal stripes, 8 pixels wide
+        bool stripe = mod(gl_FragCoord.x + gl_FragCoord.y, 16.0) < 8.0;
+        if (lum > exposure.params.y && stripe) {
+            color = vec3(0.0);
+        }
+        out_color = vec4(color, 1.0);
+    }
+}
@@ -0,0 +1,128 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform VolumetricData {
+    mat4 inv_view_proj; // Of the depth pass
+    vec4 view_pos;
+    uvec4 num_samples; // x is the number of steps along each ray
+} volumetric_data;
+
+#define MAX_NUM_LIGHTS (16)
+#define LIGHT_KIND_DIRECTIONAL (0)
+#define LIGHT_KIND_POINT (1)
+#define LIGHT_KIND_SPOT (2)
+// See uniform::PackedLight
+struct PackedLight {
+    vec4 pos_range; // .w is the range
+    uvec4 dir_color; // halves: dir.xy, dir.z + volumetric density, color.rg, color.b
+    uvec4 cone_shadow_kind; // .x is cos(outer), cos(inner) as unorm16, .y the shadow index, .z the kind
+};
+
+vec3 light_direction(PackedLight l) {
+    return vec3(unpackHalf2x16(l.dir_color.x), unpackHalf2x16(l.dir_color.y).x);
+}
+
+vec3 light_color(PackedLight l) {
+    return vec3(unpackHalf2x16(l.dir_color.z), unpackHalf2x16(l.dir_color.w).x);
+}
+
+float light_volumetric_density(PackedLight l) {
+    return unpackHalf2x16(l.dir_color.y).y;
+}
+
+// Cone attenuation, as recommended by KHR_lights_punctual. It is 1.0 inside the inner angle and
+// falls off to 0.0 at the outer angle. See uniform::cone_scale_offset.
+float cone_attenuation(PackedLight l, vec3 light_vec) {
+    vec2 cos_outer_inner = unpackUnorm2x16(l.cone_shadow_kind.x);
+    float scale = 1.0 / max(cos_outer_inner.y - cos_outer_inner.x, 0.001);
+    float offset = -cos_outer_inner.x * scale;
+    float cos_angle = dot(normalize(light_vec), normalize(-light_direction(l)));
+    float attenuation = clamp(cos_angle * scale + offset, 0.0, 1.0);
+    return attenuation * attenuation;
+}
+
+layout(set = 0, binding = 1) uniform LightingData {
+    PackedLight lights[MAX_NUM_LIGHTS];
+    vec4 ambient; // vec3 color + float strength
+    uint num_lights; // The number of lights in the array
+} lighting_data;
+
+#define NUM_SPOTLIGHT_SHADOW_MAPS (16)
+layout(set = 0, binding = 2) uniform sampler2D spotlight_shadow_maps[NUM_SPOTLIGHT_SHADOW_MAPS];
+
+layout(set = 0, binding = 3) uniform ShadowMatrices {
+    mat4 matrices[MAX_NUM_LIGHTS];
+    uint num_matrices;
+} shadow_matrices;
+
+layout(set = 1, binding = 0) uniform sampler2D scene_depth;
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 out_color;
+
+#define MAX_NUM_SAMPLES (128)
+
+// Same as in the PBR shader, see pbr/frag.glsl
+float distance_attenuation(vec3 light_vec, float light_range) {
+    float dist_sqr = dot(light_vec, light_vec);
+    float range_sqr = pow(light_range, 2.0);
+    float attenuation = 1.0 / max(dist_sqr, pow(0.01, 2.0));
+    float smooth_factor = pow(clamp(1.0 - pow(dist_sqr / range_sqr, 2.0), 0.0, 1.0), 2.0);
+
+    return attenuation * smooth_factor;
+}
+
+// 1.0 if the light reaches pos
+float visibility(uint shadow_idx, vec3 pos) {
+    vec4 clip = shadow_matrices.matrices[shadow_idx] * vec4(pos, 1.0);
+    vec3 coords = clip.xyz / clip.w;
+    vec2 shadow_uv = coords.xy * 0.5 + 0.5;
+    // Texture sample is done before 'if' to remain within uniform ctrl-flow
+    float depth = texture(spotlight_shadow_maps[shadow_idx], shadow_uv).r;
+    if (clip.w <= 0.0 || coords.z > 1.0) {
+        return 0.0;
+    }
+    return coords.z - 0.005 < depth ? 1.0 : 0.0;
+}
+
+// Interleaved gradient noise, to offset the steps of neighbouring pixels so that banding turns into
+// noise that the upsampling smooths out
+float step_offset() {
+    return fract(52.9829189 * fract(dot(gl_FragCoord.xy, vec2(0.06711056, 0.00583715))));
+}
+
+void main() {
+    float depth = texture(scene_depth, uv).r;
+    vec4 end = volumetric_data.inv_view_proj * vec4(uv * 2.0 - 1.0, depth, 1.0);
+    end /= end.w;
+
+    vec3 start = volumetric_data.view_pos.xyz;
+    vec3 ray = end.xyz - start;
+    uint num_samples = clamp(volumetric_data.num_samples.x, 1, MAX_NUM_SAMPLES);
+    float step_length = length(ray) / float(num_samples);
+    float offset = step_offset();
+
+    vec3 scattered = vec3(0.0);
+    uint num_lights = min(lighting_data.num_lights, MAX_NUM_LIGHTS);
+    for (uint i = 0; i < num_lights; ++i) {
+        PackedLight l = lighting_data.lights[i];
+        float density = light_volumetric_density(l);
+        uint shadow_idx = l.cone_shadow_kind.y;
+        // Only spot lights, which have shadow maps, can be volumetric
+        if (density <= 0.0 || l.cone_shadow_kind.z != LIGHT_KIND_SPOT || shadow_idx == 0xFFFFFFFF) {
+            continue;
+        }
+
+        float in_scattered = 0.0;
+        for (uint s = 0; s < num_samples; ++s) {
+            vec3 pos = start + ray * ((float(s) + offset) / float(num_samples));
+            vec3 light_vec = l.pos_range.xyz - pos;
+            float attenuation = distance_attenuation(light_vec, l.pos_range.w) * cone_attenuation(l, light_vec);
+            in_scattered += attenuation * visibility(shadow_idx, pos);
+        }
+        scattered += light_color(l) * in_scattered * density * step_length;
+    }
+
+    out_color = vec4(scattered, 1.0);
+}
@@ -0,0 +1,26 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform sampler2D base_color_texture;
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 tex_coords;
+layout(location = 3) flat in vec4 color;
+layout(location = 4) flat in vec3 to_light;
+
+layout(location = 0) out vec4 out_color;
+
+const float AMBIENT = 0.3;
+
+void main() {
+    vec3 n = normal;
+    // Meshes without normals get the normal of the triangle
+    if (dot(n, n) == 0.0) {
+        n = cross(dFdx(position), dFdy(position));
+    }
+    // The triangles are not culled, so both sides are lit
+    float diffuse = abs(dot(normalize(n), normalize(to_light)));
+    vec4 base_color = color * texture(base_color_texture, tex_coords);
+    out_color = vec4(base_color.rgb * (AMBIENT + (1.0 - AMBIENT) * diffuse), 1.0);
+}
@@ -0,0 +1,28 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// The preview meshes are already in the space of the preview, see render::thumbnail
+layout(push_constant) uniform Object {
+    mat4 view_proj;
+    vec4 color;
+    vec4 to_light;
+} object;
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 tex_coords;
+
+layout(location = 0) out vec3 out_position;
+layout(location = 1) out vec3 out_normal;
+layout(location = 2) out vec2 out_tex_coords;
+layout(location = 3) flat out vec4 out_color;
+layout(location = 4) flat out vec3 out_to_light;
+
+void main() {
+    out_position = position;
+    out_normal = normal;
+    out_tex_coords = tex_coords;
+    out_color = object.color;
+    out_to_light = object.to_light.xyz;
+    gl_Position = object.view_proj * vec4(position, 1.0);
+}
@@ -1,12 +1,20 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
 
+#if HAS_VERTEX_COLOR
+layout(location = 0) in vec4 vertex_color;
+#else
 layout(set = 1, binding = 0) uniform UnlitUniformData {
     vec4 color;
 } ubo;
+#endif
 
 layout(location = 0) out vec4 outColor;
 
 void main() {
+#if HAS_VERTEX_COLOR
+    outColor = vertex_color;
+#else
     outColor = ubo.color;
+#endif
 }
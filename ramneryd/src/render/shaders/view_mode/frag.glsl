@@ -0,0 +1,33 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec3 world_normal;
+layout(location = 1) in vec3 to_view;
+layout(location = 2) in float depth;
+layout(location = 3) flat in vec4 color;
+layout(location = 4) flat in vec4 params;
+
+layout(location = 0) out vec4 out_color;
+
+// The same values as render::view_mode::ViewMode
+const uint NORMALS = 2;
+const uint DEPTH = 3;
+
+void main() {
+    uint mode = uint(params.x);
+    if (mode == NORMALS) {
+        vec3 n = world_normal;
+        // Meshes without normals get the normal of the triangle, facing the camera. The world
+        // position is the same for all pixels up to the translation, so to_view works as well.
+        if (dot(n, n) == 0.0) {
+            n = cross(dFdx(to_view), dFdy(to_view));
+            n = dot(n, to_view) < 0.0 ? -n : n;
+        }
+        out_color = vec4(normalize(n) * 0.5 + 0.5, 1.0);
+    } else if (mode == DEPTH) {
+        out_color = vec4(vec3(clamp(depth / params.y, 0.0, 1.0)), 1.0);
+    } else {
+        // Unlit and wireframe
+        out_color = color;
+    }
+}
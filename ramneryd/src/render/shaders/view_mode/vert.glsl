@@ -0,0 +1,43 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform ViewData {
+    mat4 view_proj;
+    vec4 view_pos;
+} view_data;
+
+layout(push_constant) uniform Object {
+    mat4 model;
+    // The linear depth is dot(depth_plane.xyz, world_pos) + depth_plane.w
+    vec4 depth_plane;
+    vec4 color;
+    // .x is the view mode and .y the depth that is shown as white
+    vec4 params;
+} object;
+
+// The locations are the same as for pbr/vert.glsl. The vertices of skinned and morphed meshes
+// were already deformed, see render::deformation.
+layout(location = 0) in vec3 position;
+#if HAS_NORMALS
+layout(location = 1) in vec3 normal;
+#endif
+
+layout(location = 0) out vec3 out_world_normal;
+layout(location = 1) out vec3 out_to_view;
+layout(location = 2) out float out_depth;
+layout(location = 3) flat out vec4 out_color;
+layout(location = 4) flat out vec4 out_params;
+
+void main() {
+    vec4 world_pos = object.model * vec4(position, 1.0);
+#if HAS_NORMALS
+    out_world_normal = transpose(inverse(mat3(object.model))) * normal;
+#else
+    out_world_normal = vec3(0.0);
+#endif
+    out_to_view = view_data.view_pos.xyz - world_pos.xyz;
+    out_depth = dot(object.depth_plane.xyz, world_pos.xyz) + object.depth_plane.w;
+    out_color = object.color;
+    out_params = object.params;
+    gl_Position = view_data.view_proj * world_pos;
+}
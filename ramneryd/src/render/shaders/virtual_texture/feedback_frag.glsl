@@ -0,0 +1,33 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 1, binding = 0) uniform VirtualTextureData {
+    // .xy is the extent of the texture and .zw the extent of a page, in texels
+    vec4 extents;
+    // .x is the id of the texture, .y the number of mip levels, .z the first level of the mip tail
+    // and .w the bias of the mip level, as the feedback target is smaller than the scene.
+    vec4 params;
+} vt;
+
+layout(location = 0) in vec2 tex_coords;
+
+// The page that is sampled: x, y, level and id + 1, as integers. Zero where nothing was drawn.
+layout(location = 0) out vec4 feedback;
+
+void main() {
+    vec2 texels = tex_coords * vt.extents.xy;
+    vec2 dx = dFdx(texels);
+    vec2 dy = dFdy(texels);
+    float lod = 0.5 * log2(max(dot(dx, dx), dot(dy, dy))) + vt.params.w;
+    // The pages of the mip tail are always resident
+    float level = min(clamp(floor(lod), 0.0, vt.params.y - 1.0), vt.params.z);
+
+    // Repeated texture coordinates sample the same pages
+    vec2 level_extent = max(floor(vt.extents.xy / exp2(level)), vec2(1.0));
+    vec2 page = floor(fract(tex_coords) * level_extent / vt.extents.zw);
+    if (level == vt.params.z) {
+        page = vec2(0.0);
+    }
+
+    feedback = vec4(page, level, vt.params.x + 1.0) / 255.0;
+}
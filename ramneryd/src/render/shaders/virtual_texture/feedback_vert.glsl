@@ -0,0 +1,24 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform ViewData {
+    mat4 view_proj;
+    vec4 view_pos;
+} view_data;
+
+layout(push_constant) uniform Model {
+    mat4 model;
+    mat4 model_it;
+} model_tfm;
+
+// The locations are the same as for pbr/vert.glsl. The vertices of skinned and morphed meshes
+// were already deformed, see render::deformation.
+layout(location = 0) in vec3 position;
+layout(location = TEX_COORDS_LOC) in vec2 tex_coords;
+
+layout(location = 0) out vec2 out_tex_coords;
+
+void main() {
+    gl_Position = view_data.view_proj * model_tfm.model * vec4(position, 1.0);
+    out_tex_coords = tex_coords;
+}
@@ -0,0 +1,556 @@
+//! Shadows of static lights, baked into a volume that covers the static geometry. Each voxel stores
+//! the visibility of up to four lights, one per channel, found by raycasting from its center to the
+//! lights against the colliders of the static entities, see collision. The PBR shader samples the
+//! volume instead of a shadow map for the baked lights, so they don't take any of the shadow map
+//! slots, and directional and point lights get shadows as well.
+//!
+//! The volume is uploaded as a 2D texture with the xy-slices side by side along x, and the shader
+//! filters between the two closest slices. Surfaces sample it one voxel out along their normal, so
+//! walls that are thinner than a voxel leak light.
+//!
+//! Entities are static if they, or an entity above them, have the Static component. A light also
+//! needs it to be baked. The volume is baked the first time there is static geometry, and again
+//! when ShadowMask::rebake is set. Volumetric lights keep their shadow maps, as the light shafts are
+//! marched through them.
+
+use std::collections::HashSet;
+
+use ramneryd_derive::Inspect;
+
+use trekanten::resource::Handle;
+use trekanten::texture::{MipMaps, Texture, TextureDescriptor};
+use trekanten::util::{Extent2D, Format};
+use trekanten::Renderer;
+
+use crate::collision::{self, raycast, Collider};
+use crate::common::Name;
+use crate::ecs::prelude::*;
+use crate::editor::Inspect;
+use crate::graph::{sys as graph, Children};
+use crate::math::{BoundingBox, ModelMatrix, Ray, Transform, Vec3};
+use crate::render::ui::UiFrame;
+
+use super::light::{Light, Volumetric};
+use super::uniform::LightingData;
+
+/// The number of channels of the mask
+pub const MAX_BAKED_LIGHTS: usize = 4;
+/// The texture that the volume is uploaded as is at most this wide and high
+const MAX_ATLAS_SIZE: usize = 8192;
+/// The voxel size is increased until the volume has at most this many voxels
+const MAX_VOXELS: usize = 1 << 22;
+const EPS: f32 = 0.001;
+
+/// The entity, and the entities below it, don't move. Static lights have their shadows baked.
+#[derive(Default, Component)]
+#[component(storage = "NullStorage")]
+pub struct Static;
+
+#[derive(Debug, Clone, Copy, PartialEq, Inspect)]
+pub struct ShadowBakeSettings {
+    /// The edge length of the voxels, in meters
+    pub voxel_size: f32,
+}
+
+impl Default for ShadowBakeSettings {
+    fn default() -> Self {
+        Self { voxel_size: 0.25 }
+    }
+}
+
+/// Where a light was when it was baked
+#[derive(Debug, Clone, Copy)]
+enum BakedLight {
+    /// The direction towards the light
+    Directional(Vec3),
+    Positional(Vec3),
+}
+
+impl BakedLight {
+    fn new(light: &Light, tfm: &Transform) -> Option<Self> {
+        match light {
+            Light::Directional { .. } => Some(Self::Directional(
+                -(tfm.rotation * Light::DEFAULT_FACING).normalized(),
+            )),
+            Light::Point { .. } | Light::Spot { .. } => Some(Self::Positional(tfm.position)),
+            Light::Ambient { .. } => None,
+        }
+    }
+
+    fn is_visible(&self, colliders: &[Collider], pos: Vec3) -> bool {
+        // The positional rays end at the light, at t = 1.0
+        let (ray, max_distance) = match *self {
+            Self::Directional(dir) => (
+                Ray {
+                    origin: pos,
+                    direction: dir,
+                },
+                std::f32::INFINITY,
+            ),
+            Self::Positional(light_pos) => (
+                Ray {
+                    origin: pos,
+                    direction: light_pos - pos,
+                },
+                1.0,
+            ),
+        };
+        raycast(colliders, &ray, max_distance).is_none()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BakedShadowMask {
+    /// The corner of the volume with the smallest coordinates
+    pub min: Vec3,
+    pub voxel_size: f32,
+    pub dims: [usize; 3],
+    /// The light of each channel
+    pub lights: Vec<Entity>,
+    /// RGBA8, with the xy-slices side by side along x
+    texels: Vec<u8>,
+}
+
+impl BakedShadowMask {
+    fn bake(
+        colliders: &[Collider],
+        lights: &[(Entity, BakedLight)],
+        settings: &ShadowBakeSettings,
+    ) -> Option<Self> {
+        let mut bounds = colliders.iter().map(Collider::world_bbox).fold(
+            None,
+            |acc: Option<BoundingBox>, mut bbox| {
+                if let Some(acc) = acc {
+                    bbox.combine(acc);
+                }
+                Some(bbox)
+            },
+        )?;
+
+        let mut voxel_size = settings.voxel_size.max(EPS);
+        let dims = loop {
+            let extent = bounds.max - bounds.min + Vec3::broadcast(2.0 * voxel_size);
+            let dims = [
+                ((extent.x / voxel_size).ceil() as usize).max(1),
+                ((extent.y / voxel_size).ceil() as usize).max(1),
+                ((extent.z / voxel_size).ceil() as usize).max(1),
+            ];
+            if dims[0] * dims[2] <= MAX_ATLAS_SIZE
+                && dims[1] <= MAX_ATLAS_SIZE
+                && dims[0] * dims[1] * dims[2] <= MAX_VOXELS
+            {
+                break dims;
+            }
+            voxel_size *= 2.0;
+        };
+        if voxel_size > settings.voxel_size {
+            log::warn!(
+                "The shadow mask is too large, using {} m voxels instead",
+                voxel_size
+            );
+        }
+        // Pad by one voxel, so that surfaces on the bounds sample outside of them
+        bounds.min -= Vec3::broadcast(voxel_size);
+
+        let mut mask = Self {
+            min: bounds.min,
+            voxel_size,
+            dims,
+            lights: lights.iter().map(|(ent, _)| *ent).collect(),
+            texels: vec![255; dims[0] * dims[1] * dims[2] * 4],
+        };
+        for y in 0..dims[1] {
+            for z in 0..dims[2] {
+                for x in 0..dims[0] {
+                    let pos = mask.voxel_center([x, y, z]);
+                    let texel = mask.texel_index([x, y, z]);
+                    for (channel, (_, light)) in lights.iter().enumerate() {
+                        if !light.is_visible(colliders, pos) {
+                            mask.texels[texel + channel] = 0;
+                        }
+                    }
+                }
+            }
+        }
+
+        Some(mask)
+    }
+
+    fn voxel_center(&self, [x, y, z]: [usize; 3]) -> Vec3 {
+        self.min + Vec3::new(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5) * self.voxel_size
+    }
+
+    fn atlas_extent(&self) -> Extent2D {
+        Extent2D {
+            width: (self.dims[0] * self.dims[2]) as u32,
+            height: self.dims[1] as u32,
+        }
+    }
+
+    fn texel_index(&self, [x, y, z]: [usize; 3]) -> usize {
+        (y * self.dims[0] * self.dims[2] + z * self.dims[0] + x) * 4
+    }
+
+    /// The channel that has the shadows of the light, if it was baked
+    pub fn channel(&self, light: Entity) -> Option<u32> {
+        self.lights
+            .iter()
+            .position(|ent| *ent == light)
+            .map(|c| c as u32)
+    }
+
+    /// The visibility of the light of the channel at the center of the voxel, 0 in shadow and 1 if
+    /// it is lit
+    pub fn visibility(&self, voxel: [usize; 3], channel: u32) -> f32 {
+        self.texels[self.texel_index(voxel) + channel as usize] as f32 / 255.0
+    }
+
+    /// Fills in the volume, for the PBR shader to sample
+    pub(super) fn write_lighting_data(&self, lighting_data: &mut LightingData) {
+        lighting_data.shadow_mask_min = [self.min.x, self.min.y, self.min.z, self.voxel_size];
+        lighting_data.shadow_mask_dims = [
+            self.dims[0] as u32,
+            self.dims[1] as u32,
+            self.dims[2] as u32,
+            1,
+        ];
+    }
+}
+
+/// Baked shadows of the static lights. It is in world space, so the transform of its entity should
+/// be identity. Only the first one is used.
+#[derive(Component)]
+#[component(inspect)]
+pub struct ShadowMask {
+    pub settings: ShadowBakeSettings,
+    /// Bake the shadows again, e.g. after the static geometry or lights have changed
+    pub rebake: bool,
+    #[inspect(ignore)]
+    baked: Option<BakedShadowMask>,
+    /// Counts the bakes, so that the renderer knows when to upload it again
+    #[inspect(ignore)]
+    generation: u32,
+}
+
+impl ShadowMask {
+    pub fn new(settings: ShadowBakeSettings) -> Self {
+        Self {
+            settings,
+            rebake: false,
+            baked: None,
+            generation: 0,
+        }
+    }
+
+    pub fn baked(&self) -> Option<&BakedShadowMask> {
+        self.baked.as_ref()
+    }
+}
+
+pub fn create(world: &mut World, settings: ShadowBakeSettings) -> Entity {
+    world
+        .create_entity()
+        .with(Name::from("ShadowMask"))
+        .with(Transform::identity())
+        .with(ShadowMask::new(settings))
+        .build()
+}
+
+/// The entities with Static and the entities below them
+fn static_entities<'a>(
+    entities: &Entities<'a>,
+    statics: &ReadStorage<'a, Static>,
+    children: &ReadStorage<'a, Children>,
+) -> HashSet<Entity> {
+    let mut result = HashSet::new();
+    for (ent, _) in (entities, statics).join() {
+        graph::breadth_first(children, ent, |node| {
+            result.insert(node);
+        });
+    }
+    result
+}
+
+pub struct BakeShadowMask;
+
+impl BakeShadowMask {
+    pub const ID: &'static str = "BakeShadowMask";
+}
+
+#[derive(SystemData)]
+pub struct BakeShadowMaskData<'a> {
+    entities: Entities<'a>,
+    masks: WriteStorage<'a, ShadowMask>,
+    statics: ReadStorage<'a, Static>,
+    children: ReadStorage<'a, Children>,
+    bboxes: ReadStorage<'a, BoundingBox>,
+    model_matrices: ReadStorage<'a, ModelMatrix>,
+    lights: ReadStorage<'a, Light>,
+    volumetrics: ReadStorage<'a, Volumetric>,
+    transforms: ReadStorage<'a, Transform>,
+}
+
+impl<'a> System<'a> for BakeShadowMask {
+    type SystemData = BakeShadowMaskData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let BakeShadowMaskData {
+            entities,
+            mut masks,
+            statics,
+            children,
+            bboxes,
+            model_matrices,
+            lights,
+            volumetrics,
+            transforms,
+        } = data;
+
+        for mask in (&mut masks).join() {
+            if mask.baked.is_some() && !mask.rebake {
+                continue;
+            }
+
+            let static_ents = static_entities(&entities, &statics, &children);
+            let colliders = collision::gather(&entities, &bboxes, &model_matrices, |ent| {
+                static_ents.contains(&ent)
+            });
+            // The scene might still be loading
+            if colliders.is_empty() {
+                continue;
+            }
+
+            let mut baked_lights = Vec::new();
+            for (ent, light, tfm, _) in (&entities, &lights, &transforms, !&volumetrics).join() {
+                if !static_ents.contains(&ent) {
+                    continue;
+                }
+                let baked = match BakedLight::new(light, tfm) {
+                    Some(baked) => baked,
+                    None => continue,
+                };
+                if baked_lights.len() == MAX_BAKED_LIGHTS {
+                    log::warn!(
+                        "Too many static lights, only the first {} are baked",
+                        MAX_BAKED_LIGHTS
+                    );
+                    break;
+                }
+                baked_lights.push((ent, baked));
+            }
+
+            let start = std::time::Instant::now();
+            mask.baked = BakedShadowMask::bake(&colliders, &baked_lights, &mask.settings);
+            mask.generation += 1;
+            mask.rebake = false;
+            if let Some(baked) = &mask.baked {
+                log::info!(
+                    "Baked the shadows of {} lights into {}x{}x{} voxels from {} colliders in {:.2} s",
+                    baked.lights.len(),
+                    baked.dims[0],
+                    baked.dims[1],
+                    baked.dims[2],
+                    colliders.len(),
+                    start.elapsed().as_secs_f32()
+                );
+            }
+        }
+    }
+}
+
+/// The uploaded shadow mask. Bound with the lights, as it is used for all of them.
+pub(super) struct ShadowMaskTexture {
+    texture: Handle<Texture>,
+    /// The mask entity and the generation of the bake that was uploaded
+    uploaded: Option<(Entity, u32)>,
+}
+
+fn create_texture(renderer: &mut Renderer, data: Vec<u8>, extent: Extent2D) -> Handle<Texture> {
+    renderer
+        .create_texture(TextureDescriptor::from_vec(
+            data,
+            extent,
+            Format::RGBA_UNORM,
+            MipMaps::None,
+        ))
+        .expect("Failed to create shadow mask texture")
+}
+
+impl ShadowMaskTexture {
+    /// A white texture, i.e. everything is lit, until there is a mask to upload
+    pub(super) fn new(renderer: &mut Renderer) -> Self {
+        let extent = Extent2D {
+            width: 1,
+            height: 1,
+        };
+        Self {
+            texture: create_texture(renderer, vec![255; 4], extent),
+            uploaded: None,
+        }
+    }
+
+    pub(super) fn texture(&self) -> &Handle<Texture> {
+        &self.texture
+    }
+
+    /// Uploads the mask if it has been baked since the last time. Returns true if the texture
+    /// changed, and the descriptor set that it is bound in has to be created again.
+    pub(super) fn prepare(&mut self, world: &World, renderer: &mut Renderer) -> bool {
+        let entities = world.entities();
+        let masks = world.read_storage::<ShadowMask>();
+        let current = (&entities, &masks)
+            .join()
+            .find(|(_, mask)| mask.baked.is_some());
+        let (ent, mask) = match current {
+            Some(current) => current,
+            None => return false,
+        };
+        if self.uploaded == Some((ent, mask.generation)) {
+            return false;
+        }
+
+        let baked = mask.baked.as_ref().expect("Only baked masks are uploaded");
+        let texture = create_texture(renderer, baked.texels.clone(), baked.atlas_extent());
+        renderer.destroy_deferred(std::mem::replace(&mut self.texture, texture));
+        self.uploaded = Some((ent, mask.generation));
+        true
+    }
+
+    /// The mask that was uploaded, if it is still there
+    pub(super) fn baked<'a>(
+        &self,
+        masks: &'a ReadStorage<ShadowMask>,
+    ) -> Option<&'a BakedShadowMask> {
+        let (ent, generation) = self.uploaded?;
+        masks
+            .get(ent)
+            .filter(|mask| mask.generation == generation)
+            .and_then(|mask| mask.baked.as_ref())
+    }
+}
+
+/// Marks the selected entities as static and bakes the shadow mask
+pub(crate) fn build_ui<'a>(world: &mut World, ui: &UiFrame<'a>, pos: [f32; 2]) -> [f32; 2] {
+    let size = [300.0, 150.0];
+
+    let mut create_mask = false;
+    imgui::Window::new(imgui::im_str!("Shadow bake"))
+        .position(pos, imgui::Condition::FirstUseEver)
+        .size(size, imgui::Condition::FirstUseEver)
+        .collapsed(true, imgui::Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let selected: Vec<Entity> = world
+                .try_fetch::<crate::editor::Selection>()
+                .map(|selection| selection.entities().to_vec())
+                .unwrap_or_default();
+            let selected: Vec<Entity> = selected
+                .into_iter()
+                .filter(|ent| world.is_alive(*ent))
+                .collect();
+
+            {
+                let mut statics = world.write_storage::<Static>();
+                ui.inner().text(imgui::im_str!(
+                    "{} static roots, {} selected",
+                    (&statics).join().count(),
+                    selected.len()
+                ));
+                if ui.inner().button(imgui::im_str!("Make static"), [0.0, 0.0]) {
+                    for &ent in selected.iter() {
+                        statics
+                            .insert(ent, Static)
+                            .expect("Selected entities are alive");
+                    }
+                }
+                ui.inner().same_line(0.0);
+                if ui
+                    .inner()
+                    .button(imgui::im_str!("Make dynamic"), [0.0, 0.0])
+                {
+                    for &ent in selected.iter() {
+                        statics.remove(ent);
+                    }
+                }
+            }
+            ui.inner().separator();
+
+            let mut masks = world.write_storage::<ShadowMask>();
+            let mask = match (&mut masks).join().next() {
+                Some(mask) => mask,
+                None => {
+                    create_mask = ui.inner().button(imgui::im_str!("Bake"), [0.0, 0.0]);
+                    return;
+                }
+            };
+            mask.settings.voxel_size.inspect_mut(ui, "voxel size");
+            match &mask.baked {
+                Some(baked) => ui.inner().text(imgui::im_str!(
+                    "{} lights in {}x{}x{} voxels of {} m",
+                    baked.lights.len(),
+                    baked.dims[0],
+                    baked.dims[1],
+                    baked.dims[2],
+                    baked.voxel_size
+                )),
+                None => ui.inner().text(imgui::im_str!("Not baked")),
+            }
+            if ui.inner().button(imgui::im_str!("Bake"), [0.0, 0.0]) {
+                mask.rebake = true;
+            }
+        });
+
+    if create_mask {
+        create(world, ShadowBakeSettings::default());
+    }
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Mat4;
+
+    fn collider(min: [f32; 3], max: [f32; 3]) -> Collider {
+        let bbox = BoundingBox {
+            min: Vec3::from(min),
+            max: Vec3::from(max),
+        };
+        Collider::new(bbox, Mat4::identity())
+    }
+
+    #[test]
+    fn wall_shadows_floor() {
+        let colliders = [
+            collider([0.0, -1.0, 0.0], [4.0, 0.0, 4.0]),
+            // A wall along z in the middle of the floor
+            collider([1.75, 0.0, 0.0], [2.25, 2.0, 4.0]),
+        ];
+        let mut world = World::new();
+        let lights = [
+            (
+                world.create_entity().build(),
+                BakedLight::Positional(Vec3::new(0.5, 1.0, 2.0)),
+            ),
+            (
+                world.create_entity().build(),
+                BakedLight::Directional(Vec3::unit_y()),
+            ),
+        ];
+        let settings = ShadowBakeSettings { voxel_size: 0.5 };
+        let mask = BakedShadowMask::bake(&colliders, &lights, &settings).unwrap();
+        assert_eq!(mask.dims, [10, 8, 10]);
+        assert_eq!(mask.channel(lights[1].0), Some(1));
+
+        // Just above the floor, on either side of the wall
+        let voxel = |x: f32, z: f32| {
+            let v = (Vec3::new(x, 0.25, z) - mask.min) / mask.voxel_size;
+            [v.x as usize, v.y as usize, v.z as usize]
+        };
+        assert_eq!(mask.visibility(voxel(1.25, 2.0), 0), 1.0);
+        assert_eq!(mask.visibility(voxel(3.25, 2.0), 0), 0.0);
+        assert_eq!(mask.visibility(voxel(3.25, 2.0), 1), 1.0);
+        // Unused channels are lit
+        assert_eq!(mask.visibility(voxel(3.25, 2.0), 3), 1.0);
+    }
+}
@@ -0,0 +1,525 @@
+//! Streams the textures of physically based materials that are loaded from files. A placeholder is
+//! bound until the mip tail, the smallest mip levels, of the texture has been uploaded. After that,
+//! the texture is uploaded again at the mip level that matches the distance to the camera, closest
+//! entities first, as long as all streamed textures fit in the budget of the render settings.
+
+use trekanten::loader::ResourceLoader as _;
+use trekanten::resource::Async;
+use trekanten::texture::{self, MipMaps, Texture, TextureDescriptor};
+use trekanten::util::{Extent2D, Format};
+use trekanten::{Handle, Renderer};
+
+use crate::ecs::prelude::*;
+use crate::math::ModelMatrix;
+use crate::render::debug_window::RenderSettings;
+use crate::render::material::{self, GpuMaterial, PendingMaterial, PhysicallyBased, TextureUse};
+use crate::render::virtual_texture::VirtualTexture;
+use crate::render::Pending;
+
+/// Textures are never streamed at a lower resolution than this
+const MIP_TAIL_SIZE: u32 = 64;
+/// Limits the number of images that are decoded at the same time
+const MAX_REQUESTS_PER_FRAME: usize = 8;
+/// The textures of a physically based material, in the order of PhysicallyBased::textures()
+const NUM_SLOTS: usize = 5;
+const BASE_COLOR_SLOT: usize = 1;
+
+/// The mip level that the texture is uploaded at, and the handle that the GpuMaterial uses for it
+#[derive(Debug, Clone, Copy)]
+struct Resident {
+    level: u32,
+    handle: Handle<Texture>,
+}
+
+#[derive(Debug)]
+struct StreamedTexture {
+    /// Of the image in the file
+    extent: Extent2D,
+    /// None while the placeholder is bound
+    resident: Option<Resident>,
+    loading: Option<u32>,
+}
+
+impl StreamedTexture {
+    fn bytes(&self) -> u64 {
+        let resident = self.resident.map(|r| texture_bytes(self.extent, r.level));
+        let loading = self.loading.map(|level| texture_bytes(self.extent, level));
+        resident.unwrap_or(0) + loading.unwrap_or(0)
+    }
+}
+
+/// The streaming state of the textures of a PhysicallyBased material. Slots are None if the
+/// texture is not streamed.
+#[derive(Debug, Default, Component)]
+pub struct StreamedTextures {
+    slots: [Option<StreamedTexture>; NUM_SLOTS],
+}
+
+impl StreamedTextures {
+    /// The mip level that the texture in the slot is uploaded at, None if it is not streamed or the
+    /// placeholder is bound.
+    fn resident_level(&self, slot: usize) -> Option<u32> {
+        self.slots[slot]
+            .as_ref()
+            .and_then(|s| s.resident)
+            .map(|r| r.level)
+    }
+}
+
+pub struct TextureStreaming {
+    /// Bound until the first upload is done, one for each slot
+    placeholders: [Handle<Texture>; NUM_SLOTS],
+    /// Of all streamed textures, including the ones that are being uploaded
+    usage_bytes: u64,
+}
+
+impl TextureStreaming {
+    pub fn new(renderer: &mut Renderer) -> Self {
+        let mut placeholder = |color: [u8; 4]| {
+            renderer
+                .create_texture(TextureDescriptor::from_vec(
+                    color.to_vec(),
+                    Extent2D {
+                        width: 1,
+                        height: 1,
+                    },
+                    Format::RGBA_UNORM,
+                    MipMaps::None,
+                ))
+                .expect("Failed to create placeholder texture")
+        };
+        let white = placeholder([255, 255, 255, 255]);
+        Self {
+            // Normals that point straight out of the surface, the factors of the material as they
+            // are, no emission and no occlusion.
+            placeholders: [
+                placeholder([128, 128, 255, 255]),
+                white,
+                white,
+                placeholder([0, 0, 0, 255]),
+                white,
+            ],
+            usage_bytes: 0,
+        }
+    }
+
+    pub fn usage_bytes(&self) -> u64 {
+        self.usage_bytes
+    }
+
+    /// The placeholders are shared between all materials and are never destroyed
+    pub(super) fn is_placeholder(&self, h: &Handle<Texture>) -> bool {
+        self.placeholders.contains(h)
+    }
+}
+
+fn texture_slots<T>(
+    normal_map: T,
+    base_color_texture: T,
+    metallic_roughness_texture: T,
+    emissive_texture: T,
+    occlusion_texture: T,
+) -> [T; NUM_SLOTS] {
+    [
+        normal_map,
+        base_color_texture,
+        metallic_roughness_texture,
+        emissive_texture,
+        occlusion_texture,
+    ]
+}
+
+fn gpu_textures(mat: &GpuMaterial) -> Option<[&Option<TextureUse<Texture>>; NUM_SLOTS]> {
+    match mat {
+        GpuMaterial::PBR {
+            normal_map,
+            base_color_texture,
+            metallic_roughness_texture,
+            emissive_texture,
+            occlusion_texture,
+            ..
+        } => Some(texture_slots(
+            normal_map,
+            base_color_texture,
+            metallic_roughness_texture,
+            emissive_texture,
+            occlusion_texture,
+        )),
+        GpuMaterial::Unlit { .. } => None,
+    }
+}
+
+/// Only textures from files can be uploaded at a lower mip level
+pub(super) fn is_streamed(tex: &material::TextureUse2) -> bool {
+    matches!(tex.desc, TextureDescriptor::File { .. })
+}
+
+/// The descriptor of a streamed texture at mip level `level`
+pub(super) fn level_descriptor(desc: &TextureDescriptor, level: u32) -> TextureDescriptor {
+    match desc {
+        TextureDescriptor::File {
+            path,
+            format,
+            mipmaps,
+        } => TextureDescriptor::file_mip_level(path.clone(), *format, *mipmaps, level),
+        _ => unreachable!("Only file textures are streamed"),
+    }
+}
+
+/// The placeholder texture for the texture of a new material, if it is streamed. Otherwise, it is
+/// loaded as is.
+pub(super) fn placeholder(
+    streaming: &TextureStreaming,
+    pb_mat: &PhysicallyBased,
+    slot: usize,
+) -> Option<TextureUse<Texture>> {
+    let textures = texture_slots(
+        &pb_mat.normal_map,
+        &pb_mat.base_color_texture,
+        &pb_mat.metallic_roughness_texture,
+        &pb_mat.emissive_texture,
+        &pb_mat.occlusion_texture,
+    );
+    textures[slot]
+        .as_ref()
+        .filter(|tex| is_streamed(tex))
+        .map(|tex| TextureUse {
+            handle: streaming.placeholders[slot],
+            coord_set: tex.coord_set,
+        })
+}
+
+/// The descriptor to reload the texture in the slot with, when its file has changed. None if the
+/// placeholder is still bound, the streaming will upload it later.
+pub(super) fn reload_descriptor(
+    streamed: Option<&StreamedTextures>,
+    tex: &material::TextureUse2,
+    slot: usize,
+) -> Option<TextureDescriptor> {
+    if !is_streamed(tex) {
+        return Some(tex.desc.clone());
+    }
+
+    streamed
+        .and_then(|s| s.resident_level(slot))
+        .map(|level| level_descriptor(&tex.desc, level))
+}
+
+/// In bytes, including the mip chain that is generated after the upload
+fn texture_bytes(extent: Extent2D, level: u32) -> u64 {
+    let extent = texture::mip_extent(extent, level);
+    let base = extent.width as u64 * extent.height as u64 * 4;
+    base * 4 / 3
+}
+
+/// The largest mip level, by extent, that has at most MIP_TAIL_SIZE pixels along each side
+fn mip_tail_level(extent: Extent2D) -> u32 {
+    let mut level = 0;
+    while (extent.max_dim() >> level) > MIP_TAIL_SIZE {
+        level += 1;
+    }
+    level
+}
+
+/// The mip level that is needed at `distance` from the camera. Full resolution is used within
+/// `full_resolution_distance` and one level less each time the distance doubles after that.
+fn desired_level(distance: f32, full_resolution_distance: f32, tail_level: u32) -> u32 {
+    if distance <= full_resolution_distance {
+        return 0;
+    }
+
+    let level = (distance / full_resolution_distance).log2().floor() as u32 + 1;
+    level.min(tail_level)
+}
+
+struct Request {
+    entity: Entity,
+    slot: usize,
+    level: u32,
+    distance: f32,
+    is_mip_tail: bool,
+    extra_bytes: i64,
+}
+
+/// Tracks the textures that have been swapped in since the last frame and requests new mip levels.
+/// The textures that are replaced are destroyed by resource_tracking::release_unused.
+#[profiling::function]
+pub fn update(world: &mut World) {
+    let camera_pos = super::camera_pos(world);
+    let (budget_bytes, full_resolution_distance) = {
+        let settings = world.read_resource::<RenderSettings>();
+        (
+            settings.texture_budget_mb as u64 * 1024 * 1024,
+            settings.texture_full_resolution_distance,
+        )
+    };
+
+    let entities = world.entities();
+    let loader = world.read_resource::<trekanten::Loader>();
+    let mut streaming = world.write_resource::<TextureStreaming>();
+    let physically_based = world.read_storage::<PhysicallyBased>();
+    let gpu_materials = world.read_storage::<GpuMaterial>();
+    let mut pending_materials = world.write_storage::<PendingMaterial>();
+    let mut streamed_textures = world.write_storage::<StreamedTextures>();
+    let model_matrices = world.read_storage::<ModelMatrix>();
+    let virtual_textures = world.read_storage::<VirtualTexture>();
+
+    // New materials
+    let new: Vec<Entity> = (
+        &entities,
+        &physically_based,
+        &gpu_materials,
+        !&streamed_textures,
+    )
+        .join()
+        .map(|(ent, ..)| ent)
+        .collect();
+    for ent in new {
+        let pb_mat = physically_based.get(ent).expect("This is alive");
+        let textures = texture_slots(
+            &pb_mat.normal_map,
+            &pb_mat.base_color_texture,
+            &pb_mat.metallic_roughness_texture,
+            &pb_mat.emissive_texture,
+            &pb_mat.occlusion_texture,
+        );
+        let mut streamed = StreamedTextures::default();
+        for (slot, tex) in textures.iter().enumerate() {
+            let path = match tex.as_ref().filter(|tex| is_streamed(tex)) {
+                Some(material::TextureUse2 {
+                    desc: TextureDescriptor::File { path, .. },
+                    ..
+                }) => path,
+                _ => continue,
+            };
+            match texture::image_extent(path) {
+                Ok(extent) => {
+                    streamed.slots[slot] = Some(StreamedTexture {
+                        extent,
+                        resident: None,
+                        loading: None,
+                    })
+                }
+                Err(e) => log::error!("Can't stream {}: {}", path.display(), e),
+            }
+        }
+        streamed_textures
+            .insert(ent, streamed)
+            .expect("This is alive");
+    }
+
+    // The base color texture is replaced by the virtual texture, see render::virtual_texture
+    for (streamed, _) in (&mut streamed_textures, &virtual_textures).join() {
+        streamed.slots[BASE_COLOR_SLOT] = None;
+    }
+
+    // Textures that have been swapped in
+    for (gpu_mat, streamed, _) in
+        (&gpu_materials, &mut streamed_textures, !&pending_materials).join()
+    {
+        let current = match gpu_textures(gpu_mat) {
+            Some(current) => current,
+            None => continue,
+        };
+        for (slot, state) in streamed.slots.iter_mut().enumerate() {
+            let (state, current) = match (state, current[slot]) {
+                (Some(state), Some(current)) => (state, current.handle),
+                _ => continue,
+            };
+            let expected = state
+                .resident
+                .map(|r| r.handle)
+                .unwrap_or(streaming.placeholders[slot]);
+            if current == expected {
+                continue;
+            }
+
+            // If nothing was loading, the file was changed and reloaded at the same level
+            let level = match (state.loading.take(), state.resident) {
+                (Some(level), _) => level,
+                (None, Some(resident)) => resident.level,
+                (None, None) => continue,
+            };
+            state.resident = Some(Resident {
+                level,
+                handle: current,
+            });
+        }
+    }
+
+    streaming.usage_bytes = (&streamed_textures)
+        .join()
+        .flat_map(|streamed| streamed.slots.iter().flatten())
+        .map(StreamedTexture::bytes)
+        .sum();
+
+    // Entities that are still loading something can't be updated until the new material is done
+    let mut requests = Vec::new();
+    for (ent, streamed, model, _) in (
+        &entities,
+        &streamed_textures,
+        model_matrices.maybe(),
+        !&pending_materials,
+    )
+        .join()
+    {
+        let distance = model
+            .map(|m| (m.0.cols.w.xyz() - camera_pos).magnitude())
+            .unwrap_or(0.0);
+        for (slot, state) in streamed.slots.iter().enumerate() {
+            let state = match state {
+                Some(state) => state,
+                None => continue,
+            };
+            let tail_level = mip_tail_level(state.extent);
+            let current_bytes = state.bytes() as i64;
+            let request = |level: u32, is_mip_tail: bool| Request {
+                entity: ent,
+                slot,
+                level,
+                distance,
+                is_mip_tail,
+                extra_bytes: texture_bytes(state.extent, level) as i64,
+            };
+            match state.resident {
+                None => requests.push(request(tail_level, true)),
+                Some(resident) => {
+                    let level = desired_level(distance, full_resolution_distance, tail_level);
+                    // Going down a single level is delayed until the budget is needed, so that
+                    // moving back and forth over a boundary doesn't reload the texture every time.
+                    let is_downgrade = level > resident.level + 1
+                        || (level > resident.level && streaming.usage_bytes > budget_bytes);
+                    if level < resident.level || is_downgrade {
+                        let mut r = request(level, false);
+                        r.extra_bytes -= current_bytes;
+                        requests.push(r);
+                    }
+                }
+            }
+        }
+    }
+
+    // Mip tails first, as placeholders are bound, then the closest. Lower levels that free memory
+    // are always allowed.
+    requests.sort_by(|a, b| {
+        b.is_mip_tail.cmp(&a.is_mip_tail).then(
+            a.distance
+                .partial_cmp(&b.distance)
+                .unwrap_or(std::cmp::Ordering::Equal),
+        )
+    });
+    let mut usage = streaming.usage_bytes as i64;
+    let mut per_entity: Vec<(Entity, [Option<u32>; NUM_SLOTS])> = Vec::new();
+    for request in requests {
+        if per_entity.len() >= MAX_REQUESTS_PER_FRAME
+            && !per_entity.iter().any(|(ent, _)| *ent == request.entity)
+        {
+            break;
+        }
+
+        let fits = usage + request.extra_bytes <= budget_bytes as i64;
+        if !(request.is_mip_tail || request.extra_bytes <= 0 || fits) {
+            continue;
+        }
+
+        usage += request.extra_bytes;
+        match per_entity
+            .iter_mut()
+            .find(|(ent, _)| *ent == request.entity)
+        {
+            Some((_, levels)) => levels[request.slot] = Some(request.level),
+            None => {
+                let mut levels = [None; NUM_SLOTS];
+                levels[request.slot] = Some(request.level);
+                per_entity.push((request.entity, levels));
+            }
+        }
+    }
+
+    for (ent, levels) in per_entity {
+        let pb_mat = physically_based.get(ent).expect("This is alive");
+        let gpu_mat = gpu_materials.get(ent).expect("This is alive");
+        let streamed = streamed_textures.get_mut(ent).expect("This is alive");
+        let sources = texture_slots(
+            &pb_mat.normal_map,
+            &pb_mat.base_color_texture,
+            &pb_mat.metallic_roughness_texture,
+            &pb_mat.emissive_texture,
+            &pb_mat.occlusion_texture,
+        );
+
+        let mut load =
+            |slot: usize, cur: &Option<TextureUse<Texture>>| match (levels[slot], sources[slot]) {
+                (Some(level), Some(src)) => {
+                    let handle: Handle<Async<Texture>> = loader
+                        .load(level_descriptor(&src.desc, level))
+                        .expect("Failed to load texture");
+                    if let Some(state) = &mut streamed.slots[slot] {
+                        state.loading = Some(level);
+                    }
+                    Some(Pending::Pending(TextureUse {
+                        handle,
+                        coord_set: src.coord_set,
+                    }))
+                }
+                _ => cur.clone().map(Pending::Available),
+            };
+
+        if let GpuMaterial::PBR {
+            material_uniforms,
+            normal_map,
+            base_color_texture,
+            metallic_roughness_texture,
+            emissive_texture,
+            occlusion_texture,
+            has_vertex_colors,
+        } = gpu_mat
+        {
+            pending_materials
+                .insert(
+                    ent,
+                    PendingMaterial::PBR {
+                        material_uniforms: Pending::Available(*material_uniforms),
+                        normal_map: load(0, normal_map),
+                        base_color_texture: load(1, base_color_texture),
+                        metallic_roughness_texture: load(2, metallic_roughness_texture),
+                        emissive_texture: load(3, emissive_texture),
+                        occlusion_texture: load(4, occlusion_texture),
+                        has_vertex_colors: *has_vertex_colors,
+                    },
+                )
+                .expect("This is alive");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_tail_fits_tail_size() {
+        let extent = Extent2D {
+            width: 2048,
+            height: 512,
+        };
+        let level = mip_tail_level(extent);
+        assert_eq!(level, 5);
+        assert_eq!(texture::mip_extent(extent, level).width, MIP_TAIL_SIZE);
+        assert_eq!(
+            mip_tail_level(Extent2D {
+                width: 16,
+                height: 16
+            }),
+            0
+        );
+    }
+
+    #[test]
+    fn lower_resolution_further_away() {
+        assert_eq!(desired_level(5.0, 10.0, 6), 0);
+        assert_eq!(desired_level(15.0, 10.0, 6), 1);
+        assert_eq!(desired_level(25.0, 10.0, 6), 2);
+        assert_eq!(desired_level(1000.0, 10.0, 6), 6);
+    }
+}
@@ -0,0 +1,710 @@
+//! Small images of assets and materials for the editor. Image files are downscaled. glTF and mesh
+//! files, and the materials of the MaterialLibrary on a sphere, are rendered to textures with a
+//! simple headlight, in an offscreen pass before the ui is drawn, see ThumbnailRenderer.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use trekanten::descriptor::DescriptorSet;
+use trekanten::mem::{
+    BufferDescriptor as _, BufferMutability, IndexBuffer, OwningIndexBufferDescriptor,
+    OwningVertexBufferDescriptor, VertexBuffer,
+};
+use trekanten::pipeline::{
+    GraphicsPipeline, GraphicsPipelineDescriptor, ShaderDescriptor, ShaderStage, TriangleCulling,
+};
+use trekanten::raw_vk;
+use trekanten::resource::Handle;
+use trekanten::texture::{MipMaps, TextureDescriptor, TextureUsage};
+use trekanten::util::{self, Extent2D, Format};
+use trekanten::vertex::VertexFormat;
+use trekanten::{BufferHandle, CommandBuffer, Frame, RenderPass, RenderTarget, Renderer, Texture};
+
+use crate::math::{BoundingBox, Mat4, Vec2, Vec3, Vec4};
+
+use super::geometry::{self, PrimitiveOptions};
+use super::material::{self, MaterialId, PhysicallyBased};
+use super::{pipeline, uniform, MaterialError};
+
+pub const THUMBNAIL_SIZE: u32 = 64;
+
+const EXTENT: Extent2D = Extent2D {
+    width: THUMBNAIL_SIZE,
+    height: THUMBNAIL_SIZE,
+};
+/// The previews are seen from above, at an angle
+const VIEW_DIRECTION: [f32; 3] = [-1.0, -0.8, -1.0];
+const FOV_Y: f32 = std::f32::consts::FRAC_PI_4;
+/// The base color textures are downscaled to this size before they are uploaded
+const TEXTURE_SIZE: u32 = 2 * THUMBNAIL_SIZE;
+
+/// What a thumbnail is of
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum ThumbnailKey {
+    Asset(PathBuf),
+    Material(MaterialId),
+}
+
+/// What a thumbnail shows. A thumbnail is rendered again when it changes.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ThumbnailSource {
+    /// An image, glTF or mesh file
+    Asset(PathBuf),
+    /// A sphere with the base color of a material
+    Material {
+        base_color_factor: [f32; 4],
+        base_color_texture: Option<PathBuf>,
+    },
+}
+
+impl ThumbnailSource {
+    fn material(material: &PhysicallyBased) -> Self {
+        Self::Material {
+            base_color_factor: material.base_color_factor.into_array(),
+            base_color_texture: material
+                .base_color_texture
+                .as_ref()
+                .and_then(material::TextureUse2::path)
+                .map(Path::to_path_buf),
+        }
+    }
+}
+
+enum Thumbnail {
+    Requested,
+    Available(imgui::TextureId),
+    /// The source changed, the old thumbnail is shown until it is rendered again
+    Outdated(imgui::TextureId),
+    Unavailable,
+}
+
+/// Cache of small images of assets that can be used in ui widgets. Thumbnails are requested when
+/// they are first looked up and are generated before the next frame.
+#[derive(Default)]
+pub struct Thumbnails {
+    cache: HashMap<ThumbnailKey, (ThumbnailSource, Thumbnail)>,
+}
+
+impl Thumbnails {
+    fn lookup(&mut self, key: ThumbnailKey, source: ThumbnailSource) -> Option<imgui::TextureId> {
+        let (prev, thumbnail) = self
+            .cache
+            .entry(key)
+            .or_insert_with(|| (source.clone(), Thumbnail::Requested));
+        if *prev != source {
+            *prev = source;
+            *thumbnail = match thumbnail {
+                Thumbnail::Available(id) | Thumbnail::Outdated(id) => Thumbnail::Outdated(*id),
+                Thumbnail::Requested | Thumbnail::Unavailable => Thumbnail::Requested,
+            };
+        }
+
+        match thumbnail {
+            Thumbnail::Available(id) | Thumbnail::Outdated(id) => Some(*id),
+            Thumbnail::Requested | Thumbnail::Unavailable => None,
+        }
+    }
+
+    pub fn get(&mut self, path: &Path) -> Option<imgui::TextureId> {
+        self.lookup(
+            ThumbnailKey::Asset(path.to_path_buf()),
+            ThumbnailSource::Asset(path.to_path_buf()),
+        )
+    }
+
+    /// The thumbnail of a material of the MaterialLibrary
+    pub fn material(
+        &mut self,
+        id: MaterialId,
+        material: &PhysicallyBased,
+    ) -> Option<imgui::TextureId> {
+        self.lookup(
+            ThumbnailKey::Material(id),
+            ThumbnailSource::material(material),
+        )
+    }
+
+    /// The thumbnails to generate, with the texture id of the previous thumbnail if it is outdated
+    pub(crate) fn requested(
+        &self,
+    ) -> Vec<(ThumbnailKey, ThumbnailSource, Option<imgui::TextureId>)> {
+        self.cache
+            .iter()
+            .filter_map(|(key, (source, thumbnail))| match thumbnail {
+                Thumbnail::Requested => Some((key.clone(), source.clone(), None)),
+                Thumbnail::Outdated(id) => Some((key.clone(), source.clone(), Some(*id))),
+                Thumbnail::Available(_) | Thumbnail::Unavailable => None,
+            })
+            .collect()
+    }
+
+    pub(crate) fn set(&mut self, key: ThumbnailKey, id: Option<imgui::TextureId>) {
+        let thumbnail = match id {
+            Some(id) => Thumbnail::Available(id),
+            None => Thumbnail::Unavailable,
+        };
+        if let Some((_, prev)) = self.cache.get_mut(&key) {
+            *prev = thumbnail;
+        }
+    }
+}
+
+pub fn is_image(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("png") | Some("jpg") | Some("jpeg") | Some("bmp") | Some("tga")
+    )
+}
+
+fn load_image(path: &Path, size: u32) -> Option<image::RgbaImage> {
+    match trekanten::texture::load_image(&path) {
+        Ok(image) => Some(image::imageops::thumbnail(&image, size, size)),
+        Err(e) => {
+            log::warn!("Failed to load {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn image_descriptor(image: image::RgbaImage) -> TextureDescriptor {
+    let extent = Extent2D {
+        width: image.width(),
+        height: image.height(),
+    };
+    TextureDescriptor::from_vec(image.into_raw(), extent, Format::RGBA_SRGB, MipMaps::None)
+}
+
+/// Triangles of a preview that are drawn with the same base color
+struct PreviewPrimitive {
+    first_index: u32,
+    n_indices: u32,
+    color: [f32; 4],
+    /// Index into Preview::images
+    texture: Option<usize>,
+}
+
+/// The geometry of a preview and the base colors of its materials, before they are uploaded
+struct Preview {
+    mesh: geometry::Mesh,
+    bbox: BoundingBox,
+    primitives: Vec<PreviewPrimitive>,
+    images: Vec<image::RgbaImage>,
+}
+
+/// Collects the triangles of the meshes of a preview in one vertex and index buffer
+#[derive(Default)]
+struct PreviewBuilder {
+    vertices: Vec<u8>,
+    n_vertices: u32,
+    indices: Vec<u32>,
+    bbox: Option<BoundingBox>,
+    primitives: Vec<PreviewPrimitive>,
+    images: Vec<image::RgbaImage>,
+}
+
+impl PreviewBuilder {
+    /// Normals and texture coordinates default to zero
+    fn push(
+        &mut self,
+        positions: &[Vec3],
+        normals: Option<&[Vec3]>,
+        tex_coords: Option<&[Vec2]>,
+        indices: &[u32],
+        color: [f32; 4],
+        texture: Option<usize>,
+    ) {
+        if indices.is_empty() {
+            return;
+        }
+
+        for (i, pos) in positions.iter().enumerate() {
+            let normal = normals.map_or(Vec3::zero(), |n| n[i]);
+            let uv = tex_coords.map_or(Vec2::zero(), |t| t[i]);
+            self.vertices
+                .extend_from_slice(util::as_bytes(&pos.into_array()));
+            self.vertices
+                .extend_from_slice(util::as_bytes(&normal.into_array()));
+            self.vertices
+                .extend_from_slice(util::as_bytes(&uv.into_array()));
+        }
+
+        let bbox = BoundingBox::from_points(indices.iter().map(|&i| positions[i as usize]))
+            .expect("There are indices");
+        match &mut self.bbox {
+            Some(prev) => prev.combine(bbox),
+            None => self.bbox = Some(bbox),
+        }
+
+        self.primitives.push(PreviewPrimitive {
+            first_index: self.indices.len() as u32,
+            n_indices: indices.len() as u32,
+            color,
+            texture,
+        });
+        let base = self.n_vertices;
+        self.indices.extend(indices.iter().map(|&i| base + i));
+        self.n_vertices += positions.len() as u32;
+    }
+
+    fn add_image(&mut self, image: image::RgbaImage) -> usize {
+        self.images.push(image);
+        self.images.len() - 1
+    }
+
+    /// None if no triangles were pushed
+    fn build(self) -> Option<Preview> {
+        let bbox = self.bbox?;
+        let vertices = OwningVertexBufferDescriptor::from_raw(
+            self.vertices,
+            vertex_format(),
+            BufferMutability::Immutable,
+        );
+        let indices =
+            OwningIndexBufferDescriptor::from_vec(self.indices, BufferMutability::Immutable);
+        Some(Preview {
+            mesh: (vertices, indices),
+            bbox,
+            primitives: self.primitives,
+            images: self.images,
+        })
+    }
+}
+
+/// The same as the vertex format of geometry::textured_sphere_mesh without tangents
+fn vertex_format() -> VertexFormat {
+    VertexFormat::builder()
+        .add_attribute(Format::FLOAT3)
+        .add_attribute(Format::FLOAT3)
+        .add_attribute(Format::FLOAT2)
+        .build()
+}
+
+fn gltf_texture(data: &gltf::image::Data) -> Option<image::RgbaImage> {
+    let pixels = match data.format {
+        gltf::image::Format::R8G8B8A8 => data.pixels.clone(),
+        gltf::image::Format::R8G8B8 => data
+            .pixels
+            .chunks_exact(3)
+            .flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2], u8::MAX])
+            .collect(),
+        _ => return None,
+    };
+    let image = image::RgbaImage::from_raw(data.width, data.height, pixels)?;
+    Some(image::imageops::thumbnail(
+        &image,
+        TEXTURE_SIZE,
+        TEXTURE_SIZE,
+    ))
+}
+
+/// The triangles of the meshes in the default scene, or the root nodes if there are no scenes,
+/// with the base color of their materials
+fn gltf_preview(path: &Path) -> Option<Preview> {
+    let (document, buffers, images) = match gltf::import(path) {
+        Ok(imported) => imported,
+        Err(e) => {
+            log::warn!("Failed to load {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    let mut builder = PreviewBuilder::default();
+    // glTF image index to preview image index
+    let mut textures: HashMap<usize, Option<usize>> = HashMap::new();
+    let mut nodes: Vec<(gltf::Node, Mat4)> = match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(scene) => scene.nodes().map(|n| (n, Mat4::identity())).collect(),
+        None => crate::asset::gltf::root_nodes(&document)
+            .into_iter()
+            .map(|n| (n, Mat4::identity()))
+            .collect(),
+    };
+
+    while let Some((node, parent)) = nodes.pop() {
+        let mtx = parent * Mat4::from_col_arrays(node.transform().matrix());
+        nodes.extend(node.children().map(|child| (child, mtx)));
+        let mesh = match node.mesh() {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+
+        let normal_mtx = mtx.inverted().transposed();
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let positions: Vec<Vec3> = match reader.read_positions() {
+                Some(positions) => positions
+                    .map(|p| (mtx * Vec4::from_point(Vec3::from(p))).xyz())
+                    .collect(),
+                None => continue,
+            };
+            let normals: Option<Vec<Vec3>> = reader.read_normals().map(|normals| {
+                normals
+                    .map(|n| (normal_mtx * Vec4::from_direction(Vec3::from(n))).xyz())
+                    .collect()
+            });
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+
+            let pbr = primitive.material().pbr_metallic_roughness();
+            let texture = pbr.base_color_texture().and_then(|info| {
+                let source = info.texture().source().index();
+                let tex_coords: Vec<Vec2> = reader
+                    .read_tex_coords(info.tex_coord())?
+                    .into_f32()
+                    .map(Vec2::from)
+                    .collect();
+                let texture = *textures.entry(source).or_insert_with(|| {
+                    gltf_texture(&images[source]).map(|image| builder.add_image(image))
+                });
+                Some((tex_coords, texture?))
+            });
+
+            builder.push(
+                &positions,
+                normals.as_deref(),
+                texture
+                    .as_ref()
+                    .map(|(tex_coords, _)| tex_coords.as_slice()),
+                &indices,
+                pbr.base_color_factor(),
+                texture.map(|(_, texture)| texture),
+            );
+        }
+    }
+
+    builder.build()
+}
+
+fn mesh_file_preview(path: &Path) -> Option<Preview> {
+    let (positions, normals, indices) = match crate::asset::mesh_file::read_triangles(path) {
+        Ok(triangles) => triangles,
+        Err(e) => {
+            log::warn!("Failed to load {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    let mut builder = PreviewBuilder::default();
+    builder.push(&positions, Some(&normals), None, &indices, [1.0; 4], None);
+    builder.build()
+}
+
+fn material_preview(base_color_factor: [f32; 4], base_color_texture: Option<&Path>) -> Preview {
+    let options = PrimitiveOptions {
+        uv_mapping: None,
+        subdivisions: 2,
+        tangents: false,
+    };
+    let mesh = geometry::textured_sphere_mesh(1.0, &options);
+    let n_indices = mesh.1.n_elems();
+    let images: Vec<image::RgbaImage> = base_color_texture
+        .and_then(|path| load_image(path, TEXTURE_SIZE))
+        .into_iter()
+        .collect();
+    Preview {
+        mesh,
+        bbox: BoundingBox {
+            min: Vec3::broadcast(-1.0),
+            max: Vec3::broadcast(1.0),
+        },
+        primitives: vec![PreviewPrimitive {
+            first_index: 0,
+            n_indices,
+            color: base_color_factor,
+            texture: if images.is_empty() { None } else { Some(0) },
+        }],
+        images,
+    }
+}
+
+fn preview(source: &ThumbnailSource) -> Option<Preview> {
+    match source {
+        ThumbnailSource::Asset(path) => match path.extension().and_then(|e| e.to_str()) {
+            Some("gltf") | Some("glb") => gltf_preview(path),
+            _ if crate::asset::mesh_file::is_mesh_file(path) => mesh_file_preview(path),
+            _ => None,
+        },
+        ThumbnailSource::Material {
+            base_color_factor,
+            base_color_texture,
+        } => Some(material_preview(
+            *base_color_factor,
+            base_color_texture.as_deref(),
+        )),
+    }
+}
+
+/// The camera that frames the bounding sphere of the preview and the direction towards the light,
+/// which is at the camera
+fn view(bbox: &BoundingBox) -> (Mat4, Vec3) {
+    let sphere = bbox.bounding_sphere();
+    let radius = sphere.radius.max(0.001);
+    let view_dir = Vec3::from(VIEW_DIRECTION).normalized();
+    let distance = radius / (FOV_Y * 0.5).sin();
+    let pos = sphere.center - view_dir * distance;
+
+    let view = crate::math::look_to(pos, view_dir, Vec3::unit_y());
+    let near = (distance - radius).max(distance * 0.01);
+    let proj = crate::math::perspective_vk(FOV_Y, 1.0, near, distance + radius);
+    (proj * view, -view_dir)
+}
+
+/// A texture that thumbnails are rendered to, it is reused when the thumbnail is rendered again
+struct Target {
+    texture: Handle<Texture>,
+    render_target: Handle<RenderTarget>,
+}
+
+struct Draw {
+    first_index: u32,
+    n_indices: u32,
+    color: [f32; 4],
+    descriptor_set: Handle<DescriptorSet>,
+}
+
+/// A thumbnail that is rendered in the next frame, with the resources that are destroyed after it
+struct PendingThumbnail {
+    render_target: Handle<RenderTarget>,
+    vertex_buffer: BufferHandle<VertexBuffer>,
+    index_buffer: BufferHandle<IndexBuffer>,
+    textures: Vec<Handle<Texture>>,
+    descriptor_sets: Vec<Handle<DescriptorSet>>,
+    draws: Vec<Draw>,
+    view_proj: Mat4,
+    to_light: Vec3,
+}
+
+/// Renders the previews of glTF and mesh files and materials to the textures of their thumbnails.
+/// The thumbnails are rendered once, in the frame after they were requested.
+pub(super) struct ThumbnailRenderer {
+    render_pass: Handle<RenderPass>,
+    pipeline: Handle<GraphicsPipeline>,
+    // For the previews without a base color texture
+    _white: Handle<Texture>,
+    white_descriptor_set: Handle<DescriptorSet>,
+    targets: HashMap<ThumbnailKey, Target>,
+    pending: Vec<PendingThumbnail>,
+    recorded: AtomicBool,
+}
+
+impl ThumbnailRenderer {
+    pub fn new(
+        shader_compiler: &pipeline::ShaderCompiler,
+        renderer: &mut Renderer,
+    ) -> Result<Self, MaterialError> {
+        let msaa_sample_count = renderer
+            .supported_msaa_sample_counts()
+            .into_iter()
+            .filter(|&n| n <= 4)
+            .max()
+            .unwrap_or(1);
+        let render_pass = renderer
+            .offscreen_render_pass(Format::RGBA_SRGB, msaa_sample_count)
+            .expect("Failed to create thumbnail render pass");
+
+        let vert = shader_compiler.compile(
+            &pipeline::Defines::empty(),
+            "thumbnail/vert.glsl",
+            pipeline::ShaderType::Vertex,
+        )?;
+        let frag = shader_compiler.compile(
+            &pipeline::Defines::empty(),
+            "thumbnail/frag.glsl",
+            pipeline::ShaderType::Fragment,
+        )?;
+        let desc = GraphicsPipelineDescriptor::builder()
+            .vert(ShaderDescriptor::FromRawSpirv(vert.data()))
+            .frag(ShaderDescriptor::FromRawSpirv(frag.data()))
+            .vertex_format(vertex_format())
+            .culling(TriangleCulling::None)
+            .build()?;
+        let pipeline = renderer.create_gfx_pipeline(desc, &render_pass)?;
+
+        let white = image::RgbaImage::from_pixel(1, 1, image::Rgba([u8::MAX; 4]));
+        let white = renderer
+            .create_texture(image_descriptor(white))
+            .expect("Failed to create thumbnail texture");
+        let white_descriptor_set = DescriptorSet::builder(renderer)
+            .add_texture(&white, 0, ShaderStage::FRAGMENT, false)
+            .build();
+
+        Ok(Self {
+            render_pass,
+            pipeline,
+            _white: white,
+            white_descriptor_set,
+            targets: HashMap::new(),
+            pending: Vec::new(),
+            recorded: AtomicBool::new(false),
+        })
+    }
+
+    /// Destroys the resources of the thumbnails that were recorded in the last frame. Has to be
+    /// called before the thumbnails of this frame are generated.
+    pub fn release_recorded(&mut self, renderer: &mut Renderer) {
+        if !self.recorded.load(Ordering::Relaxed) {
+            return;
+        }
+
+        for thumbnail in self.pending.drain(..) {
+            renderer.destroy_deferred(thumbnail.vertex_buffer);
+            renderer.destroy_deferred(thumbnail.index_buffer);
+            for texture in thumbnail.textures {
+                renderer.destroy_deferred(texture);
+            }
+            for descriptor_set in thumbnail.descriptor_sets {
+                renderer.destroy_deferred(descriptor_set);
+            }
+        }
+    }
+
+    /// Creates the texture of the thumbnail. Image files are downscaled, the other sources are
+    /// rendered to it in the next frame. None if the source has no thumbnail.
+    pub fn generate(
+        &mut self,
+        renderer: &mut Renderer,
+        key: &ThumbnailKey,
+        source: &ThumbnailSource,
+    ) -> Result<Option<Handle<Texture>>, trekanten::RenderError> {
+        if let ThumbnailSource::Asset(path) = source {
+            if is_image(path) {
+                let image = match load_image(path, THUMBNAIL_SIZE) {
+                    Some(image) => image,
+                    None => return Ok(None),
+                };
+                return Ok(Some(renderer.create_texture(image_descriptor(image))?));
+            }
+        }
+
+        let preview = match preview(source) {
+            Some(preview) => preview,
+            None => return Ok(None),
+        };
+
+        let (texture, render_target) = match self.targets.get(key) {
+            Some(target) => (target.texture, target.render_target),
+            None => {
+                let texture = renderer.create_texture(TextureDescriptor::Empty {
+                    extent: EXTENT,
+                    format: Format::RGBA_SRGB,
+                    usage: TextureUsage::COLOR_ATTACHMENT,
+                    sampler: super::post::sampler(),
+                })?;
+                let render_target =
+                    renderer.create_offscreen_render_target(&self.render_pass, &texture)?;
+                self.targets.insert(
+                    key.clone(),
+                    Target {
+                        texture,
+                        render_target,
+                    },
+                );
+                (texture, render_target)
+            }
+        };
+
+        let (vertices, indices) = preview.mesh;
+        let vertex_buffer = renderer.create_resource_blocking(vertices)?;
+        let index_buffer = renderer.create_resource_blocking(indices)?;
+        let mut textures = Vec::new();
+        let mut descriptor_sets = Vec::new();
+        for image in preview.images {
+            let texture = renderer.create_texture(image_descriptor(image))?;
+            let descriptor_set = DescriptorSet::builder(renderer)
+                .add_texture(&texture, 0, ShaderStage::FRAGMENT, false)
+                .build();
+            textures.push(texture);
+            descriptor_sets.push(descriptor_set);
+        }
+
+        let draws = preview
+            .primitives
+            .iter()
+            .map(|primitive| Draw {
+                first_index: primitive.first_index,
+                n_indices: primitive.n_indices,
+                color: primitive.color,
+                descriptor_set: primitive
+                    .texture
+                    .map_or(self.white_descriptor_set, |i| descriptor_sets[i]),
+            })
+            .collect();
+
+        let (view_proj, to_light) = view(&preview.bbox);
+        self.pending.push(PendingThumbnail {
+            render_target,
+            vertex_buffer,
+            index_buffer,
+            textures,
+            descriptor_sets,
+            draws,
+            view_proj,
+            to_light,
+        });
+        self.recorded.store(false, Ordering::Relaxed);
+        Ok(Some(texture))
+    }
+
+    /// Renders the thumbnails that were generated since the last frame. Has to be recorded before
+    /// the ui is drawn.
+    pub fn record(
+        &self,
+        frame: &Frame,
+        mut cmd_buffer: CommandBuffer,
+    ) -> Result<CommandBuffer, trekanten::RenderError> {
+        if self.recorded.swap(true, Ordering::Relaxed) {
+            return Ok(cmd_buffer);
+        }
+
+        // Transparent, so that the ui is seen around the preview
+        let clear_values = [
+            raw_vk::ClearValue {
+                color: raw_vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 0.0],
+                },
+            },
+            raw_vk::ClearValue {
+                depth_stencil: raw_vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        for thumbnail in self.pending.iter() {
+            let mut pass = frame.begin_render_pass(
+                cmd_buffer,
+                &self.render_pass,
+                &thumbnail.render_target,
+                EXTENT,
+                &clear_values,
+            )?;
+            pass.bind_graphics_pipeline(&self.pipeline)
+                .bind_vertex_buffer(&thumbnail.vertex_buffer)
+                .bind_index_buffer(&thumbnail.index_buffer);
+            for draw in thumbnail.draws.iter() {
+                let object = uniform::ThumbnailObject {
+                    view_proj: thumbnail.view_proj.into_col_array(),
+                    color: draw.color,
+                    to_light: Vec4::from_direction(thumbnail.to_light).into_array(),
+                };
+                pass.bind_shader_resource_group(0u32, &draw.descriptor_set, &self.pipeline)
+                    .bind_push_constant(&self.pipeline, ShaderStage::VERTEX, &object)
+                    .draw_indexed(
+                        draw.n_indices,
+                        thumbnail.index_buffer.idx() + draw.first_index,
+                        thumbnail.vertex_buffer.idx() as i32,
+                    );
+            }
+            cmd_buffer = pass.end()?;
+        }
+        Ok(cmd_buffer)
+    }
+}
@@ -20,7 +20,9 @@ use trekanten::{BufferHandle, Handle};
 use crate::common::Name;
 use crate::io::input;
 use crate::io::input::KeyCode;
+use crate::render::inspector::RenderTargetInspector;
 use crate::render::pipeline::{Defines, ShaderCompiler, ShaderType};
+use crate::render::thumbnail::{ThumbnailRenderer, Thumbnails};
 use crate::time::Time;
 
 use specs::world::WorldExt;
@@ -29,6 +31,7 @@ use specs::World;
 use imgui::im_str;
 
 use std::borrow::BorrowMut;
+use std::collections::HashSet;
 use std::path::Path;
 
 struct ImGuiVertex {
@@ -59,16 +62,36 @@ pub type UIModules = Vec<Box<dyn UIModule>>;
 
 pub type UiStateStorage = std::cell::RefCell<polymap::PolyMap<String>>;
 
+/// Settings of the ui that can be changed at runtime, see crate::cvar
+pub struct UiSettings {
+    /// Scales the text, on top of the scale factor of the monitor
+    pub scale: f32,
+}
+
+impl Default for UiSettings {
+    fn default() -> Self {
+        Self { scale: 1.0 }
+    }
+}
+
 /// The main ui context. Holds gpu resource pointers and imgui context. Should live as long as application
 pub struct UIContext {
     imgui: imgui::Context,
     _font_texture: Handle<Texture>,
     pipeline: Handle<GraphicsPipeline>,
-    desc_set: Handle<DescriptorSet>,
+    /// Used for the textures in depth_textures, shows the depth as grayscale
+    depth_pipeline: Handle<GraphicsPipeline>,
+    font_desc_set: Handle<DescriptorSet>,
+    /// Descriptor sets for all textures that can be used in imgui widgets, including the font atlas
+    textures: imgui::Textures<Handle<DescriptorSet>>,
+    depth_textures: HashSet<imgui::TextureId>,
+    _thumbnail_textures: Vec<Handle<Texture>>,
     input_entity: specs::Entity,
     per_frame_data: Option<PerFrameData>,
     storage: UiStateStorage,
     modules: UIModules,
+    /// The scale factor of the monitor
+    scale_factor: f64,
 }
 
 /// The data for one frame of the ui. Ui modules get this and register ui draw calls
@@ -195,6 +218,11 @@ impl UIContext {
         self.imgui.io_mut().display_size = [extent.width as f32, extent.height as f32];
     }
 
+    /// The ui is drawn in physical pixels, so the text is scaled up on high DPI monitors
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.scale_factor = scale_factor;
+    }
+
     fn create_input_context(
         wants_mouse: bool,
         wants_keyboard: bool,
@@ -301,39 +329,27 @@ impl UIContext {
             .build()
     }
 
-    pub fn new(renderer: &mut Renderer, world: &mut World, modules: UIModules) -> Self {
-        log::trace!("Setup ui resources");
-
-        let mut imgui_ctx = Self::init_imgui_ctx();
-
-        let font_texture = {
-            let mut fonts = imgui_ctx.fonts();
-            let atlas_texture = fonts.build_rgba32_texture();
-
-            // We get borrowed data from imgui for the texture so we need a copy here
-            // TODO: This is a use case for supporting borrowed data in a synchronous api
-            let tex_desc = TextureDescriptor::from_vec(
-                atlas_texture.data.to_owned(),
-                Extent2D {
-                    width: atlas_texture.width,
-                    height: atlas_texture.height,
-                },
-                Format::RGBA_UNORM,
-                MipMaps::None,
-            );
-            renderer
-                .create_texture(tex_desc)
-                .expect("Failed to create font texture")
-        };
-
+    fn create_pipeline(
+        world: &World,
+        renderer: &mut Renderer,
+        depth: bool,
+    ) -> Handle<GraphicsPipeline> {
         let (vert, frag) = {
             let compiler = world.read_resource::<ShaderCompiler>();
             let defines = Defines::default();
             let vert = compiler
                 .compile(&defines, Path::new("imgui/vert.glsl"), ShaderType::Vertex)
                 .expect("Failed to compile imgui vert");
+            let mut frag_defines = Defines::default();
+            if depth {
+                frag_defines.push((String::from("DEPTH_TEXTURE"), String::from("1")));
+            }
             let frag = compiler
-                .compile(&defines, Path::new("imgui/frag.glsl"), ShaderType::Fragment)
+                .compile(
+                    &frag_defines,
+                    Path::new("imgui/frag.glsl"),
+                    ShaderType::Fragment,
+                )
                 .expect("Failed to compile imgui frag");
             (vert, frag)
         };
@@ -350,28 +366,83 @@ impl UIContext {
             .build()
             .expect("Failed to build graphics pipeline descriptor");
 
-        let pipeline = {
-            let render_pass = &world.read_resource::<super::FrameData>().main_render_pass;
-            renderer
-                .create_gfx_pipeline(pipeline_descriptor, &render_pass)
-                .expect("Failed to create graphics pipeline")
-        };
+        let render_pass = &world.read_resource::<super::FrameData>().main_render_pass;
+        renderer
+            .create_gfx_pipeline(pipeline_descriptor, &render_pass)
+            .expect("Failed to create graphics pipeline")
+    }
 
-        let desc_set = DescriptorSet::builder(renderer)
+    /// The pipelines depend on the main render pass, so they have to be recreated along with it
+    pub fn recreate_pipeline(&mut self, world: &World, renderer: &mut Renderer) {
+        let pipeline = Self::create_pipeline(world, renderer, false);
+        let depth_pipeline = Self::create_pipeline(world, renderer, true);
+        renderer.destroy_deferred(std::mem::replace(&mut self.pipeline, pipeline));
+        renderer.destroy_deferred(std::mem::replace(&mut self.depth_pipeline, depth_pipeline));
+    }
+
+    fn create_font_texture(
+        imgui_ctx: &mut imgui::Context,
+        renderer: &mut Renderer,
+    ) -> Handle<Texture> {
+        let mut fonts = imgui_ctx.fonts();
+        let atlas_texture = fonts.build_rgba32_texture();
+
+        // We get borrowed data from imgui for the texture so we need a copy here
+        // TODO: This is a use case for supporting borrowed data in a synchronous api
+        let tex_desc = TextureDescriptor::from_vec(
+            atlas_texture.data.to_owned(),
+            Extent2D {
+                width: atlas_texture.width,
+                height: atlas_texture.height,
+            },
+            Format::RGBA_UNORM,
+            MipMaps::None,
+        );
+        renderer
+            .create_texture(tex_desc)
+            .expect("Failed to create font texture")
+    }
+
+    pub fn new(renderer: &mut Renderer, world: &mut World, modules: UIModules) -> Self {
+        log::trace!("Setup ui resources");
+
+        let mut imgui_ctx = Self::init_imgui_ctx();
+        let font_texture = Self::create_font_texture(&mut imgui_ctx, renderer);
+
+        let pipeline = Self::create_pipeline(world, renderer, false);
+        let depth_pipeline = Self::create_pipeline(world, renderer, true);
+
+        let font_desc_set = DescriptorSet::builder(renderer)
             .add_texture(&font_texture, 0, ShaderStage::FRAGMENT, false)
             .build();
+        let mut textures = imgui::Textures::new();
+        imgui_ctx.fonts().tex_id = textures.insert(font_desc_set);
 
         let input_entity = Self::init_entity(world);
+        world.insert(Thumbnails::default());
+        world.insert(UiSettings::default());
+        crate::cvar::register(
+            world,
+            "ui.scale",
+            "Scales the text of the ui",
+            Some((0.5, 4.0)),
+            |s: &mut UiSettings| &mut s.scale,
+        );
 
         let mut ui_ctx = UIContext {
             imgui: imgui_ctx,
             pipeline,
-            desc_set,
+            depth_pipeline,
+            font_desc_set,
+            textures,
+            depth_textures: HashSet::new(),
+            _thumbnail_textures: Vec::new(),
             _font_texture: font_texture,
             input_entity,
             per_frame_data: None,
             modules,
             storage: std::cell::RefCell::new(polymap::PolyMap::default()),
+            scale_factor: 1.0,
         };
 
         ui_ctx.resize(renderer.swapchain_extent());
@@ -381,11 +452,77 @@ impl UIContext {
         ui_ctx
     }
 
+    /// Creates the gpu resources again after the device was lost with them, see
+    /// render::recreate_device. The thumbnails are generated again when they are shown.
+    pub fn recreate_gpu_resources(&mut self, world: &mut World, renderer: &mut Renderer) {
+        self._font_texture = Self::create_font_texture(&mut self.imgui, renderer);
+        self.recreate_pipeline(world, renderer);
+        self.font_desc_set = DescriptorSet::builder(renderer)
+            .add_texture(&self._font_texture, 0, ShaderStage::FRAGMENT, false)
+            .build();
+        self.textures = imgui::Textures::new();
+        self.imgui.fonts().tex_id = self.textures.insert(self.font_desc_set);
+        self.depth_textures.clear();
+        self._thumbnail_textures.clear();
+        self.per_frame_data = None;
+        world.insert(Thumbnails::default());
+    }
+
+    /// Create the textures for the thumbnails that were requested during the last frame. The
+    /// rendered thumbnails are recorded in this frame, see ThumbnailRenderer::record.
+    pub(super) fn generate_thumbnails(
+        &mut self,
+        world: &World,
+        renderer: &mut Renderer,
+        thumbnail_renderer: &mut ThumbnailRenderer,
+    ) {
+        let mut thumbnails = world.write_resource::<Thumbnails>();
+        for (key, source, prev) in thumbnails.requested() {
+            let texture = match thumbnail_renderer.generate(renderer, &key, &source) {
+                Ok(texture) => texture,
+                Err(e) => {
+                    log::error!("Failed to create thumbnail for {:?}: {}", key, e);
+                    None
+                }
+            };
+            // Outdated thumbnails are rendered to the same texture again
+            let id = match (texture, prev) {
+                (Some(_), Some(prev)) => Some(prev),
+                (Some(texture), None) => {
+                    let desc_set = DescriptorSet::builder(renderer)
+                        .add_texture(&texture, 0, ShaderStage::FRAGMENT, false)
+                        .build();
+                    self._thumbnail_textures.push(texture);
+                    Some(self.textures.insert(desc_set))
+                }
+                (None, _) => None,
+            };
+            thumbnails.set(key, id);
+        }
+    }
+
+    /// Create the texture ids for the render targets that were registered since the last frame
+    pub fn register_inspected_targets(&mut self, world: &World, renderer: &mut Renderer) {
+        let mut inspector = world.write_resource::<RenderTargetInspector>();
+        for (idx, texture, depth) in inspector.unregistered() {
+            let desc_set = DescriptorSet::builder(renderer)
+                .add_texture(&texture, 0, ShaderStage::FRAGMENT, depth)
+                .build();
+            let id = self.textures.insert(desc_set);
+            if depth {
+                self.depth_textures.insert(id);
+            }
+            inspector.set_texture_id(idx, id);
+        }
+    }
+
     pub fn pre_frame(&mut self, world: &World) {
         let dt = world.read_resource::<Time>().delta_sim();
         self.imgui
             .io_mut()
             .update_delta_time(std::time::Duration::from(dt));
+        self.imgui.io_mut().font_global_scale =
+            self.scale_factor as f32 * world.read_resource::<UiSettings>().scale;
 
         let mouse = self.imgui.io().want_capture_mouse;
         let keyboard = self.imgui.io().want_capture_keyboard;
@@ -518,9 +655,9 @@ impl UIContext {
                         cmd_params:
                             DrawCmdParams {
                                 clip_rect,
+                                texture_id,
                                 vtx_offset,
                                 idx_offset,
-                                ..
                             },
                     } => {
                         // Project scissor/clipping rectangles into framebuffer space
@@ -556,8 +693,18 @@ impl UIContext {
                                 },
                             };
 
+                            let desc_set = match self.textures.get(texture_id) {
+                                Some(desc_set) => *desc_set,
+                                None => {
+                                    log::error!("Unknown imgui texture id: {:?}", texture_id);
+                                    self.font_desc_set
+                                }
+                            };
+
                             commands.push(UIDrawCommand {
                                 scissor,
+                                desc_set,
+                                depth: self.depth_textures.contains(&texture_id),
                                 vertices_idx: (vtx_offset + global_vertices_idx) as i32,
                                 indices_idx: (idx_offset + global_indices_idx) as u32,
                                 count: count as u32,
@@ -621,7 +768,7 @@ impl UIContext {
         Some(UIDrawCommands {
             per_frame_data,
             pipeline: self.pipeline,
-            desc_set: self.desc_set,
+            depth_pipeline: self.depth_pipeline,
             vertex_shader_data,
             commands,
         })
@@ -631,6 +778,8 @@ impl UIContext {
 #[derive(Debug)]
 struct UIDrawCommand {
     scissor: Rect2D,
+    desc_set: Handle<DescriptorSet>,
+    depth: bool,
     vertices_idx: i32,
     indices_idx: u32,
     count: u32,
@@ -640,7 +789,7 @@ struct UIDrawCommand {
 pub struct UIDrawCommands {
     per_frame_data: PerFrameData,
     pipeline: Handle<GraphicsPipeline>,
-    desc_set: Handle<DescriptorSet>,
+    depth_pipeline: Handle<GraphicsPipeline>,
     vertex_shader_data: VertexShaderData,
     commands: Vec<UIDrawCommand>,
 }
@@ -663,7 +812,7 @@ impl UIDrawCommands {
                     fb_height,
                 },
             pipeline,
-            desc_set,
+            depth_pipeline,
             vertex_shader_data,
             commands,
         } = self;
@@ -682,10 +831,23 @@ impl UIDrawCommands {
             .bind_graphics_pipeline(&pipeline)
             .bind_index_buffer(&index_buffer)
             .bind_vertex_buffer(&vertex_buffer)
-            .bind_shader_resource_group(0, &desc_set, &pipeline)
             .bind_push_constant(&pipeline, ShaderStage::VERTEX, &vertex_shader_data);
 
+        // Only bind the pipeline and descriptor set if we need to
+        let mut prev_depth = false;
+        let mut prev_desc_set: Option<Handle<DescriptorSet>> = None;
         for cmd in commands.iter() {
+            let cmd_pipeline = if cmd.depth { depth_pipeline } else { pipeline };
+            if prev_depth != cmd.depth {
+                cmd_buf
+                    .bind_graphics_pipeline(&cmd_pipeline)
+                    .bind_push_constant(&cmd_pipeline, ShaderStage::VERTEX, &vertex_shader_data);
+                prev_depth = cmd.depth;
+            }
+            if prev_desc_set != Some(cmd.desc_set) {
+                cmd_buf.bind_shader_resource_group(0, &cmd.desc_set, &cmd_pipeline);
+                prev_desc_set = Some(cmd.desc_set);
+            }
             cmd_buf.set_scissor(cmd.scissor).draw_indexed(
                 cmd.count,
                 cmd.indices_idx,
@@ -5,14 +5,20 @@ pub trait UniformBlock {
     const BINDING: u32;
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 #[repr(C, packed)]
 pub struct PBRMaterialData {
     pub base_color_factor: [f32; 4],
     pub metallic_factor: f32,
     pub roughness_factor: f32,
     pub normal_scale: f32,
-    pub _padding: f32,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 4],
+    /// .x is the transmission, .y the index of refraction
+    pub transmission_ior: [f32; 4],
+    /// The slots of the textures in the bindless array, if the material uses it. [0] is base
+    /// color, metallic roughness, normal and emissive, [1][0] occlusion.
+    pub texture_indices: [[u32; 4]; 2],
 }
 
 impl UniformBlock for PBRMaterialData {
@@ -22,6 +28,9 @@ impl UniformBlock for PBRMaterialData {
 
 impl Uniform for PBRMaterialData {}
 
+/// The set of the bindless texture array in the PBR shaders, see trekanten::bindless
+pub const BINDLESS_TEXTURES_SET: u32 = 4;
+
 #[derive(Copy, Clone, Debug)]
 #[repr(C, packed)]
 pub struct UnlitUniformData {
@@ -34,22 +43,149 @@ impl UniformBlock for UnlitUniformData {
 }
 impl Uniform for UnlitUniformData {}
 
+pub const LIGHT_KIND_DIRECTIONAL: u32 = 0;
+pub const LIGHT_KIND_POINT: u32 = 1;
+pub const LIGHT_KIND_SPOT: u32 = 2;
+/// Set in the shadow index of lights with baked shadows
+pub const BAKED_SHADOW_BIT: u32 = 0x8000_0000;
+
+/// Two f32s as the halves of a u32, same as packHalf2x16 in glsl. Values outside of the f16 range
+/// are clamped.
+pub fn pack_half2(x: f32, y: f32) -> u32 {
+    let half = |v: f32| half::f16::from_f32(v.max(-65504.0).min(65504.0)).to_bits() as u32;
+    half(x) | (half(y) << 16)
+}
+
+pub fn unpack_half2(v: u32) -> [f32; 2] {
+    [
+        half::f16::from_bits(v as u16).to_f32(),
+        half::f16::from_bits((v >> 16) as u16).to_f32(),
+    ]
+}
+
+/// Two f32s in [0, 1] as the halves of a u32, same as packUnorm2x16 in glsl
+pub fn pack_unorm2(x: f32, y: f32) -> u32 {
+    let unorm = |v: f32| (v.max(0.0).min(1.0) * 65535.0).round() as u32;
+    unorm(x) | (unorm(y) << 16)
+}
+
+pub fn unpack_unorm2(v: u32) -> [f32; 2] {
+    [(v & 0xFFFF) as f32 / 65535.0, (v >> 16) as f32 / 65535.0]
+}
+
+/// The scale and offset that map the cosine of the angle to the spot direction to the cone
+/// attenuation. It is 1.0 inside the inner angle and falls off to 0.0 at the outer angle, as
+/// recommended by KHR_lights_punctual. Computed in the shaders from the packed cosines.
+pub fn cone_scale_offset(cos_inner: f32, cos_outer: f32) -> (f32, f32) {
+    let scale = 1.0 / (cos_inner - cos_outer).max(0.001);
+    (scale, -cos_outer * scale)
+}
+
+/// A punctual light, 48 bytes. Direction, color and volumetric density are fp16 and the cosines
+/// of the spot cone angles are unorm16, see the unpack functions in the shaders.
 #[derive(Copy, Clone, Debug)]
 #[repr(C, packed)]
 pub struct PackedLight {
-    pub pos: [f32; 4],         // position for point/spot light
-    pub dir_cutoff: [f32; 4], // direction for spot/directional light. .w is the cos(cutoff_angle) of the spotlight
-    pub color_range: [f32; 4], // color for all light types. .w is the range of point/spot lights
-    pub shadow_idx: [u32; 4],
+    pub pos_range: [f32; 4],        // .w is the range of point/spot lights
+    pub dir_color: [u32; 4],        // halves: dir.xy, dir.z + volumetric density, color.rg, color.b
+    pub cone_shadow_kind: [u32; 4], // .x is cos(outer), cos(inner) as unorm16, .y the shadow index, .z the kind
+}
+
+impl PackedLight {
+    pub fn directional(direction: [f32; 3], color: [f32; 3]) -> Self {
+        Self {
+            pos_range: [0.0; 4],
+            dir_color: Self::pack_dir_color(direction, color, 0.0),
+            cone_shadow_kind: [0, u32::MAX, LIGHT_KIND_DIRECTIONAL, 0],
+        }
+    }
+
+    pub fn point(pos: [f32; 3], color: [f32; 3], range: f32) -> Self {
+        Self {
+            pos_range: [pos[0], pos[1], pos[2], range],
+            dir_color: Self::pack_dir_color([0.0; 3], color, 0.0),
+            cone_shadow_kind: [0, u32::MAX, LIGHT_KIND_POINT, 0],
+        }
+    }
+
+    /// The angles are from the spot direction, in radians
+    pub fn spot(
+        pos: [f32; 3],
+        direction: [f32; 3],
+        color: [f32; 3],
+        range: f32,
+        (inner_angle, outer_angle): (f32, f32),
+    ) -> Self {
+        let cone = pack_unorm2(outer_angle.cos(), inner_angle.cos());
+        Self {
+            pos_range: [pos[0], pos[1], pos[2], range],
+            dir_color: Self::pack_dir_color(direction, color, 0.0),
+            cone_shadow_kind: [cone, u32::MAX, LIGHT_KIND_SPOT, 0],
+        }
+    }
+
+    pub fn with_shadow_idx(mut self, shadow_idx: u32) -> Self {
+        self.cone_shadow_kind[1] = shadow_idx;
+        self
+    }
+
+    /// The shadows are sampled from this channel of the baked shadow mask, see shadow_bake
+    pub fn with_baked_shadow(self, channel: u32) -> Self {
+        self.with_shadow_idx(BAKED_SHADOW_BIT | channel)
+    }
+
+    pub fn with_volumetric_density(mut self, density: f32) -> Self {
+        let dir_z = unpack_half2(self.dir_color[1])[0];
+        self.dir_color[1] = pack_half2(dir_z, density);
+        self
+    }
+
+    fn pack_dir_color(direction: [f32; 3], color: [f32; 3], density: f32) -> [u32; 4] {
+        [
+            pack_half2(direction[0], direction[1]),
+            pack_half2(direction[2], density),
+            pack_half2(color[0], color[1]),
+            pack_half2(color[2], 0.0),
+        ]
+    }
+
+    pub fn kind(&self) -> u32 {
+        self.cone_shadow_kind[2]
+    }
+
+    pub fn shadow_idx(&self) -> u32 {
+        self.cone_shadow_kind[1]
+    }
+
+    pub fn direction(&self) -> [f32; 3] {
+        let [x, y] = unpack_half2(self.dir_color[0]);
+        let [z, _] = unpack_half2(self.dir_color[1]);
+        [x, y, z]
+    }
+
+    pub fn color(&self) -> [f32; 3] {
+        let [r, g] = unpack_half2(self.dir_color[2]);
+        let [b, _] = unpack_half2(self.dir_color[3]);
+        [r, g, b]
+    }
+
+    pub fn volumetric_density(&self) -> f32 {
+        unpack_half2(self.dir_color[1])[1]
+    }
+
+    /// See cone_scale_offset
+    pub fn cone_scale_offset(&self) -> (f32, f32) {
+        let [cos_outer, cos_inner] = unpack_unorm2(self.cone_shadow_kind[0]);
+        cone_scale_offset(cos_inner, cos_outer)
+    }
 }
 
 impl Default for PackedLight {
     fn default() -> Self {
         Self {
-            pos: [0.0; 4],
-            dir_cutoff: [0.0; 4],
-            color_range: [0.0; 4],
-            shadow_idx: [u32::MAX; 4],
+            pos_range: [0.0; 4],
+            dir_color: [0; 4],
+            cone_shadow_kind: [0, u32::MAX, LIGHT_KIND_DIRECTIONAL, 0],
         }
     }
 }
@@ -76,6 +212,11 @@ pub struct LightingData {
     pub punctual_lights: [PackedLight; MAX_NUM_LIGHTS],
     pub ambient: [f32; 4],
     pub num_lights: u32,
+    pub _padding: [u32; 3],
+    /// .xyz is the corner of the baked shadow mask and .w the size of its voxels, see shadow_bake
+    pub shadow_mask_min: [f32; 4],
+    /// The number of voxels along each axis. .w is 0 if there is no mask.
+    pub shadow_mask_dims: [u32; 4],
 }
 
 impl UniformBlock for LightingData {
@@ -84,6 +225,43 @@ impl UniformBlock for LightingData {
 }
 impl Uniform for LightingData {}
 
+pub const MAX_NUM_FOG_VOLUMES: usize = 8;
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C, packed)]
+pub struct PackedFogVolume {
+    pub world_to_local: Mat4, // Maps the volume to the unit cube or the unit sphere
+    pub color_density: [f32; 4],
+    pub shape: [u32; 4], // .x is 0 for boxes and 1 for spheres
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C, packed)]
+pub struct FogData {
+    pub volumes: [PackedFogVolume; MAX_NUM_FOG_VOLUMES],
+    pub num_volumes: u32,
+}
+
+impl UniformBlock for FogData {
+    const SET: u32 = 0;
+    const BINDING: u32 = 4;
+}
+impl Uniform for FogData {}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C, packed)]
+pub struct VolumetricData {
+    pub inv_view_proj: Mat4, // Of the depth pass, maps its depth back to world space
+    pub view_pos: [f32; 4],
+    pub num_samples: [u32; 4], // .x is the number of steps along each view ray
+}
+
+impl UniformBlock for VolumetricData {
+    const SET: u32 = 0;
+    const BINDING: u32 = 0;
+}
+impl Uniform for VolumetricData {}
+
 #[derive(Copy, Clone, Debug)]
 #[repr(C, packed)]
 pub struct Model {
@@ -91,6 +269,46 @@ pub struct Model {
     pub model_it: Mat4,
 }
 
+/// The element type of the object buffer, see render::object_data
+impl UniformBlock for Model {
+    const SET: u32 = 3;
+    const BINDING: u32 = 0;
+}
+impl Uniform for Model {}
+
+/// The push constants of the aov pass, see render::aov
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct AovObject {
+    pub model: Mat4,
+    /// The linear depth is the dot product with the world position, plus w
+    pub depth_plane: [f32; 4],
+    /// .x is the id of the entity + 1, as zero means that nothing was drawn
+    pub entity: [f32; 4],
+}
+
+/// The push constants of the draft view modes, see render::view_mode
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct ViewModeObject {
+    pub model: Mat4,
+    /// The linear depth is the dot product with the world position, plus w
+    pub depth_plane: [f32; 4],
+    pub color: [f32; 4],
+    /// .x is the ViewMode and .y the depth that is shown as white
+    pub params: [f32; 4],
+}
+
+/// The push constants of the thumbnails, see render::thumbnail
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct ThumbnailObject {
+    pub view_proj: Mat4,
+    pub color: [f32; 4],
+    /// The direction towards the light, in the space of the preview
+    pub to_light: [f32; 4],
+}
+
 #[derive(Copy, Clone, Debug, Default)]
 #[repr(C, packed)]
 pub struct ViewData {
@@ -103,3 +321,110 @@ impl UniformBlock for ViewData {
     const BINDING: u32 = 0;
 }
 impl Uniform for ViewData {}
+
+pub const MAX_NUM_JOINTS: usize = 128;
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct JointMatrices {
+    pub matrices: [Mat4; MAX_NUM_JOINTS],
+}
+
+// Read by the deformation pre-pass, see deformation_comp.glsl
+impl UniformBlock for JointMatrices {
+    const SET: u32 = 0;
+    const BINDING: u32 = 0;
+}
+impl Uniform for JointMatrices {}
+
+pub const MAX_NUM_MORPH_TARGETS: usize = 4;
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct MorphWeightData {
+    pub weights: [f32; MAX_NUM_MORPH_TARGETS],
+}
+
+// Read by the deformation pre-pass, see deformation_comp.glsl
+impl UniformBlock for MorphWeightData {
+    const SET: u32 = 0;
+    const BINDING: u32 = 1;
+}
+impl Uniform for MorphWeightData {}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct VirtualTextureData {
+    pub extents: [f32; 4], // .xy is the extent of the texture, .zw the extent of a page
+    pub params: [f32; 4], // .x is the id, .y the number of levels, .z the mip tail and .w the lod bias
+}
+
+impl UniformBlock for VirtualTextureData {
+    const SET: u32 = 1;
+    const BINDING: u32 = 0;
+}
+impl Uniform for VirtualTextureData {}
+
+/// The view and the lights that the path tracer samples, see render::path_trace
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C, packed)]
+pub struct PathTraceData {
+    pub inv_view_proj: Mat4,
+    pub eye: [f32; 4],
+    pub lights: [PackedLight; MAX_NUM_LIGHTS],
+    pub ambient: [f32; 4],
+    pub extent_bounces: [u32; 4], // .xy is the extent of the image, .z the bounces and .w the number of lights
+    pub scene: [u32; 4],          // .x is the number of triangles, nothing is hit if it is 0
+}
+
+impl UniformBlock for PathTraceData {
+    const SET: u32 = 0;
+    const BINDING: u32 = 0;
+}
+impl Uniform for PathTraceData {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vek::approx::assert_abs_diff_eq;
+
+    #[test]
+    fn packed_light_roundtrip() {
+        let (inner, outer) = (0.2f32, 0.4f32);
+        let light = PackedLight::spot(
+            [1.0, 2.0, 3.0],
+            [0.0, -0.6, 0.8],
+            [1.0, 10.0, 400.0],
+            5.0,
+            (inner, outer),
+        )
+        .with_shadow_idx(3)
+        .with_volumetric_density(0.25);
+        assert_eq!(std::mem::size_of::<PackedLight>(), 48);
+        assert_eq!(light.kind(), LIGHT_KIND_SPOT);
+        assert_eq!(light.shadow_idx(), 3);
+        let [x, y, z] = light.direction();
+        assert_abs_diff_eq!(
+            vek::Vec3::new(x, y, z),
+            vek::Vec3::new(0.0, -0.6, 0.8),
+            epsilon = 0.001
+        );
+        let [r, g, b] = light.color();
+        assert_abs_diff_eq!(
+            vek::Vec3::new(r, g, b),
+            vek::Vec3::new(1.0, 10.0, 400.0),
+            epsilon = 0.5
+        );
+        assert_abs_diff_eq!(light.volumetric_density(), 0.25);
+
+        let (scale, offset) = light.cone_scale_offset();
+        let attenuation = |angle: f32| (angle.cos() * scale + offset).max(0.0).min(1.0);
+        assert_abs_diff_eq!(attenuation(inner), 1.0, epsilon = 0.01);
+        assert_abs_diff_eq!(attenuation(outer), 0.0, epsilon = 0.01);
+    }
+
+    #[test]
+    fn half_is_clamped() {
+        assert_eq!(unpack_half2(pack_half2(1.0e6, -1.0e6)), [65504.0, -65504.0]);
+    }
+}
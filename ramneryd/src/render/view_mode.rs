@@ -0,0 +1,337 @@
+//! Draft view modes of the viewport, like those of DCC tools: the meshes are drawn with the color
+//! of their material, their normals, their depth or their edges instead of being lit. The view mode
+//! is a component of the camera, and each mode replaces the pipelines of the entities at draw time,
+//! with a pipeline per vertex format, so the materials are left as they are.
+//!
+//! The bloom, the post effects and the volumetric lighting are skipped in the draft modes, so that
+//! the colors are shown as they are. Only the positions and normals of the meshes are read, after
+//! they were skinned and morphed by the deformation pre-pass, and the meshes without normals (those
+//! with unlit materials) are shown with the normals of their triangles.
+
+use std::collections::HashMap;
+
+use trekanten::descriptor::DescriptorSet;
+use trekanten::mem::{IndexBuffer, VertexBuffer};
+use trekanten::pipeline::{
+    GraphicsPipeline, GraphicsPipelineDescriptor, PolygonMode, ShaderDescriptor, ShaderStage,
+    TriangleCulling,
+};
+use trekanten::resource::Handle;
+use trekanten::vertex::VertexFormat;
+use trekanten::{BufferHandle, RenderPass, RenderPassEncoder, Renderer};
+
+use crate::camera::Camera;
+use crate::ecs::prelude::*;
+use crate::math::ModelMatrix;
+use crate::render::ui::UiFrame;
+
+use super::debug_window::{RenderMode, RenderSettings};
+use super::deformation::GpuDeformation;
+use super::material::{self, GpuMaterial};
+use super::mesh::GpuMesh;
+use super::pipeline::{self, pbr_gltf};
+use super::visibility::{self, Pass, RenderLayer, Visibility};
+use super::{uniform, Hidden, MaterialError, ViewOverride};
+
+const WIREFRAME_COLOR: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+
+/// How the camera shows the scene. The values are the same as in view_mode/frag.glsl.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Component)]
+#[component(storage = "HashMapStorage", inspect)]
+pub enum ViewMode {
+    /// Lit, with the materials
+    Shaded,
+    /// The base color of the materials, without textures
+    Unlit,
+    /// The world space normals, mapped from [-1, 1] to [0, 1]
+    Normals,
+    /// The linear depth, white at RenderSettings::view_mode_depth_range
+    Depth,
+    /// The edges of the triangles
+    Wireframe,
+}
+
+impl Default for ViewMode {
+    fn default() -> Self {
+        Self::Shaded
+    }
+}
+
+impl ViewMode {
+    pub const ALL: [ViewMode; 5] = [
+        ViewMode::Shaded,
+        ViewMode::Unlit,
+        ViewMode::Normals,
+        ViewMode::Depth,
+        ViewMode::Wireframe,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ViewMode::Shaded => "Shaded",
+            ViewMode::Unlit => "Unlit",
+            ViewMode::Normals => "Normals",
+            ViewMode::Depth => "Depth",
+            ViewMode::Wireframe => "Wireframe",
+        }
+    }
+}
+
+/// The view mode of the camera. The wireframe render mode (r.mode) is used when the camera has
+/// none, and the panorama faces are always shaded.
+pub fn current(world: &World) -> ViewMode {
+    if world.has_value::<ViewOverride>() {
+        return ViewMode::Shaded;
+    }
+
+    let from_camera = crate::ecs::find_singleton_entity::<Camera>(world)
+        .and_then(|cam| world.read_storage::<ViewMode>().get(cam).copied());
+    match from_camera {
+        Some(mode) => mode,
+        None => match world.read_resource::<RenderSettings>().render_mode {
+            RenderMode::Opaque => ViewMode::Shaded,
+            RenderMode::Wireframe => ViewMode::Wireframe,
+        },
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    vertex_format: VertexFormat,
+    polygon_mode: PolygonMode,
+    has_normals: bool,
+}
+
+struct DrawItem {
+    pipeline: Handle<GraphicsPipeline>,
+    vertex_buffer: BufferHandle<VertexBuffer>,
+    index_buffer: BufferHandle<IndexBuffer>,
+    object: uniform::ViewModeObject,
+}
+
+#[derive(Default)]
+pub(super) struct ViewModeRenderer {
+    pipelines: HashMap<PipelineKey, Handle<GraphicsPipeline>>,
+    items: Vec<DrawItem>,
+}
+
+impl ViewModeRenderer {
+    /// The pipelines are created for the scene render pass, so they have to be recreated when it is
+    pub fn clear_pipelines(&mut self, renderer: &mut Renderer) {
+        for (_, pipeline) in self.pipelines.drain() {
+            renderer.destroy_deferred(pipeline);
+        }
+        self.items.clear();
+    }
+
+    fn pipeline(
+        &mut self,
+        shader_compiler: &pipeline::ShaderCompiler,
+        renderer: &mut Renderer,
+        scene_render_pass: &Handle<RenderPass>,
+        key: PipelineKey,
+    ) -> Result<Handle<GraphicsPipeline>, MaterialError> {
+        if let Some(pipeline) = self.pipelines.get(&key) {
+            return Ok(*pipeline);
+        }
+
+        let (vert, frag) = pbr_gltf::compile_view_mode(shader_compiler, key.has_normals)?;
+        let desc = GraphicsPipelineDescriptor::builder()
+            .vert(ShaderDescriptor::FromRawSpirv(vert.data()))
+            .frag(ShaderDescriptor::FromRawSpirv(frag.data()))
+            .vertex_format(key.vertex_format.clone())
+            .culling(TriangleCulling::None)
+            .polygon_mode(key.polygon_mode)
+            .build()?;
+        let pipeline = renderer.create_gfx_pipeline(desc, scene_render_pass)?;
+        self.pipelines.insert(key, pipeline);
+        Ok(pipeline)
+    }
+
+    /// Collects the meshes that the camera draws and creates the pipelines for them, if the view
+    /// mode is not ViewMode::Shaded. Has to be called before the frame is started.
+    pub fn prepare(
+        &mut self,
+        world: &World,
+        renderer: &mut Renderer,
+        scene_render_pass: &Handle<RenderPass>,
+        mode: ViewMode,
+    ) {
+        self.items.clear();
+        if mode == ViewMode::Shaded {
+            return;
+        }
+
+        // The linear depth is the negated z of the view space position
+        let (view, _) = super::get_view_data(world);
+        let depth_plane = [-view[(2, 0)], -view[(2, 1)], -view[(2, 2)], -view[(2, 3)]];
+        let depth_range = world
+            .read_resource::<RenderSettings>()
+            .view_mode_depth_range;
+        let params = [mode as u32 as f32, depth_range, 0.0, 0.0];
+
+        let shader_compiler = world.read_resource::<pipeline::ShaderCompiler>();
+        let meshes = world.read_storage::<GpuMesh>();
+        let materials = world.read_storage::<GpuMaterial>();
+        let pbr_materials = world.read_storage::<material::PhysicallyBased>();
+        let unlit_materials = world.read_storage::<material::Unlit>();
+        let model_matrices = world.read_storage::<ModelMatrix>();
+        let hidden = world.read_storage::<Hidden>();
+        let visibilities = world.read_storage::<Visibility>();
+        let layers = world.read_storage::<RenderLayer>();
+        let deformations = world.read_storage::<GpuDeformation>();
+        let camera_layers = visibility::pass_layers(world, Pass::Camera);
+        for (mesh, mat, pbr, unlit, mtx, deformation, visibility, layer, _) in (
+            &meshes,
+            &materials,
+            pbr_materials.maybe(),
+            unlit_materials.maybe(),
+            &model_matrices,
+            deformations.maybe(),
+            visibilities.maybe(),
+            layers.maybe(),
+            !&hidden,
+        )
+            .join()
+        {
+            if !visibility::is_drawn(visibility, layer, Pass::Camera, camera_layers) {
+                continue;
+            }
+
+            let color = match (mode, pbr, unlit) {
+                (ViewMode::Wireframe, _, _) => WIREFRAME_COLOR,
+                (_, Some(pbr), _) => pbr.base_color_factor.into_array(),
+                (_, None, Some(unlit)) => unlit.color.into_array(),
+                (_, None, None) => [1.0; 4],
+            };
+            // Lines and points are drawn as they are
+            let polygon_mode = match (mode, mesh.polygon_mode) {
+                (ViewMode::Wireframe, PolygonMode::Fill) => PolygonMode::Line,
+                (_, polygon_mode) => polygon_mode,
+            };
+            let key = PipelineKey {
+                vertex_format: super::vertex_format(renderer, mesh),
+                polygon_mode,
+                has_normals: matches!(mat, GpuMaterial::PBR { .. }),
+            };
+            let pipeline = match self.pipeline(&shader_compiler, renderer, scene_render_pass, key) {
+                Ok(pipeline) => pipeline,
+                Err(e) => {
+                    log::error!("Failed to create view mode pipeline: {}", e);
+                    continue;
+                }
+            };
+
+            self.items.push(DrawItem {
+                pipeline,
+                vertex_buffer: deformation.map_or(mesh.vertex_buffer, |d| d.vertex_buffer),
+                index_buffer: mesh.index_buffer,
+                object: uniform::ViewModeObject {
+                    model: mtx.0.into_col_array(),
+                    depth_plane,
+                    color,
+                    params,
+                },
+            });
+        }
+    }
+
+    /// Expects the view data to be bound in the shader resource group
+    pub fn record(
+        &self,
+        cmd_buf: &mut RenderPassEncoder,
+        shader_resource_group: &Handle<DescriptorSet>,
+    ) {
+        let mut bound = None;
+        for item in self.items.iter() {
+            if bound != Some(item.pipeline) {
+                cmd_buf
+                    .bind_graphics_pipeline(&item.pipeline)
+                    .bind_shader_resource_group(0u32, shader_resource_group, &item.pipeline);
+                bound = Some(item.pipeline);
+            }
+
+            cmd_buf
+                .bind_vertex_buffer(&item.vertex_buffer)
+                .bind_index_buffer(&item.index_buffer)
+                .bind_push_constant(&item.pipeline, ShaderStage::VERTEX, &item.object)
+                .draw_indexed(
+                    item.index_buffer.n_elems(),
+                    item.index_buffer.idx(),
+                    item.vertex_buffer.idx() as i32,
+                );
+        }
+    }
+}
+
+/// Picks the view mode of the camera
+pub(crate) fn build_ui<'a>(world: &mut World, ui: &UiFrame<'a>, pos: [f32; 2]) -> [f32; 2] {
+    let size = [300.0, 80.0];
+
+    imgui::Window::new(imgui::im_str!("Viewport"))
+        .position(pos, imgui::Condition::FirstUseEver)
+        .size(size, imgui::Condition::FirstUseEver)
+        .collapsed(true, imgui::Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let cam = match crate::ecs::find_singleton_entity::<Camera>(world) {
+                Some(cam) => cam,
+                None => {
+                    ui.inner().text(imgui::im_str!("No camera"));
+                    return;
+                }
+            };
+
+            let current = current(world);
+            let names: Vec<imgui::ImString> = ViewMode::ALL
+                .iter()
+                .map(|mode| imgui::ImString::new(mode.name()))
+                .collect();
+            let items: Vec<&imgui::ImStr> = names.iter().map(|name| name.as_ref()).collect();
+            let mut idx = ViewMode::ALL
+                .iter()
+                .position(|&mode| mode == current)
+                .unwrap_or(0);
+            if imgui::ComboBox::new(imgui::im_str!("View mode")).build_simple_string(
+                ui.inner(),
+                &mut idx,
+                &items,
+            ) {
+                world
+                    .write_storage::<ViewMode>()
+                    .insert(cam, ViewMode::ALL[idx])
+                    .expect("The camera is alive");
+            }
+        });
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn view_mode_of_camera() {
+        let mut world = World::new();
+        crate::ecs::meta::register_all_components(&mut world);
+        world.insert(RenderSettings::default());
+        let cam = world.create_entity().with(Camera::default()).build();
+        assert_eq!(current(&world), ViewMode::Shaded);
+
+        // The render mode is used when the camera has no view mode
+        world.write_resource::<RenderSettings>().render_mode = RenderMode::Wireframe;
+        assert_eq!(current(&world), ViewMode::Wireframe);
+
+        world
+            .write_storage::<ViewMode>()
+            .insert(cam, ViewMode::Normals)
+            .unwrap();
+        assert_eq!(current(&world), ViewMode::Normals);
+
+        world.insert(ViewOverride {
+            view: crate::math::Mat4::identity(),
+            pos: crate::math::Vec3::zero(),
+        });
+        assert_eq!(current(&world), ViewMode::Shaded);
+    }
+}
@@ -0,0 +1,1169 @@
+//! Virtual textures, for images that are too large for the device memory, e.g. of terrain or
+//! texture atlases. The base color texture of an entity with a VirtualTexture is replaced with a
+//! sparse texture, see trekanten::sparse, where only the pages that are seen are resident. Which
+//! pages are seen is found with a feedback pass. It renders the page that each pixel would sample
+//! to a target that is a fraction of the size of the scene, which is read back a few times per
+//! second. The pages are cut from the image on a worker thread and are uploaded before the next
+//! frame. When the resident pages need more memory than the budget of the render settings, the
+//! pages that were seen the longest ago are evicted.
+//!
+//! The worker decodes the whole image and keeps its mip levels in host memory, so the image has to
+//! fit there. The feedback stores the page coordinates in 8 bits each, which limits textures to 256
+//! pages along each side, and the id of the texture in another 8 bits.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use thiserror::Error;
+
+use trekanten::descriptor::DescriptorSet;
+use trekanten::loader::ResourceLoader as _;
+use trekanten::mem::{
+    BufferMutability, IndexBuffer, OwningUniformBufferDescriptor, UniformBuffer, VertexBuffer,
+};
+use trekanten::pipeline::{
+    DepthTest, GraphicsPipeline, GraphicsPipelineDescriptor, ShaderDescriptor, ShaderStage,
+};
+use trekanten::raw_vk;
+use trekanten::resource::Handle;
+use trekanten::sparse::{self, Page, SparseTextureDescriptor};
+use trekanten::texture::{self, SamplerDescriptor, Texture, TextureDescriptor, TextureUsage};
+use trekanten::util::{self, Extent2D};
+use trekanten::vertex::VertexFormat;
+use trekanten::{
+    BufferHandle, CommandBuffer, Destroyable, Frame, RenderPass, RenderTarget, Renderer,
+};
+
+use crate::ecs::prelude::*;
+use crate::math::ModelMatrix;
+
+use super::debug_window::RenderSettings;
+use super::material::{GpuMaterial, PendingMaterial, PhysicallyBased, TextureUse};
+use super::mesh::GpuMesh;
+use super::pipeline::{self, pbr_gltf};
+use super::{uniform, Hidden, MaterialError, MaterialShaders, Pending, ReloadMaterial};
+
+/// The feedback target is this many times smaller than the scene, along each side
+const FEEDBACK_SCALE: u32 = 8;
+const FEEDBACK_INTERVAL: Duration = Duration::from_millis(100);
+const FEEDBACK_FORMAT: util::Format = util::Format::RGBA_UNORM;
+/// Ids are written to 8 bits of the feedback, with zero meaning that nothing was drawn
+const MAX_TEXTURES: usize = 255;
+const MAX_PAGES_PER_SIDE: u32 = 256;
+/// Limits the number of pages that are cut from the images at the same time
+const MAX_PAGES_IN_FLIGHT: usize = 32;
+/// Evicted pages are unbound once the frames in flight are done with them, and are not requested
+/// again until then.
+const EVICTION_FRAMES: u64 = 3;
+
+/// Replaces the base color texture of the physically based material of the entity with a virtual
+/// texture of the image at the path. The material needs a base color texture of its own, for the
+/// texture coordinates. Ignored if the device does not support sparse textures.
+#[derive(Debug, Clone, Default, Component)]
+#[component(inspect)]
+pub struct VirtualTexture {
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Error)]
+enum VirtualTextureError {
+    #[error("Failed to read image: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("Renderer error: {0}")]
+    Render(#[from] trekanten::RenderError),
+    #[error("{0} pages along a side, the feedback can address at most 256")]
+    TooManyPages(u32),
+}
+
+enum Job {
+    Page {
+        id: usize,
+        handle: Handle<Texture>,
+        path: PathBuf,
+        page: Page,
+        offset: util::Offset2D,
+        extent: Extent2D,
+    },
+    /// No texture uses the image anymore
+    Close(PathBuf),
+}
+
+struct PageResult {
+    id: usize,
+    handle: Handle<Texture>,
+    page: Page,
+    texels: Result<Vec<u8>, String>,
+}
+
+/// The mip levels of an image, created when they are first needed
+type MipLevels = Vec<Option<image::RgbaImage>>;
+
+fn read_page(
+    images: &mut HashMap<PathBuf, MipLevels>,
+    path: &Path,
+    level: u32,
+    offset: util::Offset2D,
+    extent: Extent2D,
+) -> Result<Vec<u8>, String> {
+    if !images.contains_key(path) {
+        let image = texture::load_image(&path).map_err(|e| e.to_string())?;
+        images.insert(path.to_path_buf(), vec![Some(image)]);
+    }
+
+    let levels = images.get_mut(path).expect("Inserted above");
+    let level = level as usize;
+    if levels.len() <= level {
+        levels.resize(level + 1, None);
+    }
+    if levels[level].is_none() {
+        let base = levels[0]
+            .as_ref()
+            .expect("The first level is always loaded");
+        let e = texture::mip_extent(
+            Extent2D {
+                width: base.width(),
+                height: base.height(),
+            },
+            level as u32,
+        );
+        levels[level] = Some(image::imageops::resize(
+            base,
+            e.width,
+            e.height,
+            image::imageops::FilterType::Triangle,
+        ));
+    }
+
+    let image = levels[level].as_ref().expect("Created above");
+    let page = image::imageops::crop_imm(
+        image,
+        offset.x as u32,
+        offset.y as u32,
+        extent.width,
+        extent.height,
+    );
+    Ok(page.to_image().into_raw())
+}
+
+fn run_worker(jobs: Receiver<Job>, results: Sender<PageResult>, cancelled: Arc<AtomicBool>) {
+    let mut images = HashMap::new();
+    loop {
+        let job = match jobs.recv() {
+            Ok(job) => job,
+            // The queue is closed
+            Err(_) => return,
+        };
+        if cancelled.load(Ordering::Acquire) {
+            return;
+        }
+
+        match job {
+            Job::Close(path) => {
+                images.remove(&path);
+            }
+            Job::Page {
+                id,
+                handle,
+                path,
+                page,
+                offset,
+                extent,
+            } => {
+                let texels = read_page(&mut images, &path, page.level, offset, extent);
+                let result = PageResult {
+                    id,
+                    handle,
+                    page,
+                    texels,
+                };
+                if results.send(result).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+struct PageLoader {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<PageResult>,
+    worker: Option<JoinHandle<()>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl PageLoader {
+    fn new() -> Self {
+        let (jobs, job_receiver) = unbounded();
+        let (result_sender, results) = unbounded();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let cancelled = Arc::clone(&cancelled);
+            std::thread::Builder::new()
+                .name(String::from("ramneryd::virtual_texture_loader"))
+                .spawn(move || run_worker(job_receiver, result_sender, cancelled))
+                .expect("Failed to start virtual texture loader")
+        };
+
+        Self {
+            jobs: Some(jobs),
+            results,
+            worker: Some(worker),
+            cancelled,
+        }
+    }
+
+    fn send(&self, job: Job) -> bool {
+        self.jobs
+            .as_ref()
+            .map(|jobs| jobs.send(job).is_ok())
+            .unwrap_or(false)
+    }
+}
+
+impl Drop for PageLoader {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Release);
+        self.jobs = None;
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                log::error!("Virtual texture loader panicked");
+            }
+        }
+    }
+}
+
+struct Entry {
+    entity: Entity,
+    path: PathBuf,
+    handle: Handle<Texture>,
+    extent: Extent2D,
+    page_extent: Extent2D,
+    page_size: u64,
+    mip_tail_first_level: u32,
+    descriptor_set: Handle<DescriptorSet>,
+    uniform: BufferHandle<UniformBuffer>,
+    /// The feedback round that each resident page, outside of the mip tail, was last seen in
+    last_seen: HashMap<Page, u64>,
+    loading: HashSet<Page>,
+    /// The frame that each page was evicted in
+    evicted: HashMap<Page, u64>,
+    /// The levels of the mip tail that are left to write before the texture can be sampled
+    tail_remaining: u32,
+    /// The texture has replaced the base color texture of the material
+    swapped_in: bool,
+}
+
+impl Entry {
+    fn new(
+        renderer: &mut Renderer,
+        entity: Entity,
+        path: &Path,
+        id: usize,
+    ) -> Result<Self, VirtualTextureError> {
+        let extent = texture::image_extent(&path)?;
+        let handle = renderer.create_sparse_texture(&SparseTextureDescriptor {
+            extent,
+            format: util::Format::RGBA_SRGB,
+            sampler: SamplerDescriptor::default(),
+        })?;
+        let image = renderer
+            .get_texture(&handle)
+            .and_then(Texture::sparse)
+            .expect("Created above");
+        let page_extent = image.page_extent();
+        let page_size = image.page_size();
+        let mip_levels = image.mip_levels();
+        let mip_tail_first_level = image.mip_tail_first_level();
+        let (x, y) = image.pages_in_level(0);
+        if x.max(y) > MAX_PAGES_PER_SIDE {
+            renderer.destroy_deferred(handle);
+            return Err(VirtualTextureError::TooManyPages(x.max(y)));
+        }
+
+        let data = uniform::VirtualTextureData {
+            extents: [
+                extent.width as f32,
+                extent.height as f32,
+                page_extent.width as f32,
+                page_extent.height as f32,
+            ],
+            params: [
+                id as f32,
+                mip_levels as f32,
+                mip_tail_first_level as f32,
+                -(FEEDBACK_SCALE as f32).log2(),
+            ],
+        };
+        let uniform: BufferHandle<UniformBuffer> = renderer.create_resource_blocking(
+            OwningUniformBufferDescriptor::from_vec(vec![data], BufferMutability::Immutable),
+        )?;
+        let descriptor_set = DescriptorSet::builder(renderer)
+            .add_buffer(&uniform, 0, ShaderStage::FRAGMENT)
+            .build();
+
+        Ok(Self {
+            entity,
+            path: path.to_path_buf(),
+            handle,
+            extent,
+            page_extent,
+            page_size,
+            mip_tail_first_level,
+            descriptor_set,
+            uniform,
+            last_seen: HashMap::new(),
+            loading: HashSet::new(),
+            evicted: HashMap::new(),
+            tail_remaining: mip_levels.saturating_sub(mip_tail_first_level),
+            swapped_in: false,
+        })
+    }
+
+    fn is_ready(&self) -> bool {
+        self.tail_remaining == 0
+    }
+
+    /// Once it has been swapped in, the texture is released with the material instead
+    fn destroy(self, renderer: &mut Renderer, loader: &PageLoader) {
+        renderer.destroy_deferred(self.descriptor_set);
+        renderer.destroy_deferred(self.uniform);
+        if !self.swapped_in {
+            renderer.destroy_deferred(self.handle);
+        }
+        loader.send(Job::Close(self.path));
+    }
+}
+
+fn base_color_handle(material: Option<&GpuMaterial>) -> Option<Handle<Texture>> {
+    match material {
+        Some(GpuMaterial::PBR {
+            base_color_texture: Some(tex),
+            ..
+        }) => Some(tex.handle),
+        _ => None,
+    }
+}
+
+/// The pages that the feedback pass wrote, with the ids of their textures
+fn decode_feedback(data: &[u8]) -> HashSet<(usize, Page)> {
+    data.chunks_exact(4)
+        .filter(|texel| texel[3] > 0)
+        .map(|texel| {
+            let page = Page {
+                level: texel[2] as u32,
+                x: texel[0] as u32,
+                y: texel[1] as u32,
+            };
+            (texel[3] as usize - 1, page)
+        })
+        .collect()
+}
+
+struct DrawItem {
+    pipeline: Handle<GraphicsPipeline>,
+    descriptor_set: Handle<DescriptorSet>,
+    vertex_buffer: BufferHandle<VertexBuffer>,
+    index_buffer: BufferHandle<IndexBuffer>,
+    model: uniform::Model,
+}
+
+fn feedback_render_pass(renderer: &mut Renderer) -> Handle<RenderPass> {
+    let color_attach = raw_vk::AttachmentDescription {
+        format: FEEDBACK_FORMAT.into(),
+        samples: raw_vk::SampleCountFlags::TYPE_1,
+        load_op: raw_vk::AttachmentLoadOp::CLEAR,
+        store_op: raw_vk::AttachmentStoreOp::STORE,
+        stencil_load_op: raw_vk::AttachmentLoadOp::DONT_CARE,
+        stencil_store_op: raw_vk::AttachmentStoreOp::DONT_CARE,
+        initial_layout: raw_vk::ImageLayout::UNDEFINED,
+        // Renderer::read_texture expects this
+        final_layout: raw_vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        flags: raw_vk::AttachmentDescriptionFlags::empty(),
+    };
+    let depth_attach = raw_vk::AttachmentDescription {
+        format: util::Format::D16_UNORM.into(),
+        samples: raw_vk::SampleCountFlags::TYPE_1,
+        load_op: raw_vk::AttachmentLoadOp::CLEAR,
+        store_op: raw_vk::AttachmentStoreOp::DONT_CARE,
+        stencil_load_op: raw_vk::AttachmentLoadOp::DONT_CARE,
+        stencil_store_op: raw_vk::AttachmentStoreOp::DONT_CARE,
+        initial_layout: raw_vk::ImageLayout::UNDEFINED,
+        final_layout: raw_vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        flags: raw_vk::AttachmentDescriptionFlags::empty(),
+    };
+
+    let color_refs = [raw_vk::AttachmentReference {
+        attachment: 0,
+        layout: raw_vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    }];
+    let depth_ref = raw_vk::AttachmentReference {
+        attachment: 1,
+        layout: raw_vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+
+    let subpass = raw_vk::SubpassDescription::builder()
+        .pipeline_bind_point(raw_vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_refs)
+        .depth_stencil_attachment(&depth_ref);
+
+    let deps = [
+        raw_vk::SubpassDependency {
+            // The previous frame might still be writing to the targets
+            src_subpass: raw_vk::SUBPASS_EXTERNAL,
+            src_stage_mask: raw_vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | raw_vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            src_access_mask: raw_vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | raw_vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dst_subpass: 0,
+            dst_stage_mask: raw_vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | raw_vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            dst_access_mask: raw_vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | raw_vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dependency_flags: raw_vk::DependencyFlags::empty(),
+        },
+        raw_vk::SubpassDependency {
+            // The feedback is copied to the host
+            src_subpass: 0,
+            src_stage_mask: raw_vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            src_access_mask: raw_vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_subpass: raw_vk::SUBPASS_EXTERNAL,
+            dst_stage_mask: raw_vk::PipelineStageFlags::TRANSFER,
+            dst_access_mask: raw_vk::AccessFlags::TRANSFER_READ,
+            dependency_flags: raw_vk::DependencyFlags::empty(),
+        },
+    ];
+
+    let attachments = [color_attach, depth_attach];
+    let subpasses = [subpass.build()];
+    let create_info = raw_vk::RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(&subpasses)
+        .dependencies(&deps);
+
+    renderer
+        .create_render_pass(&create_info)
+        .expect("Failed to create virtual texture feedback render pass")
+}
+
+struct FeedbackTargets {
+    extent: Extent2D,
+    color: Handle<Texture>,
+    depth: Handle<Texture>,
+    render_target: Handle<RenderTarget>,
+}
+
+impl FeedbackTargets {
+    fn extent(renderer: &Renderer) -> Extent2D {
+        let extent = renderer.swapchain_extent();
+        Extent2D {
+            width: (extent.width / FEEDBACK_SCALE).max(1),
+            height: (extent.height / FEEDBACK_SCALE).max(1),
+        }
+    }
+
+    fn new(renderer: &mut Renderer, render_pass: &Handle<RenderPass>) -> Self {
+        let extent = Self::extent(renderer);
+        let mut texture = |format, usage| {
+            renderer
+                .create_texture(TextureDescriptor::Empty {
+                    extent,
+                    format,
+                    usage,
+                    sampler: SamplerDescriptor::default(),
+                })
+                .expect("Failed to create virtual texture feedback target")
+        };
+        let color = texture(
+            FEEDBACK_FORMAT,
+            TextureUsage::COLOR_ATTACHMENT | TextureUsage::TRANSFER_SRC,
+        );
+        let depth = texture(
+            util::Format::D16_UNORM,
+            TextureUsage::DEPTH_STENCIL_ATTACHMENT,
+        );
+        let render_target = renderer
+            .create_render_target(render_pass, &[&color, &depth])
+            .expect("Failed to create virtual texture feedback render target");
+
+        Self {
+            extent,
+            color,
+            depth,
+            render_target,
+        }
+    }
+
+    fn destroy(self, renderer: &mut Renderer) -> Result<(), trekanten::RenderError> {
+        renderer.destroy_render_target(self.render_target)?;
+        renderer.destroy_texture(self.color)?;
+        renderer.destroy_texture(self.depth)
+    }
+}
+
+struct FeedbackPass {
+    render_pass: Handle<RenderPass>,
+    targets: FeedbackTargets,
+    pipelines: HashMap<(VertexFormat, pbr_gltf::ShaderDefinition), Handle<GraphicsPipeline>>,
+    items: Vec<DrawItem>,
+}
+
+impl FeedbackPass {
+    fn new(renderer: &mut Renderer) -> Self {
+        let render_pass = feedback_render_pass(renderer);
+        let targets = FeedbackTargets::new(renderer, &render_pass);
+        Self {
+            render_pass,
+            targets,
+            pipelines: HashMap::new(),
+            items: Vec::new(),
+        }
+    }
+
+    fn resize(&mut self, renderer: &mut Renderer) {
+        if self.targets.extent == FeedbackTargets::extent(renderer) {
+            return;
+        }
+
+        let targets = FeedbackTargets::new(renderer, &self.render_pass);
+        let old = std::mem::replace(&mut self.targets, targets);
+        if let Err(e) = old.destroy(renderer) {
+            log::error!("Failed to destroy virtual texture feedback targets: {}", e);
+        }
+    }
+
+    fn pipeline(
+        &mut self,
+        shader_compiler: &pipeline::ShaderCompiler,
+        renderer: &mut Renderer,
+        vertex_format: VertexFormat,
+        def: pbr_gltf::ShaderDefinition,
+    ) -> Result<Handle<GraphicsPipeline>, MaterialError> {
+        let key = (vertex_format, def);
+        if let Some(pipeline) = self.pipelines.get(&key) {
+            return Ok(*pipeline);
+        }
+
+        let (vert, frag) = pbr_gltf::compile_virtual_texture_feedback(shader_compiler, &key.1)?;
+        let desc = GraphicsPipelineDescriptor::builder()
+            .vert(ShaderDescriptor::FromRawSpirv(vert.data()))
+            .frag(ShaderDescriptor::FromRawSpirv(frag.data()))
+            .vertex_format(key.0.clone())
+            .build()?;
+        let pipeline = renderer.create_gfx_pipeline(desc, &self.render_pass)?;
+        self.pipelines.insert(key, pipeline);
+        Ok(pipeline)
+    }
+}
+
+/// The virtual textures of all entities, with the state of their pages
+pub struct VirtualTextures {
+    /// Indexed by the id of the texture
+    entries: Vec<Option<Entry>>,
+    /// The entities that can't have a virtual texture of the path, e.g. as they have no texture
+    /// coordinates, so that they are only reported once
+    skipped: HashMap<Entity, PathBuf>,
+    loader: PageLoader,
+    /// Created when the first virtual texture is
+    feedback: Option<FeedbackPass>,
+    /// The feedback target has been written by a frame
+    has_feedback: bool,
+    feedback_read: Option<Instant>,
+    feedback_round: u64,
+    frame: u64,
+}
+
+impl Default for VirtualTextures {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualTextures {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            skipped: HashMap::new(),
+            loader: PageLoader::new(),
+            feedback: None,
+            has_feedback: false,
+            feedback_read: None,
+            feedback_round: 0,
+            frame: 0,
+        }
+    }
+
+    /// The memory that is bound for the pages of all virtual textures, excluding their mip tails
+    pub fn resident_bytes(&self) -> u64 {
+        self.entries
+            .iter()
+            .flatten()
+            .map(|e| e.last_seen.len() as u64 * e.page_size)
+            .sum()
+    }
+
+    fn loading_bytes(&self) -> u64 {
+        self.entries
+            .iter()
+            .flatten()
+            .map(|e| e.loading.len() as u64 * e.page_size)
+            .sum()
+    }
+
+    fn request(&mut self, renderer: &Renderer, id: usize, page: Page) {
+        let entry = self.entries[id].as_mut().expect("Valid id");
+        let (offset, extent) = match renderer
+            .get_texture(&entry.handle)
+            .and_then(Texture::sparse)
+        {
+            Some(image) => image.region(page),
+            None => return,
+        };
+        let sent = self.loader.send(Job::Page {
+            id,
+            handle: entry.handle,
+            path: entry.path.clone(),
+            page,
+            offset,
+            extent,
+        });
+        if sent {
+            entry.loading.insert(page);
+        } else {
+            log::error!("The virtual texture loader has stopped");
+        }
+    }
+
+    /// Entities that were deleted, or whose VirtualTexture or material has changed
+    fn remove_stale(&mut self, world: &World, renderer: &mut Renderer) {
+        let entities = world.entities();
+        let virtual_textures = world.read_storage::<VirtualTexture>();
+        let gpu_materials = world.read_storage::<GpuMaterial>();
+        let physically_based = world.read_storage::<PhysicallyBased>();
+        let loader = world.read_resource::<trekanten::Loader>();
+        let mut pending = world.write_storage::<PendingMaterial>();
+        let mut reload = world.write_storage::<ReloadMaterial>();
+
+        self.skipped.retain(|ent, path| {
+            virtual_textures
+                .get(*ent)
+                .map(|vt| vt.path == *path)
+                .unwrap_or(false)
+        });
+
+        for slot in self.entries.iter_mut() {
+            let entry = match slot {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let ent = entry.entity;
+            let removed = !virtual_textures
+                .get(ent)
+                .map(|vt| vt.path == entry.path)
+                .unwrap_or(false);
+            if entities.is_alive(ent) && entry.swapped_in {
+                if pending.contains(ent) {
+                    // Wait for the material to be done
+                    continue;
+                }
+
+                let current = base_color_handle(gpu_materials.get(ent));
+                if current == Some(entry.handle) && removed {
+                    // Load the original texture again, this one is released with the material
+                    let original = physically_based
+                        .get(ent)
+                        .and_then(|pb| pb.base_color_texture.as_ref());
+                    if let (Some(gpu_mat), Some(original)) = (gpu_materials.get(ent), original) {
+                        let mut material = PendingMaterial::from(gpu_mat);
+                        if let PendingMaterial::PBR {
+                            base_color_texture, ..
+                        } = &mut material
+                        {
+                            *base_color_texture = Some(Pending::Pending(TextureUse {
+                                handle: loader
+                                    .load(original.desc.clone())
+                                    .expect("Failed to load texture"),
+                                coord_set: original.coord_set,
+                            }));
+                        }
+                        pending.insert(ent, material).expect("This is alive");
+                        reload.insert(ent, ReloadMaterial).expect("This is alive");
+                    }
+                } else if current == Some(entry.handle) {
+                    continue;
+                }
+                // Otherwise the material was replaced, e.g. when it was reloaded, and the texture
+                // was released with it.
+            } else if entities.is_alive(ent) && !removed {
+                continue;
+            }
+
+            let entry = slot.take().expect("Checked above");
+            entry.destroy(renderer, &self.loader);
+        }
+    }
+
+    fn create_new(&mut self, world: &World, renderer: &mut Renderer) {
+        let entities = world.entities();
+        let virtual_textures = world.read_storage::<VirtualTexture>();
+        let gpu_materials = world.read_storage::<GpuMaterial>();
+        let pending = world.read_storage::<PendingMaterial>();
+
+        for (ent, vt, mat, _) in (&entities, &virtual_textures, &gpu_materials, !&pending).join() {
+            if self.skipped.contains_key(&ent)
+                || self.entries.iter().flatten().any(|e| e.entity == ent)
+            {
+                continue;
+            }
+
+            let has_base_color = matches!(
+                mat,
+                GpuMaterial::PBR {
+                    base_color_texture: Some(_),
+                    ..
+                }
+            );
+            let id = self.entries.iter().position(Option::is_none).or_else(|| {
+                if self.entries.len() < MAX_TEXTURES {
+                    self.entries.push(None);
+                    Some(self.entries.len() - 1)
+                } else {
+                    None
+                }
+            });
+
+            let entry = if !has_base_color {
+                Err(String::from(
+                    "it needs a physically based material with a base color texture",
+                ))
+            } else if !renderer.supports_sparse_textures() {
+                Err(String::from("sparse textures are not supported"))
+            } else if let Some(id) = id {
+                Entry::new(renderer, ent, &vt.path, id)
+                    .map(|entry| (id, entry))
+                    .map_err(|e| e.to_string())
+            } else {
+                Err(format!(
+                    "there are already {} virtual textures",
+                    MAX_TEXTURES
+                ))
+            };
+
+            match entry {
+                Ok((id, entry)) => {
+                    let tail = (entry.mip_tail_first_level..)
+                        .take(entry.tail_remaining as usize)
+                        .collect::<Vec<_>>();
+                    self.entries[id] = Some(entry);
+                    for level in tail {
+                        self.request(renderer, id, Page { level, x: 0, y: 0 });
+                    }
+                }
+                Err(e) => {
+                    log::warn!(
+                        "{:?} can't have a virtual texture of {}: {}",
+                        ent,
+                        vt.path.display(),
+                        e
+                    );
+                    self.skipped.insert(ent, vt.path.clone());
+                }
+            }
+        }
+    }
+
+    /// Writes the pages that the loader is done with
+    fn receive(&mut self, renderer: &mut Renderer) {
+        let mut done: HashMap<usize, Vec<(Page, Vec<u8>)>> = HashMap::new();
+        for result in self.loader.results.try_iter() {
+            let entry = match self.entries.get_mut(result.id).and_then(Option::as_mut) {
+                // The id may have been reused since the page was requested
+                Some(entry) if entry.handle == result.handle => entry,
+                _ => continue,
+            };
+            entry.loading.remove(&result.page);
+            match result.texels {
+                Ok(texels) => done
+                    .entry(result.id)
+                    .or_default()
+                    .push((result.page, texels)),
+                Err(e) => log::error!(
+                    "Failed to read page {:?} of {}: {}",
+                    result.page,
+                    entry.path.display(),
+                    e
+                ),
+            }
+        }
+
+        for (id, pages) in done {
+            let entry = self.entries[id].as_mut().expect("Checked above");
+            let writes: Vec<(Page, &[u8])> = pages
+                .iter()
+                .map(|(page, texels)| (*page, texels.as_slice()))
+                .collect();
+            if let Err(e) = renderer.write_sparse_pages(&entry.handle, &writes) {
+                log::error!("Failed to write pages of {}: {}", entry.path.display(), e);
+                continue;
+            }
+
+            for (page, _) in writes {
+                if page.level >= entry.mip_tail_first_level {
+                    entry.tail_remaining -= 1;
+                } else {
+                    entry.last_seen.insert(page, self.feedback_round);
+                }
+            }
+        }
+    }
+
+    /// Replaces the base color texture of the materials once the mip tail is written
+    fn swap_in(&mut self, world: &World) {
+        let gpu_materials = world.read_storage::<GpuMaterial>();
+        let mut pending = world.write_storage::<PendingMaterial>();
+        let mut reload = world.write_storage::<ReloadMaterial>();
+
+        for entry in self.entries.iter_mut().flatten() {
+            if entry.swapped_in || !entry.is_ready() || pending.contains(entry.entity) {
+                continue;
+            }
+
+            let gpu_mat = gpu_materials.get(entry.entity);
+            let coord_set = match gpu_mat {
+                Some(GpuMaterial::PBR {
+                    base_color_texture: Some(tex),
+                    ..
+                }) => tex.coord_set,
+                _ => continue,
+            };
+            let mut material = PendingMaterial::from(gpu_mat.expect("Matched above"));
+            if let PendingMaterial::PBR {
+                base_color_texture, ..
+            } = &mut material
+            {
+                *base_color_texture = Some(Pending::Available(TextureUse {
+                    handle: entry.handle,
+                    coord_set,
+                }));
+            }
+            // The pipeline samples sparse textures differently, see pipeline_key
+            pending
+                .insert(entry.entity, material)
+                .expect("This is alive");
+            reload
+                .insert(entry.entity, ReloadMaterial)
+                .expect("This is alive");
+            entry.swapped_in = true;
+        }
+    }
+
+    /// Evicts the resident page that was seen the longest ago, unless it was seen in the current
+    /// feedback round. Returns the memory of the page.
+    fn evict_oldest(&mut self, renderer: &mut Renderer) -> Option<u64> {
+        let round = self.feedback_round;
+        let (id, page) = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(id, e)| e.as_ref().map(|e| (id, e)))
+            .flat_map(|(id, e)| {
+                e.last_seen
+                    .iter()
+                    .map(move |(page, seen)| (id, *page, *seen))
+            })
+            .filter(|(_, _, seen)| *seen < round)
+            .min_by_key(|(_, _, seen)| *seen)
+            .map(|(id, page, _)| (id, page))?;
+
+        let entry = self.entries[id].as_mut().expect("Found above");
+        entry.last_seen.remove(&page);
+        entry.evicted.insert(page, self.frame);
+        renderer.destroy_deferred(Destroyable::SparsePage(entry.handle, page));
+        Some(entry.page_size)
+    }
+
+    /// Reads back the feedback and requests the pages that were seen but are not resident, as
+    /// long as they fit in the budget.
+    fn read_feedback(&mut self, world: &World, renderer: &mut Renderer) {
+        let color = match &self.feedback {
+            Some(feedback) if self.has_feedback => feedback.targets.color,
+            _ => return,
+        };
+        if let Some(read) = self.feedback_read {
+            if read.elapsed() < FEEDBACK_INTERVAL {
+                return;
+            }
+        }
+        self.feedback_read = Some(Instant::now());
+
+        let data = match renderer.read_texture(&color) {
+            Ok((_, _, data)) => data,
+            Err(e) => {
+                log::error!("Failed to read virtual texture feedback: {}", e);
+                return;
+            }
+        };
+        self.feedback_round += 1;
+        let round = self.feedback_round;
+        let frame = self.frame;
+
+        let mut wanted = Vec::new();
+        for (id, page) in decode_feedback(&data) {
+            let entry = match self.entries.get_mut(id).and_then(Option::as_mut) {
+                Some(entry) if entry.swapped_in => entry,
+                _ => continue,
+            };
+            let (x, y) = sparse::pages_in_level(entry.extent, entry.page_extent, page.level);
+            if page.level >= entry.mip_tail_first_level || page.x >= x || page.y >= y {
+                continue;
+            }
+
+            if let Some(seen) = entry.last_seen.get_mut(&page) {
+                *seen = round;
+                continue;
+            }
+            let recently_evicted = entry
+                .evicted
+                .get(&page)
+                .map(|evicted| frame < evicted + EVICTION_FRAMES)
+                .unwrap_or(false);
+            if !entry.loading.contains(&page) && !recently_evicted {
+                wanted.push((id, page));
+            }
+        }
+
+        for entry in self.entries.iter_mut().flatten() {
+            entry
+                .evicted
+                .retain(|_, evicted| frame < *evicted + EVICTION_FRAMES);
+        }
+
+        // Coarse pages first, they cover more of the screen and finer pages fall back to them
+        wanted.sort_by(|a, b| b.1.level.cmp(&a.1.level));
+
+        let budget = world
+            .read_resource::<RenderSettings>()
+            .virtual_texture_budget_mb as u64
+            * 1024
+            * 1024;
+        let mut usage = self.resident_bytes() + self.loading_bytes();
+        let mut in_flight: usize = self.entries.iter().flatten().map(|e| e.loading.len()).sum();
+        for (id, page) in wanted {
+            if in_flight >= MAX_PAGES_IN_FLIGHT {
+                break;
+            }
+
+            let page_size = self.entries[id].as_ref().expect("Valid id").page_size;
+            while usage + page_size > budget {
+                match self.evict_oldest(renderer) {
+                    Some(evicted) => usage -= evicted,
+                    None => break,
+                }
+            }
+            if usage + page_size > budget {
+                break;
+            }
+
+            self.request(renderer, id, page);
+            usage += page_size;
+            in_flight += 1;
+        }
+    }
+
+    /// Collects the meshes with virtual textures for the feedback pass of this frame
+    fn prepare_feedback(&mut self, world: &World, renderer: &mut Renderer) {
+        let descriptor_sets: HashMap<Entity, Handle<DescriptorSet>> = self
+            .entries
+            .iter()
+            .flatten()
+            .filter(|e| e.swapped_in)
+            .map(|e| (e.entity, e.descriptor_set))
+            .collect();
+        if let Some(feedback) = &mut self.feedback {
+            feedback.items.clear();
+        }
+        if descriptor_sets.is_empty() {
+            self.has_feedback = false;
+            return;
+        }
+
+        let feedback = self
+            .feedback
+            .get_or_insert_with(|| FeedbackPass::new(renderer));
+        feedback.resize(renderer);
+
+        let shader_compiler = world.read_resource::<pipeline::ShaderCompiler>();
+        let meshes = world.read_storage::<GpuMesh>();
+        let materials = world.read_storage::<GpuMaterial>();
+        let model_matrices = world.read_storage::<ModelMatrix>();
+        let skeletons = world.read_storage::<crate::anim::Skeleton>();
+        let morph_weights = world.read_storage::<crate::anim::MorphWeights>();
+        let deformations = world.read_storage::<super::deformation::GpuDeformation>();
+        let hidden = world.read_storage::<Hidden>();
+        for (ent, mesh, mat, mtx, skeleton, weights, deformation, _) in (
+            &world.entities(),
+            &meshes,
+            &materials,
+            &model_matrices,
+            skeletons.maybe(),
+            morph_weights.maybe(),
+            deformations.maybe(),
+            !&hidden,
+        )
+            .join()
+        {
+            let descriptor_set = match descriptor_sets.get(&ent) {
+                Some(descriptor_set) => *descriptor_set,
+                None => continue,
+            };
+
+            let num_morph_targets = weights.map(|w| w.0.len() as u32).unwrap_or(0);
+            // The feedback pass pushes the model matrices
+            let key = super::pipeline_key(
+                renderer,
+                mesh,
+                mat,
+                skeleton.is_some(),
+                num_morph_targets,
+                false,
+                DepthTest::Enabled,
+            );
+            let def = match key.shaders {
+                MaterialShaders::PBR(def) => def,
+                MaterialShaders::Unlit => continue,
+            };
+            let pipeline =
+                match feedback.pipeline(&shader_compiler, renderer, key.vertex_format, def) {
+                    Ok(pipeline) => pipeline,
+                    Err(e) => {
+                        log::error!("Failed to create virtual texture feedback pipeline: {}", e);
+                        continue;
+                    }
+                };
+
+            feedback.items.push(DrawItem {
+                pipeline,
+                descriptor_set,
+                vertex_buffer: deformation.map_or(mesh.vertex_buffer, |d| d.vertex_buffer),
+                index_buffer: mesh.index_buffer,
+                model: uniform::Model {
+                    model: mtx.0.into_col_array(),
+                    model_it: mtx.0.inverted().transposed().into_col_array(),
+                },
+            });
+        }
+        self.has_feedback = !feedback.items.is_empty();
+    }
+
+    /// Renders the feedback, if any entities with virtual textures are drawn. Expects the view data
+    /// to be bound in the shader resource group.
+    pub(super) fn record_feedback(
+        &self,
+        frame: &Frame,
+        cmd_buffer: CommandBuffer,
+        shader_resource_group: &Handle<DescriptorSet>,
+    ) -> CommandBuffer {
+        let feedback = match &self.feedback {
+            Some(feedback) if !feedback.items.is_empty() => feedback,
+            _ => return cmd_buffer,
+        };
+
+        let clear_values = [
+            raw_vk::ClearValue {
+                color: raw_vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 0.0],
+                },
+            },
+            raw_vk::ClearValue {
+                depth_stencil: raw_vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        let mut pass = frame
+            .begin_render_pass(
+                cmd_buffer,
+                &feedback.render_pass,
+                &feedback.targets.render_target,
+                feedback.targets.extent,
+                &clear_values,
+            )
+            .expect("Failed to begin virtual texture feedback pass");
+
+        let mut bound = None;
+        for item in feedback.items.iter() {
+            if bound != Some(item.pipeline) {
+                pass.bind_graphics_pipeline(&item.pipeline)
+                    .bind_shader_resource_group(0u32, shader_resource_group, &item.pipeline);
+                bound = Some(item.pipeline);
+            }
+
+            pass.bind_shader_resource_group(1u32, &item.descriptor_set, &item.pipeline)
+                .bind_vertex_buffer(&item.vertex_buffer)
+                .bind_index_buffer(&item.index_buffer)
+                .bind_push_constant(&item.pipeline, ShaderStage::VERTEX, &item.model)
+                .draw_indexed(
+                    item.index_buffer.n_elems(),
+                    item.index_buffer.idx(),
+                    item.vertex_buffer.idx() as i32,
+                );
+        }
+
+        pass.end()
+            .expect("Failed to end virtual texture feedback pass")
+    }
+}
+
+/// Creates the virtual textures of new entities, writes the pages that have been loaded, reads back
+/// the feedback and requests the pages that are missing. Has to run before the frame is started, as
+/// the writes and the readback wait for the renderer.
+#[profiling::function]
+pub(super) fn update(world: &World, renderer: &mut Renderer) {
+    let mut virtual_textures = world.write_resource::<VirtualTextures>();
+    virtual_textures.frame += 1;
+    virtual_textures.remove_stale(world, renderer);
+    virtual_textures.create_new(world, renderer);
+    virtual_textures.receive(renderer);
+    virtual_textures.swap_in(world);
+    virtual_textures.read_feedback(world, renderer);
+    virtual_textures.prepare_feedback(world, renderer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feedback_is_decoded_to_pages() {
+        let data = [
+            // Nothing was drawn
+            0, 0, 0, 0, //
+            3, 4, 2, 1, //
+            3, 4, 2, 1, //
+            0, 255, 0, 255,
+        ];
+        let pages = decode_feedback(&data);
+        assert_eq!(pages.len(), 2);
+        assert!(pages.contains(&(
+            0,
+            Page {
+                level: 2,
+                x: 3,
+                y: 4
+            }
+        )));
+        assert!(pages.contains(&(
+            254,
+            Page {
+                level: 0,
+                x: 0,
+                y: 255
+            }
+        )));
+    }
+}
@@ -0,0 +1,108 @@
+//! Which passes an entity is drawn in. Visibility hides an entity or only draws it into the shadow
+//! maps, and RenderLayer puts it on layers of which the camera and the shadows each draw a subset,
+//! see RenderSettings::camera_layers and shadow_layers. Entities without the components are visible
+//! on the default layer.
+//!
+//! This is separate from Hidden, which the editor and the LOD groups toggle, so that they don't
+//! change what was set on the entity.
+
+use crate::ecs::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+#[component(inspect)]
+pub enum Visibility {
+    Visible,
+    /// Neither drawn in the scene nor in the shadow maps
+    Hidden,
+    /// Not drawn in the scene, but casts shadows
+    ShadowOnly,
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Self::Visible
+    }
+}
+
+/// A bit mask of the layers the entity is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+#[component(inspect)]
+pub struct RenderLayer(pub u32);
+
+impl RenderLayer {
+    pub const DEFAULT: Self = Self(1);
+    pub const ALL: Self = Self(u32::MAX);
+
+    /// Only the layer with the index, 0 to 31
+    pub fn layer(idx: u32) -> Self {
+        assert!(idx < 32, "There are only 32 layers");
+        Self(1 << idx)
+    }
+
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl Default for RenderLayer {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    /// Everything that is drawn from the camera, including its depth
+    Camera,
+    Shadow,
+}
+
+/// If an entity with the, optional, components is drawn in the pass that draws the layers
+pub fn is_drawn(
+    visibility: Option<&Visibility>,
+    layer: Option<&RenderLayer>,
+    pass: Pass,
+    pass_layers: RenderLayer,
+) -> bool {
+    let in_pass = match (visibility.copied().unwrap_or_default(), pass) {
+        (Visibility::Visible, _) => true,
+        (Visibility::Hidden, _) => false,
+        (Visibility::ShadowOnly, pass) => pass == Pass::Shadow,
+    };
+    in_pass && layer.copied().unwrap_or_default().intersects(pass_layers)
+}
+
+/// The layers that the pass draws
+pub fn pass_layers(world: &World, pass: Pass) -> RenderLayer {
+    let settings = world.read_resource::<super::debug_window::RenderSettings>();
+    match pass {
+        Pass::Camera => RenderLayer(settings.camera_layers),
+        Pass::Shadow => RenderLayer(settings.shadow_layers),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visibility_and_layers_select_the_passes() {
+        let all = RenderLayer::ALL;
+        assert!(is_drawn(None, None, Pass::Camera, all));
+        assert!(is_drawn(None, None, Pass::Shadow, all));
+
+        let shadow_only = Some(&Visibility::ShadowOnly);
+        assert!(!is_drawn(shadow_only, None, Pass::Camera, all));
+        assert!(is_drawn(shadow_only, None, Pass::Shadow, all));
+        let hidden = Some(&Visibility::Hidden);
+        assert!(!is_drawn(hidden, None, Pass::Camera, all));
+        assert!(!is_drawn(hidden, None, Pass::Shadow, all));
+
+        // E.g. a layer that is excluded from the shadows
+        let no_shadows = RenderLayer(!RenderLayer::layer(3).0);
+        let layer = Some(&RenderLayer::layer(3));
+        assert!(is_drawn(None, layer, Pass::Camera, all));
+        assert!(!is_drawn(None, layer, Pass::Shadow, no_shadows));
+        assert!(is_drawn(None, None, Pass::Shadow, no_shadows));
+    }
+}
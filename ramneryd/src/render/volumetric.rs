@@ -0,0 +1,416 @@
+//! Light shafts for spot lights with the Volumetric component. The depth of the scene is drawn again
+//! at half resolution, with a far plane that just covers the volumetric lights. For every pixel, the
+//! view ray up to that depth is marched and the light that is scattered towards the camera is
+//! accumulated from the volumetric lights that reach each step, according to their shadow maps. The
+//! result is added to the HDR scene color, so it is part of the bloom and the composite.
+
+use trekanten::descriptor::DescriptorSet;
+use trekanten::mem::{
+    BufferMutability, OwningUniformBufferDescriptor, TransientSlice, UniformBuffer,
+};
+use trekanten::pipeline::{BlendState, GraphicsPipeline, ShaderStage};
+use trekanten::raw_vk;
+use trekanten::resource::Handle;
+use trekanten::texture::{
+    BorderColor, Filter, SamplerAddressMode, SamplerDescriptor, TextureDescriptor, TextureUsage,
+};
+use trekanten::util;
+use trekanten::{BufferHandle, CommandBuffer, Frame, RenderPass, RenderTarget, Renderer};
+
+use crate::ecs::prelude::*;
+use crate::math::{Mat4, Transform, Vec3};
+
+use super::light::{Light, Volumetric};
+use super::uniform::{self, UniformBlock as _};
+use super::{debug_window::RenderSettings, pipeline, post, MaterialError};
+
+struct RenderPasses {
+    // The shadow render pass, so that the shadow pipelines can be used
+    depth: Handle<RenderPass>,
+    scattering: Handle<RenderPass>,
+    // Blends onto the scene color
+    add: Handle<RenderPass>,
+}
+
+// Everything that depends on the size of the presented image
+struct Targets {
+    scene_extent: util::Extent2D,
+    extent: util::Extent2D,
+    depth: Handle<trekanten::Texture>,
+    depth_target: Handle<RenderTarget>,
+    scattering: Handle<trekanten::Texture>,
+    scattering_target: Handle<RenderTarget>,
+    // The scene color, that the scattering is added to
+    scene_target: Handle<RenderTarget>,
+    depth_source: Handle<DescriptorSet>,
+    scattering_source: Handle<DescriptorSet>,
+}
+
+pub struct VolumetricLighting {
+    render_passes: RenderPasses,
+    scattering_pipeline: Handle<GraphicsPipeline>,
+    add_pipeline: Handle<GraphicsPipeline>,
+    depth_view_data_set: Handle<DescriptorSet>,
+    volumetric_data: BufferHandle<UniformBuffer>,
+    // The volumetric data, the lights and their shadow maps
+    inputs: Handle<DescriptorSet>,
+    targets: Targets,
+}
+
+impl Targets {
+    fn new(
+        renderer: &mut Renderer,
+        render_passes: &RenderPasses,
+        scene_color: &Handle<trekanten::Texture>,
+    ) -> Self {
+        let scene_extent = renderer.swapchain_extent();
+        let extent = util::Extent2D {
+            width: (scene_extent.width / 2).max(1),
+            height: (scene_extent.height / 2).max(1),
+        };
+
+        // Same format as the shadow maps, as it is drawn with the same pipelines
+        let depth = renderer
+            .create_texture(TextureDescriptor::Empty {
+                extent,
+                format: util::Format::D16_UNORM,
+                usage: TextureUsage::DEPTH_STENCIL_ATTACHMENT,
+                sampler: SamplerDescriptor {
+                    filter: Filter::Nearest,
+                    address_mode: SamplerAddressMode::ClampToEdge,
+                    max_anisotropy: None,
+                    border_color: BorderColor::FloatOpaqueWhite,
+                },
+            })
+            .expect("Failed to create volumetric depth texture");
+        let depth_target = renderer
+            .create_render_target(&render_passes.depth, &[&depth])
+            .expect("Failed to create volumetric depth render target");
+
+        let scattering = post::color_texture(renderer, extent);
+        let scattering_target = renderer
+            .create_render_target(&render_passes.scattering, &[&scattering])
+            .expect("Failed to create volumetric render target");
+        let scene_target = renderer
+            .create_render_target(&render_passes.add, &[scene_color])
+            .expect("Failed to create scene color render target");
+
+        let depth_source = DescriptorSet::builder(renderer)
+            .add_texture(&depth, 0, ShaderStage::FRAGMENT, true)
+            .build();
+        let scattering_source = post::source_descriptor_set(renderer, &scattering);
+
+        Self {
+            scene_extent,
+            extent,
+            depth,
+            depth_target,
+            scattering,
+            scattering_target,
+            scene_target,
+            depth_source,
+            scattering_source,
+        }
+    }
+
+    fn destroy(self, renderer: &mut Renderer) -> Result<(), trekanten::RenderError> {
+        renderer.destroy_descriptor_set(self.scattering_source)?;
+        renderer.destroy_descriptor_set(self.depth_source)?;
+        renderer.destroy_render_target(self.scene_target)?;
+        renderer.destroy_render_target(self.scattering_target)?;
+        renderer.destroy_texture(self.scattering)?;
+        renderer.destroy_render_target(self.depth_target)?;
+        renderer.destroy_texture(self.depth)
+    }
+}
+
+impl VolumetricLighting {
+    /// The scattering is added to `scene_color`, which is expected to be the resolved scene
+    pub(super) fn new(
+        shader_compiler: &pipeline::ShaderCompiler,
+        renderer: &mut Renderer,
+        shadow: &super::ShadowData,
+        scene_color: &Handle<trekanten::Texture>,
+    ) -> Result<Self, MaterialError> {
+        let render_passes = RenderPasses {
+            depth: shadow.render_pass,
+            scattering: post::fullscreen_render_pass(renderer, false),
+            add: post::fullscreen_render_pass(renderer, true),
+        };
+
+        let scattering_pipeline = post::fullscreen_pipeline(
+            shader_compiler,
+            renderer,
+            &render_passes.scattering,
+            "post/volumetric_frag.glsl",
+            &pipeline::Defines::empty(),
+            BlendState::Disabled,
+        )?;
+        // Upsamples the scattering and adds it
+        let mut no_bloom = pipeline::Defines::empty();
+        no_bloom.push((String::from("HAS_BLOOM"), String::from("0")));
+        let add_pipeline = post::fullscreen_pipeline(
+            shader_compiler,
+            renderer,
+            &render_passes.add,
+            "post/composite_frag.glsl",
+            &no_bloom,
+            BlendState::Additive,
+        )?;
+
+        let depth_view_data_set = DescriptorSet::builder(renderer)
+            .add_transient::<uniform::ViewData>(uniform::ViewData::BINDING, ShaderStage::VERTEX)
+            .build();
+
+        let volumetric_data = renderer
+            .create_resource_blocking(OwningUniformBufferDescriptor::from_vec(
+                vec![uniform::VolumetricData::default()],
+                BufferMutability::Mutable,
+            ))
+            .expect("Failed to create volumetric uniform buffer");
+        let inputs = inputs_set(renderer, &volumetric_data, shadow);
+
+        let targets = Targets::new(renderer, &render_passes, scene_color);
+
+        Ok(Self {
+            render_passes,
+            scattering_pipeline,
+            add_pipeline,
+            depth_view_data_set,
+            volumetric_data,
+            inputs,
+            targets,
+        })
+    }
+
+    /// Samples the new shadow maps, after they were recreated
+    pub(super) fn recreate_inputs(&mut self, renderer: &mut Renderer, shadow: &super::ShadowData) {
+        let inputs = inputs_set(renderer, &self.volumetric_data, shadow);
+        let prev = std::mem::replace(&mut self.inputs, inputs);
+        renderer.destroy_deferred(prev);
+    }
+
+    /// Points the inputs at the lights of this frame
+    pub(super) fn bind_lighting(
+        &self,
+        frame: &mut Frame,
+        lighting: TransientSlice,
+        shadow_matrices: TransientSlice,
+    ) {
+        frame.bind_transient(&self.inputs, uniform::LightingData::BINDING, lighting);
+        frame.bind_transient(
+            &self.inputs,
+            uniform::ShadowMatrices::BINDING,
+            shadow_matrices,
+        );
+    }
+
+    /// Has to be called when the scene color texture has been recreated, see
+    /// PostProcessing::scene_color
+    pub fn recreate_targets(
+        &mut self,
+        renderer: &mut Renderer,
+        scene_color: &Handle<trekanten::Texture>,
+    ) {
+        let targets = Targets::new(renderer, &self.render_passes, scene_color);
+        let old = std::mem::replace(&mut self.targets, targets);
+        if let Err(e) = old.destroy(renderer) {
+            log::error!("Failed to destroy volumetric targets: {}", e);
+        }
+    }
+}
+
+/// The volumetric data, the lights and the spot light shadow maps for the scattering pass
+fn inputs_set(
+    renderer: &mut Renderer,
+    volumetric_data: &BufferHandle<UniformBuffer>,
+    shadow: &super::ShadowData,
+) -> Handle<DescriptorSet> {
+    let shadow_maps = shadow.spotlights.iter().map(|x| (x.texture, true));
+    DescriptorSet::builder(renderer)
+        .add_buffer(
+            volumetric_data,
+            uniform::VolumetricData::BINDING,
+            ShaderStage::FRAGMENT,
+        )
+        .add_transient::<uniform::LightingData>(
+            uniform::LightingData::BINDING,
+            ShaderStage::FRAGMENT,
+        )
+        .add_textures(shadow_maps, 2, ShaderStage::FRAGMENT)
+        .add_transient::<uniform::ShadowMatrices>(
+            uniform::ShadowMatrices::BINDING,
+            ShaderStage::FRAGMENT,
+        )
+        .build()
+}
+
+/// The distance from `view_pos` to the far end of the furthest volumetric light, if there are any
+fn volumetric_range(world: &World, view_pos: Vec3) -> Option<f32> {
+    let lights = world.read_storage::<Light>();
+    let volumetrics = world.read_storage::<Volumetric>();
+    let transforms = world.read_storage::<Transform>();
+
+    (&lights, &volumetrics, &transforms)
+        .join()
+        .filter_map(|(light, volumetric, tfm)| match light {
+            Light::Spot { range, .. } if volumetric.density > 0.0 => {
+                Some(tfm.position.distance(view_pos) + range)
+            }
+            _ => None,
+        })
+        .fold(None, |acc: Option<f32>, d| {
+            Some(acc.map_or(d, |a| a.max(d)))
+        })
+}
+
+/// Records the depth pass, the ray marching and the addition to the scene color, if there are any
+/// volumetric lights. Has to be recorded after the scene pass and the shadow passes.
+pub fn volumetric_passes(
+    world: &World,
+    frame: &mut Frame,
+    frame_resources: &super::FrameData,
+    view_matrix: Mat4,
+    view_pos: Vec3,
+    aspect_ratio: f32,
+    mut cmd_buffer: CommandBuffer,
+) -> CommandBuffer {
+    let num_samples = {
+        let settings = world.read_resource::<RenderSettings>();
+        if !settings.volumetric_lighting {
+            return cmd_buffer;
+        }
+        settings.volumetric_samples
+    };
+
+    let far = match volumetric_range(world, view_pos) {
+        Some(far) => far.max(1.0),
+        None => return cmd_buffer,
+    };
+
+    let volumetric = &frame_resources.volumetric;
+    let targets = &volumetric.targets;
+    // The depth buffer only has 16 bits, so the depth range is kept as small as possible
+    let view_proj = super::get_proj_matrix_with_far(world, aspect_ratio, far) * view_matrix;
+    let view_data = uniform::ViewData {
+        view_proj: view_proj.into_col_array(),
+        view_pos: [view_pos.x, view_pos.y, view_pos.z, 1.0],
+    };
+    let view_data = frame
+        .write_transient(&view_data)
+        .expect("Failed to write view data for the volumetric depth pass");
+    frame.bind_transient(
+        &volumetric.depth_view_data_set,
+        uniform::ViewData::BINDING,
+        view_data,
+    );
+    let volumetric_data = uniform::VolumetricData {
+        inv_view_proj: view_proj.inverted().into_col_array(),
+        view_pos: [view_pos.x, view_pos.y, view_pos.z, 1.0],
+        num_samples: [num_samples; 4],
+    };
+    frame
+        .update_uniform_blocking(&volumetric.volumetric_data, &volumetric_data)
+        .expect("Failed to update volumetric data");
+
+    let clear_values = [raw_vk::ClearValue {
+        depth_stencil: raw_vk::ClearDepthStencilValue {
+            depth: 1.0,
+            stencil: 0,
+        },
+    }];
+    let dummy_pipeline = &frame_resources.shadow.dummy_pipeline;
+    let mut depth_pass = frame
+        .begin_render_pass(
+            cmd_buffer,
+            &volumetric.render_passes.depth,
+            &targets.depth_target,
+            targets.extent,
+            &clear_values,
+        )
+        .expect("Failed to begin volumetric depth render pass");
+    depth_pass
+        .bind_graphics_pipeline(dummy_pipeline)
+        .bind_shader_resource_group(0, &volumetric.depth_view_data_set, dummy_pipeline);
+    super::draw_entities(world, &mut depth_pass, super::DrawMode::DepthOnly);
+    cmd_buffer = depth_pass
+        .end()
+        .expect("Failed to end volumetric depth render pass");
+
+    let pipeline = &volumetric.scattering_pipeline;
+    let mut scattering_pass = frame
+        .begin_render_pass(
+            cmd_buffer,
+            &volumetric.render_passes.scattering,
+            &targets.scattering_target,
+            targets.extent,
+            &[],
+        )
+        .expect("Failed to begin volumetric render pass");
+    scattering_pass
+        .bind_graphics_pipeline(pipeline)
+        .bind_shader_resource_group(0, &volumetric.inputs, pipeline)
+        .bind_shader_resource_group(1, &targets.depth_source, pipeline)
+        .draw(3);
+    cmd_buffer = scattering_pass
+        .end()
+        .expect("Failed to end volumetric render pass");
+
+    let pipeline = &volumetric.add_pipeline;
+    let mut add_pass = frame
+        .begin_render_pass(
+            cmd_buffer,
+            &volumetric.render_passes.add,
+            &targets.scene_target,
+            targets.scene_extent,
+            &[],
+        )
+        .expect("Failed to begin volumetric composite render pass");
+    add_pass
+        .bind_graphics_pipeline(pipeline)
+        .bind_shader_resource_group(0, &targets.scattering_source, pipeline)
+        .draw(3);
+    add_pass
+        .end()
+        .expect("Failed to end volumetric composite render pass")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_covers_volumetric_spot_lights() {
+        let mut world = World::new();
+        world.register::<Light>();
+        world.register::<Volumetric>();
+        world.register::<Transform>();
+
+        assert_eq!(volumetric_range(&world, Vec3::zero()), None);
+
+        world
+            .create_entity()
+            .with(Light::default())
+            .with(Volumetric::default())
+            .with(Transform::pos(3.0, 0.0, 4.0))
+            .build();
+        // Not volumetric
+        world
+            .create_entity()
+            .with(Light::default())
+            .with(Transform::pos(100.0, 0.0, 0.0))
+            .build();
+        // Only spot lights can be volumetric
+        world
+            .create_entity()
+            .with(Light::Directional {
+                color: crate::math::Rgb::new(1.0, 1.0, 1.0),
+            })
+            .with(Volumetric::default())
+            .with(Transform::pos(100.0, 0.0, 0.0))
+            .build();
+
+        // The default spot light has a range of 5
+        assert_eq!(volumetric_range(&world, Vec3::zero()), Some(10.0));
+    }
+}
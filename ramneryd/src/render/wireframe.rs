@@ -0,0 +1,323 @@
+//! The edges of the triangles of the meshes, drawn over the lit scene to inspect their geometry.
+//! The lines are drawn with the unlit shaders and PolygonMode::Line, with a pipeline per vertex
+//! format, and are biased towards the camera so that they are not hidden by the triangles they are
+//! the edges of.
+//!
+//! The pipelines and draws are shared with the review pass, which draws the same overlays with
+//! other colors and line widths.
+
+use std::collections::{HashMap, HashSet};
+
+use num_derive::FromPrimitive;
+use ramneryd_derive::Inspect;
+
+use trekanten::descriptor::DescriptorSet;
+use trekanten::mem::{
+    BufferMutability, IndexBuffer, OwningUniformBufferDescriptor, UniformBuffer, VertexBuffer,
+};
+use trekanten::pipeline::{BlendState, DepthTest, GraphicsPipeline, PolygonMode, ShaderStage};
+use trekanten::resource::Handle;
+use trekanten::vertex::VertexFormat;
+use trekanten::{BufferHandle, RenderPass, RenderPassEncoder, Renderer};
+
+use crate::ecs::prelude::*;
+use crate::graph;
+use crate::math::ModelMatrix;
+
+use super::debug_window::RenderSettings;
+use super::deformation::GpuDeformation;
+use super::mesh::GpuMesh;
+use super::{pipeline, uniform, Hidden, MaterialError};
+
+const COLOR: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+/// Per LOD level, the levels after the last use the last color
+const LOD_COLORS: [[f32; 4]; 4] = [
+    [0.0, 1.0, 0.0, 1.0],
+    [1.0, 1.0, 0.0, 1.0],
+    [1.0, 0.5, 0.0, 1.0],
+    [1.0, 0.0, 0.0, 1.0],
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive, Inspect)]
+pub enum WireframeOverlay {
+    Off,
+    /// Only the meshes of the entities that are selected in the editor, and their children
+    Selected,
+    All,
+    /// The meshes of LodGroups, colored by the level that is shown, see render::lod
+    Lod,
+}
+
+impl_cvar_type_enum!(WireframeOverlay);
+
+/// How the meshes are drawn over the scene
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum OverlayStyle {
+    /// The edges, biased towards the camera. The width is in pixels.
+    Lines { width: u8 },
+    /// Transparent, in front of everything else
+    XRay,
+}
+
+/// The unlit pipelines for the overlays, for each vertex format and style
+#[derive(Default)]
+pub(super) struct OverlayPipelines {
+    pipelines: HashMap<(VertexFormat, OverlayStyle), Handle<GraphicsPipeline>>,
+}
+
+impl OverlayPipelines {
+    /// The pipelines are created for the scene render pass, so they have to be recreated when it is
+    pub fn clear(&mut self, renderer: &mut Renderer) {
+        for (_, pipeline) in self.pipelines.drain() {
+            renderer.destroy_deferred(pipeline);
+        }
+    }
+
+    pub fn get(
+        &mut self,
+        shader_compiler: &pipeline::ShaderCompiler,
+        renderer: &mut Renderer,
+        scene_render_pass: &Handle<RenderPass>,
+        vertex_format: VertexFormat,
+        style: OverlayStyle,
+    ) -> Result<Handle<GraphicsPipeline>, MaterialError> {
+        if let Some(pipeline) = self.pipelines.get(&(vertex_format.clone(), style)) {
+            return Ok(*pipeline);
+        }
+
+        let desc = match style {
+            OverlayStyle::Lines { width } => {
+                let mut desc = super::unlit_pipeline_desc(
+                    shader_compiler,
+                    vertex_format.clone(),
+                    PolygonMode::Line,
+                )?;
+                desc.depth_testing = DepthTest::Overlay;
+                desc.line_width = width;
+                desc
+            }
+            OverlayStyle::XRay => {
+                let mut desc = super::unlit_pipeline_desc(
+                    shader_compiler,
+                    vertex_format.clone(),
+                    PolygonMode::Fill,
+                )?;
+                desc.depth_testing = DepthTest::Disabled;
+                desc.blend_state = BlendState::Enabled;
+                desc
+            }
+        };
+        let pipeline = renderer.create_gfx_pipeline(desc, scene_render_pass)?;
+        self.pipelines.insert((vertex_format, style), pipeline);
+        Ok(pipeline)
+    }
+}
+
+/// A mesh drawn with an overlay pipeline, in the color of the descriptor set
+pub(super) struct DrawItem {
+    pipeline: Handle<GraphicsPipeline>,
+    vertex_buffer: BufferHandle<VertexBuffer>,
+    index_buffer: BufferHandle<IndexBuffer>,
+    model: uniform::Model,
+    color: Handle<DescriptorSet>,
+}
+
+impl DrawItem {
+    pub fn new(
+        pipeline: Handle<GraphicsPipeline>,
+        mesh: &GpuMesh,
+        deformation: Option<&GpuDeformation>,
+        mtx: &ModelMatrix,
+        color: Handle<DescriptorSet>,
+    ) -> Self {
+        Self {
+            pipeline,
+            // Deformed by the pre-pass, like in the passes of the draw list
+            vertex_buffer: deformation.map_or(mesh.vertex_buffer, |d| d.vertex_buffer),
+            index_buffer: mesh.index_buffer,
+            model: uniform::Model {
+                model: mtx.0.into_col_array(),
+                model_it: mtx.0.inverted().transposed().into_col_array(),
+            },
+            color,
+        }
+    }
+}
+
+/// Expects the view data to be bound in the shader resource group
+pub(super) fn record_items(
+    cmd_buf: &mut RenderPassEncoder,
+    shader_resource_group: &Handle<DescriptorSet>,
+    items: &[DrawItem],
+) {
+    let mut bound = None;
+    let mut bound_color = None;
+    for item in items.iter() {
+        if bound != Some(item.pipeline) {
+            cmd_buf
+                .bind_graphics_pipeline(&item.pipeline)
+                .bind_shader_resource_group(0u32, shader_resource_group, &item.pipeline);
+            bound = Some(item.pipeline);
+            bound_color = None;
+        }
+        if bound_color != Some(item.color) {
+            cmd_buf.bind_shader_resource_group(1u32, &item.color, &item.pipeline);
+            bound_color = Some(item.color);
+        }
+
+        cmd_buf
+            .bind_vertex_buffer(&item.vertex_buffer)
+            .bind_index_buffer(&item.index_buffer)
+            .bind_push_constant(&item.pipeline, ShaderStage::VERTEX, &item.model)
+            .draw_indexed(
+                item.index_buffer.n_elems(),
+                item.index_buffer.idx(),
+                item.vertex_buffer.idx() as i32,
+            );
+    }
+}
+
+pub(super) struct WireframeRenderer {
+    pipelines: OverlayPipelines,
+    color_descriptor_set: Handle<DescriptorSet>,
+    lod_color_descriptor_sets: Vec<Handle<DescriptorSet>>,
+    items: Vec<DrawItem>,
+}
+
+fn color_descriptor_set(renderer: &mut Renderer, color: [f32; 4]) -> Handle<DescriptorSet> {
+    let color = OwningUniformBufferDescriptor::from_vec(
+        vec![uniform::UnlitUniformData { color }],
+        BufferMutability::Immutable,
+    );
+    let color: BufferHandle<UniformBuffer> = renderer
+        .create_resource_blocking(color)
+        .expect("Failed to create wireframe color");
+    DescriptorSet::builder(renderer)
+        .add_buffer(&color, 0, ShaderStage::FRAGMENT)
+        .build()
+}
+
+impl WireframeRenderer {
+    pub fn new(renderer: &mut Renderer) -> Self {
+        let color_descriptor_set = color_descriptor_set(renderer, COLOR);
+        let lod_color_descriptor_sets = LOD_COLORS
+            .iter()
+            .map(|color| color_descriptor_set(renderer, *color))
+            .collect();
+
+        Self {
+            pipelines: OverlayPipelines::default(),
+            color_descriptor_set,
+            lod_color_descriptor_sets,
+            items: Vec::new(),
+        }
+    }
+
+    /// The pipelines are created for the scene render pass, so they have to be recreated when it is
+    pub fn clear_pipelines(&mut self, renderer: &mut Renderer) {
+        self.pipelines.clear(renderer);
+        self.items.clear();
+    }
+
+    /// Collects the meshes to draw this frame and creates the pipelines for them. Has to be called
+    /// before the frame is started.
+    pub fn prepare(
+        &mut self,
+        world: &World,
+        renderer: &mut Renderer,
+        scene_render_pass: &Handle<RenderPass>,
+    ) {
+        self.items.clear();
+        let overlay = world.read_resource::<RenderSettings>().wireframe_overlay;
+        let selected = match overlay {
+            WireframeOverlay::Off => return,
+            WireframeOverlay::Selected => Some(selected_entities(world)),
+            WireframeOverlay::All => None,
+            WireframeOverlay::Lod => None,
+        };
+        // Drawn by the review pass instead
+        let reviewed = super::review::review_displays(world);
+        let lod_levels = if let WireframeOverlay::Lod = overlay {
+            Some(super::lod::mesh_levels(world))
+        } else {
+            None
+        };
+
+        let shader_compiler = world.read_resource::<pipeline::ShaderCompiler>();
+        let meshes = world.read_storage::<GpuMesh>();
+        let model_matrices = world.read_storage::<ModelMatrix>();
+        let hidden = world.read_storage::<Hidden>();
+        let deformations = world.read_storage::<GpuDeformation>();
+        for (ent, mesh, mtx, deformation, _) in (
+            &world.entities(),
+            &meshes,
+            &model_matrices,
+            deformations.maybe(),
+            !&hidden,
+        )
+            .join()
+        {
+            if let Some(selected) = &selected {
+                if !selected.contains(&ent) {
+                    continue;
+                }
+            }
+            if reviewed.contains_key(&ent) {
+                continue;
+            }
+            let color = match &lod_levels {
+                Some(levels) => match levels.get(&ent) {
+                    Some(&level) => {
+                        let idx = (level as usize).min(self.lod_color_descriptor_sets.len() - 1);
+                        self.lod_color_descriptor_sets[idx]
+                    }
+                    None => continue,
+                },
+                None => self.color_descriptor_set,
+            };
+
+            let vertex_format = renderer
+                .get_resource(&mesh.vertex_buffer)
+                .expect("Invalid handle")
+                .format()
+                .clone();
+            let pipeline = match self.pipelines.get(
+                &shader_compiler,
+                renderer,
+                scene_render_pass,
+                vertex_format,
+                OverlayStyle::Lines { width: 1 },
+            ) {
+                Ok(pipeline) => pipeline,
+                Err(e) => {
+                    log::error!("Failed to create wireframe pipeline: {}", e);
+                    continue;
+                }
+            };
+
+            self.items
+                .push(DrawItem::new(pipeline, mesh, deformation, mtx, color));
+        }
+    }
+
+    /// Expects the view data to be bound in the shader resource group
+    pub fn record(
+        &self,
+        cmd_buf: &mut RenderPassEncoder,
+        shader_resource_group: &Handle<DescriptorSet>,
+    ) {
+        record_items(cmd_buf, shader_resource_group, &self.items);
+    }
+}
+
+fn selected_entities(world: &World) -> HashSet<Entity> {
+    let mut entities = HashSet::new();
+    if let Some(selection) = world.try_fetch::<crate::editor::Selection>() {
+        for &ent in selection.entities() {
+            graph::world::breadth_first(world, ent, |node| {
+                entities.insert(node);
+            });
+        }
+    }
+    entities
+}
@@ -0,0 +1,688 @@
+//! A scene is the part of the world that is edited through the inspector: lights, fog volumes,
+//! the camera and the assets that have been loaded, with their transforms and materials. Assets
+//! are stored as references to their files and are loaded again when the scene is, with the edits
+//! applied on top. Scenes and glTF files can also be appended to the current one, see append.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use std::path::{Path, PathBuf};
+
+use crate::asset::gltf::{GltfAsset, LoadGltfAsset, SceneSelection};
+use crate::camera::{Camera, CameraRotationState};
+use crate::common::Name;
+use crate::ecs::prelude::*;
+use crate::graph::{Children, Parent};
+use crate::math::{Rgb, Transform, Vec4};
+use crate::render::fog::FogVolume;
+use crate::render::light::{Light, Volumetric};
+use crate::render::material::{PhysicallyBased, DEFAULT_IOR};
+
+#[derive(Debug, Error)]
+pub enum SceneError {
+    #[error("Failed to access {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Failed to (de)serialize scene: {0}")]
+    Ron(#[from] ron::Error),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AssetReference {
+    Gltf { path: PathBuf, scene: usize },
+}
+
+/// The material factors of an entity in a loaded asset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaterialOverride {
+    /// Child indices from the asset root down to the entity with the material
+    path: Vec<usize>,
+    base_color_factor: Vec4,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    #[serde(default)]
+    emissive_factor: Rgb,
+    #[serde(default = "full_occlusion")]
+    occlusion_strength: f32,
+    #[serde(default)]
+    transmission: f32,
+    #[serde(default = "default_ior")]
+    ior: f32,
+}
+
+/// Scenes saved before occlusion textures were supported
+fn full_occlusion() -> f32 {
+    1.0
+}
+
+/// Scenes saved before transmission was supported
+fn default_ior() -> f32 {
+    DEFAULT_IOR
+}
+
+impl MaterialOverride {
+    fn new(path: Vec<usize>, material: &PhysicallyBased) -> Self {
+        Self {
+            path,
+            base_color_factor: material.base_color_factor,
+            metallic_factor: material.metallic_factor,
+            roughness_factor: material.roughness_factor,
+            normal_scale: material.normal_scale,
+            emissive_factor: material.emissive_factor,
+            occlusion_strength: material.occlusion_strength,
+            transmission: material.transmission,
+            ior: material.ior,
+        }
+    }
+
+    fn apply(&self, material: &mut PhysicallyBased) {
+        material.base_color_factor = self.base_color_factor;
+        material.metallic_factor = self.metallic_factor;
+        material.roughness_factor = self.roughness_factor;
+        material.normal_scale = self.normal_scale;
+        material.emissive_factor = self.emissive_factor;
+        material.occlusion_strength = self.occlusion_strength;
+        material.transmission = self.transmission;
+        material.ior = self.ior;
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EntityData {
+    /// Index of the parent in the scene, the entity is a root if None
+    #[serde(default)]
+    parent: Option<usize>,
+    name: Option<Name>,
+    transform: Option<Transform>,
+    light: Option<Light>,
+    #[serde(default)]
+    volumetric: Option<Volumetric>,
+    #[serde(default)]
+    fog: Option<FogVolume>,
+    camera: Option<CameraRotationState>,
+    #[serde(default)]
+    lens: Option<Camera>,
+    asset: Option<AssetReference>,
+    materials: Vec<MaterialOverride>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Scene {
+    entities: Vec<EntityData>,
+}
+
+/// The children that belong to the same asset as ent, i.e. without the assets that were appended
+/// below it. Paths are indices into these so they do not depend on what has been appended.
+fn asset_children(
+    children: &ReadStorage<Children>,
+    gltf_assets: &ReadStorage<GltfAsset>,
+    ent: Entity,
+) -> Vec<Entity> {
+    children
+        .get(ent)
+        .into_iter()
+        .flat_map(|c| c.iter().copied())
+        .filter(|child| !gltf_assets.contains(*child))
+        .collect()
+}
+
+fn find_descendant(
+    children: &ReadStorage<Children>,
+    gltf_assets: &ReadStorage<GltfAsset>,
+    root: Entity,
+    path: &[usize],
+) -> Option<Entity> {
+    path.iter().try_fold(root, |ent, &i| {
+        asset_children(children, gltf_assets, ent).get(i).copied()
+    })
+}
+
+// The original values are not kept around when an asset is loaded, so all materials are stored
+fn material_overrides(
+    children: &ReadStorage<Children>,
+    gltf_assets: &ReadStorage<GltfAsset>,
+    materials: &ReadStorage<PhysicallyBased>,
+    root: Entity,
+) -> Vec<MaterialOverride> {
+    let mut overrides = Vec::new();
+    let mut stack = vec![(root, Vec::new())];
+    while let Some((ent, path)) = stack.pop() {
+        if let Some(material) = materials.get(ent) {
+            overrides.push(MaterialOverride::new(path.clone(), material));
+        }
+
+        for (i, child) in asset_children(children, gltf_assets, ent)
+            .into_iter()
+            .enumerate()
+        {
+            let mut child_path = path.clone();
+            child_path.push(i);
+            stack.push((child, child_path));
+        }
+    }
+
+    overrides
+}
+
+impl Scene {
+    /// Only root entities and the entities that were appended below them are stored. The other
+    /// children are created again by the assets they belong to.
+    pub fn from_world(world: &World) -> Self {
+        let entities = world.entities();
+        let parents = world.read_storage::<Parent>();
+        let children = world.read_storage::<Children>();
+        let names = world.read_storage::<Name>();
+        let transforms = world.read_storage::<Transform>();
+        let lights = world.read_storage::<Light>();
+        let volumetrics = world.read_storage::<Volumetric>();
+        let fog_volumes = world.read_storage::<FogVolume>();
+        let cameras = world.read_storage::<Camera>();
+        let rotation_states = world.read_storage::<CameraRotationState>();
+        let gltf_assets = world.read_storage::<GltfAsset>();
+        let pbr_materials = world.read_storage::<PhysicallyBased>();
+
+        let entity_data = |ent: Entity, parent: Option<usize>| -> Option<EntityData> {
+            let camera = if cameras.contains(ent) {
+                rotation_states.get(ent).cloned()
+            } else {
+                None
+            };
+            let lens = cameras.get(ent).copied();
+            let light = lights.get(ent).cloned();
+            let volumetric = volumetrics.get(ent).copied();
+            let fog = fog_volumes.get(ent).cloned();
+            let asset = gltf_assets.get(ent).map(|asset| AssetReference::Gltf {
+                path: asset.path().to_path_buf(),
+                scene: asset.scene(),
+            });
+
+            if camera.is_none() && light.is_none() && fog.is_none() && asset.is_none() {
+                return None;
+            }
+
+            let materials = if asset.is_some() {
+                material_overrides(&children, &gltf_assets, &pbr_materials, ent)
+            } else {
+                Vec::new()
+            };
+
+            Some(EntityData {
+                parent,
+                name: names.get(ent).cloned(),
+                transform: transforms.get(ent).copied(),
+                light,
+                volumetric,
+                fog,
+                camera,
+                lens,
+                asset,
+                materials,
+            })
+        };
+
+        let mut scene_entities = Vec::new();
+        let mut stored = Vec::new();
+        for (ent, _) in (&entities, !&parents).join() {
+            if let Some(data) = entity_data(ent, None) {
+                scene_entities.push(data);
+                stored.push(ent);
+            }
+        }
+
+        // Parents are stored before their children so they exist when the children are created.
+        // Below an asset, only other assets have been appended, the rest belongs to the asset.
+        let mut i = 0;
+        while i < stored.len() {
+            let parent = stored[i];
+            let parent_is_asset = gltf_assets.contains(parent);
+            for child in children.get(parent).iter().flat_map(|c| c.iter()) {
+                if parent_is_asset && !gltf_assets.contains(*child) {
+                    continue;
+                }
+                if let Some(data) = entity_data(*child, Some(i)) {
+                    scene_entities.push(data);
+                    stored.push(*child);
+                }
+            }
+            i += 1;
+        }
+
+        Self {
+            entities: scene_entities,
+        }
+    }
+
+    /// Create the entities of the scene. The camera is created by the engine so it is updated
+    /// instead of adding another one.
+    pub fn instantiate(self, world: &mut World) {
+        self.instantiate_below(world, None, None);
+    }
+
+    /// Create the entities of the scene below parent, with the root transforms relative to offset.
+    /// The camera of the scene is ignored.
+    pub fn append(self, world: &mut World, parent: Option<Entity>, offset: Transform) {
+        self.instantiate_below(world, parent, Some(offset));
+    }
+
+    fn instantiate_below(
+        self,
+        world: &mut World,
+        root_parent: Option<Entity>,
+        offset: Option<Transform>,
+    ) {
+        let mut created: Vec<Option<Entity>> = Vec::with_capacity(self.entities.len());
+        for data in self.entities {
+            if let Some(rotation_state) = data.camera {
+                created.push(None);
+                if offset.is_some() {
+                    continue;
+                }
+                let entities = world.entities();
+                let mut cameras = world.write_storage::<Camera>();
+                let mut rotation_states = world.write_storage::<CameraRotationState>();
+                let mut transforms = world.write_storage::<Transform>();
+                match (&entities, &mut cameras, &mut rotation_states)
+                    .join()
+                    .next()
+                {
+                    Some((ent, camera, state)) => {
+                        *state = rotation_state;
+                        if let Some(lens) = data.lens {
+                            *camera = lens;
+                        }
+                        if let Some(transform) = data.transform {
+                            transforms
+                                .insert(ent, transform)
+                                .expect("Failed to insert camera transform");
+                        }
+                    }
+                    None => log::warn!("No camera to apply the scene camera to"),
+                }
+                continue;
+            }
+
+            let parent = match data.parent {
+                Some(idx) => match created.get(idx).copied().flatten() {
+                    Some(parent) => Some(parent),
+                    None => {
+                        log::warn!("Invalid parent {} in scene, skipping entity", idx);
+                        created.push(None);
+                        continue;
+                    }
+                },
+                None => root_parent,
+            };
+            let transform = match (data.parent, offset) {
+                (None, Some(offset)) => Some(offset * data.transform.unwrap_or_default()),
+                _ => data.transform,
+            };
+
+            let mut builder = world.create_entity();
+            if let Some(name) = data.name {
+                builder = builder.with(name);
+            }
+            if let Some(transform) = transform {
+                builder = builder.with(transform);
+            }
+            if let Some(light) = data.light {
+                builder = builder.with(light);
+            }
+            if let Some(volumetric) = data.volumetric {
+                builder = builder.with(volumetric);
+            }
+            if let Some(fog) = data.fog {
+                builder = builder.with(fog);
+            }
+            if let Some(AssetReference::Gltf { path, scene }) = data.asset {
+                // The loader resets the transform of the root
+                builder = builder
+                    .with(LoadGltfAsset::new(path, SceneSelection::Index(scene)))
+                    .with(SceneOverrides {
+                        transform,
+                        materials: data.materials,
+                    });
+            }
+            let ent = builder.build();
+            if let Some(parent) = parent {
+                crate::graph::world::add_edge(world, parent, ent);
+            }
+            created.push(Some(ent));
+        }
+    }
+}
+
+fn is_gltf(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("gltf") | Some("glb")
+    )
+}
+
+pub fn save(world: &World, path: &Path) -> Result<(), SceneError> {
+    let scene = Scene::from_world(world);
+    let contents = ron::ser::to_string_pretty(&scene, ron::ser::PrettyConfig::default())?;
+    std::fs::write(path, contents).map_err(|e| SceneError::Io(path.to_path_buf(), e))
+}
+
+fn read(path: &Path) -> Result<Scene, SceneError> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| SceneError::Io(path.to_path_buf(), e))?;
+    Ok(ron::de::from_str(&contents)?)
+}
+
+pub fn load(world: &mut World, path: &Path) -> Result<(), SceneError> {
+    let scene = read(path)?;
+    // The assets that were requested for the previous scene are not needed anymore
+    world
+        .write_resource::<crate::asset::server::AssetServer>()
+        .cancel_pending();
+    scene.instantiate(world);
+    Ok(())
+}
+
+/// Load a scene or a glTF file into the current world, below parent and offset by the transform,
+/// without replacing anything.
+pub fn append(
+    world: &mut World,
+    path: &Path,
+    parent: Option<Entity>,
+    offset: Transform,
+) -> Result<(), SceneError> {
+    if !is_gltf(path) {
+        read(path)?.append(world, parent, offset);
+        return Ok(());
+    }
+
+    if !path.exists() {
+        return Err(SceneError::Io(
+            path.to_path_buf(),
+            std::io::Error::from(std::io::ErrorKind::NotFound),
+        ));
+    }
+
+    let ent = world
+        .create_entity()
+        .with(offset)
+        .with(LoadGltfAsset::new(
+            path.to_path_buf(),
+            SceneSelection::Default,
+        ))
+        .with(SceneOverrides {
+            transform: Some(offset),
+            materials: Vec::new(),
+        })
+        .build();
+    if let Some(parent) = parent {
+        crate::graph::world::add_edge(world, parent, ent);
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct AppendState {
+    path: imgui::ImString,
+    below_selected: bool,
+    offset: Transform,
+}
+
+/// Append a scene or a glTF file to the current one, optionally below the selected entity
+pub(crate) fn build_ui<'a>(
+    world: &mut World,
+    ui: &crate::render::ui::UiFrame<'a>,
+    pos: [f32; 2],
+) -> [f32; 2] {
+    use crate::editor::Inspect as _;
+
+    let size = [300.0, 200.0];
+
+    imgui::Window::new(imgui::im_str!("Append scene"))
+        .position(pos, imgui::Condition::FirstUseEver)
+        .size(size, imgui::Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let mut storage = ui.storage();
+            let id = String::from("AppendScene");
+            if !storage.contains_key(&id) {
+                storage.insert(id.clone(), AppendState::default());
+            }
+            let state: &mut AppendState = storage
+                .get_mut(&id)
+                .expect("Append scene state was just inserted");
+
+            ui.inner()
+                .input_text(imgui::im_str!("Path"), &mut state.path)
+                .resize_buffer(true)
+                .build();
+            let selected = crate::editor::selected_entity(world);
+            ui.inner().checkbox(
+                imgui::im_str!("Below the selected entity"),
+                &mut state.below_selected,
+            );
+            if state.below_selected && selected.is_none() {
+                ui.inner().text(imgui::im_str!("No entity is selected"));
+            }
+            state.offset.inspect_mut(ui, "offset");
+
+            if ui.inner().button(imgui::im_str!("Append"), [0.0, 0.0]) {
+                let path = PathBuf::from(state.path.to_str());
+                let parent = selected.filter(|_| state.below_selected);
+                let offset = state.offset;
+                drop(storage);
+                match append(world, &path, parent, offset) {
+                    Ok(()) => log::info!("Appended {}", path.display()),
+                    Err(e) => log::error!("Failed to append {}: {}", path.display(), e),
+                }
+            }
+        });
+
+    size
+}
+
+/// Edits from a scene that are applied when the asset on the same entity has been loaded
+#[derive(Debug, Component)]
+struct SceneOverrides {
+    transform: Option<Transform>,
+    materials: Vec<MaterialOverride>,
+}
+
+struct ApplySceneOverrides;
+
+impl ApplySceneOverrides {
+    pub const ID: &'static str = "ApplySceneOverrides";
+}
+
+#[derive(SystemData)]
+struct ApplySceneOverridesData<'a> {
+    entities: Entities<'a>,
+    overrides: WriteStorage<'a, SceneOverrides>,
+    gltf_assets: ReadStorage<'a, GltfAsset>,
+    children: ReadStorage<'a, Children>,
+    transforms: WriteStorage<'a, Transform>,
+    materials: WriteStorage<'a, PhysicallyBased>,
+}
+
+impl<'a> System<'a> for ApplySceneOverrides {
+    type SystemData = ApplySceneOverridesData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let ApplySceneOverridesData {
+            entities,
+            mut overrides,
+            gltf_assets,
+            children,
+            mut transforms,
+            mut materials,
+        } = data;
+
+        let mut applied = Vec::new();
+        for (ent, scene_overrides, _) in (&entities, &overrides, &gltf_assets).join() {
+            if let Some(transform) = scene_overrides.transform {
+                transforms
+                    .insert(ent, transform)
+                    .expect("Failed to insert transform");
+            }
+
+            for material in scene_overrides.materials.iter() {
+                match find_descendant(&children, &gltf_assets, ent, &material.path)
+                    .and_then(|e| materials.get_mut(e))
+                {
+                    Some(m) => material.apply(m),
+                    None => log::warn!(
+                        "No material at {:?} in the loaded asset, the scene is out of date",
+                        material.path
+                    ),
+                }
+            }
+            applied.push(ent);
+        }
+
+        for ent in applied {
+            overrides.remove(ent);
+        }
+    }
+}
+
+pub fn register_systems<'a, 'b>(builder: ExecutorBuilder<'a, 'b>) -> ExecutorBuilder<'a, 'b> {
+    builder.with(
+        ApplySceneOverrides,
+        ApplySceneOverrides::ID,
+        &[crate::asset::gltf::GltfLoader::ID],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene_world() -> World {
+        let mut world = World::new();
+        world.register::<Parent>();
+        world.register::<Children>();
+        world.register::<Name>();
+        world.register::<Transform>();
+        world.register::<Light>();
+        world.register::<Volumetric>();
+        world.register::<FogVolume>();
+        world.register::<Camera>();
+        world.register::<CameraRotationState>();
+        world.register::<GltfAsset>();
+        world.register::<LoadGltfAsset>();
+        world.register::<PhysicallyBased>();
+        world.register::<SceneOverrides>();
+        world
+    }
+
+    #[test]
+    fn lights_round_trip() {
+        let mut world = scene_world();
+        let sun = world
+            .create_entity()
+            .with(Name::from("Sun"))
+            .with(Transform::identity())
+            .with(Light::Directional {
+                color: Rgb::new(1.0, 0.5, 0.0),
+            })
+            .build();
+        // Children belong to assets and are not stored
+        let child = world
+            .create_entity()
+            .with(Light::Point {
+                color: Rgb::new(1.0, 1.0, 1.0),
+                intensity: Default::default(),
+                range: 1.0,
+            })
+            .build();
+        crate::graph::world::add_edge(&mut world, sun, child);
+
+        let contents = ron::ser::to_string(&Scene::from_world(&world)).unwrap();
+        let scene: Scene = ron::de::from_str(&contents).unwrap();
+        assert_eq!(scene.entities.len(), 1);
+
+        let mut loaded = scene_world();
+        scene.instantiate(&mut loaded);
+        let names = loaded.read_storage::<Name>();
+        let lights = loaded.read_storage::<Light>();
+        let loaded_lights = (&names, &lights).join().collect::<Vec<_>>();
+        assert_eq!(loaded_lights.len(), 1);
+        assert_eq!(loaded_lights[0].0, &Name::from("Sun"));
+        assert!(matches!(loaded_lights[0].1, Light::Directional { .. }));
+    }
+
+    #[test]
+    fn descendant_by_path() {
+        let mut world = scene_world();
+        let root = world.create_entity().build();
+        let a = world.create_entity().build();
+        let b = world.create_entity().build();
+        let c = world.create_entity().build();
+        crate::graph::world::add_edge(&mut world, root, a);
+        crate::graph::world::add_edge(&mut world, root, b);
+        crate::graph::world::add_edge(&mut world, b, c);
+
+        let children = world.read_storage::<Children>();
+        let assets = world.read_storage::<GltfAsset>();
+        assert_eq!(find_descendant(&children, &assets, root, &[]), Some(root));
+        assert_eq!(find_descendant(&children, &assets, root, &[1]), Some(b));
+        assert_eq!(find_descendant(&children, &assets, root, &[1, 0]), Some(c));
+        assert_eq!(find_descendant(&children, &assets, root, &[0, 0]), None);
+        assert_eq!(find_descendant(&children, &assets, root, &[2]), None);
+    }
+
+    #[test]
+    fn append_offsets_roots_below_parent() {
+        let mut world = scene_world();
+        world
+            .create_entity()
+            .with(Transform::pos(1.0, 0.0, 0.0))
+            .with(Light::Directional {
+                color: Rgb::new(1.0, 1.0, 1.0),
+            })
+            .build();
+        let scene = Scene::from_world(&world);
+
+        let mut target = scene_world();
+        let parent = target.create_entity().build();
+        scene.append(&mut target, Some(parent), Transform::pos(0.0, 2.0, 0.0));
+
+        let parents = target.read_storage::<Parent>();
+        let transforms = target.read_storage::<Transform>();
+        let lights = target.read_storage::<Light>();
+        let appended = (&parents, &transforms, &lights).join().collect::<Vec<_>>();
+        assert_eq!(appended.len(), 1);
+        assert_eq!(appended[0].0.parent, parent);
+        assert_eq!(
+            appended[0].1.position,
+            crate::math::Vec3::new(1.0, 2.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn appended_assets_are_stored_below_their_parent() {
+        let mut world = scene_world();
+        let base = world
+            .create_entity()
+            .with(Light::Directional {
+                color: Rgb::new(1.0, 1.0, 1.0),
+            })
+            .build();
+        let asset = world
+            .create_entity()
+            .with(GltfAsset::default())
+            .with(Transform::pos(0.0, 0.0, 3.0))
+            .build();
+        crate::graph::world::add_edge(&mut world, base, asset);
+
+        let contents = ron::ser::to_string(&Scene::from_world(&world)).unwrap();
+        let scene: Scene = ron::de::from_str(&contents).unwrap();
+        assert_eq!(scene.entities.len(), 2);
+        assert_eq!(scene.entities[0].parent, None);
+        assert_eq!(scene.entities[1].parent, Some(0));
+
+        let mut loaded = scene_world();
+        scene.instantiate(&mut loaded);
+        let parents = loaded.read_storage::<Parent>();
+        let lights = loaded.read_storage::<Light>();
+        let load_assets = loaded.read_storage::<LoadGltfAsset>();
+        let (child_parent, _) = (&parents, &load_assets).join().next().unwrap();
+        assert!(lights.contains(child_parent.parent));
+    }
+}
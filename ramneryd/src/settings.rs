@@ -0,0 +1,347 @@
+//! User settings that are kept between runs. Currently the key bindings, which map buttons to
+//! named actions, e.g. `camera.forward`. They are loaded from a RON file with the action names as
+//! keys, where the actions that are not in the file keep their default binding:
+//!
+//! ```ron
+//! {
+//!     "camera.forward": Key(Up),
+//!     "camera.move": Mouse(Left),
+//! }
+//! ```
+//!
+//! The systems that own an input context build it from the bindings and rebuild it when they
+//! change, see KeyBindings::version.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::ecs::prelude::*;
+use crate::io::input::{Button, CurrentFrameExternalInputs, ExternalInput, KeyCode, MouseButton};
+
+#[derive(Debug, Error)]
+pub enum SettingsError {
+    #[error("Unknown action {0}")]
+    UnknownAction(String),
+    #[error("{button:?} is already bound to {action}")]
+    AlreadyBound { button: Button, action: String },
+    #[error("No file to save the key bindings to")]
+    NoPath,
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("RON error: {0}")]
+    Ron(#[from] ron::Error),
+}
+
+pub const CAMERA_FORWARD: &str = "camera.forward";
+pub const CAMERA_BACKWARD: &str = "camera.backward";
+pub const CAMERA_LEFT: &str = "camera.left";
+pub const CAMERA_RIGHT: &str = "camera.right";
+pub const CAMERA_UP: &str = "camera.up";
+pub const CAMERA_DOWN: &str = "camera.down";
+pub const CAMERA_MOVE: &str = "camera.move";
+pub const CAMERA_PAN: &str = "camera.pan";
+pub const CAMERA_SWITCH_CONTROLLER: &str = "camera.switch_controller";
+pub const CAMERA_BOOKMARK_1: &str = "camera.bookmark_1";
+pub const CAMERA_BOOKMARK_2: &str = "camera.bookmark_2";
+pub const CAMERA_BOOKMARK_3: &str = "camera.bookmark_3";
+pub const CAMERA_BOOKMARK_4: &str = "camera.bookmark_4";
+pub const CAMERA_BOOKMARK_5: &str = "camera.bookmark_5";
+pub const CAMERA_BOOKMARK_6: &str = "camera.bookmark_6";
+pub const CAMERA_BOOKMARK_7: &str = "camera.bookmark_7";
+pub const CAMERA_BOOKMARK_8: &str = "camera.bookmark_8";
+pub const CAMERA_BOOKMARK_9: &str = "camera.bookmark_9";
+pub const RENDER_MODE_SWITCH: &str = "render.switch_mode";
+pub const RENDER_BOUNDING_BOX_SWITCH: &str = "render.bounding_box";
+pub const RELOAD_SHADERS: &str = "render.reload_shaders";
+pub const CAPTURE_FRAME: &str = "render.capture_frame";
+pub const WIREFRAME_OVERLAY_SWITCH: &str = "render.wireframe_overlay";
+pub const GAME_STATE_SWITCH: &str = "game.pause";
+
+const DEFAULT_BINDINGS: &[(&str, Button)] = &[
+    (CAMERA_FORWARD, Button::Key(KeyCode::W)),
+    (CAMERA_BACKWARD, Button::Key(KeyCode::S)),
+    (CAMERA_LEFT, Button::Key(KeyCode::A)),
+    (CAMERA_RIGHT, Button::Key(KeyCode::D)),
+    (CAMERA_UP, Button::Key(KeyCode::E)),
+    (CAMERA_DOWN, Button::Key(KeyCode::Q)),
+    (CAMERA_MOVE, Button::Mouse(MouseButton::Right)),
+    (CAMERA_PAN, Button::Mouse(MouseButton::Middle)),
+    (CAMERA_SWITCH_CONTROLLER, Button::Key(KeyCode::T)),
+    (CAMERA_BOOKMARK_1, Button::Key(KeyCode::Key1)),
+    (CAMERA_BOOKMARK_2, Button::Key(KeyCode::Key2)),
+    (CAMERA_BOOKMARK_3, Button::Key(KeyCode::Key3)),
+    (CAMERA_BOOKMARK_4, Button::Key(KeyCode::Key4)),
+    (CAMERA_BOOKMARK_5, Button::Key(KeyCode::Key5)),
+    (CAMERA_BOOKMARK_6, Button::Key(KeyCode::Key6)),
+    (CAMERA_BOOKMARK_7, Button::Key(KeyCode::Key7)),
+    (CAMERA_BOOKMARK_8, Button::Key(KeyCode::Key8)),
+    (CAMERA_BOOKMARK_9, Button::Key(KeyCode::Key9)),
+    (RENDER_MODE_SWITCH, Button::Key(KeyCode::O)),
+    (RENDER_BOUNDING_BOX_SWITCH, Button::Key(KeyCode::P)),
+    (RELOAD_SHADERS, Button::Key(KeyCode::R)),
+    (CAPTURE_FRAME, Button::Key(KeyCode::C)),
+    (WIREFRAME_OVERLAY_SWITCH, Button::Key(KeyCode::L)),
+    (GAME_STATE_SWITCH, Button::Key(KeyCode::Escape)),
+];
+
+/// The button of each action. A button is bound to at most one action.
+pub struct KeyBindings {
+    bindings: BTreeMap<String, Button>,
+    /// Where the bindings are saved to
+    path: Option<PathBuf>,
+    version: u32,
+    /// The action that the next pressed button is bound to
+    rebinding: Option<String>,
+    error: Option<String>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            bindings: DEFAULT_BINDINGS
+                .iter()
+                .map(|(action, button)| (action.to_string(), *button))
+                .collect(),
+            path: None,
+            version: 0,
+            rebinding: None,
+            error: None,
+        }
+    }
+}
+
+impl KeyBindings {
+    /// The defaults, with the bindings in the file at `path` applied. The bindings are saved to
+    /// the same file.
+    pub fn load(path: &Path) -> Result<Self, SettingsError> {
+        let mut bindings = Self {
+            path: Some(path.to_path_buf()),
+            ..Default::default()
+        };
+        if !path.exists() {
+            return Ok(bindings);
+        }
+
+        let contents = std::fs::read_to_string(path)?;
+        let loaded: BTreeMap<String, Button> = ron::de::from_str(&contents)?;
+        bindings.apply(loaded);
+        Ok(bindings)
+    }
+
+    fn apply(&mut self, loaded: BTreeMap<String, Button>) {
+        // Unbind the buttons that are used by the loaded bindings first, so that swapped bindings
+        // don't conflict
+        for button in loaded.values() {
+            self.bindings.retain(|_, b| b != button);
+        }
+        for (action, button) in loaded {
+            if let Err(e) = self.set(&action, button) {
+                log::warn!("Ignoring the binding of {}: {}", action, e);
+            }
+        }
+        // The default buttons of actions that lost theirs are free again
+        for (action, button) in DEFAULT_BINDINGS {
+            if !self.bindings.contains_key(*action) && self.action_for(*button).is_none() {
+                self.bindings.insert(action.to_string(), *button);
+            }
+        }
+    }
+
+    /// Loads the file again, e.g. after it was edited outside of the engine. Returns the actions
+    /// whose button changed, with None for the actions that lost their button.
+    pub fn reload(&mut self) -> Result<Vec<(String, Option<Button>)>, SettingsError> {
+        let path = self.path.as_ref().ok_or(SettingsError::NoPath)?;
+        let loaded = Self::load(path)?;
+        Ok(self.replace(loaded.bindings))
+    }
+
+    fn replace(&mut self, bindings: BTreeMap<String, Button>) -> Vec<(String, Option<Button>)> {
+        let changed: Vec<(String, Option<Button>)> = DEFAULT_BINDINGS
+            .iter()
+            .filter(|(action, _)| self.bindings.get(*action) != bindings.get(*action))
+            .map(|(action, _)| (action.to_string(), bindings.get(*action).copied()))
+            .collect();
+        if !changed.is_empty() {
+            self.bindings = bindings;
+            self.version += 1;
+        }
+        changed
+    }
+
+    pub fn save(&self) -> Result<(), SettingsError> {
+        let path = self.path.as_ref().ok_or(SettingsError::NoPath)?;
+        let contents =
+            ron::ser::to_string_pretty(&self.bindings, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// Actions without a button can't be triggered
+    pub fn get(&self, action: &str) -> Option<Button> {
+        self.bindings.get(action).copied()
+    }
+
+    pub fn action_for(&self, button: Button) -> Option<&str> {
+        self.bindings
+            .iter()
+            .find(|(_, b)| **b == button)
+            .map(|(action, _)| action.as_str())
+    }
+
+    pub fn set(&mut self, action: &str, button: Button) -> Result<(), SettingsError> {
+        if !DEFAULT_BINDINGS.iter().any(|(a, _)| *a == action) {
+            return Err(SettingsError::UnknownAction(action.to_string()));
+        }
+        match self.action_for(button) {
+            Some(other) if other == action => return Ok(()),
+            Some(other) => {
+                return Err(SettingsError::AlreadyBound {
+                    button,
+                    action: other.to_string(),
+                })
+            }
+            None => (),
+        }
+
+        self.bindings.insert(action.to_string(), button);
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Incremented when a binding changes, so that the input contexts can be rebuilt
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+}
+
+/// Rebinding editor. Rebinding waits for the next button press, escape cancels.
+pub fn build_ui<'a>(
+    world: &mut World,
+    ui: &crate::render::ui::UiFrame<'a>,
+    pos: [f32; 2],
+) -> [f32; 2] {
+    let size = [300.0, 200.0];
+    let mut bindings = world.write_resource::<KeyBindings>();
+
+    if let Some(action) = bindings.rebinding.clone() {
+        let inputs = world.read_resource::<CurrentFrameExternalInputs>();
+        let pressed = inputs.0.iter().find_map(|i| match i {
+            ExternalInput::Press(button) => Some(*button),
+            _ => None,
+        });
+        match pressed {
+            Some(Button::Key(KeyCode::Escape)) => bindings.rebinding = None,
+            Some(button) => {
+                bindings.error = bindings.set(&action, button).err().map(|e| e.to_string());
+                bindings.rebinding = None;
+            }
+            None => (),
+        }
+    }
+
+    imgui::Window::new(imgui::im_str!("Key bindings"))
+        .position(pos, imgui::Condition::FirstUseEver)
+        .size(size, imgui::Condition::FirstUseEver)
+        .collapsed(true, imgui::Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let ui = ui.inner();
+            let actions: Vec<String> = bindings.bindings.keys().cloned().collect();
+            for action in actions {
+                let label = if bindings.rebinding.as_ref() == Some(&action) {
+                    imgui::im_str!("Press a button...##{}", action)
+                } else {
+                    imgui::im_str!("{:?}##{}", bindings.bindings[&action], action)
+                };
+                if ui.button(&label, [120.0, 0.0]) {
+                    bindings.rebinding = Some(action.clone());
+                    bindings.error = None;
+                }
+                ui.same_line(0.0);
+                ui.text(&action);
+            }
+
+            if let Some(error) = &bindings.error {
+                ui.text_colored([1.0, 0.3, 0.3, 1.0], error);
+            }
+
+            if ui.button(imgui::im_str!("Reset to defaults"), [0.0, 0.0]) {
+                let version = bindings.version;
+                *bindings = KeyBindings {
+                    path: bindings.path.take(),
+                    version: version + 1,
+                    ..Default::default()
+                };
+            }
+            if bindings.path.is_some() {
+                ui.same_line(0.0);
+                if ui.button(imgui::im_str!("Save"), [0.0, 0.0]) {
+                    if let Err(e) = bindings.save() {
+                        log::error!("Failed to save the key bindings: {}", e);
+                    }
+                }
+            }
+        });
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loaded_bindings_override_defaults() {
+        let mut bindings = KeyBindings::default();
+        let loaded: BTreeMap<String, Button> = ron::de::from_str(
+            r#"{ "camera.forward": Key(Up), "camera.move": Key(W), "unknown": Key(X) }"#,
+        )
+        .unwrap();
+        bindings.apply(loaded);
+
+        assert_eq!(bindings.get(CAMERA_FORWARD), Some(Button::Key(KeyCode::Up)));
+        assert_eq!(bindings.get(CAMERA_MOVE), Some(Button::Key(KeyCode::W)));
+        assert_eq!(bindings.get(CAMERA_LEFT), Some(Button::Key(KeyCode::A)));
+        assert_eq!(bindings.get("unknown"), None);
+        // Right mouse button is no longer used
+        assert_eq!(bindings.action_for(Button::Mouse(MouseButton::Right)), None);
+    }
+
+    #[test]
+    fn button_is_bound_once() {
+        let mut bindings = KeyBindings::default();
+        let version = bindings.version();
+        assert!(matches!(
+            bindings.set(CAMERA_FORWARD, Button::Key(KeyCode::S)),
+            Err(SettingsError::AlreadyBound { .. })
+        ));
+        assert_eq!(bindings.version(), version);
+
+        bindings
+            .set(CAMERA_FORWARD, Button::Key(KeyCode::Up))
+            .unwrap();
+        assert_eq!(bindings.get(CAMERA_FORWARD), Some(Button::Key(KeyCode::Up)));
+        assert_eq!(bindings.version(), version + 1);
+    }
+
+    #[test]
+    fn replacing_reports_changed_actions() {
+        let mut bindings = KeyBindings::default();
+        let mut edited = KeyBindings::default();
+        let loaded: BTreeMap<String, Button> =
+            ron::de::from_str(r#"{ "camera.forward": Key(Up) }"#).unwrap();
+        edited.apply(loaded);
+
+        let version = bindings.version();
+        assert!(bindings.replace(KeyBindings::default().bindings).is_empty());
+        assert_eq!(bindings.version(), version);
+
+        assert_eq!(
+            bindings.replace(edited.bindings),
+            vec![(CAMERA_FORWARD.to_string(), Some(Button::Key(KeyCode::Up)))]
+        );
+        assert_eq!(bindings.get(CAMERA_FORWARD), Some(Button::Key(KeyCode::Up)));
+        assert_eq!(bindings.version(), version + 1);
+    }
+}
@@ -0,0 +1,433 @@
+//! Queries for the entities near a point or in a volume, e.g. to select everything within a radius
+//! in the editor or to find the closest object in a gameplay system. The world space bounding boxes
+//! of the entities are kept in a bounding volume hierarchy, the SpatialIndex resource, which is
+//! built again each frame once the transforms have been propagated. Results are in no particular
+//! order.
+//!
+//! The hierarchy holds any items with a bounding box, e.g. the triangles of the path tracer. It can
+//! be flattened to an array of nodes, for shaders to traverse.
+
+use crate::ecs::prelude::*;
+use crate::math::{BoundingBox, ModelMatrix, Vec3};
+
+/// Leaves with at most this many items are not split
+const MAX_LEAF_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+enum NodeKind {
+    /// The range of Bvh::items
+    Leaf {
+        start: usize,
+        end: usize,
+    },
+    Inner {
+        left: usize,
+        right: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    bbox: BoundingBox,
+    kind: NodeKind,
+}
+
+fn center(bbox: &BoundingBox) -> Vec3 {
+    (bbox.min + bbox.max) * 0.5
+}
+
+/// Zero if the point is in the box
+fn distance_squared(bbox: &BoundingBox, point: Vec3) -> f32 {
+    let closest = Vec3::partial_max(bbox.min, Vec3::partial_min(point, bbox.max));
+    (closest - point).magnitude_squared()
+}
+
+fn overlaps(a: &BoundingBox, b: &BoundingBox) -> bool {
+    a.min.x <= b.max.x
+        && b.min.x <= a.max.x
+        && a.min.y <= b.max.y
+        && b.min.y <= a.max.y
+        && a.min.z <= b.max.z
+        && b.min.z <= a.max.z
+}
+
+/// What a node of Bvh::flatten holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlatNodeKind {
+    /// The range of Bvh::items
+    Leaf { start: usize, end: usize },
+    /// The children are this node and the one after it
+    Inner { first_child: usize },
+}
+
+/// A node of Bvh::flatten
+#[derive(Debug, Clone, Copy)]
+pub struct FlatNode {
+    pub bbox: BoundingBox,
+    /// The root is at depth 0
+    pub depth: usize,
+    pub kind: FlatNodeKind,
+}
+
+/// A bounding volume hierarchy of items with a bounding box
+#[derive(Debug)]
+pub struct Bvh<T> {
+    items: Vec<(T, BoundingBox)>,
+    /// The root is the first node, if there are any items
+    nodes: Vec<Node>,
+}
+
+/// The entities with a bounding box, see the module docs
+pub type SpatialIndex = Bvh<Entity>;
+
+impl<T> Default for Bvh<T> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            nodes: Vec::new(),
+        }
+    }
+}
+
+impl<T: Copy> Bvh<T> {
+    /// The boxes are in world space
+    pub fn new(items: Vec<(T, BoundingBox)>) -> Self {
+        let mut index = Self {
+            items,
+            nodes: Vec::new(),
+        };
+        if !index.items.is_empty() {
+            index.build(0, index.items.len());
+        }
+        index
+    }
+
+    /// Splits the items at the median of the longest axis of their centers. Returns the node.
+    fn build(&mut self, start: usize, end: usize) -> usize {
+        let items = &mut self.items[start..end];
+        let mut bbox = items[0].1;
+        let mut centers = BoundingBox {
+            min: center(&items[0].1),
+            max: center(&items[0].1),
+        };
+        for (_, item) in items.iter() {
+            bbox.combine(*item);
+            let c = center(item);
+            centers.combine(BoundingBox { min: c, max: c });
+        }
+
+        let idx = self.nodes.len();
+        self.nodes.push(Node {
+            bbox,
+            kind: NodeKind::Leaf { start, end },
+        });
+        if items.len() <= MAX_LEAF_SIZE {
+            return idx;
+        }
+
+        let extent = centers.max - centers.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        items.sort_by(|(_, a), (_, b)| {
+            center(a)[axis]
+                .partial_cmp(&center(b)[axis])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let mid = start + (end - start) / 2;
+        let left = self.build(start, mid);
+        let right = self.build(mid, end);
+        self.nodes[idx].kind = NodeKind::Inner { left, right };
+        idx
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The items in the order of the leaf ranges of the nodes
+    pub fn items(&self) -> &[(T, BoundingBox)] {
+        &self.items
+    }
+
+    /// The nodes in breadth first order, starting with the root, so the nodes of each depth are
+    /// next to each other and after those of the depths above them. The boxes of the nodes of one
+    /// depth can be computed from those of the depth below it, e.g. to refit the hierarchy after
+    /// its items have moved.
+    pub fn flatten(&self) -> Vec<FlatNode> {
+        let mut flat = Vec::with_capacity(self.nodes.len());
+        let mut queue = std::collections::VecDeque::new();
+        if !self.nodes.is_empty() {
+            queue.push_back((0, 0));
+        }
+        // Children are queued in pairs, after all the nodes that are queued before them
+        let mut next = 1;
+        while let Some((idx, depth)) = queue.pop_front() {
+            let node = &self.nodes[idx];
+            let kind = match node.kind {
+                NodeKind::Leaf { start, end } => FlatNodeKind::Leaf { start, end },
+                NodeKind::Inner { left, right } => {
+                    queue.push_back((left, depth + 1));
+                    queue.push_back((right, depth + 1));
+                    next += 2;
+                    FlatNodeKind::Inner {
+                        first_child: next - 2,
+                    }
+                }
+            };
+            flat.push(FlatNode {
+                bbox: node.bbox,
+                depth,
+                kind,
+            });
+        }
+        flat
+    }
+
+    /// The items in the leaves whose boxes pass the test, if their own box also passes it
+    fn find(&self, test: impl Fn(&BoundingBox) -> bool) -> Vec<T> {
+        let mut found = Vec::new();
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            if !test(&node.bbox) {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf { start, end } => found.extend(
+                    self.items[start..end]
+                        .iter()
+                        .filter(|(_, bbox)| test(bbox))
+                        .map(|(item, _)| *item),
+                ),
+                NodeKind::Inner { left, right } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+        found
+    }
+
+    /// The items whose boxes overlap the sphere
+    pub fn overlap_sphere(&self, center: Vec3, radius: f32) -> Vec<T> {
+        self.find(|bbox| distance_squared(bbox, center) <= radius * radius)
+    }
+
+    /// The items whose boxes overlap the box, which is in world space
+    pub fn overlap_box(&self, bbox: &BoundingBox) -> Vec<T> {
+        self.find(|other| overlaps(bbox, other))
+    }
+
+    /// The item whose box is closest to the point, and the distance to it. The distance is zero if
+    /// the point is in the box.
+    pub fn nearest(&self, point: Vec3) -> Option<(T, f32)> {
+        self.nearest_where(point, |_| true)
+    }
+
+    /// The same as nearest, but only for the items that pass the filter, e.g. to skip the entity
+    /// that is asking
+    pub fn nearest_where(
+        &self,
+        point: Vec3,
+        mut filter: impl FnMut(T) -> bool,
+    ) -> Option<(T, f32)> {
+        let mut best: Option<(T, f32)> = None;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        let is_closer = |best: &Option<(T, f32)>, d: f32| best.map_or(true, |(_, b)| d < b);
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            if !is_closer(&best, distance_squared(&node.bbox, point)) {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf { start, end } => {
+                    for (item, bbox) in self.items[start..end].iter() {
+                        let d = distance_squared(bbox, point);
+                        if is_closer(&best, d) && filter(*item) {
+                            best = Some((*item, d));
+                        }
+                    }
+                }
+                NodeKind::Inner { left, right } => {
+                    // The closer child is visited first, so that more of the other can be skipped
+                    let dl = distance_squared(&self.nodes[left].bbox, point);
+                    let dr = distance_squared(&self.nodes[right].bbox, point);
+                    if dl < dr {
+                        stack.push(right);
+                        stack.push(left);
+                    } else {
+                        stack.push(left);
+                        stack.push(right);
+                    }
+                }
+            }
+        }
+        best.map(|(item, d)| (item, d.sqrt()))
+    }
+}
+
+pub struct UpdateSpatialIndex;
+
+impl UpdateSpatialIndex {
+    pub const ID: &'static str = "UpdateSpatialIndex";
+}
+
+impl<'a> System<'a> for UpdateSpatialIndex {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, BoundingBox>,
+        ReadStorage<'a, ModelMatrix>,
+        Write<'a, SpatialIndex>,
+    );
+
+    fn run(&mut self, (entities, bboxes, model_matrices, mut index): Self::SystemData) {
+        profiling::scope!("build spatial index");
+        let items = (&entities, &bboxes, &model_matrices)
+            .join()
+            .map(|(ent, bbox, model)| (ent, bbox.transformed(&model.0)))
+            .collect();
+        *index = SpatialIndex::new(items);
+    }
+}
+
+/// These systems depend on the propagated transforms
+pub fn register_post_transform_systems<'a, 'b>(
+    builder: ExecutorBuilder<'a, 'b>,
+) -> ExecutorBuilder<'a, 'b> {
+    builder.with(
+        UpdateSpatialIndex,
+        UpdateSpatialIndex::ID,
+        &[
+            crate::graph::TransformPropagation::ID,
+            crate::anim::UpdateDeformedBounds::ID,
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_match_a_linear_search() {
+        let mut world = World::new();
+        let items: Vec<(Entity, BoundingBox)> = (0..50)
+            .map(|i| {
+                let min = Vec3::new((i % 5) as f32 * 3.0, (i / 5) as f32 * 2.0, (i % 3) as f32);
+                let bbox = BoundingBox {
+                    min,
+                    max: min + Vec3::new(1.0, 1.0, 1.0),
+                };
+                (world.create_entity().build(), bbox)
+            })
+            .collect();
+        let index = SpatialIndex::new(items.clone());
+        assert_eq!(index.len(), 50);
+
+        let sorted = |mut ents: Vec<Entity>| {
+            ents.sort();
+            ents
+        };
+        let linear = |test: &dyn Fn(&BoundingBox) -> bool| {
+            sorted(
+                items
+                    .iter()
+                    .filter(|(_, bbox)| test(bbox))
+                    .map(|(ent, _)| *ent)
+                    .collect(),
+            )
+        };
+
+        let (center, radius) = (Vec3::new(6.0, 9.0, 0.5), 3.0);
+        let in_sphere = index.overlap_sphere(center, radius);
+        assert!(!in_sphere.is_empty());
+        assert_eq!(
+            sorted(in_sphere),
+            linear(&|bbox| distance_squared(bbox, center) <= radius * radius)
+        );
+
+        let query = BoundingBox {
+            min: Vec3::new(2.5, 3.5, 0.0),
+            max: Vec3::new(7.5, 6.0, 0.5),
+        };
+        assert_eq!(
+            sorted(index.overlap_box(&query)),
+            linear(&|bbox| overlaps(&query, bbox))
+        );
+
+        let point = Vec3::new(20.0, 4.2, 0.5);
+        let (nearest, distance) = index.nearest(point).unwrap();
+        let closest = items
+            .iter()
+            .map(|(_, bbox)| distance_squared(bbox, point).sqrt())
+            .fold(f32::MAX, f32::min);
+        assert_eq!(distance, closest);
+        let (other, _) = index.nearest_where(point, |ent| ent != nearest).unwrap();
+        assert_ne!(other, nearest);
+
+        assert!(SpatialIndex::default().nearest(point).is_none());
+    }
+
+    #[test]
+    fn flattened_nodes_are_ordered_by_depth() {
+        let boxes: Vec<BoundingBox> = (0..37)
+            .map(|i| {
+                let min = Vec3::new((i % 7) as f32, (i / 7) as f32 * 2.0, (i % 4) as f32 * 0.5);
+                BoundingBox {
+                    min,
+                    max: min + Vec3::new(0.5, 1.0, 0.25),
+                }
+            })
+            .collect();
+        let bvh = Bvh::new(boxes.iter().copied().enumerate().collect());
+        let flat = bvh.flatten();
+        assert_eq!(flat[0].depth, 0);
+
+        let contains = |outer: &BoundingBox, inner: &BoundingBox| {
+            outer.min.x <= inner.min.x
+                && outer.min.y <= inner.min.y
+                && outer.min.z <= inner.min.z
+                && outer.max.x >= inner.max.x
+                && outer.max.y >= inner.max.y
+                && outer.max.z >= inner.max.z
+        };
+        let mut in_leaves = vec![0; boxes.len()];
+        for (idx, node) in flat.iter().enumerate() {
+            if idx > 0 {
+                assert!(flat[idx - 1].depth <= node.depth);
+            }
+            match node.kind {
+                FlatNodeKind::Leaf { start, end } => {
+                    for (item, bbox) in bvh.items()[start..end].iter() {
+                        in_leaves[*item] += 1;
+                        assert!(contains(&node.bbox, bbox));
+                    }
+                }
+                FlatNodeKind::Inner { first_child } => {
+                    for child in &flat[first_child..first_child + 2] {
+                        assert_eq!(child.depth, node.depth + 1);
+                        assert!(contains(&node.bbox, &child.bbox));
+                    }
+                }
+            }
+        }
+        assert!(in_leaves.iter().all(|n| *n == 1));
+        assert!(Bvh::<usize>::default().flatten().is_empty());
+    }
+}
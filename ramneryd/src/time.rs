@@ -60,7 +60,6 @@ impl Time {
         self.delta
     }
 
-    #[allow(dead_code)]
     pub fn elapsed_real(&self) -> DeltaTime {
         DeltaTime(Instant::now() - self.start)
     }
@@ -0,0 +1,126 @@
+//! Problems that the engine recovered from but that should be fixed in the content, e.g. a texture
+//! that could not be loaded and was replaced by the error texture. They are listed in the Warnings
+//! window until they are cleared.
+
+use std::path::{Path, PathBuf};
+
+use crate::ecs::prelude::*;
+use crate::render::ui::UiFrame;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// What kind of problem it is, e.g. "Texture"
+    pub category: &'static str,
+    /// The file with the problem, if there is one
+    pub path: Option<PathBuf>,
+    pub message: String,
+    /// The number of times the same warning was pushed
+    pub count: u32,
+}
+
+/// The warnings in the order they were first pushed
+#[derive(Debug, Default)]
+pub struct Warnings {
+    warnings: Vec<Warning>,
+}
+
+impl Warnings {
+    /// Warnings with the same category, path and message are only listed once
+    pub fn push(&mut self, category: &'static str, path: Option<&Path>, message: String) {
+        let existing = self
+            .warnings
+            .iter_mut()
+            .find(|w| w.category == category && w.path.as_deref() == path && w.message == message);
+        match existing {
+            Some(warning) => warning.count += 1,
+            None => self.warnings.push(Warning {
+                category,
+                path: path.map(Path::to_path_buf),
+                message,
+                count: 1,
+            }),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Warning> {
+        self.warnings.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.warnings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.warnings.clear();
+    }
+}
+
+pub(crate) fn build_ui<'a>(world: &mut World, ui: &UiFrame<'a>, pos: [f32; 2]) -> [f32; 2] {
+    let size = [400.0, 200.0];
+
+    imgui::Window::new(imgui::im_str!("Warnings"))
+        .position(pos, imgui::Condition::FirstUseEver)
+        .size(size, imgui::Condition::FirstUseEver)
+        .collapsed(true, imgui::Condition::FirstUseEver)
+        .build(ui.inner(), || {
+            let ui = ui.inner();
+            let mut warnings = world.write_resource::<Warnings>();
+            ui.text(imgui::im_str!("{} warnings", warnings.len()));
+            if !warnings.is_empty() {
+                ui.same_line(0.0);
+                if ui.button(imgui::im_str!("Clear"), [0.0, 0.0]) {
+                    warnings.clear();
+                    return;
+                }
+            }
+            ui.separator();
+
+            for warning in warnings.iter() {
+                let path = warning
+                    .path
+                    .as_ref()
+                    .map(|p| format!("{}: ", p.display()))
+                    .unwrap_or_default();
+                let count = if warning.count > 1 {
+                    format!(" ({} times)", warning.count)
+                } else {
+                    String::new()
+                };
+                ui.text_wrapped(&imgui::im_str!(
+                    "[{}] {}{}{}",
+                    warning.category,
+                    path,
+                    warning.message,
+                    count
+                ));
+            }
+        });
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_warning_is_counted() {
+        let mut warnings = Warnings::default();
+        let path = Path::new("missing.png");
+        warnings.push("Texture", Some(path), String::from("not found"));
+        warnings.push("Texture", Some(path), String::from("not found"));
+        warnings.push(
+            "Texture",
+            Some(Path::new("other.png")),
+            String::from("not found"),
+        );
+        assert_eq!(warnings.len(), 2);
+        let first = warnings.iter().next().unwrap();
+        assert_eq!(first.path.as_deref(), Some(path));
+        assert_eq!(first.count, 2);
+    }
+}
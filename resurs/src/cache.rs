@@ -24,6 +24,19 @@ impl<D: Hash + Eq, T> Cache<D, T> {
         self.cache.insert(desc, h);
     }
 
+    /// Remove the descriptor that maps to h, if any. Linear in the size of the cache.
+    pub fn remove_handle(&mut self, h: &Handle<T>) -> Option<D> {
+        let mut removed = None;
+        for (desc, cached) in std::mem::take(&mut self.cache) {
+            if cached == *h {
+                removed = Some(desc);
+            } else {
+                self.cache.insert(desc, cached);
+            }
+        }
+        removed
+    }
+
     pub fn len(&self) -> usize {
         self.cache.len()
     }
@@ -32,8 +45,11 @@ impl<D: Hash + Eq, T> Cache<D, T> {
         self.cache.is_empty()
     }
 
+    /// Iterate in handle order, so that the order is the same between runs
     pub fn iter(&mut self) -> impl Iterator<Item = (&D, &Handle<T>)> {
-        self.cache.iter()
+        let mut entries: Vec<_> = self.cache.iter().collect();
+        entries.sort_by_key(|(_, h)| **h);
+        entries.into_iter()
     }
 }
 
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::hash::Hash;
 
 use super::cache::*;
@@ -16,6 +17,7 @@ where
 {
     cache: Cache<ResourceDescriptor, Resource>,
     storage: Storage<Resource>,
+    ref_counts: HashMap<Handle<Resource>, usize>,
     stats: Stats,
 }
 
@@ -52,6 +54,7 @@ where
                 h
             }
         };
+        *self.ref_counts.entry(h).or_insert(0) += 1;
 
         Ok(h)
     }
@@ -63,10 +66,37 @@ where
     pub fn add(&mut self, descriptor: ResourceDescriptor, r: Resource) -> Handle<Resource> {
         let h = self.storage.add(r);
         self.cache.add(descriptor, h);
+        self.ref_counts.insert(h, 1);
 
         h
     }
 
+    /// Each handle returned from get_or_add or add holds a reference to the resource. Releasing the
+    /// last one removes the resource from both the storage and the cache and returns it.
+    pub fn release(&mut self, h: &Handle<Resource>) -> Option<Resource> {
+        let count = self.ref_counts.get_mut(h)?;
+        *count -= 1;
+        if *count > 0 {
+            return None;
+        }
+
+        self.ref_counts.remove(h);
+        self.cache.remove_handle(h);
+        self.storage.remove(*h)
+    }
+
+    pub fn ref_count(&self, h: &Handle<Resource>) -> usize {
+        self.ref_counts.get(h).copied().unwrap_or(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+    }
+
     pub fn has(&self, h: &Handle<Resource>) -> bool {
         self.get(h).is_some()
     }
@@ -78,6 +108,7 @@ where
     /// Iterate over the storage contents mutably.
     pub fn iter_mut(&mut self) -> impl Iterator<Item = (&ResourceDescriptor, &mut Resource)> {
         assert_eq!(self.cache.len(), self.storage.len());
+        // Both are in handle order, so the descriptors line up with their resources
         self.cache
             .iter()
             .zip(self.storage.iter_mut())
@@ -94,7 +125,56 @@ where
         Self {
             cache: Default::default(),
             storage: Default::default(),
+            ref_counts: Default::default(),
             stats: Default::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iter_mut_pairs_descriptors_with_resources() {
+        let mut storage = CachedStorage::<String, usize>::new();
+        let descriptors = (0..64)
+            .map(|i| format!("resource{}", i))
+            .collect::<Vec<_>>();
+        for (i, d) in descriptors.iter().enumerate() {
+            storage.add(d.clone(), i);
+        }
+
+        let contents = storage
+            .iter_mut()
+            .map(|(d, r)| (d.clone(), *r))
+            .collect::<Vec<_>>();
+        let expected = descriptors.into_iter().zip(0..).collect::<Vec<_>>();
+        assert_eq!(contents, expected);
+    }
+
+    #[test]
+    fn release_removes_last_reference() {
+        let mut storage = CachedStorage::<String, usize>::new();
+        let create = |_: &String| -> Result<usize, ()> { Ok(7) };
+        let h0 = storage.get_or_add(String::from("a"), create).unwrap();
+        let h1 = storage.get_or_add(String::from("a"), create).unwrap();
+        assert_eq!(h0, h1);
+        assert_eq!(storage.ref_count(&h0), 2);
+
+        assert_eq!(storage.release(&h0), None);
+        assert!(storage.has(&h0));
+        assert_eq!(storage.cached(&String::from("a")), Some(h0));
+
+        assert_eq!(storage.release(&h1), Some(7));
+        assert!(!storage.has(&h0));
+        assert!(storage.is_empty());
+        assert_eq!(storage.cached(&String::from("a")), None);
+        assert_eq!(storage.release(&h1), None);
+
+        // The same descriptor creates a new resource after it has been released
+        let h2 = storage.get_or_add(String::from("a"), create).unwrap();
+        assert_ne!(h2, h0);
+        assert_eq!(storage.ref_count(&h2), 1);
+    }
+}
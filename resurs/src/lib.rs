@@ -36,6 +36,19 @@ impl<T> std::cmp::PartialEq for Handle<T> {
 
 impl<T> std::cmp::Eq for Handle<T> {}
 
+impl<T> std::cmp::PartialOrd for Handle<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Ordered by slot index, which is the order the resources are iterated in their storage
+impl<T> std::cmp::Ord for Handle<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
 impl<T> std::hash::Hash for Handle<T> {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.id.hash(state);
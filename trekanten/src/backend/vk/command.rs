@@ -5,11 +5,13 @@ use thiserror::Error;
 
 use super::device::{Device, HasVkDevice, VkDeviceHandle};
 use super::framebuffer::Framebuffer;
+use super::query::QueryPool;
 use super::queue::QueueFamily;
 use super::render_pass::RenderPass;
 
 use crate::descriptor::DescriptorSet;
 use crate::mem::{IndexBuffer, VertexBuffer};
+use crate::pipeline::ComputePipeline;
 use crate::pipeline::GraphicsPipeline;
 use crate::pipeline::Pipeline;
 use crate::pipeline::ShaderStage;
@@ -261,6 +263,20 @@ impl CommandBuffer {
         self
     }
 
+    pub fn bind_compute_pipeline(&mut self, compute_pipeline: &ComputePipeline) -> &mut Self {
+        assert!(self.queue_flags.contains(vk::QueueFlags::COMPUTE));
+
+        unsafe {
+            self.vk_device.cmd_bind_pipeline(
+                self.vk_cmd_buffer,
+                ComputePipeline::BIND_POINT,
+                *compute_pipeline.vk_pipeline(),
+            );
+        }
+
+        self
+    }
+
     pub fn bind_vertex_buffer(&mut self, buffer: &VertexBuffer, offset: u64) -> &mut Self {
         assert!(self.queue_flags.contains(vk::QueueFlags::GRAPHICS));
 
@@ -332,6 +348,51 @@ impl CommandBuffer {
         self
     }
 
+    pub fn bind_compute_descriptor_set(
+        &mut self,
+        idx: u32,
+        set: &DescriptorSet,
+        pipeline: &ComputePipeline,
+    ) -> &mut Self {
+        assert!(self.queue_flags.contains(vk::QueueFlags::COMPUTE));
+
+        let sets = [*set.vk_descriptor_set()];
+        unsafe {
+            self.vk_device.cmd_bind_descriptor_sets(
+                self.vk_cmd_buffer,
+                ComputePipeline::BIND_POINT,
+                *pipeline.vk_pipeline_layout(),
+                idx,
+                &sets,
+                &[],
+            );
+        }
+
+        self
+    }
+
+    /// Has to be recorded outside of a render pass
+    pub fn dispatch(&mut self, x: u32, y: u32, z: u32) -> &mut Self {
+        assert!(self.queue_flags.contains(vk::QueueFlags::COMPUTE));
+
+        unsafe {
+            self.vk_device.cmd_dispatch(self.vk_cmd_buffer, x, y, z);
+        }
+
+        self
+    }
+
+    pub fn draw(&mut self, n_vertices: u32) -> &mut Self {
+        assert!(self.queue_flags.contains(vk::QueueFlags::GRAPHICS));
+
+        unsafe {
+            self.vk_device
+                .cmd_draw(self.vk_cmd_buffer, n_vertices, 1, 0, 0);
+        }
+
+        self
+    }
+
     pub fn draw_indexed(
         &mut self,
         n_vertices: u32,
@@ -408,6 +469,64 @@ impl CommandBuffer {
         self
     }
 
+    /// dst has to be in TRANSFER_DST_OPTIMAL
+    pub fn copy_buffer_to_image_regions(
+        &mut self,
+        src: &vk::Buffer,
+        dst: &vk::Image,
+        regions: &[vk::BufferImageCopy],
+    ) -> &mut Self {
+        unsafe {
+            self.vk_device.cmd_copy_buffer_to_image(
+                self.vk_cmd_buffer,
+                *src,
+                *dst,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                regions,
+            );
+        }
+
+        self
+    }
+
+    /// Copies the color aspect of src, which has to be in TRANSFER_SRC_OPTIMAL, into tightly packed rows in dst
+    pub fn copy_image_to_buffer(
+        &mut self,
+        src: &vk::Image,
+        dst: &vk::Buffer,
+        extent: &util::Extent2D,
+    ) -> &mut Self {
+        let info = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+        };
+
+        unsafe {
+            self.vk_device.cmd_copy_image_to_buffer(
+                self.vk_cmd_buffer,
+                *src,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                *dst,
+                &[info],
+            );
+        }
+
+        self
+    }
+
     pub fn copy_image(
         &mut self,
         src: &vk::Image,
@@ -468,6 +587,33 @@ impl CommandBuffer {
         self
     }
 
+    /// Makes all writes of `src_stage` visible to the reads of `dst_stage`, e.g. the vertices
+    /// written by a compute shader to the vertex input of the draws after it
+    pub fn memory_barrier(
+        &mut self,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) -> &mut Self {
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access);
+        unsafe {
+            self.vk_device.cmd_pipeline_barrier(
+                self.vk_cmd_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[*barrier],
+                &[],
+                &[],
+            );
+        }
+
+        self
+    }
+
     pub fn blit_image(
         &mut self,
         src: &vk::Image,
@@ -490,22 +636,67 @@ impl CommandBuffer {
         self
     }
 
+    /// Has to be recorded outside of a render pass
+    pub fn reset_query_pool(&mut self, pool: &QueryPool) -> &mut Self {
+        unsafe {
+            self.vk_device.cmd_reset_query_pool(
+                self.vk_cmd_buffer,
+                *pool.vk_query_pool(),
+                0,
+                pool.count(),
+            );
+        }
+
+        self
+    }
+
+    /// Writes the timestamp when all previous commands have reached `stage`
+    pub fn write_timestamp(
+        &mut self,
+        pool: &QueryPool,
+        query: u32,
+        stage: vk::PipelineStageFlags,
+    ) -> &mut Self {
+        unsafe {
+            self.vk_device.cmd_write_timestamp(
+                self.vk_cmd_buffer,
+                stage,
+                *pool.vk_query_pool(),
+                query,
+            );
+        }
+
+        self
+    }
+
     pub fn bind_push_constant<V: Copy>(
         &mut self,
         pipeline: &GraphicsPipeline,
         stage: ShaderStage,
         v: &V,
+    ) -> &mut Self {
+        self.push_constants(*pipeline.vk_pipeline_layout(), stage, v)
+    }
+
+    pub fn bind_compute_push_constant<V: Copy>(
+        &mut self,
+        pipeline: &ComputePipeline,
+        v: &V,
+    ) -> &mut Self {
+        self.push_constants(*pipeline.vk_pipeline_layout(), ShaderStage::COMPUTE, v)
+    }
+
+    fn push_constants<V: Copy>(
+        &mut self,
+        layout: vk::PipelineLayout,
+        stage: ShaderStage,
+        v: &V,
     ) -> &mut Self {
         let bytes = util::as_bytes(v);
         assert!(bytes.len() <= 128);
         unsafe {
-            self.vk_device.cmd_push_constants(
-                self.vk_cmd_buffer,
-                *pipeline.vk_pipeline_layout(),
-                stage.into(),
-                0,
-                bytes,
-            )
+            self.vk_device
+                .cmd_push_constants(self.vk_cmd_buffer, layout, stage.into(), 0, bytes)
         }
 
         self
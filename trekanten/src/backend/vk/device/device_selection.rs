@@ -1,4 +1,4 @@
-use ash::version::InstanceV1_0;
+use ash::version::{InstanceV1_0, InstanceV1_1};
 use ash::vk;
 
 use std::ffi::CStr;
@@ -42,8 +42,12 @@ fn log_device(instance: &Instance, device: &vk::PhysicalDevice) {
     });
 }
 
-fn required_device_extensions() -> Vec<CString> {
-    vec![ash::extensions::khr::Swapchain::name().to_owned()]
+fn required_device_extensions(surface: Option<&Surface>) -> Vec<CString> {
+    if surface.is_some() {
+        vec![ash::extensions::khr::Swapchain::name().to_owned()]
+    } else {
+        Vec::new()
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -97,7 +101,7 @@ impl TryFrom<QueueFamiliesQuery> for QueueFamilies {
 fn find_queue_families(
     instance: &Instance,
     device: &vk::PhysicalDevice,
-    surface: &Surface,
+    surface: Option<&Surface>,
 ) -> Result<QueueFamiliesQuery, DeviceCreationError> {
     log::trace!("Checking queues for:");
     log_device(instance, device);
@@ -121,7 +125,12 @@ fn find_queue_families(
 
     families.graphics = queue_fam_props.iter().enumerate().find_map(|(i, fam)| {
         assert!(i <= u32::MAX as usize);
-        if fam.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
+        // Compute passes are recorded to the command buffers of the frames, see
+        // Frame::begin_compute_pass. There is always a family with both.
+        if fam
+            .queue_flags
+            .contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+        {
             Some(QueueSelection {
                 family: QueueFamily {
                     props: *fam,
@@ -143,9 +152,12 @@ fn find_queue_families(
             // If this is the same family as graphics, we use the same queue as we don't multithread
             // the access to the queue for drawing/presenting
             let same_as_gfx = gfx_index as usize == i;
-            if surface.is_supported_by(device, i as u32)?
-                && (same_as_gfx || families.present.is_none())
-            {
+            let supports_present = match surface {
+                Some(surface) => surface.is_supported_by(device, i as u32)?,
+                // Nothing is presented without a surface, so just use the graphics queue
+                None => same_as_gfx,
+            };
+            if supports_present && (same_as_gfx || families.present.is_none()) {
                 families.present = Some(QueueSelection {
                     family: QueueFamily {
                         props: *fam,
@@ -245,6 +257,62 @@ fn required_device_features() -> vk::PhysicalDeviceFeatures {
     }
 }
 
+/// Sparse textures are only used if the device supports partially resident 2D images, and shaders
+/// that check if what they sample is resident. The pages are bound on the graphics queue.
+fn supports_sparse_textures(
+    instance: &Instance,
+    phys_device: &vk::PhysicalDevice,
+    graphics: &QueueFamily,
+) -> bool {
+    let supported = unsafe {
+        instance
+            .vk_instance()
+            .get_physical_device_features(*phys_device)
+    };
+
+    supported.sparse_binding == vk::TRUE
+        && supported.sparse_residency_image2_d == vk::TRUE
+        && supported.shader_resource_residency == vk::TRUE
+        && graphics
+            .props
+            .queue_flags
+            .contains(vk::QueueFlags::SPARSE_BINDING)
+}
+
+/// Lines wider than one pixel, e.g. for wireframes. Optional, as some devices don't support them.
+fn supports_wide_lines(instance: &Instance, phys_device: &vk::PhysicalDevice) -> bool {
+    let supported = unsafe {
+        instance
+            .vk_instance()
+            .get_physical_device_features(*phys_device)
+    };
+
+    supported.wide_lines == vk::TRUE
+}
+
+/// Bindless textures need shaders that index sampler arrays, also with indices that differ between
+/// invocations, and an array that is partially bound and can be updated while it is in use, see
+/// crate::bindless
+fn supports_bindless_textures(instance: &Instance, phys_device: &vk::PhysicalDevice) -> bool {
+    let mut indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+    let dynamic_indexing = {
+        let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut indexing);
+        unsafe {
+            instance
+                .vk_instance()
+                .get_physical_device_features2(*phys_device, &mut features);
+        }
+        features
+            .features
+            .shader_sampled_image_array_dynamic_indexing
+    };
+
+    dynamic_indexing == vk::TRUE
+        && indexing.shader_sampled_image_array_non_uniform_indexing == vk::TRUE
+        && indexing.descriptor_binding_partially_bound == vk::TRUE
+        && indexing.descriptor_binding_sampled_image_update_after_bind == vk::TRUE
+}
+
 // TODO: ash does not support struct eq for features :(
 fn device_supports_features(
     instance: &Instance,
@@ -277,9 +345,9 @@ fn device_supports_mipmap_generation(
 fn check_device_suitability(
     instance: &Instance,
     device: &vk::PhysicalDevice,
-    surface: &Surface,
+    surface: Option<&Surface>,
 ) -> Result<DeviceSuitability, DeviceCreationError> {
-    if !device_supports_extensions(instance, device, &required_device_extensions())? {
+    if !device_supports_extensions(instance, device, &required_device_extensions(surface))? {
         return Ok(DeviceSuitability::MissingRequiredExtensions);
     }
 
@@ -309,14 +377,16 @@ fn check_device_suitability(
         return Ok(DeviceSuitability::MissingTransferQueue);
     }
 
-    let swapchain_query = surface.query_swapchain_support(device)?;
+    if let Some(surface) = surface {
+        let swapchain_query = surface.query_swapchain_support(device)?;
 
-    if swapchain_query.formats.is_empty() {
-        return Ok(DeviceSuitability::UnsuitableSwapchainFormat);
-    }
+        if swapchain_query.formats.is_empty() {
+            return Ok(DeviceSuitability::UnsuitableSwapchainFormat);
+        }
 
-    if swapchain_query.present_modes.is_empty() {
-        return Ok(DeviceSuitability::UnsuitableSwapchainPresentMode);
+        if swapchain_query.present_modes.is_empty() {
+            return Ok(DeviceSuitability::UnsuitableSwapchainPresentMode);
+        }
     }
 
     Ok(DeviceSuitability::Suitable)
@@ -325,7 +395,7 @@ fn check_device_suitability(
 fn score_device(
     instance: &Instance,
     device: &vk::PhysicalDevice,
-    surface: &Surface,
+    surface: Option<&Surface>,
 ) -> Result<u32, DeviceCreationError> {
     let device_props = unsafe {
         instance
@@ -363,10 +433,25 @@ fn log_queue_families(qfams: &QueueFamilies) {
     log_queue_selection(&qfams.transfer);
 }
 
+/// The device and what was chosen for it
+pub struct Selection {
+    pub vk_device: ash::Device,
+    pub vk_phys_device: vk::PhysicalDevice,
+    pub queue_families: QueueFamilies,
+    /// The features for sparse textures are enabled, see `supports_sparse_textures`
+    pub sparse_textures: bool,
+    /// The wide lines feature is enabled
+    pub wide_lines: bool,
+    /// The descriptor indexing features for bindless textures are enabled, see
+    /// `supports_bindless_textures`
+    pub bindless_textures: bool,
+}
+
+/// Without a surface, no swapchain support is required and the present queue is the graphics queue
 pub fn device_selection(
     instance: &Instance,
-    surface: &Surface,
-) -> Result<(ash::Device, vk::PhysicalDevice, QueueFamilies), DeviceCreationError> {
+    surface: Option<&Surface>,
+) -> Result<Selection, DeviceCreationError> {
     let physical_devices = unsafe {
         instance
             .vk_instance()
@@ -437,20 +522,45 @@ pub fn device_selection(
         }
     }
 
-    let validation_layers =
-        super::super::validation_layers::choose_validation_layers(instance.vk_entry());
+    let validation_layers = super::super::validation_layers::choose_validation_layers(
+        instance.vk_entry(),
+        instance.debug_enabled(),
+    );
     let layers_ptrs = util::ffi::vec_cstring_to_raw(validation_layers);
 
-    let extensions = required_device_extensions();
+    let extensions = required_device_extensions(surface);
     let extensions_ptrs = util::ffi::vec_cstring_to_raw(extensions);
 
-    let features = required_device_features();
+    let sparse_textures =
+        supports_sparse_textures(instance, &vk_phys_device, &queue_families.graphics.family);
+    log::info!("Sparse textures supported: {}", sparse_textures);
+    let mut features = required_device_features();
+    if sparse_textures {
+        features.sparse_binding = vk::TRUE;
+        features.sparse_residency_image2_d = vk::TRUE;
+        features.shader_resource_residency = vk::TRUE;
+    }
+    let wide_lines = supports_wide_lines(instance, &vk_phys_device);
+    log::info!("Wide lines supported: {}", wide_lines);
+    if wide_lines {
+        features.wide_lines = vk::TRUE;
+    }
+    let bindless_textures = supports_bindless_textures(instance, &vk_phys_device);
+    log::info!("Bindless textures supported: {}", bindless_textures);
+    let mut indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+    if bindless_textures {
+        features.shader_sampled_image_array_dynamic_indexing = vk::TRUE;
+        indexing.shader_sampled_image_array_non_uniform_indexing = vk::TRUE;
+        indexing.descriptor_binding_partially_bound = vk::TRUE;
+        indexing.descriptor_binding_sampled_image_update_after_bind = vk::TRUE;
+    }
 
     let device_info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_infos)
         .enabled_layer_names(&layers_ptrs)
         .enabled_extension_names(&extensions_ptrs)
-        .enabled_features(&features);
+        .enabled_features(&features)
+        .push_next(&mut indexing);
 
     let vk_device = unsafe {
         instance
@@ -462,5 +572,12 @@ pub fn device_selection(
     let _owned_layers = util::ffi::vec_cstring_from_raw(layers_ptrs);
     let _owned_extensions = util::ffi::vec_cstring_from_raw(extensions_ptrs);
 
-    Ok((vk_device, vk_phys_device, queue_families))
+    Ok(Selection {
+        vk_device,
+        vk_phys_device,
+        queue_families,
+        sparse_textures,
+        wide_lines,
+        bindless_textures,
+    })
 }
@@ -34,8 +34,11 @@ struct PhysicalDeviceProperties {
     memory_properties: vk::PhysicalDeviceMemoryProperties,
     vk_device_properties: vk::PhysicalDeviceProperties,
     depth_buffer_format: vk::Format,
-    _supported_msaa_sample_counts: vk::SampleCountFlags,
+    supported_msaa_sample_counts: vk::SampleCountFlags,
     max_supported_msaa_sample_count: vk::SampleCountFlags,
+    sparse_textures: bool,
+    wide_lines: bool,
+    bindless_textures: bool,
 }
 
 struct QueueInfo {
@@ -142,9 +145,15 @@ fn get_max_supported_msaa(flags: vk::SampleCountFlags) -> vk::SampleCountFlags {
 }
 
 impl Device {
-    pub fn new(instance: &Instance, surface: &Surface) -> Result<Self, DeviceError> {
-        let (vk_device, vk_phys_device, queue_families) =
-            device_selection::device_selection(instance, surface)?;
+    pub fn new(instance: &Instance, surface: Option<&Surface>) -> Result<Self, DeviceError> {
+        let device_selection::Selection {
+            vk_device,
+            vk_phys_device,
+            queue_families,
+            sparse_textures,
+            wide_lines,
+            bindless_textures,
+        } = device_selection::device_selection(instance, surface)?;
 
         let device_selection::QueueFamilies {
             graphics: graphics_fam,
@@ -178,17 +187,20 @@ impl Device {
                 .vk_instance()
                 .get_physical_device_properties(vk_phys_device);
 
-            let _supported_msaa_sample_counts = vk_props.limits.framebuffer_color_sample_counts
+            let supported_msaa_sample_counts = vk_props.limits.framebuffer_color_sample_counts
                 & vk_props.limits.framebuffer_depth_sample_counts;
             let max_supported_msaa_sample_count =
-                get_max_supported_msaa(_supported_msaa_sample_counts);
+                get_max_supported_msaa(supported_msaa_sample_counts);
 
             PhysicalDeviceProperties {
                 memory_properties,
                 vk_device_properties: vk_props,
                 depth_buffer_format,
-                _supported_msaa_sample_counts,
+                supported_msaa_sample_counts,
                 max_supported_msaa_sample_count,
+                sparse_textures,
+                wide_lines,
+                bindless_textures,
             }
         };
 
@@ -260,6 +272,10 @@ impl Device {
             .max_supported_msaa_sample_count
     }
 
+    pub fn supported_msaa_sample_counts(&self) -> vk::SampleCountFlags {
+        self.physical_device_properties.supported_msaa_sample_counts
+    }
+
     pub fn uniform_buffer_offset_alignment(&self) -> u64 {
         self.physical_device_properties
             .vk_device_properties
@@ -267,6 +283,50 @@ impl Device {
             .min_uniform_buffer_offset_alignment
     }
 
+    /// Nanoseconds per timestamp tick
+    pub fn timestamp_period(&self) -> f32 {
+        self.physical_device_properties
+            .vk_device_properties
+            .limits
+            .timestamp_period
+    }
+
+    /// The number of valid bits of timestamps written on the graphics queue, 0 if it does not support
+    /// timestamps
+    pub fn timestamp_valid_bits(&self) -> u32 {
+        self.graphics_queue_family().props.timestamp_valid_bits
+    }
+
+    /// If sparse textures can be created, see sparse::SparseImage
+    pub fn supports_sparse_textures(&self) -> bool {
+        self.physical_device_properties.sparse_textures
+    }
+
+    /// If the bindless texture array can be created, see crate::bindless
+    pub fn supports_bindless_textures(&self) -> bool {
+        self.physical_device_properties.bindless_textures
+    }
+
+    /// The number of descriptor sets that a pipeline can use
+    pub fn max_bound_descriptor_sets(&self) -> u32 {
+        self.physical_device_properties
+            .vk_device_properties
+            .limits
+            .max_bound_descriptor_sets
+    }
+
+    /// The widest lines that pipelines can draw, 1 if wide lines are not supported
+    pub fn max_line_width(&self) -> f32 {
+        if self.physical_device_properties.wide_lines {
+            self.physical_device_properties
+                .vk_device_properties
+                .limits
+                .line_width_range[1]
+        } else {
+            1.0
+        }
+    }
+
     pub fn allocator(&self) -> AllocatorHandle {
         AllocatorHandle::clone(&self.allocator)
     }
@@ -11,6 +11,7 @@ pub use error::*;
 pub struct Instance {
     entry: Entry,
     vk_instance: ash::Instance,
+    debug: bool,
     lifetime_token: LifetimeToken<Self>,
 }
 
@@ -53,21 +54,16 @@ fn check_extensions<T: AsRef<CStr>>(
     Ok(())
 }
 
-fn choose_instance_extensions<W: raw_window_handle::HasRawWindowHandle>(
+fn choose_instance_extensions(
     entry: &Entry,
-    window: &W,
+    mut required: Vec<&'static CStr>,
+    debug: bool,
 ) -> Result<Vec<*const c_char>, InstanceError> {
     let available = entry
         .enumerate_instance_extension_properties()
         .map_err(|e| InstanceError::InternalVulkan(e, "Instance extension enumeration"))?;
-    let mut required = ash_window::enumerate_required_extensions(window).map_err(|e| {
-        InstanceError::InternalVulkan(
-            e,
-            "Couldn't infer required window/surface extensions from window handle",
-        )
-    })?;
-
-    if super::validation_layers::use_vk_validation() {
+
+    if debug {
         required.push(ash::extensions::ext::DebugUtils::name());
     }
 
@@ -80,19 +76,43 @@ fn choose_instance_extensions<W: raw_window_handle::HasRawWindowHandle>(
 }
 
 impl Instance {
+    /// `debug` requests the validation layers and debug utils, see
+    /// validation_layers::use_vk_validation
     pub fn new<W: raw_window_handle::HasRawWindowHandle>(
         window: &W,
+        debug: bool,
     ) -> Result<Self, InstanceError> {
         let entry = Entry::new().expect("Failed to create Entry!");
+        let window_extensions = ash_window::enumerate_required_extensions(window).map_err(|e| {
+            InstanceError::InternalVulkan(
+                e,
+                "Couldn't infer required window/surface extensions from window handle",
+            )
+        })?;
+
+        Self::with_extensions(entry, window_extensions, debug)
+    }
+
+    /// Create an instance without any window/surface extensions, for rendering offscreen only
+    pub fn headless(debug: bool) -> Result<Self, InstanceError> {
+        let entry = Entry::new().expect("Failed to create Entry!");
+        Self::with_extensions(entry, Vec::new(), debug)
+    }
 
+    fn with_extensions(
+        entry: Entry,
+        required_extensions: Vec<&'static CStr>,
+        debug: bool,
+    ) -> Result<Self, InstanceError> {
+        let debug = super::validation_layers::use_vk_validation(debug);
         let app_info = vk::ApplicationInfo {
             api_version: vk::make_version(1, 2, 0),
             ..Default::default()
         };
 
-        let extensions_ptrs = choose_instance_extensions(&entry, window)?;
+        let extensions_ptrs = choose_instance_extensions(&entry, required_extensions, debug)?;
 
-        let validation_layers = super::validation_layers::choose_validation_layers(&entry);
+        let validation_layers = super::validation_layers::choose_validation_layers(&entry, debug);
         let layers_ptrs = vec_cstring_to_raw(validation_layers);
 
         let create_info = vk::InstanceCreateInfo::builder()
@@ -113,6 +133,7 @@ impl Instance {
         let instance = Instance {
             entry,
             vk_instance,
+            debug,
             lifetime_token,
         };
 
@@ -123,6 +144,11 @@ impl Instance {
         self.lifetime_token.clone()
     }
 
+    /// If the validation layers and debug utils are enabled
+    pub fn debug_enabled(&self) -> bool {
+        self.debug
+    }
+
     pub fn vk_instance(&self) -> &ash::Instance {
         &self.vk_instance
     }
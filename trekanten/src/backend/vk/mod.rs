@@ -6,6 +6,8 @@ pub mod device;
 pub mod framebuffer;
 pub mod image;
 pub mod instance;
+pub mod offscreen;
+pub mod query;
 pub mod queue;
 pub mod render_pass;
 pub mod surface;
@@ -0,0 +1,138 @@
+use ash::vk;
+
+use thiserror::Error;
+
+use super::color_buffer::ColorBuffer;
+use super::depth_buffer::DepthBuffer;
+use super::device::{Device, HasVkDevice, VkDeviceHandle};
+use super::framebuffer::{Framebuffer, FramebufferError};
+use super::image::{ImageView, ImageViewError};
+use super::render_pass::RenderPass;
+
+use crate::mem::{DeviceImage, MemoryError};
+use crate::util;
+
+#[derive(Debug, Error)]
+pub enum OffscreenError {
+    #[error("Offscreen image memory error: {0}")]
+    Memory(#[from] MemoryError),
+    #[error("Image view creation failed: {0}")]
+    ImageView(#[from] ImageViewError),
+    #[error("Framebuffer creation failed: {0}")]
+    Framebuffer(#[from] FramebufferError),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OffscreenInfo {
+    pub format: vk::Format,
+    pub extent: util::Extent2D,
+}
+
+/// Stands in for a swapchain when there is no window. The images are rendered to in a round-robin
+/// fashion and are left in TRANSFER_SRC_OPTIMAL so that they can be read back.
+pub struct OffscreenTarget {
+    image_views: Vec<ImageView>,
+    images: Vec<DeviceImage>,
+    next_image: u32,
+    presented_image: Option<u32>,
+    info: OffscreenInfo,
+    vk_device: VkDeviceHandle,
+}
+
+impl OffscreenTarget {
+    pub const FORMAT: util::Format = util::Format::RGBA_SRGB;
+
+    pub fn new(
+        device: &Device,
+        extent: &util::Extent2D,
+        num_images: usize,
+    ) -> Result<Self, OffscreenError> {
+        let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC;
+        let mip_levels = 1;
+
+        let images = (0..num_images)
+            .map(|_| {
+                DeviceImage::empty_2d(
+                    &device.allocator(),
+                    *extent,
+                    Self::FORMAT,
+                    usage,
+                    vk_mem::MemoryUsage::GpuOnly,
+                    mip_levels,
+                    vk::SampleCountFlags::TYPE_1,
+                )
+            })
+            .collect::<Result<Vec<_>, MemoryError>>()?;
+
+        let image_views = images
+            .iter()
+            .map(|img| {
+                ImageView::new(
+                    device,
+                    img.vk_image(),
+                    Self::FORMAT,
+                    vk::ImageAspectFlags::COLOR,
+                    mip_levels,
+                )
+            })
+            .collect::<Result<Vec<_>, ImageViewError>>()?;
+
+        Ok(Self {
+            image_views,
+            images,
+            next_image: 0,
+            presented_image: None,
+            info: OffscreenInfo {
+                format: Self::FORMAT.into(),
+                extent: *extent,
+            },
+            vk_device: device.vk_device(),
+        })
+    }
+
+    pub fn info(&self) -> &OffscreenInfo {
+        &self.info
+    }
+
+    pub fn create_framebuffers_for(
+        &self,
+        render_pass: &RenderPass,
+        depth_buffer: &DepthBuffer,
+        color_buffer: &ColorBuffer,
+    ) -> Result<Vec<Framebuffer>, OffscreenError> {
+        self.image_views
+            .iter()
+            .map(|iv| {
+                let views = [color_buffer.image_view(), depth_buffer.image_view(), iv];
+                Framebuffer::new(&self.vk_device, &views, render_pass, &self.info.extent)
+            })
+            .collect::<Result<Vec<_>, FramebufferError>>()
+            .map_err(OffscreenError::Framebuffer)
+    }
+
+    /// Unlike a swapchain, the image is available immediately. The caller has to make sure that
+    /// the previous rendering to it has finished.
+    pub fn acquire_next_image(&mut self) -> u32 {
+        let idx = self.next_image;
+        self.next_image = (self.next_image + 1) % self.images.len() as u32;
+        idx
+    }
+
+    pub fn set_presented(&mut self, idx: u32) {
+        self.presented_image = Some(idx);
+    }
+
+    /// The image that was rendered to by the last submitted frame
+    pub fn presented_image(&self) -> Option<u32> {
+        self.presented_image
+    }
+
+    pub fn vk_image(&self, idx: u32) -> &vk::Image {
+        self.images[idx as usize].vk_image()
+    }
+
+    pub fn num_images(&self) -> usize {
+        assert_eq!(self.images.len(), self.image_views.len());
+        self.images.len()
+    }
+}
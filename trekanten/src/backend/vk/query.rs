@@ -0,0 +1,79 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use thiserror::Error;
+
+use super::device::{HasVkDevice, VkDeviceHandle};
+
+#[derive(Debug, Copy, Clone, Error)]
+pub enum QueryError {
+    #[error("Query pool creation failed {0}")]
+    PoolCreation(vk::Result),
+    #[error("Couldn't get query results {0}")]
+    Results(vk::Result),
+}
+
+/// Timestamp queries, written with CommandBuffer::write_timestamp
+pub struct QueryPool {
+    vk_query_pool: vk::QueryPool,
+    vk_device: VkDeviceHandle,
+    count: u32,
+}
+
+impl std::ops::Drop for QueryPool {
+    fn drop(&mut self) {
+        unsafe {
+            self.vk_device.destroy_query_pool(self.vk_query_pool, None);
+        }
+    }
+}
+
+impl QueryPool {
+    pub fn timestamps<D: HasVkDevice>(device: &D, count: u32) -> Result<Self, QueryError> {
+        let vk_device = device.vk_device();
+        let info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(count);
+
+        let vk_query_pool = unsafe {
+            vk_device
+                .create_query_pool(&info, None)
+                .map_err(QueryError::PoolCreation)?
+        };
+
+        Ok(Self {
+            vk_query_pool,
+            vk_device,
+            count,
+        })
+    }
+
+    pub fn vk_query_pool(&self) -> &vk::QueryPool {
+        &self.vk_query_pool
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// The results of `count` queries starting at `first`, without waiting for them. Returns None
+    /// if any of them are not available yet.
+    pub fn results(&self, first: u32, count: u32) -> Result<Option<Vec<u64>>, QueryError> {
+        let mut data = vec![0u64; count as usize];
+        let result = unsafe {
+            self.vk_device.get_query_pool_results(
+                self.vk_query_pool,
+                first,
+                count,
+                &mut data,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
+
+        match result {
+            Ok(()) => Ok(Some(data)),
+            Err(vk::Result::NOT_READY) => Ok(None),
+            Err(e) => Err(QueryError::Results(e)),
+        }
+    }
+}
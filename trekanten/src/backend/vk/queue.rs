@@ -10,6 +10,8 @@ use super::sync::{Fence, SyncError};
 pub enum QueueError {
     #[error("Failed to submit on queue {0}")]
     Submit(vk::Result),
+    #[error("Failed to bind sparse memory on queue {0}")]
+    BindSparse(vk::Result),
     #[error("Failed to wait on fence {0}")]
     Fence(#[from] SyncError),
 }
@@ -29,8 +31,19 @@ struct VkSubmitFn(
 );
 unsafe impl Send for VkSubmitFn {}
 
+struct VkBindSparseFn(
+    extern "system" fn(
+        queue: vk::Queue,
+        bind_info_count: u32,
+        p_bind_info: *const vk::BindSparseInfo,
+        fence: vk::Fence,
+    ) -> vk::Result,
+);
+unsafe impl Send for VkBindSparseFn {}
+
 pub struct Queue {
     vk_submit_fn: VkSubmitFn,
+    vk_bind_sparse_fn: VkBindSparseFn,
     vk_queue: vk::Queue,
     queue_family: QueueFamily,
 }
@@ -39,8 +52,10 @@ impl Queue {
     pub fn new<D: HasVkDevice>(device: D, queue_family: QueueFamily, vk_queue: vk::Queue) -> Self {
         let vk_device = device.vk_device();
         let vk_submit_fn = VkSubmitFn(vk_device.fp_v1_0().queue_submit);
+        let vk_bind_sparse_fn = VkBindSparseFn(vk_device.fp_v1_0().queue_bind_sparse);
         Self {
             vk_submit_fn,
+            vk_bind_sparse_fn,
             vk_queue,
             queue_family,
         }
@@ -62,6 +77,23 @@ impl Queue {
         }
     }
 
+    /// The queue family has to support sparse binding
+    pub fn bind_sparse(&self, info: &vk::BindSparseInfo, fence: &Fence) -> Result<(), QueueError> {
+        let infos = [*info];
+        let result = (self.vk_bind_sparse_fn.0)(
+            self.vk_queue,
+            infos.len() as u32,
+            infos.as_ptr(),
+            *fence.vk_fence(),
+        );
+
+        if result == vk::Result::SUCCESS {
+            Ok(())
+        } else {
+            Err(QueueError::BindSparse(result))
+        }
+    }
+
     pub fn vk_queue(&self) -> &vk::Queue {
         &self.vk_queue
     }
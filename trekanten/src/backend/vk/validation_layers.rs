@@ -19,20 +19,22 @@ fn validation_layers() -> Vec<CString> {
     vec![CString::new("VK_LAYER_KHRONOS_validation").expect("Failed to create CString")]
 }
 
-pub fn use_vk_validation() -> bool {
+/// `requested` enables validation at runtime, e.g. from a command line flag. Otherwise it is on if
+/// the validation-layers feature is enabled and the env var isn't set.
+pub fn use_vk_validation(requested: bool) -> bool {
     #[cfg(feature = "validation-layers")]
     {
-        std::env::var(DISABLE_VALIDATION_LAYERS_ENV_VAR).is_err()
+        requested || std::env::var(DISABLE_VALIDATION_LAYERS_ENV_VAR).is_err()
     }
 
     #[cfg(not(feature = "validation-layers"))]
     {
-        false
+        requested
     }
 }
 
-pub fn choose_validation_layers(entry: &Entry) -> Vec<CString> {
-    if use_vk_validation() {
+pub fn choose_validation_layers(entry: &Entry, use_validation: bool) -> Vec<CString> {
+    if use_validation {
         let requested = validation_layers();
         log::trace!("Requested vk layers:");
         crate::util::ffi::log_cstrings(&requested);
@@ -73,6 +75,8 @@ pub fn choose_validation_layers(entry: &Entry) -> Vec<CString> {
 pub enum DebugUtilsError {
     #[error("Failed to create vulkan debug utils extension {0}")]
     Creation(vk::Result),
+    #[error("Failed to set the name of a vulkan object {0}")]
+    ObjectName(vk::Result),
 }
 
 pub struct DebugUtilsEnabled {
@@ -81,7 +85,6 @@ pub struct DebugUtilsEnabled {
     _parent_lifetime_token: LifetimeToken<Instance>,
 }
 
-#[allow(dead_code)]
 impl DebugUtilsEnabled {
     fn new(instance: &Instance) -> Result<Self, DebugUtilsError> {
         let loader = ext::DebugUtils::new(instance.entry(), instance.vk_instance());
@@ -103,6 +106,26 @@ impl DebugUtilsEnabled {
             _parent_lifetime_token: instance.lifetime_token(),
         })
     }
+
+    fn set_object_name(
+        &self,
+        vk_device: vk::Device,
+        object_type: vk::ObjectType,
+        object_handle: u64,
+        name: &str,
+    ) -> Result<(), DebugUtilsError> {
+        // Interior nul bytes would cut the name short, drop them instead of failing
+        let name = CString::new(name.replace('\0', "")).expect("Failed to create CString");
+        let info = vk::DebugUtilsObjectNameInfoEXT::builder()
+            .object_type(object_type)
+            .object_handle(object_handle)
+            .object_name(&name);
+        unsafe {
+            self.loader
+                .debug_utils_set_object_name(vk_device, &info)
+                .map_err(DebugUtilsError::ObjectName)
+        }
+    }
 }
 
 impl Drop for DebugUtilsEnabled {
@@ -116,31 +139,42 @@ impl Drop for DebugUtilsEnabled {
 
 pub struct DebugUtilsDisabled {}
 
-#[allow(dead_code)]
 pub enum DebugUtils {
     Enabled(DebugUtilsEnabled),
     Disabled(DebugUtilsDisabled),
 }
 
 impl DebugUtils {
-    #[allow(unused)]
     pub fn new(instance: &Instance) -> Result<Self, DebugUtilsError> {
-        #[cfg(not(feature = "validation-layers"))]
-        {
+        if instance.debug_enabled() {
+            Ok(Self::Enabled(DebugUtilsEnabled::new(instance)?))
+        } else {
             Ok(Self::Disabled(DebugUtilsDisabled {}))
         }
-        #[cfg(feature = "validation-layers")]
-        {
-            if use_vk_validation() {
-                Ok(Self::Enabled(DebugUtilsEnabled::new(instance)?))
-            } else {
-                Ok(Self::Disabled(DebugUtilsDisabled {}))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        matches!(self, Self::Enabled(_))
+    }
+
+    /// Names the object in validation messages and capture tools like RenderDoc. Does nothing if
+    /// debug utils are disabled.
+    pub fn set_object_name(
+        &self,
+        vk_device: vk::Device,
+        object_type: vk::ObjectType,
+        object_handle: u64,
+        name: &str,
+    ) -> Result<(), DebugUtilsError> {
+        match self {
+            Self::Enabled(utils) => {
+                utils.set_object_name(vk_device, object_type, object_handle, name)
             }
+            Self::Disabled(_) => Ok(()),
         }
     }
 }
 
-#[allow(dead_code)]
 unsafe fn write_maybe_null(mut s: &mut String, p: *const c_char) {
     if p.is_null() {
         write!(&mut s, "(NULL)").expect("vk_debug_callback failed to write");
@@ -149,7 +183,6 @@ unsafe fn write_maybe_null(mut s: &mut String, p: *const c_char) {
     }
 }
 
-#[allow(dead_code)]
 unsafe extern "system" fn vk_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_type: vk::DebugUtilsMessageTypeFlagsEXT,
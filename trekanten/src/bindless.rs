@@ -0,0 +1,227 @@
+//! One descriptor set with an array of textures that shaders index into, e.g. with indices from a
+//! material buffer, instead of binding a set with the textures of each material. Textures are added
+//! to the array the first time their index is asked for, and keep their slot until they are
+//! destroyed.
+//!
+//! The array is partially bound and updated after bind, so that textures can be added while frames
+//! that use the set are in flight, as long as those frames don't read the new slots. A slot is only
+//! reused after its texture is destroyed, which is deferred until no frame in flight can use it.
+//! Needs descriptor indexing, see Renderer::supports_bindless_textures. Indices that differ between
+//! the invocations of a draw or dispatch have to be marked with `nonuniformEXT`.
+
+use std::collections::HashMap;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::descriptor::DescriptorError;
+use crate::device::{Device, HasVkDevice, VkDevice, VkDeviceHandle};
+use crate::resource::Handle;
+use crate::texture::Texture;
+
+/// The length of the array. Shaders declare it with this many elements, e.g.
+/// `layout(set = N, binding = 0) uniform sampler2D textures[BINDLESS_TEXTURE_COUNT];`
+pub const BINDLESS_TEXTURE_COUNT: u32 = 4096;
+
+fn layout_binding() -> vk::DescriptorSetLayoutBinding {
+    vk::DescriptorSetLayoutBinding {
+        binding: 0,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        descriptor_count: BINDLESS_TEXTURE_COUNT,
+        stage_flags: vk::ShaderStageFlags::VERTEX
+            | vk::ShaderStageFlags::FRAGMENT
+            | vk::ShaderStageFlags::COMPUTE,
+        ..Default::default()
+    }
+}
+
+/// If a set of a pipeline is the bindless array, i.e. its only binding is an array of
+/// BINDLESS_TEXTURE_COUNT textures at binding 0. Its layout has to be created with
+/// `create_layout` for the bindless set to be compatible with it.
+pub(crate) fn is_bindless_layout(bindings: &[vk::DescriptorSetLayoutBinding]) -> bool {
+    matches!(bindings, [b] if b.binding == 0
+        && b.descriptor_type == vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+        && b.descriptor_count == BINDLESS_TEXTURE_COUNT)
+}
+
+pub(crate) fn create_layout(vk_device: &VkDevice) -> Result<vk::DescriptorSetLayout, vk::Result> {
+    let bindings = [layout_binding()];
+    let flags =
+        [vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND];
+    let mut binding_flags =
+        vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder().binding_flags(&flags);
+    let info = vk::DescriptorSetLayoutCreateInfo::builder()
+        .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+        .bindings(&bindings)
+        .push_next(&mut binding_flags);
+
+    unsafe { vk_device.create_descriptor_set_layout(&info, None) }
+}
+
+/// The free slots of the array
+#[derive(Debug)]
+struct Slots {
+    capacity: u32,
+    /// The slots from this one and up have never been used
+    next: u32,
+    released: Vec<u32>,
+}
+
+impl Slots {
+    fn new(capacity: u32) -> Self {
+        Self {
+            capacity,
+            next: 0,
+            released: Vec::new(),
+        }
+    }
+
+    fn alloc(&mut self) -> Option<u32> {
+        if let Some(slot) = self.released.pop() {
+            return Some(slot);
+        }
+        if self.next == self.capacity {
+            return None;
+        }
+        self.next += 1;
+        Some(self.next - 1)
+    }
+
+    fn release(&mut self, slot: u32) {
+        self.released.push(slot);
+    }
+}
+
+pub(crate) struct BindlessTextures {
+    vk_device: VkDeviceHandle,
+    vk_descriptor_pool: vk::DescriptorPool,
+    vk_descriptor_set_layout: vk::DescriptorSetLayout,
+    vk_descriptor_set: vk::DescriptorSet,
+    slots: Slots,
+    indices: HashMap<Handle<Texture>, u32>,
+}
+
+impl std::ops::Drop for BindlessTextures {
+    fn drop(&mut self) {
+        unsafe {
+            self.vk_device
+                .destroy_descriptor_pool(self.vk_descriptor_pool, None);
+            self.vk_device
+                .destroy_descriptor_set_layout(self.vk_descriptor_set_layout, None);
+        }
+    }
+}
+
+impl BindlessTextures {
+    pub fn new(device: &Device) -> Result<Self, DescriptorError> {
+        let vk_device = device.vk_device();
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: BINDLESS_TEXTURE_COUNT,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .pool_sizes(&pool_sizes)
+            .max_sets(1);
+        let vk_descriptor_pool = unsafe {
+            vk_device
+                .create_descriptor_pool(&pool_info, None)
+                .map_err(DescriptorError::PoolCreation)?
+        };
+
+        let vk_descriptor_set_layout =
+            create_layout(&vk_device).map_err(DescriptorError::LayoutCreation)?;
+        let layouts = [vk_descriptor_set_layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(vk_descriptor_pool)
+            .set_layouts(&layouts);
+        let vk_descriptor_set = unsafe {
+            vk_device
+                .allocate_descriptor_sets(&alloc_info)
+                .map_err(DescriptorError::SetAllocation)?[0]
+        };
+
+        Ok(Self {
+            vk_device,
+            vk_descriptor_pool,
+            vk_descriptor_set_layout,
+            vk_descriptor_set,
+            slots: Slots::new(BINDLESS_TEXTURE_COUNT),
+            indices: HashMap::new(),
+        })
+    }
+
+    pub fn vk_descriptor_set(&self) -> vk::DescriptorSet {
+        self.vk_descriptor_set
+    }
+
+    /// The slot of the texture, which is added to the array if it isn't in it. None if the array
+    /// is full.
+    pub fn index(&mut self, h: Handle<Texture>, texture: &Texture) -> Option<u32> {
+        if let Some(&idx) = self.indices.get(&h) {
+            return Some(idx);
+        }
+
+        let idx = match self.slots.alloc() {
+            Some(idx) => idx,
+            None => {
+                log::error!(
+                    "All {} bindless texture slots are in use",
+                    BINDLESS_TEXTURE_COUNT
+                );
+                return None;
+            }
+        };
+        self.indices.insert(h, idx);
+        self.write(idx, texture);
+        Some(idx)
+    }
+
+    /// Writes the texture to its slot again, after its image was replaced
+    pub fn rewrite(&self, h: &Handle<Texture>, texture: &Texture) {
+        if let Some(&idx) = self.indices.get(h) {
+            self.write(idx, texture);
+        }
+    }
+
+    /// Frees the slot of the texture. No frame in flight may use it.
+    pub fn remove(&mut self, h: &Handle<Texture>) {
+        if let Some(idx) = self.indices.remove(h) {
+            self.slots.release(idx);
+        }
+    }
+
+    fn write(&self, idx: u32, texture: &Texture) {
+        let image_info = [vk::DescriptorImageInfo {
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            image_view: *texture.image_view().vk_image_view(),
+            sampler: *texture.vk_sampler(),
+        }];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.vk_descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(idx)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_info);
+        unsafe {
+            self.vk_device.update_descriptor_sets(&[*write], &[]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn released_slots_are_reused_before_new_ones() {
+        let mut slots = Slots::new(3);
+        assert_eq!(slots.alloc(), Some(0));
+        assert_eq!(slots.alloc(), Some(1));
+        slots.release(0);
+        assert_eq!(slots.alloc(), Some(0));
+        assert_eq!(slots.alloc(), Some(2));
+        assert_eq!(slots.alloc(), None);
+    }
+}
@@ -0,0 +1,212 @@
+use ash::vk;
+
+use crate::backend::command::{CommandBuffer, CommandError};
+use crate::descriptor::DescriptorSet;
+use crate::pipeline::ComputePipeline;
+use crate::resource::{Handle, Resources};
+use crate::texture::Texture;
+use crate::trace::{Command, CommandTrace};
+
+/// Records compute dispatches, outside of any render pass. The pipeline binds and dispatches are
+/// traced, see trace.
+pub struct ComputePassEncoder<'a> {
+    resources: &'a Resources,
+    frame_idx: u32,
+    command_buffer: CommandBuffer,
+    trace: Option<&'a CommandTrace>,
+}
+
+impl<'a> ComputePassEncoder<'a> {
+    pub(crate) fn new(
+        resources: &'a Resources,
+        command_buffer: CommandBuffer,
+        frame_idx: u32,
+        trace: Option<&'a CommandTrace>,
+    ) -> Self {
+        Self {
+            resources,
+            frame_idx,
+            command_buffer,
+            trace,
+        }
+    }
+
+    fn record(&self, cmd: Command) {
+        if let Some(trace) = self.trace {
+            trace.record(cmd);
+        }
+    }
+
+    fn pipeline(&self, pipeline: &Handle<ComputePipeline>) -> &'a ComputePipeline {
+        self.resources
+            .compute_pipelines
+            .get(pipeline)
+            .expect("Failed to find compute pipeline")
+    }
+
+    pub fn bind_compute_pipeline(&mut self, pipeline: &Handle<ComputePipeline>) -> &mut Self {
+        self.record(Command::BindComputePipeline {
+            pipeline: pipeline.id(),
+        });
+        let pipeline = self.pipeline(pipeline);
+        self.command_buffer.bind_compute_pipeline(pipeline);
+
+        self
+    }
+
+    pub fn bind_shader_resource_group(
+        &mut self,
+        idx: u32,
+        dset: &Handle<DescriptorSet>,
+        pipeline: &Handle<ComputePipeline>,
+    ) -> &mut Self {
+        let dset = self
+            .resources
+            .descriptor_sets
+            .get(dset, self.frame_idx as usize)
+            .expect("Failed to find descriptor set");
+        let pipeline = self.pipeline(pipeline);
+        self.command_buffer
+            .bind_compute_descriptor_set(idx, dset, pipeline);
+
+        self
+    }
+
+    pub fn bind_push_constant<V: Copy>(
+        &mut self,
+        pipeline: &Handle<ComputePipeline>,
+        v: &V,
+    ) -> &mut Self {
+        let pipeline = self.pipeline(pipeline);
+        self.command_buffer.bind_compute_push_constant(pipeline, v);
+
+        self
+    }
+
+    /// Runs x * y * z workgroups of the bound pipeline
+    pub fn dispatch(&mut self, x: u32, y: u32, z: u32) -> &mut Self {
+        self.record(Command::Dispatch { x, y, z });
+        self.command_buffer.dispatch(x, y, z);
+
+        self
+    }
+
+    /// Makes what the dispatches before this wrote visible to the ones after it, e.g. when one
+    /// dispatch reads what the previous one wrote, or to wait for the dispatches of the previous
+    /// frame that wrote the same buffers
+    pub fn barrier(&mut self) -> &mut Self {
+        self.command_buffer.memory_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        );
+
+        self
+    }
+
+    fn storage_image_barrier(
+        &mut self,
+        texture: &Handle<Texture>,
+        (old_layout, src_access_mask, src_stage): (
+            vk::ImageLayout,
+            vk::AccessFlags,
+            vk::PipelineStageFlags,
+        ),
+        (new_layout, dst_access_mask, dst_stage): (
+            vk::ImageLayout,
+            vk::AccessFlags,
+            vk::PipelineStageFlags,
+        ),
+    ) {
+        let image = *self
+            .resources
+            .textures
+            .get(texture)
+            .expect("Failed to find texture")
+            .vk_image();
+        let barrier = vk::ImageMemoryBarrier {
+            old_layout,
+            new_layout,
+            src_access_mask,
+            dst_access_mask,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        };
+        self.command_buffer
+            .pipeline_barrier(&[barrier], src_stage, dst_stage);
+    }
+
+    /// Moves a texture with TextureUsage::STORAGE to the GENERAL layout, so that the dispatches
+    /// after this can write it. The texture is either sampled by the fragment shaders of a previous
+    /// frame, see end_storage_image, or it has not been written yet and its texels are discarded.
+    pub fn begin_storage_image(&mut self, texture: &Handle<Texture>, written: bool) -> &mut Self {
+        let old_layout = if written {
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        } else {
+            vk::ImageLayout::UNDEFINED
+        };
+        self.storage_image_barrier(
+            texture,
+            (
+                old_layout,
+                vk::AccessFlags::empty(),
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+            ),
+            (
+                vk::ImageLayout::GENERAL,
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+            ),
+        );
+
+        self
+    }
+
+    /// Moves a texture from begin_storage_image back to SHADER_READ_ONLY_OPTIMAL, after the
+    /// dispatches that write it, so that the fragment shaders of the passes after this can sample
+    /// it like any other texture
+    pub fn end_storage_image(&mut self, texture: &Handle<Texture>) -> &mut Self {
+        self.storage_image_barrier(
+            texture,
+            (
+                vk::ImageLayout::GENERAL,
+                vk::AccessFlags::SHADER_WRITE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+            ),
+            (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::SHADER_READ,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+            ),
+        );
+
+        self
+    }
+
+    /// The buffers written by the dispatches can be read as vertices, and by the vertex shaders,
+    /// of the passes that are recorded after this
+    pub fn end(mut self) -> Result<CommandBuffer, CommandError> {
+        self.command_buffer.memory_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::PipelineStageFlags::VERTEX_INPUT | vk::PipelineStageFlags::VERTEX_SHADER,
+            vk::AccessFlags::VERTEX_ATTRIBUTE_READ | vk::AccessFlags::SHADER_READ,
+        );
+        Ok(self.command_buffer)
+    }
+
+    /// For commands that are not tied to the pass, e.g. Frame::begin_gpu_timer
+    pub fn command_buffer_mut(&mut self) -> &mut CommandBuffer {
+        &mut self.command_buffer
+    }
+}
@@ -0,0 +1,137 @@
+//! Names for vulkan objects, so that validation messages and capture tools like RenderDoc refer to
+//! e.g. "shadow map 3" instead of a raw handle. Names are only set when the renderer was created
+//! with debug enabled, see RendererOptions.
+
+use ash::vk;
+use ash::vk::Handle as _;
+
+use crate::descriptor::DescriptorSet;
+use crate::mem::{BufferHandle, IndexBuffer, StorageBuffer, UniformBuffer, VertexBuffer};
+use crate::pipeline::GraphicsPipeline;
+use crate::resource::{Handle, Resources};
+use crate::{RenderPass, RenderTarget, Texture};
+
+/// A vulkan object of a resource, with the suffix to add to the name of the resource, e.g. " view"
+/// for the image view of a texture.
+pub type NamedObject = (vk::ObjectType, u64, &'static str);
+
+/// Resources that can be named with Renderer::set_debug_name
+pub trait DebugNamed {
+    /// The vulkan objects that make up the resource. Empty if the handle is not valid.
+    fn vk_objects(&self, resources: &Resources) -> Vec<NamedObject>;
+}
+
+impl DebugNamed for Handle<Texture> {
+    fn vk_objects(&self, resources: &Resources) -> Vec<NamedObject> {
+        resources.textures.get(self).map_or_else(Vec::new, |t| {
+            vec![
+                (vk::ObjectType::IMAGE, t.vk_image().as_raw(), ""),
+                (
+                    vk::ObjectType::IMAGE_VIEW,
+                    t.image_view().vk_image_view().as_raw(),
+                    " view",
+                ),
+            ]
+        })
+    }
+}
+
+impl DebugNamed for Handle<RenderTarget> {
+    fn vk_objects(&self, resources: &Resources) -> Vec<NamedObject> {
+        resources
+            .render_targets
+            .get(self)
+            .map_or_else(Vec::new, |rt| {
+                vec![(
+                    vk::ObjectType::FRAMEBUFFER,
+                    rt.inner.vk_framebuffer().as_raw(),
+                    "",
+                )]
+            })
+    }
+}
+
+impl DebugNamed for Handle<RenderPass> {
+    fn vk_objects(&self, resources: &Resources) -> Vec<NamedObject> {
+        resources
+            .render_passes
+            .get(self)
+            .map_or_else(Vec::new, |rp| {
+                vec![(
+                    vk::ObjectType::RENDER_PASS,
+                    rp.0.vk_render_pass().as_raw(),
+                    "",
+                )]
+            })
+    }
+}
+
+impl DebugNamed for Handle<GraphicsPipeline> {
+    fn vk_objects(&self, resources: &Resources) -> Vec<NamedObject> {
+        use crate::pipeline::Pipeline as _;
+        resources
+            .graphics_pipelines
+            .get(self)
+            .map_or_else(Vec::new, |p| {
+                vec![
+                    (vk::ObjectType::PIPELINE, p.vk_pipeline().as_raw(), ""),
+                    (
+                        vk::ObjectType::PIPELINE_LAYOUT,
+                        p.vk_pipeline_layout().as_raw(),
+                        " layout",
+                    ),
+                ]
+            })
+    }
+}
+
+/// Mutable resources have one object per frame in flight
+const FRAME_SUFFIXES: [&str; 2] = [" [frame 0]", " [frame 1]"];
+
+impl DebugNamed for Handle<DescriptorSet> {
+    fn vk_objects(&self, resources: &Resources) -> Vec<NamedObject> {
+        (0..FRAME_SUFFIXES.len())
+            .filter_map(|i| {
+                resources.descriptor_sets.get(self, i).map(|set| {
+                    (
+                        vk::ObjectType::DESCRIPTOR_SET,
+                        set.vk_descriptor_set().as_raw(),
+                        FRAME_SUFFIXES[i],
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+macro_rules! impl_debug_named_buffer {
+    ($buffer:ty, $storage:ident) => {
+        impl DebugNamed for BufferHandle<$buffer> {
+            fn vk_objects(&self, resources: &Resources) -> Vec<NamedObject> {
+                match resources.$storage.get_all(self) {
+                    Some((buf, None)) => {
+                        vec![(vk::ObjectType::BUFFER, buf.vk_buffer().as_raw(), "")]
+                    }
+                    Some((buf0, Some(buf1))) => vec![
+                        (
+                            vk::ObjectType::BUFFER,
+                            buf0.vk_buffer().as_raw(),
+                            FRAME_SUFFIXES[0],
+                        ),
+                        (
+                            vk::ObjectType::BUFFER,
+                            buf1.vk_buffer().as_raw(),
+                            FRAME_SUFFIXES[1],
+                        ),
+                    ],
+                    None => Vec::new(),
+                }
+            }
+        }
+    };
+}
+
+impl_debug_named_buffer!(UniformBuffer, uniform_buffers);
+impl_debug_named_buffer!(StorageBuffer, storage_buffers);
+impl_debug_named_buffer!(VertexBuffer, vertex_buffers);
+impl_debug_named_buffer!(IndexBuffer, index_buffers);
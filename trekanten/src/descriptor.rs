@@ -8,7 +8,7 @@ use crate::device::Device;
 use crate::device::HasVkDevice;
 use crate::device::VkDeviceHandle;
 use crate::mem::BufferHandle;
-use crate::mem::UniformBuffer;
+use crate::mem::{StorageBuffer, UniformBuffer, VertexBuffer};
 use crate::pipeline::ShaderStage;
 use crate::resource::{BufferedStorage, Handle};
 use crate::texture::Texture;
@@ -20,8 +20,54 @@ use crate::common::MAX_FRAMES_IN_FLIGHT;
 pub enum DescriptorError {
     #[error("Failed to create descriptor pool: {0}")]
     PoolCreation(vk::Result),
+    #[error("Failed to create descriptor set layout: {0}")]
+    LayoutCreation(vk::Result),
     #[error("Failed to allocate descriptor set: {0}")]
     SetAllocation(vk::Result),
+    #[error("Failed to free descriptor set: {0}")]
+    SetFree(vk::Result),
+    #[error("Failed to reset descriptor pool: {0}")]
+    PoolReset(vk::Result),
+}
+
+/// The number of sets in each pool of the transient sets. More pools are created if a frame needs
+/// more.
+const TRANSIENT_SETS_PER_POOL: u32 = 64;
+
+fn create_pool(
+    vk_device: &VkDeviceHandle,
+    flags: vk::DescriptorPoolCreateFlags,
+    max_sets: u32,
+) -> Result<vk::DescriptorPool, DescriptorError> {
+    let pool_sizes = [
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: max_sets * 2 as u32,
+        },
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: max_sets,
+        },
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: max_sets * 2 as u32,
+        },
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_IMAGE,
+            descriptor_count: max_sets,
+        },
+    ];
+
+    let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
+        .flags(flags)
+        .pool_sizes(&pool_sizes)
+        .max_sets(max_sets);
+
+    unsafe {
+        vk_device
+            .create_descriptor_pool(&pool_create_info, None)
+            .map_err(DescriptorError::PoolCreation)
+    }
 }
 
 struct DescriptorPool {
@@ -54,27 +100,11 @@ impl DescriptorPool {
     // TODO: Accept layout here to compute individual descriptor counts
     fn new(device: &Device) -> Result<Self, DescriptorError> {
         let max_allocatable_sets = 128 * MAX_FRAMES_IN_FLIGHT as u32;
-        let pool_sizes = [
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::UNIFORM_BUFFER,
-                descriptor_count: max_allocatable_sets * 2 as u32,
-            },
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                descriptor_count: max_allocatable_sets * 2 as u32,
-            },
-        ];
-
-        let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
-            .pool_sizes(&pool_sizes)
-            .max_sets(max_allocatable_sets);
-
-        let vk_descriptor_pool = unsafe {
-            device
-                .vk_device()
-                .create_descriptor_pool(&pool_create_info, None)
-                .map_err(DescriptorError::PoolCreation)?
-        };
+        let vk_descriptor_pool = create_pool(
+            &device.vk_device(),
+            vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+            max_allocatable_sets,
+        )?;
 
         Ok(Self {
             vk_device: device.vk_device(),
@@ -104,7 +134,7 @@ impl DescriptorPool {
                 .allocate_descriptor_sets(&info)
                 .map_err(DescriptorError::SetAllocation)?
                 .into_iter()
-                .map(DescriptorSet::new)
+                .map(|set| DescriptorSet::new(set, Allocation::Pool))
                 .collect()
         };
 
@@ -113,6 +143,107 @@ impl DescriptorPool {
 
         Ok(desc_sets)
     }
+
+    fn free(&mut self, sets: &[vk::DescriptorSet]) -> Result<(), DescriptorError> {
+        unsafe {
+            self.vk_device
+                .free_descriptor_sets(self.vk_descriptor_pool, sets)
+                .map_err(DescriptorError::SetFree)?;
+        }
+        self.n_allocated_sets -= sets.len() as u32;
+        Ok(())
+    }
+}
+
+/// The pools that the transient sets of one frame in flight are allocated from, see
+/// DescriptorSet::transient_builder. The sets are never freed one by one, all the pools are reset
+/// at once when the frame slot is reused, so they don't fragment.
+struct TransientPools {
+    vk_device: VkDeviceHandle,
+    vk_descriptor_pools: Vec<vk::DescriptorPool>,
+    /// The pool that sets are allocated from, the ones before it are full
+    current: usize,
+    vk_descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    handles: Vec<Handle<DescriptorSet>>,
+}
+
+impl std::ops::Drop for TransientPools {
+    fn drop(&mut self) {
+        unsafe {
+            for &pool in self.vk_descriptor_pools.iter() {
+                self.vk_device.destroy_descriptor_pool(pool, None);
+            }
+            for &layout in self.vk_descriptor_set_layouts.iter() {
+                self.vk_device.destroy_descriptor_set_layout(layout, None);
+            }
+        }
+    }
+}
+
+impl TransientPools {
+    fn new(vk_device: VkDeviceHandle) -> Self {
+        Self {
+            vk_device,
+            vk_descriptor_pools: Vec::new(),
+            current: 0,
+            vk_descriptor_set_layouts: Vec::new(),
+            handles: Vec::new(),
+        }
+    }
+
+    fn alloc(
+        &mut self,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet, DescriptorError> {
+        self.vk_descriptor_set_layouts.push(layout);
+        let layouts = [layout];
+        loop {
+            let new_pool = self.current == self.vk_descriptor_pools.len();
+            if new_pool {
+                self.vk_descriptor_pools.push(create_pool(
+                    &self.vk_device,
+                    vk::DescriptorPoolCreateFlags::empty(),
+                    TRANSIENT_SETS_PER_POOL,
+                )?);
+            }
+
+            let info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.vk_descriptor_pools[self.current])
+                .set_layouts(&layouts);
+            match unsafe { self.vk_device.allocate_descriptor_sets(&info) } {
+                Ok(sets) => return Ok(sets[0]),
+                // Full, try the next one. A set that doesn't fit in an empty pool never will.
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY)
+                | Err(vk::Result::ERROR_FRAGMENTED_POOL)
+                    if !new_pool =>
+                {
+                    self.current += 1
+                }
+                Err(e) => return Err(DescriptorError::SetAllocation(e)),
+            }
+        }
+    }
+
+    /// Returns the handles of the sets, which are no longer valid. The gpu has to be done with the
+    /// frame that they were allocated in.
+    fn reset(&mut self) -> Result<Vec<Handle<DescriptorSet>>, DescriptorError> {
+        unsafe {
+            for &pool in self.vk_descriptor_pools.iter() {
+                self.vk_device
+                    .reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())
+                    .map_err(DescriptorError::PoolReset)?;
+            }
+            for layout in self.vk_descriptor_set_layouts.drain(..) {
+                self.vk_device.destroy_descriptor_set_layout(layout, None);
+            }
+        }
+        self.current = 0;
+        Ok(std::mem::take(&mut self.handles))
+    }
+}
+
+fn is_buffer(ty: vk::DescriptorType) -> bool {
+    ty == vk::DescriptorType::UNIFORM_BUFFER || ty == vk::DescriptorType::STORAGE_BUFFER
 }
 
 pub struct DescriptorSetBuilder<'a> {
@@ -120,15 +251,17 @@ pub struct DescriptorSetBuilder<'a> {
     bindings: Vec<(vk::DescriptorSetLayoutBinding, usize)>,
     buffer_infos: Vec<[vk::DescriptorBufferInfo; MAX_FRAMES_IN_FLIGHT]>,
     image_infos: Vec<vk::DescriptorImageInfo>,
+    transient: bool,
 }
 
 impl<'a> DescriptorSetBuilder<'a> {
-    fn new(renderer: &'a mut Renderer) -> Self {
+    fn new(renderer: &'a mut Renderer, transient: bool) -> Self {
         Self {
             renderer,
             bindings: Vec::new(),
             buffer_infos: Vec::new(),
             image_infos: Vec::new(),
+            transient,
         }
     }
 }
@@ -141,7 +274,7 @@ impl<'a> DescriptorSetBuilder<'a> {
         stage_flags: vk::ShaderStageFlags,
         count: u32,
     ) {
-        let idx = if let vk::DescriptorType::UNIFORM_BUFFER = ty {
+        let idx = if is_buffer(ty) {
             self.buffer_infos.len()
         } else {
             self.image_infos.len()
@@ -213,6 +346,109 @@ impl<'a> DescriptorSetBuilder<'a> {
         self
     }
 
+    /// The whole storage buffer of each frame is bound, starting at the first element of the handle
+    pub fn add_storage_buffer(
+        mut self,
+        buf_h: &BufferHandle<StorageBuffer>,
+        binding: u32,
+        stage: ShaderStage,
+    ) -> Self {
+        let (buf0, buf1, stride) = {
+            let sbufs = &self.renderer.resources.storage_buffers;
+
+            let (buf0, buf1) = sbufs.get_all(&buf_h).expect("Failed to get buffer");
+
+            assert!(
+                buf1.is_some() || buf_h.mutability() == crate::mem::BufferMutability::Immutable
+            );
+            let buf1 = buf1.unwrap_or(buf0);
+            (*buf0.vk_buffer(), *buf1.vk_buffer(), buf0.stride())
+        };
+
+        self.add_binding(
+            vk::DescriptorType::STORAGE_BUFFER,
+            binding,
+            vk::ShaderStageFlags::from(stage),
+            1,
+        );
+
+        let info = |buffer| vk::DescriptorBufferInfo {
+            buffer,
+            offset: buf_h.idx() as u64 * stride as u64,
+            range: buf_h.n_elems() as u64 * stride as u64,
+        };
+        self.buffer_infos.push([info(buf0), info(buf1)]);
+
+        log::trace!("Added buffer info {:?}", self.buffer_infos.last().unwrap());
+        self
+    }
+
+    /// The vertices as a storage buffer, e.g. for a compute shader that deforms them. Like
+    /// add_storage_buffer, the buffer of each frame is bound if it is mutable. The whole buffer is
+    /// bound, as the offset of a sub-buffer is not necessarily aligned for storage buffers, so the
+    /// shader has to start at buf_h.idx().
+    pub fn add_vertex_buffer(
+        mut self,
+        buf_h: &BufferHandle<VertexBuffer>,
+        binding: u32,
+        stage: ShaderStage,
+    ) -> Self {
+        let (buf0, buf1) = {
+            let vbufs = &self.renderer.resources.vertex_buffers;
+
+            let (buf0, buf1) = vbufs.get_all(&buf_h).expect("Failed to get buffer");
+
+            assert!(
+                buf1.is_some() || buf_h.mutability() == crate::mem::BufferMutability::Immutable
+            );
+            let buf1 = buf1.unwrap_or(buf0);
+            (*buf0.vk_buffer(), *buf1.vk_buffer())
+        };
+
+        self.add_binding(
+            vk::DescriptorType::STORAGE_BUFFER,
+            binding,
+            vk::ShaderStageFlags::from(stage),
+            1,
+        );
+
+        let info = |buffer| vk::DescriptorBufferInfo {
+            buffer,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        };
+        self.buffer_infos.push([info(buf0), info(buf1)]);
+
+        log::trace!("Added buffer info {:?}", self.buffer_infos.last().unwrap());
+        self
+    }
+
+    /// A uniform buffer binding in the transient buffers of the frames, for data that is written
+    /// every frame. It has to be pointed at a slice with Frame::bind_transient each frame before
+    /// the set is bound.
+    pub fn add_transient<T: Copy>(mut self, binding: u32, stage: ShaderStage) -> Self {
+        self.add_binding(
+            vk::DescriptorType::UNIFORM_BUFFER,
+            binding,
+            vk::ShaderStageFlags::from(stage),
+            1,
+        );
+
+        let transient = &self.renderer.transient_buffers;
+        let mut infos = [vk::DescriptorBufferInfo::default(); MAX_FRAMES_IN_FLIGHT];
+        for (frame_idx, info) in infos.iter_mut().enumerate() {
+            *info = vk::DescriptorBufferInfo {
+                buffer: *transient.vk_buffer(frame_idx as u32),
+                offset: 0,
+                range: std::mem::size_of::<T>() as u64,
+            };
+        }
+        self.buffer_infos.push(infos);
+
+        log::trace!("Added buffer info {:?}", self.buffer_infos.last().unwrap());
+        self
+    }
+
     pub fn add_texture(
         mut self,
         tex_h: &Handle<Texture>,
@@ -250,6 +486,39 @@ impl<'a> DescriptorSetBuilder<'a> {
         self
     }
 
+    /// An image that compute shaders write, e.g. with imageStore. The texture has to be created
+    /// with TextureUsage::STORAGE, and it is in the GENERAL layout while it is written, see
+    /// ComputePassEncoder::begin_storage_image.
+    pub fn add_storage_image(
+        mut self,
+        tex_h: &Handle<Texture>,
+        binding: u32,
+        stage: ShaderStage,
+    ) -> Self {
+        let tex = self
+            .renderer
+            .get_texture(tex_h)
+            .expect("Failed to get texture");
+
+        let image_view = *tex.image_view().vk_image_view();
+
+        self.add_binding(
+            vk::DescriptorType::STORAGE_IMAGE,
+            binding,
+            vk::ShaderStageFlags::from(stage),
+            1,
+        );
+
+        self.image_infos.push(vk::DescriptorImageInfo {
+            image_layout: vk::ImageLayout::GENERAL,
+            image_view,
+            sampler: vk::Sampler::null(),
+        });
+        log::trace!("Added texture info {:?}", self.image_infos.last().unwrap());
+
+        self
+    }
+
     pub fn add_textures<I>(mut self, itr: I, binding: u32, stage: ShaderStage) -> Self
     where
         I: Iterator<Item = (Handle<Texture>, bool)> + ExactSizeIterator,
@@ -287,25 +556,44 @@ impl<'a> DescriptorSetBuilder<'a> {
 
     pub fn build(self) -> Handle<DescriptorSet> {
         let bindings_only = self.bindings.iter().map(|(x, _)| *x).collect::<Vec<_>>();
-        let (handle, sets) = self.renderer.allocate_descriptor_sets(&bindings_only);
+        // A transient set is only used in the frame that it was allocated in, so only the buffers
+        // of that frame are written to it
+        let (handle, sets) = if self.transient {
+            let frame_idx = self.renderer.frame_idx as usize;
+            let (handle, set) = self
+                .renderer
+                .allocate_transient_descriptor_set(&bindings_only);
+            (handle, vec![(frame_idx, set.vk_descriptor_set)])
+        } else {
+            let (handle, sets) = self.renderer.allocate_descriptor_sets(&bindings_only);
+            let sets = sets.iter().map(|set| set.vk_descriptor_set).enumerate();
+            (handle, sets.collect())
+        };
         let mut writes = Vec::new();
 
         for (bind_idx, (bind, info_idx)) in self.bindings.into_iter().enumerate() {
-            for (set_idx, set) in sets.iter().enumerate() {
-                if bind.descriptor_type == vk::DescriptorType::UNIFORM_BUFFER {
-                    writes.push(set.write_buffer(
-                        &self.buffer_infos[info_idx][set_idx],
-                        bind_idx as u32,
-                        1,
-                    ));
+            for &(frame_idx, vk_set) in sets.iter() {
+                if is_buffer(bind.descriptor_type) {
+                    writes.push(vk::WriteDescriptorSet {
+                        dst_set: vk_set,
+                        dst_binding: bind_idx as u32,
+                        descriptor_count: 1,
+                        descriptor_type: bind.descriptor_type,
+                        p_buffer_info: &self.buffer_infos[info_idx][frame_idx]
+                            as *const vk::DescriptorBufferInfo,
+                        ..Default::default()
+                    });
                 } else {
-                    assert!(bind.descriptor_type == vk::DescriptorType::COMBINED_IMAGE_SAMPLER);
+                    assert!(
+                        bind.descriptor_type == vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+                            || bind.descriptor_type == vk::DescriptorType::STORAGE_IMAGE
+                    );
                     assert!(self.image_infos.len() >= (info_idx + bind.descriptor_count as usize));
                     let write = vk::WriteDescriptorSet {
-                        dst_set: set.vk_descriptor_set,
+                        dst_set: vk_set,
                         dst_binding: bind_idx as u32,
                         descriptor_count: bind.descriptor_count,
-                        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                        descriptor_type: bind.descriptor_type,
                         p_image_info: &self.image_infos[info_idx] as *const vk::DescriptorImageInfo,
                         ..Default::default()
                     };
@@ -320,23 +608,45 @@ impl<'a> DescriptorSetBuilder<'a> {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Allocation {
+    /// From the pool, freed with the handle
+    Pool,
+    /// From the transient pools of a frame, see DescriptorSet::transient_builder
+    Transient,
+    /// Owned by something else, e.g. the bindless set
+    External,
+}
+
 // TODO: Rename? (to avoid DescriptorSetDescriptor)
 pub struct DescriptorSet {
     vk_descriptor_set: vk::DescriptorSet,
+    allocation: Allocation,
 }
 
 impl DescriptorSet {
-    fn new(vk_descriptor_set: vk::DescriptorSet) -> Self {
-        Self { vk_descriptor_set }
+    fn new(vk_descriptor_set: vk::DescriptorSet, allocation: Allocation) -> Self {
+        Self {
+            vk_descriptor_set,
+            allocation,
+        }
     }
 
     pub fn builder(renderer: &mut crate::Renderer) -> DescriptorSetBuilder {
-        DescriptorSetBuilder::new(renderer)
+        DescriptorSetBuilder::new(renderer, false)
     }
 
-    fn write_buffer(
+    /// A set that is only valid in this frame, e.g. for a texture that is recreated every frame.
+    /// It is allocated from pools that belong to the frame slot and are reset when it is reused,
+    /// so the set is neither destroyed nor freed.
+    pub fn transient_builder<'a>(frame: &'a mut crate::Frame<'_>) -> DescriptorSetBuilder<'a> {
+        DescriptorSetBuilder::new(&mut *frame.renderer, true)
+    }
+
+    pub(crate) fn write_buffer(
         &self,
         buffer: &vk::DescriptorBufferInfo,
+        descriptor_type: vk::DescriptorType,
         dst_binding: u32,
         count: u32,
     ) -> vk::WriteDescriptorSet {
@@ -344,7 +654,7 @@ impl DescriptorSet {
             dst_set: self.vk_descriptor_set,
             dst_binding,
             descriptor_count: count,
-            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_type,
             p_buffer_info: buffer as *const vk::DescriptorBufferInfo,
             ..Default::default()
         }
@@ -362,6 +672,7 @@ pub struct DescriptorSetDescriptor {
 pub struct DescriptorSets {
     vk_device: VkDeviceHandle,
     descriptor_pool: DescriptorPool,
+    transient_pools: [TransientPools; MAX_FRAMES_IN_FLIGHT],
     storage: BufferedStorage<DescriptorSet>,
 }
 
@@ -370,21 +681,32 @@ impl DescriptorSets {
         Ok(Self {
             vk_device: device.vk_device(),
             descriptor_pool: DescriptorPool::new(device)?,
+            transient_pools: [
+                TransientPools::new(device.vk_device()),
+                TransientPools::new(device.vk_device()),
+            ],
             storage: Default::default(),
         })
     }
 
-    pub fn alloc(
-        &mut self,
+    fn create_layout(
+        &self,
         bindings: &[vk::DescriptorSetLayoutBinding],
-    ) -> Result<(Handle<DescriptorSet>, &[DescriptorSet; 2]), DescriptorError> {
+    ) -> Result<vk::DescriptorSetLayout, DescriptorError> {
         // TODO: We should not create a descriptor set layout everytime we allocate. Hash bindings instead?
         let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
-        let dset_layout = unsafe {
+        unsafe {
             self.vk_device
                 .create_descriptor_set_layout(&info, None)
-                .expect("Failed to create desc set layout")
-        };
+                .map_err(DescriptorError::LayoutCreation)
+        }
+    }
+
+    pub fn alloc(
+        &mut self,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> Result<(Handle<DescriptorSet>, &[DescriptorSet; 2]), DescriptorError> {
+        let dset_layout = self.create_layout(bindings)?;
 
         let mut desc_sets = self
             .descriptor_pool
@@ -400,7 +722,89 @@ impl DescriptorSets {
         Ok((handle, sets))
     }
 
+    /// A set from the transient pools of the frame. The handle is valid until the pools are reset
+    /// with reset_transient.
+    pub fn alloc_transient(
+        &mut self,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+        frame_idx: u32,
+    ) -> Result<(Handle<DescriptorSet>, &DescriptorSet), DescriptorError> {
+        let dset_layout = self.create_layout(bindings)?;
+        let pools = &mut self.transient_pools[frame_idx as usize];
+        let vk_descriptor_set = pools.alloc(dset_layout)?;
+        // Both frames get the same set, as only one of them uses it
+        let set = || DescriptorSet::new(vk_descriptor_set, Allocation::Transient);
+        let handle = self.storage.add([set(), set()]);
+        pools.handles.push(handle);
+        let set = self
+            .storage
+            .get(&handle, frame_idx as usize)
+            .expect("Descriptor set that we just added is missing...");
+
+        Ok((handle, set))
+    }
+
+    /// Frees all the transient sets of the frame at once. The gpu has to be done with the frame
+    /// that they were allocated in.
+    pub fn reset_transient(&mut self, frame_idx: u32) -> Result<(), DescriptorError> {
+        for h in self.transient_pools[frame_idx as usize].reset()? {
+            self.storage.remove(h);
+        }
+        Ok(())
+    }
+
+    /// The number of transient sets that the frames in flight have allocated
+    pub fn transient_len(&self) -> usize {
+        self.transient_pools.iter().map(|p| p.handles.len()).sum()
+    }
+
+    /// The number of descriptor pools, including the transient ones
+    pub fn pool_count(&self) -> usize {
+        1 + self
+            .transient_pools
+            .iter()
+            .map(|p| p.vk_descriptor_pools.len())
+            .sum::<usize>()
+    }
+
+    /// A handle for a set that was not allocated from the pool, so that it can be bound like the
+    /// others. The same set is used in all frames, and it is not freed with the handle.
+    pub(crate) fn add_external(
+        &mut self,
+        vk_descriptor_set: vk::DescriptorSet,
+    ) -> Handle<DescriptorSet> {
+        let set = || DescriptorSet::new(vk_descriptor_set, Allocation::External);
+        self.storage.add([set(), set()])
+    }
+
     pub fn get(&self, h: &Handle<DescriptorSet>, frame_idx: usize) -> Option<&DescriptorSet> {
         self.storage.get(h, frame_idx)
     }
+
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+    }
+
+    /// The sets must not be in use by any frame in flight
+    pub fn free(&mut self, h: Handle<DescriptorSet>) -> Result<bool, DescriptorError> {
+        if self
+            .storage
+            .get(&h, 0)
+            .map_or(false, |set| set.allocation != Allocation::Pool)
+        {
+            log::error!("Descriptor set {:?} is not owned by the pool", h);
+            return Ok(false);
+        }
+        if let Some(sets) = self.storage.remove(h) {
+            let vk_sets = [sets[0].vk_descriptor_set, sets[1].vk_descriptor_set];
+            self.descriptor_pool.free(&vk_sets)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
 }
@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+
+use crate::descriptor::DescriptorSet;
+use crate::mem::{BufferHandle, IndexBuffer, StorageBuffer, UniformBuffer, VertexBuffer};
+use crate::pipeline::{ComputePipeline, GraphicsPipeline};
+use crate::render_pass::RenderPass;
+use crate::resource::Handle;
+use crate::sparse::Page;
+use crate::texture::Texture;
+
+/// A resource that can be destroyed with Renderer::destroy_deferred
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Destroyable {
+    Texture(Handle<Texture>),
+    DescriptorSet(Handle<DescriptorSet>),
+    /// Pipelines are shared through their cache so this releases one reference to it.
+    GraphicsPipeline(Handle<GraphicsPipeline>),
+    ComputePipeline(Handle<ComputePipeline>),
+    /// Destroys the whole buffer, including all sub-buffers of it.
+    VertexBuffer(BufferHandle<VertexBuffer>),
+    IndexBuffer(BufferHandle<IndexBuffer>),
+    UniformBuffer(BufferHandle<UniformBuffer>),
+    StorageBuffer(BufferHandle<StorageBuffer>),
+    /// Render passes are replaced when their sample count changes
+    RenderPass(Handle<RenderPass>),
+    /// Unbinds the memory of a page of a sparse texture, see sparse
+    SparsePage(Handle<Texture>, Page),
+}
+
+impl From<Handle<Texture>> for Destroyable {
+    fn from(h: Handle<Texture>) -> Self {
+        Self::Texture(h)
+    }
+}
+
+impl From<Handle<DescriptorSet>> for Destroyable {
+    fn from(h: Handle<DescriptorSet>) -> Self {
+        Self::DescriptorSet(h)
+    }
+}
+
+impl From<Handle<GraphicsPipeline>> for Destroyable {
+    fn from(h: Handle<GraphicsPipeline>) -> Self {
+        Self::GraphicsPipeline(h)
+    }
+}
+
+impl From<Handle<ComputePipeline>> for Destroyable {
+    fn from(h: Handle<ComputePipeline>) -> Self {
+        Self::ComputePipeline(h)
+    }
+}
+
+impl From<BufferHandle<VertexBuffer>> for Destroyable {
+    fn from(h: BufferHandle<VertexBuffer>) -> Self {
+        Self::VertexBuffer(h)
+    }
+}
+
+impl From<BufferHandle<IndexBuffer>> for Destroyable {
+    fn from(h: BufferHandle<IndexBuffer>) -> Self {
+        Self::IndexBuffer(h)
+    }
+}
+
+impl From<BufferHandle<UniformBuffer>> for Destroyable {
+    fn from(h: BufferHandle<UniformBuffer>) -> Self {
+        Self::UniformBuffer(h)
+    }
+}
+
+impl From<BufferHandle<StorageBuffer>> for Destroyable {
+    fn from(h: BufferHandle<StorageBuffer>) -> Self {
+        Self::StorageBuffer(h)
+    }
+}
+
+impl From<Handle<RenderPass>> for Destroyable {
+    fn from(h: Handle<RenderPass>) -> Self {
+        Self::RenderPass(h)
+    }
+}
+
+/// Holds resources until the frames that might use them are no longer in flight. Frames are
+/// numbered by the order they are submitted in.
+#[derive(Debug)]
+pub(crate) struct DestructionQueue<T> {
+    frames_in_flight: u64,
+    queue: VecDeque<(u64, T)>,
+}
+
+impl<T> DestructionQueue<T> {
+    pub fn new(frames_in_flight: usize) -> Self {
+        Self {
+            frames_in_flight: frames_in_flight as u64,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Queue t for destruction. It may be used by frame and any frame submitted before it.
+    pub fn push(&mut self, frame: u64, t: T) {
+        debug_assert!(self.queue.back().map(|(f, _)| *f <= frame).unwrap_or(true));
+        self.queue.push_back((frame, t));
+    }
+
+    /// Remove everything that can be destroyed when frame is about to be recorded, i.e. when the
+    /// frame frames_in_flight before it has finished executing.
+    pub fn pop_done(&mut self, frame: u64) -> Vec<T> {
+        let mut done = Vec::new();
+        while let Some((queued, _)) = self.queue.front() {
+            if queued + self.frames_in_flight > frame {
+                break;
+            }
+            done.push(self.queue.pop_front().expect("Front was just checked").1);
+        }
+        done
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.queue.drain(..).map(|(_, t)| t)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_for_frames_in_flight() {
+        let mut queue = DestructionQueue::new(2);
+        queue.push(0, "a");
+        queue.push(1, "b");
+        queue.push(1, "c");
+
+        assert!(queue.pop_done(0).is_empty());
+        assert!(queue.pop_done(1).is_empty());
+        assert_eq!(queue.pop_done(2), vec!["a"]);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop_done(3), vec!["b", "c"]);
+        assert!(queue.pop_done(4).is_empty());
+    }
+
+    #[test]
+    fn drain_empties_queue() {
+        let mut queue = DestructionQueue::new(2);
+        queue.push(5, 1);
+        queue.push(6, 2);
+        assert_eq!(queue.drain().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(queue.len(), 0);
+    }
+}
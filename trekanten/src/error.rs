@@ -4,6 +4,8 @@ use crate::backend::*;
 use crate::descriptor;
 use crate::mem;
 use crate::pipeline;
+use crate::sparse;
+use crate::texture;
 
 use crate::resource::ID;
 
@@ -28,10 +30,18 @@ pub enum RenderError {
     DepthBuffer(#[from] depth_buffer::DepthBufferError),
     Sync(#[from] sync::SyncError),
     Swapchain(swapchain::SwapchainError),
+    Offscreen(#[from] offscreen::OffscreenError),
+    Query(#[from] query::QueryError),
+    Readback(mem::MemoryError),
     RenderTarget(#[from] framebuffer::FramebufferError),
     UniformBuffer(mem::MemoryError),
+    StorageBuffer(mem::MemoryError),
+    TransientBuffer(mem::MemoryError),
     VertexBuffer(mem::MemoryError),
     IndexBuffer(mem::MemoryError),
+    Texture(#[from] texture::TextureError),
+    Sparse(#[from] sparse::SparseError),
+    SparseUpload(mem::MemoryError),
     // TODO: Should this be an error?
     NeedsResize(ResizeReason),
     // TODO: Resource typename here as well
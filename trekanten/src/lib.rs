@@ -3,8 +3,12 @@ use ash::vk;
 pub use ash::vk as raw_vk;
 
 mod backend;
+pub mod bindless;
 mod common;
+mod compute_pass;
+pub mod debug_name;
 pub mod descriptor;
+mod destruction;
 mod error;
 pub mod loader;
 pub mod mem;
@@ -12,10 +16,16 @@ pub mod pipeline;
 mod render_pass;
 mod render_target;
 pub mod resource;
+pub mod sparse;
+pub mod stats;
 pub mod texture;
+mod timestamps;
+pub mod trace;
 pub mod util;
 pub mod vertex;
 
+pub use compute_pass::ComputePassEncoder;
+pub use destruction::Destroyable;
 pub use error::RenderError;
 pub use error::ResizeReason;
 pub use loader::Loader;
@@ -23,16 +33,20 @@ pub use mem::{BufferHandle, BufferMutability};
 pub use render_pass::{RenderPass, RenderPassEncoder};
 pub use render_target::RenderTarget;
 pub use resource::{Async, Handle, MutResourceManager, ResourceManager};
+pub use stats::{FrameStats, GpuTiming, ResourceCounts};
 pub use texture::Texture;
+pub use timestamps::{GpuTimer, MAX_GPU_TIMERS};
 
 pub use command::CommandBuffer;
+pub use debug_name::DebugNamed;
 
 use ash::version::DeviceV1_0;
 use backend::*;
-use common::MAX_FRAMES_IN_FLIGHT;
+pub use common::MAX_FRAMES_IN_FLIGHT;
 use device::HasVkDevice;
 
 use crate::mem::BufferDescriptor as _;
+use crate::sparse::{Page, SparseError, SparseImage, SparseTextureDescriptor};
 
 // Notes:
 // We can have N number of swapchain images, it depends on the backing presentation implementation.
@@ -47,19 +61,23 @@ struct FrameSynchronization {
     render_done: sync::Semaphore,
     in_flight: sync::Fence,
     command_pool: Option<command::CommandPool>,
+    // Written while recording, through the shared reference of the render pass encoders
+    timestamps: std::cell::RefCell<timestamps::FrameTimestamps>,
 }
 
 impl FrameSynchronization {
-    pub fn new(device: &device::Device) -> Result<Self, sync::SyncError> {
+    pub fn new(device: &device::Device) -> Result<Self, RenderError> {
         let image_avail = sync::Semaphore::new(device)?;
         let render_done = sync::Semaphore::new(device)?;
         let in_flight = sync::Fence::signaled(device)?;
+        let timestamps = timestamps::FrameTimestamps::new(device)?;
 
         Ok(Self {
             image_available: image_avail,
             render_done,
             in_flight,
             command_pool: None,
+            timestamps: std::cell::RefCell::new(timestamps),
         })
     }
 }
@@ -67,11 +85,14 @@ impl FrameSynchronization {
 pub struct Frame<'a> {
     renderer: &'a mut Renderer,
     recorded_command_buffers: Vec<vk::CommandBuffer>,
+    // Resets the timestamp queries, submitted before the recorded command buffers
+    query_reset_command_buffer: vk::CommandBuffer,
     gfx_command_pool: command::CommandPool,
 }
 
 pub struct FinishedFrame {
     recorded_command_buffers: Vec<vk::CommandBuffer>,
+    query_reset_command_buffer: vk::CommandBuffer,
     gfx_command_pool: command::CommandPool,
 }
 
@@ -96,6 +117,59 @@ impl<'a> Frame<'a> {
         self.renderer.update_uniform(h, data)
     }
 
+    /// Writes `data` to the transient buffer of this frame. The slice is only valid in this frame,
+    /// so this is meant for uniform data that is written every frame, see bind_transient.
+    pub fn write_transient<T: Copy>(
+        &mut self,
+        data: &T,
+    ) -> Result<mem::TransientSlice, RenderError> {
+        let frame_idx = self.renderer.frame_idx;
+        self.renderer
+            .transient_buffers
+            .write(frame_idx, data)
+            .map_err(RenderError::TransientBuffer)
+    }
+
+    /// Points a binding that was added with DescriptorSetBuilder::add_transient at a slice of this
+    /// frame. Only the copy of the set that belongs to this frame is updated, so this has to be
+    /// done before the set is bound in this frame.
+    pub fn bind_transient(
+        &mut self,
+        set: &Handle<descriptor::DescriptorSet>,
+        binding: u32,
+        slice: mem::TransientSlice,
+    ) {
+        let frame_idx = self.renderer.frame_idx;
+        assert_eq!(
+            slice.frame_idx(),
+            frame_idx,
+            "Transient slices are only valid in the frame that they were allocated in"
+        );
+        let info = vk::DescriptorBufferInfo {
+            buffer: *self.renderer.transient_buffers.vk_buffer(frame_idx),
+            offset: slice.offset(),
+            range: slice.size(),
+        };
+        let set = self
+            .renderer
+            .resources
+            .descriptor_sets
+            .get(set, frame_idx as usize)
+            .expect("Failed to find descriptor set");
+        let write = set.write_buffer(&info, vk::DescriptorType::UNIFORM_BUFFER, binding, 1);
+        self.renderer.update_descriptor_sets(&[write]);
+    }
+
+    /// Writes `data` to the elements of the storage buffer of this frame, starting at the first
+    /// element of the handle
+    pub fn update_storage_blocking<T: Copy>(
+        &mut self,
+        h: &BufferHandle<mem::StorageBuffer>,
+        data: &[T],
+    ) -> Result<(), RenderError> {
+        self.renderer.update_storage(h, data)
+    }
+
     pub fn begin_render_pass(
         &'a self,
         mut buf: command::CommandBuffer,
@@ -104,6 +178,13 @@ impl<'a> Frame<'a> {
         extent: util::Extent2D,
         clear_values: &[vk::ClearValue],
     ) -> Result<render_pass::RenderPassEncoder<'a>, command::CommandError> {
+        let trace = self.renderer.command_trace.as_ref();
+        if let Some(trace) = trace {
+            trace.record(trace::Command::BeginRenderPass {
+                render_pass: render_pass.id(),
+                extent,
+            });
+        }
         let render_pass = self
             .renderer
             .resources
@@ -122,7 +203,22 @@ impl<'a> Frame<'a> {
             &self.renderer.resources,
             buf,
             self.renderer.frame_idx,
-        ))
+        )
+        .with_trace(trace))
+    }
+
+    /// For dispatches that have to finish before the render passes that are recorded after the
+    /// returned encoder is ended
+    pub fn begin_compute_pass(
+        &'a self,
+        buf: command::CommandBuffer,
+    ) -> compute_pass::ComputePassEncoder<'a> {
+        compute_pass::ComputePassEncoder::new(
+            &self.renderer.resources,
+            buf,
+            self.renderer.frame_idx,
+            self.renderer.command_trace.as_ref(),
+        )
     }
 
     pub fn begin_presentation_pass(
@@ -156,16 +252,39 @@ impl<'a> Frame<'a> {
         self.renderer.swapchain_extent()
     }
 
+    /// Starts timing the gpu work that is recorded to `cmd_buffer` from here until the timer is
+    /// ended. The result shows up in FrameStats::gpu_timings when the gpu has finished the frame.
+    /// Does nothing if the device does not support timestamps.
+    pub fn begin_gpu_timer(
+        &self,
+        cmd_buffer: &mut command::CommandBuffer,
+        name: &'static str,
+    ) -> GpuTimer {
+        self.renderer.frame_synchronization[self.renderer.frame_idx as usize]
+            .timestamps
+            .borrow_mut()
+            .begin(cmd_buffer, name)
+    }
+
+    pub fn end_gpu_timer(&self, cmd_buffer: &mut command::CommandBuffer, timer: GpuTimer) {
+        self.renderer.frame_synchronization[self.renderer.frame_idx as usize]
+            .timestamps
+            .borrow_mut()
+            .end(cmd_buffer, timer)
+    }
+
     pub fn finish(self) -> FinishedFrame {
         assert!(!self.recorded_command_buffers.is_empty());
         let Frame {
             recorded_command_buffers,
+            query_reset_command_buffer,
             gfx_command_pool,
             ..
         } = self;
 
         FinishedFrame {
             recorded_command_buffers,
+            query_reset_command_buffer,
             gfx_command_pool,
         }
     }
@@ -176,6 +295,14 @@ impl<'a> Frame<'a> {
     pub fn get_texture(&self, handle: &Handle<Texture>) -> Option<&Texture> {
         self.renderer.get_texture(handle)
     }
+
+    pub fn frame_stats(&self) -> &FrameStats {
+        self.renderer.frame_stats()
+    }
+
+    pub fn resource_counts(&self) -> ResourceCounts {
+        self.renderer.resource_counts()
+    }
 }
 
 macro_rules! impl_mut_buffer_manager_frame {
@@ -277,6 +404,9 @@ pub enum SyncResourceCommand {
     CreateUniformBuffer {
         descriptor: mem::OwningUniformBufferDescriptor,
     },
+    CreateStorageBuffer {
+        descriptor: mem::OwningStorageBufferDescriptor,
+    },
     CreateTexture {
         descriptor: texture::TextureDescriptor,
     },
@@ -295,6 +425,10 @@ pub enum PendingSyncResourceCommand {
         handle: mem::BufferHandle<mem::UniformBuffer>,
         transients: [Option<mem::DeviceBuffer>; 2],
     },
+    CreateStorageBuffer {
+        handle: mem::BufferHandle<mem::StorageBuffer>,
+        transients: [Option<mem::DeviceBuffer>; 2],
+    },
     CreateTexture {
         handle: resurs::Handle<texture::Texture>,
         transients: mem::DeviceBuffer,
@@ -313,6 +447,9 @@ impl PendingSyncResourceCommand {
             Self::CreateUniformBuffer { handle, .. } => {
                 FinishedResourceCommand::CreateUniformBuffer { handle }
             }
+            Self::CreateStorageBuffer { handle, .. } => {
+                FinishedResourceCommand::CreateStorageBuffer { handle }
+            }
             Self::CreateTexture { handle, .. } => FinishedResourceCommand::CreateTexture { handle },
         }
     }
@@ -328,6 +465,9 @@ pub enum FinishedResourceCommand {
     CreateUniformBuffer {
         handle: mem::BufferHandle<mem::UniformBuffer>,
     },
+    CreateStorageBuffer {
+        handle: mem::BufferHandle<mem::StorageBuffer>,
+    },
     CreateTexture {
         handle: resurs::Handle<texture::Texture>,
     },
@@ -340,12 +480,41 @@ struct PresentationRenderTarget {
     _color_buffer: color_buffer::ColorBuffer,
 }
 
+/// Where the presentation render pass ends up, either the window or an image that can be read back
+enum Presenter {
+    Swapchain(swapchain::Swapchain),
+    Offscreen(offscreen::OffscreenTarget),
+}
+
+impl Presenter {
+    fn extent(&self) -> util::Extent2D {
+        match self {
+            Presenter::Swapchain(swapchain) => swapchain.info().extent,
+            Presenter::Offscreen(target) => target.info().extent,
+        }
+    }
+
+    fn format(&self) -> util::Format {
+        match self {
+            Presenter::Swapchain(swapchain) => util::Format::from(swapchain.info().format),
+            Presenter::Offscreen(target) => util::Format::from(target.info().format),
+        }
+    }
+
+    fn final_layout(&self) -> vk::ImageLayout {
+        match self {
+            Presenter::Swapchain(_) => vk::ImageLayout::PRESENT_SRC_KHR,
+            Presenter::Offscreen(_) => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        }
+    }
+}
+
 pub struct Renderer {
     resources: resource::Resources,
 
     // Swapchain-related
     presentation_render_target: Option<PresentationRenderTarget>,
-    swapchain: swapchain::Swapchain,
+    presenter: Presenter,
     swapchain_image_idx: u32, // TODO: Bake this into the swapchain?
     image_to_frame_idx: Vec<Option<u32>>,
 
@@ -353,14 +522,31 @@ pub struct Renderer {
 
     util_command_pool: command::CommandPool,
 
-    // Needs to be kept-alive
-    _debug_utils: backend::validation_layers::DebugUtils,
+    // Needs to be kept-alive, also names the vulkan objects
+    debug_utils: backend::validation_layers::DebugUtils,
 
     frame_synchronization: [FrameSynchronization; MAX_FRAMES_IN_FLIGHT],
     frame_idx: u32,
+    frame_stats: FrameStats,
+    // Total number of submitted frames, used to number the frames for deferred destruction
+    submitted_frames: u64,
+    destruction_queue: destruction::DestructionQueue<Destroyable>,
+    pipeline_cache: pipeline::PipelineCache,
+    // Some while tracing the recorded commands
+    command_trace: Option<trace::CommandTrace>,
+    // Textures that were replaced by the error texture, see take_texture_failures
+    texture_failures: Vec<texture::TextureLoadFailure>,
+    // Uniform data that is written every frame, see Frame::write_transient
+    transient_buffers: mem::TransientBuffers,
+    // None if the device doesn't support it. The handle binds its set, see bindless_descriptor_set.
+    bindless: Option<(
+        bindless::BindlessTextures,
+        Handle<descriptor::DescriptorSet>,
+    )>,
 
     device: device::Device,
-    surface: surface::Surface,
+    // None when rendering headless
+    surface: Option<surface::Surface>,
     instance: instance::Instance,
 }
 
@@ -373,9 +559,92 @@ impl std::ops::Drop for Renderer {
     }
 }
 
+/// The parts of the renderer that are created with the device, see Renderer::recreate_device. The
+/// device is last, so that it is dropped after the rest.
+struct DeviceObjects {
+    resources: resource::Resources,
+    presentation_render_target: Option<PresentationRenderTarget>,
+    presenter: Presenter,
+    image_to_frame_idx: Vec<Option<u32>>,
+    loader: Option<Loader>,
+    util_command_pool: command::CommandPool,
+    frame_synchronization: [FrameSynchronization; MAX_FRAMES_IN_FLIGHT],
+    destruction_queue: destruction::DestructionQueue<Destroyable>,
+    pipeline_cache: pipeline::PipelineCache,
+    transient_buffers: mem::TransientBuffers,
+    bindless: Option<(
+        bindless::BindlessTextures,
+        Handle<descriptor::DescriptorSet>,
+    )>,
+    device: device::Device,
+}
+
+impl DeviceObjects {
+    fn new(
+        mut device: device::Device,
+        presenter_and_co: PresenterAndCo,
+    ) -> Result<Self, RenderError> {
+        let PresenterAndCo {
+            presenter,
+            image_to_frame_idx,
+        } = presenter_and_co;
+
+        let frame_synchronization = [
+            FrameSynchronization::new(&device)?,
+            FrameSynchronization::new(&device)?,
+        ];
+
+        let util_command_pool =
+            command::CommandPool::new(&device, device.graphics_queue_family().clone())?;
+        let mut descriptor_sets = descriptor::DescriptorSets::new(&device)?;
+        let bindless = if device.supports_bindless_textures() {
+            let textures = bindless::BindlessTextures::new(&device)?;
+            let set = descriptor_sets.add_external(textures.vk_descriptor_set());
+            Some((textures, set))
+        } else {
+            None
+        };
+        let transient_buffers = mem::TransientBuffers::new(
+            &device.allocator(),
+            device.uniform_buffer_offset_alignment(),
+        )
+        .map_err(RenderError::TransientBuffer)?;
+        let resources = resource::Resources {
+            uniform_buffers: mem::UniformBuffers::default(),
+            storage_buffers: mem::StorageBuffers::default(),
+            vertex_buffers: mem::VertexBuffers::default(),
+            index_buffers: mem::IndexBuffers::default(),
+            textures: texture::Textures::default(),
+            graphics_pipelines: pipeline::GraphicsPipelines::default(),
+            compute_pipelines: resurs::Storage::default(),
+            descriptor_sets,
+            render_passes: resurs::Storage::default(),
+            render_targets: resurs::Storage::default(),
+        };
+
+        let loader = Some(Loader::new(&mut device));
+        let pipeline_cache = pipeline::PipelineCache::new(&device)?;
+
+        Ok(Self {
+            resources,
+            presentation_render_target: None,
+            presenter,
+            image_to_frame_idx,
+            loader,
+            util_command_pool,
+            frame_synchronization,
+            destruction_queue: destruction::DestructionQueue::new(MAX_FRAMES_IN_FLIGHT),
+            pipeline_cache,
+            transient_buffers,
+            bindless,
+            device,
+        })
+    }
+}
+
 // Result holder struct
-struct SwapchainAndCo {
-    swapchain: swapchain::Swapchain,
+struct PresenterAndCo {
+    presenter: Presenter,
     image_to_frame_idx: Vec<Option<u32>>,
 }
 
@@ -385,13 +654,26 @@ fn create_swapchain_and_co(
     surface: &surface::Surface,
     requested_extent: &util::Extent2D,
     old: Option<&swapchain::Swapchain>,
-) -> Result<SwapchainAndCo, RenderError> {
+) -> Result<PresenterAndCo, RenderError> {
     let swapchain =
         swapchain::Swapchain::new(&instance, &device, &surface, &requested_extent, old)?;
 
     let image_to_frame_idx: Vec<Option<u32>> = (0..swapchain.num_images()).map(|_| None).collect();
-    Ok(SwapchainAndCo {
-        swapchain,
+    Ok(PresenterAndCo {
+        presenter: Presenter::Swapchain(swapchain),
+        image_to_frame_idx,
+    })
+}
+
+fn create_offscreen_and_co(
+    device: &device::Device,
+    extent: &util::Extent2D,
+) -> Result<PresenterAndCo, RenderError> {
+    let target = offscreen::OffscreenTarget::new(device, extent, MAX_FRAMES_IN_FLIGHT)?;
+
+    let image_to_frame_idx: Vec<Option<u32>> = (0..target.num_images()).map(|_| None).collect();
+    Ok(PresenterAndCo {
+        presenter: Presenter::Offscreen(target),
         image_to_frame_idx,
     })
 }
@@ -455,10 +737,20 @@ impl Renderer {
                     uniform_buffers
                 )
             }
+            SyncResourceCommand::CreateStorageBuffer { descriptor } => {
+                process_buffer_creation!(
+                    CreateStorageBuffer,
+                    descriptor,
+                    self,
+                    cmd_buffer,
+                    storage_buffers
+                )
+            }
             SyncResourceCommand::CreateTexture { descriptor } => {
-                let (image, transients) = descriptor
-                    .enqueue(&self.device.allocator(), &self.device, cmd_buffer)
+                let (image, transients, failure) = descriptor
+                    .enqueue_or_error_texture(&self.device.allocator(), &self.device, cmd_buffer)
                     .expect("Fail");
+                self.texture_failures.extend(failure);
 
                 let handle = self.resources.textures.add(image);
                 Some(PendingSyncResourceCommand::CreateTexture { handle, transients })
@@ -506,25 +798,26 @@ impl Renderer {
             .get(&render_pass_h)
             .expect("No presentation pass handle");
         let msaa_sample_count = render_pass.0.msaa_sample_count();
-        let _depth_buffer = depth_buffer::DepthBuffer::new(
-            &self.device,
-            &self.swapchain.info().extent,
-            msaa_sample_count,
-        )?;
-        let _color_buffer = color_buffer::ColorBuffer::new(
-            &self.device,
-            format,
-            &self.swapchain.info().extent,
-            msaa_sample_count,
-        )?;
-        let swapchain_render_targets = self
-            .swapchain
-            .create_framebuffers_for(&render_pass.0, &_depth_buffer, &_color_buffer)?
+        let extent = self.presenter.extent();
+        let _depth_buffer =
+            depth_buffer::DepthBuffer::new(&self.device, &extent, msaa_sample_count)?;
+        let _color_buffer =
+            color_buffer::ColorBuffer::new(&self.device, format, &extent, msaa_sample_count)?;
+        let framebuffers = match &self.presenter {
+            Presenter::Swapchain(swapchain) => {
+                swapchain.create_framebuffers_for(&render_pass.0, &_depth_buffer, &_color_buffer)?
+            }
+            Presenter::Offscreen(target) => {
+                target.create_framebuffers_for(&render_pass.0, &_depth_buffer, &_color_buffer)?
+            }
+        };
+        let swapchain_render_targets = framebuffers
             .into_iter()
             .map(|fb| {
-                self.resources
-                    .render_targets
-                    .add(RenderTarget { inner: fb })
+                self.resources.render_targets.add(RenderTarget {
+                    inner: fb,
+                    _msaa_buffers: None,
+                })
             })
             .collect();
 
@@ -537,78 +830,220 @@ impl Renderer {
     }
 }
 
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RendererOptions {
+    /// Enable the validation layers and the debug utils messenger, and name the vulkan objects
+    /// that are given names with Renderer::set_debug_name. Always on with the validation-layers
+    /// feature, unless TREK_DISABLE_VALIDATION_LAYERS is set.
+    pub vk_debug: bool,
+}
+
 impl Renderer {
     pub fn new<W>(window: &W, window_extent: util::Extent2D) -> Result<Self, RenderError>
     where
         W: raw_window_handle::HasRawWindowHandle,
     {
-        let instance = instance::Instance::new(window)?;
-        let _debug_utils = backend::validation_layers::DebugUtils::new(&instance)?;
+        Self::new_with_options(window, window_extent, RendererOptions::default())
+    }
+
+    pub fn new_with_options<W>(
+        window: &W,
+        window_extent: util::Extent2D,
+        options: RendererOptions,
+    ) -> Result<Self, RenderError>
+    where
+        W: raw_window_handle::HasRawWindowHandle,
+    {
+        let instance = instance::Instance::new(window, options.vk_debug)?;
+        let debug_utils = backend::validation_layers::DebugUtils::new(&instance)?;
         let surface = surface::Surface::new(&instance, window)?;
-        let mut device = device::Device::new(&instance, &surface)?;
+        let device = device::Device::new(&instance, Some(&surface))?;
 
-        let SwapchainAndCo {
-            swapchain,
-            image_to_frame_idx,
-        } = create_swapchain_and_co(&instance, &device, &surface, &window_extent, None)?;
+        let presenter_and_co =
+            create_swapchain_and_co(&instance, &device, &surface, &window_extent, None)?;
 
-        let frame_synchronization = [
-            FrameSynchronization::new(&device)?,
-            FrameSynchronization::new(&device)?,
-        ];
+        Self::with_presenter(
+            instance,
+            debug_utils,
+            Some(surface),
+            device,
+            presenter_and_co,
+        )
+    }
 
-        let util_command_pool =
-            command::CommandPool::new(&device, device.graphics_queue_family().clone())?;
-        let descriptor_sets = descriptor::DescriptorSets::new(&device)?;
-        let resources = resource::Resources {
-            uniform_buffers: mem::UniformBuffers::default(),
-            vertex_buffers: mem::VertexBuffers::default(),
-            index_buffers: mem::IndexBuffers::default(),
-            textures: texture::Textures::default(),
-            graphics_pipelines: pipeline::GraphicsPipelines::default(),
-            descriptor_sets,
-            render_passes: resurs::Storage::default(),
-            render_targets: resurs::Storage::default(),
-        };
+    /// Create a renderer without a window. The presentation render pass targets offscreen images
+    /// that can be read with [`Renderer::read_presented_image`].
+    pub fn new_headless(extent: util::Extent2D) -> Result<Self, RenderError> {
+        Self::new_headless_with_options(extent, RendererOptions::default())
+    }
 
-        let loader = Some(Loader::new(&mut device));
-        let presentation_render_target = None;
+    pub fn new_headless_with_options(
+        extent: util::Extent2D,
+        options: RendererOptions,
+    ) -> Result<Self, RenderError> {
+        let instance = instance::Instance::headless(options.vk_debug)?;
+        let debug_utils = backend::validation_layers::DebugUtils::new(&instance)?;
+        let device = device::Device::new(&instance, None)?;
+
+        let presenter_and_co = create_offscreen_and_co(&device, &extent)?;
+
+        Self::with_presenter(instance, debug_utils, None, device, presenter_and_co)
+    }
+
+    fn with_presenter(
+        instance: instance::Instance,
+        debug_utils: backend::validation_layers::DebugUtils,
+        surface: Option<surface::Surface>,
+        device: device::Device,
+        presenter_and_co: PresenterAndCo,
+    ) -> Result<Self, RenderError> {
+        let DeviceObjects {
+            resources,
+            presentation_render_target,
+            presenter,
+            image_to_frame_idx,
+            loader,
+            util_command_pool,
+            frame_synchronization,
+            destruction_queue,
+            pipeline_cache,
+            transient_buffers,
+            bindless,
+            device,
+        } = DeviceObjects::new(device, presenter_and_co)?;
 
         Ok(Self {
             instance,
             surface,
             device,
-            swapchain,
+            presenter,
             image_to_frame_idx,
             presentation_render_target,
             frame_synchronization,
             frame_idx: 0,
+            frame_stats: FrameStats::new(MAX_FRAMES_IN_FLIGHT),
+            submitted_frames: 0,
+            destruction_queue,
+            pipeline_cache,
+            command_trace: None,
+            texture_failures: Vec::new(),
+            transient_buffers,
+            bindless,
             swapchain_image_idx: 0,
-            _debug_utils,
+            debug_utils,
             resources,
             util_command_pool,
             loader,
         })
     }
 
+    /// If the device is lost, e.g. after a driver reset or a switch of the gpu of a laptop. Vulkan
+    /// reports it from whichever call notices it first, so this is meant to be checked after any
+    /// error. See recreate_device.
+    pub fn is_device_lost(&self) -> bool {
+        matches!(
+            self.device.wait_idle(),
+            Err(device::DeviceError::WaitIdle(vk::Result::ERROR_DEVICE_LOST))
+        )
+    }
+
+    /// Replaces a lost device with a new one, with a new swapchain for the surface. All resources
+    /// are lost with the device, so the handles to them are invalid and have to be created again.
+    /// The loader that was taken from the renderer uses the old device and has to be given back
+    /// here, a new one can be taken afterwards.
+    pub fn recreate_device(&mut self, loader: Option<Loader>) -> Result<(), RenderError> {
+        log::warn!("Recreating the device");
+        drop(loader);
+        let extent = self.swapchain_extent();
+
+        // The window can only have one swapchain, so the new device presents offscreen until the
+        // old swapchain is destroyed with the rest of the old device
+        let device = device::Device::new(&self.instance, self.surface.as_ref())?;
+        let offscreen = create_offscreen_and_co(&device, &extent)?;
+        let objects = DeviceObjects::new(device, offscreen)?;
+        drop(self.replace_device_objects(objects));
+
+        if let Some(surface) = &self.surface {
+            let PresenterAndCo {
+                presenter,
+                image_to_frame_idx,
+            } = create_swapchain_and_co(&self.instance, &self.device, surface, &extent, None)?;
+            self.presenter = presenter;
+            self.image_to_frame_idx = image_to_frame_idx;
+        }
+
+        self.frame_idx = 0;
+        self.swapchain_image_idx = 0;
+        self.submitted_frames = 0;
+        self.frame_stats = FrameStats::new(MAX_FRAMES_IN_FLIGHT);
+        Ok(())
+    }
+
+    /// Returns the old objects, which have to be dropped before anything else is created on the
+    /// old device
+    fn replace_device_objects(&mut self, objects: DeviceObjects) -> DeviceObjects {
+        use std::mem::replace;
+        DeviceObjects {
+            resources: replace(&mut self.resources, objects.resources),
+            presentation_render_target: replace(
+                &mut self.presentation_render_target,
+                objects.presentation_render_target,
+            ),
+            presenter: replace(&mut self.presenter, objects.presenter),
+            image_to_frame_idx: replace(&mut self.image_to_frame_idx, objects.image_to_frame_idx),
+            loader: replace(&mut self.loader, objects.loader),
+            util_command_pool: replace(&mut self.util_command_pool, objects.util_command_pool),
+            frame_synchronization: replace(
+                &mut self.frame_synchronization,
+                objects.frame_synchronization,
+            ),
+            destruction_queue: replace(&mut self.destruction_queue, objects.destruction_queue),
+            pipeline_cache: replace(&mut self.pipeline_cache, objects.pipeline_cache),
+            transient_buffers: replace(&mut self.transient_buffers, objects.transient_buffers),
+            bindless: replace(&mut self.bindless, objects.bindless),
+            device: replace(&mut self.device, objects.device),
+        }
+    }
+
     #[profiling::function]
     pub fn next_frame<'a, 'b: 'a>(&'b mut self) -> Result<Frame<'a>, RenderError> {
+        let next_frame_start = std::time::Instant::now();
+        self.frame_stats.begin_frame();
         {
             profiling::scope!("wait_and_acquire");
             let frame_sync = &mut self.frame_synchronization[self.frame_idx as usize];
+            let start = std::time::Instant::now();
             frame_sync.in_flight.blocking_wait()?;
+            self.frame_stats.fence_waited(start.elapsed());
+            self.transient_buffers.reset(self.frame_idx);
+            self.resources
+                .descriptor_sets
+                .reset_transient(self.frame_idx)?;
+
+            let start = std::time::Instant::now();
+            self.swapchain_image_idx = match &mut self.presenter {
+                Presenter::Swapchain(swapchain) => {
+                    swapchain.acquire_next_image(Some(&frame_sync.image_available))?
+                }
+                Presenter::Offscreen(target) => target.acquire_next_image(),
+            };
+            self.frame_stats.acquired(start.elapsed());
+        }
 
-            self.swapchain_image_idx = self
-                .swapchain
-                .acquire_next_image(Some(&frame_sync.image_available))?;
+        // The frame that used this slot before has finished, which also means that all frames
+        // submitted before it have.
+        for resource in self.destruction_queue.pop_done(self.submitted_frames) {
+            self.destroy_now(resource);
         }
 
         // This means that we received an image that might be in the process of rendering
         if let Some(mapped_frame_idx) = self.image_to_frame_idx[self.swapchain_image_idx as usize] {
             profiling::scope!("wait_image_in_use");
+            let start = std::time::Instant::now();
             self.frame_synchronization[mapped_frame_idx as usize]
                 .in_flight
                 .blocking_wait()?;
+            self.frame_stats.image_in_use_waited(start.elapsed());
         }
 
         let frame_sync = &mut self.frame_synchronization[self.frame_idx as usize];
@@ -616,11 +1051,21 @@ impl Renderer {
         let gfx_command_pool =
             command::CommandPool::new(&self.device, self.device.graphics_queue_family().clone())?;
 
+        // The fence of this slot was waited for above, so its queries are done
+        let timestamps = frame_sync.timestamps.get_mut();
+        self.frame_stats.gpu_timed(timestamps.read(&self.device)?);
+        let mut query_reset =
+            gfx_command_pool.create_command_buffer(command::CommandBufferSubmission::Single)?;
+        timestamps.reset(&mut query_reset);
+        query_reset.end()?;
+
         self.image_to_frame_idx[self.swapchain_image_idx as usize] = Some(self.frame_idx);
+        self.frame_stats.next_frame_done(next_frame_start.elapsed());
 
         Ok(Frame::<'a> {
             renderer: self,
             recorded_command_buffers: Vec::new(),
+            query_reset_command_buffer: *query_reset.vk_command_buffer(),
             gfx_command_pool,
         })
     }
@@ -640,8 +1085,10 @@ impl Renderer {
         // Their handles are passed submit info and stored in the driver before we deallocate the Vec holding them.
         let FinishedFrame {
             gfx_command_pool,
-            recorded_command_buffers,
+            mut recorded_command_buffers,
+            query_reset_command_buffer,
         } = frame;
+        recorded_command_buffers.insert(0, query_reset_command_buffer);
 
         frame_sync.command_pool = Some(gfx_command_pool);
 
@@ -649,27 +1096,38 @@ impl Renderer {
         let wait_dst_mask = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
         let vk_sig_sems = [*frame_sync.render_done.vk_semaphore()];
 
-        let info = vk::SubmitInfo::builder()
-            .wait_semaphores(&vk_wait_sems)
-            .wait_dst_stage_mask(&wait_dst_mask)
-            .signal_semaphores(&vk_sig_sems)
-            .command_buffers(&recorded_command_buffers);
+        let mut info = vk::SubmitInfo::builder().command_buffers(&recorded_command_buffers);
+        // Offscreen images are not acquired or presented so there is nothing to synchronize with
+        if let Presenter::Swapchain(_) = self.presenter {
+            info = info
+                .wait_semaphores(&vk_wait_sems)
+                .wait_dst_stage_mask(&wait_dst_mask)
+                .signal_semaphores(&vk_sig_sems);
+        }
 
         let gfx_queue = self.device.graphics_queue();
         frame_sync.in_flight.reset()?;
 
         gfx_queue.submit(&info, &frame_sync.in_flight)?;
-
-        let swapchains = [*self.swapchain.vk_swapchain()];
-        let indices = [self.swapchain_image_idx];
-        let present_info = vk::PresentInfoKHR::builder()
-            .wait_semaphores(&vk_sig_sems)
-            .swapchains(&swapchains)
-            .image_indices(&indices);
-
-        let status = self
-            .swapchain
-            .enqueue_present(self.device.present_queue(), present_info.build())?;
+        self.submitted_frames += 1;
+
+        let status = match &mut self.presenter {
+            Presenter::Swapchain(swapchain) => {
+                let swapchains = [*swapchain.vk_swapchain()];
+                let indices = [self.swapchain_image_idx];
+                let present_info = vk::PresentInfoKHR::builder()
+                    .wait_semaphores(&vk_sig_sems)
+                    .swapchains(&swapchains)
+                    .image_indices(&indices);
+
+                swapchain.enqueue_present(self.device.present_queue(), present_info.build())?
+            }
+            Presenter::Offscreen(target) => {
+                target.set_presented(self.swapchain_image_idx);
+                swapchain::SwapchainStatus::Optimal
+            }
+        };
+        self.frame_stats.presented();
 
         if let swapchain::SwapchainStatus::SubOptimal = status {
             return Err(RenderError::NeedsResize(ResizeReason::SubOptimal));
@@ -689,31 +1147,46 @@ impl Renderer {
         );
         self.device.wait_idle()?;
 
-        let SwapchainAndCo {
-            swapchain,
+        let PresenterAndCo {
+            presenter,
             image_to_frame_idx,
-        } = create_swapchain_and_co(
-            &self.instance,
-            &self.device,
-            &self.surface,
-            &new_extent,
-            Some(&self.swapchain),
-        )?;
+        } = match (&self.presenter, &self.surface) {
+            (Presenter::Swapchain(old), Some(surface)) => create_swapchain_and_co(
+                &self.instance,
+                &self.device,
+                surface,
+                &new_extent,
+                Some(old),
+            )?,
+            (Presenter::Swapchain(_), None) => unreachable!("A swapchain always has a surface"),
+            (Presenter::Offscreen(_), _) => create_offscreen_and_co(&self.device, &new_extent)?,
+        };
 
-        self.swapchain = swapchain;
+        self.presenter = presenter;
         self.image_to_frame_idx = image_to_frame_idx;
 
         if let Some(prt) = self.presentation_render_target.take() {
-            let rt = self.create_presentation_render_target(
-                util::Format::from(self.swapchain.info().format),
-                prt.render_pass,
-            )?;
+            let rt =
+                self.create_presentation_render_target(self.presenter.format(), prt.render_pass)?;
             self.presentation_render_target = Some(rt)
         }
 
         Ok(())
     }
 
+    /// Gets the renderer ready for the next frame after a frame that failed part way, e.g. one that
+    /// acquired a swapchain image but was never submitted. Waits for the device and recreates the
+    /// frame synchronization and the swapchain. Fails if the device is lost.
+    #[profiling::function]
+    pub fn recover(&mut self, extent: util::Extent2D) -> Result<(), RenderError> {
+        log::info!("Recovering the renderer after a failed frame");
+        self.device.wait_idle()?;
+        for frame_sync in self.frame_synchronization.iter_mut() {
+            *frame_sync = FrameSynchronization::new(&self.device)?;
+        }
+        self.resize(extent)
+    }
+
     pub fn aspect_ratio(&self) -> f32 {
         let util::Extent2D { width, height } = self.swapchain_extent();
 
@@ -721,23 +1194,179 @@ impl Renderer {
     }
 
     pub fn swapchain_extent(&self) -> util::Extent2D {
-        self.swapchain.info().extent
+        self.presenter.extent()
+    }
+
+    pub fn is_headless(&self) -> bool {
+        matches!(self.presenter, Presenter::Offscreen(_))
+    }
+
+    /// Read back the image that was rendered by the last submitted frame of a headless renderer.
+    /// Returns None when presenting to a window or if no frame has been submitted yet.
+    pub fn read_presented_image(&self) -> Result<Option<image::RgbaImage>, RenderError> {
+        let target = match &self.presenter {
+            Presenter::Offscreen(target) => target,
+            Presenter::Swapchain(_) => return Ok(None),
+        };
+
+        let idx = match target.presented_image() {
+            Some(idx) => idx,
+            None => return Ok(None),
+        };
+
+        // Make sure the frame that rendered the image is done
+        self.device.wait_idle()?;
+
+        let extent = target.info().extent;
+        let size = extent.width as usize
+            * extent.height as usize
+            * offscreen::OffscreenTarget::FORMAT.size() as usize;
+        let mut buffer = mem::DeviceBuffer::empty(
+            &self.device.allocator(),
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk_mem::MemoryUsage::GpuToCpu,
+        )
+        .map_err(RenderError::Readback)?;
+
+        let mut cmd_buf = self.util_command_pool.begin_single_submit()?;
+        cmd_buf.copy_image_to_buffer(target.vk_image(idx), buffer.vk_buffer(), &extent);
+        self.submit_command_buffer(cmd_buf).blocking_wait()?;
+
+        let data = {
+            let ptr = buffer.map().map_err(RenderError::Readback)?;
+            let data = unsafe { std::slice::from_raw_parts(ptr, size) }.to_vec();
+            buffer.unmap();
+            data
+        };
+
+        Ok(image::RgbaImage::from_raw(
+            extent.width,
+            extent.height,
+            data,
+        ))
+    }
+
+    /// Read back a single-sampled color texture that shaders sample from, e.g. a render target of
+    /// an earlier pass, once the submitted frames are done. The texture has to be created with
+    /// TextureUsage::TRANSFER_SRC. The rows are tightly packed in the format of the texture.
+    pub fn read_texture(
+        &self,
+        handle: &Handle<Texture>,
+    ) -> Result<(util::Extent2D, util::Format, Vec<u8>), RenderError> {
+        let texture = self
+            .resources
+            .textures
+            .get(handle)
+            .ok_or(RenderError::InvalidHandle(handle.id()))?;
+        let extent = texture.extent();
+        let format = texture.format();
+
+        self.device.wait_idle()?;
+
+        let size = extent.width as usize * extent.height as usize * format.size() as usize;
+        let mut buffer = mem::DeviceBuffer::empty(
+            &self.device.allocator(),
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk_mem::MemoryUsage::GpuToCpu,
+        )
+        .map_err(RenderError::Readback)?;
+
+        let mut cmd_buf = self.util_command_pool.begin_single_submit()?;
+        mem::transition_image_layout(
+            &mut cmd_buf,
+            texture.vk_image(),
+            1,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        );
+        cmd_buf.copy_image_to_buffer(texture.vk_image(), buffer.vk_buffer(), &extent);
+        mem::transition_image_layout(
+            &mut cmd_buf,
+            texture.vk_image(),
+            1,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        self.submit_command_buffer(cmd_buf).blocking_wait()?;
+
+        let data = {
+            let ptr = buffer.map().map_err(RenderError::Readback)?;
+            let data = unsafe { std::slice::from_raw_parts(ptr, size) }.to_vec();
+            buffer.unmap();
+            data
+        };
+
+        Ok((extent, format, data))
     }
 
     pub fn loader(&mut self) -> Option<Loader> {
         self.loader.take()
     }
+
+    pub fn frame_stats(&self) -> &FrameStats {
+        &self.frame_stats
+    }
+
+    /// Record the commands of the render pass encoders into a trace, see the trace module
+    pub fn set_command_tracing(&mut self, enabled: bool) {
+        self.command_trace = if enabled {
+            Some(trace::CommandTrace::default())
+        } else {
+            None
+        };
+    }
+
+    /// The commands that were recorded since the last call, if tracing is enabled
+    pub fn take_command_trace(&mut self) -> Option<Vec<trace::Command>> {
+        self.command_trace.as_ref().map(trace::CommandTrace::take)
+    }
+
+    /// The textures that could not be loaded by create_texture since the last call. The error
+    /// texture was created in their place.
+    pub fn take_texture_failures(&mut self) -> Vec<texture::TextureLoadFailure> {
+        std::mem::take(&mut self.texture_failures)
+    }
+
+    /// The number of live resources of each kind, used to track down leaks
+    pub fn resource_counts(&self) -> ResourceCounts {
+        ResourceCounts {
+            textures: self.resources.textures.len(),
+            vertex_buffers: self.resources.vertex_buffers.len(),
+            index_buffers: self.resources.index_buffers.len(),
+            uniform_buffers: self.resources.uniform_buffers.len(),
+            storage_buffers: self.resources.storage_buffers.len(),
+            graphics_pipelines: self.resources.graphics_pipelines.len(),
+            descriptor_sets: self.resources.descriptor_sets.len(),
+            transient_descriptor_sets: self.resources.descriptor_sets.transient_len(),
+            descriptor_pools: self.resources.descriptor_sets.pool_count(),
+            pending_destruction: self.destruction_queue.len(),
+        }
+    }
 }
 
 /// Vulkan-specific
 impl Renderer {
+    /// Creates the render pass that renders to the presented image. Calling this again, e.g. to
+    /// change the sample count, replaces the previous presentation render target and pipelines
+    /// created for the previous render pass have to be recreated.
     pub fn presentation_render_pass(
         &mut self,
         msaa_sample_count: u8,
     ) -> Result<Handle<RenderPass>, RenderError> {
-        let format = util::Format::from(self.swapchain.info().format);
-        let render_pass =
-            RenderPass::presentation_render_pass(&self.device, format, msaa_sample_count)?;
+        if self.presentation_render_target.is_some() {
+            // The framebuffers of the previous target might still be in use
+            self.device.wait_idle()?;
+        }
+
+        let format = self.presenter.format();
+        let render_pass = RenderPass::presentation_render_pass(
+            &self.device,
+            format,
+            msaa_sample_count,
+            self.presenter.final_layout(),
+        )?;
         let render_pass = self.resources.render_passes.add(render_pass);
         self.presentation_render_target =
             Some(self.create_presentation_render_target(format, render_pass.clone())?);
@@ -745,6 +1374,16 @@ impl Renderer {
         Ok(render_pass)
     }
 
+    /// The sample counts that can be used for the presentation render pass, in increasing order
+    pub fn supported_msaa_sample_counts(&self) -> Vec<u8> {
+        let supported = self.device.supported_msaa_sample_counts();
+        [1, 2, 4, 8, 16, 32, 64]
+            .iter()
+            .copied()
+            .filter(|&n| supported.contains(backend::vk::n_to_sample_count(n)))
+            .collect()
+    }
+
     pub fn create_render_pass(
         &mut self,
         create_info: &vk::RenderPassCreateInfo,
@@ -752,6 +1391,31 @@ impl Renderer {
         let rp = RenderPass::new_vk(&self.device, create_info)?;
         Ok(self.resources.render_passes.add(rp))
     }
+
+    /// Creates a multisampled render pass that resolves into a sampled texture, see
+    /// `create_offscreen_render_target`.
+    pub fn offscreen_render_pass(
+        &mut self,
+        format: util::Format,
+        msaa_sample_count: u8,
+    ) -> Result<Handle<RenderPass>, RenderError> {
+        let rp = RenderPass::offscreen_render_pass(&self.device, format, msaa_sample_count)?;
+        Ok(self.resources.render_passes.add(rp))
+    }
+
+    /// See `RenderPass::split_offscreen_render_passes`
+    pub fn split_offscreen_render_passes(
+        &mut self,
+        format: util::Format,
+        msaa_sample_count: u8,
+    ) -> Result<[Handle<RenderPass>; 2], RenderError> {
+        let [first, second] =
+            RenderPass::split_offscreen_render_passes(&self.device, format, msaa_sample_count)?;
+        Ok([
+            self.resources.render_passes.add(first),
+            self.resources.render_passes.add(second),
+        ])
+    }
 }
 
 /// These are functions only used by other parts of this lib
@@ -771,6 +1435,21 @@ impl Renderer {
             .map_err(RenderError::UniformBuffer)
     }
 
+    fn update_storage<T: Copy>(
+        &mut self,
+        h: &BufferHandle<mem::StorageBuffer>,
+        data: &[T],
+    ) -> Result<(), RenderError> {
+        let sbuf = self
+            .resources
+            .storage_buffers
+            .get_buffered_mut(h, self.frame_idx as usize)
+            .ok_or_else(|| RenderError::InvalidHandle(h.handle().id()))?;
+
+        sbuf.update_with(data, h.idx() as u64)
+            .map_err(RenderError::StorageBuffer)
+    }
+
     fn current_present_target(&self) -> &Handle<render_target::RenderTarget> {
         &self
             .presentation_render_target
@@ -804,6 +1483,19 @@ impl Renderer {
             .alloc(bindings)
             .expect("Failed to alloc")
     }
+
+    fn allocate_transient_descriptor_set(
+        &mut self,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> (
+        Handle<descriptor::DescriptorSet>,
+        &descriptor::DescriptorSet,
+    ) {
+        self.resources
+            .descriptor_sets
+            .alloc_transient(bindings, self.frame_idx)
+            .expect("Failed to alloc transient descriptor set")
+    }
 }
 
 macro_rules! impl_buffer_manager {
@@ -851,6 +1543,13 @@ impl_buffer_manager!(
     CreateUniformBuffer,
     uniform_buffers
 );
+impl_buffer_manager!(
+    mem::OwningStorageBufferDescriptor,
+    mem::StorageBuffer,
+    BufferHandle<mem::StorageBuffer>,
+    CreateStorageBuffer,
+    storage_buffers
+);
 
 use pipeline::{GraphicsPipeline, GraphicsPipelineDescriptor, PipelineError};
 
@@ -865,6 +1564,7 @@ impl Renderer {
         render_pass: &Handle<RenderPass>,
     ) -> Result<Handle<GraphicsPipeline>, PipelineError> {
         let device = &self.device;
+        let cache = &self.pipeline_cache;
         let render_pass = self
             .resources
             .render_passes
@@ -873,10 +1573,76 @@ impl Renderer {
         let handle = self
             .resources
             .graphics_pipelines
-            .get_or_add(descriptor, |d| d.create(device, &render_pass.0))?;
+            .get_or_add(descriptor, |d| d.create(device, &render_pass.0, cache))?;
 
         Ok(handle)
     }
+
+    /// Unlike the graphics pipelines, these are not cached so each call creates a new pipeline
+    pub fn create_compute_pipeline(
+        &mut self,
+        descriptor: &pipeline::ComputePipelineDescriptor,
+    ) -> Result<Handle<pipeline::ComputePipeline>, PipelineError> {
+        let pipeline = descriptor.create(&self.device, &self.pipeline_cache)?;
+        Ok(self.resources.compute_pipelines.add(pipeline))
+    }
+
+    /// A factory for pipelines that use the render pass, e.g. to create them on other threads
+    pub fn pipeline_factory(&self, render_pass: &Handle<RenderPass>) -> pipeline::PipelineFactory {
+        let render_pass = self
+            .resources
+            .render_passes
+            .get(render_pass)
+            .expect("No such pass");
+        pipeline::PipelineFactory::new(&self.device, &render_pass.0, &self.pipeline_cache)
+    }
+
+    /// Add a pipeline that was created by a PipelineFactory. If there already is a pipeline for the
+    /// descriptor, that one is used and this one is destroyed. Either way, this adds a reference
+    /// like create_gfx_pipeline.
+    pub fn add_gfx_pipeline(
+        &mut self,
+        descriptor: GraphicsPipelineDescriptor,
+        pipeline: GraphicsPipeline,
+    ) -> Handle<GraphicsPipeline> {
+        self.resources
+            .graphics_pipelines
+            .get_or_add(descriptor, |_| Ok::<_, PipelineError>(pipeline))
+            .expect("Adding an existing pipeline can't fail")
+    }
+
+    /// Load the pipeline cache from the file, if it exists, and store it there when the renderer is
+    /// dropped. Only pipelines created after this are added to the stored cache. Pipeline factories
+    /// that were created before this can't be used after it.
+    pub fn set_pipeline_cache_file(&mut self, path: &std::path::Path) -> Result<(), PipelineError> {
+        self.pipeline_cache = pipeline::PipelineCache::from_file(&self.device, path)?;
+        Ok(())
+    }
+
+    /// If the renderer was created with vk_debug, see RendererOptions
+    pub fn vk_debug_enabled(&self) -> bool {
+        self.debug_utils.is_enabled()
+    }
+
+    /// Names the vulkan objects of the resource in validation messages and capture tools. Mutable
+    /// resources get a suffix with the frame in flight, textures one for their view etc. Does
+    /// nothing unless vk debug is enabled.
+    pub fn set_debug_name<H: DebugNamed>(&self, h: &H, name: &str) {
+        if !self.debug_utils.is_enabled() {
+            return;
+        }
+
+        let vk_device = self.device.vk_device().handle();
+        for (object_type, object_handle, suffix) in h.vk_objects(&self.resources) {
+            let full_name = format!("{}{}", name, suffix);
+            if let Err(e) =
+                self.debug_utils
+                    .set_object_name(vk_device, object_type, object_handle, &full_name)
+            {
+                log::warn!("Failed to name {:?} {}: {}", object_type, full_name, e);
+            }
+        }
+    }
 }
 
 use crate::texture::{TextureDescriptor, TextureError};
@@ -962,6 +1728,9 @@ impl Renderer {
                 texture::Texture::from_device_image(&self.device, dst_image, format, mip_levels)
                     .expect("Failed to create mipmapped texture");
             old_textures.push(std::mem::replace(texture, new));
+            if let Some((bindless, _)) = &self.bindless {
+                bindless.rewrite(handle, texture);
+            }
         }
 
         let done = self.submit_command_buffer(cmd_buf);
@@ -970,6 +1739,146 @@ impl Renderer {
     }
 }
 
+impl Renderer {
+    pub fn supports_sparse_textures(&self) -> bool {
+        self.device.supports_sparse_textures()
+    }
+
+    /// If textures can be sampled through the bindless set, see bindless
+    pub fn supports_bindless_textures(&self) -> bool {
+        self.bindless.is_some()
+    }
+
+    /// The number of descriptor sets that a pipeline can use
+    pub fn max_bound_descriptor_sets(&self) -> u32 {
+        self.device.max_bound_descriptor_sets()
+    }
+
+    /// The index of the texture in the bindless set. The texture is added to it the first time,
+    /// and removed when it is destroyed. None if bindless textures are not supported, if the
+    /// handle is invalid or if the set is full.
+    pub fn bindless_texture_index(&mut self, handle: &Handle<Texture>) -> Option<u32> {
+        let (bindless, _) = self.bindless.as_mut()?;
+        let texture = self.resources.textures.get(handle)?;
+        bindless.index(*handle, texture)
+    }
+
+    /// The set with the array of bindless textures, for pipelines that declare it, see bindless
+    pub fn bindless_descriptor_set(&self) -> Option<&Handle<descriptor::DescriptorSet>> {
+        self.bindless.as_ref().map(|(_, set)| set)
+    }
+
+    /// The widest lines that pipelines can draw, see GraphicsPipelineDescriptor::line_width
+    pub fn max_line_width(&self) -> f32 {
+        self.device.max_line_width()
+    }
+
+    /// Creates a texture where only the mip tail is resident, see sparse. Each level of the mip
+    /// tail has to be written with write_sparse_pages before the texture is sampled.
+    pub fn create_sparse_texture(
+        &mut self,
+        descriptor: &SparseTextureDescriptor,
+    ) -> Result<Handle<Texture>, RenderError> {
+        if !self.device.supports_sparse_textures() {
+            return Err(SparseError::Unsupported.into());
+        }
+
+        let image = SparseImage::new(
+            &self.device,
+            &self.device.allocator(),
+            self.device.graphics_queue(),
+            descriptor.extent,
+            descriptor.format,
+        )?;
+
+        let mut cmd_buf = self.util_command_pool.begin_single_submit()?;
+        cmd_buf.pipeline_barrier(
+            &[image.barrier(
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::SHADER_READ,
+            )],
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        );
+        self.submit_command_buffer(cmd_buf).blocking_wait()?;
+
+        let texture = Texture::from_sparse_image(&self.device, image, &descriptor.sampler)?;
+        Ok(self.resources.textures.add(texture))
+    }
+
+    /// Makes the pages resident and writes the texels to them. The data of a page is tightly packed
+    /// rows in the format of the texture, see sparse::SparseImage::page_bytes. Blocks until the
+    /// pages are written, and frames in flight may still sample the texture.
+    pub fn write_sparse_pages(
+        &mut self,
+        handle: &Handle<Texture>,
+        pages: &[(Page, &[u8])],
+    ) -> Result<(), RenderError> {
+        if pages.is_empty() {
+            return Ok(());
+        }
+
+        let texture = self
+            .resources
+            .textures
+            .get_mut(handle)
+            .ok_or(RenderError::InvalidHandle(handle.id()))?;
+        let image = texture
+            .sparse_mut()
+            .ok_or(RenderError::InvalidHandle(handle.id()))?;
+
+        let to_bind: Vec<Page> = pages.iter().map(|(page, _)| *page).collect();
+        image.bind(self.device.graphics_queue(), &to_bind)?;
+
+        let mut data = Vec::new();
+        let mut regions = Vec::with_capacity(pages.len());
+        for (page, texels) in pages {
+            if texels.len() != image.page_bytes(*page) {
+                log::error!(
+                    "Page {:?} has {} bytes, expected {}",
+                    page,
+                    texels.len(),
+                    image.page_bytes(*page)
+                );
+                return Err(SparseError::InvalidPage(*page).into());
+            }
+            regions.push(image.copy_region(*page, data.len() as u64));
+            data.extend_from_slice(texels);
+        }
+
+        let staging = mem::DeviceBuffer::staging_with_data(&self.device.allocator(), &data, 1, 1)
+            .map_err(RenderError::SparseUpload)?;
+        let mut cmd_buf = self.util_command_pool.begin_single_submit()?;
+        cmd_buf
+            .pipeline_barrier(
+                &[image.barrier(
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::SHADER_READ,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                )],
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+            )
+            .copy_buffer_to_image_regions(staging.vk_buffer(), image.vk_image(), &regions)
+            .pipeline_barrier(
+                &[image.barrier(
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                )],
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+            );
+        self.submit_command_buffer(cmd_buf).blocking_wait()?;
+
+        Ok(())
+    }
+}
+
 impl Renderer {
     pub fn create_render_target(
         &mut self,
@@ -996,4 +1905,124 @@ impl Renderer {
         let render_target = self.resources.render_targets.add(data);
         Ok(render_target)
     }
+
+    /// Creates a render target for a pass from `offscreen_render_pass`. The multisampled
+    /// attachments are owned by the render target and the pass resolves into `resolve`.
+    pub fn create_offscreen_render_target(
+        &mut self,
+        render_pass: &Handle<RenderPass>,
+        resolve: &Handle<Texture>,
+    ) -> Result<Handle<RenderTarget>, RenderError> {
+        let render_pass = self
+            .resources
+            .render_passes
+            .get(render_pass)
+            .ok_or(RenderError::InvalidHandle(render_pass.id()))?;
+        let resolve = self
+            .resources
+            .textures
+            .get(resolve)
+            .ok_or(RenderError::InvalidHandle(resolve.id()))?;
+        let data = RenderTarget::new_resolving(&self.device, resolve, render_pass)?;
+        Ok(self.resources.render_targets.add(data))
+    }
+
+    /// Waits for the device to be idle as the render target might be used by a frame in flight
+    pub fn destroy_render_target(
+        &mut self,
+        handle: Handle<RenderTarget>,
+    ) -> Result<(), RenderError> {
+        self.device.wait_idle()?;
+        self.resources
+            .render_targets
+            .remove(handle)
+            .ok_or(RenderError::InvalidHandle(handle.id()))?;
+        Ok(())
+    }
+}
+
+impl Renderer {
+    /// Destroy the resource once no frame in flight can use it, without waiting for the device.
+    /// The handle must not be used after this call. Pipelines are destroyed when the last handle
+    /// to them is released.
+    pub fn destroy_deferred(&mut self, resource: impl Into<Destroyable>) {
+        self.destruction_queue
+            .push(self.submitted_frames, resource.into());
+    }
+
+    fn destroy_now(&mut self, resource: Destroyable) {
+        let destroyed = match resource {
+            Destroyable::Texture(h) => {
+                if let Some((bindless, _)) = &mut self.bindless {
+                    bindless.remove(&h);
+                }
+                self.resources.textures.remove(h).is_some()
+            }
+            Destroyable::DescriptorSet(h) => match self.resources.descriptor_sets.free(h) {
+                Ok(freed) => freed,
+                Err(e) => {
+                    log::error!("Failed to free descriptor set {:?}: {}", h, e);
+                    true
+                }
+            },
+            Destroyable::GraphicsPipeline(h) => {
+                let valid = self.resources.graphics_pipelines.has(&h);
+                self.resources.graphics_pipelines.release(&h);
+                valid
+            }
+            Destroyable::ComputePipeline(h) => self.resources.compute_pipelines.remove(h).is_some(),
+            Destroyable::VertexBuffer(h) => self.resources.vertex_buffers.remove(&h).is_some(),
+            Destroyable::IndexBuffer(h) => self.resources.index_buffers.remove(&h).is_some(),
+            Destroyable::UniformBuffer(h) => self.resources.uniform_buffers.remove(&h).is_some(),
+            Destroyable::StorageBuffer(h) => self.resources.storage_buffers.remove(&h).is_some(),
+            Destroyable::RenderPass(h) => self.resources.render_passes.remove(h).is_some(),
+            Destroyable::SparsePage(h, page) => {
+                match self
+                    .resources
+                    .textures
+                    .get_mut(&h)
+                    .and_then(|t| t.sparse_mut())
+                {
+                    Some(image) => {
+                        if let Err(e) = image.unbind(self.device.graphics_queue(), page) {
+                            log::error!("Failed to evict page {:?}: {}", page, e);
+                        }
+                        true
+                    }
+                    None => false,
+                }
+            }
+        };
+
+        if !destroyed {
+            log::warn!("Deferred destruction of invalid handle: {:?}", resource);
+        }
+    }
+
+    /// Waits for the device to be idle as the descriptor set might be used by a frame in flight
+    pub fn destroy_descriptor_set(
+        &mut self,
+        handle: Handle<descriptor::DescriptorSet>,
+    ) -> Result<(), RenderError> {
+        self.device.wait_idle()?;
+        if self.resources.descriptor_sets.free(handle)? {
+            Ok(())
+        } else {
+            Err(RenderError::InvalidHandle(handle.id()))
+        }
+    }
+
+    /// Waits for the device to be idle as the texture might be used by a frame in flight. Any
+    /// descriptor sets or render targets referring to the texture have to be destroyed or recreated.
+    pub fn destroy_texture(&mut self, handle: Handle<Texture>) -> Result<(), RenderError> {
+        self.device.wait_idle()?;
+        if let Some((bindless, _)) = &mut self.bindless {
+            bindless.remove(&handle);
+        }
+        self.resources
+            .textures
+            .remove(handle)
+            .ok_or(RenderError::InvalidHandle(handle.id()))?;
+        Ok(())
+    }
 }
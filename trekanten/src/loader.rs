@@ -6,7 +6,9 @@ use crate::mem::{
     UniformBuffer, VertexBuffer,
 };
 use crate::resource::{Async, AsyncResources, Handle, Resources};
-use crate::texture::{DrainIterator as TextureDrainIterator, Texture, TextureDescriptor};
+use crate::texture::{
+    DrainIterator as TextureDrainIterator, Texture, TextureDescriptor, TextureLoadFailure,
+};
 use crate::Renderer;
 use crate::{
     backend::{
@@ -18,7 +20,9 @@ use crate::{
 // TODO: Don't use vk directly here
 use ash::vk;
 
+use std::collections::VecDeque;
 use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
 use thiserror::Error;
 
@@ -87,6 +91,42 @@ enum PendingResourceCommand {
     },
 }
 
+impl PendingResourceCommand {
+    /// The number of bytes that are copied to the gpu
+    fn transfer_size(&self) -> usize {
+        match self {
+            Self::CreateVertexBuffer { transients, .. }
+            | Self::CreateIndexBuffer { transients, .. }
+            | Self::CreateUniformBuffer { transients, .. } => {
+                transients.iter().flatten().map(DeviceBuffer::size).sum()
+            }
+            Self::CreateTexture { transients, .. } => transients.size(),
+        }
+    }
+}
+
+/// Limits how much the loader transfers per poll, see Loader::set_upload_budget. The loads that
+/// don't fit are queued and transferred in later polls. At least one load is transferred per poll,
+/// so that one that is larger than the budget is not stuck.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct UploadBudget {
+    /// None for no limit
+    pub bytes: Option<usize>,
+    /// Includes reading and decoding the image files. None for no limit.
+    pub time: Option<Duration>,
+}
+
+impl UploadBudget {
+    pub fn is_limited(&self) -> bool {
+        self.bytes.is_some() || self.time.is_some()
+    }
+
+    fn is_spent(&self, bytes: usize, time: Duration) -> bool {
+        self.bytes.map(|max| bytes >= max).unwrap_or(false)
+            || self.time.map(|max| time >= max).unwrap_or(false)
+    }
+}
+
 pub enum HandleMapping {
     UniformBuffer {
         old: BufferHandle<Async<UniformBuffer>>,
@@ -111,12 +151,17 @@ struct NonSync {
     command_pool: CommandPool,
     pending_resource_jobs: Vec<PendingResourceJob>,
     resources: AsyncResources,
+    /// Loads that are waiting for the upload budget
+    queued: VecDeque<AsyncResourceCommand>,
+    budget: UploadBudget,
 }
 
 pub struct Loader {
     allocator: AllocatorHandle,
     vk_device: VkDeviceHandle,
     locked: Mutex<NonSync>,
+    /// Textures that were replaced by the error texture, see take_texture_failures
+    texture_failures: Mutex<Vec<TextureLoadFailure>>,
 }
 
 struct PendingResourceJob {
@@ -168,13 +213,19 @@ impl Loader {
                 process_buffer_creation!(CreateUniformBuffer, descriptor, self, cmd_buffer, handle)
             }
             AsyncResourceCommand::CreateTexture { handle, descriptor } => {
-                let (image, transients) = descriptor
-                    .enqueue(
+                let (image, transients, failure) = descriptor
+                    .enqueue_or_error_texture(
                         &self.allocator,
                         &self.vk_device,
                         cmd_buffer.expect("texture creation needs command buffer"),
                     )
                     .expect("Fail");
+                if let Some(failure) = failure {
+                    self.texture_failures
+                        .lock()
+                        .expect("Failed to lock mutex")
+                        .push(failure);
+                }
                 Some(PendingResourceCommand::CreateTexture {
                     descriptor,
                     handle,
@@ -184,6 +235,52 @@ impl Loader {
             }
         }
     }
+
+    /// Submits the recorded commands to the transfer queue
+    fn submit(
+        &self,
+        guard: &mut NonSync,
+        mut cmd_buffer: CommandBuffer,
+        commands: Vec<PendingResourceCommand>,
+    ) -> Result<(), LoaderError> {
+        cmd_buffer.end()?;
+        let done = Fence::unsignaled(&self.vk_device)?;
+        let buffers = [*cmd_buffer.vk_command_buffer()];
+        let info = vk::SubmitInfo::builder().command_buffers(&buffers);
+        let job = PendingResourceJob { commands, done };
+
+        guard.queue.submit(&info, &job.done)?;
+        guard.pending_resource_jobs.push(job);
+        Ok(())
+    }
+
+    /// Transfers the queued loads, in the order they were queued, until the budget is spent
+    fn transfer_queued(&self, guard: &mut NonSync) -> Result<(), LoaderError> {
+        if guard.queued.is_empty() {
+            return Ok(());
+        }
+
+        let start = Instant::now();
+        let mut bytes = 0;
+        let mut cmd_buffer = guard.command_pool.begin_single_submit()?;
+        let mut commands = Vec::new();
+        while let Some(cmd) = guard.queued.pop_front() {
+            if let Some(pending) = self.process_command(cmd, Some(&mut cmd_buffer)) {
+                bytes += pending.transfer_size();
+                commands.push(pending);
+            }
+            if guard.budget.is_spent(bytes, start.elapsed()) {
+                break;
+            }
+        }
+        log::trace!(
+            "Transferred {} bytes, {} loads are queued",
+            bytes,
+            guard.queued.len()
+        );
+
+        self.submit(guard, cmd_buffer, commands)
+    }
 }
 
 // Good reads for mutex + iterating over contents
@@ -295,11 +392,14 @@ impl Loader {
             command_pool,
             pending_resource_jobs: Vec::with_capacity(16),
             resources: AsyncResources::default(),
+            queued: VecDeque::new(),
+            budget: UploadBudget::default(),
         });
         Self {
             vk_device,
             allocator,
             locked,
+            texture_failures: Mutex::new(Vec::new()),
         }
     }
 
@@ -370,6 +470,30 @@ impl Loader {
                 i += 1;
             }
         }
+
+        self.transfer_queued(&mut guard)
+            .expect("Failed to transfer the queued resources");
+    }
+
+    /// Queue the loads that don't fit in the budget, instead of transferring them when they are
+    /// loaded. The queue is transferred when the loader is polled.
+    pub fn set_upload_budget(&self, budget: UploadBudget) {
+        self.locked.lock().expect("Failed to lock mutex").budget = budget;
+    }
+
+    /// The number of loads that are waiting for the upload budget
+    pub fn num_queued(&self) -> usize {
+        self.locked
+            .lock()
+            .expect("Failed to lock mutex")
+            .queued
+            .len()
+    }
+
+    /// The textures that could not be loaded since the last call. The error texture was loaded in
+    /// their place.
+    pub fn take_texture_failures(&self) -> Vec<TextureLoadFailure> {
+        std::mem::take(&mut *self.texture_failures.lock().expect("Failed to lock mutex"))
     }
 
     pub fn transfer<'mutex, 'loader: 'mutex, 'renderer>(
@@ -419,6 +543,11 @@ macro_rules! impl_loader {
                 let handle = guard.resources.$storage.allocate(&descriptor);
                 let cmd = AsyncResourceCommand::$cmd_enum { descriptor, handle };
 
+                if guard.budget.is_limited() {
+                    guard.queued.push_back(cmd);
+                    return Ok(handle);
+                }
+
                 let mut cmd_buffer = guard.command_pool.begin_single_submit()?;
 
                 // TODO: Allocation. Switch to small vec
@@ -427,14 +556,7 @@ macro_rules! impl_loader {
                     commands.push(cmd);
                 }
 
-                cmd_buffer.end()?;
-                let done = Fence::unsignaled(&self.vk_device)?;
-                let buffers = [*cmd_buffer.vk_command_buffer()];
-                let info = vk::SubmitInfo::builder().command_buffers(&buffers);
-                let job = PendingResourceJob { commands, done };
-
-                guard.queue.submit(&info, &job.done)?;
-                guard.pending_resource_jobs.push(job);
+                self.submit(&mut guard, cmd_buffer, commands)?;
 
                 Ok(handle)
             }
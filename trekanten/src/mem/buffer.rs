@@ -512,12 +512,44 @@ impl OwningUniformBufferDescriptor {
     }
 }
 
+/// Storage buffers are indexed in the shaders, so the elements are tightly packed. The element
+/// type has to follow the std430 layout rules.
+#[derive(Debug, Clone)]
+pub struct StorageBufferType;
+impl BufferType for StorageBufferType {
+    const USAGE: vk::BufferUsageFlags = vk::BufferUsageFlags::STORAGE_BUFFER;
+}
+
+pub type OwningStorageBufferDescriptor = OwningBufferDescriptor<StorageBufferType>;
+impl OwningStorageBufferDescriptor {
+    pub fn from_vec<T: Copy + Uniform + 'static>(
+        data: Vec<T>,
+        mutability: BufferMutability,
+    ) -> Self {
+        let n_elems = data.len() as u32;
+        let data = unsafe { Arc::new(ByteBuffer::from_vec(data)) };
+        Self {
+            data,
+            n_elems,
+            elem_size: std::mem::size_of::<T>() as u16,
+            mutability,
+            buffer_type: StorageBufferType,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct VertexBufferType {
     format: VertexFormat,
 }
+
+// Also storage buffers, so that compute shaders can read and write the vertices, see
+// DescriptorSetBuilder::add_vertex_buffer
 impl BufferType for VertexBufferType {
-    const USAGE: vk::BufferUsageFlags = vk::BufferUsageFlags::VERTEX_BUFFER;
+    const USAGE: vk::BufferUsageFlags = vk::BufferUsageFlags::from_raw(
+        vk::BufferUsageFlags::VERTEX_BUFFER.as_raw()
+            | vk::BufferUsageFlags::STORAGE_BUFFER.as_raw(),
+    );
 }
 
 pub type OwningVertexBufferDescriptor = OwningBufferDescriptor<VertexBufferType>;
@@ -552,6 +584,10 @@ impl OwningVertexBufferDescriptor {
             buffer_type: VertexBufferType { format },
         }
     }
+
+    pub fn format(&self) -> &VertexFormat {
+        &self.buffer_type.format
+    }
 }
 
 pub type BorrowingVertexBufferDescriptor<'a> = BorrowingBufferDescriptor<'a, VertexBufferType>;
@@ -638,6 +674,10 @@ impl OwningIndexBufferDescriptor {
             buffer_type: IndexBufferType { index_size },
         }
     }
+
+    pub fn index_size(&self) -> IndexSize {
+        self.buffer_type.index_size
+    }
 }
 
 pub type BorrowingIndexBufferDescriptor<'a> = BorrowingBufferDescriptor<'a, IndexBufferType>;
@@ -823,3 +863,18 @@ impl UniformBuffer {
         self.stride() as u64 * self.n_elems() as u64
     }
 }
+
+pub type StorageBuffer = TypedBuffer<StorageBufferType>;
+impl StorageBuffer {
+    /// Writes `data` to the elements starting at `first`
+    pub fn update_with<T: Copy>(&mut self, data: &[T], first: u64) -> Result<(), MemoryError> {
+        assert!(first + data.len() as u64 <= self.n_elems() as u64);
+        let raw_data = as_byte_slice(data);
+        let offset = (first * self.stride() as u64) as usize;
+        self.buffer_mut().update_data_at(raw_data, offset)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.stride() as u64 * self.n_elems() as u64
+    }
+}
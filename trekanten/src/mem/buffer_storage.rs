@@ -80,6 +80,24 @@ impl<T> DeviceBufferStorage<T> {
         }
     }
 
+    /// Removes the whole buffer, including any other sub-buffers of it
+    pub fn remove(&mut self, h: &BufferHandle<T>) -> Option<(T, Option<T>)> {
+        match h.mutability() {
+            BufferMutability::Immutable => self.unbuffered.remove(*h.handle()).map(|x| (x, None)),
+            BufferMutability::Mutable => {
+                self.buffered.remove(*h.handle()).map(|[x, y]| (x, Some(y)))
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.unbuffered.len() + self.buffered.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn drain_filter<F1, F2>(&mut self, f1: F1, f2: F2) -> DrainFilter<'_, F1, F2, T>
     where
         F1: FnMut(&mut T) -> bool,
@@ -207,6 +225,7 @@ pub type DrainIterator<'a, T> =
 use super::buffer::*;
 pub type UniformBuffers = DeviceBufferStorage<UniformBuffer>;
 pub type AsyncUniformBuffers = AsyncDeviceBufferStorage<UniformBuffer>;
+pub type StorageBuffers = DeviceBufferStorage<StorageBuffer>;
 pub type VertexBuffers = DeviceBufferStorage<VertexBuffer>;
 pub type AsyncVertexBuffers = AsyncDeviceBufferStorage<VertexBuffer>;
 pub type IndexBuffers = DeviceBufferStorage<IndexBuffer>;
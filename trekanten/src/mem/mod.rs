@@ -6,10 +6,12 @@ use crate::queue::QueueError;
 mod buffer;
 mod buffer_storage;
 mod image;
+mod transient;
 
 pub use self::image::*;
 pub use buffer::*;
 pub use buffer_storage::*;
+pub use transient::*;
 
 #[derive(Debug, Error)]
 pub enum MemoryError {
@@ -27,4 +29,6 @@ pub enum MemoryError {
     Realloc(vk_mem::Error),
     #[error("memory binding failed {0}")]
     MemoryBinding(vk_mem::Error),
+    #[error("transient buffer of the frame is full, failed to allocate {0} bytes")]
+    TransientBufferFull(u64),
 }
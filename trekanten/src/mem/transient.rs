@@ -0,0 +1,148 @@
+//! Memory for uniform data that is written every frame, e.g. the view data of the camera. Each frame
+//! in flight has a persistent mapped buffer that slices are handed out from, front to back. The
+//! buffer of a frame is reset when the next frame in the same slot starts, after its fence has been
+//! waited for, so writing to a slice never waits for the gpu.
+
+use ash::vk;
+
+use vk_mem::MemoryUsage;
+
+use super::{DeviceBuffer, MemoryError};
+use crate::common::MAX_FRAMES_IN_FLIGHT;
+use crate::device::AllocatorHandle;
+use crate::util::as_bytes;
+
+/// The size of the transient buffer of each frame in flight. The joint matrices of the skinned
+/// meshes are the largest writes, 8 KiB each, so this fits a few hundred of them.
+pub const TRANSIENT_BUFFER_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Part of the transient buffer of a frame. Only valid in the frame that it was allocated in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransientSlice {
+    frame_idx: u32,
+    offset: u64,
+    size: u64,
+}
+
+impl TransientSlice {
+    pub fn frame_idx(&self) -> u32 {
+        self.frame_idx
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Hands out aligned ranges of a buffer, front to back, until it is reset
+#[derive(Debug, Clone, Copy)]
+struct Ring {
+    capacity: u64,
+    alignment: u64,
+    head: u64,
+}
+
+impl Ring {
+    fn new(capacity: u64, alignment: u64) -> Self {
+        assert!(alignment.is_power_of_two());
+        Self {
+            capacity,
+            alignment,
+            head: 0,
+        }
+    }
+
+    /// The offset of `size` free bytes, None if the buffer is full
+    fn alloc(&mut self, size: u64) -> Option<u64> {
+        let offset = (self.head + self.alignment - 1) & !(self.alignment - 1);
+        if offset + size > self.capacity {
+            return None;
+        }
+        self.head = offset + size;
+        Some(offset)
+    }
+
+    fn reset(&mut self) {
+        self.head = 0;
+    }
+}
+
+pub struct TransientBuffers {
+    /// One for each frame in flight
+    buffers: Vec<DeviceBuffer>,
+    rings: [Ring; MAX_FRAMES_IN_FLIGHT],
+}
+
+impl TransientBuffers {
+    /// `alignment` is the minimum offset alignment of uniform buffers of the device
+    pub fn new(allocator: &AllocatorHandle, alignment: u64) -> Result<Self, MemoryError> {
+        let create = || -> Result<DeviceBuffer, MemoryError> {
+            let mut buffer = DeviceBuffer::empty(
+                allocator,
+                TRANSIENT_BUFFER_SIZE as usize,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                MemoryUsage::CpuToGpu,
+            )?;
+            buffer.map()?;
+            Ok(buffer)
+        };
+
+        Ok(Self {
+            buffers: (0..MAX_FRAMES_IN_FLIGHT)
+                .map(|_| create())
+                .collect::<Result<_, _>>()?,
+            rings: [Ring::new(TRANSIENT_BUFFER_SIZE, alignment); MAX_FRAMES_IN_FLIGHT],
+        })
+    }
+
+    pub fn vk_buffer(&self, frame_idx: u32) -> &vk::Buffer {
+        self.buffers[frame_idx as usize].vk_buffer()
+    }
+
+    /// Makes the whole buffer of the frame available again. The gpu has to be done with the frame
+    /// that last used it.
+    pub fn reset(&mut self, frame_idx: u32) {
+        self.rings[frame_idx as usize].reset();
+    }
+
+    /// Copies `data` to a new slice of the buffer of the frame
+    pub fn write<T: Copy>(
+        &mut self,
+        frame_idx: u32,
+        data: &T,
+    ) -> Result<TransientSlice, MemoryError> {
+        let size = std::mem::size_of::<T>() as u64;
+        let offset = self.rings[frame_idx as usize]
+            .alloc(size)
+            .ok_or(MemoryError::TransientBufferFull(size))?;
+
+        self.buffers[frame_idx as usize].update_data_at(as_bytes(data), offset as usize)?;
+
+        Ok(TransientSlice {
+            frame_idx,
+            offset,
+            size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_aligns_slices_and_is_reused_after_reset() {
+        let mut ring = Ring::new(256, 64);
+        assert_eq!(ring.alloc(80), Some(0));
+        assert_eq!(ring.alloc(16), Some(128));
+        assert_eq!(ring.alloc(64), Some(192));
+        assert_eq!(ring.alloc(1), None);
+
+        ring.reset();
+        assert_eq!(ring.alloc(256), Some(0));
+    }
+}
@@ -0,0 +1,100 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use std::path::{Path, PathBuf};
+
+use crate::device::HasVkDevice;
+use crate::device::VkDeviceHandle;
+
+use super::PipelineError;
+
+/// A vulkan pipeline cache that can be stored in a file, so that the driver can skip compiling
+/// pipelines that were created in a previous run.
+pub struct PipelineCache {
+    vk_device: VkDeviceHandle,
+    vk_pipeline_cache: vk::PipelineCache,
+    file: Option<PathBuf>,
+}
+
+impl PipelineCache {
+    /// An empty cache that is not stored anywhere
+    pub fn new<D: HasVkDevice>(device: &D) -> Result<Self, PipelineError> {
+        Self::with_data(device, &[], None)
+    }
+
+    /// A cache with the contents of the file, if it exists, that is written back to it when dropped.
+    /// Data from another driver or device is ignored by the driver.
+    pub fn from_file<D: HasVkDevice>(device: &D, path: &Path) -> Result<Self, PipelineError> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(PipelineError::IO(e)),
+        };
+        log::info!(
+            "Loaded {} bytes of pipeline cache from {}",
+            data.len(),
+            path.display()
+        );
+
+        Self::with_data(device, &data, Some(path.to_path_buf()))
+    }
+
+    fn with_data<D: HasVkDevice>(
+        device: &D,
+        data: &[u8],
+        file: Option<PathBuf>,
+    ) -> Result<Self, PipelineError> {
+        let vk_device = device.vk_device();
+        let info = vk::PipelineCacheCreateInfo::builder().initial_data(data);
+        let vk_pipeline_cache = unsafe {
+            vk_device
+                .create_pipeline_cache(&info, None)
+                .map_err(|e| PipelineError::VulkanObjectCreation(e, "Pipeline cache"))?
+        };
+
+        Ok(Self {
+            vk_device,
+            vk_pipeline_cache,
+            file,
+        })
+    }
+
+    pub fn vk_pipeline_cache(&self) -> &vk::PipelineCache {
+        &self.vk_pipeline_cache
+    }
+
+    fn save(&self, path: &Path) -> Result<(), PipelineError> {
+        let data = unsafe {
+            self.vk_device
+                .get_pipeline_cache_data(self.vk_pipeline_cache)
+                .map_err(|e| PipelineError::VulkanObjectCreation(e, "Pipeline cache data"))?
+        };
+        std::fs::write(path, &data)?;
+        log::info!(
+            "Stored {} bytes of pipeline cache in {}",
+            data.len(),
+            path.display()
+        );
+
+        Ok(())
+    }
+}
+
+impl std::ops::Drop for PipelineCache {
+    fn drop(&mut self) {
+        if let Some(path) = &self.file {
+            if let Err(e) = self.save(path) {
+                log::error!(
+                    "Failed to store pipeline cache in {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+
+        unsafe {
+            self.vk_device
+                .destroy_pipeline_cache(self.vk_pipeline_cache, None);
+        }
+    }
+}
@@ -17,17 +17,24 @@ use crate::device::VkDeviceHandle;
 use crate::resource::{CachedStorage, Handle};
 use crate::vertex::VertexFormat;
 
+mod cache;
 mod error;
 mod spirv;
 
+pub use cache::PipelineCache;
 pub use error::PipelineError;
 use spirv::{parse_spirv, ReflectionData};
+pub use spirv::{
+    reflect_interface, BlockMember, InterfaceBinding, InterfacePushConstant, ShaderInterface,
+    SpirvError,
+};
 use std::sync::Arc;
 
 bitflags::bitflags! {
     pub struct ShaderStage: u8 {
         const VERTEX = 0b1;
         const FRAGMENT = 0b10;
+        const COMPUTE = 0b100;
     }
 }
 
@@ -52,6 +59,10 @@ impl From<ShaderStage> for vk::ShaderStageFlags {
             out |= vk::ShaderStageFlags::FRAGMENT;
         }
 
+        if s.contains(ShaderStage::COMPUTE) {
+            out |= vk::ShaderStageFlags::COMPUTE;
+        }
+
         out
     }
 }
@@ -169,6 +180,8 @@ impl From<TriangleWinding> for vk::FrontFace {
 pub enum BlendState {
     Enabled,
     Disabled,
+    /// The output is added to what is already in the attachment
+    Additive,
 }
 
 impl Default for BlendState {
@@ -181,6 +194,9 @@ impl Default for BlendState {
 pub enum DepthTest {
     Enabled,
     Disabled,
+    /// Tested but not written, and pulled slightly towards the camera, so that e.g. lines drawn
+    /// over the same triangles are not hidden by them
+    Overlay,
 }
 
 impl Default for DepthTest {
@@ -202,6 +218,27 @@ impl Default for PolygonMode {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PrimitiveTopology {
+    TriangleList,
+    LineList,
+}
+
+impl Default for PrimitiveTopology {
+    fn default() -> Self {
+        Self::TriangleList
+    }
+}
+
+impl From<PrimitiveTopology> for vk::PrimitiveTopology {
+    fn from(topology: PrimitiveTopology) -> Self {
+        match topology {
+            PrimitiveTopology::TriangleList => vk::PrimitiveTopology::TRIANGLE_LIST,
+            PrimitiveTopology::LineList => vk::PrimitiveTopology::LINE_LIST,
+        }
+    }
+}
+
 pub struct GraphicsPipeline {
     vk_device: VkDeviceHandle,
     vk_pipeline: vk::Pipeline,
@@ -239,64 +276,113 @@ struct PipelineCreationInfo {
 
 const DEFAULT_SPV_ENTRY_NAME_NULLED: &[u8] = b"main\0";
 
-impl GraphicsPipeline {
-    pub fn vk_descriptor_set_layouts(&self) -> &[vk::DescriptorSetLayout] {
-        &self.vk_descriptor_set_layouts
-    }
+fn shader_stage<D: HasVkDevice>(
+    device: &D,
+    refl_data: &mut ReflectionData,
+    desc: &ShaderDescriptor,
+    stage: vk::ShaderStageFlags,
+) -> Result<PipelineCreationInfo, PipelineError> {
+    log::trace!("Creating shader for pipeline");
+    let raw = match desc {
+        ShaderDescriptor::FromRawSpirv(data) => {
+            log::trace!("from raw");
+            RawShader {
+                data: Cow::from(data),
+            }
+        }
+        ShaderDescriptor::FromPath(path) => {
+            log::trace!("from path \"{}\"", path.display());
+            read_shader(path)?
+        }
+    };
 
-    pub fn vk_pipeline_layout(&self) -> &vk::PipelineLayout {
-        &self.vk_pipeline_layout
-    }
+    let name = CStr::from_bytes_with_nul(DEFAULT_SPV_ENTRY_NAME_NULLED).unwrap();
 
-    fn shader<'a, D: HasVkDevice>(
-        device: &D,
-        refl_data: &mut ReflectionData,
-        desc: &'a ShaderDescriptor,
-        stage: vk::ShaderStageFlags,
-    ) -> Result<PipelineCreationInfo, PipelineError> {
-        log::trace!("Creating shader for pipeline");
-        let raw = match desc {
-            ShaderDescriptor::FromRawSpirv(data) => {
-                log::trace!("from raw");
-                RawShader {
-                    data: Cow::from(data),
-                }
-            }
-            ShaderDescriptor::FromPath(path) => {
-                log::trace!("from path \"{}\"", path.display());
-                read_shader(path)?
-            }
-        };
+    let shader_module = ShaderModule::new(device, &raw)?;
+    let create_info = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(stage)
+        .module(shader_module.vk_shader_module)
+        .name(name)
+        .build();
 
-        let name = CStr::from_bytes_with_nul(DEFAULT_SPV_ENTRY_NAME_NULLED).unwrap();
+    let new_refl_data = parse_spirv(&raw.data).map_err(PipelineError::Reflection)?;
 
-        let shader_module = ShaderModule::new(device, &raw)?;
-        let create_info = vk::PipelineShaderStageCreateInfo::builder()
-            .stage(stage)
-            .module(shader_module.vk_shader_module)
-            .name(name)
-            .build();
+    refl_data.merge(new_refl_data);
 
-        let new_refl_data = parse_spirv(&raw.data).map_err(PipelineError::Reflection)?;
+    Ok(PipelineCreationInfo {
+        create_info,
+        shader_module,
+    })
+}
 
-        refl_data.merge(new_refl_data);
+/// The layout of the descriptor sets and push constants that the shaders of a pipeline use
+fn create_pipeline_layout(
+    vk_device: &VkDeviceHandle,
+    reflection_data: &ReflectionData,
+) -> Result<(vk::PipelineLayout, Vec<vk::DescriptorSetLayout>), PipelineError> {
+    // The position in the pipeline layout is the set index, so the layouts have to be ordered
+    // and any sets not used by the shaders still need an (empty) layout.
+    let n_sets = reflection_data
+        .desc_layouts
+        .iter()
+        .map(|dset| dset.set_idx + 1)
+        .max()
+        .unwrap_or(0);
+    let mut descriptor_set_layouts = Vec::with_capacity(n_sets);
+    for set_idx in 0..n_sets {
+        let bindings = reflection_data
+            .desc_layouts
+            .iter()
+            .find(|dset| dset.set_idx == set_idx)
+            .map(|dset| dset.bindings.as_slice())
+            .unwrap_or(&[]);
+        // The bindless set can only be bound to sets with the same layout flags
+        let dset_layout = if crate::bindless::is_bindless_layout(bindings) {
+            crate::bindless::create_layout(vk_device)
+        } else {
+            let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+            unsafe { vk_device.create_descriptor_set_layout(&info, None) }
+        }
+        .map_err(|e| PipelineError::VulkanObjectCreation(e, "Descriptor set layout"))?;
 
-        Ok(PipelineCreationInfo {
-            create_info,
-            shader_module,
-        })
+        descriptor_set_layouts.push(dset_layout);
+    }
+
+    let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&descriptor_set_layouts)
+        .push_constant_ranges(&reflection_data.push_constants);
+
+    let pipeline_layout = unsafe {
+        vk_device
+            .create_pipeline_layout(&pipeline_layout_info, None)
+            .map_err(|e| PipelineError::VulkanObjectCreation(e, "Pipeline layout"))?
+    };
+
+    log::trace!("Created pipeline layout {:?}", pipeline_layout);
+    Ok((pipeline_layout, descriptor_set_layouts))
+}
+
+impl GraphicsPipeline {
+    pub fn vk_descriptor_set_layouts(&self) -> &[vk::DescriptorSetLayout] {
+        &self.vk_descriptor_set_layouts
+    }
+
+    pub fn vk_pipeline_layout(&self) -> &vk::PipelineLayout {
+        &self.vk_pipeline_layout
     }
 
     fn create<D: HasVkDevice>(
         device: &D,
-        render_pass: &RenderPass,
+        vk_render_pass: vk::RenderPass,
+        msaa_sample_count: vk::SampleCountFlags,
+        vk_pipeline_cache: vk::PipelineCache,
         desc: &GraphicsPipelineDescriptor,
     ) -> Result<Self, PipelineError> {
         let mut reflection_data = ReflectionData::new();
         let PipelineCreationInfo {
             shader_module: _vert_module,
             create_info: vert_create_info,
-        } = Self::shader(
+        } = shader_stage(
             device,
             &mut reflection_data,
             &desc.vert,
@@ -306,7 +392,7 @@ impl GraphicsPipeline {
             .frag
             .as_ref()
             .map(|frag| {
-                Self::shader(
+                shader_stage(
                     device,
                     &mut reflection_data,
                     frag,
@@ -326,7 +412,7 @@ impl GraphicsPipeline {
         }
 
         let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .topology(desc.topology.into())
             .primitive_restart_enable(false);
 
         let vk_polygon_mode = match desc.polygon_mode {
@@ -339,14 +425,16 @@ impl GraphicsPipeline {
             .depth_clamp_enable(false)
             .rasterizer_discard_enable(false)
             .polygon_mode(vk_polygon_mode)
-            .line_width(1.0)
+            .line_width(desc.line_width as f32)
             .cull_mode(desc.culling.into())
             .front_face(desc.winding.into())
-            .depth_bias_enable(false);
+            .depth_bias_enable(desc.depth_testing == DepthTest::Overlay)
+            .depth_bias_constant_factor(-1.0)
+            .depth_bias_slope_factor(-1.0);
 
         let msaa_info = vk::PipelineMultisampleStateCreateInfo::builder()
             .sample_shading_enable(false)
-            .rasterization_samples(render_pass.msaa_sample_count());
+            .rasterization_samples(msaa_sample_count);
 
         let color_blend_attach_info = vk::PipelineColorBlendAttachmentState::builder()
             .color_write_mask(vk::ColorComponentFlags::all());
@@ -361,6 +449,14 @@ impl GraphicsPipeline {
                 .src_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
                 .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
                 .alpha_blend_op(vk::BlendOp::ADD),
+            BlendState::Additive => color_blend_attach_info
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::ONE)
+                .dst_color_blend_factor(vk::BlendFactor::ONE)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                .alpha_blend_op(vk::BlendOp::ADD),
         };
 
         let attachments = [*color_blend_attach_info];
@@ -368,30 +464,8 @@ impl GraphicsPipeline {
             .logic_op_enable(false)
             .attachments(&attachments);
 
-        let mut descriptor_set_layouts = Vec::with_capacity(reflection_data.desc_layouts.len());
-        for dset in reflection_data.desc_layouts.iter() {
-            let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&dset.bindings);
-
-            let dset_layout = unsafe {
-                vk_device
-                    .create_descriptor_set_layout(&info, None)
-                    .map_err(|e| PipelineError::VulkanObjectCreation(e, "Descriptor set layout"))?
-            };
-
-            descriptor_set_layouts.push(dset_layout);
-        }
-
-        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
-            .set_layouts(&descriptor_set_layouts)
-            .push_constant_ranges(&reflection_data.push_constants);
-
-        let pipeline_layout = unsafe {
-            vk_device
-                .create_pipeline_layout(&pipeline_layout_info, None)
-                .map_err(|e| PipelineError::VulkanObjectCreation(e, "Pipeline layout"))?
-        };
-
-        log::trace!("Created pipeline layout {:?}", pipeline_layout);
+        let (pipeline_layout, descriptor_set_layouts) =
+            create_pipeline_layout(&vk_device, &reflection_data)?;
 
         let depth_stencil = match desc.depth_testing {
             DepthTest::Disabled => vk::PipelineDepthStencilStateCreateInfo::builder()
@@ -403,6 +477,12 @@ impl GraphicsPipeline {
                 .depth_compare_op(vk::CompareOp::LESS)
                 .depth_bounds_test_enable(false)
                 .stencil_test_enable(false),
+            DepthTest::Overlay => vk::PipelineDepthStencilStateCreateInfo::builder()
+                .depth_test_enable(true)
+                .depth_write_enable(false)
+                .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+                .depth_bounds_test_enable(false)
+                .stencil_test_enable(false),
         };
 
         let viewport = vk::Viewport::builder()
@@ -443,15 +523,13 @@ impl GraphicsPipeline {
             .depth_stencil_state(&depth_stencil)
             .dynamic_state(&dynamic_state)
             .layout(pipeline_layout)
-            .render_pass(*render_pass.vk_render_pass())
+            .render_pass(vk_render_pass)
             .subpass(0);
 
         let create_infos = [*g_pipeline_info];
 
-        // TODO: Use the cache
-        let vk_pipelines_result = unsafe {
-            vk_device.create_graphics_pipelines(vk::PipelineCache::null(), &create_infos, None)
-        };
+        let vk_pipelines_result =
+            unsafe { vk_device.create_graphics_pipelines(vk_pipeline_cache, &create_infos, None) };
         // According to: https://renderdoc.org/vkspec_chunked/chap10.html#pipelines-multiple
         // Implementations will attempt to create as many pipelines as possible, but if any fail, we really want to exit anyway.
 
@@ -495,6 +573,12 @@ pub struct GraphicsPipelineDescriptor {
     pub depth_testing: DepthTest,
     #[builder(default)]
     pub polygon_mode: PolygonMode,
+    #[builder(default)]
+    pub topology: PrimitiveTopology,
+    /// In pixels, for PolygonMode::Line and line topologies. Above 1 requires wide line support,
+    /// see Renderer::max_line_width.
+    #[builder(default = "1")]
+    pub line_width: u8,
 }
 
 impl GraphicsPipelineDescriptorBuilder {
@@ -513,13 +597,141 @@ impl GraphicsPipelineDescriptor {
         &self,
         device: &D,
         render_pass: &RenderPass,
+        cache: &PipelineCache,
     ) -> Result<GraphicsPipeline, PipelineError> {
-        GraphicsPipeline::create(device, render_pass, self)
+        GraphicsPipeline::create(
+            device,
+            *render_pass.vk_render_pass(),
+            render_pass.msaa_sample_count(),
+            *cache.vk_pipeline_cache(),
+            self,
+        )
+    }
+}
+
+/// Creates graphics pipelines for a render pass on any thread. Add them to the renderer with
+/// Renderer::add_gfx_pipeline. The render pass and the pipeline cache of the renderer have to
+/// outlive the factory.
+#[derive(Clone)]
+pub struct PipelineFactory {
+    vk_device: VkDeviceHandle,
+    vk_render_pass: vk::RenderPass,
+    msaa_sample_count: vk::SampleCountFlags,
+    vk_pipeline_cache: vk::PipelineCache,
+}
+
+impl PipelineFactory {
+    pub(crate) fn new<D: HasVkDevice>(
+        device: &D,
+        render_pass: &RenderPass,
+        cache: &PipelineCache,
+    ) -> Self {
+        Self {
+            vk_device: device.vk_device(),
+            vk_render_pass: *render_pass.vk_render_pass(),
+            msaa_sample_count: render_pass.msaa_sample_count(),
+            vk_pipeline_cache: *cache.vk_pipeline_cache(),
+        }
+    }
+
+    pub fn create(
+        &self,
+        desc: &GraphicsPipelineDescriptor,
+    ) -> Result<GraphicsPipeline, PipelineError> {
+        GraphicsPipeline::create(
+            &self.vk_device,
+            self.vk_render_pass,
+            self.msaa_sample_count,
+            self.vk_pipeline_cache,
+            desc,
+        )
     }
 }
 
 pub type GraphicsPipelines = CachedStorage<GraphicsPipelineDescriptor, GraphicsPipeline>;
 
+/// A pipeline with a single compute shader, see Frame::begin_compute_pass
+pub struct ComputePipeline {
+    vk_device: VkDeviceHandle,
+    vk_pipeline: vk::Pipeline,
+    vk_pipeline_layout: vk::PipelineLayout,
+    vk_descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+}
+
+impl Pipeline for ComputePipeline {
+    const BIND_POINT: vk::PipelineBindPoint = vk::PipelineBindPoint::COMPUTE;
+
+    fn vk_pipeline(&self) -> &vk::Pipeline {
+        &self.vk_pipeline
+    }
+}
+
+impl std::ops::Drop for ComputePipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.vk_device.destroy_pipeline(self.vk_pipeline, None);
+
+            self.vk_device
+                .destroy_pipeline_layout(self.vk_pipeline_layout, None);
+            for &dset_layout in self.vk_descriptor_set_layouts.iter() {
+                self.vk_device
+                    .destroy_descriptor_set_layout(dset_layout, None);
+            }
+        }
+    }
+}
+
+impl ComputePipeline {
+    pub fn vk_pipeline_layout(&self) -> &vk::PipelineLayout {
+        &self.vk_pipeline_layout
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ComputePipelineDescriptor {
+    pub shader: ShaderDescriptor,
+}
+
+impl ComputePipelineDescriptor {
+    pub fn create<D: HasVkDevice>(
+        &self,
+        device: &D,
+        cache: &PipelineCache,
+    ) -> Result<ComputePipeline, PipelineError> {
+        let mut reflection_data = ReflectionData::new();
+        let PipelineCreationInfo {
+            shader_module: _module,
+            create_info,
+        } = shader_stage(
+            device,
+            &mut reflection_data,
+            &self.shader,
+            vk::ShaderStageFlags::COMPUTE,
+        )?;
+
+        let vk_device = device.vk_device();
+        let (pipeline_layout, descriptor_set_layouts) =
+            create_pipeline_layout(&vk_device, &reflection_data)?;
+
+        let info = vk::ComputePipelineCreateInfo::builder()
+            .stage(create_info)
+            .layout(pipeline_layout);
+        let pipelines = unsafe {
+            vk_device.create_compute_pipelines(*cache.vk_pipeline_cache(), &[*info], None)
+        }
+        .map_err(|(_vec, e)| PipelineError::VulkanObjectCreation(e, "Compute pipeline"))?;
+
+        assert_eq!(pipelines.len(), 1, "Expected single pipeline");
+
+        Ok(ComputePipeline {
+            vk_device,
+            vk_pipeline: pipelines[0],
+            vk_pipeline_layout: pipeline_layout,
+            vk_descriptor_set_layouts: descriptor_set_layouts,
+        })
+    }
+}
+
 use crate::resource::Async;
 use parking_lot::{MappedRwLockReadGuard, RwLock, RwLockReadGuard};
 
@@ -106,9 +106,14 @@ fn map_shader_stage_flags(refl_stage: ReflectShaderStageFlags) -> vk::ShaderStag
         out |= vk::ShaderStageFlags::FRAGMENT;
     }
 
-    if refl_stage & (!(ReflectShaderStageFlags::VERTEX | ReflectShaderStageFlags::FRAGMENT))
-        != ReflectShaderStageFlags::empty()
-    {
+    if refl_stage.contains(ReflectShaderStageFlags::COMPUTE) {
+        out |= vk::ShaderStageFlags::COMPUTE;
+    }
+
+    let supported = ReflectShaderStageFlags::VERTEX
+        | ReflectShaderStageFlags::FRAGMENT
+        | ReflectShaderStageFlags::COMPUTE;
+    if refl_stage & !supported != ReflectShaderStageFlags::empty() {
         unimplemented!("Unsupported shader stage: {:?}", refl_stage);
     }
 
@@ -118,7 +123,9 @@ fn map_shader_stage_flags(refl_stage: ReflectShaderStageFlags) -> vk::ShaderStag
 fn map_descriptor_type(refl_desc_ty: &ReflectDescriptorType) -> vk::DescriptorType {
     match *refl_desc_ty {
         ReflectDescriptorType::UniformBuffer => vk::DescriptorType::UNIFORM_BUFFER,
+        ReflectDescriptorType::StorageBuffer => vk::DescriptorType::STORAGE_BUFFER,
         ReflectDescriptorType::CombinedImageSampler => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        ReflectDescriptorType::StorageImage => vk::DescriptorType::STORAGE_IMAGE,
         _ => unimplemented!("Unsupported descriptor type: {:?}", refl_desc_ty),
     }
 }
@@ -199,6 +206,111 @@ pub fn parse_spirv(spv_data: &[u32]) -> Result<ReflectionData, SpirvError> {
     })
 }
 
+/// A member of a uniform block or push constant block, offsets are relative to the block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockMember {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceBinding {
+    pub set: u32,
+    pub binding: u32,
+    /// The instance name, e.g. `ubo` for `uniform Data { ... } ubo;`
+    pub name: String,
+    /// The block or resource type name, e.g. `Data` for `uniform Data { ... } ubo;`
+    pub type_name: String,
+    pub descriptor_type: vk::DescriptorType,
+    pub count: u32,
+    /// Empty for anything other than uniform and storage buffers
+    pub members: Vec<BlockMember>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfacePushConstant {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+    pub members: Vec<BlockMember>,
+}
+
+/// Everything a shader expects to be bound, with names, for documentation purposes. Pipeline
+/// creation uses `ReflectionData` instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShaderInterface {
+    pub stage: vk::ShaderStageFlags,
+    /// Sorted by set and binding
+    pub bindings: Vec<InterfaceBinding>,
+    pub push_constants: Vec<InterfacePushConstant>,
+}
+
+fn block_members(block: &spirv_reflect::types::variable::ReflectBlockVariable) -> Vec<BlockMember> {
+    block
+        .members
+        .iter()
+        .map(|m| BlockMember {
+            name: m.name.clone(),
+            offset: m.offset,
+            size: m.size,
+        })
+        .collect()
+}
+
+pub fn reflect_interface(spv_data: &[u32]) -> Result<ShaderInterface, SpirvError> {
+    let module = ShaderModule::load_u32_data(spv_data).map_err(SpirvError::Loading)?;
+    let stage = map_shader_stage_flags(module.get_shader_stage());
+
+    let mut bindings: Vec<InterfaceBinding> = module
+        .enumerate_descriptor_bindings(None)
+        .map_err(SpirvError::Parsing)?
+        .iter()
+        .map(|b| {
+            let descriptor_type = map_descriptor_type(&b.descriptor_type);
+            let members = if descriptor_type == vk::DescriptorType::UNIFORM_BUFFER
+                || descriptor_type == vk::DescriptorType::STORAGE_BUFFER
+            {
+                block_members(&b.block)
+            } else {
+                Vec::new()
+            };
+            InterfaceBinding {
+                set: b.set,
+                binding: b.binding,
+                name: b.name.clone(),
+                type_name: b
+                    .type_description
+                    .as_ref()
+                    .map(|t| t.type_name.clone())
+                    .unwrap_or_default(),
+                descriptor_type,
+                count: b.count,
+                members,
+            }
+        })
+        .collect();
+    bindings.sort_by_key(|b| (b.set, b.binding));
+
+    let push_constants = module
+        .enumerate_push_constant_blocks(None)
+        .map_err(SpirvError::Parsing)?
+        .iter()
+        .map(|pc| InterfacePushConstant {
+            name: pc.name.clone(),
+            offset: pc.offset,
+            size: pc.size,
+            members: block_members(pc),
+        })
+        .collect();
+
+    Ok(ShaderInterface {
+        stage,
+        bindings,
+        push_constants,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     static UBO_SPV_VERT: &[u32] = inline_spirv::inline_spirv!(
@@ -275,6 +387,48 @@ mod tests {
         frag
     );
 
+    static SSBO_SPV_VERT: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450 core
+        struct Object {
+            mat4 model;
+        };
+
+        layout(std430, set = 3, binding = 0) readonly buffer Objects {
+            Object objects[];
+        } object_data;
+
+        layout(push_constant) uniform ObjectIndex {
+            uint idx;
+        } object_index;
+
+        void main() {
+            gl_Position = object_data.objects[object_index.idx].model * vec4(1.0);
+        }
+    ",
+        vert
+    );
+
+    static SSBO_SPV_COMP: &[u32] = inline_spirv::inline_spirv!(
+        r"
+        #version 450 core
+        layout(local_size_x = 64) in;
+
+        layout(std430, set = 0, binding = 0) readonly buffer Src {
+            float src[];
+        };
+
+        layout(std430, set = 0, binding = 1) writeonly buffer Dst {
+            float dst[];
+        };
+
+        void main() {
+            dst[gl_GlobalInvocationID.x] = src[gl_GlobalInvocationID.x];
+        }
+    ",
+        comp
+    );
+
     use super::*;
 
     #[test]
@@ -374,4 +528,72 @@ mod tests {
         assert_eq!(binding1.descriptor_count, 1);
         assert_eq!(binding1.stage_flags, vk::ShaderStageFlags::FRAGMENT);
     }
+
+    #[test]
+    fn reflect_uniform_block_interface() {
+        let res = reflect_interface(UBO_SPV_VERT).expect("Failed to reflect!");
+        assert_eq!(res.stage, vk::ShaderStageFlags::VERTEX);
+        assert_eq!(res.push_constants.len(), 0);
+        assert_eq!(res.bindings.len(), 1);
+
+        let binding = &res.bindings[0];
+        assert_eq!(binding.set, 0);
+        assert_eq!(binding.binding, 0);
+        assert_eq!(binding.name, "ubo");
+        assert_eq!(binding.type_name, "UniformBufferObject");
+        assert_eq!(binding.descriptor_type, vk::DescriptorType::UNIFORM_BUFFER);
+
+        let members: Vec<(&str, u32, u32)> = binding
+            .members
+            .iter()
+            .map(|m| (m.name.as_str(), m.offset, m.size))
+            .collect();
+        assert_eq!(
+            members,
+            vec![("model", 0, 64), ("view", 64, 64), ("proj", 128, 64)]
+        );
+    }
+
+    #[test]
+    fn reflect_push_constant_interface() {
+        let res = reflect_interface(PUSH_CONSTANT_SPV_FRAG).expect("Failed to reflect!");
+        assert_eq!(res.stage, vk::ShaderStageFlags::FRAGMENT);
+        assert_eq!(res.bindings.len(), 0);
+        assert_eq!(res.push_constants.len(), 1);
+
+        let pc = &res.push_constants[0];
+        assert_eq!(pc.offset, 0);
+        assert_eq!(pc.size, 128);
+        let names: Vec<&str> = pc.members.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["model", "model_it"]);
+    }
+
+    #[test]
+    fn parse_storage_buffer() {
+        let refl_data = parse_spirv(SSBO_SPV_VERT).expect("Failed to parse!");
+
+        assert_eq!(refl_data.push_constants.len(), 1);
+        assert_eq!(refl_data.push_constants[0].size, 4);
+        let res = refl_data.desc_layouts;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].set_idx, 3);
+        assert_eq!(
+            res[0].bindings[0].descriptor_type,
+            vk::DescriptorType::STORAGE_BUFFER
+        );
+    }
+
+    #[test]
+    fn parse_compute_storage_buffers() {
+        let refl_data = parse_spirv(SSBO_SPV_COMP).expect("Failed to parse!");
+
+        assert_eq!(refl_data.push_constants.len(), 0);
+        let res = refl_data.desc_layouts;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].bindings.len(), 2);
+        for binding in res[0].bindings.iter() {
+            assert_eq!(binding.descriptor_type, vk::DescriptorType::STORAGE_BUFFER);
+            assert_eq!(binding.stage_flags, vk::ShaderStageFlags::COMPUTE);
+        }
+    }
 }
@@ -7,21 +7,34 @@ use crate::backend::command::{CommandBuffer, CommandError};
 use crate::descriptor::DescriptorSet;
 use crate::pipeline::{GraphicsPipeline, ShaderStage};
 use crate::resource::Resources;
+use crate::trace::{Command, CommandTrace};
 use ash::vk as vk_raw;
 
 pub struct RenderPassEncoder<'a> {
     resources: &'a Resources,
     frame_idx: u32,
     command_buffer: CommandBuffer,
+    trace: Option<&'a CommandTrace>,
 }
 
 impl<'a> RenderPassEncoder<'a> {
+    fn record(&self, cmd: Command) {
+        if let Some(trace) = self.trace {
+            trace.record(cmd);
+        }
+    }
+
     pub fn bind_shader_resource_group(
         &mut self,
         idx: u32,
         dset: &Handle<DescriptorSet>,
         pipeline: &Handle<GraphicsPipeline>,
     ) -> &mut Self {
+        self.record(Command::BindDescriptorSet {
+            idx,
+            set: dset.id(),
+            pipeline: pipeline.id(),
+        });
         let dset = self
             .resources
             .descriptor_sets
@@ -40,6 +53,9 @@ impl<'a> RenderPassEncoder<'a> {
     }
 
     pub fn bind_graphics_pipeline(&mut self, pipeline: &Handle<GraphicsPipeline>) -> &mut Self {
+        self.record(Command::BindPipeline {
+            pipeline: pipeline.id(),
+        });
         let pipeline = self
             .resources
             .graphics_pipelines
@@ -52,6 +68,9 @@ impl<'a> RenderPassEncoder<'a> {
     }
 
     pub fn bind_index_buffer(&mut self, handle: &BufferHandle<IndexBuffer>) -> &mut Self {
+        self.record(Command::BindIndexBuffer {
+            buffer: handle.handle().id(),
+        });
         let ib = self
             .resources
             .index_buffers
@@ -64,6 +83,9 @@ impl<'a> RenderPassEncoder<'a> {
     }
 
     pub fn bind_vertex_buffer(&mut self, handle: &BufferHandle<VertexBuffer>) -> &mut Self {
+        self.record(Command::BindVertexBuffer {
+            buffer: handle.handle().id(),
+        });
         let vb = self
             .resources
             .vertex_buffers
@@ -83,6 +105,17 @@ impl<'a> RenderPassEncoder<'a> {
         let vertex_index = vertex_buffer.idx() as i32;
         let indices_index = index_buffer.idx();
         let n_indices = index_buffer.n_elems();
+        self.record(Command::BindIndexBuffer {
+            buffer: index_buffer.handle().id(),
+        });
+        self.record(Command::BindVertexBuffer {
+            buffer: vertex_buffer.handle().id(),
+        });
+        self.record(Command::DrawIndexed {
+            n_indices,
+            first_index: indices_index,
+            vertex_offset: vertex_index,
+        });
 
         let vb = self
             .resources
@@ -110,19 +143,35 @@ impl<'a> RenderPassEncoder<'a> {
         indices_index: u32,
         vertices_index: i32,
     ) -> &mut Self {
+        self.record(Command::DrawIndexed {
+            n_indices,
+            first_index: indices_index,
+            vertex_offset: vertices_index,
+        });
         self.command_buffer
             .draw_indexed(n_indices, indices_index, vertices_index);
 
         self
     }
 
+    /// Draw without any bound vertex or index buffers, e.g. a fullscreen triangle generated in the
+    /// vertex shader
+    pub fn draw(&mut self, n_vertices: u32) -> &mut Self {
+        self.record(Command::Draw { n_vertices });
+        self.command_buffer.draw(n_vertices);
+
+        self
+    }
+
     pub fn set_scissor(&mut self, scissor: util::Rect2D) -> &mut Self {
+        self.record(Command::SetScissor(scissor));
         self.command_buffer.set_scissor(scissor);
 
         self
     }
 
     pub fn set_viewport(&mut self, viewport: util::Viewport) -> &mut Self {
+        self.record(Command::SetViewport(viewport));
         self.command_buffer.set_viewport(viewport);
 
         self
@@ -134,6 +183,10 @@ impl<'a> RenderPassEncoder<'a> {
         stage: ShaderStage,
         v: &V,
     ) -> &mut Self {
+        self.record(Command::PushConstant {
+            pipeline: pipeline.id(),
+            size: std::mem::size_of::<V>(),
+        });
         let pipeline = self
             .resources
             .graphics_pipelines
@@ -150,14 +203,27 @@ impl<'a> RenderPassEncoder<'a> {
             resources,
             command_buffer,
             frame_idx,
+            trace: None,
         }
     }
 
+    /// Record the commands into the trace as well, see Renderer::set_command_tracing
+    pub fn with_trace(mut self, trace: Option<&'a CommandTrace>) -> Self {
+        self.trace = trace;
+        self
+    }
+
     pub fn end(mut self) -> Result<CommandBuffer, CommandError> {
+        self.record(Command::EndRenderPass);
         self.command_buffer.end_render_pass();
         Ok(self.command_buffer)
     }
 
+    /// For commands that are not tied to the render pass, e.g. Frame::begin_gpu_timer
+    pub fn command_buffer_mut(&mut self) -> &mut CommandBuffer {
+        &mut self.command_buffer
+    }
+
     pub fn inner(self) -> CommandBuffer {
         let Self { command_buffer, .. } = self;
         command_buffer
@@ -166,6 +232,17 @@ impl<'a> RenderPassEncoder<'a> {
 
 use crate::backend::render_pass::RenderPass as BackendRenderPass;
 
+/// If the multisampled color and depth of a resolving render pass are kept for another pass that
+/// continues drawing on them, see `RenderPass::split_offscreen_render_passes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Continuation {
+    None,
+    /// Kept for the next pass
+    Continued,
+    /// Loaded from the previous pass
+    Continues,
+}
+
 pub struct RenderPass(pub(crate) BackendRenderPass);
 
 impl RenderPass {
@@ -173,16 +250,173 @@ impl RenderPass {
         device: &backend::device::Device,
         format: util::Format,
         msaa_sample_count: u8,
+        final_layout: vk_raw::ImageLayout,
+    ) -> Result<Self, crate::error::RenderError> {
+        let subpass_dependency = vk_raw::SubpassDependency::builder()
+            .src_subpass(vk_raw::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk_raw::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk_raw::AccessFlags::empty())
+            .dst_stage_mask(vk_raw::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(vk_raw::AccessFlags::COLOR_ATTACHMENT_WRITE);
+
+        let dependencies = [subpass_dependency.build()];
+        Self::resolving_render_pass(
+            device,
+            format,
+            msaa_sample_count,
+            final_layout,
+            &dependencies,
+            Continuation::None,
+        )
+    }
+
+    /// Same layout as the presentation render pass, but the resolved color attachment is a texture
+    /// that is sampled by later passes.
+    pub fn offscreen_render_pass(
+        device: &backend::device::Device,
+        format: util::Format,
+        msaa_sample_count: u8,
+    ) -> Result<Self, crate::error::RenderError> {
+        // The previous frame might still be sampling the resolve texture
+        let before = vk_raw::SubpassDependency::builder()
+            .src_subpass(vk_raw::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk_raw::PipelineStageFlags::FRAGMENT_SHADER)
+            .src_access_mask(vk_raw::AccessFlags::SHADER_READ)
+            .dst_stage_mask(vk_raw::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(vk_raw::AccessFlags::COLOR_ATTACHMENT_WRITE);
+
+        let after = vk_raw::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk_raw::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk_raw::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk_raw::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk_raw::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(vk_raw::AccessFlags::SHADER_READ);
+
+        let dependencies = [before.build(), after.build()];
+        Self::resolving_render_pass(
+            device,
+            format,
+            msaa_sample_count,
+            vk_raw::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            &dependencies,
+            Continuation::None,
+        )
+    }
+
+    /// The offscreen render pass split in two, so that the resolved color of the first can be
+    /// sampled before the second continues drawing on top of it. The first keeps the multisampled
+    /// color and depth, the second starts from them instead of clearing them. Both are compatible
+    /// with the render targets of `offscreen_render_pass`.
+    pub fn split_offscreen_render_passes(
+        device: &backend::device::Device,
+        format: util::Format,
+        msaa_sample_count: u8,
+    ) -> Result<[Self; 2], crate::error::RenderError> {
+        let after = vk_raw::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk_raw::SUBPASS_EXTERNAL)
+            .src_stage_mask(
+                vk_raw::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk_raw::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .src_access_mask(
+                vk_raw::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk_raw::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .dst_stage_mask(vk_raw::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(vk_raw::AccessFlags::SHADER_READ);
+
+        let first_before = vk_raw::SubpassDependency::builder()
+            .src_subpass(vk_raw::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk_raw::PipelineStageFlags::FRAGMENT_SHADER)
+            .src_access_mask(vk_raw::AccessFlags::SHADER_READ)
+            .dst_stage_mask(vk_raw::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(vk_raw::AccessFlags::COLOR_ATTACHMENT_WRITE);
+
+        // The attachments were written by the first pass and the resolved color is read by the
+        // passes in between
+        let second_before = vk_raw::SubpassDependency::builder()
+            .src_subpass(vk_raw::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(
+                vk_raw::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk_raw::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | vk_raw::PipelineStageFlags::FRAGMENT_SHADER,
+            )
+            .src_access_mask(
+                vk_raw::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk_raw::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+                    | vk_raw::AccessFlags::SHADER_READ,
+            )
+            .dst_stage_mask(
+                vk_raw::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk_raw::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk_raw::AccessFlags::COLOR_ATTACHMENT_READ
+                    | vk_raw::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk_raw::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk_raw::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            );
+
+        let first = Self::resolving_render_pass(
+            device,
+            format,
+            msaa_sample_count,
+            vk_raw::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            &[first_before.build(), after.build()],
+            Continuation::Continued,
+        )?;
+        let second = Self::resolving_render_pass(
+            device,
+            format,
+            msaa_sample_count,
+            vk_raw::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            &[second_before.build(), after.build()],
+            Continuation::Continues,
+        )?;
+        Ok([first, second])
+    }
+
+    /// Attachments are [msaa color, depth, resolved color]
+    fn resolving_render_pass(
+        device: &backend::device::Device,
+        format: util::Format,
+        msaa_sample_count: u8,
+        final_layout: vk_raw::ImageLayout,
+        dependencies: &[vk_raw::SubpassDependency],
+        continuation: Continuation,
     ) -> Result<Self, crate::error::RenderError> {
+        let (load_op, color_layout, depth_layout) = match continuation {
+            Continuation::Continues => (
+                vk_raw::AttachmentLoadOp::LOAD,
+                vk_raw::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk_raw::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ),
+            Continuation::None | Continuation::Continued => (
+                vk_raw::AttachmentLoadOp::CLEAR,
+                vk_raw::ImageLayout::UNDEFINED,
+                vk_raw::ImageLayout::UNDEFINED,
+            ),
+        };
+        let store_op = match continuation {
+            Continuation::Continued => vk_raw::AttachmentStoreOp::STORE,
+            Continuation::None | Continuation::Continues => vk_raw::AttachmentStoreOp::DONT_CARE,
+        };
+
         let msaa_sample_count = backend::vk::n_to_sample_count(msaa_sample_count);
         let msaa_color_attach = vk_raw::AttachmentDescription::builder()
             .format(vk_raw::Format::from(format))
             .samples(msaa_sample_count)
-            .load_op(vk_raw::AttachmentLoadOp::CLEAR)
-            .store_op(vk_raw::AttachmentStoreOp::DONT_CARE)
+            .load_op(load_op)
+            .store_op(store_op)
             .stencil_load_op(vk_raw::AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(vk_raw::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk_raw::ImageLayout::UNDEFINED)
+            .initial_layout(color_layout)
             .final_layout(vk_raw::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
 
         let resolve_color_attach = vk_raw::AttachmentDescription::builder()
@@ -193,7 +427,7 @@ impl RenderPass {
             .stencil_load_op(vk_raw::AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(vk_raw::AttachmentStoreOp::DONT_CARE)
             .initial_layout(vk_raw::ImageLayout::UNDEFINED)
-            .final_layout(vk_raw::ImageLayout::PRESENT_SRC_KHR);
+            .final_layout(final_layout);
 
         let msaa_color_attach_ref = vk_raw::AttachmentReference {
             attachment: 0,
@@ -208,11 +442,11 @@ impl RenderPass {
         let depth_attach = vk_raw::AttachmentDescription::builder()
             .format(device.depth_buffer_format())
             .samples(msaa_sample_count)
-            .load_op(vk_raw::AttachmentLoadOp::CLEAR)
-            .store_op(vk_raw::AttachmentStoreOp::DONT_CARE)
+            .load_op(load_op)
+            .store_op(store_op)
             .stencil_load_op(vk_raw::AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(vk_raw::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk_raw::ImageLayout::UNDEFINED)
+            .initial_layout(depth_layout)
             .final_layout(vk_raw::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
 
         let depth_attach_ref = vk_raw::AttachmentReference {
@@ -232,20 +466,10 @@ impl RenderPass {
         let attachments = [*msaa_color_attach, *depth_attach, *resolve_color_attach];
         let subpasses = [*subpass];
 
-        let subpass_dependency = vk_raw::SubpassDependency::builder()
-            .src_subpass(vk_raw::SUBPASS_EXTERNAL)
-            .dst_subpass(0)
-            .src_stage_mask(vk_raw::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-            .src_access_mask(vk_raw::AccessFlags::empty())
-            .dst_stage_mask(vk_raw::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-            .dst_access_mask(vk_raw::AccessFlags::COLOR_ATTACHMENT_WRITE);
-
-        let dependencies = [subpass_dependency.build()];
-
         let create_info = vk_raw::RenderPassCreateInfo::builder()
             .attachments(&attachments)
             .subpasses(&subpasses)
-            .dependencies(&dependencies);
+            .dependencies(dependencies);
         Self::new_vk(device, &create_info)
     }
 }
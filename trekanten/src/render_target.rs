@@ -1,3 +1,5 @@
+use crate::backend::color_buffer::ColorBuffer;
+use crate::backend::depth_buffer::DepthBuffer;
 use crate::backend::device::Device;
 use crate::backend::framebuffer::Framebuffer as FrameBuffer;
 use crate::backend::image::ImageView;
@@ -9,6 +11,8 @@ use crate::util;
 
 pub struct RenderTarget {
     pub(crate) inner: FrameBuffer,
+    // Multisampled attachments that are only used by this target
+    pub(crate) _msaa_buffers: Option<(ColorBuffer, DepthBuffer)>,
 }
 
 impl RenderTarget {
@@ -20,6 +24,32 @@ impl RenderTarget {
     ) -> Result<Self, RenderError> {
         let image_views: Vec<&ImageView> = attachments.iter().map(|t| t.image_view()).collect();
         let inner = FrameBuffer::new(device, &image_views, &render_pass.0, extent)?;
-        Ok(Self { inner })
+        Ok(Self {
+            inner,
+            _msaa_buffers: None,
+        })
+    }
+
+    /// For render passes created with `RenderPass::offscreen_render_pass`. The multisampled color
+    /// and depth attachments are created here and `resolve` is where the result ends up.
+    pub fn new_resolving(
+        device: &Device,
+        resolve: &Texture,
+        render_pass: &RenderPass,
+    ) -> Result<Self, RenderError> {
+        let extent = resolve.extent();
+        let msaa_sample_count = render_pass.0.msaa_sample_count();
+        let color_buffer = ColorBuffer::new(device, resolve.format(), &extent, msaa_sample_count)?;
+        let depth_buffer = DepthBuffer::new(device, &extent, msaa_sample_count)?;
+        let image_views = [
+            color_buffer.image_view(),
+            depth_buffer.image_view(),
+            resolve.image_view(),
+        ];
+        let inner = FrameBuffer::new(device, &image_views, &render_pass.0, &extent)?;
+        Ok(Self {
+            inner,
+            _msaa_buffers: Some((color_buffer, depth_buffer)),
+        })
     }
 }
@@ -45,10 +45,12 @@ pub struct AsyncResources {
 
 pub struct Resources {
     pub uniform_buffers: mem::UniformBuffers,
+    pub storage_buffers: mem::StorageBuffers,
     pub vertex_buffers: mem::VertexBuffers,
     pub index_buffers: mem::IndexBuffers,
     pub textures: texture::Textures,
     pub graphics_pipelines: pipeline::GraphicsPipelines,
+    pub compute_pipelines: resurs::Storage<pipeline::ComputePipeline>,
     pub descriptor_sets: descriptor::DescriptorSets,
     pub render_passes: resurs::Storage<render_pass::RenderPass>,
     pub render_targets: resurs::Storage<render_target::RenderTarget>,
@@ -0,0 +1,493 @@
+//! Partially resident textures. The image of a sparse texture is split into pages and memory is
+//! only bound to the pages that are made resident, so the texture can be larger than the device
+//! memory. The smallest mip levels, the mip tail, are always resident. Shaders have to check if
+//! what they sample is resident, e.g. with sparseTextureLodARB, as non-resident pages read
+//! undefined values.
+
+use std::collections::HashMap;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use thiserror::Error;
+use vk_mem::{Allocation, AllocationCreateInfo, AllocationInfo, MemoryUsage};
+
+use crate::backend::{AllocatorHandle, HasVkDevice, VkDeviceHandle};
+use crate::queue::{Queue, QueueError};
+use crate::sync::{Fence, SyncError};
+use crate::texture::{mip_extent, mip_levels_for, SamplerDescriptor};
+use crate::util::{self, Extent2D};
+
+#[derive(Debug, Error)]
+pub enum SparseError {
+    #[error("Sparse textures are not supported by the device")]
+    Unsupported,
+    #[error("Failed to create sparse image: {0}")]
+    ImageCreation(vk::Result),
+    #[error("The format has no sparse image requirements for the color aspect")]
+    MissingRequirements,
+    #[error("Failed to allocate page memory: {0}")]
+    Allocation(vk_mem::Error),
+    #[error("Failed to bind page memory: {0}")]
+    Bind(#[from] QueueError),
+    #[error("Failed to wait for binding: {0}")]
+    Sync(#[from] SyncError),
+    #[error("Page {0:?} is outside of the texture")]
+    InvalidPage(Page),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SparseTextureDescriptor {
+    pub extent: Extent2D,
+    pub format: util::Format,
+    pub sampler: SamplerDescriptor,
+}
+
+/// A page of a mip level, in units of the page extent. The levels of the mip tail have a single
+/// page, covering the whole level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Page {
+    pub level: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+/// The number of pages in x and y of the level
+pub fn pages_in_level(extent: Extent2D, page_extent: Extent2D, level: u32) -> (u32, u32) {
+    let e = mip_extent(extent, level);
+    (
+        (e.width + page_extent.width - 1) / page_extent.width,
+        (e.height + page_extent.height - 1) / page_extent.height,
+    )
+}
+
+/// The texels of the page, clamped to the extent of its level
+pub fn page_region(
+    extent: Extent2D,
+    page_extent: Extent2D,
+    page: Page,
+) -> (util::Offset2D, Extent2D) {
+    let e = mip_extent(extent, page.level);
+    let x = page.x * page_extent.width;
+    let y = page.y * page_extent.height;
+    (
+        util::Offset2D {
+            x: x as i32,
+            y: y as i32,
+        },
+        Extent2D {
+            width: page_extent.width.min(e.width.saturating_sub(x)),
+            height: page_extent.height.min(e.height.saturating_sub(y)),
+        },
+    )
+}
+
+struct PageMemory {
+    allocation: Allocation,
+    info: AllocationInfo,
+}
+
+pub struct SparseImage {
+    vk_device: VkDeviceHandle,
+    allocator: AllocatorHandle,
+    vk_image: vk::Image,
+    extent: Extent2D,
+    format: util::Format,
+    mip_levels: u32,
+    page_extent: Extent2D,
+    /// Memory is allocated a page at a time
+    page_requirements: vk::MemoryRequirements,
+    /// The levels from this one are in the mip tail
+    mip_tail_first_level: u32,
+    mip_tail: Option<PageMemory>,
+    pages: HashMap<Page, PageMemory>,
+}
+
+impl SparseImage {
+    /// The mip tail is bound before this returns, the other pages are not resident. The image is
+    /// in UNDEFINED layout.
+    pub(crate) fn new<D: HasVkDevice>(
+        device: &D,
+        allocator: &AllocatorHandle,
+        queue: &Queue,
+        extent: Extent2D,
+        format: util::Format,
+    ) -> Result<Self, SparseError> {
+        let vk_device = device.vk_device();
+        let mip_levels = mip_levels_for(extent);
+        let extent3d = util::Extent3D::from_2d(extent, 1);
+        let info = vk::ImageCreateInfo::builder()
+            .flags(vk::ImageCreateFlags::SPARSE_BINDING | vk::ImageCreateFlags::SPARSE_RESIDENCY)
+            .image_type(vk::ImageType::TYPE_2D)
+            .extent(extent3d.into())
+            .mip_levels(mip_levels)
+            .array_layers(1)
+            .format(format.into())
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .samples(vk::SampleCountFlags::TYPE_1);
+
+        let vk_image = unsafe {
+            vk_device
+                .create_image(&info, None)
+                .map_err(SparseError::ImageCreation)?
+        };
+
+        let (page_requirements, sparse_requirements) = unsafe {
+            (
+                vk_device.get_image_memory_requirements(vk_image),
+                vk_device.get_image_sparse_memory_requirements(vk_image),
+            )
+        };
+        let color = match sparse_requirements.iter().find(|r| {
+            r.format_properties
+                .aspect_mask
+                .contains(vk::ImageAspectFlags::COLOR)
+        }) {
+            Some(color) => *color,
+            None => {
+                unsafe { vk_device.destroy_image(vk_image, None) };
+                return Err(SparseError::MissingRequirements);
+            }
+        };
+
+        let granularity = color.format_properties.image_granularity;
+        let mut image = Self {
+            vk_device,
+            allocator: AllocatorHandle::clone(allocator),
+            vk_image,
+            extent,
+            format,
+            mip_levels,
+            page_extent: Extent2D {
+                width: granularity.width,
+                height: granularity.height,
+            },
+            page_requirements: vk::MemoryRequirements {
+                size: page_requirements.alignment,
+                ..page_requirements
+            },
+            mip_tail_first_level: color.image_mip_tail_first_lod,
+            mip_tail: None,
+            pages: HashMap::new(),
+        };
+
+        log::debug!(
+            "Created sparse image of {} with {} levels, pages of {} and mip tail from level {}",
+            extent,
+            mip_levels,
+            image.page_extent,
+            image.mip_tail_first_level
+        );
+
+        if image.mip_tail_first_level < mip_levels {
+            let memory = image.allocate(color.image_mip_tail_size)?;
+            let bind = vk::SparseMemoryBind {
+                resource_offset: color.image_mip_tail_offset,
+                size: color.image_mip_tail_size,
+                memory: memory.info.get_device_memory(),
+                memory_offset: memory.info.get_offset() as u64,
+                flags: vk::SparseMemoryBindFlags::empty(),
+            };
+            image.mip_tail = Some(memory);
+            let binds = [bind];
+            let opaque = [vk::SparseImageOpaqueMemoryBindInfo::builder()
+                .image(vk_image)
+                .binds(&binds)
+                .build()];
+            let info = vk::BindSparseInfo::builder().image_opaque_binds(&opaque);
+            image.bind_blocking(queue, &info)?;
+        }
+
+        Ok(image)
+    }
+
+    fn allocate(&self, size: u64) -> Result<PageMemory, SparseError> {
+        let requirements = vk::MemoryRequirements {
+            size,
+            ..self.page_requirements
+        };
+        let create_info = AllocationCreateInfo {
+            usage: MemoryUsage::GpuOnly,
+            ..Default::default()
+        };
+        let (allocation, info) = self
+            .allocator
+            .allocate_memory(&requirements, &create_info)
+            .map_err(SparseError::Allocation)?;
+        Ok(PageMemory { allocation, info })
+    }
+
+    fn bind_blocking(&self, queue: &Queue, info: &vk::BindSparseInfo) -> Result<(), SparseError> {
+        let done = Fence::unsignaled(&self.vk_device)?;
+        queue.bind_sparse(info, &done)?;
+        done.blocking_wait()?;
+        Ok(())
+    }
+
+    /// The texels of the page in its level. The levels of the mip tail are a single page each.
+    pub fn region(&self, page: Page) -> (util::Offset2D, Extent2D) {
+        if self.is_mip_tail(page.level) {
+            (
+                util::Offset2D { x: 0, y: 0 },
+                mip_extent(self.extent, page.level),
+            )
+        } else {
+            page_region(self.extent, self.page_extent, page)
+        }
+    }
+
+    fn image_bind(&self, page: Page, memory: Option<&PageMemory>) -> vk::SparseImageMemoryBind {
+        let (offset, extent) = self.region(page);
+        vk::SparseImageMemoryBind {
+            subresource: vk::ImageSubresource {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: page.level,
+                array_layer: 0,
+            },
+            offset: vk::Offset3D {
+                x: offset.x,
+                y: offset.y,
+                z: 0,
+            },
+            extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            memory: memory
+                .map(|m| m.info.get_device_memory())
+                .unwrap_or_else(vk::DeviceMemory::null),
+            memory_offset: memory.map(|m| m.info.get_offset() as u64).unwrap_or(0),
+            flags: vk::SparseMemoryBindFlags::empty(),
+        }
+    }
+
+    fn check_page(&self, page: Page) -> Result<(), SparseError> {
+        if page.level >= self.mip_levels {
+            return Err(SparseError::InvalidPage(page));
+        }
+        let (x, y) = pages_in_level(self.extent, self.page_extent, page.level);
+        if self.is_mip_tail(page.level) && (page.x, page.y) != (0, 0) || page.x >= x || page.y >= y
+        {
+            return Err(SparseError::InvalidPage(page));
+        }
+        Ok(())
+    }
+
+    /// Allocates and binds memory for the pages that are not resident. The contents of the new
+    /// pages are undefined until they are written.
+    pub(crate) fn bind(&mut self, queue: &Queue, pages: &[Page]) -> Result<(), SparseError> {
+        let mut new = Vec::new();
+        for &page in pages {
+            self.check_page(page)?;
+            if !self.is_resident(page) && !new.iter().any(|(p, _)| *p == page) {
+                new.push((page, self.allocate(self.page_requirements.size)?));
+            }
+        }
+
+        if new.is_empty() {
+            return Ok(());
+        }
+
+        let binds: Vec<vk::SparseImageMemoryBind> = new
+            .iter()
+            .map(|(page, memory)| self.image_bind(*page, Some(memory)))
+            .collect();
+        let image_binds = [vk::SparseImageMemoryBindInfo::builder()
+            .image(self.vk_image)
+            .binds(&binds)
+            .build()];
+        let info = vk::BindSparseInfo::builder().image_binds(&image_binds);
+        if let Err(e) = self.bind_blocking(queue, &info) {
+            for (_, memory) in new {
+                self.allocator.free_memory(&memory.allocation);
+            }
+            return Err(e);
+        }
+
+        self.pages.extend(new);
+        Ok(())
+    }
+
+    /// Unbinds and frees the memory of the page. It must not be used by a frame in flight.
+    pub(crate) fn unbind(&mut self, queue: &Queue, page: Page) -> Result<(), SparseError> {
+        let memory = match self.pages.remove(&page) {
+            Some(memory) => memory,
+            None => return Ok(()),
+        };
+
+        let binds = [self.image_bind(page, None)];
+        let image_binds = [vk::SparseImageMemoryBindInfo::builder()
+            .image(self.vk_image)
+            .binds(&binds)
+            .build()];
+        let info = vk::BindSparseInfo::builder().image_binds(&image_binds);
+        let result = self.bind_blocking(queue, &info);
+        self.allocator.free_memory(&memory.allocation);
+        result
+    }
+
+    /// For all levels of the image
+    pub(crate) fn barrier(
+        &self,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        src_access_mask: vk::AccessFlags,
+        dst_access_mask: vk::AccessFlags,
+    ) -> vk::ImageMemoryBarrier {
+        vk::ImageMemoryBarrier {
+            old_layout,
+            new_layout,
+            src_access_mask,
+            dst_access_mask,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: self.vk_image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: self.mip_levels,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        }
+    }
+
+    /// The region that a page covers, for copying its texels
+    pub(crate) fn copy_region(&self, page: Page, buffer_offset: u64) -> vk::BufferImageCopy {
+        let (offset, extent) = self.region(page);
+        vk::BufferImageCopy {
+            buffer_offset,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: page.level,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D {
+                x: offset.x,
+                y: offset.y,
+                z: 0,
+            },
+            image_extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+        }
+    }
+
+    pub fn is_mip_tail(&self, level: u32) -> bool {
+        level >= self.mip_tail_first_level
+    }
+
+    pub fn is_resident(&self, page: Page) -> bool {
+        self.is_mip_tail(page.level) || self.pages.contains_key(&page)
+    }
+
+    /// The number of pages in x and y of the level
+    pub fn pages_in_level(&self, level: u32) -> (u32, u32) {
+        if self.is_mip_tail(level) {
+            (1, 1)
+        } else {
+            pages_in_level(self.extent, self.page_extent, level)
+        }
+    }
+
+    /// The number of bytes a page is expected to have when it is written, tightly packed
+    pub fn page_bytes(&self, page: Page) -> usize {
+        let extent = self.region(page).1;
+        extent.width as usize * extent.height as usize * self.format.size() as usize
+    }
+
+    /// The memory that is bound for the pages, excluding the mip tail
+    pub fn resident_bytes(&self) -> u64 {
+        self.pages.len() as u64 * self.page_requirements.size
+    }
+
+    /// The memory that is bound for each page
+    pub fn page_size(&self) -> u64 {
+        self.page_requirements.size
+    }
+
+    pub fn page_extent(&self) -> Extent2D {
+        self.page_extent
+    }
+
+    pub fn mip_tail_first_level(&self) -> u32 {
+        self.mip_tail_first_level
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels
+    }
+
+    pub fn extent(&self) -> Extent2D {
+        self.extent
+    }
+
+    pub fn format(&self) -> util::Format {
+        self.format
+    }
+
+    pub fn vk_image(&self) -> &vk::Image {
+        &self.vk_image
+    }
+}
+
+impl std::ops::Drop for SparseImage {
+    fn drop(&mut self) {
+        unsafe {
+            self.vk_device.destroy_image(self.vk_image, None);
+        }
+        for (_, memory) in self.pages.drain() {
+            self.allocator.free_memory(&memory.allocation);
+        }
+        if let Some(memory) = self.mip_tail.take() {
+            self.allocator.free_memory(&memory.allocation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_cover_the_level() {
+        let extent = Extent2D {
+            width: 1000,
+            height: 300,
+        };
+        let page_extent = Extent2D {
+            width: 128,
+            height: 128,
+        };
+        assert_eq!(pages_in_level(extent, page_extent, 0), (8, 3));
+        assert_eq!(pages_in_level(extent, page_extent, 2), (2, 1));
+
+        let (offset, region) = page_region(
+            extent,
+            page_extent,
+            Page {
+                level: 0,
+                x: 7,
+                y: 2,
+            },
+        );
+        assert_eq!(offset, util::Offset2D { x: 896, y: 256 });
+        assert_eq!(
+            region,
+            Extent2D {
+                width: 104,
+                height: 44
+            }
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Cpu-side timings for one frame. Used to track how much time the cpu spends blocked on the gpu
+/// and how long it takes from acquiring a swapchain image until it is queued for presentation.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameTimings {
+    /// Waiting for the in-flight fence of the frame slot we are about to reuse
+    pub fence_wait: Duration,
+    /// Acquiring the next swapchain image
+    pub acquire: Duration,
+    /// Waiting for the fence of another frame that still uses the acquired image
+    pub image_in_use_wait: Duration,
+    /// Total time blocked in next_frame()
+    pub next_frame: Duration,
+    /// From when the swapchain image was acquired until it was enqueued for presentation
+    pub acquire_to_present: Duration,
+}
+
+impl FrameTimings {
+    fn max(&self, other: &Self) -> Self {
+        Self {
+            fence_wait: self.fence_wait.max(other.fence_wait),
+            acquire: self.acquire.max(other.acquire),
+            image_in_use_wait: self.image_in_use_wait.max(other.image_in_use_wait),
+            next_frame: self.next_frame.max(other.next_frame),
+            acquire_to_present: self.acquire_to_present.max(other.acquire_to_present),
+        }
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        Self {
+            fence_wait: self.fence_wait + other.fence_wait,
+            acquire: self.acquire + other.acquire,
+            image_in_use_wait: self.image_in_use_wait + other.image_in_use_wait,
+            next_frame: self.next_frame + other.next_frame,
+            acquire_to_present: self.acquire_to_present + other.acquire_to_present,
+        }
+    }
+
+    fn div(&self, n: u32) -> Self {
+        Self {
+            fence_wait: self.fence_wait / n,
+            acquire: self.acquire / n,
+            image_in_use_wait: self.image_in_use_wait / n,
+            next_frame: self.next_frame / n,
+            acquire_to_present: self.acquire_to_present / n,
+        }
+    }
+}
+
+/// The gpu time of a named part of a frame, see Frame::begin_gpu_timer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GpuTiming {
+    pub name: &'static str,
+    pub duration: Duration,
+}
+
+/// Number of live resources in the renderer. If these grow while the scene does not, resources are
+/// leaking.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceCounts {
+    pub textures: usize,
+    pub vertex_buffers: usize,
+    pub index_buffers: usize,
+    pub uniform_buffers: usize,
+    pub storage_buffers: usize,
+    pub graphics_pipelines: usize,
+    pub descriptor_sets: usize,
+    /// Of the descriptor sets, the ones that the frames in flight allocated for themselves, see
+    /// DescriptorSet::transient_builder
+    pub transient_descriptor_sets: usize,
+    pub descriptor_pools: usize,
+    /// Resources that are waiting for the frames in flight to finish before they are destroyed
+    pub pending_destruction: usize,
+}
+
+pub const FRAME_STATS_HISTORY_LEN: usize = 64;
+
+/// Rolling statistics over the last FRAME_STATS_HISTORY_LEN submitted frames
+#[derive(Debug, Clone)]
+pub struct FrameStats {
+    frames_in_flight: usize,
+    history: VecDeque<FrameTimings>,
+    current: FrameTimings,
+    acquired_at: Option<Instant>,
+    gpu_timings: Vec<GpuTiming>,
+}
+
+impl FrameStats {
+    pub(crate) fn new(frames_in_flight: usize) -> Self {
+        Self {
+            frames_in_flight,
+            history: VecDeque::with_capacity(FRAME_STATS_HISTORY_LEN),
+            current: FrameTimings::default(),
+            acquired_at: None,
+            gpu_timings: Vec::new(),
+        }
+    }
+
+    pub(crate) fn begin_frame(&mut self) {
+        self.current = FrameTimings::default();
+        self.acquired_at = None;
+    }
+
+    pub(crate) fn fence_waited(&mut self, d: Duration) {
+        self.current.fence_wait = d;
+    }
+
+    pub(crate) fn acquired(&mut self, d: Duration) {
+        self.current.acquire = d;
+        self.acquired_at = Some(Instant::now());
+    }
+
+    pub(crate) fn image_in_use_waited(&mut self, d: Duration) {
+        self.current.image_in_use_wait = d;
+    }
+
+    pub(crate) fn gpu_timed(&mut self, timings: Vec<GpuTiming>) {
+        self.gpu_timings = timings;
+    }
+
+    pub(crate) fn next_frame_done(&mut self, d: Duration) {
+        self.current.next_frame = d;
+    }
+
+    pub(crate) fn presented(&mut self) {
+        if let Some(acquired_at) = self.acquired_at.take() {
+            self.current.acquire_to_present = acquired_at.elapsed();
+        }
+
+        if self.history.len() == FRAME_STATS_HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(self.current);
+    }
+
+    /// The number of frames the cpu may be ahead of the gpu
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames_in_flight
+    }
+
+    /// Timings of the most recently presented frame
+    pub fn latest(&self) -> Option<&FrameTimings> {
+        self.history.back()
+    }
+
+    /// The gpu timers of the most recent frame that the gpu has finished, in the order they ended
+    pub fn gpu_timings(&self) -> &[GpuTiming] {
+        &self.gpu_timings
+    }
+
+    /// Oldest first
+    pub fn history(&self) -> impl ExactSizeIterator<Item = &FrameTimings> {
+        self.history.iter()
+    }
+
+    pub fn average(&self) -> FrameTimings {
+        if self.history.is_empty() {
+            return FrameTimings::default();
+        }
+
+        self.history
+            .iter()
+            .fold(FrameTimings::default(), |acc, t| acc.add(t))
+            .div(self.history.len() as u32)
+    }
+
+    pub fn max(&self) -> FrameTimings {
+        self.history
+            .iter()
+            .fold(FrameTimings::default(), |acc, t| acc.max(t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn present_with_fence_wait(stats: &mut FrameStats, ms: u64) {
+        stats.begin_frame();
+        stats.fence_waited(Duration::from_millis(ms));
+        stats.presented();
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut stats = FrameStats::new(2);
+        for i in 0..(FRAME_STATS_HISTORY_LEN as u64 + 10) {
+            present_with_fence_wait(&mut stats, i);
+        }
+
+        assert_eq!(stats.history().len(), FRAME_STATS_HISTORY_LEN);
+        assert_eq!(
+            stats.latest().unwrap().fence_wait,
+            Duration::from_millis(FRAME_STATS_HISTORY_LEN as u64 + 9)
+        );
+        assert_eq!(
+            stats.history().next().unwrap().fence_wait,
+            Duration::from_millis(10)
+        );
+    }
+
+    #[test]
+    fn average_and_max() {
+        let mut stats = FrameStats::new(2);
+        assert_eq!(stats.average().fence_wait, Duration::from_millis(0));
+
+        present_with_fence_wait(&mut stats, 2);
+        present_with_fence_wait(&mut stats, 4);
+        present_with_fence_wait(&mut stats, 6);
+
+        assert_eq!(stats.average().fence_wait, Duration::from_millis(4));
+        assert_eq!(stats.max().fence_wait, Duration::from_millis(6));
+        assert_eq!(stats.max().acquire_to_present, Duration::from_millis(0));
+    }
+}
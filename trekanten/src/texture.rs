@@ -12,6 +12,7 @@ use crate::mem::DeviceBuffer;
 use crate::mem::DeviceImage;
 use crate::mem::MemoryError;
 use crate::resource::{Handle, Storage};
+use crate::sparse::SparseImage;
 use crate::util;
 use util::Extent2D;
 
@@ -43,6 +44,31 @@ pub fn load_image<P: AsRef<Path>>(p: &P) -> Result<image::RgbaImage, image::Imag
     Ok(image)
 }
 
+/// A texture whose file could not be loaded, see TextureDescriptor::enqueue_or_error_texture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextureLoadFailure {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// The side of the error texture, in pixels, and of each of its squares
+const ERROR_TEXTURE_SIZE: u32 = 64;
+const ERROR_SQUARE_SIZE: u32 = 8;
+
+/// The extent of the image in the file, without loading all of it
+pub fn image_extent<P: AsRef<Path>>(p: &P) -> Result<Extent2D, image::ImageError> {
+    let (width, height) = image::image_dimensions(p)?;
+    Ok(Extent2D { width, height })
+}
+
+/// The extent of mip level `level` of an image with extent `e`
+pub fn mip_extent(e: Extent2D, level: u32) -> Extent2D {
+    Extent2D {
+        width: (e.width >> level).max(1),
+        height: (e.height >> level).max(1),
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MipMaps {
     None,
@@ -58,6 +84,8 @@ bitflags::bitflags! {
         const COLOR_ATTACHMENT = 0b10;
         const TRANSFER_SRC = 0b100;
         const TRANSFER_DST = 0b1000;
+        /// Written by compute shaders, see DescriptorSetBuilder::add_storage_image
+        const STORAGE = 0b10000;
     }
 }
 
@@ -82,7 +110,8 @@ impl From<TextureUsage> for vk::ImageUsageFlags {
             DEPTH_STENCIL_ATTACHMENT,
             COLOR_ATTACHMENT,
             TRANSFER_SRC,
-            TRANSFER_DST
+            TRANSFER_DST,
+            STORAGE
         );
 
         ret
@@ -96,6 +125,13 @@ pub enum TextureDescriptor {
         format: util::Format,
         mipmaps: MipMaps,
     },
+    /// The image in the file, downscaled to the extent of one of its mip levels
+    FileMipLevel {
+        path: PathBuf,
+        format: util::Format,
+        mipmaps: MipMaps,
+        level: u32,
+    },
     Raw {
         data: Arc<util::ByteBuffer>,
         extent: Extent2D,
@@ -113,7 +149,9 @@ pub enum TextureDescriptor {
 impl TextureDescriptor {
     pub fn mipmaps(&self) -> MipMaps {
         match self {
-            Self::File { mipmaps, .. } | Self::Raw { mipmaps, .. } => *mipmaps,
+            Self::File { mipmaps, .. }
+            | Self::FileMipLevel { mipmaps, .. }
+            | Self::Raw { mipmaps, .. } => *mipmaps,
             Self::Empty { .. } => MipMaps::None,
         }
     }
@@ -126,6 +164,15 @@ impl TextureDescriptor {
         }
     }
 
+    pub fn file_mip_level(p: PathBuf, format: util::Format, mipmaps: MipMaps, level: u32) -> Self {
+        Self::FileMipLevel {
+            path: p,
+            format,
+            mipmaps,
+            level,
+        }
+    }
+
     pub fn from_vec(
         data: Vec<u8>,
         extent: Extent2D,
@@ -140,6 +187,45 @@ impl TextureDescriptor {
         }
     }
 
+    /// Magenta and black squares, with the format and mipmaps of this descriptor, to show where a
+    /// texture is missing
+    pub fn error_texture(&self) -> Self {
+        let format = match self {
+            Self::File { format, .. }
+            | Self::FileMipLevel { format, .. }
+            | Self::Raw { format, .. }
+            | Self::Empty { format, .. } => *format,
+        };
+        let mut data = Vec::with_capacity((ERROR_TEXTURE_SIZE * ERROR_TEXTURE_SIZE * 4) as usize);
+        for y in 0..ERROR_TEXTURE_SIZE {
+            for x in 0..ERROR_TEXTURE_SIZE {
+                let magenta = (x / ERROR_SQUARE_SIZE + y / ERROR_SQUARE_SIZE) % 2 == 0;
+                if magenta {
+                    data.extend_from_slice(&[255, 0, 255, 255]);
+                } else {
+                    data.extend_from_slice(&[0, 0, 0, 255]);
+                }
+            }
+        }
+        Self::from_vec(
+            data,
+            Extent2D {
+                width: ERROR_TEXTURE_SIZE,
+                height: ERROR_TEXTURE_SIZE,
+            },
+            format,
+            self.mipmaps(),
+        )
+    }
+
+    /// The file the texture is loaded from, if any
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::File { path, .. } | Self::FileMipLevel { path, .. } => Some(path),
+            Self::Raw { .. } | Self::Empty { .. } => None,
+        }
+    }
+
     pub(crate) fn needs_command_buffer(&self) -> bool {
         if let TextureDescriptor::Empty { .. } = self {
             false
@@ -176,6 +262,37 @@ impl TextureDescriptor {
                     &raw_image_data,
                 )
             }
+            TextureDescriptor::FileMipLevel {
+                path,
+                format,
+                mipmaps,
+                level,
+            } => {
+                let image = load_image(&path)?;
+                let extent = mip_extent(
+                    Extent2D {
+                        width: image.width(),
+                        height: image.height(),
+                    },
+                    *level,
+                );
+                let image = image::imageops::resize(
+                    &image,
+                    extent.width,
+                    extent.height,
+                    image::imageops::FilterType::Triangle,
+                );
+                let raw_image_data = image.into_raw();
+                Texture::from_raw(
+                    device,
+                    allocator,
+                    command_buffer,
+                    extent,
+                    *format,
+                    *mipmaps,
+                    &raw_image_data,
+                )
+            }
             TextureDescriptor::Raw {
                 data,
                 extent,
@@ -193,6 +310,31 @@ impl TextureDescriptor {
             _ => unreachable!("This should not be created with a command buffer"),
         }
     }
+
+    /// Same as enqueue, but if the file can't be read or decoded, the error texture is created
+    /// instead and the failure is returned with it
+    pub(crate) fn enqueue_or_error_texture<D: HasVkDevice>(
+        &self,
+        allocator: &AllocatorHandle,
+        device: &D,
+        command_buffer: &mut CommandBuffer,
+    ) -> Result<(Texture, DeviceBuffer, Option<TextureLoadFailure>), TextureError> {
+        match (self.enqueue(allocator, device, command_buffer), self.path()) {
+            (Ok((texture, transients)), _) => Ok((texture, transients, None)),
+            (Err(TextureError::Loading(e)), Some(path)) => {
+                log::error!("Failed to load texture {}: {}", path.display(), e);
+                let (texture, transients) =
+                    self.error_texture()
+                        .enqueue(allocator, device, command_buffer)?;
+                let failure = TextureLoadFailure {
+                    path: path.to_path_buf(),
+                    error: e.to_string(),
+                };
+                Ok((texture, transients, Some(failure)))
+            }
+            (Err(e), _) => Err(e),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -336,10 +478,15 @@ pub fn mip_levels_for(e: Extent2D) -> u32 {
     (e.max_dim() as f32).log2().floor() as u32 + 1
 }
 
+enum TextureImage {
+    Device(DeviceImage),
+    Sparse(SparseImage),
+}
+
 pub struct Texture {
     sampler: Sampler,
     image_view: ImageView,
-    image: DeviceImage,
+    image: TextureImage,
 }
 
 impl Texture {
@@ -378,7 +525,7 @@ impl Texture {
                 ImageView::new(device, image.vk_image(), *format, aspect_mask, mip_levels)?;
             let sampler = Sampler::new(device, &sampler_descriptor)?;
             Ok(Self {
-                image,
+                image: TextureImage::Device(image),
                 image_view,
                 sampler,
             })
@@ -400,11 +547,33 @@ impl Texture {
         let sampler = Sampler::new(device, &SamplerDescriptor::default())?;
 
         Ok(Self {
-            image,
+            image: TextureImage::Device(image),
             image_view,
             sampler,
         })
     }
+
+    pub(crate) fn from_sparse_image<D: HasVkDevice>(
+        device: &D,
+        image: SparseImage,
+        sampler: &SamplerDescriptor,
+    ) -> Result<Self, TextureError> {
+        let image_view = ImageView::new(
+            device,
+            image.vk_image(),
+            image.format(),
+            vk::ImageAspectFlags::COLOR,
+            image.mip_levels(),
+        )?;
+        let sampler = Sampler::new(device, sampler)?;
+
+        Ok(Self {
+            image: TextureImage::Sparse(image),
+            image_view,
+            sampler,
+        })
+    }
+
     fn from_raw<'a, D: HasVkDevice>(
         device: &D,
         allocator: &AllocatorHandle,
@@ -443,7 +612,10 @@ impl Texture {
     }
 
     pub fn vk_image(&self) -> &vk::Image {
-        &self.image.vk_image()
+        match &self.image {
+            TextureImage::Device(image) => image.vk_image(),
+            TextureImage::Sparse(image) => image.vk_image(),
+        }
     }
 
     pub fn vk_sampler(&self) -> &vk::Sampler {
@@ -451,11 +623,32 @@ impl Texture {
     }
 
     pub fn extent(&self) -> Extent2D {
-        self.image.extent()
+        match &self.image {
+            TextureImage::Device(image) => image.extent(),
+            TextureImage::Sparse(image) => image.extent(),
+        }
     }
 
     pub fn format(&self) -> util::Format {
-        self.image.format()
+        match &self.image {
+            TextureImage::Device(image) => image.format(),
+            TextureImage::Sparse(image) => image.format(),
+        }
+    }
+
+    /// None if the texture is fully resident
+    pub fn sparse(&self) -> Option<&SparseImage> {
+        match &self.image {
+            TextureImage::Device(_) => None,
+            TextureImage::Sparse(image) => Some(image),
+        }
+    }
+
+    pub(crate) fn sparse_mut(&mut self) -> Option<&mut SparseImage> {
+        match &mut self.image {
+            TextureImage::Device(_) => None,
+            TextureImage::Sparse(image) => Some(image),
+        }
     }
 }
 
@@ -488,6 +681,18 @@ impl<T> TextureStorage<T> {
         self.storage.add(t)
     }
 
+    pub fn remove(&mut self, handle: Handle<T>) -> Option<T> {
+        self.storage.remove(handle)
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+    }
+
     pub fn cached(&self, _descriptor: &TextureDescriptor) -> Option<Handle<T>> {
         None
     }
@@ -515,3 +720,35 @@ impl<T> Default for TextureStorage<T> {
 pub type Textures = TextureStorage<Texture>;
 use crate::resource::Async;
 pub type AsyncTextures = TextureStorage<Async<Texture>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_texture_keeps_the_format() {
+        let missing = TextureDescriptor::file(
+            PathBuf::from("missing.png"),
+            util::Format::RGBA_UNORM,
+            MipMaps::None,
+        );
+        assert_eq!(missing.path(), Some(Path::new("missing.png")));
+        match missing.error_texture() {
+            TextureDescriptor::Raw {
+                data,
+                extent,
+                format,
+                mipmaps,
+            } => {
+                assert_eq!(format, util::Format::RGBA_UNORM);
+                assert_eq!(mipmaps, MipMaps::None);
+                assert_eq!(data.len() as u32, extent.width * extent.height * 4);
+                // Magenta in the first square, black in the next
+                assert_eq!(&data[0..4], &[255, 0, 255, 255]);
+                let next = (ERROR_SQUARE_SIZE * 4) as usize;
+                assert_eq!(&data[next..next + 4], &[0, 0, 0, 255]);
+            }
+            _ => panic!("The error texture is raw data"),
+        }
+    }
+}
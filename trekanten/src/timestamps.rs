@@ -0,0 +1,148 @@
+//! Gpu timings of named parts of a frame, measured with timestamp queries. The queries of a frame
+//! are read back when its frame slot is reused, after waiting for its fence, so the timings are a
+//! few frames old when they show up in FrameStats::gpu_timings.
+
+use std::time::Duration;
+
+use ash::vk;
+
+use crate::backend::command::CommandBuffer;
+use crate::backend::device::Device;
+use crate::backend::query::{QueryError, QueryPool};
+use crate::stats::GpuTiming;
+
+/// The most timers that can be used in a frame, the rest are ignored
+pub const MAX_GPU_TIMERS: u32 = 32;
+
+/// A timer that has been started with Frame::begin_gpu_timer
+#[must_use = "Timers have to be ended with Frame::end_gpu_timer"]
+pub struct GpuTimer {
+    name: &'static str,
+    // None if timestamps are not supported or there were too many timers
+    first_query: Option<u32>,
+}
+
+/// The queries of one frame slot
+pub(crate) struct FrameTimestamps {
+    // None if the graphics queue does not support timestamps
+    pool: Option<QueryPool>,
+    // The name and first query of the ended timers
+    timers: Vec<(&'static str, u32)>,
+    next_query: u32,
+}
+
+impl FrameTimestamps {
+    pub fn new(device: &Device) -> Result<Self, QueryError> {
+        let pool = if device.timestamp_valid_bits() > 0 {
+            Some(QueryPool::timestamps(device, MAX_GPU_TIMERS * 2)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            pool,
+            timers: Vec::new(),
+            next_query: 0,
+        })
+    }
+
+    /// The timings of the frame that last used this slot. Its fence has to have been waited for.
+    pub fn read(&self, device: &Device) -> Result<Vec<GpuTiming>, QueryError> {
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut timings = Vec::with_capacity(self.timers.len());
+        for (name, first_query) in self.timers.iter() {
+            if let Some(ticks) = pool.results(*first_query, 2)? {
+                timings.push(GpuTiming {
+                    name,
+                    duration: ticks_to_duration(
+                        ticks[0],
+                        ticks[1],
+                        device.timestamp_valid_bits(),
+                        device.timestamp_period(),
+                    ),
+                });
+            }
+        }
+
+        Ok(timings)
+    }
+
+    /// Forgets the timers of the previous frame and records the reset of the queries, which has to
+    /// be submitted before the commands of the new frame
+    pub fn reset(&mut self, cmd_buffer: &mut CommandBuffer) {
+        self.timers.clear();
+        self.next_query = 0;
+        if let Some(pool) = &self.pool {
+            cmd_buffer.reset_query_pool(pool);
+        }
+    }
+
+    pub fn begin(&mut self, cmd_buffer: &mut CommandBuffer, name: &'static str) -> GpuTimer {
+        let first_query = match &self.pool {
+            Some(pool) if self.next_query + 2 <= pool.count() => {
+                let query = self.next_query;
+                self.next_query += 2;
+                cmd_buffer.write_timestamp(pool, query, vk::PipelineStageFlags::TOP_OF_PIPE);
+                Some(query)
+            }
+            Some(_) => {
+                log::warn!("Too many gpu timers, {} is not timed", name);
+                None
+            }
+            None => None,
+        };
+
+        GpuTimer { name, first_query }
+    }
+
+    pub fn end(&mut self, cmd_buffer: &mut CommandBuffer, timer: GpuTimer) {
+        if let (Some(pool), Some(query)) = (&self.pool, timer.first_query) {
+            cmd_buffer.write_timestamp(pool, query + 1, vk::PipelineStageFlags::BOTTOM_OF_PIPE);
+            self.timers.push((timer.name, query));
+        }
+    }
+}
+
+/// Only the lowest `valid_bits` of the timestamps are valid, which may wrap around between the two.
+/// `period` is the number of nanoseconds per tick.
+fn ticks_to_duration(begin: u64, end: u64, valid_bits: u32, period: f32) -> Duration {
+    let mask = if valid_bits >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << valid_bits) - 1
+    };
+    let ticks = (end & mask).wrapping_sub(begin & mask) & mask;
+    Duration::from_nanos((ticks as f64 * period as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_are_scaled_by_the_period() {
+        assert_eq!(
+            ticks_to_duration(1000, 3000, 64, 1.0),
+            Duration::from_nanos(2000)
+        );
+        assert_eq!(
+            ticks_to_duration(1000, 3000, 64, 52.08),
+            Duration::from_nanos(104_160)
+        );
+    }
+
+    #[test]
+    fn invalid_bits_are_ignored_and_wrap_around() {
+        // The upper bits are garbage
+        assert_eq!(
+            ticks_to_duration(0xff00_0000_0000_0010, 0x0000_0000_0000_0020, 36, 1.0),
+            Duration::from_nanos(0x10)
+        );
+        let max = (1u64 << 36) - 1;
+        assert_eq!(ticks_to_duration(max, 4, 36, 1.0), Duration::from_nanos(5));
+    }
+}
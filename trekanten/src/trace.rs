@@ -0,0 +1,256 @@
+//! A trace of the commands that are recorded with a RenderPassEncoder or a ComputePassEncoder, to
+//! check that a change to how a frame is drawn does not change the work that is submitted to the
+//! gpu.
+//!
+//! The trace is text, with one command per line. Handles are numbered in the order they are first
+//! used within the frame, e.g. `p0` is the first pipeline that is bound, so that the trace does not
+//! depend on the order the resources were created in.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::resource::ID;
+use crate::util::{Extent2D, Rect2D, Viewport};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    BeginRenderPass {
+        render_pass: ID,
+        extent: Extent2D,
+    },
+    BindPipeline {
+        pipeline: ID,
+    },
+    BindDescriptorSet {
+        idx: u32,
+        set: ID,
+        pipeline: ID,
+    },
+    BindVertexBuffer {
+        buffer: ID,
+    },
+    BindIndexBuffer {
+        buffer: ID,
+    },
+    PushConstant {
+        pipeline: ID,
+        size: usize,
+    },
+    DrawIndexed {
+        n_indices: u32,
+        first_index: u32,
+        vertex_offset: i32,
+    },
+    Draw {
+        n_vertices: u32,
+    },
+    SetScissor(Rect2D),
+    SetViewport(Viewport),
+    EndRenderPass,
+    BindComputePipeline {
+        pipeline: ID,
+    },
+    Dispatch {
+        x: u32,
+        y: u32,
+        z: u32,
+    },
+}
+
+/// The commands of the frame that is being recorded. Shared by the encoders of the frame.
+#[derive(Debug, Default)]
+pub struct CommandTrace {
+    commands: Mutex<Vec<Command>>,
+}
+
+impl CommandTrace {
+    pub(crate) fn record(&self, cmd: Command) {
+        self.commands
+            .lock()
+            .expect("Command trace lock poisoned")
+            .push(cmd);
+    }
+
+    pub(crate) fn take(&self) -> Vec<Command> {
+        std::mem::take(&mut *self.commands.lock().expect("Command trace lock poisoned"))
+    }
+}
+
+#[derive(Default)]
+struct HandleNames {
+    ids: HashMap<(&'static str, ID), usize>,
+    counts: HashMap<&'static str, usize>,
+}
+
+impl HandleNames {
+    fn name(&mut self, prefix: &'static str, id: ID) -> String {
+        let counts = &mut self.counts;
+        let n = *self.ids.entry((prefix, id)).or_insert_with(|| {
+            let count = counts.entry(prefix).or_insert(0);
+            *count += 1;
+            *count - 1
+        });
+        format!("{}{}", prefix, n)
+    }
+}
+
+/// The commands of one frame as text, one command per line
+pub fn format_frame(commands: &[Command]) -> String {
+    let mut names = HandleNames::default();
+    let mut out = String::new();
+    for cmd in commands {
+        let line = match *cmd {
+            Command::BeginRenderPass {
+                render_pass,
+                extent,
+            } => format!(
+                "begin_render_pass {} {}x{}",
+                names.name("rp", render_pass),
+                extent.width,
+                extent.height
+            ),
+            Command::BindPipeline { pipeline } => {
+                format!("bind_pipeline {}", names.name("p", pipeline))
+            }
+            Command::BindDescriptorSet { idx, set, pipeline } => format!(
+                "bind_descriptor_set {} {} {}",
+                idx,
+                names.name("ds", set),
+                names.name("p", pipeline)
+            ),
+            Command::BindVertexBuffer { buffer } => {
+                format!("bind_vertex_buffer {}", names.name("vb", buffer))
+            }
+            Command::BindIndexBuffer { buffer } => {
+                format!("bind_index_buffer {}", names.name("ib", buffer))
+            }
+            Command::PushConstant { pipeline, size } => {
+                format!("push_constant {} {}", names.name("p", pipeline), size)
+            }
+            Command::DrawIndexed {
+                n_indices,
+                first_index,
+                vertex_offset,
+            } => format!(
+                "draw_indexed {} {} {}",
+                n_indices, first_index, vertex_offset
+            ),
+            Command::Draw { n_vertices } => format!("draw {}", n_vertices),
+            Command::SetScissor(r) => format!(
+                "set_scissor {} {} {}x{}",
+                r.offset.x, r.offset.y, r.extent.width, r.extent.height
+            ),
+            Command::SetViewport(v) => format!(
+                "set_viewport {} {} {}x{} {}..{}",
+                v.x, v.y, v.width, v.height, v.min_depth, v.max_depth
+            ),
+            Command::EndRenderPass => String::from("end_render_pass"),
+            Command::BindComputePipeline { pipeline } => {
+                format!("bind_compute_pipeline {}", names.name("cp", pipeline))
+            }
+            Command::Dispatch { x, y, z } => format!("dispatch {} {} {}", x, y, z),
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+/// The first line where two traces differ. A missing line means that the trace ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceMismatch {
+    /// Counting from 1
+    pub line: usize,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+}
+
+impl std::fmt::Display for TraceMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}: expected {}, got {}",
+            self.line,
+            self.expected.as_deref().unwrap_or("end of trace"),
+            self.actual.as_deref().unwrap_or("end of trace")
+        )
+    }
+}
+
+impl std::error::Error for TraceMismatch {}
+
+/// Compares two traces line by line, ignoring trailing whitespace
+pub fn compare(expected: &str, actual: &str) -> Result<(), TraceMismatch> {
+    let mut expected_lines = expected.lines().map(str::trim_end);
+    let mut actual_lines = actual.lines().map(str::trim_end);
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => return Ok(()),
+            (e, a) if e == a => line += 1,
+            (e, a) => {
+                return Err(TraceMismatch {
+                    line,
+                    expected: e.map(String::from),
+                    actual: a.map(String::from),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(render_pass: ID, pipelines: [ID; 2]) -> Vec<Command> {
+        vec![
+            Command::BeginRenderPass {
+                render_pass,
+                extent: Extent2D {
+                    width: 1280,
+                    height: 720,
+                },
+            },
+            Command::BindPipeline {
+                pipeline: pipelines[0],
+            },
+            Command::DrawIndexed {
+                n_indices: 36,
+                first_index: 0,
+                vertex_offset: 0,
+            },
+            Command::BindPipeline {
+                pipeline: pipelines[1],
+            },
+            Command::Draw { n_vertices: 3 },
+            Command::EndRenderPass,
+        ]
+    }
+
+    #[test]
+    fn traces_do_not_depend_on_handle_ids() {
+        let mut storage = resurs::Storage::<u32>::new();
+        let ids: Vec<ID> = (0..4).map(|i| storage.add(i).id()).collect();
+
+        let a = format_frame(&frame(ids[0], [ids[1], ids[2]]));
+        assert_eq!(
+            a,
+            "begin_render_pass rp0 1280x720\nbind_pipeline p0\ndraw_indexed 36 0 0\n\
+             bind_pipeline p1\ndraw 3\nend_render_pass\n"
+        );
+        assert_eq!(
+            compare(&a, &format_frame(&frame(ids[3], [ids[2], ids[0]]))),
+            Ok(())
+        );
+
+        let mismatch = compare(&a, &format_frame(&frame(ids[0], [ids[1], ids[1]]))).unwrap_err();
+        assert_eq!(mismatch.line, 4);
+        assert_eq!(mismatch.expected.as_deref(), Some("bind_pipeline p1"));
+        assert_eq!(mismatch.actual.as_deref(), Some("bind_pipeline p0"));
+
+        let mismatch = compare(&a, "begin_render_pass rp0 1280x720\n").unwrap_err();
+        assert_eq!(mismatch.line, 2);
+        assert_eq!(mismatch.actual, None);
+    }
+}
@@ -12,6 +12,7 @@ impl Format {
             Self::FLOAT1 => 4,
             Self::RGBA_SRGB => 4,
             Self::RGBA_UNORM => 4,
+            Self::RGBA_F16 => 8,
             _ => unimplemented!("Missing case in match"),
         }
     }
@@ -23,6 +24,7 @@ impl Format {
 
     pub const RGBA_SRGB: Self = Self(vk::Format::R8G8B8A8_SRGB);
     pub const RGBA_UNORM: Self = Self(vk::Format::R8G8B8A8_UNORM);
+    pub const RGBA_F16: Self = Self(vk::Format::R16G16B16A16_SFLOAT);
 
     pub const D16_UNORM: Self = Self(vk::Format::D16_UNORM);
 }
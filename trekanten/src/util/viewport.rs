@@ -1,5 +1,6 @@
 use ash::vk;
 
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Viewport {
     pub x: f32,
     pub y: f32,